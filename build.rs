@@ -1,7 +1,11 @@
 fn main() {
     println!("cargo:rerun-if-changed=src/protos/my_grpc_service.proto");
+    println!("cargo:rerun-if-changed=src/protos/message_stream.proto");
     tonic_build::configure()
         .out_dir("src/protos")
-        .compile(&["src/protos/my_grpc_service.proto"], &["src/protos"])
+        .compile(
+            &["src/protos/my_grpc_service.proto", "src/protos/message_stream.proto"],
+            &["src/protos"],
+        )
         .unwrap_or_else(|e| panic!("Failed to compile protos: {:?}", e));
 }
@@ -11,8 +11,8 @@
 ///   the agent's domain understanding.
 /// - `update_knowledge_graph`: Updates the knowledge graph with new information,
 ///   allowing for the incremental enrichment of the agent's knowledge base.
-/// - `search`: Searches within the graph for nodes containing specific strings,
-///   facilitating efficient information retrieval based on query terms.
+/// - `search`: Ranks the graph's nodes by TF-IDF relevance to a query and returns the
+///   top matches with their scores, so callers can keep the best few or filter on a threshold.
 /// - `summarize`: Generates a concise overview of the knowledge graph's content,
 ///   aiding in the visualization of the graph's structure.
 ///
@@ -31,6 +31,35 @@
 /// easy storage, transmission, and reconstruction of the agent's knowledge graph, making
 /// the module versatile for applications requiring data exchange and persistence.
 ///
+/// # Interchange Formats
+///
+/// A `KnowledgeAgent` can be exported as JSON-LD (`export_jsonld` / `import_jsonld`) or as
+/// RDF Turtle (`export_rdf_turtle`). Each concept becomes a node with an `rdfs:label`; word
+/// adjacency is exported as `kg:follows`, typed relations under the `rel:` namespace and
+/// literal attributes under the `attr:` namespace.
+///
+/// # Persistence
+///
+/// `save` writes a `KnowledgeAgent` to a `KVStore` under a caller-chosen key and `load` reads
+/// it back, so learned knowledge survives restarts. The value is a UTF-8 JSON object:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "knowledge_graph": { "the": ["cat", "mat"], "cat": ["sat"] },
+///   "relations": { "cat": [["is_a", "animal"]] },
+///   "attributes": { "cat": { "legs": 4 } }
+/// }
+/// ```
+///
+/// `knowledge_graph` maps each word to the words that follow it, `relations` maps a node to
+/// its `[relation, target]` edges and `attributes` maps a node to its literal attributes.
+/// `version` is bumped whenever the layout changes; `load` rejects versions it doesn't know.
+///
+/// Besides bulk ingestion with `update_knowledge_graph`, single edges can be changed with
+/// `add_edge` / `remove_edge`. The `follows` relation addresses the word-adjacency graph and
+/// every other relation a typed edge.
+///
 /// # Overview
 ///
 /// This module leverages Rust's powerful type system and ownership model to manage complex
@@ -39,8 +68,12 @@
 
 
 
+use crate::clients::kv::KVStore;
+use crate::utils::bigboterror::BigbotError;
+
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Agent<'a> {
@@ -55,8 +88,47 @@ pub struct Agent<'a> {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KnowledgeAgent {
     knowledge_graph: HashMap<String, Vec<String>>,
+    // Typed edges: head -> [(relation, target)]
+    #[serde(default)]
+    relations: HashMap<String, Vec<(String, String)>>,
+    // Literal attributes attached to nodes
+    #[serde(default)]
+    attributes: HashMap<String, HashMap<String, Value>>,
 }
 
+// A node matched by `KnowledgeAgent::search`, with its TF-IDF relevance to the query
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub text: String,
+    pub score: f32,
+}
+
+// The relation `add_edge` / `remove_edge` map onto the word-adjacency graph
+pub const FOLLOWS_RELATION: &str = "follows";
+
+// Version of the layout written by `KnowledgeAgent::save`
+const STORAGE_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct StoredGraph<'a> {
+    version: u32,
+    #[serde(flatten)]
+    agent: &'a KnowledgeAgent,
+}
+
+#[derive(Deserialize)]
+struct LoadedGraph {
+    version: u32,
+    #[serde(flatten)]
+    agent: KnowledgeAgent,
+}
+
+const KG_NAMESPACE: &str = "https://ourown.ai/ns/knowledge#";
+const RELATION_NAMESPACE: &str = "https://ourown.ai/ns/knowledge/relation#";
+const ATTRIBUTE_NAMESPACE: &str = "https://ourown.ai/ns/knowledge/attribute#";
+const RDFS_LABEL: &str = "http://www.w3.org/2000/01/rdf-schema#label";
+const NODE_IRI_PREFIX: &str = "urn:ooai:kg:";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProviderMetadata {
     pub name: String,
@@ -251,6 +323,8 @@ impl KnowledgeAgent {
     pub fn new() -> Self {
         Self {
             knowledge_graph: HashMap::new(),
+            relations: HashMap::new(),
+            attributes: HashMap::new(),
         }
     }
 
@@ -273,11 +347,525 @@ impl KnowledgeAgent {
         }
     }
 
-    pub fn search(&self, query: &str) -> Vec<&str> {
-        self.knowledge_graph
+    // Ranks the graph's nodes by TF-IDF relevance to `query` and returns the best `top_k`. A node
+    // is scored on its own label plus the nodes it points at, with label terms counting double so
+    // a node about a term outranks one that merely links to it. Nodes sharing no terms with the
+    // query are left out; ties are broken by label so results are stable.
+    pub fn search(&self, query: &str, top_k: usize) -> Vec<SearchHit> {
+        let query_terms: BTreeSet<String> = tokenize(query).collect();
+        if query_terms.is_empty() || top_k == 0 {
+            return Vec::new();
+        }
+
+        let documents: Vec<(&str, HashMap<String, usize>)> = self
+            .node_labels()
+            .into_iter()
+            .map(|label| (label, self.node_terms(label)))
+            .collect();
+        let idf = |term: &String| {
+            let document_frequency = documents.iter().filter(|(_, terms)| terms.contains_key(term)).count();
+            ((documents.len() as f32 + 1.0) / (document_frequency as f32 + 1.0)).ln() + 1.0
+        };
+        let weights: Vec<(&String, f32)> = query_terms.iter().map(|term| (term, idf(term))).collect();
+
+        let mut hits: Vec<SearchHit> = documents
             .iter()
-            .filter(|(head_word, _)| head_word.contains(query))
-            .flat_map(|(_, deps)| deps.iter().map(|dep| dep.as_str()))
-            .collect()
+            .filter_map(|(label, terms)| {
+                let length: usize = terms.values().sum();
+                let score: f32 = weights
+                    .iter()
+                    .filter_map(|(term, idf)| terms.get(*term).map(|&count| count as f32 / length as f32 * idf))
+                    .sum();
+                (score > 0.0).then(|| SearchHit { text: label.to_string(), score })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.text.cmp(&b.text)));
+        hits.truncate(top_k);
+        hits
+    }
+
+    // Adds a single `subject -relation-> object` edge, returning false if it already existed.
+    // `follows` edges go into the word-adjacency graph, anything else is a typed relation.
+    pub fn add_edge(&mut self, subject: &str, relation: &str, object: &str) -> bool {
+        if relation != FOLLOWS_RELATION {
+            let exists = self.relations(subject).contains(&(relation, object));
+            self.add_relation(subject, relation, object);
+            return !exists;
+        }
+        let deps = self.knowledge_graph.entry(subject.to_string()).or_insert_with(Vec::new);
+        if deps.iter().any(|dep| dep == object) {
+            return false;
+        }
+        deps.push(object.to_string());
+        self.knowledge_graph.entry(object.to_string()).or_insert_with(Vec::new);
+        true
+    }
+
+    // Removes a single edge, returning whether it existed. The nodes themselves are kept.
+    pub fn remove_edge(&mut self, subject: &str, relation: &str, object: &str) -> bool {
+        if relation == FOLLOWS_RELATION {
+            let Some(deps) = self.knowledge_graph.get_mut(subject) else {
+                return false;
+            };
+            let before = deps.len();
+            deps.retain(|dep| dep != object);
+            return deps.len() != before;
+        }
+        let Some(edges) = self.relations.get_mut(subject) else {
+            return false;
+        };
+        let before = edges.len();
+        edges.retain(|(edge_relation, target)| edge_relation != relation || target != object);
+        let removed = edges.len() != before;
+        if edges.is_empty() {
+            self.relations.remove(subject);
+        }
+        removed
+    }
+
+    // Writes the whole graph to `store` under `key` in the format described in the module docs
+    pub async fn save(&self, store: &dyn KVStore, key: &str) -> Result<(), BigbotError> {
+        let stored = StoredGraph { version: STORAGE_VERSION, agent: self };
+        let bytes = serde_json::to_vec(&stored)
+            .map_err(|e| BigbotError::DatabaseError(format!("Failed to serialize knowledge graph {}: {}", key, e)))?;
+        store.set(key.as_bytes().to_vec(), bytes).await
+    }
+
+    // Reads a graph written by `save`. Returns None if nothing is stored under `key`.
+    pub async fn load(store: &dyn KVStore, key: &str) -> Result<Option<Self>, BigbotError> {
+        let Some(bytes) = store.get(key.as_bytes()).await? else {
+            return Ok(None);
+        };
+        let loaded: LoadedGraph = serde_json::from_slice(&bytes)
+            .map_err(|e| BigbotError::DatabaseError(format!("Corrupt knowledge graph {}: {}", key, e)))?;
+        if loaded.version != STORAGE_VERSION {
+            return Err(BigbotError::DatabaseError(format!(
+                "Knowledge graph {} has unsupported version {}",
+                key, loaded.version
+            )));
+        }
+        Ok(Some(loaded.agent))
+    }
+
+    pub fn add_relation(&mut self, head: &str, relation: &str, target: &str) {
+        let edge = (relation.to_string(), target.to_string());
+        let edges = self.relations.entry(head.to_string()).or_insert_with(Vec::new);
+        if !edges.contains(&edge) {
+            edges.push(edge);
+        }
+    }
+
+    pub fn relations(&self, head: &str) -> Vec<(&str, &str)> {
+        self.relations
+            .get(head)
+            .map(|edges| edges.iter().map(|(relation, target)| (relation.as_str(), target.as_str())).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set_attribute(&mut self, node: &str, name: &str, value: Value) {
+        self.attributes
+            .entry(node.to_string())
+            .or_insert_with(HashMap::new)
+            .insert(name.to_string(), value);
+    }
+
+    pub fn attribute(&self, node: &str, name: &str) -> Option<&Value> {
+        self.attributes.get(node).and_then(|attributes| attributes.get(name))
+    }
+
+    // Exports the graph as a JSON-LD document. Nodes and their properties are emitted in sorted
+    // order so the same graph always produces the same document.
+    pub fn export_jsonld(&self) -> Value {
+        let graph: Vec<Value> = self
+            .node_labels()
+            .into_iter()
+            .map(|label| {
+                let mut node = Map::new();
+                node.insert("@id".to_string(), json!(node_iri(label)));
+                if self.knowledge_graph.contains_key(label) {
+                    node.insert("@type".to_string(), json!("kg:Concept"));
+                }
+                node.insert("label".to_string(), json!(label));
+
+                if let Some(deps) = self.knowledge_graph.get(label).filter(|deps| !deps.is_empty()) {
+                    let targets: Vec<Value> = deps.iter().map(|dep| json!({ "@id": node_iri(dep) })).collect();
+                    node.insert("kg:follows".to_string(), Value::Array(targets));
+                }
+
+                let mut relations: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+                for (relation, target) in self.relations.get(label).into_iter().flatten() {
+                    relations.entry(relation).or_default().push(json!({ "@id": node_iri(target) }));
+                }
+                for (relation, targets) in relations {
+                    node.insert(format!("rel:{}", encode_term(relation)), Value::Array(targets));
+                }
+
+                if let Some(attributes) = self.attributes.get(label) {
+                    let sorted: BTreeMap<&String, &Value> = attributes.iter().collect();
+                    for (name, value) in sorted {
+                        node.insert(format!("attr:{}", encode_term(name)), jsonld_literal(value));
+                    }
+                }
+                Value::Object(node)
+            })
+            .collect();
+
+        json!({
+            "@context": {
+                "kg": KG_NAMESPACE,
+                "rel": RELATION_NAMESPACE,
+                "attr": ATTRIBUTE_NAMESPACE,
+                "label": RDFS_LABEL,
+            },
+            "@graph": graph,
+        })
+    }
+
+    // Replaces the agent's graph with the one described by a document from `export_jsonld`.
+    // Terms may be compact (`rel:is_a`) or full IRIs in the knowledge namespaces.
+    pub fn import_jsonld(&mut self, document: &Value) -> Result<(), BigbotError> {
+        let nodes = document
+            .get("@graph")
+            .and_then(Value::as_array)
+            .ok_or_else(|| BigbotError::InvalidInput("JSON-LD document has no @graph".to_string()))?;
+
+        // Labels are resolved up front so edges can point at nodes defined later in the graph
+        let mut labels = HashMap::new();
+        for node in nodes {
+            let id = node_id(node)?;
+            let label = node
+                .get("label")
+                .or_else(|| node.get(RDFS_LABEL))
+                .and_then(literal_value)
+                .and_then(|label| label.as_str().map(str::to_string))
+                .ok_or_else(|| BigbotError::InvalidInput(format!("Node {} has no label", id)))?;
+            labels.insert(id.to_string(), label);
+        }
+        let resolve = |reference: &Value| -> Result<String, BigbotError> {
+            let id = node_id(reference)?;
+            labels
+                .get(id)
+                .cloned()
+                .ok_or_else(|| BigbotError::InvalidInput(format!("Edge points at unknown node {}", id)))
+        };
+
+        let mut imported = KnowledgeAgent::new();
+        for node in nodes {
+            let label = labels[node_id(node)?].clone();
+            let is_concept = as_values(node.get("@type"))
+                .iter()
+                .any(|node_type| expand_term(node_type.as_str().unwrap_or_default()) == format!("{}Concept", KG_NAMESPACE));
+            if is_concept {
+                imported.knowledge_graph.entry(label.clone()).or_insert_with(Vec::new);
+            }
+
+            let properties = node
+                .as_object()
+                .ok_or_else(|| BigbotError::InvalidInput(format!("Node {} is not an object", label)))?;
+            for (key, value) in properties {
+                let key = expand_term(key);
+                if key == format!("{}follows", KG_NAMESPACE) {
+                    for target in as_values(Some(value)) {
+                        let target = resolve(target)?;
+                        imported.knowledge_graph.entry(label.clone()).or_insert_with(Vec::new).push(target);
+                    }
+                } else if let Some(relation) = key.strip_prefix(RELATION_NAMESPACE) {
+                    let relation = decode_term(relation)?;
+                    for target in as_values(Some(value)) {
+                        imported.add_relation(&label, &relation, &resolve(target)?);
+                    }
+                } else if let Some(name) = key.strip_prefix(ATTRIBUTE_NAMESPACE) {
+                    let literal = literal_value(value)
+                        .ok_or_else(|| BigbotError::InvalidInput(format!("Attribute {} on {} is not a literal", name, label)))?;
+                    imported.set_attribute(&label, &decode_term(name)?, literal);
+                }
+            }
+        }
+
+        *self = imported;
+        Ok(())
+    }
+
+    // Exports the graph as RDF Turtle using the same vocabulary as `export_jsonld`
+    pub fn export_rdf_turtle(&self) -> String {
+        let mut turtle = String::new();
+        turtle.push_str(&format!("@prefix kg: <{}> .\n", KG_NAMESPACE));
+        turtle.push_str(&format!("@prefix rel: <{}> .\n", RELATION_NAMESPACE));
+        turtle.push_str(&format!("@prefix attr: <{}> .\n", ATTRIBUTE_NAMESPACE));
+        turtle.push_str("@prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n");
+        turtle.push_str("@prefix rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#> .\n");
+        turtle.push_str("@prefix xsd: <http://www.w3.org/2001/XMLSchema#> .\n");
+
+        for label in self.node_labels() {
+            let mut predicates = Vec::new();
+            if self.knowledge_graph.contains_key(label) {
+                predicates.push("a kg:Concept".to_string());
+            }
+            predicates.push(format!("rdfs:label {}", turtle_string(label)));
+            for dep in self.knowledge_graph.get(label).into_iter().flatten() {
+                predicates.push(format!("kg:follows <{}>", node_iri(dep)));
+            }
+            let mut relations: Vec<&(String, String)> = self.relations.get(label).into_iter().flatten().collect();
+            relations.sort();
+            for (relation, target) in relations {
+                predicates.push(format!("rel:{} <{}>", encode_term(relation), node_iri(target)));
+            }
+            if let Some(attributes) = self.attributes.get(label) {
+                let sorted: BTreeMap<&String, &Value> = attributes.iter().collect();
+                for (name, value) in sorted {
+                    predicates.push(format!("attr:{} {}", encode_term(name), turtle_literal(value)));
+                }
+            }
+            turtle.push_str(&format!("\n<{}>\n    {} .\n", node_iri(label), predicates.join(" ;\n    ")));
+        }
+        turtle
+    }
+
+    // Term counts for a node: its label twice, then the labels of its `follows` and relation targets
+    fn node_terms(&self, label: &str) -> HashMap<String, usize> {
+        let neighbours = self
+            .knowledge_graph
+            .get(label)
+            .into_iter()
+            .flatten()
+            .chain(self.relations.get(label).into_iter().flatten().map(|(_, target)| target));
+        let mut terms = HashMap::new();
+        for term in tokenize(label).chain(tokenize(label)).chain(neighbours.flat_map(|neighbour| tokenize(neighbour))) {
+            *terms.entry(term).or_insert(0) += 1;
+        }
+        terms
+    }
+
+    // Every node mentioned anywhere in the graph, sorted
+    fn node_labels(&self) -> BTreeSet<&str> {
+        let mut labels = BTreeSet::new();
+        for (head, deps) in &self.knowledge_graph {
+            labels.insert(head.as_str());
+            labels.extend(deps.iter().map(String::as_str));
+        }
+        for (head, edges) in &self.relations {
+            labels.insert(head.as_str());
+            labels.extend(edges.iter().map(|(_, target)| target.as_str()));
+        }
+        labels.extend(self.attributes.keys().map(String::as_str));
+        labels
+    }
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn node_iri(label: &str) -> String {
+    format!("{}{}", NODE_IRI_PREFIX, encode_term(label))
+}
+
+// Percent-encodes everything but ASCII alphanumerics and `_`, which keeps terms valid both as
+// IRIs and as Turtle local names
+fn encode_term(term: &str) -> String {
+    term.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || byte == b'_' {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+fn decode_term(term: &str) -> Result<String, BigbotError> {
+    let invalid = || BigbotError::InvalidInput(format!("Invalid percent-encoding in term: {}", term));
+    let bytes = term.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = term.get(i + 1..i + 3).ok_or_else(invalid)?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+// Expands the compact IRIs used in exported documents; anything else is returned as-is
+fn expand_term(term: &str) -> String {
+    for (prefix, namespace) in [("kg:", KG_NAMESPACE), ("rel:", RELATION_NAMESPACE), ("attr:", ATTRIBUTE_NAMESPACE)] {
+        if let Some(local) = term.strip_prefix(prefix) {
+            return format!("{}{}", namespace, local);
+        }
+    }
+    term.to_string()
+}
+
+fn node_id(node: &Value) -> Result<&str, BigbotError> {
+    node.get("@id")
+        .and_then(Value::as_str)
+        .ok_or_else(|| BigbotError::InvalidInput(format!("Expected a node reference, got {}", node)))
+}
+
+fn as_values(value: Option<&Value>) -> Vec<&Value> {
+    match value {
+        Some(Value::Array(values)) => values.iter().collect(),
+        Some(value) => vec![value],
+        None => Vec::new(),
     }
-}
\ No newline at end of file
+}
+
+// Strings, numbers and booleans are native JSON-LD literals; anything else is a JSON literal
+fn jsonld_literal(value: &Value) -> Value {
+    match value {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => json!({ "@value": value }),
+        _ => json!({ "@value": value, "@type": "@json" }),
+    }
+}
+
+fn literal_value(value: &Value) -> Option<Value> {
+    match value {
+        Value::Object(object) => object.get("@value").cloned(),
+        Value::Array(_) => None,
+        value => Some(value.clone()),
+    }
+}
+
+fn turtle_string(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+fn turtle_literal(value: &Value) -> String {
+    match value {
+        Value::String(string) => turtle_string(string),
+        Value::Bool(boolean) => boolean.to_string(),
+        Value::Number(number) if number.is_f64() => format!("{}^^xsd:double", turtle_string(&number.to_string())),
+        Value::Number(number) => number.to_string(),
+        _ => format!("{}^^rdf:JSON", turtle_string(&value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::kv::MemoryKVStore;
+
+    fn sample_agent() -> KnowledgeAgent {
+        let mut agent = KnowledgeAgent::new();
+        agent.update_knowledge_graph("the cat sat on the mat");
+        agent.add_relation("cat", "is_a", "small animal");
+        agent.add_relation("mat", "located in", "kitchen");
+        agent.set_attribute("cat", "legs", json!(4));
+        agent.set_attribute("cat", "name", json!("Tom \"the\" cat"));
+        agent.set_attribute("small animal", "weight_kg", json!(4.5));
+        agent.set_attribute("mat", "tags", json!(["woven", "red"]));
+        agent
+    }
+
+    #[test]
+    fn test_jsonld_round_trip() {
+        let agent = sample_agent();
+        let document = agent.export_jsonld();
+
+        let mut imported = KnowledgeAgent::new();
+        imported.import_jsonld(&document).unwrap();
+
+        for query in ["the", "at", "on", "kitchen", "animal"] {
+            assert_eq!(imported.search(query, 10), agent.search(query, 10), "query {}", query);
+        }
+        assert_eq!(imported.knowledge_graph, agent.knowledge_graph);
+        assert_eq!(imported.relations("cat"), vec![("is_a", "small animal")]);
+        assert_eq!(imported.relations("mat"), vec![("located in", "kitchen")]);
+        assert_eq!(imported.attributes, agent.attributes);
+        assert_eq!(imported.export_jsonld(), document);
+    }
+
+    #[test]
+    fn test_turtle_export() {
+        let turtle = sample_agent().export_rdf_turtle();
+
+        assert!(turtle.contains("@prefix rel: <https://ourown.ai/ns/knowledge/relation#> ."));
+        assert!(turtle.contains("<urn:ooai:kg:cat>\n    a kg:Concept ;\n    rdfs:label \"cat\""));
+        assert!(turtle.contains("kg:follows <urn:ooai:kg:sat>"));
+        assert!(turtle.contains("rel:is_a <urn:ooai:kg:small%20animal>"));
+        assert!(turtle.contains("rel:located%20in <urn:ooai:kg:kitchen>"));
+        assert!(turtle.contains("attr:legs 4"));
+        assert!(turtle.contains("attr:name \"Tom \\\"the\\\" cat\""));
+        assert!(turtle.contains("attr:weight_kg \"4.5\"^^xsd:double"));
+        assert!(turtle.contains("attr:tags \"[\\\"woven\\\",\\\"red\\\"]\"^^rdf:JSON"));
+    }
+
+    #[test]
+    fn test_search_ranks_more_relevant_nodes_first() {
+        let mut agent = KnowledgeAgent::new();
+        agent.add_relation("rust programming", "used_for", "systems programming");
+        agent.add_relation("python programming", "used_for", "data science");
+
+        let hits = agent.search("Rust programming", 10);
+        let texts: Vec<&str> = hits.iter().map(|hit| hit.text.as_str()).collect();
+        assert_eq!(texts[0], "rust programming");
+        assert!(hits[0].score > hits[1].score);
+        assert!(hits.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert!(!texts.contains(&"data science"));
+
+        assert_eq!(agent.search("Rust programming", 1), hits[..1].to_vec());
+        assert!(agent.search("", 10).is_empty());
+        assert!(agent.search("haskell", 10).is_empty());
+    }
+
+    #[test]
+    fn test_add_and_remove_edges() {
+        let mut agent = KnowledgeAgent::new();
+        assert!(agent.add_edge("cat", FOLLOWS_RELATION, "sat"));
+        assert!(!agent.add_edge("cat", FOLLOWS_RELATION, "sat"));
+        assert!(agent.add_edge("cat", "is_a", "animal"));
+        assert!(!agent.add_edge("cat", "is_a", "animal"));
+        assert_eq!(agent.knowledge_graph["cat"], vec!["sat".to_string()]);
+        assert!(agent.knowledge_graph.contains_key("sat"));
+        assert_eq!(agent.relations("cat"), vec![("is_a", "animal")]);
+
+        assert!(agent.remove_edge("cat", FOLLOWS_RELATION, "sat"));
+        assert!(!agent.remove_edge("cat", FOLLOWS_RELATION, "sat"));
+        assert!(agent.remove_edge("cat", "is_a", "animal"));
+        assert!(!agent.remove_edge("cat", "is_a", "animal"));
+        assert!(agent.knowledge_graph["cat"].is_empty());
+        assert!(!agent.relations.contains_key("cat"));
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load_round_trip() {
+        let store = MemoryKVStore::default();
+        let mut agent = sample_agent();
+        agent.add_edge("kitchen", "part_of", "house");
+        agent.save(&store, "knowledge/text").await.unwrap();
+
+        let loaded = KnowledgeAgent::load(&store, "knowledge/text").await.unwrap().unwrap();
+        for query in ["the cat", "mat", "kitchen", "small animal", "house"] {
+            assert_eq!(loaded.search(query, 10), agent.search(query, 10), "query {}", query);
+        }
+        assert_eq!(loaded.attributes, agent.attributes);
+        assert!(KnowledgeAgent::load(&store, "knowledge/audio").await.unwrap().is_none());
+
+        store.set(b"knowledge/old".to_vec(), br#"{"version":2,"knowledge_graph":{}}"#.to_vec()).await.unwrap();
+        assert!(KnowledgeAgent::load(&store, "knowledge/old").await.is_err());
+    }
+
+    #[test]
+    fn test_import_rejects_dangling_edges() {
+        let document = json!({
+            "@graph": [
+                { "@id": "urn:ooai:kg:cat", "label": "cat", "rel:is_a": [{ "@id": "urn:ooai:kg:animal" }] }
+            ]
+        });
+        assert!(KnowledgeAgent::new().import_jsonld(&document).is_err());
+    }
+}
@@ -9,25 +9,41 @@
 /// - `learning_rate`: The rate at which the agent incorporates new information.
 /// - `exploration_rate`: The probability of selecting a random action for exploration.
 /// - `batch_size`: The number of experiences to sample from the replay buffer when updating.
-/// - `replay_buffer`: A binary heap of `Experience` structs for experience replay.
+/// - `replay_buffer`: A prioritized replay buffer of `Experience` structs, sampled in proportion to `priority^alpha`.
+/// - `alpha`: How strongly replay favours experiences with a large TD error; 0 samples uniformly.
+/// - `beta`: How fully importance-sampling weights correct for prioritized sampling; 1 corrects completely.
 /// - `eligibility_traces`: A 2D vector for applying updates across state-action pairs.
 /// - `softmax_temp`: The temperature parameter for the softmax action selection policy.
+/// - `min_exploration_rate`: The floor the exploration rate decays towards.
+/// - `exploration_decay_rate`: The fraction of the exploration rate removed per decay step, if fixed.
+/// - `double_q`: Whether Double Q-learning is enabled.
+/// - `second_q_table`: The second Q-table used in Double Q-learning mode; empty otherwise.
+/// - `rng`: The source of every random draw the agent makes, including its initial Q-values.
 ///
 /// # Methods
 /// - `new`: Initializes a new `QLearningAgent` with specified hyperparameters.
+/// - `from_config`: Initializes a new `QLearningAgent` from a `QLearningConfig` with named fields.
+/// - `with_seed`: Like `from_config`, but every random draw comes from a generator seeded with `seed`,
+///   so runs can be reproduced exactly.
+/// - `with_double_q`: Enables Double Q-learning on a newly constructed agent.
 /// - `choose_action`: Selects an action from a given state using a softmax probability distribution.
 /// - `update_q_values`: Updates the Q-table using a batch of experiences from the replay buffer.
+/// - `train`: Runs episodes against an `Environment`, learning from each step and decaying exploration.
 ///
 /// # Advanced Features
-/// - **Experience Replay**: Enhances learning efficiency by revisiting past decisions and outcomes.
+/// - **Prioritized Experience Replay**: Revisits past experiences, favouring the ones the agent predicted worst,
+///   and weights each update so the bias this introduces is corrected.
 /// - **Eligibility Traces**: Aids in faster convergence to optimal policies by tracking visited states and actions.
 /// - **Softmax Action Selection**: Provides a nuanced exploration strategy over the simpler epsilon-greedy method.
+/// - **Double Q-learning**: Optionally keeps two Q-tables, using one to select the next action and the
+///   other to evaluate it, which removes the overestimation bias of bootstrapping from a max.
 ///
 /// # Examples
 /// ```
 /// let mut q_agent = QLearningAgent::new(num_states, num_actions, gamma, learning_rate, exploration_rate, batch_size, softmax_temp);
-/// let action = q_agent.choose_action(current_state, &valid_actions);
+/// let action = q_agent.choose_action(current_state, &valid_actions)?;
 /// q_agent.update_q_values();
+/// let episode_returns = q_agent.train(&mut environment, 500, 100);
 /// ```
 ///
 /// # Note
@@ -38,42 +54,85 @@ use crate::iam::user::User;
 use crate::iam::verifiable_credentials::{VerifiableCredential, CredentialSubject, sign_credential_with_wallet};
 use crate::utils::file_storage::{FileStorageError, UploadedFile};
 
-use rand::Rng;
+use crate::buffers::replay_buffer::PrioritizedReplayBuffer;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::HashMap;
 
 // Define a struct to represent an experience in the replay buffer.
-// Includes state, action taken, reward received, next state, and a priority for sampling.
+// Includes state, action taken, reward received and next state; its sampling priority lives in the buffer.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Experience {
     state: usize,
     action: usize,
     reward: f32,
     next_state: usize,
-    priority: f32,
+    // The episode ended at `next_state`, so nothing is bootstrapped from it
+    #[serde(default)]
+    done: bool,
 }
 
-// Implement ordering for experiences based on their priority.
-// This is necessary for storing them in a binary heap.
-impl Ord for Experience {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.priority.partial_cmp(&other.priority).unwrap()
-    }
+// A task the agent can be trained on: discrete states and actions, with episodes that end when
+// `step` reports done or the step limit is reached.
+pub trait Environment {
+    // Starts a new episode and returns its initial state
+    fn reset(&mut self) -> usize;
+    // Applies an action, returning the next state, the reward and whether the episode is over
+    fn step(&mut self, action: usize) -> (usize, f32, bool);
+    fn valid_actions(&self, state: usize) -> Vec<usize>;
 }
 
-impl PartialOrd for Experience {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
+/// Hyperparameters for a `QLearningAgent`, by name. `QLearningConfig::new` fills in defaults for
+/// everything but the table dimensions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QLearningConfig {
+    pub num_states: usize,
+    pub num_actions: usize,
+    pub gamma: f32,
+    pub learning_rate: f32,
+    pub exploration_rate: f32,
+    pub min_exploration_rate: f32,
+    // With `None`, exploration decays linearly to the minimum over the run passed to
+    // `update_exploration_rate`; otherwise each call multiplies it by `1 - rate`
+    pub exploration_decay_rate: Option<f32>,
+    pub batch_size: usize,
+    pub softmax_temp: f32,
+    pub replay_capacity: usize,
+    pub alpha: f32,
+    pub beta: f32,
 }
 
-impl PartialEq for Experience {
-    fn eq(&self, other: &Self) -> bool {
-        self.priority == other.priority
+pub const DEFAULT_MIN_EXPLORATION_RATE: f32 = 0.01;
+pub const DEFAULT_REPLAY_CAPACITY: usize = 10_000;
+pub const DEFAULT_ALPHA: f32 = 0.6;
+pub const DEFAULT_BETA: f32 = 0.4;
+
+impl QLearningConfig {
+    pub fn new(num_states: usize, num_actions: usize) -> Self {
+        Self {
+            num_states,
+            num_actions,
+            gamma: 0.9,
+            learning_rate: 0.1,
+            exploration_rate: 0.1,
+            min_exploration_rate: DEFAULT_MIN_EXPLORATION_RATE,
+            exploration_decay_rate: None,
+            batch_size: 1,
+            softmax_temp: 1.0,
+            replay_capacity: DEFAULT_REPLAY_CAPACITY,
+            alpha: DEFAULT_ALPHA,
+            beta: DEFAULT_BETA,
+        }
     }
 }
 
-impl Eq for Experience {}
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum QLearningError {
+    #[error("No valid actions available in state {0}")]
+    NoValidActions(usize),
+}
 
 // The QLearningAgent struct now includes a replay buffer for experience replay,
 // eligibility traces for more nuanced learning, and a softmax temperature for action selection.
@@ -84,13 +143,30 @@ pub struct QLearningAgent {
     learning_rate: f32,
     exploration_rate: f32,
     batch_size: usize,
-    replay_buffer: BinaryHeap<Experience>,
+    replay_buffer: PrioritizedReplayBuffer<Experience>,
+    alpha: f32,
+    beta: f32,
     eligibility_traces: Vec<Vec<f32>>,
     softmax_temp: f32,
+    #[serde(default)]
+    double_q: bool,
+    #[serde(default)]
+    second_q_table: Vec<Vec<f32>>,
+    #[serde(default = "default_min_exploration_rate")]
+    min_exploration_rate: f32,
+    #[serde(default)]
+    exploration_decay_rate: Option<f32>,
+    #[serde(skip, default = "StdRng::from_entropy")]
+    rng: StdRng,
+}
+
+fn default_min_exploration_rate() -> f32 {
+    DEFAULT_MIN_EXPLORATION_RATE
 }
 
 impl QLearningAgent {
     // Initialize a new agent with given parameters, including the size of the replay buffer and softmax temperature.
+    // Exploration decays to the default minimum over the run passed to `update_exploration_rate`.
     pub fn new(
         num_states: usize,
         num_actions: usize,
@@ -100,97 +176,248 @@ impl QLearningAgent {
         batch_size: usize,
         softmax_temp: f32,
     ) -> Self {
-        Self {
-            agent: Agent::new(num_states, num_actions),
+        Self::from_config(QLearningConfig {
             gamma,
             learning_rate,
             exploration_rate,
             batch_size,
-            replay_buffer: BinaryHeap::new(),
-            eligibility_traces: vec![vec![0.0; num_actions]; num_states],
             softmax_temp,
+            ..QLearningConfig::new(num_states, num_actions)
+        })
+    }
+
+    pub fn from_config(config: QLearningConfig) -> Self {
+        Self::with_rng(config, StdRng::from_entropy())
+    }
+
+    pub fn with_seed(config: QLearningConfig, seed: u64) -> Self {
+        Self::with_rng(config, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(config: QLearningConfig, mut rng: StdRng) -> Self {
+        let mut agent = Agent::new(config.num_states, config.num_actions);
+        // Drawn here rather than by `Agent::new` so a seeded agent also starts from the same table
+        agent.q_table = (0..config.num_states)
+            .map(|_| (0..config.num_actions).map(|_| rng.gen_range(-0.5..0.5)).collect())
+            .collect();
+        Self {
+            agent,
+            gamma: config.gamma,
+            learning_rate: config.learning_rate,
+            exploration_rate: config.exploration_rate,
+            batch_size: config.batch_size,
+            replay_buffer: PrioritizedReplayBuffer::new(config.replay_capacity, config.alpha),
+            alpha: config.alpha,
+            beta: config.beta,
+            eligibility_traces: vec![vec![0.0; config.num_actions]; config.num_states],
+            softmax_temp: config.softmax_temp,
+            double_q: false,
+            second_q_table: Vec::new(),
+            min_exploration_rate: config.min_exploration_rate,
+            exploration_decay_rate: config.exploration_decay_rate,
+            rng,
+        }
+    }
+
+    // Switch a freshly constructed agent to Double Q-learning. The second table starts as a copy
+    // of the first so both estimators share the same initial bias.
+    pub fn with_double_q(mut self) -> Self {
+        self.double_q = true;
+        self.second_q_table = self.agent.q_table.clone();
+        self
+    }
+
+    pub fn is_double_q(&self) -> bool {
+        self.double_q
+    }
+
+    // The agent's action-value estimates for a state; the mean of both tables in Double Q mode.
+    pub fn action_values(&self, state: usize) -> Vec<f32> {
+        if self.double_q {
+            self.agent.q_table[state]
+                .iter()
+                .zip(&self.second_q_table[state])
+                .map(|(a, b)| (a + b) / 2.0)
+                .collect()
+        } else {
+            self.agent.q_table[state].clone()
         }
     }
 
     // Choose an action for a given state using a softmax probability distribution over valid actions.
     // This approach considers the relative value of each action more nuancedly than picking the max value directly.
-    pub fn choose_action(&self, state: usize, valid_actions: &[usize]) -> usize {
-        let mut rng = rand::thread_rng();
-        if rng.gen::<f32>() < self.exploration_rate {
+    // A temperature of zero (or below) picks the best valid action outright.
+    pub fn choose_action(&mut self, state: usize, valid_actions: &[usize]) -> Result<usize, QLearningError> {
+        if valid_actions.is_empty() {
+            return Err(QLearningError::NoValidActions(state));
+        }
+        if self.rng.gen::<f32>() < self.exploration_rate {
             // Exploration: choose a random valid action
-            let index = rng.gen_range(0..valid_actions.len());
-            valid_actions[index]
-        } else {
-            // Exploitation: choose the best valid action based on softmax distribution
-            let q_values = &self.agent.q_table[state];
-            let mut softmax_sum = 0.0;
-            let mut softmax_probs = vec![0.0; valid_actions.len()];
-            for (i, &action) in valid_actions.iter().enumerate() {
-                softmax_probs[i] = (q_values[action] / self.softmax_temp).exp();
-                softmax_sum += softmax_probs[i];
-            }
-            let mut rand_val = rng.gen_range(0.0..softmax_sum);
-            for (i, &prob) in softmax_probs.iter().enumerate() {
-                rand_val -= prob;
-                if rand_val <= 0.0 {
-                    return valid_actions[i];
-                }
+            let index = self.rng.gen_range(0..valid_actions.len());
+            return Ok(valid_actions[index]);
+        }
+        let q_values = self.action_values(state);
+        let valid_q_values: Vec<f32> = valid_actions.iter().map(|&action| q_values[action]).collect();
+        if self.softmax_temp <= 0.0 {
+            return Ok(valid_actions[argmax(&valid_q_values)]);
+        }
+        // Exploitation: sample from the softmax distribution. Shifting by the largest Q-value keeps
+        // every exponent at or below zero, so nothing overflows and the best action has weight 1.
+        let max_q_value = valid_q_values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let softmax_probs: Vec<f32> = valid_q_values
+            .iter()
+            .map(|q_value| ((q_value - max_q_value) / self.softmax_temp).exp())
+            .collect();
+        let softmax_sum: f32 = softmax_probs.iter().sum();
+        let mut rand_val = self.rng.gen::<f32>() * softmax_sum;
+        for (i, &prob) in softmax_probs.iter().enumerate() {
+            rand_val -= prob;
+            if rand_val <= 0.0 {
+                return Ok(valid_actions[i]);
             }
-            // If no action is selected due to floating-point issues, choose the first valid action
-            valid_actions[0]
         }
+        // Rounding can leave a sliver of the sum unassigned; it belongs to the last action
+        Ok(valid_actions[valid_actions.len() - 1])
     }
 
     // Update Q-values based on a batch of experiences from the replay buffer.
-    // This method samples a batch of experiences to update the agent's knowledge.
+    // Experiences are sampled by priority; each TD update is scaled by the sample's importance-sampling
+    // weight, and the experience's priority is then set to the size of its TD error.
     pub fn update_q_values(&mut self) {
         if self.replay_buffer.len() < self.batch_size {
             return;
         }
-        let mut batch = Vec::new();
-        for _ in 0..self.batch_size {
-            batch.push(self.replay_buffer.pop().unwrap());
-        }
-        for experience in &batch {
+        let batch = self.replay_buffer.sample(self.batch_size, self.beta, &mut self.rng);
+        for sample in batch {
+            let experience = self.replay_buffer.get(sample.index).clone();
             let state = experience.state;
             let action = experience.action;
             let reward = experience.reward;
             let next_state = experience.next_state;
-            let old_q_value = self.agent.q_table[state][action];
-            let next_state_q_values = &self.agent.q_table[next_state];
-            let max_next_q_value = next_state_q_values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
-            let td_error = reward + self.gamma * max_next_q_value - old_q_value;
-            self.eligibility_traces[state][action] += 1.0;
-            // Update Q-values across all states and actions using the eligibility traces,
-            // allowing for more effective learning over sequences of actions.
-            for s in 0..self.agent.state {
-                for a in 0..self.agent.q_table[s].len() {
-                    self.agent.q_table[s][a] += self.learning_rate * td_error * self.eligibility_traces[s][a];
-                    self.eligibility_traces[s][a] *= self.gamma;
-                }
+            if self.double_q {
+                // A coin flip picks the table to update; the other table evaluates the next
+                // action selected by the updated one.
+                let (q_table, evaluation_table) = if self.rng.gen::<bool>() {
+                    (&mut self.second_q_table, &self.agent.q_table)
+                } else {
+                    (&mut self.agent.q_table, &self.second_q_table)
+                };
+                let best_next_action = argmax(&q_table[next_state]);
+                let next_value = if experience.done { 0.0 } else { evaluation_table[next_state][best_next_action] };
+                let td_error = reward + self.gamma * next_value - q_table[state][action];
+                Self::apply_td_error(q_table, &mut self.eligibility_traces, state, action, td_error * sample.weight, self.learning_rate, self.gamma);
+                self.replay_buffer.update_priority(sample.index, td_error);
+            } else {
+                let q_table = &mut self.agent.q_table;
+                let max_next_q_value = if experience.done {
+                    0.0
+                } else {
+                    q_table[next_state].iter().cloned().fold(f32::NEG_INFINITY, f32::max)
+                };
+                let td_error = reward + self.gamma * max_next_q_value - q_table[state][action];
+                Self::apply_td_error(q_table, &mut self.eligibility_traces, state, action, td_error * sample.weight, self.learning_rate, self.gamma);
+                self.replay_buffer.update_priority(sample.index, td_error);
+            }
+        }
+    }
+
+    // Update Q-values across all states and actions using the eligibility traces,
+    // allowing for more effective learning over sequences of actions.
+    fn apply_td_error(
+        q_table: &mut [Vec<f32>],
+        eligibility_traces: &mut [Vec<f32>],
+        state: usize,
+        action: usize,
+        td_error: f32,
+        learning_rate: f32,
+        gamma: f32,
+    ) {
+        eligibility_traces[state][action] += 1.0;
+        for s in 0..q_table.len() {
+            for a in 0..q_table[s].len() {
+                q_table[s][a] += learning_rate * td_error * eligibility_traces[s][a];
+                eligibility_traces[s][a] *= gamma;
             }
         }
     }
 
-    // Add an experience to the replay buffer with a simple priority scheme based on the absolute reward.
+    // Add an experience to the replay buffer; it starts at the highest priority seen so it is replayed soon.
     pub fn add_experience(&mut self, state: usize, action: usize, reward: f32, next_state: usize) {
-        let priority = reward.abs(); // Simple priority based on absolute reward
+        self.push_experience(state, action, reward, next_state, false);
+    }
+
+    fn push_experience(&mut self, state: usize, action: usize, reward: f32, next_state: usize, done: bool) {
         let experience = Experience {
             state,
             action,
             reward,
             next_state,
-            priority,
+            done,
         };
         self.replay_buffer.push(experience);
     }
 
+    // Runs `episodes` episodes of at most `max_steps` steps each, learning after every step, and
+    // returns the total reward of each episode. Eligibility traces are cleared between episodes
+    // and the exploration rate decays over the run.
+    pub fn train<E: Environment>(&mut self, env: &mut E, episodes: usize, max_steps: usize) -> Vec<f32> {
+        let mut episode_returns = Vec::with_capacity(episodes);
+        for episode in 0..episodes {
+            for traces in &mut self.eligibility_traces {
+                traces.iter_mut().for_each(|trace| *trace = 0.0);
+            }
+            let mut state = env.reset();
+            self.set_state(state);
+            let mut episode_return = 0.0;
+
+            for _ in 0..max_steps {
+                // A state with nothing to do ends the episode
+                let Ok(action) = self.choose_action(state, &env.valid_actions(state)) else {
+                    break;
+                };
+                let (next_state, reward, done) = env.step(action);
+                self.push_experience(state, action, reward, next_state, done);
+                self.update_q_values();
+
+                episode_return += reward;
+                state = next_state;
+                self.set_state(state);
+                if done {
+                    break;
+                }
+            }
+
+            self.update_exploration_rate(episode, episodes);
+            episode_returns.push(episode_return);
+        }
+        episode_returns
+    }
+
     // Dynamically adjust the exploration rate based on the number of iterations,
     // encouraging exploration early on and exploitation later.
     pub fn update_exploration_rate(&mut self, iteration: usize, max_iterations: usize) {
-        let min_exploration_rate = 0.01;
-        let decay_rate = (self.exploration_rate - min_exploration_rate) / max_iterations as f32;
-        self.exploration_rate = (self.exploration_rate - decay_rate * iteration as f32).max(min_exploration_rate);
+        let min_exploration_rate = self.min_exploration_rate;
+        self.exploration_rate = match self.exploration_decay_rate {
+            Some(decay_rate) => self.exploration_rate * (1.0 - decay_rate),
+            None => {
+                let decay_rate = (self.exploration_rate - min_exploration_rate) / max_iterations as f32;
+                self.exploration_rate - decay_rate * iteration as f32
+            }
+        }
+        .max(min_exploration_rate);
+    }
+
+    pub fn exploration_rate(&self) -> f32 {
+        self.exploration_rate
+    }
+
+    // The prioritization exponent and importance-sampling correction used for replay.
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    pub fn beta(&self) -> f32 {
+        self.beta
     }
 
     // Save the current Q-table to a file.
@@ -288,6 +515,15 @@ impl QLearningAgent {
     }
 }
 
+// Index of the highest value; the first one on ties.
+fn argmax(values: &[f32]) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .fold((0, f32::NEG_INFINITY), |best, (i, &value)| if value > best.1 { (i, value) } else { best })
+        .0
+}
+
 // Helper functions for encryption and storage (to be implemented separately)
 fn encrypt_data(data: &str, key: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     // Implement the logic to encrypt the data using the provided encryption key
@@ -310,3 +546,176 @@ fn save_mapping_to_storage(mapping: &HashMap<String, String>) -> Result<(), Box<
     // Implement the logic to save the Q-table mapping to a persistent storage
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const NUM_ACTIONS: usize = 8;
+    const GAMMA: f32 = 0.5;
+    const MEAN_REWARD: f32 = -0.1;
+
+    // Classic overestimation MDP: state 0 moves to state 1 with no reward, and every action in
+    // state 1 ends the episode (state 2) with a noisy reward averaging -0.1. The true value of
+    // state 0 is therefore GAMMA * -0.1, but a max over noisy estimates of state 1 is positive.
+    // Returns the mean estimate of state 0 over the second half of training.
+    fn estimate_start_value(mut agent: QLearningAgent, seed: u64) -> f32 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let updates = 20_000;
+        let mut estimates = Vec::new();
+        for i in 0..updates {
+            agent.add_experience(0, 0, 0.0, 1);
+            agent.update_q_values();
+            let reward = rng.gen_range(MEAN_REWARD - 1.0..MEAN_REWARD + 1.0);
+            agent.add_experience(1, i % NUM_ACTIONS, reward, 2);
+            agent.update_q_values();
+            if i >= updates / 2 {
+                estimates.push(agent.action_values(0)[0]);
+            }
+        }
+        estimates.iter().sum::<f32>() / estimates.len() as f32
+    }
+
+    // Q-tables start with random values; the terminal state is zeroed so it contributes nothing
+    fn agent() -> QLearningAgent {
+        let mut agent = QLearningAgent::new(3, NUM_ACTIONS, GAMMA, 0.1, 0.0, 1, 1.0);
+        agent.agent.q_table[2] = vec![0.0; NUM_ACTIONS];
+        agent
+    }
+
+    #[test]
+    fn test_double_q_is_less_biased_than_single_q() {
+        let true_value = GAMMA * MEAN_REWARD;
+        let single_bias = (estimate_start_value(agent(), 7) - true_value).abs();
+        let double_bias = (estimate_start_value(agent().with_double_q(), 7) - true_value).abs();
+        assert!(
+            double_bias < single_bias,
+            "double-Q bias {} should be below single-Q bias {}",
+            double_bias,
+            single_bias
+        );
+    }
+
+    // 4x4 grid starting in the top-left corner; reaching the bottom-right corner ends the episode.
+    // Actions are up, down, left and right, and moves off the edge leave the agent in place.
+    struct GridWorld {
+        position: usize,
+    }
+
+    const GRID_WIDTH: usize = 4;
+    const GOAL: usize = GRID_WIDTH * GRID_WIDTH - 1;
+
+    impl Environment for GridWorld {
+        fn reset(&mut self) -> usize {
+            self.position = 0;
+            self.position
+        }
+
+        fn step(&mut self, action: usize) -> (usize, f32, bool) {
+            let (mut row, mut column) = (self.position / GRID_WIDTH, self.position % GRID_WIDTH);
+            match action {
+                0 => row = row.saturating_sub(1),
+                1 => row = (row + 1).min(GRID_WIDTH - 1),
+                2 => column = column.saturating_sub(1),
+                _ => column = (column + 1).min(GRID_WIDTH - 1),
+            }
+            self.position = row * GRID_WIDTH + column;
+            if self.position == GOAL {
+                (self.position, 1.0, true)
+            } else {
+                (self.position, -0.05, false)
+            }
+        }
+
+        fn valid_actions(&self, _state: usize) -> Vec<usize> {
+            vec![0, 1, 2, 3]
+        }
+    }
+
+    #[test]
+    fn test_train_learns_shortest_path_to_goal() {
+        let mut env = GridWorld { position: 0 };
+        let config = QLearningConfig {
+            gamma: 0.8,
+            learning_rate: 0.05,
+            exploration_rate: 1.0,
+            softmax_temp: 0.1,
+            ..QLearningConfig::new(GRID_WIDTH * GRID_WIDTH, 4)
+        };
+        let mut agent = QLearningAgent::with_seed(config, 11);
+        let returns = agent.train(&mut env, 300, 50);
+        assert_eq!(returns.len(), 300);
+
+        // Following the greedy policy reaches the goal in the minimum of six moves
+        let mut state = env.reset();
+        let mut moves = 0;
+        while state != GOAL && moves < 10 {
+            state = env.step(argmax(&agent.action_values(state))).0;
+            moves += 1;
+        }
+        assert_eq!(state, GOAL);
+        assert_eq!(moves, 2 * (GRID_WIDTH - 1));
+    }
+
+    #[test]
+    fn test_action_values_average_both_tables() {
+        let mut agent = agent().with_double_q();
+        agent.agent.q_table[0] = vec![1.0; NUM_ACTIONS];
+        agent.second_q_table[0] = vec![3.0; NUM_ACTIONS];
+        assert!(agent.is_double_q());
+        assert_eq!(agent.action_values(0), vec![2.0; NUM_ACTIONS]);
+    }
+
+    #[test]
+    fn test_from_config_decays_exploration_to_configured_floor() {
+        let agent = QLearningAgent::new(3, 2, 0.9, 0.1, 0.5, 4, 1.0);
+        assert_eq!(agent.min_exploration_rate, DEFAULT_MIN_EXPLORATION_RATE);
+        assert_eq!(agent.exploration_decay_rate, None);
+
+        let mut agent = QLearningAgent::from_config(QLearningConfig {
+            exploration_rate: 0.5,
+            min_exploration_rate: 0.2,
+            exploration_decay_rate: Some(0.5),
+            ..QLearningConfig::new(3, 2)
+        });
+        agent.update_exploration_rate(0, 10);
+        assert!((agent.exploration_rate() - 0.25).abs() < 1e-6);
+        agent.update_exploration_rate(1, 10);
+        assert_eq!(agent.exploration_rate(), 0.2);
+    }
+
+    #[test]
+    fn test_choose_action_is_stable_for_large_q_values() {
+        let mut agent = QLearningAgent::new(1, 3, 0.9, 0.1, 0.0, 1, 1.0);
+        agent.agent.q_table[0] = vec![10_000.0, 9_000.0, 20_000.0];
+        for _ in 0..50 {
+            assert_eq!(agent.choose_action(0, &[0, 1]), Ok(0));
+        }
+
+        agent.softmax_temp = 0.0;
+        assert_eq!(agent.choose_action(0, &[0, 1, 2]), Ok(2));
+        assert_eq!(agent.choose_action(0, &[]), Err(QLearningError::NoValidActions(0)));
+    }
+
+    #[test]
+    fn test_same_seed_gives_identical_q_tables() {
+        let config = QLearningConfig {
+            batch_size: 4,
+            ..QLearningConfig::new(5, 3)
+        };
+        let run = |seed| {
+            let mut agent = QLearningAgent::with_seed(config.clone(), seed).with_double_q();
+            for step in 0..200 {
+                let state = step % 5;
+                let action = agent.choose_action(state, &[0, 1, 2]).unwrap();
+                agent.add_experience(state, action, (step % 7) as f32 - 3.0, (state + 1) % 5);
+                agent.update_q_values();
+            }
+            (agent.agent.q_table.clone(), agent.second_q_table.clone())
+        };
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+    }
+}
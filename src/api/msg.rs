@@ -27,7 +27,7 @@ lazy_static! {
         );
         let vault_store = KeysStore::new(Arc::new(secret_client));
         let encrypt_handler = EncryptHandler::new(vault_store);
-        PIIHandler::new(Arc::new(encrypt_handler))
+        PIIHandler::new(Arc::new(encrypt_handler)).unwrap()
     };
 }
 
@@ -174,21 +174,21 @@ impl FromStr for EntityLabel {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "PERSON" => Ok(EntityLabel::Person),
-            "ORG" => Ok(EntityLabel::Organization),
-            "GPE" => Ok(EntityLabel::GeopoliticalEntity),
-            "LOC" => Ok(EntityLabel::Location),
-            "PRODUCT" => Ok(EntityLabel::Product),
-            "EVENT" => Ok(EntityLabel::Event),
-            "WORK_OF_ART" => Ok(EntityLabel::WorkOfArt),
-            "LAW" => Ok(EntityLabel::Law),
-            "LANGUAGE" => Ok(EntityLabel::Language),
+            "ORG" => Ok(EntityLabel::Org),
+            "GPE" => Ok(EntityLabel::Gpe),
+            "LOC" => Ok(EntityLabel::Loc),
             "DATE" => Ok(EntityLabel::Date),
             "TIME" => Ok(EntityLabel::Time),
-            "PERCENT" => Ok(EntityLabel::Percent),
             "MONEY" => Ok(EntityLabel::Money),
-            "QUANTITY" => Ok(EntityLabel::Quantity),
-            "ORDINAL" => Ok(EntityLabel::Ordinal),
+            "NORP" => Ok(EntityLabel::Norp),
+            "WORK_OF_ART" => Ok(EntityLabel::WorkOfArt),
+            "PHONE" => Ok(EntityLabel::Phone),
+            "EMAIL" => Ok(EntityLabel::Email),
             "CARDINAL" => Ok(EntityLabel::Cardinal),
+            // spaCy labels the graph doesn't distinguish
+            "PRODUCT" | "EVENT" | "LAW" | "LANGUAGE" | "PERCENT" | "QUANTITY" | "ORDINAL" | "FAC" => {
+                Ok(EntityLabel::Unknown)
+            }
             _ => Err(BigbotError::EntityLabelConversionError(format!("Invalid entity label: {}", s))),
         }
     }
@@ -211,6 +211,10 @@ impl EntityGraph {
         let texts = self.entities.entry(label).or_insert(HashSet::new());
         texts.insert(text);
     }
+
+    pub fn entities_of(&self, label: &EntityLabel) -> impl Iterator<Item = &str> {
+        self.entities.get(label).into_iter().flatten().map(String::as_str)
+    }
 }
 
 #[pyclass]
@@ -242,6 +246,10 @@ impl SpacyEntity {
     pub fn text(&self, py: Python) -> Result<String, BigbotError> {
         Ok(self.obj.getattr(py, "text")?.extract(py)?)
     }
+
+    pub fn label(&self, py: Python) -> Result<String, BigbotError> {
+        Ok(self.obj.getattr(py, "label_")?.extract(py)?)
+    }
 }
 
 impl ToPyObject for SpacyEntity {
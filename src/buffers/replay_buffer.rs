@@ -1,5 +1,6 @@
 use rand::seq::SliceRandom;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 pub struct ReplayBuffer<T> {
     buffer: Vec<T>,
@@ -42,6 +43,215 @@ impl<T> ReplayBuffer<T> {
     }
 }
 
+/// A binary tree whose leaves hold priorities and whose inner nodes hold the sum of their
+/// children, so the total is at the root and a prefix-sum lookup takes O(log n).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SumTree {
+    capacity: usize,
+    // Node i has children 2i and 2i + 1; leaves start at `capacity`
+    nodes: Vec<f32>,
+}
+
+impl SumTree {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        SumTree {
+            capacity,
+            nodes: vec![0.0; 2 * capacity],
+        }
+    }
+
+    pub fn total(&self) -> f32 {
+        self.nodes[1]
+    }
+
+    pub fn get(&self, index: usize) -> f32 {
+        self.nodes[self.capacity + index]
+    }
+
+    pub fn set(&mut self, index: usize, priority: f32) {
+        let mut node = self.capacity + index;
+        self.nodes[node] = priority;
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+
+    /// Returns the leaf whose cumulative priority range contains `value`, for `0 <= value < total`.
+    pub fn find(&self, mut value: f32) -> usize {
+        let mut node = 1;
+        while node < self.capacity {
+            let left = 2 * node;
+            if value < self.nodes[left] || self.nodes[left + 1] <= 0.0 {
+                node = left;
+            } else {
+                value -= self.nodes[left];
+                node = left + 1;
+            }
+        }
+        node - self.capacity
+    }
+}
+
+/// An experience drawn from a `PrioritizedReplayBuffer`, with the importance-sampling weight that
+/// corrects for it being drawn more often than under uniform sampling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrioritizedSample {
+    pub index: usize,
+    pub weight: f32,
+}
+
+/// A bounded replay buffer that samples each experience with probability proportional to
+/// `priority^alpha`. New experiences get the highest priority seen so far, so each is likely to be
+/// replayed at least once; once full, the oldest experience is overwritten.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrioritizedReplayBuffer<T> {
+    items: Vec<T>,
+    tree: SumTree,
+    capacity: usize,
+    next: usize,
+    alpha: f32,
+    max_priority: f32,
+}
+
+// Keeps experiences with a zero TD error sampleable
+const MIN_PRIORITY: f32 = 1e-5;
+
+impl<T> PrioritizedReplayBuffer<T> {
+    pub fn new(capacity: usize, alpha: f32) -> Self {
+        let capacity = capacity.max(1);
+        PrioritizedReplayBuffer {
+            items: Vec::with_capacity(capacity),
+            tree: SumTree::new(capacity),
+            capacity,
+            next: 0,
+            alpha,
+            max_priority: 1.0,
+        }
+    }
+
+    pub fn push(&mut self, experience: T) {
+        if self.items.len() < self.capacity {
+            self.items.push(experience);
+        } else {
+            self.items[self.next] = experience;
+        }
+        self.tree.set(self.next, self.max_priority.powf(self.alpha));
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    pub fn get(&self, index: usize) -> &T {
+        &self.items[index]
+    }
+
+    /// Sets an experience's priority, usually to the magnitude of its latest TD error.
+    pub fn update_priority(&mut self, index: usize, priority: f32) {
+        let priority = priority.abs().max(MIN_PRIORITY);
+        self.max_priority = self.max_priority.max(priority);
+        self.tree.set(index, priority.powf(self.alpha));
+    }
+
+    /// Draws `sample_size` experiences, with replacement, by priority. Each weight is
+    /// `(n * P(i))^-beta`, normalised by the largest weight in the batch so updates only shrink.
+    pub fn sample<R: Rng>(&self, sample_size: usize, beta: f32, rng: &mut R) -> Vec<PrioritizedSample> {
+        let total = self.tree.total();
+        if self.items.is_empty() || total <= 0.0 {
+            return Vec::new();
+        }
+        // Stratified: one draw from each of `sample_size` equal slices of the priority mass
+        let segment = total / sample_size as f32;
+        let n = self.items.len() as f32;
+        let mut samples: Vec<PrioritizedSample> = (0..sample_size)
+            .map(|i| {
+                let value = (segment * (i as f32 + rng.gen::<f32>())).min(total * (1.0 - f32::EPSILON));
+                let index = self.tree.find(value).min(self.items.len() - 1);
+                let probability = self.tree.get(index) / total;
+                PrioritizedSample {
+                    index,
+                    weight: (n * probability).powf(-beta),
+                }
+            })
+            .collect();
+        let max_weight = samples.iter().map(|s| s.weight).fold(0.0, f32::max);
+        if max_weight > 0.0 && max_weight.is_finite() {
+            samples.iter_mut().for_each(|s| s.weight /= max_weight);
+        }
+        samples
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_sum_tree_finds_leaf_by_prefix_sum() {
+        let mut tree = SumTree::new(4);
+        for (i, p) in [1.0, 2.0, 3.0, 4.0].into_iter().enumerate() {
+            tree.set(i, p);
+        }
+        assert_eq!(tree.total(), 10.0);
+        assert_eq!(tree.find(0.5), 0);
+        assert_eq!(tree.find(1.0), 1);
+        assert_eq!(tree.find(5.9), 2);
+        assert_eq!(tree.find(9.9), 3);
+        tree.set(3, 0.0);
+        assert_eq!(tree.total(), 6.0);
+        assert_eq!(tree.find(5.9), 2);
+    }
+
+    #[test]
+    fn test_sampling_follows_priority_and_weights_correct_for_it() {
+        let mut buffer = PrioritizedReplayBuffer::new(3, 1.0);
+        for item in ["rare", "common", "never"] {
+            buffer.push(item);
+        }
+        buffer.update_priority(0, 1.0);
+        buffer.update_priority(1, 9.0);
+        buffer.update_priority(2, 0.0);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut counts = [0usize; 3];
+        let mut rare_weight = 0.0;
+        for _ in 0..2000 {
+            for sample in buffer.sample(2, 1.0, &mut rng) {
+                counts[sample.index] += 1;
+                if sample.index == 0 {
+                    rare_weight = sample.weight;
+                }
+            }
+        }
+        // Priority 1 vs 9 is a 10% share; the zero-priority experience is all but never drawn
+        let share = counts[0] as f32 / 4000.0;
+        assert!((0.07..0.13).contains(&share), "rare share {}", share);
+        assert!(counts[2] < 5);
+        assert_eq!(rare_weight, 1.0);
+        assert_eq!(*buffer.get(1), "common");
+    }
+
+    #[test]
+    fn test_full_buffer_overwrites_oldest() {
+        let mut buffer = PrioritizedReplayBuffer::new(2, 0.6);
+        buffer.push(1);
+        buffer.push(2);
+        buffer.push(3);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(*buffer.get(0), 3);
+        assert_eq!(*buffer.get(1), 2);
+    }
+}
+
 /*
 This module implements a replay buffer data structure (used in the reinforcement learning algorithms). It stores a fixed-size buffer of experiences, and when the buffer is full, new experiences are randomly added to replace older ones.
 
@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use crate::utils::bigboterror::BigbotError;
 use thiserror::Error;
@@ -23,6 +24,10 @@ impl From<KVError> for BigbotError {
     }
 }
 
+// The current time as a store sees it. Stores with expiry take one so tests can move time
+// forward instead of sleeping.
+pub type Clock<T> = Arc<dyn Fn() -> T + Send + Sync>;
+
 // Define the KVStore trait with async methods
 #[async_trait]
 pub trait KVStore: Send + Sync {
@@ -30,6 +35,59 @@ pub trait KVStore: Send + Sync {
     async fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), BigbotError>;
     async fn delete(&self, key: &[u8]) -> Result<(), BigbotError>;
     async fn keys(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, BigbotError>;
+
+    // Stores the value only if the key is absent, returning whether it was stored. With a `ttl`
+    // the entry expires after that long. The default isn't atomic and, like `put_with_ttl`,
+    // refuses a TTL it can't honour; stores that can do better should override it.
+    async fn set_if_absent(&self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>) -> Result<bool, BigbotError> {
+        if ttl.is_some() {
+            return Err(expiry_unsupported(&key));
+        }
+        if self.get(&key).await?.is_some() {
+            return Ok(false);
+        }
+        self.set(key, value).await?;
+        Ok(true)
+    }
+
+    // Stores the value so that it expires after `ttl`. Stores without expiry return an error
+    // rather than keep the entry forever.
+    async fn put_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<(), BigbotError> {
+        let _ = (value, ttl);
+        Err(expiry_unsupported(&key))
+    }
+
+    // Returns up to `limit` live entries whose keys start with `sub_prefix`, in key order. Keys
+    // are relative to the store, so a prefixed store strips its namespace. Stores that can't scan
+    // return an error.
+    async fn scan_prefix(&self, sub_prefix: &str, limit: usize) -> Result<Vec<(String, Vec<u8>)>, BigbotError> {
+        let _ = limit;
+        Err(BigbotError::DatabaseError(format!(
+            "Store does not support prefix scans (prefix '{}')",
+            sub_prefix
+        )))
+    }
+
+    // Stores `value` only if the key currently holds `expected` (`None` meaning absent), returning
+    // whether it was stored. Stores that can't do this atomically return an error.
+    async fn compare_and_swap(&self, key: Vec<u8>, expected: Option<Vec<u8>>, value: Vec<u8>) -> Result<bool, BigbotError> {
+        let _ = (expected, value);
+        Err(BigbotError::DatabaseError(format!(
+            "Store does not support compare-and-swap (key '{}')",
+            String::from_utf8_lossy(&key)
+        )))
+    }
+}
+
+fn expiry_unsupported(key: &[u8]) -> BigbotError {
+    BigbotError::DatabaseError(format!(
+        "Store does not support expiring entries (key '{}')",
+        String::from_utf8_lossy(key)
+    ))
+}
+
+pub(crate) fn utf8_key(key: Vec<u8>) -> Result<String, BigbotError> {
+    String::from_utf8(key).map_err(|e| BigbotError::DatabaseError(format!("Scanned key is not UTF-8: {}", e)))
 }
 
 // Implement the KVStore trait for Arc<dyn KVStore>
@@ -50,6 +108,22 @@ impl KVStore for Arc<dyn KVStore> {
     async fn keys(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, BigbotError> {
         self.as_ref().keys(prefix).await
     }
+
+    async fn set_if_absent(&self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>) -> Result<bool, BigbotError> {
+        self.as_ref().set_if_absent(key, value, ttl).await
+    }
+
+    async fn put_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<(), BigbotError> {
+        self.as_ref().put_with_ttl(key, value, ttl).await
+    }
+
+    async fn scan_prefix(&self, sub_prefix: &str, limit: usize) -> Result<Vec<(String, Vec<u8>)>, BigbotError> {
+        self.as_ref().scan_prefix(sub_prefix, limit).await
+    }
+
+    async fn compare_and_swap(&self, key: Vec<u8>, expected: Option<Vec<u8>>, value: Vec<u8>) -> Result<bool, BigbotError> {
+        self.as_ref().compare_and_swap(key, expected, value).await
+    }
 }
 
 // Define the PrefixedKVStore struct
@@ -93,23 +167,89 @@ impl<T: KVStore> KVStore for PrefixedKVStore<T> {
     async fn keys(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, BigbotError> {
         self.store.keys(self.make_prefix(prefix).as_slice()).await
     }
+
+    async fn set_if_absent(&self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>) -> Result<bool, BigbotError> {
+        self.store
+            .set_if_absent(self.make_prefix(key.as_slice()), value, ttl)
+            .await
+    }
+
+    async fn put_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<(), BigbotError> {
+        self.store
+            .put_with_ttl(self.make_prefix(key.as_slice()), value, ttl)
+            .await
+    }
+
+    async fn scan_prefix(&self, sub_prefix: &str, limit: usize) -> Result<Vec<(String, Vec<u8>)>, BigbotError> {
+        let namespace = std::str::from_utf8(&self.prefix)
+            .map_err(|e| BigbotError::DatabaseError(format!("Store prefix is not UTF-8: {}", e)))?;
+        let entries = self
+            .store
+            .scan_prefix(&format!("{}{}", namespace, sub_prefix), limit)
+            .await?;
+        Ok(entries
+            .into_iter()
+            .map(|(key, value)| (key[namespace.len()..].to_string(), value))
+            .collect())
+    }
+
+    async fn compare_and_swap(&self, key: Vec<u8>, expected: Option<Vec<u8>>, value: Vec<u8>) -> Result<bool, BigbotError> {
+        self.store
+            .compare_and_swap(self.make_prefix(key.as_slice()), expected, value)
+            .await
+    }
 }
 
 // Define the MemoryKVStore struct for testing purposes
-#[derive(Default)]
 pub struct MemoryKVStore {
-    values: Arc<Mutex<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    values: Arc<Mutex<BTreeMap<Vec<u8>, MemoryEntry>>>,
+    clock: Clock<Instant>,
+}
+
+impl Default for MemoryKVStore {
+    fn default() -> Self {
+        Self {
+            values: Arc::default(),
+            clock: Arc::new(Instant::now),
+        }
+    }
+}
+
+impl MemoryKVStore {
+    pub fn with_clock(mut self, clock: Clock<Instant>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+struct MemoryEntry {
+    value: Vec<u8>,
+    expires_at: Option<Instant>,
 }
 
-// Implement the KVStore trait for MemoryKVStore
+impl MemoryEntry {
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires_at.map_or(true, |expires_at| expires_at > now)
+    }
+}
+
+// Implement the KVStore trait for MemoryKVStore. Expired entries are evicted lazily on access.
 #[async_trait]
 impl KVStore for MemoryKVStore {
     async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BigbotError> {
-        Ok(self.values.lock().await.get(key).cloned())
+        let mut values = self.values.lock().await;
+        match values.get(key) {
+            Some(entry) if entry.is_live((self.clock)()) => Ok(Some(entry.value.clone())),
+            Some(_) => {
+                values.remove(key);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
     }
 
     async fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), BigbotError> {
-        self.values.lock().await.insert(key, value);
+        self.values.lock().await.insert(key, MemoryEntry { value, expires_at: None });
         Ok(())
     }
 
@@ -119,13 +259,165 @@ impl KVStore for MemoryKVStore {
     }
 
     async fn keys(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, BigbotError> {
-        Ok(self
-            .values
-            .lock()
-            .await
-            .iter()
-            .filter(|(k, _)| k.starts_with(prefix))
-            .map(|(k, _)| k.clone())
+        let now = (self.clock)();
+        let mut values = self.values.lock().await;
+        values.retain(|_, entry| entry.is_live(now));
+        Ok(values
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
             .collect())
     }
-}
\ No newline at end of file
+
+    async fn set_if_absent(&self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>) -> Result<bool, BigbotError> {
+        let now = (self.clock)();
+        let mut values = self.values.lock().await;
+        if values.get(&key).map_or(false, |entry| entry.is_live(now)) {
+            return Ok(false);
+        }
+        let expires_at = ttl.map(|ttl| now + ttl);
+        values.insert(key, MemoryEntry { value, expires_at });
+        Ok(true)
+    }
+
+    async fn put_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<(), BigbotError> {
+        let expires_at = Some((self.clock)() + ttl);
+        self.values.lock().await.insert(key, MemoryEntry { value, expires_at });
+        Ok(())
+    }
+
+    async fn scan_prefix(&self, sub_prefix: &str, limit: usize) -> Result<Vec<(String, Vec<u8>)>, BigbotError> {
+        let now = (self.clock)();
+        let mut values = self.values.lock().await;
+        values.retain(|_, entry| entry.is_live(now));
+        values
+            .range(sub_prefix.as_bytes().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(sub_prefix.as_bytes()))
+            .take(limit)
+            .map(|(key, entry)| Ok((utf8_key(key.clone())?, entry.value.clone())))
+            .collect()
+    }
+
+    async fn compare_and_swap(&self, key: Vec<u8>, expected: Option<Vec<u8>>, value: Vec<u8>) -> Result<bool, BigbotError> {
+        let now = (self.clock)();
+        let mut values = self.values.lock().await;
+        let current = values.get(&key).filter(|entry| entry.is_live(now)).map(|entry| &entry.value);
+        if current != expected.as_ref() {
+            return Ok(false);
+        }
+        values.insert(key, MemoryEntry { value, expires_at: None });
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // A clock that only moves when the returned counter of elapsed milliseconds is advanced
+    fn manual_clock() -> (Clock<Instant>, Arc<AtomicU64>) {
+        let start = Instant::now();
+        let elapsed_ms = Arc::new(AtomicU64::new(0));
+        let elapsed = elapsed_ms.clone();
+        let clock: Clock<Instant> = Arc::new(move || start + Duration::from_millis(elapsed.load(Ordering::SeqCst)));
+        (clock, elapsed_ms)
+    }
+
+    #[tokio::test]
+    async fn test_set_if_absent_respects_ttl() {
+        let (clock, elapsed_ms) = manual_clock();
+        let store = PrefixedKVStore::new(MemoryKVStore::default().with_clock(clock), b"ns:".to_vec());
+
+        assert!(store.set_if_absent(b"a".to_vec(), b"1".to_vec(), None).await.unwrap());
+        assert!(!store.set_if_absent(b"a".to_vec(), b"2".to_vec(), None).await.unwrap());
+        assert_eq!(store.get(b"a").await.unwrap(), Some(b"1".to_vec()));
+
+        assert!(store.set_if_absent(b"b".to_vec(), b"1".to_vec(), Some(Duration::from_millis(20))).await.unwrap());
+        assert!(!store.set_if_absent(b"b".to_vec(), b"2".to_vec(), None).await.unwrap());
+        elapsed_ms.store(19, Ordering::SeqCst);
+        assert_eq!(store.get(b"b").await.unwrap(), Some(b"1".to_vec()));
+        elapsed_ms.store(20, Ordering::SeqCst);
+        assert_eq!(store.get(b"b").await.unwrap(), None);
+        assert_eq!(store.keys(b"").await.unwrap(), vec![b"ns:a".to_vec()]);
+        assert!(store.set_if_absent(b"b".to_vec(), b"3".to_vec(), None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_prefixed_scan_strips_namespace() {
+        let inner: Arc<dyn KVStore> = Arc::new(MemoryKVStore::default());
+        let store = PrefixedKVStore::new(inner.clone(), b"ns:".to_vec());
+        for key in ["chan:2", "chan:1", "chan:3", "user:1"] {
+            store.set(key.as_bytes().to_vec(), key.as_bytes().to_vec()).await.unwrap();
+        }
+        inner.set(b"other:chan:9".to_vec(), b"x".to_vec()).await.unwrap();
+
+        let scanned = store.scan_prefix("chan:", 2).await.unwrap();
+        assert_eq!(
+            scanned,
+            vec![
+                ("chan:1".to_string(), b"chan:1".to_vec()),
+                ("chan:2".to_string(), b"chan:2".to_vec()),
+            ]
+        );
+        assert_eq!(store.scan_prefix("", usize::MAX).await.unwrap().len(), 4);
+        assert_eq!(inner.scan_prefix("ns:user", 10).await.unwrap()[0].0, "ns:user:1");
+    }
+
+    #[tokio::test]
+    async fn test_put_with_ttl_expires_entries() {
+        let (clock, elapsed_ms) = manual_clock();
+        let store = MemoryKVStore::default().with_clock(clock);
+        store.put_with_ttl(b"nonce:1".to_vec(), b"a".to_vec(), Duration::from_secs(60)).await.unwrap();
+        store.put_with_ttl(b"nonce:2".to_vec(), b"b".to_vec(), Duration::from_secs(3600)).await.unwrap();
+        assert_eq!(store.get(b"nonce:1").await.unwrap(), Some(b"a".to_vec()));
+        elapsed_ms.store(60_000, Ordering::SeqCst);
+
+        assert_eq!(store.get(b"nonce:1").await.unwrap(), None);
+        assert_eq!(store.get(b"nonce:2").await.unwrap(), Some(b"b".to_vec()));
+        assert_eq!(
+            store.scan_prefix("nonce:", 10).await.unwrap(),
+            vec![("nonce:2".to_string(), b"b".to_vec())]
+        );
+        assert!(store.values.lock().await.get(b"nonce:1".as_slice()).is_none());
+    }
+
+    // Stores without expiry refuse a TTL rather than keep the entry forever
+    #[tokio::test]
+    async fn test_default_ttl_methods_refuse_expiry() {
+        struct NoExpiry(MemoryKVStore);
+
+        #[async_trait]
+        impl KVStore for NoExpiry {
+            async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BigbotError> {
+                self.0.get(key).await
+            }
+            async fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), BigbotError> {
+                self.0.set(key, value).await
+            }
+            async fn delete(&self, key: &[u8]) -> Result<(), BigbotError> {
+                self.0.delete(key).await
+            }
+            async fn keys(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, BigbotError> {
+                self.0.keys(prefix).await
+            }
+        }
+
+        let store = NoExpiry(MemoryKVStore::default());
+        assert!(store.put_with_ttl(b"a".to_vec(), b"1".to_vec(), Duration::from_secs(1)).await.is_err());
+        assert!(store.set_if_absent(b"a".to_vec(), b"1".to_vec(), Some(Duration::from_secs(1))).await.is_err());
+        assert_eq!(store.get(b"a").await.unwrap(), None);
+        assert!(store.set_if_absent(b"a".to_vec(), b"1".to_vec(), None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_compare_and_swap_only_replaces_the_expected_value() {
+        let store = PrefixedKVStore::new(MemoryKVStore::default(), b"ns:".to_vec());
+        assert!(store.compare_and_swap(b"k".to_vec(), None, b"a".to_vec()).await.unwrap());
+        // The key is no longer absent
+        assert!(!store.compare_and_swap(b"k".to_vec(), None, b"b".to_vec()).await.unwrap());
+        assert!(!store.compare_and_swap(b"k".to_vec(), Some(b"x".to_vec()), b"b".to_vec()).await.unwrap());
+        assert!(store.compare_and_swap(b"k".to_vec(), Some(b"a".to_vec()), b"b".to_vec()).await.unwrap());
+        assert_eq!(store.get(b"k").await.unwrap(), Some(b"b".to_vec()));
+    }
+}
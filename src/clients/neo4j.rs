@@ -1,9 +1,17 @@
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 
-use neo4rs::Graph;
+use async_trait::async_trait;
+use neo4rs::{query, Graph};
 
+use crate::config::config::{Config, Neo4jConfig};
+use crate::graphs::graph_backend::{GraphBackend, GraphQuery, GraphRow, GraphTransaction};
 use crate::utils::bigboterror::BigbotError;
 
+// Longest wait between two connection attempts; the delay doubles after each failure up to this
+const MAX_CONNECT_RETRY_DELAY: Duration = Duration::from_secs(10);
+
 pub async fn new_neo4j_client(
     uri: &str,
     user: &str,
@@ -16,6 +24,161 @@ pub async fn new_neo4j_client_from_env() -> Result<Arc<Graph>, BigbotError> {
     let uri = std::env::var("NEO4J_URI").unwrap_or_default();
     let user = std::env::var("NEO4J_USER").unwrap_or_default();
     let passwd = std::env::var("NEO4J_PASSWD").unwrap_or_default();
-    println!("uri: {}, user: {}, passwd: {}", uri, user, passwd);
+    log::debug!("Connecting to Neo4j at {} as {}", uri, user);
     new_neo4j_client(&uri, &user, &passwd).await
 }
+
+// A Neo4j connection that is checked with `RETURN 1` before it is handed out. Clones share the
+// same `Graph`, so one client can be passed to every handler.
+#[derive(Clone)]
+pub struct Neo4jClient {
+    graph: Arc<Graph>,
+}
+
+impl Neo4jClient {
+    pub async fn connect(uri: &str, user: &str, password: &str) -> Result<Self, BigbotError> {
+        Self::connect_with(&Neo4jConfig::new(uri, user, password)).await
+    }
+
+    pub async fn from_config(config: &Config) -> Result<Self, BigbotError> {
+        Self::connect_with(&Neo4jConfig::from_config(config)?).await
+    }
+
+    // Retries up to `connect_attempts` times, since the server may still be starting
+    pub async fn connect_with(neo4j: &Neo4jConfig) -> Result<Self, BigbotError> {
+        retry_connect(neo4j.connect_attempts, neo4j.retry_delay, || async {
+            let client = Self {
+                graph: Arc::new(Graph::new(&neo4j.uri, &neo4j.user, &neo4j.password).await?),
+            };
+            client.ping().await?;
+            Ok(client)
+        })
+        .await
+    }
+
+    pub fn graph(&self) -> Arc<Graph> {
+        self.graph.clone()
+    }
+
+    // Runs `RETURN 1` to check the server is reachable and answering queries
+    pub async fn ping(&self) -> Result<(), BigbotError> {
+        let mut rows = self.graph.execute(query("RETURN 1 AS ok")).await?;
+        let Some(row) = rows.next().await? else {
+            return Err(BigbotError::DatabaseError(
+                "Neo4j returned no row for ping".to_string(),
+            ));
+        };
+        match row.get::<i64>("ok") {
+            Ok(1) => Ok(()),
+            Ok(other) => Err(BigbotError::DatabaseError(format!(
+                "unexpected Neo4j ping response: {}",
+                other
+            ))),
+            Err(e) => Err(BigbotError::DatabaseError(format!(
+                "unreadable Neo4j ping response: {}",
+                e
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl GraphBackend for Neo4jClient {
+    async fn run(&self, query: GraphQuery) -> Result<(), BigbotError> {
+        GraphBackend::run(self.graph.as_ref(), query).await
+    }
+
+    async fn query_rows(&self, query: GraphQuery) -> Result<Vec<GraphRow>, BigbotError> {
+        GraphBackend::query_rows(self.graph.as_ref(), query).await
+    }
+
+    async fn begin<'a>(&'a self) -> Result<Box<dyn GraphTransaction + 'a>, BigbotError> {
+        GraphBackend::begin(self.graph.as_ref()).await
+    }
+}
+
+// Calls `connect` until it succeeds or `attempts` calls have failed, doubling the delay between
+// calls each time
+async fn retry_connect<T, F, Fut>(
+    attempts: u32,
+    delay: Duration,
+    mut connect: F,
+) -> Result<T, BigbotError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, BigbotError>>,
+{
+    let attempts = attempts.max(1);
+    let mut delay = delay;
+    let mut attempt = 1;
+    loop {
+        match connect().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= attempts => {
+                return Err(BigbotError::DatabaseError(format!(
+                    "failed to connect to Neo4j after {} attempts: {}",
+                    attempts, e
+                )));
+            }
+            Err(e) => {
+                log::warn!(
+                    "Neo4j connection attempt {} of {} failed: {}",
+                    attempt,
+                    attempts,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay = delay.saturating_mul(2).min(MAX_CONNECT_RETRY_DELAY);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn test_connect_retries_before_failing() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), BigbotError> = retry_connect(3, Duration::ZERO, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(BigbotError::DatabaseError("connection refused".to_string()))
+        })
+        .await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(
+            matches!(result, Err(BigbotError::DatabaseError(ref msg)) if msg.contains("after 3 attempts"))
+        );
+
+        // A server that comes up on the second attempt is connected to without further retries
+        let calls = AtomicU32::new(0);
+        let result = retry_connect(3, Duration::ZERO, || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(BigbotError::DatabaseError("connection refused".to_string())),
+                _ => Ok("connected"),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), "connected");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    // Runs against the server described by NEO4J_URI, NEO4J_USER and NEO4J_PASSWD and is skipped
+    // without them
+    #[tokio::test]
+    async fn test_ping_live_server() {
+        let Ok(uri) = std::env::var("NEO4J_URI") else {
+            eprintln!("skipping: NEO4J_URI is not set");
+            return;
+        };
+        let user = std::env::var("NEO4J_USER").unwrap_or_else(|_| "neo4j".to_string());
+        let password = std::env::var("NEO4J_PASSWD").unwrap_or_default();
+        let client = Neo4jClient::connect(&uri, &user, &password).await.unwrap();
+        client.ping().await.unwrap();
+        // Clones share the cached connection pool
+        assert!(Arc::ptr_eq(&client.graph(), &client.clone().graph()));
+    }
+}
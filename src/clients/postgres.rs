@@ -2,10 +2,13 @@ use crate::clients::kv::KVStore;
 use crate::utils::bigboterror::BigbotError;
 
 use async_trait::async_trait;
+use deadpool_postgres::{Config, ManagerConfig, Pool, PoolConfig, RecyclingMethod, Runtime};
+use futures::future::BoxFuture;
 use std::sync::Arc;
 use std::sync::OnceLock;
 use thiserror::Error;
-use tokio_postgres::{Client, NoTls};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, NoTls, Row, Transaction};
 
 pub static PG_CLIENT: OnceLock<Arc<Client>> = OnceLock::new();
 
@@ -39,6 +42,167 @@ pub async fn init_postgres_client_from_env() -> Result<(), PostgresError> {
     Ok(())
 }
 
+// Connections the pool opens at most unless told otherwise
+pub const DEFAULT_MAX_CONNECTIONS: usize = 16;
+
+// Where and as whom a `PostgresClient` connects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: Option<String>,
+    pub dbname: String,
+    pub max_connections: usize,
+}
+
+impl ConnectionInfo {
+    pub fn new(host: &str, port: u16, user: &str, dbname: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            user: user.to_string(),
+            password: None,
+            dbname: dbname.to_string(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+
+    pub fn with_password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    // Reads POSTGRES_ADDR (host:port), POSTGRES_USER, POSTGRES_PASSWORD and POSTGRES_DB, with the
+    // same defaults as `init_postgres_client_from_env`
+    pub fn from_env() -> Result<Self, BigbotError> {
+        let addr = std::env::var("POSTGRES_ADDR").unwrap_or_else(|_| "127.0.0.1:5432".to_string());
+        let (host, port) = match addr.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port.parse::<u16>().map_err(|e| {
+                    BigbotError::InvalidInput(format!(
+                        "invalid POSTGRES_ADDR port {:?}: {}",
+                        port, e
+                    ))
+                })?;
+                (host.to_string(), port)
+            }
+            None => (addr, 5432),
+        };
+        let user = std::env::var("POSTGRES_USER").unwrap_or_default();
+        let dbname = std::env::var("POSTGRES_DB").unwrap_or_else(|_| "postgres".to_string());
+        let mut info = Self::new(&host, port, &user, &dbname);
+        info.password = std::env::var("POSTGRES_PASSWORD").ok();
+        Ok(info)
+    }
+
+    fn pool_config(&self) -> Config {
+        let mut cfg = Config::new();
+        cfg.host = Some(self.host.clone());
+        cfg.port = Some(self.port);
+        cfg.user = Some(self.user.clone());
+        cfg.password = self.password.clone();
+        cfg.dbname = Some(self.dbname.clone());
+        cfg.keepalives = Some(true);
+        cfg.manager = Some(ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        });
+        cfg.pool = Some(PoolConfig::new(self.max_connections));
+        cfg
+    }
+}
+
+// A pooled PostgreSQL client. Connections are opened lazily as queries need them, and every
+// driver or pool error comes back as `BigbotError::DatabaseError`.
+#[derive(Clone)]
+pub struct PostgresClient {
+    pool: Pool,
+}
+
+impl PostgresClient {
+    pub fn new(info: &ConnectionInfo) -> Result<Self, BigbotError> {
+        let pool = info
+            .pool_config()
+            .create_pool(Some(Runtime::Tokio1), NoTls)
+            .map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+        Ok(Self { pool })
+    }
+
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    pub async fn query(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, BigbotError> {
+        let client = self.connection().await?;
+        Ok(client.query(sql, params).await?)
+    }
+
+    // Returns the number of rows affected
+    pub async fn execute(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, BigbotError> {
+        let client = self.connection().await?;
+        Ok(client.execute(sql, params).await?)
+    }
+
+    // Runs `f` inside a transaction on one pooled connection. The transaction commits if `f`
+    // returns Ok and rolls back if it returns Err, whose error is passed through unchanged even if
+    // the rollback fails.
+    //
+    //     client
+    //         .with_transaction(|tx| {
+    //             Box::pin(async move {
+    //                 tx.execute("UPDATE accounts SET balance = balance - 10 WHERE id = 1", &[])
+    //                     .await?;
+    //                 Ok(())
+    //             })
+    //         })
+    //         .await?;
+    pub async fn with_transaction<T, F>(&self, f: F) -> Result<T, BigbotError>
+    where
+        T: Send,
+        F: for<'t> FnOnce(&'t Transaction<'t>) -> BoxFuture<'t, Result<T, BigbotError>>,
+    {
+        let mut client = self.connection().await?;
+        let tx = client.transaction().await?;
+        match f(&tx).await {
+            Ok(value) => {
+                tx.commit().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                // A failed rollback is logged rather than returned so the caller sees why `f` failed
+                if let Err(rollback_error) = tx.rollback().await {
+                    log::warn!(
+                        "Failed to roll back transaction after {}: {}",
+                        e,
+                        rollback_error
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn connection(&self) -> Result<deadpool_postgres::Object, BigbotError> {
+        self.pool
+            .get()
+            .await
+            .map_err(|e| BigbotError::DatabaseError(e.to_string()))
+    }
+}
+
 // Define a struct for the PostgreSQL table-based key-value client
 pub struct PGTableKVClient {
     pg_client: Arc<Client>,
@@ -132,3 +296,76 @@ impl KVStore for PGTableKVClient {
             .collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    // Runs against the server described by the POSTGRES_* variables and is skipped without them
+    #[tokio::test]
+    async fn test_transaction_rolls_back_on_error() {
+        if std::env::var("POSTGRES_ADDR").is_err() {
+            eprintln!("skipping: POSTGRES_ADDR is not set");
+            return;
+        }
+        let client = PostgresClient::new(&ConnectionInfo::from_env().unwrap()).unwrap();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let table = format!("pg_client_test_{}_{}", std::process::id(), nanos);
+        client
+            .execute(
+                &format!(
+                    "CREATE TABLE {} (id INT PRIMARY KEY, name TEXT NOT NULL)",
+                    table
+                ),
+                &[],
+            )
+            .await
+            .unwrap();
+
+        let insert = format!("INSERT INTO {} (id, name) VALUES ($1, $2)", table);
+        let select = format!("SELECT name FROM {} ORDER BY id", table);
+
+        let inserted = client
+            .with_transaction(|tx| {
+                let (insert, select) = (insert.clone(), select.clone());
+                Box::pin(async move {
+                    tx.execute(&insert, &[&1i32, &"alice"]).await?;
+                    let rows = tx.query(&select, &[]).await?;
+                    Ok(rows.len())
+                })
+            })
+            .await
+            .unwrap();
+        assert_eq!(inserted, 1);
+
+        let result: Result<(), BigbotError> = client
+            .with_transaction(|tx| {
+                let (insert, select) = (insert.clone(), select.clone());
+                Box::pin(async move {
+                    tx.execute(&insert, &[&2i32, &"bob"]).await?;
+                    // The insert is visible inside the transaction before it is rolled back
+                    assert_eq!(tx.query(&select, &[]).await?.len(), 2);
+                    Err(BigbotError::InvalidInput("forced failure".to_string()))
+                })
+            })
+            .await;
+        assert!(matches!(result, Err(BigbotError::InvalidInput(_))));
+
+        let names: Vec<String> = client
+            .query(&select, &[])
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        client
+            .execute(&format!("DROP TABLE {}", table), &[])
+            .await
+            .unwrap();
+        assert_eq!(names, vec!["alice".to_string()]);
+    }
+}
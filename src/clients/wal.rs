@@ -0,0 +1,442 @@
+//! # Write-Ahead-Logged KV Store
+//!
+//! `WalKVStore` keeps its data in memory like `MemoryKVStore` but survives restarts. Every
+//! mutation is appended to `wal.log` in the store's directory and synced before it is applied,
+//! and every `snapshot_every` mutations the whole state is written to `snapshot` and the log is
+//! truncated. Opening the store loads the snapshot and replays the log on top of it.
+//!
+//! Log records are absolute (set this key to this value, delete this key), so replaying a record
+//! that the snapshot already contains is harmless. That makes a crash at any point of a snapshot
+//! recoverable: the snapshot is written to a temporary file and renamed into place before the log
+//! is truncated. A record torn by a crash mid-append fails its checksum and is discarded along with
+//! anything after it.
+//!
+//! TTLs are stored as wall-clock deadlines so they keep counting down while the process is down.
+
+use crate::clients::kv::{utf8_key, Clock, KVStore};
+use crate::utils::bigboterror::BigbotError;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+pub const DEFAULT_SNAPSHOT_EVERY: usize = 1000;
+
+const WAL_FILE: &str = "wal.log";
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+
+// Each record is framed as a little-endian u32 payload length, the payload, and the first four
+// bytes of the payload's SHA-256
+const LENGTH_BYTES: usize = 4;
+const CHECKSUM_BYTES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct WalEntry {
+    value: Vec<u8>,
+    // Unix time in milliseconds
+    expires_at_ms: Option<u64>,
+}
+
+impl WalEntry {
+    fn is_live(&self, now_ms: u64) -> bool {
+        self.expires_at_ms.map_or(true, |expires_at_ms| expires_at_ms > now_ms)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum WalRecord {
+    Set { key: Vec<u8>, entry: WalEntry },
+    Delete { key: Vec<u8> },
+}
+
+struct WalState {
+    values: BTreeMap<Vec<u8>, WalEntry>,
+    wal: File,
+    records_since_snapshot: usize,
+}
+
+pub struct WalKVStore {
+    dir: PathBuf,
+    snapshot_every: usize,
+    // Unix time in milliseconds
+    clock: Clock<u64>,
+    state: Mutex<WalState>,
+}
+
+impl WalKVStore {
+    // Opens the store in `dir`, creating it if needed and recovering any existing state
+    pub async fn open(dir: impl AsRef<Path>) -> Result<Self, BigbotError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).await?;
+
+        let mut values: BTreeMap<Vec<u8>, WalEntry> = match fs::read(dir.join(SNAPSHOT_FILE)).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| BigbotError::DatabaseError(format!("Corrupt snapshot in {}: {}", dir.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let wal_path = dir.join(WAL_FILE);
+        let log = match fs::read(&wal_path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let (records, valid_len) = decode_records(&log);
+        let records_since_snapshot = records.len();
+        for record in records {
+            apply(&mut values, record);
+        }
+
+        let wal = OpenOptions::new().create(true).write(true).open(&wal_path).await?;
+        // Drop a torn tail so new records follow the last complete one
+        wal.set_len(valid_len as u64).await?;
+        let wal = OpenOptions::new().append(true).open(&wal_path).await?;
+
+        Ok(Self {
+            dir,
+            snapshot_every: DEFAULT_SNAPSHOT_EVERY,
+            clock: Arc::new(now_ms),
+            state: Mutex::new(WalState {
+                values,
+                wal,
+                records_since_snapshot,
+            }),
+        })
+    }
+
+    pub fn with_snapshot_every(mut self, snapshot_every: usize) -> Self {
+        self.snapshot_every = snapshot_every.max(1);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Clock<u64>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Writes the current state to the snapshot and truncates the log
+    pub async fn snapshot(&self) -> Result<(), BigbotError> {
+        let mut state = self.state.lock().await;
+        self.write_snapshot(&mut state).await
+    }
+
+    async fn write_snapshot(&self, state: &mut WalState) -> Result<(), BigbotError> {
+        let now = (self.clock)();
+        state.values.retain(|_, entry| entry.is_live(now));
+        let bytes = serde_json::to_vec(&state.values).map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut tmp = File::create(&tmp_path).await?;
+        tmp.write_all(&bytes).await?;
+        tmp.sync_all().await?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE)).await?;
+        sync_dir(&self.dir).await?;
+
+        state.wal.set_len(0).await?;
+        state.wal.sync_all().await?;
+        state.records_since_snapshot = 0;
+        Ok(())
+    }
+
+    // Logs the record durably, then applies it
+    async fn commit(&self, state: &mut WalState, record: WalRecord) -> Result<(), BigbotError> {
+        let frame = encode_record(&record)?;
+        state.wal.write_all(&frame).await?;
+        state.wal.flush().await?;
+        state.wal.sync_data().await?;
+        apply(&mut state.values, record);
+
+        state.records_since_snapshot += 1;
+        if state.records_since_snapshot >= self.snapshot_every {
+            self.write_snapshot(state).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl KVStore for WalKVStore {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BigbotError> {
+        let state = self.state.lock().await;
+        Ok(state
+            .values
+            .get(key)
+            .filter(|entry| entry.is_live((self.clock)()))
+            .map(|entry| entry.value.clone()))
+    }
+
+    async fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), BigbotError> {
+        let mut state = self.state.lock().await;
+        let entry = WalEntry { value, expires_at_ms: None };
+        self.commit(&mut state, WalRecord::Set { key, entry }).await
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<(), BigbotError> {
+        let mut state = self.state.lock().await;
+        if !state.values.contains_key(key) {
+            return Ok(());
+        }
+        self.commit(&mut state, WalRecord::Delete { key: key.to_vec() }).await
+    }
+
+    async fn keys(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, BigbotError> {
+        let state = self.state.lock().await;
+        let now = (self.clock)();
+        Ok(state
+            .values
+            .iter()
+            .filter(|(key, entry)| key.starts_with(prefix) && entry.is_live(now))
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+
+    async fn set_if_absent(&self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>) -> Result<bool, BigbotError> {
+        let mut state = self.state.lock().await;
+        let now = (self.clock)();
+        if state.values.get(&key).map_or(false, |entry| entry.is_live(now)) {
+            return Ok(false);
+        }
+        let expires_at_ms = ttl.map(|ttl| now + ttl.as_millis() as u64);
+        let entry = WalEntry { value, expires_at_ms };
+        self.commit(&mut state, WalRecord::Set { key, entry }).await?;
+        Ok(true)
+    }
+
+    async fn put_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<(), BigbotError> {
+        let mut state = self.state.lock().await;
+        let expires_at_ms = Some((self.clock)() + ttl.as_millis() as u64);
+        let entry = WalEntry { value, expires_at_ms };
+        self.commit(&mut state, WalRecord::Set { key, entry }).await
+    }
+
+    async fn scan_prefix(&self, sub_prefix: &str, limit: usize) -> Result<Vec<(String, Vec<u8>)>, BigbotError> {
+        let state = self.state.lock().await;
+        let now = (self.clock)();
+        state
+            .values
+            .range(sub_prefix.as_bytes().to_vec()..)
+            .take_while(|(key, _)| key.starts_with(sub_prefix.as_bytes()))
+            .filter(|(_, entry)| entry.is_live(now))
+            .take(limit)
+            .map(|(key, entry)| Ok((utf8_key(key.clone())?, entry.value.clone())))
+            .collect()
+    }
+
+    async fn compare_and_swap(&self, key: Vec<u8>, expected: Option<Vec<u8>>, value: Vec<u8>) -> Result<bool, BigbotError> {
+        let mut state = self.state.lock().await;
+        let now = (self.clock)();
+        let current = state.values.get(&key).filter(|entry| entry.is_live(now)).map(|entry| &entry.value);
+        if current != expected.as_ref() {
+            return Ok(false);
+        }
+        let entry = WalEntry { value, expires_at_ms: None };
+        self.commit(&mut state, WalRecord::Set { key, entry }).await?;
+        Ok(true)
+    }
+}
+
+fn apply(values: &mut BTreeMap<Vec<u8>, WalEntry>, record: WalRecord) {
+    match record {
+        WalRecord::Set { key, entry } => {
+            values.insert(key, entry);
+        }
+        WalRecord::Delete { key } => {
+            values.remove(&key);
+        }
+    }
+}
+
+fn encode_record(record: &WalRecord) -> Result<Vec<u8>, BigbotError> {
+    let payload = serde_json::to_vec(record).map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+    let mut frame = Vec::with_capacity(LENGTH_BYTES + payload.len() + CHECKSUM_BYTES);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    frame.extend_from_slice(&checksum(&payload));
+    Ok(frame)
+}
+
+// Decodes complete records, stopping at the first torn or corrupt one. Also returns the length
+// of the log up to the end of the last good record.
+fn decode_records(log: &[u8]) -> (Vec<WalRecord>, usize) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + LENGTH_BYTES <= log.len() {
+        let mut length = [0u8; LENGTH_BYTES];
+        length.copy_from_slice(&log[offset..offset + LENGTH_BYTES]);
+        let payload_start = offset + LENGTH_BYTES;
+        let payload_end = payload_start + u32::from_le_bytes(length) as usize;
+        let frame_end = payload_end + CHECKSUM_BYTES;
+        if frame_end > log.len() {
+            break;
+        }
+        let payload = &log[payload_start..payload_end];
+        if log[payload_end..frame_end] != checksum(payload) {
+            break;
+        }
+        match serde_json::from_slice(payload) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+        offset = frame_end;
+    }
+    (records, offset)
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_BYTES] {
+    let digest = Sha256::digest(payload);
+    let mut checksum = [0u8; CHECKSUM_BYTES];
+    checksum.copy_from_slice(&digest[..CHECKSUM_BYTES]);
+    checksum
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+// Makes a rename in `dir` durable; directories can't be opened for syncing on Windows
+async fn sync_dir(dir: &Path) -> Result<(), BigbotError> {
+    #[cfg(unix)]
+    File::open(dir).await?.sync_all().await?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // A wall clock that only moves when the returned counter is advanced
+    fn manual_clock() -> (Clock<u64>, Arc<AtomicU64>) {
+        let now = Arc::new(AtomicU64::new(now_ms()));
+        let reading = now.clone();
+        (Arc::new(move || reading.load(Ordering::SeqCst)), now)
+    }
+
+    async fn contents(store: &WalKVStore) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        let mut contents = Vec::new();
+        for key in store.keys(b"").await.unwrap() {
+            let value = store.get(&key).await.unwrap();
+            contents.push((key, value));
+        }
+        contents
+    }
+
+    #[tokio::test]
+    async fn test_mutations_survive_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let (clock, now) = manual_clock();
+        {
+            let store = WalKVStore::open(dir.path()).await.unwrap().with_clock(clock.clone());
+            store.set(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+            store.set(b"b".to_vec(), b"2".to_vec()).await.unwrap();
+            store.set(b"a".to_vec(), b"3".to_vec()).await.unwrap();
+            store.delete(b"b").await.unwrap();
+            assert!(store.set_if_absent(b"lock".to_vec(), vec![], Some(Duration::from_secs(60))).await.unwrap());
+            assert!(store.set_if_absent(b"gone".to_vec(), vec![], Some(Duration::from_millis(1))).await.unwrap());
+            // Dropped without a snapshot, as in a crash
+        }
+        // A record torn by the crash is discarded
+        let mut wal = OpenOptions::new().append(true).open(dir.path().join(WAL_FILE)).await.unwrap();
+        wal.write_all(&[42, 0, 0, 0, b'{']).await.unwrap();
+        wal.flush().await.unwrap();
+        drop(wal);
+        now.fetch_add(1, Ordering::SeqCst);
+
+        let store = WalKVStore::open(dir.path()).await.unwrap().with_clock(clock);
+        assert_eq!(store.get(b"a").await.unwrap(), Some(b"3".to_vec()));
+        assert_eq!(store.get(b"b").await.unwrap(), None);
+        // The TTL entries keep their deadlines across the restart
+        assert!(!store.set_if_absent(b"lock".to_vec(), vec![], None).await.unwrap());
+        assert_eq!(store.get(b"gone").await.unwrap(), None);
+
+        // New records are appended after the last good one
+        store.set(b"c".to_vec(), b"4".to_vec()).await.unwrap();
+        drop(store);
+        let store = WalKVStore::open(dir.path()).await.unwrap();
+        assert_eq!(store.get(b"c").await.unwrap(), Some(b"4".to_vec()));
+        assert_eq!(store.get(b"a").await.unwrap(), Some(b"3".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_replay_reconstruct_identical_state() {
+        let snapshotted_dir = tempfile::tempdir().unwrap();
+        let log_only_dir = tempfile::tempdir().unwrap();
+        let snapshotted = WalKVStore::open(snapshotted_dir.path()).await.unwrap().with_snapshot_every(3);
+        let log_only = WalKVStore::open(log_only_dir.path()).await.unwrap();
+
+        for store in [&snapshotted, &log_only] {
+            for i in 0..10u8 {
+                store.set(vec![b'k', i % 4], vec![i]).await.unwrap();
+                if i % 3 == 0 {
+                    store.delete(&[b'k', (i + 1) % 4]).await.unwrap();
+                }
+            }
+            store.set_if_absent(b"ttl".to_vec(), b"v".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        }
+        // Some mutations are in the snapshot and some only in the log
+        assert!(fs::metadata(snapshotted_dir.path().join(SNAPSHOT_FILE)).await.is_ok());
+        assert!(fs::metadata(snapshotted_dir.path().join(WAL_FILE)).await.unwrap().len() > 0);
+        let expected = contents(&log_only).await;
+        assert_eq!(contents(&snapshotted).await, expected);
+        drop(snapshotted);
+        drop(log_only);
+
+        let snapshotted = WalKVStore::open(snapshotted_dir.path()).await.unwrap();
+        let log_only = WalKVStore::open(log_only_dir.path()).await.unwrap();
+        assert_eq!(contents(&snapshotted).await, expected);
+        assert_eq!(contents(&log_only).await, expected);
+
+        // An explicit snapshot leaves an empty log and the same state
+        log_only.snapshot().await.unwrap();
+        assert_eq!(fs::metadata(log_only_dir.path().join(WAL_FILE)).await.unwrap().len(), 0);
+        drop(log_only);
+        assert_eq!(contents(&WalKVStore::open(log_only_dir.path()).await.unwrap()).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_put_with_ttl_is_logged_and_expires() {
+        let dir = tempfile::tempdir().unwrap();
+        let (clock, now) = manual_clock();
+        let store = WalKVStore::open(dir.path()).await.unwrap().with_clock(clock.clone());
+        store.put_with_ttl(b"token".to_vec(), b"t".to_vec(), Duration::from_secs(60)).await.unwrap();
+        drop(store);
+
+        // The deadline is replayed from the log
+        let store = WalKVStore::open(dir.path()).await.unwrap().with_clock(clock);
+        assert_eq!(store.get(b"token").await.unwrap(), Some(b"t".to_vec()));
+        now.fetch_add(60_000, Ordering::SeqCst);
+        assert_eq!(store.get(b"token").await.unwrap(), None);
+        assert!(store.keys(b"").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scan_prefix_returns_live_entries_in_key_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WalKVStore::open(dir.path()).await.unwrap();
+        store.set(b"nonce:2".to_vec(), b"b".to_vec()).await.unwrap();
+        store.set(b"nonce:1".to_vec(), b"a".to_vec()).await.unwrap();
+        store.set(b"nonce:3".to_vec(), b"c".to_vec()).await.unwrap();
+        store.set(b"other".to_vec(), b"x".to_vec()).await.unwrap();
+        // An entry whose deadline has passed is skipped
+        store.set_if_absent(b"nonce:0".to_vec(), b"gone".to_vec(), Some(Duration::ZERO)).await.unwrap();
+
+        assert_eq!(
+            store.scan_prefix("nonce:", 2).await.unwrap(),
+            vec![("nonce:1".to_string(), b"a".to_vec()), ("nonce:2".to_string(), b"b".to_vec())]
+        );
+        drop(store);
+        let store = WalKVStore::open(dir.path()).await.unwrap();
+        assert_eq!(store.scan_prefix("nonce:", 10).await.unwrap().len(), 3);
+    }
+}
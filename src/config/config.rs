@@ -3,10 +3,22 @@ use serde_json::Value;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
+use std::time::Duration;
 use semver::Version;
 
+use crate::utils::bigboterror::BigbotError;
+
+pub const DEFAULT_ANTHROPIC_MODEL: &str = "claude-3-haiku-20240307";
+pub const DEFAULT_ANTHROPIC_MAX_TOKENS: u32 = 1024;
+pub const DEFAULT_ANTHROPIC_API_KEY_ENV: &str = "ANTHROPIC_API_KEY";
+pub const DEFAULT_NEO4J_URI: &str = "neo4j://localhost:7687";
+pub const DEFAULT_NEO4J_USER: &str = "neo4j";
+pub const DEFAULT_NEO4J_PASSWORD_ENV: &str = "NEO4J_PASSWD";
+pub const DEFAULT_NEO4J_CONNECT_ATTEMPTS: u32 = 5;
+pub const DEFAULT_NEO4J_RETRY_DELAY_MS: u64 = 500;
+
 #[derive(Debug, Serialize, Deserialize)]
-struct Config {
+pub struct Config {
     version: String,
     user_types: HashMap<String, UserType>,
     preference_types: HashMap<String, PreferenceType>,
@@ -33,7 +45,7 @@ struct ModuleConfig {
 }
 
 impl Config {
-    fn load(file_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn load(file_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let file = File::open(file_path)?;
         let reader = BufReader::new(file);
         let config: Config = serde_json::from_reader(reader)?;
@@ -59,6 +71,11 @@ impl Config {
         self.modules.get(module_name)
     }
 
+    // A module's base setting, without per-user-type overrides
+    pub(crate) fn module_setting(&self, module_name: &str, key: &str) -> Option<&Value> {
+        self.get_module_config(module_name).and_then(|module_config| module_config.settings.get(key))
+    }
+
     fn is_module_enabled(&self, module_name: &str) -> bool {
         self.get_module_config(module_name)
             .map(|config| config.enabled)
@@ -93,6 +110,109 @@ impl Config {
     }
 }
 
+// Settings for `providers::anthropic`, taken from the `anthropic` module. The key itself is read
+// from the environment variable named by `api_key_env` so it never lives in the config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnthropicConfig {
+    pub api_key: String,
+    pub model: String,
+    pub max_tokens: u32,
+}
+
+impl AnthropicConfig {
+    pub fn from_config(config: &Config) -> Result<Self, BigbotError> {
+        let settings = config.get_module_config("anthropic").map(|module| &module.settings);
+        let setting = |key: &str| settings.and_then(|settings| settings.get(key));
+
+        let api_key_env = setting("api_key_env")
+            .and_then(|value| value.as_str())
+            .unwrap_or(DEFAULT_ANTHROPIC_API_KEY_ENV);
+        let api_key = std::env::var(api_key_env)
+            .map_err(|_| BigbotError::InvalidInput(format!("{} must be set", api_key_env)))?;
+        let model = setting("model")
+            .and_then(|value| value.as_str())
+            .unwrap_or(DEFAULT_ANTHROPIC_MODEL)
+            .to_string();
+        let max_tokens = match setting("max_tokens") {
+            Some(value) => value
+                .as_u64()
+                .and_then(|max_tokens| u32::try_from(max_tokens).ok())
+                .ok_or_else(|| BigbotError::InvalidInput(format!("Invalid anthropic max_tokens: {}", value)))?,
+            None => DEFAULT_ANTHROPIC_MAX_TOKENS,
+        };
+        Ok(Self { api_key, model, max_tokens })
+    }
+}
+
+// Settings for `clients::neo4j`, taken from the `neo4j` module. As with the Anthropic key, the
+// password is read from the environment variable named by `password_env`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neo4jConfig {
+    pub uri: String,
+    pub user: String,
+    pub password: String,
+    pub connect_attempts: u32,
+    pub retry_delay: Duration,
+}
+
+impl Neo4jConfig {
+    pub fn new(uri: &str, user: &str, password: &str) -> Self {
+        Self {
+            uri: uri.to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+            connect_attempts: DEFAULT_NEO4J_CONNECT_ATTEMPTS,
+            retry_delay: Duration::from_millis(DEFAULT_NEO4J_RETRY_DELAY_MS),
+        }
+    }
+
+    pub fn with_connect_attempts(mut self, connect_attempts: u32) -> Self {
+        self.connect_attempts = connect_attempts.max(1);
+        self
+    }
+
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, BigbotError> {
+        let settings = config.get_module_config("neo4j").map(|module| &module.settings);
+        let setting = |key: &str| settings.and_then(|settings| settings.get(key));
+        let string_setting = |key: &str, default: &str| -> Result<String, BigbotError> {
+            match setting(key) {
+                Some(value) => value
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| BigbotError::InvalidInput(format!("Invalid neo4j {}: {}", key, value))),
+                None => Ok(default.to_string()),
+            }
+        };
+
+        let uri = string_setting("uri", DEFAULT_NEO4J_URI)?;
+        let user = string_setting("user", DEFAULT_NEO4J_USER)?;
+        let password_env = string_setting("password_env", DEFAULT_NEO4J_PASSWORD_ENV)?;
+        let password = std::env::var(&password_env)
+            .map_err(|_| BigbotError::InvalidInput(format!("{} must be set", password_env)))?;
+        let mut neo4j = Self::new(&uri, &user, &password);
+        if let Some(value) = setting("connect_attempts") {
+            let connect_attempts = value
+                .as_u64()
+                .and_then(|attempts| u32::try_from(attempts).ok())
+                .filter(|attempts| *attempts > 0)
+                .ok_or_else(|| BigbotError::InvalidInput(format!("Invalid neo4j connect_attempts: {}", value)))?;
+            neo4j = neo4j.with_connect_attempts(connect_attempts);
+        }
+        if let Some(value) = setting("retry_delay_ms") {
+            let retry_delay_ms = value
+                .as_u64()
+                .ok_or_else(|| BigbotError::InvalidInput(format!("Invalid neo4j retry_delay_ms: {}", value)))?;
+            neo4j = neo4j.with_retry_delay(Duration::from_millis(retry_delay_ms));
+        }
+        Ok(neo4j)
+    }
+}
+
 fn main() {
     let config_file = "config.json";
     let config = Config::load(config_file).unwrap();
@@ -133,4 +253,80 @@ fn main() {
 
     // Use the configuration values in your script logic
     // ...
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(modules: Value) -> Config {
+        serde_json::from_value(json!({
+            "version": "1.0.0",
+            "user_types": {},
+            "preference_types": {},
+            "modules": modules,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_anthropic_config_from_module_settings() {
+        std::env::set_var("TEST_ANTHROPIC_CONFIG_KEY", "sk-test");
+        let anthropic = AnthropicConfig::from_config(&config(json!({
+            "anthropic": {
+                "enabled": true,
+                "settings": { "api_key_env": "TEST_ANTHROPIC_CONFIG_KEY", "model": "claude-3-opus-20240229", "max_tokens": 256 },
+                "overrides": {},
+            }
+        })))
+        .unwrap();
+        assert_eq!(
+            anthropic,
+            AnthropicConfig {
+                api_key: "sk-test".to_string(),
+                model: "claude-3-opus-20240229".to_string(),
+                max_tokens: 256,
+            }
+        );
+
+        let err = AnthropicConfig::from_config(&config(json!({
+            "anthropic": {
+                "enabled": true,
+                "settings": { "api_key_env": "TEST_ANTHROPIC_CONFIG_MISSING_KEY" },
+                "overrides": {},
+            }
+        })))
+        .unwrap_err();
+        assert!(matches!(err, BigbotError::InvalidInput(ref msg) if msg.contains("TEST_ANTHROPIC_CONFIG_MISSING_KEY")));
+    }
+
+    #[test]
+    fn test_neo4j_config_from_module_settings() {
+        std::env::set_var("TEST_NEO4J_CONFIG_PASSWORD", "secret");
+        let neo4j = Neo4jConfig::from_config(&config(json!({
+            "neo4j": {
+                "enabled": true,
+                "settings": { "uri": "neo4j://graph:7687", "password_env": "TEST_NEO4J_CONFIG_PASSWORD", "connect_attempts": 3, "retry_delay_ms": 50 },
+                "overrides": {},
+            }
+        })))
+        .unwrap();
+        assert_eq!(
+            neo4j,
+            Neo4jConfig::new("neo4j://graph:7687", DEFAULT_NEO4J_USER, "secret")
+                .with_connect_attempts(3)
+                .with_retry_delay(Duration::from_millis(50))
+        );
+
+        let err = Neo4jConfig::from_config(&config(json!({
+            "neo4j": {
+                "enabled": true,
+                "settings": { "password_env": "TEST_NEO4J_CONFIG_PASSWORD", "connect_attempts": 0 },
+                "overrides": {},
+            }
+        })))
+        .unwrap_err();
+        assert!(matches!(err, BigbotError::InvalidInput(ref msg) if msg.contains("connect_attempts")));
+    }
+}
@@ -15,11 +15,16 @@
 //!
 //! - `MqttKafkaDataBridge`: An implementation of the `DataBridge` trait that combines MQTT and Kafka, allowing messages
 //! to be sent via MQTT and received via Kafka. It takes a `BridgeConfig` struct as input, which contains the necessary
-//! connection details for MQTT and Kafka.
+//! connection details for MQTT and Kafka. `bridge_mqtt_to_kafka` drives the MQTT connection and forwards everything
+//! published on the MQTT topic to Kafka.
 //!
 //! - `BridgeConfig`: A configuration struct that holds the connection details for the data bridging implementation,
 //! such as the MQTT broker URL, MQTT topic, Kafka bootstrap servers, and Kafka topic.
 //!
+//! - `SchemaValidator`: Optional JSON schema validation applied by `MqttKafkaDataBridge::with_schema`. Payloads
+//! bridged from MQTT to Kafka are checked before forwarding; failures go to a dead-letter topic together with the
+//! validation error, and `validation_stats` reports how many payloads were forwarded or dead-lettered.
+//!
 //! ## Benefits
 //!
 //! - Provides a flexible and extensible framework for data bridging, allowing for easy integration of different
@@ -35,13 +40,18 @@
 //! them while leveraging the benefits of the CloudEvents specification for message formatting and compatibility.
 
 use cloudevents::Event;
+use jsonschema::JSONSchema;
 use rdkafka::consumer::{Consumer, StreamConsumer};
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use rdkafka::ClientConfig;
-use rumqttc::{Client, MqttOptions, QoS};
+use rumqttc::{AsyncClient, Event as MqttEvent, EventLoop, MqttOptions, Packet, QoS};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
+use crate::utils::bigboterror::BigbotError;
+
 // Define the BridgeConfig struct
 #[derive(Clone)]
 pub struct BridgeConfig {
@@ -57,18 +67,119 @@ pub trait DataBridge {
     fn receive_message(&self) -> Option<Event>;
 }
 
+// A compiled JSON schema
+pub struct JsonSchema {
+    compiled: JSONSchema,
+}
+
+impl JsonSchema {
+    pub fn new(schema: &Value) -> Result<Self, BigbotError> {
+        let compiled = JSONSchema::compile(schema)
+            .map_err(|e| BigbotError::InvalidInput(format!("Invalid JSON schema: {}", e)))?;
+        Ok(Self { compiled })
+    }
+
+    // Validates an instance, joining all validation errors into one message
+    pub fn validate(&self, instance: &Value) -> Result<(), String> {
+        self.compiled.validate(instance).map_err(|errors| {
+            errors
+                .map(|error| format!("{} at '{}'", error, error.instance_path))
+                .collect::<Vec<_>>()
+                .join("; ")
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValidationStats {
+    pub validated: u64,
+    pub forwarded: u64,
+    pub dead_lettered: u64,
+}
+
+// Record published to the dead-letter topic for a rejected payload
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub source_topic: String,
+    pub error: String,
+    pub payload: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Validation {
+    Forward(Value),
+    DeadLetter(DeadLetter),
+}
+
+pub struct SchemaValidator {
+    schema: JsonSchema,
+    dead_letter_topic: String,
+    validated: AtomicU64,
+    forwarded: AtomicU64,
+    dead_lettered: AtomicU64,
+}
+
+impl SchemaValidator {
+    pub fn new(schema: JsonSchema, dead_letter_topic: &str) -> Self {
+        Self {
+            schema,
+            dead_letter_topic: dead_letter_topic.to_string(),
+            validated: AtomicU64::new(0),
+            forwarded: AtomicU64::new(0),
+            dead_lettered: AtomicU64::new(0),
+        }
+    }
+
+    pub fn dead_letter_topic(&self) -> &str {
+        &self.dead_letter_topic
+    }
+
+    // Decides whether a payload received on `source_topic` is forwarded or dead-lettered.
+    // Payloads that aren't JSON are dead-lettered with the parse error.
+    pub fn validate(&self, source_topic: &str, payload: &[u8]) -> Validation {
+        self.validated.fetch_add(1, Ordering::Relaxed);
+        let result = serde_json::from_slice::<Value>(payload)
+            .map_err(|e| format!("Payload is not valid JSON: {}", e))
+            .and_then(|value| self.schema.validate(&value).map(|_| value));
+        match result {
+            Ok(value) => {
+                self.forwarded.fetch_add(1, Ordering::Relaxed);
+                Validation::Forward(value)
+            }
+            Err(error) => {
+                self.dead_lettered.fetch_add(1, Ordering::Relaxed);
+                Validation::DeadLetter(DeadLetter {
+                    source_topic: source_topic.to_string(),
+                    error,
+                    payload: String::from_utf8_lossy(payload).to_string(),
+                })
+            }
+        }
+    }
+
+    pub fn stats(&self) -> ValidationStats {
+        ValidationStats {
+            validated: self.validated.load(Ordering::Relaxed),
+            forwarded: self.forwarded.load(Ordering::Relaxed),
+            dead_lettered: self.dead_lettered.load(Ordering::Relaxed),
+        }
+    }
+}
+
 pub struct MqttKafkaDataBridge {
-    mqtt_client: Client,
+    mqtt_client: AsyncClient,
+    mqtt_eventloop: EventLoop,
     kafka_producer: FutureProducer,
     kafka_consumer: StreamConsumer,
     config: BridgeConfig,
+    validator: Option<SchemaValidator>,
 }
 
 impl MqttKafkaDataBridge {
     pub fn new(config: &BridgeConfig) -> Self {
         let mqtt_options = MqttOptions::new("mqtt-kafka-bridge", config.mqtt_broker_url.clone(), 1883)
             .set_keep_alive(Duration::from_secs(5));
-        let mqtt_client = Client::new(mqtt_options, 10);
+        let (mqtt_client, mqtt_eventloop) = AsyncClient::new(mqtt_options, 10);
 
         let kafka_producer: FutureProducer = ClientConfig::new()
             .set("bootstrap.servers", &config.kafka_bootstrap_servers.join(","))
@@ -90,18 +201,70 @@ impl MqttKafkaDataBridge {
 
         Self {
             mqtt_client,
+            mqtt_eventloop,
             kafka_producer,
             kafka_consumer,
             config: config.clone(),
+            validator: None,
         }
     }
+
+    // Validates every payload bridged from MQTT to Kafka against `schema`, sending failures to
+    // `dead_letter_topic` instead of the configured Kafka topic.
+    pub fn with_schema(mut self, schema: JsonSchema, dead_letter_topic: &str) -> Self {
+        self.validator = Some(SchemaValidator::new(schema, dead_letter_topic));
+        self
+    }
+
+    pub fn validation_stats(&self) -> Option<ValidationStats> {
+        self.validator.as_ref().map(SchemaValidator::stats)
+    }
+
+    // Subscribes to the MQTT topic and forwards every payload published there until the MQTT
+    // connection fails. Polling the event loop is also what sends messages queued by `send_message`.
+    pub async fn bridge_mqtt_to_kafka(&mut self) -> Result<(), BigbotError> {
+        self.mqtt_client
+            .subscribe(self.config.mqtt_topic.clone(), QoS::AtLeastOnce)
+            .await
+            .map_err(|e| BigbotError::SystemError(format!("Failed to subscribe to MQTT topic: {}", e)))?;
+        loop {
+            match self.mqtt_eventloop.poll().await {
+                Ok(MqttEvent::Incoming(Packet::Publish(publish))) => {
+                    self.forward_mqtt_payload(&publish.payload).await?;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    return Err(BigbotError::SystemError(format!("MQTT connection failed: {}", e)));
+                }
+            }
+        }
+    }
+
+    // Forwards a payload received on the MQTT topic to Kafka, validating it first when a schema is set
+    pub async fn forward_mqtt_payload(&self, payload: &[u8]) -> Result<(), BigbotError> {
+        let (topic, body) = match &self.validator {
+            None => (self.config.kafka_topic.as_str(), payload.to_vec()),
+            Some(validator) => match validator.validate(&self.config.mqtt_topic, payload) {
+                Validation::Forward(_) => (self.config.kafka_topic.as_str(), payload.to_vec()),
+                Validation::DeadLetter(dead_letter) => (
+                    validator.dead_letter_topic(),
+                    serde_json::to_vec(&dead_letter).map_err(|e| BigbotError::SystemError(e.to_string()))?,
+                ),
+            },
+        };
+        self.kafka_producer
+            .send(FutureRecord::<(), _>::to(topic).payload(&body), Duration::from_secs(0))
+            .await
+            .map_err(|(e, _)| BigbotError::KafkaError(e.to_string()))?;
+        Ok(())
+    }
 }
 
 impl DataBridge for MqttKafkaDataBridge {
     fn send_message(&self, event: Event) {
         let message = serde_json::to_string(&event).expect("Failed to serialize CloudEvent");
         self.mqtt_client
-            .publish(
+            .try_publish(
                 self.config.mqtt_topic.clone(),
                 QoS::AtLeastOnce,
                 false,
@@ -126,4 +289,63 @@ impl DataBridge for MqttKafkaDataBridge {
             _ => None,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn validator() -> SchemaValidator {
+        let schema = JsonSchema::new(&json!({
+            "type": "object",
+            "required": ["sensor", "temperature"],
+            "properties": {
+                "sensor": { "type": "string" },
+                "temperature": { "type": "number" }
+            }
+        }))
+        .unwrap();
+        SchemaValidator::new(schema, "sensors.dead-letter")
+    }
+
+    #[test]
+    fn test_conforming_payload_is_forwarded() {
+        let validator = validator();
+        let payload = br#"{"sensor": "s1", "temperature": 21.5}"#;
+        assert_eq!(
+            validator.validate("sensors/s1", payload),
+            Validation::Forward(json!({"sensor": "s1", "temperature": 21.5}))
+        );
+        assert_eq!(
+            validator.stats(),
+            ValidationStats { validated: 1, forwarded: 1, dead_lettered: 0 }
+        );
+    }
+
+    #[test]
+    fn test_non_conforming_payload_is_dead_lettered_with_schema_error() {
+        let validator = validator();
+        let payload = br#"{"sensor": "s1", "temperature": "hot"}"#;
+        match validator.validate("sensors/s1", payload) {
+            Validation::DeadLetter(dead_letter) => {
+                assert_eq!(dead_letter.source_topic, "sensors/s1");
+                assert!(dead_letter.error.contains("number"), "unexpected error: {}", dead_letter.error);
+                assert!(dead_letter.error.contains("/temperature"), "unexpected error: {}", dead_letter.error);
+                assert_eq!(dead_letter.payload.as_bytes(), payload);
+            }
+            other => panic!("expected dead letter, got {:?}", other),
+        }
+
+        assert!(matches!(validator.validate("sensors/s1", b"not json"), Validation::DeadLetter(_)));
+        assert_eq!(
+            validator.stats(),
+            ValidationStats { validated: 2, forwarded: 0, dead_lettered: 2 }
+        );
+    }
+
+    #[test]
+    fn test_invalid_schema_is_rejected() {
+        assert!(JsonSchema::new(&json!({"type": 12})).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+//! # Buffered Sink
+//!
+//! Batches items before handing them to an inner `Sink<Vec<T>, E>`, so sinks with a per-call
+//! cost (a database round-trip, a network request) see one call per batch instead of one per item.
+//! A batch is written once `capacity` items have accumulated, or by a background timer every
+//! `flush_interval` so a slow producer never leaves items waiting indefinitely.
+//!
+//! Since `Drop` can't await, callers shutting down should call `close()` to write whatever is still
+//! buffered. A failed write drops its batch and reports how many items were in it, so the caller
+//! can decide whether to replay them. A failure on the timer is reported by the next call; if
+//! several timer writes fail first, the first error is kept and `dropped` counts every lost item.
+//! The item passed to a `consume` that reports a timer failure is still buffered.
+
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use super::Sink;
+
+/// A write to the inner sink failed; `dropped` is the number of items in the lost batch.
+#[derive(Debug)]
+pub struct FlushError<E> {
+    pub error: E,
+    pub dropped: usize,
+}
+
+impl<E: Display> Display for FlushError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "flushing {} buffered items failed: {}",
+            self.dropped, self.error
+        )
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for FlushError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+struct Shared<T, E> {
+    inner: Box<dyn Sink<Vec<T>, E> + Send + Sync>,
+    // Held across the inner write so batches reach the inner sink in the order they were filled
+    buffer: Mutex<Vec<T>>,
+    // The first failure from the timer task, waiting to be returned by the next call
+    background_error: std::sync::Mutex<Option<FlushError<E>>>,
+}
+
+impl<T, E> Shared<T, E>
+where
+    T: Send,
+    E: Send,
+{
+    async fn flush(&self) -> Result<(), FlushError<E>> {
+        let mut buffer = self.buffer.lock().await;
+        self.write(&mut buffer).await
+    }
+
+    async fn write(&self, buffer: &mut Vec<T>) -> Result<(), FlushError<E>> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(buffer);
+        let dropped = batch.len();
+        self.inner
+            .consume(batch)
+            .await
+            .map_err(|error| FlushError { error, dropped })
+    }
+
+    fn record_background_error(&self, e: FlushError<E>) {
+        let mut background_error = self.background_error.lock().unwrap();
+        match background_error.as_mut() {
+            Some(first) => first.dropped += e.dropped,
+            None => *background_error = Some(e),
+        }
+    }
+
+    fn take_background_error(&self) -> Result<(), FlushError<E>> {
+        match self.background_error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct BufferedSink<T, E> {
+    shared: Arc<Shared<T, E>>,
+    capacity: usize,
+    timer: JoinHandle<()>,
+}
+
+impl<T, E> BufferedSink<T, E>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    /// Wraps `inner`, writing a batch every `capacity` items or every `flush_interval`, whichever
+    /// comes first. Must be called from within a tokio runtime.
+    pub fn new(
+        inner: Box<dyn Sink<Vec<T>, E> + Send + Sync>,
+        capacity: usize,
+        flush_interval: Duration,
+    ) -> Self {
+        let capacity = capacity.max(1);
+        let shared = Arc::new(Shared {
+            inner,
+            buffer: Mutex::new(Vec::with_capacity(capacity)),
+            background_error: std::sync::Mutex::new(None),
+        });
+        let timer = tokio::spawn(Self::run_timer(Arc::downgrade(&shared), flush_interval));
+        BufferedSink {
+            shared,
+            capacity,
+            timer,
+        }
+    }
+
+    // Holds only a weak reference so dropping the sink ends the task
+    async fn run_timer(shared: Weak<Shared<T, E>>, flush_interval: Duration) {
+        let mut ticker = tokio::time::interval(flush_interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick completes immediately
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(shared) = shared.upgrade() else {
+                return;
+            };
+            if let Err(e) = shared.flush().await {
+                shared.record_background_error(e);
+            }
+        }
+    }
+
+    /// Writes any buffered items to the inner sink now.
+    pub async fn flush(&self) -> Result<(), FlushError<E>> {
+        self.shared.take_background_error()?;
+        self.shared.flush().await
+    }
+
+    /// Stops the timer and writes whatever is still buffered.
+    pub async fn close(self) -> Result<(), FlushError<E>> {
+        self.timer.abort();
+        self.flush().await
+    }
+
+    /// The number of items waiting for the next flush.
+    pub async fn len(&self) -> usize {
+        self.shared.buffer.lock().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+impl<T, E> Drop for BufferedSink<T, E> {
+    fn drop(&mut self) {
+        self.timer.abort();
+    }
+}
+
+#[async_trait]
+impl<T, E> Sink<T, FlushError<E>> for BufferedSink<T, E>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    async fn consume(&self, item: T) -> Result<(), FlushError<E>>
+    where
+        T: 'async_trait,
+    {
+        let mut buffer = self.shared.buffer.lock().await;
+        buffer.push(item);
+        self.shared.take_background_error()?;
+        if buffer.len() >= self.capacity {
+            self.shared.write(&mut buffer).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_streams::mock::MockBroker;
+    use crate::data_streams::Error;
+
+    fn buffered(
+        broker: &MockBroker<Vec<u32>>,
+        capacity: usize,
+        interval: Duration,
+    ) -> BufferedSink<u32, Error> {
+        BufferedSink::new(Box::new(broker.sink("batches")), capacity, interval)
+    }
+
+    #[tokio::test]
+    async fn test_flushes_when_capacity_is_reached() {
+        let broker = MockBroker::new();
+        let sink = buffered(&broker, 3, Duration::from_secs(3600));
+
+        for i in 0..7 {
+            sink.consume(i).await.unwrap();
+        }
+        assert_eq!(
+            broker.published("batches"),
+            vec![vec![0, 1, 2], vec![3, 4, 5]]
+        );
+        assert_eq!(sink.len().await, 1);
+
+        sink.close().await.unwrap();
+        assert_eq!(broker.published("batches").last(), Some(&vec![6]));
+    }
+
+    // Time is paused in these tests, so sleeping only moves the clock. Each sleep ends just past a
+    // tick so the timer has flushed by the time the test resumes.
+    #[tokio::test(start_paused = true)]
+    async fn test_timer_flushes_slow_producer() {
+        let broker = MockBroker::new();
+        let sink = buffered(&broker, 100, Duration::from_millis(20));
+
+        sink.consume(1).await.unwrap();
+        sink.consume(2).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(19)).await;
+        assert!(broker.published("batches").is_empty());
+
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        assert_eq!(broker.published("batches"), vec![vec![1, 2]]);
+        assert!(sink.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_inner_error_reports_batch_size() {
+        let broker = MockBroker::new();
+        let sink = buffered(&broker, 2, Duration::from_secs(3600));
+
+        sink.consume(1).await.unwrap();
+        broker.fail_next(Error::Cancelled);
+        let err = sink.consume(2).await.unwrap_err();
+        assert_eq!(err.dropped, 2);
+        assert!(matches!(err.error, Error::Cancelled));
+
+        // The sink keeps working after a failed batch
+        sink.consume(3).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(broker.published("batches"), vec![vec![3]]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timer_failures_keep_first_error_and_the_next_item() {
+        let broker = MockBroker::new();
+        let interval = Duration::from_millis(20);
+        let sink = buffered(&broker, 100, interval);
+
+        sink.consume(1).await.unwrap();
+        broker.fail_next(Error::Cancelled);
+        tokio::time::sleep(interval + Duration::from_millis(1)).await;
+        // A second timer failure before anyone calls the sink again
+        sink.shared.buffer.lock().await.push(2);
+        broker.fail_next(Error::InternalError("later".into()));
+        tokio::time::sleep(interval).await;
+
+        let err = sink.consume(3).await.unwrap_err();
+        assert!(matches!(err.error, Error::Cancelled));
+        assert_eq!(err.dropped, 2);
+        // The item passed to the failing call wasn't lost with the error
+        assert_eq!(sink.len().await, 1);
+        sink.flush().await.unwrap();
+        assert_eq!(broker.published("batches"), vec![vec![3]]);
+    }
+}
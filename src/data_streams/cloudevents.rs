@@ -1,4 +1,5 @@
-use cloudevents::{Event, EventBuilder, EventBuilderV10};
+use cloudevents::event::ExtensionValue;
+use cloudevents::{AttributesReader, Data, Event, EventBuilder, EventBuilderV10};
 use rumqttc::{AsyncClient, Event as MqttEvent, MqttOptions, QoS, EventLoop};
 use serde_json::Value;
 use tracing::error;
@@ -7,6 +8,112 @@ use rdkafka::producer::{FutureRecord, FutureProducer};
 
 use crate::data_exchange::exchange_core::Sink;
 use crate::data_streams::kafka::KafkaSink;
+use crate::graphs::nl_to_graph::EntityGraphImpl;
+use crate::messaging::decentralised_messaging::Intent;
+use crate::messaging::message::Message;
+use crate::messaging::message_metadata::MessageMetadata;
+use crate::utils::bigboterror::BigbotError;
+
+/// CloudEvents `type` of events built from a `Message`.
+pub const MESSAGE_EVENT_TYPE: &str = "message.classified";
+
+// The event `source` is the message's channel, as `/channels/<channel id>`
+const CHANNEL_SOURCE_PREFIX: &str = "/channels/";
+
+const SENDER_EXTENSION: &str = "sender";
+const RECIPIENT_EXTENSION: &str = "recipient";
+const CLASSIFICATION_EXTENSION: &str = "classification";
+
+/// Builds the CloudEvent for a message. The event id is the message id, the source its channel,
+/// the time its timestamp and the data its content as `text/plain`. The sender, the recipient
+/// and, if the message has been classified, its classification are carried as extensions.
+pub fn message_to_cloudevent(msg: &Message) -> Event {
+    let mut builder = EventBuilderV10::new()
+        .id(msg.id.to_string())
+        .source(format!("{}{}", CHANNEL_SOURCE_PREFIX, msg.channel_id))
+        .ty(MESSAGE_EVENT_TYPE)
+        .time(msg.timestamp)
+        .data("text/plain", msg.content.clone())
+        .extension(SENDER_EXTENSION, msg.sender.as_str())
+        .extension(RECIPIENT_EXTENSION, msg.recipient.as_str());
+    if let Some(classification) = msg.metadata.classification() {
+        builder = builder.extension(CLASSIFICATION_EXTENSION, classification);
+    }
+    builder.build().expect("id, source and type are always set")
+}
+
+/// Parses a CloudEvent built by [message_to_cloudevent] back into a message. Fields the event
+/// doesn't carry are left empty. Returns `InvalidInput` if the type isn't [MESSAGE_EVENT_TYPE],
+/// the id or source isn't a message id or channel, or the time, sender or recipient is missing.
+pub fn cloudevent_to_message(ev: &Event) -> Result<Message, BigbotError> {
+    if ev.ty() != MESSAGE_EVENT_TYPE {
+        return Err(BigbotError::InvalidInput(format!(
+            "unexpected event type '{}'",
+            ev.ty()
+        )));
+    }
+    let id = Uuid::parse_str(ev.id()).map_err(|e| {
+        BigbotError::InvalidInput(format!("event id '{}' is not a message id: {}", ev.id(), e))
+    })?;
+    let source = ev.source().to_string();
+    let channel_id = source
+        .strip_prefix(CHANNEL_SOURCE_PREFIX)
+        .and_then(|channel_id| Uuid::parse_str(channel_id).ok())
+        .ok_or_else(|| {
+            BigbotError::InvalidInput(format!("event source '{}' is not a channel", source))
+        })?;
+    let timestamp = *ev
+        .time()
+        .ok_or_else(|| BigbotError::InvalidInput("event is missing 'time'".to_string()))?;
+    let content = match ev.data() {
+        Some(Data::String(text)) => text.clone(),
+        Some(Data::Binary(bytes)) => String::from_utf8(bytes.clone())
+            .map_err(|e| BigbotError::InvalidInput(format!("event data is not UTF-8: {}", e)))?,
+        Some(Data::Json(json)) => json.to_string(),
+        None => String::new(),
+    };
+    let mut metadata = MessageMetadata::new();
+    if let Some(classification) = string_extension(ev, CLASSIFICATION_EXTENSION) {
+        metadata.set_classification(classification);
+    }
+    Ok(Message {
+        id,
+        channel_id,
+        sender: required_extension(ev, SENDER_EXTENSION)?,
+        recipient: required_extension(ev, RECIPIENT_EXTENSION)?,
+        content,
+        timestamp,
+        edited_at: None,
+        hash: String::new(),
+        metadata,
+        feedback_weights: vec![],
+        text: String::new(),
+        intent: Intent::default(),
+        payment: None,
+        nonce: 0,
+        name: String::new(),
+        data: vec![],
+        header: String::new(),
+        body: String::new(),
+        contexts: vec![],
+        values: vec![],
+        entity_graph: EntityGraphImpl::new(),
+        attachments: vec![],
+    })
+}
+
+fn string_extension(ev: &Event, name: &str) -> Option<String> {
+    match ev.extension(name) {
+        Some(ExtensionValue::String(value)) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+fn required_extension(ev: &Event, name: &str) -> Result<String, BigbotError> {
+    string_extension(ev, name).ok_or_else(|| {
+        BigbotError::InvalidInput(format!("event is missing the '{}' extension", name))
+    })
+}
 
 pub struct CloudEventHandler {
     producer: KafkaSink,
@@ -70,15 +177,62 @@ impl CloudEventHandler {
         }
     }
 
+    // A message event for bare text that has no sender, recipient or channel
     pub fn create_cloudevent(classification: String, message: String) -> Event {
-        let event = EventBuilderV10::new()
-            .id(Uuid::new_v4().to_string())
+        let mut message = Message::new("", "", &message);
+        message.metadata.set_classification(classification);
+        message_to_cloudevent(&message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn message() -> Message {
+        let mut metadata = MessageMetadata::new();
+        metadata.set_classification("support".to_string());
+        Message {
+            channel_id: Uuid::new_v4(),
+            timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap(),
+            metadata,
+            ..Message::for_test("alice", "bob", "Where is my order?")
+        }
+    }
+
+    #[test]
+    fn test_message_round_trips_through_cloudevent() {
+        let original = message();
+        let event = message_to_cloudevent(&original);
+        assert_eq!(event.ty(), MESSAGE_EVENT_TYPE);
+
+        let parsed = cloudevent_to_message(&event).unwrap();
+        assert_eq!(parsed.id, original.id);
+        assert_eq!(parsed.channel_id, original.channel_id);
+        assert_eq!(parsed.sender, original.sender);
+        assert_eq!(parsed.recipient, original.recipient);
+        assert_eq!(parsed.content, original.content);
+        assert_eq!(parsed.timestamp, original.timestamp);
+        assert_eq!(parsed.metadata.classification(), Some("support"));
+    }
+
+    #[test]
+    fn test_parse_rejects_events_missing_message_attributes() {
+        let foreign = EventBuilderV10::new()
+            .id("1")
             .source("example.com/message")
             .ty("message.classified")
-            .data("text/plain", message.as_bytes())
-            .extension("classification", classification)
             .build()
             .unwrap();
-        event
+        assert!(matches!(
+            cloudevent_to_message(&foreign),
+            Err(BigbotError::InvalidInput(_))
+        ));
+
+        let mut event = message_to_cloudevent(&message());
+        event.remove_extension(SENDER_EXTENSION);
+        let error = cloudevent_to_message(&event).unwrap_err();
+        assert!(error.to_string().contains("sender"));
     }
 }
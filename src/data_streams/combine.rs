@@ -1,9 +1,12 @@
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use futures::stream::{BoxStream, StreamExt};
 use futures_core::Stream;
 use pin_project_lite::pin_project;
 
+use crate::data_streams::Error;
+
 pin_project! {
     pub(crate) struct Combine<T, S, F>
     where
@@ -45,4 +48,156 @@ where
             },
         }
     }
-}
\ No newline at end of file
+}
+
+/// Interleaves several sources into one stream, polling them round-robin so a busy source can't
+/// starve the others. Errors are yielded as items, and the merged stream only ends once every
+/// source has ended.
+pub fn merge<'a, T: 'a>(
+    sources: Vec<BoxStream<'a, Result<T, Error>>>,
+) -> BoxStream<'a, Result<T, Error>> {
+    merge_with_source_id(sources).map(|(_, item)| item).boxed()
+}
+
+/// Like [merge], but tags every item with the index of the source it came from.
+pub fn merge_with_source_id<'a, T: 'a>(
+    sources: Vec<BoxStream<'a, Result<T, Error>>>,
+) -> BoxStream<'a, (usize, Result<T, Error>)> {
+    Merge {
+        sources: sources.into_iter().map(Some).collect(),
+        next: 0,
+    }
+    .boxed()
+}
+
+struct Merge<'a, I> {
+    // Sources that have ended are set to `None` so the remaining ones keep their index
+    sources: Vec<Option<BoxStream<'a, I>>>,
+    // Index of the source polled first on the next call
+    next: usize,
+}
+
+impl<'a, I> Stream for Merge<'a, I> {
+    type Item = (usize, I);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let count = this.sources.len();
+        for offset in 0..count {
+            let index = (this.next + offset) % count;
+            let Some(source) = this.sources[index].as_mut() else {
+                continue;
+            };
+            match source.poll_next_unpin(cx) {
+                Poll::Ready(Some(item)) => {
+                    this.next = (index + 1) % count;
+                    return Poll::Ready(Some((index, item)));
+                }
+                Poll::Ready(None) => this.sources[index] = None,
+                Poll::Pending => {}
+            }
+        }
+        if this.sources.iter().all(Option::is_none) {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_streams::mock::MockBroker;
+    use crate::data_streams::Error;
+    use futures::stream;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn test_combine_yields_items_until_task_completes() {
+        let broker = MockBroker::new();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let task = async move {
+            let _ = stop_rx.await;
+        };
+        let mut combined = Box::pin(Combine::new(broker.subscribe("events"), task));
+
+        broker.push("events", 1);
+        broker.push("events", 2);
+        assert_eq!(combined.next().await.unwrap().unwrap(), 1);
+        assert_eq!(combined.next().await.unwrap().unwrap(), 2);
+
+        broker.fail_next(Error::Cancelled);
+        assert!(matches!(combined.next().await, Some(Err(Error::Cancelled))));
+
+        broker.push("events", 3);
+        stop_tx.send(()).unwrap();
+        assert!(combined.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_combine_ends_when_stream_closes() {
+        let broker = MockBroker::new();
+        let mut combined = Box::pin(Combine::new(
+            broker.subscribe("events"),
+            futures::future::pending::<()>(),
+        ));
+
+        broker.push("events", "only");
+        broker.close("events");
+        assert_eq!(combined.next().await.unwrap().unwrap(), "only");
+        assert!(combined.next().await.is_none());
+    }
+
+    fn source(items: Vec<Result<u32, Error>>) -> BoxStream<'static, Result<u32, Error>> {
+        stream::iter(items).boxed()
+    }
+
+    fn tagged(item: Option<(usize, Result<u32, Error>)>) -> Option<(usize, u32)> {
+        item.map(|(id, item)| (id, item.unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_merge_yields_source_errors_as_items() {
+        let merged = merge(vec![
+            source(vec![Ok(1), Err(Error::Cancelled), Ok(2)]),
+            source(vec![Ok(10), Ok(11), Ok(12)]),
+        ]);
+        let items: Vec<Result<u32, Error>> = merged.collect().await;
+
+        assert_eq!(items.len(), 6);
+        assert!(matches!(items[2], Err(Error::Cancelled)));
+        let values: Vec<u32> = items.into_iter().filter_map(Result::ok).collect();
+        assert_eq!(values, vec![1, 10, 11, 2, 12]);
+    }
+
+    #[tokio::test]
+    async fn test_merge_continues_after_a_source_ends() {
+        let broker = MockBroker::new();
+        let mut merged = merge_with_source_id(vec![
+            source(vec![Ok(1)]),
+            broker.subscribe("events").boxed(),
+        ]);
+
+        assert_eq!(tagged(merged.next().await), Some((0, 1)));
+        broker.push("events", 2);
+        broker.push("events", 3);
+        assert_eq!(tagged(merged.next().await), Some((1, 2)));
+        assert_eq!(tagged(merged.next().await), Some((1, 3)));
+
+        broker.close("events");
+        assert!(merged.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_merge_is_fair_across_sources() {
+        let merged = merge_with_source_id(vec![
+            source(vec![Ok(0), Ok(1), Ok(2)]),
+            source(vec![Ok(10), Ok(11), Ok(12)]),
+            source(vec![Ok(20), Ok(21), Ok(22)]),
+        ]);
+        let ids: Vec<usize> = merged.map(|(id, _)| id).collect().await;
+
+        assert_eq!(ids, vec![0, 1, 2, 0, 1, 2, 0, 1, 2]);
+    }
+}
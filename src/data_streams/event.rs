@@ -3,6 +3,7 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use futures::future::join_all;
 use lazy_static::lazy_static;
+use thiserror::Error;
 use tokio::sync::Mutex;
 
 
@@ -19,7 +20,7 @@ pub async fn handle_event(event: Event) {
     EVENT_MANAGER.lock().await.handle(event);
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Location(pub f32, pub f32, pub f32);
 
 #[derive(Debug, Copy, Clone)]
@@ -55,6 +56,186 @@ pub struct Event {
 }
 
 
+#[derive(Debug, Error, PartialEq)]
+pub enum EventError {
+    #[error("Missing required event field: {0}")]
+    MissingField(&'static str),
+    #[error("Event starts at {start} which is after its end {end}")]
+    StartAfterEnd { start: i64, end: i64 },
+    #[error("Invalid event location: {0:?}")]
+    InvalidLocation(Location),
+}
+
+// builds an `Event` from named setters instead of the full field list. `id`, `name`
+// and `event_type` are taken up front, the time range is required at build time and
+// every other field falls back to an empty/zero default.
+#[derive(Debug, Clone)]
+pub struct EventBuilder {
+    id: i64,
+    name: String,
+    event_type: EventType,
+    unique_id: Option<String>,
+    user_id: Option<i64>,
+    time: i64,
+    header: EventHeader,
+    location: Location,
+    time_range: Option<(u64, u64)>,
+    slot: Option<(i32, i32)>,
+    significance: f64,
+    attributes: HashMap<String, f64>,
+    dependencies: Vec<Arc<Event>>,
+    resource: String,
+    tags: Vec<String>,
+}
+
+impl EventBuilder {
+    pub fn new(id: i64, name: impl Into<String>, event_type: EventType) -> Self {
+        Self {
+            id,
+            name: name.into(),
+            event_type,
+            unique_id: None,
+            user_id: None,
+            time: 0,
+            header: EventHeader::default(),
+            location: Location(0.0, 0.0, 0.0),
+            time_range: None,
+            slot: None,
+            significance: 0.0,
+            attributes: HashMap::new(),
+            dependencies: Vec::new(),
+            resource: String::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    // defaults to the stringified `id`
+    pub fn unique_id(mut self, unique_id: impl Into<String>) -> Self {
+        self.unique_id = Some(unique_id.into());
+        self
+    }
+
+    pub fn user_id(mut self, user_id: i64) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    pub fn time(mut self, time: i64) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn header(mut self, header: EventHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn location(mut self, location: Location) -> Self {
+        self.location = location;
+        self
+    }
+
+    // sets `start_time`/`end_time` and `duration`. required.
+    pub fn time_range(mut self, start_time: u64, end_time: u64) -> Self {
+        self.time_range = Some((start_time, end_time));
+        self
+    }
+
+    // sets the scheduling slot (`start`/`end`). defaults to the time range.
+    pub fn slot(mut self, start: i32, end: i32) -> Self {
+        self.slot = Some((start, end));
+        self
+    }
+
+    pub fn significance(mut self, significance: f64) -> Self {
+        self.significance = significance;
+        self
+    }
+
+    pub fn attribute(mut self, name: impl Into<String>, value: f64) -> Self {
+        self.attributes.insert(name.into(), value);
+        self
+    }
+
+    pub fn attributes(mut self, attributes: HashMap<String, f64>) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    pub fn dependency(mut self, dependency: Arc<Event>) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    pub fn dependencies(mut self, dependencies: Vec<Arc<Event>>) -> Self {
+        self.dependencies = dependencies;
+        self
+    }
+
+    pub fn resource(mut self, resource: impl Into<String>) -> Self {
+        self.resource = resource.into();
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn build(self) -> Result<Event, EventError> {
+        let (start_time, end_time) = self.time_range.ok_or(EventError::MissingField("time_range"))?;
+        if start_time > end_time {
+            return Err(EventError::StartAfterEnd {
+                start: start_time as i64,
+                end: end_time as i64,
+            });
+        }
+        let (start, end) = match self.slot {
+            Some(slot) => slot,
+            None => (
+                i32::try_from(start_time).unwrap_or(i32::MAX),
+                i32::try_from(end_time).unwrap_or(i32::MAX),
+            ),
+        };
+        if start > end {
+            return Err(EventError::StartAfterEnd {
+                start: start as i64,
+                end: end as i64,
+            });
+        }
+        let Location(x, y, z) = self.location;
+        if !(x.is_finite() && y.is_finite() && z.is_finite()) {
+            return Err(EventError::InvalidLocation(self.location));
+        }
+
+        Ok(Event {
+            unique_id: self.unique_id.unwrap_or_else(|| self.id.to_string()),
+            user_id: self.user_id,
+            time: self.time,
+            header: self.header,
+            event_type: self.event_type,
+            id: self.id,
+            name: self.name,
+            location: self.location,
+            start_time,
+            end_time,
+            significance: self.significance,
+            attributes: self.attributes,
+            duration: Duration(start_time, end_time),
+            dependencies: self.dependencies,
+            start,
+            end,
+            resource: self.resource,
+            tags: self.tags,
+        })
+    }
+}
+
 // the type of this event. i.e user made an utterance, user scheduled a plan
 // or user posted a media in the dialogue, etc.
 #[derive(Debug, Clone)]
@@ -140,14 +321,13 @@ pub trait EventHandler: Send {
 mod test {
     use std::sync::Arc;
     use std::time::Duration;
-    use std::collections::HashMap;
-    use crate::event::Location;
+    use crate::event::{EventBuilder, EventError, Location};
 
     use async_trait::async_trait;
     use tokio::sync::Mutex;
 
     use crate::event::{
-        handle_event, register_event_handler, Event, EventHandler, EventHeader, EventType,
+        handle_event, register_event_handler, Event, EventHandler, EventType,
     };
 
     #[tokio::test]
@@ -186,25 +366,13 @@ mod test {
             EventType::Scheduled("Go shopping tomorrow".to_string()),
         ]
         .into_iter()
-        .map(|x| Event {
-            unique_id: "UNIQUE_ID".to_string(),
-            user_id: Some(1),
-            time: 0,
-            header: EventHeader::default(),
-            event_type: x,
-            id: 0,
-            name: "".to_string(),
-            location: Location(0.0, 0.0, 0.0),
-            start_time: 0,
-            end_time: 0,
-            significance: 0.0,
-            attributes: HashMap::new(),
-            duration: Duration::from_secs(0),
-            dependencies: Vec::new(),
-            start: 0,
-            end: 0,
-            resource: "".to_string(),
-            tags: Vec::new(),
+        .map(|x| {
+            EventBuilder::new(0, "", x)
+                .unique_id("UNIQUE_ID")
+                .user_id(1)
+                .time_range(0, 0)
+                .build()
+                .unwrap()
         })
         .collect();
         
@@ -228,4 +396,48 @@ mod test {
         assert_eq!(uids.pop(), Some(1));
         assert!(uids.is_empty());
     }
+
+    #[test]
+    fn test_event_builder_valid_build() {
+        let dependency = Arc::new(
+            EventBuilder::new(1, "setup", EventType::ScheduledEvent)
+                .time_range(0, 5)
+                .build()
+                .unwrap(),
+        );
+        let event = EventBuilder::new(2, "talk", EventType::Conference)
+            .location(Location(1.0, 2.0, 3.0))
+            .time_range(5, 10)
+            .resource("hall")
+            .attribute("importance", 0.8)
+            .tag("music")
+            .dependency(dependency)
+            .build()
+            .unwrap();
+
+        assert_eq!(event.unique_id, "2");
+        assert_eq!((event.start_time, event.end_time), (5, 10));
+        assert_eq!((event.start, event.end), (5, 10));
+        assert_eq!(event.resource, "hall");
+        assert_eq!(event.attributes.get("importance"), Some(&0.8));
+        assert_eq!(event.tags, vec!["music".to_string()]);
+        assert_eq!(event.dependencies.len(), 1);
+    }
+
+    #[test]
+    fn test_event_builder_rejects_start_after_end() {
+        let result = EventBuilder::new(1, "talk", EventType::Conference)
+            .time_range(10, 5)
+            .build();
+        assert_eq!(result.unwrap_err(), EventError::StartAfterEnd { start: 10, end: 5 });
+
+        let result = EventBuilder::new(1, "talk", EventType::Conference).build();
+        assert_eq!(result.unwrap_err(), EventError::MissingField("time_range"));
+
+        let result = EventBuilder::new(1, "talk", EventType::Conference)
+            .time_range(0, 1)
+            .location(Location(f32::NAN, 0.0, 0.0))
+            .build();
+        assert!(matches!(result, Err(EventError::InvalidLocation(_))));
+    }
 }
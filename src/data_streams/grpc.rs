@@ -1,8 +1,22 @@
 use async_trait::async_trait;
-use grpcio::Channel;
+use futures::Stream;
+use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Channel;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::error;
 
-use crate::protos::my_grpc_service::{MyServiceClient, MyRequest};
+use crate::data_streams;
+use crate::messaging::message::Message;
+use crate::protos::message_stream::message_exchange_client::MessageExchangeClient;
+use crate::protos::message_stream::message_exchange_server::{MessageExchange, MessageExchangeServer};
+use crate::protos::message_stream::{StreamAck, StreamMessage};
+use crate::protos::my_grpc_service as proto;
+use crate::protos::my_grpc_service::my_service_client::MyServiceClient;
 
 // Define request and response types for the gRPC service
 pub struct HelloRequest {
@@ -32,7 +46,7 @@ pub trait HelloClient {
 }
 
 pub struct HelloClientImpl {
-    client: MyServiceClient,
+    client: MyServiceClient<Channel>,
 }
 
 impl HelloClientImpl {
@@ -42,14 +56,16 @@ impl HelloClientImpl {
     }
 }
 
+#[async_trait]
 impl HelloClient for HelloClientImpl {
     async fn say_hello(&self, req: &HelloRequest) -> Result<HelloResponse, Box<dyn std::error::Error>> {
-        let request = MyRequest {
-            data: req.name.clone(),
+        let request = proto::HelloRequest {
+            name: req.name.clone(),
         };
-        let response = self.client.my_method(&request).await?;
+        // Tonic clients are cheap to clone and calls need `&mut self`
+        let response = self.client.clone().say_hello(request).await?;
         Ok(HelloResponse {
-            message: response.result,
+            message: response.into_inner().message,
         })
     }
 }
@@ -89,7 +105,7 @@ where
     C: HelloClient + Send + Sync,
 {
     pub fn new(connection_info: Option<&ConnectionInfo>, client_factory: impl Fn(String) -> C) -> Self {
-        let address = connection_info.map(|info| &info.grpc_address).unwrap_or("localhost:50051");
+        let address = connection_info.map(|info| info.grpc_address.as_str()).unwrap_or("localhost:50051");
         let client = client_factory(address.to_string());
         GrpcDataExchangeImpl { client }
     }
@@ -134,3 +150,186 @@ where
         Ok(result)
     }
 }
+
+/// Messages a `GrpcSink` buffers before `consume` waits on the server.
+pub const DEFAULT_GRPC_SINK_CAPACITY: usize = 32;
+
+/// Streams `Message`s to a `MessageExchange` server over a single client stream. `consume` waits
+/// while the outbound buffer is full, so a slow server slows the producer down instead of growing
+/// the buffer. The `Ack` waits until the server has acknowledged every message consumed so far.
+pub struct GrpcSink {
+    outbound: mpsc::Sender<StreamMessage>,
+    sent: AtomicU64,
+    // Acknowledgements are counted by the task reading the response stream
+    acked: watch::Receiver<u64>,
+}
+
+impl GrpcSink {
+    /// Connects to `endpoint`, e.g. `http://127.0.0.1:50051`, and opens the message stream. Must
+    /// be called from within a Tokio runtime.
+    pub async fn connect(endpoint: impl Into<String>) -> Result<Self, data_streams::Error> {
+        Self::connect_with_capacity(endpoint, DEFAULT_GRPC_SINK_CAPACITY).await
+    }
+
+    pub async fn connect_with_capacity(
+        endpoint: impl Into<String>,
+        capacity: usize,
+    ) -> Result<Self, data_streams::Error> {
+        let mut client = MessageExchangeClient::connect(endpoint.into())
+            .await
+            .map_err(|e| data_streams::Error::InternalError(Box::new(e)))?;
+        let (outbound, rx) = mpsc::channel(capacity);
+        let (acked_tx, acked) = watch::channel(0);
+        tokio::spawn(async move {
+            let mut acks = match client.exchange(ReceiverStream::new(rx)).await {
+                Ok(response) => response.into_inner(),
+                Err(status) => {
+                    error!("Error when opening the message stream: {}", status);
+                    return;
+                }
+            };
+            loop {
+                match acks.message().await {
+                    Ok(Some(_ack)) => acked_tx.send_modify(|acked| *acked += 1),
+                    Ok(None) => return,
+                    Err(status) => {
+                        error!("Error when reading message acks: {}", status);
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            outbound,
+            sent: AtomicU64::new(0),
+            acked,
+        })
+    }
+}
+
+#[async_trait]
+impl<M> data_streams::Sink<M, data_streams::Error> for GrpcSink
+where
+    M: Borrow<Message> + Send + Sync,
+{
+    /// Returns `Cancelled` once the stream to the server has closed.
+    async fn consume(&self, item: M) -> Result<(), data_streams::Error>
+    where
+        M: 'async_trait,
+    {
+        let message = item.borrow();
+        let outbound = StreamMessage {
+            id: message.id.to_string(),
+            payload: serde_json::to_vec(message).map_err(data_streams::Error::CodecError)?,
+        };
+        self.outbound
+            .send(outbound)
+            .await
+            .map_err(|_| data_streams::Error::Cancelled)?;
+        self.sent.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl data_streams::Ack for GrpcSink {
+    /// Waits for the server to acknowledge every message consumed before the call. Returns
+    /// `Cancelled` if the stream closed first.
+    async fn ack(&self) -> Result<(), data_streams::Error> {
+        let sent = self.sent.load(Ordering::SeqCst);
+        let mut acked = self.acked.clone();
+        acked
+            .wait_for(|acked| *acked >= sent)
+            .await
+            .map_err(|_| data_streams::Error::Cancelled)?;
+        Ok(())
+    }
+}
+
+type AckStream = Pin<Box<dyn Stream<Item = Result<StreamAck, Status>> + Send + 'static>>;
+
+/// Serves `MessageExchange` streams by forwarding each message to the classifier channel. The
+/// next message is only read once the channel has accepted the previous one, so a full channel
+/// pushes back on clients through HTTP/2 flow control.
+#[derive(Clone)]
+pub struct GrpcMessageService {
+    classifier: mpsc::Sender<Message>,
+}
+
+impl GrpcMessageService {
+    pub fn new(classifier: mpsc::Sender<Message>) -> Self {
+        Self { classifier }
+    }
+
+    pub fn into_server(self) -> MessageExchangeServer<Self> {
+        MessageExchangeServer::new(self)
+    }
+}
+
+#[async_trait]
+impl MessageExchange for GrpcMessageService {
+    type ExchangeStream = AckStream;
+
+    async fn exchange(
+        &self,
+        request: Request<Streaming<StreamMessage>>,
+    ) -> Result<Response<Self::ExchangeStream>, Status> {
+        let mut inbound = request.into_inner();
+        let classifier = self.classifier.clone();
+        let acks = async_stream::try_stream! {
+            while let Some(received) = inbound.message().await? {
+                let message: Message = serde_json::from_slice(&received.payload).map_err(|e| {
+                    Status::invalid_argument(format!("invalid message {}: {}", received.id, e))
+                })?;
+                classifier
+                    .send(message)
+                    .await
+                    .map_err(|_| Status::unavailable("the classifier has stopped"))?;
+                yield StreamAck { id: received.id };
+            }
+        };
+        Ok(Response::new(Box::pin(acks)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_streams::{Ack, Sink};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    fn message(content: &str) -> Message {
+        Message::for_test("1", "2", content)
+    }
+
+    #[tokio::test]
+    async fn test_sink_round_trips_through_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (classifier, mut classified) = mpsc::channel(1);
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(GrpcMessageService::new(classifier).into_server())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let sink = GrpcSink::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        let sent = message("hello over grpc");
+        sink.consume(sent.clone()).await.unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(5), classified.recv())
+            .await
+            .expect("server should forward the message")
+            .unwrap();
+        assert_eq!(received.id, sent.id);
+        assert_eq!(received.content, "hello over grpc");
+        tokio::time::timeout(Duration::from_secs(5), sink.ack())
+            .await
+            .expect("server should acknowledge the message")
+            .unwrap();
+    }
+}
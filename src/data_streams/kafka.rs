@@ -4,6 +4,14 @@
 //! and sending them to a Kafka topic. It provides an asynchronous `consume` method that serializes
 //! the input item and sends it as a message to the specified Kafka topic.
 //!
+//! `KafkaSink<T>`, built with `KafkaSink::connect`, is the typed egress for `data_streams`: it
+//! implements `data_streams::Sink<T, data_streams::Error>`, serializing items with serde_json and
+//! keying each record with a user-supplied function. `consume` only enqueues the record; its `Ack`
+//! waits for every record enqueued since the last ack to be delivered. At most
+//! `MAX_PENDING_DELIVERIES` records are left outstanding: past that, `consume` waits for them and
+//! holds on to the first failure for the next ack. The producer is any `RecordProducer`, which
+//! `FutureProducer` implements.
+//!
 //! `KafkaStream` encapsulates the streaming of messages from a Kafka topic, converting them into
 //! CloudEvents and wrapping them in an `Envelope`. It leverages `rdkafka`'s `MessageStream` for
 //! consuming messages and utilizes the CloudEvents SDK for message conversion.
@@ -27,6 +35,8 @@
 //!
 //! Usage:
 //! - `KafkaSink::new` creates a new instance of `KafkaSink` with a specified `FutureProducer` and topic.
+//! - `KafkaSink::connect` creates a typed `KafkaSink<T>` from a broker list, topic, compression and key function.
+//! - `KafkaSink::with_producer` creates a typed `KafkaSink<T, P>` over an existing `RecordProducer`.
 //! - `KafkaStream::new` initializes a new instance of `KafkaStream` with a specified message stream and sender for replies.
 //! - `DataExchangeKafkaConsumer::new` creates a new Kafka consumer for data exchange.
 //! - `DataExchangeKafkaConsumer::stream` returns a stream of enveloped events or errors, integrating Kafka consumption
@@ -48,7 +58,9 @@ use serde_json::to_value;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
+use std::future::Future;
 use std::pin::Pin;
+use std::sync::Mutex;
 use std::task::{Context, Poll};
 use std::time::Duration;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
@@ -141,14 +153,174 @@ impl DataExchange<String, Result<String, Box<dyn std::error::Error + Send + Sync
     }
 }
 
-pub struct KafkaSink {
-    pub producer: FutureProducer,
+// Most deliveries `consume` leaves outstanding between acks. Reaching it makes `consume` wait for
+// them, so a sink that is never acked doesn't grow without bound.
+const MAX_PENDING_DELIVERIES: usize = 1024;
+
+// Resolves once the broker has accepted or rejected an enqueued record
+pub type PendingDelivery = Pin<Box<dyn Future<Output = Result<(), data_streams::Error>> + Send>>;
+
+/// The part of a Kafka producer the typed `KafkaSink<T>` needs.
+pub trait RecordProducer: Send + Sync {
+    /// Enqueues a record without waiting for the broker.
+    fn enqueue(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: &[u8],
+    ) -> Result<PendingDelivery, data_streams::Error>;
+}
+
+impl RecordProducer for FutureProducer {
+    fn enqueue(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: &[u8],
+    ) -> Result<PendingDelivery, data_streams::Error> {
+        let record = FutureRecord::to(topic).payload(payload).key(key);
+        let delivery = self
+            .send_result(record)
+            .map_err(|(e, _record)| data_streams::Error::InternalError(Box::new(e)))?;
+        Ok(Box::pin(async move {
+            match delivery.await {
+                Err(_canceled) => Err(data_streams::Error::Cancelled),
+                Ok(Err((e, _msg))) => Err(data_streams::Error::InternalError(Box::new(e))),
+                Ok(Ok(_)) => Ok(()),
+            }
+        }))
+    }
+}
+
+pub struct KafkaSink<T = (), P = FutureProducer> {
+    pub producer: P,
     pub topic: String,
+    key_fn: Box<dyn Fn(&T) -> String + Send + Sync>,
+    // Deliveries enqueued by `consume` that the next `ack` waits for
+    pending: Mutex<Vec<PendingDelivery>>,
+    // First failure among deliveries `consume` had to wait for, reported by the next `ack`
+    failed: Mutex<Option<data_streams::Error>>,
 }
 
 impl KafkaSink {
     pub fn new(producer: FutureProducer, topic: String) -> Self {
-        Self { producer, topic }
+        Self::with_producer(producer, topic, |_| String::new())
+    }
+}
+
+impl<T, P> KafkaSink<T, P> {
+    /// Creates a sink over any `RecordProducer`, keying each record with `key_fn`.
+    pub fn with_producer(
+        producer: P,
+        topic: impl Into<String>,
+        key_fn: impl Fn(&T) -> String + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            producer,
+            topic: topic.into(),
+            key_fn: Box::new(key_fn),
+            pending: Mutex::new(Vec::new()),
+            failed: Mutex::new(None),
+        }
+    }
+}
+
+// Waits for every delivery and returns the first failure
+async fn settle(deliveries: Vec<PendingDelivery>) -> Option<data_streams::Error> {
+    let mut first_error = None;
+    for delivery in deliveries {
+        if let Err(e) = delivery.await {
+            first_error.get_or_insert(e);
+        }
+    }
+    first_error
+}
+
+/// Compression codecs supported by the Kafka producer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KafkaCompression {
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl KafkaCompression {
+    fn as_str(&self) -> &'static str {
+        match self {
+            KafkaCompression::Gzip => "gzip",
+            KafkaCompression::Snappy => "snappy",
+            KafkaCompression::Lz4 => "lz4",
+            KafkaCompression::Zstd => "zstd",
+        }
+    }
+}
+
+impl<T> KafkaSink<T>
+where
+    T: Serialize + Send + Sync,
+{
+    /// Creates a producer for `brokers` (a comma-separated `host:port` list) writing to `topic`.
+    /// Each record's partition key is `key_fn` applied to the item.
+    pub fn connect(
+        brokers: &str,
+        topic: impl Into<String>,
+        compression: Option<KafkaCompression>,
+        key_fn: impl Fn(&T) -> String + Send + Sync + 'static,
+    ) -> Result<Self, data_streams::Error> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", brokers);
+        if let Some(compression) = compression {
+            config.set("compression.type", compression.as_str());
+        }
+        let producer: FutureProducer = config
+            .create()
+            .map_err(|e| data_streams::Error::InternalError(Box::new(e)))?;
+        Ok(Self::with_producer(producer, topic, key_fn))
+    }
+}
+
+#[async_trait]
+impl<T, P> data_streams::Sink<T, data_streams::Error> for KafkaSink<T, P>
+where
+    T: Serialize + Send + Sync,
+    P: RecordProducer,
+{
+    async fn consume(&self, item: T) -> Result<(), data_streams::Error>
+    where
+        T: 'async_trait,
+    {
+        let payload = serde_json::to_vec(&item).map_err(data_streams::Error::CodecError)?;
+        let key = (self.key_fn)(&item);
+        let delivery = self.producer.enqueue(&self.topic, &key, &payload)?;
+        let full = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push(delivery);
+            if pending.len() >= MAX_PENDING_DELIVERIES {
+                std::mem::take(&mut *pending)
+            } else {
+                Vec::new()
+            }
+        };
+        if let Some(e) = settle(full).await {
+            self.failed.lock().unwrap().get_or_insert(e);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T, P> data_streams::Ack for KafkaSink<T, P>
+where
+    T: Send + Sync,
+    P: RecordProducer,
+{
+    /// Waits for every record enqueued since the last ack and returns the first delivery failure.
+    async fn ack(&self) -> Result<(), data_streams::Error> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let latest = settle(pending).await;
+        let earlier = self.failed.lock().unwrap().take();
+        earlier.or(latest).map_or(Ok(()), Err)
     }
 }
 
@@ -313,3 +485,83 @@ impl DataExchangeKafkaConsumer {
         KafkaStream::new(self.consumer.stream(), tx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_streams::{Ack, Sink as _};
+
+    // Records what was enqueued and fails the delivery of any record keyed "bad"
+    #[derive(Default)]
+    struct MockProducer {
+        records: Mutex<Vec<(String, String, Vec<u8>)>>,
+    }
+
+    impl RecordProducer for MockProducer {
+        fn enqueue(
+            &self,
+            topic: &str,
+            key: &str,
+            payload: &[u8],
+        ) -> Result<PendingDelivery, data_streams::Error> {
+            self.records.lock().unwrap().push((
+                topic.to_string(),
+                key.to_string(),
+                payload.to_vec(),
+            ));
+            let fails = key == "bad";
+            Ok(Box::pin(async move {
+                if fails {
+                    Err(data_streams::Error::Cancelled)
+                } else {
+                    Ok(())
+                }
+            }))
+        }
+    }
+
+    fn sink() -> KafkaSink<String, MockProducer> {
+        KafkaSink::with_producer(MockProducer::default(), "events", |item: &String| {
+            item.clone()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_ack_reports_delivery_failures_once() {
+        let sink = sink();
+        sink.consume("good".to_string()).await.unwrap();
+        sink.ack().await.unwrap();
+
+        sink.consume("bad".to_string()).await.unwrap();
+        sink.consume("good".to_string()).await.unwrap();
+        assert!(matches!(
+            sink.ack().await,
+            Err(data_streams::Error::Cancelled)
+        ));
+        // The failure was reported, so an ack with nothing new outstanding succeeds
+        sink.ack().await.unwrap();
+
+        let records = sink.producer.records.lock().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].0, "events");
+        assert_eq!(records[1].1, "bad");
+        assert_eq!(records[1].2, br#""bad""#.to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_outstanding_deliveries_are_capped() {
+        let sink = sink();
+        sink.consume("bad".to_string()).await.unwrap();
+        for _ in 1..MAX_PENDING_DELIVERIES + 10 {
+            sink.consume("good".to_string()).await.unwrap();
+        }
+        assert!(sink.pending.lock().unwrap().len() < MAX_PENDING_DELIVERIES);
+        // The failure settled by `consume` is still reported by the next ack
+        assert!(matches!(
+            sink.ack().await,
+            Err(data_streams::Error::Cancelled)
+        ));
+        assert!(sink.pending.lock().unwrap().is_empty());
+        sink.ack().await.unwrap();
+    }
+}
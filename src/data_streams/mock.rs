@@ -1,10 +1,16 @@
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use async_trait::async_trait;
 use pin_project_lite::pin_project;
 use tokio::time::{sleep, Duration, Instant, Sleep};
 use tokio_stream::Stream;
 
+use super::{Ack, Error, Sink};
+
 pin_project! {
     /// A mock source that emits a unit item at a specified interval.
     pub struct MockSource<T> {
@@ -65,4 +71,389 @@ where
     fn size_hint(&self) -> (usize, Option<usize>) {
         (usize::MAX, None)
     }
-}
\ No newline at end of file
+}
+
+/// A scriptable in-memory broker for driving consumers deterministically in tests.
+///
+/// Tests `push` payloads onto topics, `close` topics to end their streams and
+/// `fail_next` to inject an error into the next stream read or sink write. Streams
+/// obtained from `subscribe` yield exactly what was pushed, in order, and end once the
+/// topic is closed and drained. Sinks obtained from `sink` publish onto the topic (so
+/// subscribers see them) and record every payload for later inspection via `published`.
+///
+/// ```ignore
+/// let broker = MockBroker::new();
+/// let mut stream = broker.subscribe("events");
+/// broker.push("events", 1);
+/// broker.close("events");
+/// assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+/// assert!(stream.next().await.is_none());
+/// ```
+pub struct MockBroker<T> {
+    state: Arc<Mutex<BrokerState<T>>>,
+}
+
+struct BrokerState<T> {
+    topics: HashMap<String, TopicState<T>>,
+    pending_failure: Option<Error>,
+}
+
+struct TopicState<T> {
+    queue: VecDeque<T>,
+    published: Vec<T>,
+    closed: bool,
+    wakers: Vec<Waker>,
+}
+
+impl<T> Default for TopicState<T> {
+    fn default() -> Self {
+        TopicState {
+            queue: VecDeque::new(),
+            published: Vec::new(),
+            closed: false,
+            wakers: Vec::new(),
+        }
+    }
+}
+
+impl<T> TopicState<T> {
+    fn wake_all(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Clone for MockBroker<T> {
+    fn clone(&self) -> Self {
+        MockBroker {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> Default for MockBroker<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MockBroker<T> {
+    /// Creates an empty broker with no topics.
+    pub fn new() -> Self {
+        MockBroker {
+            state: Arc::new(Mutex::new(BrokerState {
+                topics: HashMap::new(),
+                pending_failure: None,
+            })),
+        }
+    }
+
+    /// Enqueues a payload on the topic, waking any subscriber waiting on it.
+    pub fn push(&self, topic: &str, payload: T) {
+        let mut state = self.state.lock().unwrap();
+        let topic = state.topics.entry(topic.to_string()).or_default();
+        topic.queue.push_back(payload);
+        topic.wake_all();
+    }
+
+    /// Closes the topic; its stream ends after the already queued payloads are drained.
+    pub fn close(&self, topic: &str) {
+        let mut state = self.state.lock().unwrap();
+        let topic = state.topics.entry(topic.to_string()).or_default();
+        topic.closed = true;
+        topic.wake_all();
+    }
+
+    /// Makes the next stream read or sink write, on any topic, fail with `error`.
+    pub fn fail_next(&self, error: Error) {
+        let mut state = self.state.lock().unwrap();
+        state.pending_failure = Some(error);
+        for topic in state.topics.values_mut() {
+            topic.wake_all();
+        }
+    }
+
+    /// Returns a stream of the payloads pushed or published on the topic.
+    pub fn subscribe(&self, topic: &str) -> MockBrokerStream<T> {
+        self.state
+            .lock()
+            .unwrap()
+            .topics
+            .entry(topic.to_string())
+            .or_default();
+        MockBrokerStream {
+            state: self.state.clone(),
+            topic: topic.to_string(),
+        }
+    }
+
+    /// Returns a sink publishing onto the topic.
+    pub fn sink(&self, topic: &str) -> MockBrokerSink<T> {
+        MockBrokerSink {
+            state: self.state.clone(),
+            topic: topic.to_string(),
+        }
+    }
+
+    /// Returns the payloads successfully written to the topic through a sink, in order.
+    pub fn published(&self, topic: &str) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.state
+            .lock()
+            .unwrap()
+            .topics
+            .get(topic)
+            .map(|topic| topic.published.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// The stream side of a `MockBroker` topic.
+pub struct MockBrokerStream<T> {
+    state: Arc<Mutex<BrokerState<T>>>,
+    topic: String,
+}
+
+impl<T> Stream for MockBrokerStream<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = state.pending_failure.take() {
+            return Poll::Ready(Some(Err(error)));
+        }
+
+        let topic = state.topics.entry(self.topic.clone()).or_default();
+        if let Some(payload) = topic.queue.pop_front() {
+            Poll::Ready(Some(Ok(payload)))
+        } else if topic.closed {
+            Poll::Ready(None)
+        } else {
+            topic.wakers.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// The sink side of a `MockBroker` topic.
+pub struct MockBrokerSink<T> {
+    state: Arc<Mutex<BrokerState<T>>>,
+    topic: String,
+}
+
+#[async_trait]
+impl<T> Sink<T, Error> for MockBrokerSink<T>
+where
+    T: Clone + Send,
+{
+    async fn consume(&self, item: T) -> Result<(), Error>
+    where
+        T: 'async_trait,
+    {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = state.pending_failure.take() {
+            return Err(error);
+        }
+
+        let topic = state.topics.entry(self.topic.clone()).or_default();
+        if topic.closed {
+            return Err(Error::Cancelled);
+        }
+        topic.published.push(item.clone());
+        topic.queue.push_back(item);
+        topic.wake_all();
+        Ok(())
+    }
+}
+
+/// A sink that records everything it consumes, for asserting on what a pipeline wrote.
+///
+/// Clones share the same record, so keep a clone before boxing the sink into a `MultiSink` or
+/// `BufferedSink`. A sink made with `failing_after(n)` accepts `n` items and then fails every
+/// further write with `Error::InternalError`, without recording it.
+///
+/// ```ignore
+/// let sink = MockSink::new();
+/// let fanout = MultiSink::new(vec![Box::new(sink.clone())]);
+/// fanout.consume(1).await?;
+/// fanout.consume(2).await?;
+/// assert_eq!(sink.received(), vec![Arc::new(1), Arc::new(2)]);
+///
+/// let flaky = MockSink::failing_after(1);
+/// assert!(flaky.consume(1).await.is_ok());
+/// assert!(flaky.consume(2).await.is_err());
+/// assert_eq!(flaky.received(), vec![1]);
+/// ```
+pub struct MockSink<T> {
+    received: Arc<Mutex<Vec<T>>>,
+    fail_after: Option<usize>,
+}
+
+impl<T> Clone for MockSink<T> {
+    fn clone(&self) -> Self {
+        MockSink {
+            received: self.received.clone(),
+            fail_after: self.fail_after,
+        }
+    }
+}
+
+impl<T> Default for MockSink<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MockSink<T> {
+    /// Creates a sink that accepts every item.
+    pub fn new() -> Self {
+        MockSink {
+            received: Arc::new(Mutex::new(Vec::new())),
+            fail_after: None,
+        }
+    }
+
+    /// Creates a sink that accepts `n` items and fails every write after that.
+    pub fn failing_after(n: usize) -> Self {
+        MockSink {
+            fail_after: Some(n),
+            ..Self::new()
+        }
+    }
+
+    /// Returns the items consumed so far, in order.
+    pub fn received(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl<T> Sink<T, Error> for MockSink<T>
+where
+    T: Send,
+{
+    async fn consume(&self, item: T) -> Result<(), Error>
+    where
+        T: 'async_trait,
+    {
+        let mut received = self.received.lock().unwrap();
+        if let Some(n) = self.fail_after {
+            if received.len() >= n {
+                return Err(Error::InternalError(
+                    format!("MockSink fails after {} items", n).into(),
+                ));
+            }
+        }
+        received.push(item);
+        Ok(())
+    }
+}
+
+/// An `Ack` that counts how many times it was acknowledged. Clones share the count.
+///
+/// ```ignore
+/// let ack = MockAck::new();
+/// ack.ack().await?;
+/// assert_eq!(ack.count(), 1);
+/// ```
+#[derive(Clone, Default)]
+pub struct MockAck {
+    count: Arc<AtomicUsize>,
+}
+
+impl MockAck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how many times `ack` has been called.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Ack for MockAck {
+    async fn ack(&self) -> Result<(), Error> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_streams::MultiSink;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_sink_write_retried_after_injected_failure() {
+        let broker = MockBroker::new();
+        let sink = broker.sink("orders");
+        let mut stream = broker.subscribe("orders");
+
+        broker.fail_next(Error::Cancelled);
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match sink.consume("order-1".to_string()).await {
+                Ok(()) => break,
+                Err(_) if attempts < 3 => continue,
+                Err(e) => panic!("write kept failing: {}", e),
+            }
+        }
+
+        assert_eq!(attempts, 2);
+        assert_eq!(broker.published("orders"), vec!["order-1".to_string()]);
+        assert_eq!(stream.next().await.unwrap().unwrap(), "order-1");
+    }
+
+    #[tokio::test]
+    async fn test_closed_topic_ends_stream_and_rejects_writes() {
+        let broker = MockBroker::new();
+        let mut stream = broker.subscribe("orders");
+        broker.push("orders", 7);
+        broker.close("orders");
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), 7);
+        assert!(stream.next().await.is_none());
+        assert!(matches!(broker.sink("orders").consume(8).await, Err(Error::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn test_mock_sink_records_through_multi_sink() {
+        let first = MockSink::new();
+        let second = MockSink::new();
+        let fanout: MultiSink<i32, Error> =
+            MultiSink::new(vec![Box::new(first.clone()), Box::new(second.clone())]);
+
+        fanout.consume(1).await.unwrap();
+        fanout.consume(2).await.unwrap();
+        assert_eq!(first.received(), vec![Arc::new(1), Arc::new(2)]);
+        assert_eq!(second.received(), vec![Arc::new(1), Arc::new(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_mock_sink_fails_after_n_items() {
+        let sink = MockSink::failing_after(2);
+        sink.consume("a").await.unwrap();
+        sink.consume("b").await.unwrap();
+        assert!(matches!(
+            sink.consume("c").await,
+            Err(Error::InternalError(_))
+        ));
+        assert!(sink.consume("d").await.is_err());
+        assert_eq!(sink.received(), vec!["a", "b"]);
+
+        let ack = MockAck::new();
+        ack.clone().ack().await.unwrap();
+        ack.ack().await.unwrap();
+        assert_eq!(ack.count(), 2);
+    }
+}
@@ -2,13 +2,20 @@ use std::fmt::{Display, Formatter};
 use std::ops::Deref;
 use std::sync::Arc;
 use async_trait::async_trait;
-use futures::future::try_join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
 
+use crate::utils::bigboterror::BigbotError;
+
+pub mod buffered;
+pub mod cloudevents;
+pub mod combine;
+pub mod grpc;
 pub mod kafka;
 pub mod mock;
 pub mod mqtt;
-
-mod combine;
+pub mod postgres;
+pub mod topics;
+pub mod window;
 
 /// Domain-specific error
 #[derive(Debug)]
@@ -43,6 +50,18 @@ pub trait Sink<T, E> {
     async fn consume(&self, item: T) -> Result<(), E>
     where
         T: 'async_trait;
+
+    // Consumes items one at a time; sinks that can write in bulk should override this
+    async fn consume_batch(&self, items: Vec<T>) -> Result<(), E>
+    where
+        T: Send + 'async_trait,
+        Self: Sync,
+    {
+        for item in items {
+            self.consume(item).await?;
+        }
+        Ok(())
+    }
 }
 
 pub struct DrainSink<E>(std::marker::PhantomData<E>);
@@ -67,58 +86,168 @@ where
     }
 }
 
+/// How `MultiSink` decides whether a fan-out succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FanoutPolicy {
+    /// Every sink must accept the item; the first error fails the fan-out.
+    #[default]
+    AllOrNothing,
+    /// Runs every sink and succeeds if at least one accepts the item.
+    BestEffort,
+    /// Succeeds as soon as this many sinks accept the item, dropping the writes still in flight.
+    /// Must be between 1 and the number of sinks.
+    Quorum(usize),
+}
+
+/// The outcome of a fan-out that met its policy.
+#[derive(Debug)]
+pub struct ConsumeReport<E> {
+    /// Sinks that accepted the item.
+    pub acked: usize,
+    /// Errors from the sinks that failed before the policy was met.
+    pub errors: Vec<E>,
+}
+
 pub struct MultiSink<T, E> {
     sinks: Vec<Box<dyn Sink<Arc<T>, E> + Send + Sync>>,
+    policy: FanoutPolicy,
 }
 
 impl<T, E> MultiSink<T, E> {
     pub fn new(sinks: Vec<Box<dyn Sink<Arc<T>, E> + Send + Sync>>) -> Self {
-        Self { sinks }
+        Self {
+            sinks,
+            policy: FanoutPolicy::default(),
+        }
+    }
+
+    /// Fails for a quorum of zero or one larger than the number of sinks, which could never be met
+    /// as asked.
+    pub fn with_policy(mut self, policy: FanoutPolicy) -> Result<Self, BigbotError> {
+        if let FanoutPolicy::Quorum(n) = policy {
+            if n == 0 || n > self.sinks.len() {
+                return Err(BigbotError::InvalidInput(format!(
+                    "Quorum of {} needs between 1 and {} sinks",
+                    n,
+                    self.sinks.len()
+                )));
+            }
+        }
+        self.policy = policy;
+        Ok(self)
+    }
+
+    pub fn policy(&self) -> FanoutPolicy {
+        self.policy
     }
 }
 
-#[async_trait]
-impl<'a, T, E> Sink<T, E> for MultiSink<T, E>
+impl<T, E> MultiSink<T, E>
 where
     T: Send + Sync,
     E: Send,
 {
-    async fn consume(&self, item: T) -> Result<(), E>
-    where
-        T: 'async_trait,
-    {
+    /// Sends the item to every sink according to the policy. When the policy can't be met, the
+    /// first error is returned.
+    pub async fn consume_with_report(&self, item: T) -> Result<ConsumeReport<E>, E> {
         let shared_item = Arc::new(item);
-        let fut_vec: Vec<_> = self
+        let mut pending: FuturesUnordered<_> = self
             .sinks
             .iter()
             .map(|sink| sink.consume(shared_item.clone()))
             .collect();
-        let _ = try_join_all(fut_vec).await?;
-        Ok(())
+        let required = match self.policy {
+            FanoutPolicy::AllOrNothing => self.sinks.len(),
+            FanoutPolicy::BestEffort => 1.min(self.sinks.len()),
+            FanoutPolicy::Quorum(n) => n,
+        };
+        let mut report = ConsumeReport {
+            acked: 0,
+            errors: Vec::new(),
+        };
+        while report.acked < required {
+            // `required` never exceeds the number of sinks, so a future is always left here
+            let Some(result) = pending.next().await else {
+                break;
+            };
+            match result {
+                Ok(()) => report.acked += 1,
+                Err(e) => {
+                    report.errors.push(e);
+                    if report.acked + pending.len() < required {
+                        return Err(report.errors.swap_remove(0));
+                    }
+                }
+            }
+        }
+        // Best effort still waits on the remaining sinks so every one gets the item
+        if self.policy == FanoutPolicy::BestEffort {
+            while let Some(result) = pending.next().await {
+                match result {
+                    Ok(()) => report.acked += 1,
+                    Err(e) => report.errors.push(e),
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[async_trait]
+impl<T, E> Sink<T, E> for MultiSink<T, E>
+where
+    T: Send + Sync,
+    E: Send,
+{
+    async fn consume(&self, item: T) -> Result<(), E>
+    where
+        T: 'async_trait,
+    {
+        self.consume_with_report(item).await.map(|_| ())
     }
 }
 
+// Logs each item through the `log` facade at the sink's level
 pub struct LogSink {
     name: String,
+    level: log::Level,
 }
 
 impl LogSink {
     pub fn new(name: String) -> Self {
-        Self { name }
+        Self::with_level(name, log::Level::Info)
+    }
+
+    pub fn with_level(name: String, level: log::Level) -> Self {
+        Self { name, level }
+    }
+
+    pub fn level(&self) -> log::Level {
+        self.level
     }
 }
 
+// Items a `LogSink` can consume; exists to explain the `Debug` requirement at the use site
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be consumed by a `LogSink` because it doesn't implement `Debug`",
+    label = "`LogSink` logs items with `{{:?}}`",
+    note = "derive or implement `std::fmt::Debug` for `{Self}`, or map items to a `Debug` type before the sink"
+)]
+pub trait Loggable: std::fmt::Debug {}
+
+impl<T: std::fmt::Debug + ?Sized> Loggable for T {}
+
 #[async_trait]
 impl<T, E> Sink<T, E> for LogSink
 where
-    T: Send + Sync + std::fmt::Debug,
+    T: Send + Sync + Loggable,
     E: Send,
 {
     async fn consume(&self, item: T) -> Result<(), E>
     where
         T: 'async_trait,
     {
-        println!("{}: {:?}", self.name, item);
+        log::log!(self.level, "{}: {:?}", self.name, item);
         Ok(())
     }
 }
@@ -126,4 +255,111 @@ where
 #[async_trait]
 pub trait Ack {
     async fn ack(&self) -> Result<(), Error>;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_streams::mock::MockBroker;
+
+    // A sink whose writes never complete, standing in for a stalled downstream
+    struct StalledSink;
+
+    #[async_trait]
+    impl<T: Send, E> Sink<T, E> for StalledSink {
+        async fn consume(&self, _item: T) -> Result<(), E>
+        where
+            T: 'async_trait,
+        {
+            futures::future::pending().await
+        }
+    }
+
+    struct FailingSink;
+
+    #[async_trait]
+    impl<T: Send> Sink<T, Error> for FailingSink {
+        async fn consume(&self, _item: T) -> Result<(), Error>
+        where
+            T: 'async_trait,
+        {
+            Err(Error::Cancelled)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fanout_policies() {
+        let broker = MockBroker::new();
+        let sinks = || -> Vec<Box<dyn Sink<Arc<u32>, Error> + Send + Sync>> {
+            vec![
+                Box::new(broker.sink("a")),
+                Box::new(FailingSink),
+                Box::new(broker.sink("b")),
+            ]
+        };
+
+        let all = MultiSink::new(sinks());
+        assert_eq!(all.policy(), FanoutPolicy::AllOrNothing);
+        assert!(matches!(all.consume(1).await, Err(Error::Cancelled)));
+
+        let best_effort = MultiSink::new(sinks())
+            .with_policy(FanoutPolicy::BestEffort)
+            .unwrap();
+        let report = best_effort.consume_with_report(2).await.unwrap();
+        assert_eq!(report.acked, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert!(broker.published("a").contains(&Arc::new(2)));
+        assert!(broker.published("b").contains(&Arc::new(2)));
+
+        let nobody: MultiSink<u32, Error> =
+            MultiSink::new(vec![Box::new(FailingSink), Box::new(FailingSink)])
+                .with_policy(FanoutPolicy::BestEffort)
+                .unwrap();
+        assert!(nobody.consume(3).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_quorum_returns_without_waiting_for_stalled_sinks() {
+        let broker = MockBroker::new();
+        let quorum: MultiSink<u32, Error> = MultiSink::new(vec![
+            Box::new(StalledSink),
+            Box::new(broker.sink("a")),
+            Box::new(broker.sink("b")),
+        ])
+        .with_policy(FanoutPolicy::Quorum(2))
+        .unwrap();
+        let report = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            quorum.consume_with_report(7),
+        )
+        .await
+        .expect("quorum should not wait on the stalled sink")
+        .unwrap();
+        assert_eq!(report.acked, 2);
+
+        let unreachable: MultiSink<u32, Error> = MultiSink::new(vec![
+            Box::new(FailingSink),
+            Box::new(FailingSink),
+            Box::new(broker.sink("a")),
+        ])
+        .with_policy(FanoutPolicy::Quorum(2))
+        .unwrap();
+        assert!(unreachable.consume(8).await.is_err());
+    }
+
+    #[test]
+    fn test_unreachable_quorums_are_rejected() {
+        let sinks = || -> Vec<Box<dyn Sink<Arc<u32>, Error> + Send + Sync>> {
+            vec![Box::new(FailingSink), Box::new(FailingSink)]
+        };
+        assert!(MultiSink::new(sinks())
+            .with_policy(FanoutPolicy::Quorum(0))
+            .is_err());
+        assert!(MultiSink::new(sinks())
+            .with_policy(FanoutPolicy::Quorum(3))
+            .is_err());
+        assert!(MultiSink::new(sinks())
+            .with_policy(FanoutPolicy::Quorum(2))
+            .is_ok());
+    }
+}
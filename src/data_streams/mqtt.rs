@@ -1,19 +1,28 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::marker::PhantomData;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::task::{Context, Poll};
+use std::time::Duration;
+use async_trait::async_trait;
 use cloudevents::{Event, EventBuilder, EventBuilderV10};
 use futures::Future;
 use futures::Stream;
 use futures_core::future::LocalBoxFuture;
 use rumqttc::v5::mqttbytes::QoS;
 use rumqttc::v5::mqttbytes::v5::Publish;
-use rumqttc::v5::{AsyncClient, ConnectionError, Event as MQTTEvent, EventLoop, Incoming};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
-use tracing::error;
+use rumqttc::v5::{AsyncClient, ConnectionError, Event as MQTTEvent, EventLoop, Incoming, MqttOptions};
+use rumqttc::Outgoing;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, UnboundedSender};
+use tokio::sync::watch;
+use tracing::{error, warn};
 
 use crate::data_exchange::exchange_core::{Envelope, Reply};
+use crate::data_streams;
 use crate::data_streams::combine::Combine;
 use crate::utils::bigboterror::BigbotError;
 
@@ -234,3 +243,429 @@ impl DataExchangeMQTTStream {
         }
     }
 }
+
+// Capacity of the request channel between an `AsyncClient` and its event loop
+const REQUEST_CHANNEL_CAPACITY: usize = 64;
+
+/// Delays between reconnection attempts, doubling from `initial` up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MqttBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for MqttBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+// Polls an event loop, spacing out failed polls. rumqttc reconnects on the next poll after an
+// error, so a failed poll only needs to be retried later.
+struct Connection {
+    event_loop: EventLoop,
+    backoff: MqttBackoff,
+    delay: Duration,
+}
+
+impl Connection {
+    fn new(event_loop: EventLoop, backoff: MqttBackoff) -> Self {
+        Self {
+            event_loop,
+            backoff,
+            delay: backoff.initial,
+        }
+    }
+
+    // The next event, or None once every client has been dropped
+    async fn next(&mut self) -> Option<MQTTEvent> {
+        loop {
+            match self.event_loop.poll().await {
+                Ok(event) => {
+                    self.delay = self.backoff.initial;
+                    return Some(event);
+                }
+                Err(ConnectionError::RequestsDone) => return None,
+                Err(e) => {
+                    warn!(
+                        "MQTT connection error, reconnecting in {:?}: {}",
+                        self.delay, e
+                    );
+                    tokio::time::sleep(self.delay).await;
+                    self.delay = (self.delay * 2).min(self.backoff.max);
+                }
+            }
+        }
+    }
+}
+
+// Matches broker acknowledgements to publishes by packet id. Publishes are numbered in the order
+// the event loop sends them, and a retransmission after a reconnect keeps its number.
+#[derive(Debug, Default)]
+struct AckTracker {
+    sent: u64,
+    in_flight: HashMap<u16, u64>,
+}
+
+impl AckTracker {
+    fn sent(&mut self, pkid: u16) {
+        if !self.in_flight.contains_key(&pkid) {
+            self.sent += 1;
+            self.in_flight.insert(pkid, self.sent);
+        }
+    }
+
+    // Acknowledgements for packet ids with nothing in flight, such as duplicates, are ignored
+    fn acked(&mut self, pkid: u16) {
+        self.in_flight.remove(&pkid);
+    }
+
+    // How many publishes, counting from the first, have all been acknowledged
+    fn acked_through(&self) -> u64 {
+        self.in_flight
+            .values()
+            .min()
+            .map_or(self.sent, |oldest| oldest - 1)
+    }
+}
+
+/// Publishes serde_json-serialized items to a single topic. `consume` only hands the publish to
+/// the client; for QoS above `AtMostOnce` its `Ack` waits until the broker has acknowledged every
+/// item consumed so far (PUBACK for `AtLeastOnce`, PUBCOMP for `ExactlyOnce`).
+pub struct MqttSink<T> {
+    client: AsyncClient,
+    topic: String,
+    qos: QoS,
+    // Publishes handed to the client; the event loop task reports how many of them, from the
+    // first, the broker has acknowledged
+    published: AtomicU64,
+    acked: watch::Receiver<u64>,
+    _item: PhantomData<fn(T)>,
+}
+
+impl<T> MqttSink<T>
+where
+    T: Serialize + Send + Sync,
+{
+    /// Connects to the broker in `options`. Must be called from within a Tokio runtime, which
+    /// drives the connection until the sink is dropped.
+    pub fn connect(options: MqttOptions, topic: impl Into<String>, qos: QoS) -> Self {
+        Self::connect_with_backoff(options, topic, qos, MqttBackoff::default())
+    }
+
+    pub fn connect_with_backoff(
+        options: MqttOptions,
+        topic: impl Into<String>,
+        qos: QoS,
+        backoff: MqttBackoff,
+    ) -> Self {
+        let (client, event_loop) = AsyncClient::new(options, REQUEST_CHANNEL_CAPACITY);
+        let (acked_tx, acked) = watch::channel(0);
+        let mut connection = Connection::new(event_loop, backoff);
+        tokio::spawn(async move {
+            let mut tracker = AckTracker::default();
+            while let Some(event) = connection.next().await {
+                match event {
+                    MQTTEvent::Outgoing(Outgoing::Publish(pkid)) if qos != QoS::AtMostOnce => {
+                        tracker.sent(pkid)
+                    }
+                    MQTTEvent::Incoming(Incoming::PubAck(ack)) if qos == QoS::AtLeastOnce => {
+                        tracker.acked(ack.pkid)
+                    }
+                    MQTTEvent::Incoming(Incoming::PubComp(comp)) if qos == QoS::ExactlyOnce => {
+                        tracker.acked(comp.pkid)
+                    }
+                    _ => {}
+                }
+                acked_tx.send_if_modified(|acked| {
+                    let through = tracker.acked_through();
+                    std::mem::replace(acked, through) != through
+                });
+                if acked_tx.is_closed() {
+                    return;
+                }
+            }
+        });
+        Self {
+            client,
+            topic: topic.into(),
+            qos,
+            published: AtomicU64::new(0),
+            acked,
+            _item: PhantomData,
+        }
+    }
+
+    pub fn qos(&self) -> QoS {
+        self.qos
+    }
+}
+
+#[async_trait]
+impl<T> data_streams::Sink<T, data_streams::Error> for MqttSink<T>
+where
+    T: Serialize + Send + Sync,
+{
+    async fn consume(&self, item: T) -> Result<(), data_streams::Error>
+    where
+        T: 'async_trait,
+    {
+        let payload = serde_json::to_vec(&item).map_err(data_streams::Error::CodecError)?;
+        self.client
+            .publish(self.topic.as_str(), self.qos, false, payload)
+            .await
+            .map_err(|e| data_streams::Error::InternalError(Box::new(e)))?;
+        if self.qos != QoS::AtMostOnce {
+            self.published.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T> data_streams::Ack for MqttSink<T>
+where
+    T: Send + Sync,
+{
+    /// Waits for the broker to acknowledge every item consumed before the call. Returns
+    /// `Cancelled` if the connection task has stopped.
+    async fn ack(&self) -> Result<(), data_streams::Error> {
+        let published = self.published.load(Ordering::SeqCst);
+        let mut acked = self.acked.clone();
+        acked
+            .wait_for(|acked| *acked >= published)
+            .await
+            .map_err(|_| data_streams::Error::Cancelled)?;
+        Ok(())
+    }
+}
+
+// Publishes an `MqttSource` holds before it stops polling the broker until the stream catches up
+const SOURCE_BUFFER_CAPACITY: usize = 256;
+
+/// Subscribes to a topic filter and yields each publish deserialized with serde_json. The
+/// subscription is renewed after every reconnect, and payloads that fail to decode are yielded
+/// as `CodecError` without ending the stream. At most `SOURCE_BUFFER_CAPACITY` publishes are
+/// buffered; past that the connection waits for the stream to be read.
+pub struct MqttSource<T> {
+    events: Receiver<Result<T, data_streams::Error>>,
+}
+
+impl<T> MqttSource<T>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    /// Connects to the broker in `options` and subscribes to `topic`. Must be called from within
+    /// a Tokio runtime, which drives the connection until the source is dropped.
+    pub fn subscribe(options: MqttOptions, topic: impl Into<String>, qos: QoS) -> Self {
+        Self::subscribe_with_backoff(options, topic, qos, MqttBackoff::default())
+    }
+
+    pub fn subscribe_with_backoff(
+        options: MqttOptions,
+        topic: impl Into<String>,
+        qos: QoS,
+        backoff: MqttBackoff,
+    ) -> Self {
+        let topic = topic.into();
+        let (client, event_loop) = AsyncClient::new(options, REQUEST_CHANNEL_CAPACITY);
+        let (tx, events) = channel(SOURCE_BUFFER_CAPACITY);
+        let mut connection = Connection::new(event_loop, backoff);
+        tokio::spawn(async move {
+            while let Some(event) = connection.next().await {
+                match event {
+                    // The subscribe request is only sent once the event loop is polled again, so it
+                    // is queued rather than awaited
+                    MQTTEvent::Incoming(Incoming::ConnAck(_)) => {
+                        if let Err(e) = client.try_subscribe(topic.as_str(), qos) {
+                            error!("Error when subscribing to {}: {}", topic, e);
+                        }
+                    }
+                    MQTTEvent::Incoming(Incoming::Publish(publish)) => {
+                        let item = serde_json::from_slice(&publish.payload)
+                            .map_err(data_streams::Error::CodecError);
+                        if tx.send(item).await.is_err() {
+                            return;
+                        }
+                    }
+                    _ => {}
+                }
+                if tx.is_closed() {
+                    return;
+                }
+            }
+        });
+        Self { events }
+    }
+}
+
+impl<T> Stream for MqttSource<T> {
+    type Item = Result<T, data_streams::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().events.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_streams::{Ack, Sink};
+    use bytes::BytesMut;
+    use futures::StreamExt;
+    use rumqttc::v5::mqttbytes::v5::{
+        ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, SubAck, SubscribeReasonCode,
+    };
+    use serde::Deserialize;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::broadcast;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        sensor: String,
+        value: f64,
+    }
+
+    // Starts a broker on a local port that is just capable enough for a round trip: it
+    // acknowledges connects, subscriptions, pings and QoS 1 publishes, and forwards every publish
+    // to every connection that has subscribed, whatever its filter
+    async fn start_broker() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (publishes, _) = broadcast::channel(16);
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(socket, publishes.clone()));
+            }
+        });
+        port
+    }
+
+    async fn serve_connection(mut socket: TcpStream, publishes: broadcast::Sender<Publish>) {
+        let mut forwarded = publishes.subscribe();
+        let mut subscribed = false;
+        let mut next_pkid: u16 = 0;
+        let mut read = BytesMut::new();
+        loop {
+            let mut replies = Vec::new();
+            tokio::select! {
+                n = socket.read_buf(&mut read) => {
+                    if !matches!(n, Ok(n) if n > 0) {
+                        return;
+                    }
+                    loop {
+                        match Packet::read(&mut read, None) {
+                            Ok(Packet::Connect(..)) => replies.push(Packet::ConnAck(ConnAck {
+                                session_present: false,
+                                code: ConnectReturnCode::Success,
+                                properties: None,
+                            })),
+                            Ok(Packet::Subscribe(subscribe)) => {
+                                subscribed = true;
+                                replies.push(Packet::SubAck(SubAck {
+                                    pkid: subscribe.pkid,
+                                    return_codes: subscribe
+                                        .filters
+                                        .iter()
+                                        .map(|filter| SubscribeReasonCode::Success(filter.qos))
+                                        .collect(),
+                                    properties: None,
+                                }));
+                            }
+                            Ok(Packet::Publish(publish)) => {
+                                if publish.qos == QoS::AtLeastOnce {
+                                    replies.push(Packet::PubAck(PubAck::new(publish.pkid, None)));
+                                }
+                                let _ = publishes.send(publish);
+                            }
+                            Ok(Packet::PingReq(_)) => replies.push(Packet::PingResp(PingResp)),
+                            Ok(Packet::Disconnect(_)) => return,
+                            Ok(_) => {}
+                            Err(rumqttc::v5::mqttbytes::Error::InsufficientBytes(_)) => break,
+                            Err(_) => return,
+                        }
+                    }
+                }
+                Ok(mut publish) = forwarded.recv(), if subscribed => {
+                    next_pkid = next_pkid % u16::MAX + 1;
+                    publish.pkid = next_pkid;
+                    replies.push(Packet::Publish(publish));
+                }
+            }
+            let mut write = BytesMut::new();
+            for reply in replies {
+                reply.write(&mut write).unwrap();
+            }
+            if socket.write_all(&write).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_acks_are_matched_to_publishes_by_packet_id() {
+        let mut tracker = AckTracker::default();
+        for pkid in [1, 2, 3] {
+            tracker.sent(pkid);
+        }
+        // An ack for a later publish doesn't cover the ones still in flight before it
+        tracker.acked(2);
+        assert_eq!(tracker.acked_through(), 0);
+        tracker.acked(1);
+        assert_eq!(tracker.acked_through(), 2);
+        // Duplicate and unknown acks change nothing
+        tracker.acked(1);
+        tracker.acked(9);
+        assert_eq!(tracker.acked_through(), 2);
+
+        // A retransmission after a reconnect is still the third publish
+        tracker.sent(3);
+        tracker.acked(3);
+        assert_eq!(tracker.acked_through(), 3);
+
+        // Packet ids are reused once acknowledged
+        tracker.sent(1);
+        assert_eq!(tracker.acked_through(), 3);
+        tracker.acked(1);
+        assert_eq!(tracker.acked_through(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_sink_and_source_round_trip() {
+        let port = start_broker().await;
+
+        let mut source: MqttSource<Reading> = MqttSource::subscribe(
+            MqttOptions::new("mqtt-source-test", "127.0.0.1", port),
+            "readings/#",
+            QoS::AtLeastOnce,
+        );
+        let sink = MqttSink::connect(
+            MqttOptions::new("mqtt-sink-test", "127.0.0.1", port),
+            "readings/kitchen",
+            QoS::AtLeastOnce,
+        );
+        // Give the source time to subscribe before anything is published
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let reading = Reading {
+            sensor: "kitchen".to_string(),
+            value: 21.5,
+        };
+        sink.consume(reading.clone()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), sink.ack())
+            .await
+            .expect("broker should acknowledge the publish")
+            .unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(5), source.next())
+            .await
+            .expect("source should receive the publish")
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, reading);
+    }
+}
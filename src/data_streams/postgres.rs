@@ -0,0 +1,242 @@
+//! # Postgres Message Archive
+//!
+//! `PgMessageSink` archives messages in Postgres for long-term storage. It implements `Sink` so
+//! it can sit next to Kafka in a `MultiSink` and receive the same stream of messages.
+//!
+//! Messages are stored in a table partitioned by day, and each day is hash-partitioned by
+//! `channel_id`. Partitions are created on first use. The primary key is
+//! `(channel_id, day, message_id)`, and inserts skip rows that already exist, so redelivered
+//! messages are archived exactly once.
+//!
+//! `ensure_schema` is the only definition of the parent table; the partitions are created on
+//! demand.
+
+use crate::clients::postgres::PostgresClient;
+use crate::data_streams::{Error, Sink};
+use crate::messaging::message::Message;
+
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDate};
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashSet};
+use tokio::sync::Mutex;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
+
+pub const DEFAULT_ARCHIVE_TABLE: &str = "message_archive";
+
+// Number of hash partitions per day
+const CHANNEL_PARTITIONS: u32 = 4;
+
+// Postgres allows at most 65535 bind parameters per statement
+const COLUMNS_PER_ROW: usize = 7;
+const MAX_ROWS_PER_INSERT: usize = 1000;
+
+pub struct PgMessageSink {
+    client: PostgresClient,
+    table: String,
+    // Days whose partitions are known to exist
+    partitions: Mutex<HashSet<NaiveDate>>,
+}
+
+impl PgMessageSink {
+    pub fn new(client: PostgresClient) -> Self {
+        Self {
+            client,
+            table: DEFAULT_ARCHIVE_TABLE.to_string(),
+            partitions: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn with_table(mut self, table: &str) -> Self {
+        self.table = table.to_string();
+        self
+    }
+
+    // Creates the partitioned parent table if it doesn't exist
+    pub async fn ensure_schema(&self) -> Result<(), Error> {
+        let sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                message_id  UUID NOT NULL,
+                channel_id  UUID NOT NULL,
+                day         DATE NOT NULL,
+                sender      TEXT NOT NULL,
+                recipient   TEXT NOT NULL,
+                sent_at     TIMESTAMPTZ NOT NULL,
+                payload     JSONB NOT NULL,
+                PRIMARY KEY (channel_id, day, message_id)
+            ) PARTITION BY RANGE (day)",
+            self.table
+        );
+        self.connection().await?.batch_execute(&sql).await.map_err(pg_error)
+    }
+
+    async fn connection(&self) -> Result<deadpool_postgres::Object, Error> {
+        self.client.pool().get().await.map_err(|e| Error::InternalError(Box::new(e)))
+    }
+
+    // Creates the day's partition and its channel sub-partitions on first use
+    async fn ensure_partition(&self, day: NaiveDate) -> Result<(), Error> {
+        let mut partitions = self.partitions.lock().await;
+        if partitions.contains(&day) {
+            return Ok(());
+        }
+
+        let day_table = format!("{}_{}", self.table, day.format("%Y%m%d"));
+        let mut sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} PARTITION OF {} FOR VALUES FROM ('{}') TO ('{}') PARTITION BY HASH (channel_id);",
+            day_table,
+            self.table,
+            day,
+            day + Duration::days(1)
+        );
+        for remainder in 0..CHANNEL_PARTITIONS {
+            sql.push_str(&format!(
+                "CREATE TABLE IF NOT EXISTS {}_p{} PARTITION OF {} FOR VALUES WITH (MODULUS {}, REMAINDER {});",
+                day_table, remainder, day_table, CHANNEL_PARTITIONS, remainder
+            ));
+        }
+
+        match self.connection().await?.batch_execute(&sql).await {
+            Ok(()) => {}
+            // Another writer created the partition between our check and our create
+            Err(e) if matches!(e.code(), Some(code) if *code == SqlState::DUPLICATE_TABLE || *code == SqlState::UNIQUE_VIOLATION) => {}
+            Err(e) => return Err(pg_error(e)),
+        }
+        partitions.insert(day);
+        Ok(())
+    }
+
+    async fn insert_rows(&self, messages: &[&Message]) -> Result<(), Error> {
+        let payloads = messages
+            .iter()
+            .map(|message| serde_json::to_value(message).map_err(Error::CodecError))
+            .collect::<Result<Vec<_>, _>>()?;
+        let days: Vec<NaiveDate> = messages.iter().map(|message| message.timestamp.date_naive()).collect();
+
+        let mut values = Vec::with_capacity(messages.len());
+        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(messages.len() * COLUMNS_PER_ROW);
+        for (row, message) in messages.iter().enumerate() {
+            let first = row * COLUMNS_PER_ROW;
+            values.push(format!(
+                "(${}, ${}, ${}, ${}, ${}, ${}, ${})",
+                first + 1,
+                first + 2,
+                first + 3,
+                first + 4,
+                first + 5,
+                first + 6,
+                first + 7
+            ));
+            params.push(&message.id);
+            params.push(&message.channel_id);
+            params.push(&days[row]);
+            params.push(&message.sender);
+            params.push(&message.recipient);
+            params.push(&message.timestamp);
+            params.push(&payloads[row]);
+        }
+
+        let sql = format!(
+            "INSERT INTO {} (message_id, channel_id, day, sender, recipient, sent_at, payload) VALUES {} \
+             ON CONFLICT (channel_id, day, message_id) DO NOTHING",
+            self.table,
+            values.join(", ")
+        );
+        self.connection().await?.execute(&sql, &params).await.map_err(pg_error)?;
+        Ok(())
+    }
+
+    // Messages archived for a channel on a given day, oldest first
+    pub async fn messages_for_channel(&self, channel_id: uuid::Uuid, day: NaiveDate) -> Result<Vec<Message>, Error> {
+        let sql = format!(
+            "SELECT payload FROM {} WHERE channel_id = $1 AND day = $2 ORDER BY sent_at, message_id",
+            self.table
+        );
+        let rows = self.connection().await?.query(&sql, &[&channel_id, &day]).await.map_err(pg_error)?;
+        rows.into_iter()
+            .map(|row| serde_json::from_value(row.get(0)).map_err(Error::CodecError))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl<M> Sink<M, Error> for PgMessageSink
+where
+    M: Borrow<Message> + Send + Sync,
+{
+    async fn consume(&self, item: M) -> Result<(), Error>
+    where
+        M: 'async_trait,
+    {
+        self.consume_batch(vec![item]).await
+    }
+
+    async fn consume_batch(&self, items: Vec<M>) -> Result<(), Error>
+    where
+        M: 'async_trait,
+    {
+        let messages: Vec<&Message> = items.iter().map(|item| item.borrow()).collect();
+
+        let days: BTreeSet<NaiveDate> = messages.iter().map(|message| message.timestamp.date_naive()).collect();
+        for day in days {
+            self.ensure_partition(day).await?;
+        }
+
+        for chunk in messages.chunks(MAX_ROWS_PER_INSERT) {
+            self.insert_rows(chunk).await?;
+        }
+        Ok(())
+    }
+}
+
+fn pg_error(e: tokio_postgres::Error) -> Error {
+    Error::InternalError(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use testcontainers::clients::Cli;
+    use crate::clients::postgres::ConnectionInfo;
+    use std::sync::Arc;
+    use testcontainers_modules::postgres::Postgres;
+    use uuid::Uuid;
+
+    fn message(channel_id: Uuid, content: &str, hour: u32) -> Message {
+        Message {
+            channel_id,
+            timestamp: Utc.with_ymd_and_hms(2024, 3, 1, hour, 0, 0).unwrap(),
+            ..Message::for_test("1", "2", content)
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker for the Postgres testcontainer"]
+    async fn test_batch_insert_is_idempotent() {
+        let docker = Cli::default();
+        let container = docker.run(Postgres::default());
+        let info = ConnectionInfo::new("127.0.0.1", container.get_host_port_ipv4(5432), "postgres", "postgres")
+            .with_password("postgres");
+
+        let sink = PgMessageSink::new(PostgresClient::new(&info).unwrap());
+        sink.ensure_schema().await.unwrap();
+
+        let channel_id = Uuid::new_v4();
+        let batch = vec![
+            message(channel_id, "first", 9),
+            message(channel_id, "second", 10),
+            message(Uuid::new_v4(), "elsewhere", 11),
+        ];
+        sink.consume_batch(batch.clone()).await.unwrap();
+        // Redelivery of an already archived message is a no-op
+        sink.consume(Arc::new(batch[1].clone())).await.unwrap();
+
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let archived = sink.messages_for_channel(channel_id, day).await.unwrap();
+        let contents: Vec<&str> = archived.iter().map(|message| message.content.as_str()).collect();
+        assert_eq!(contents, vec!["first", "second"]);
+        assert_eq!(archived[0].id, batch[0].id);
+    }
+}
@@ -1,6 +1,7 @@
 //! # Topics Module
 //!
-//! This module defines constants and a function for generating Kafka topic names used in the data exchange pipeline.
+//! This module defines constants and a function for generating Kafka topic names used in the data exchange pipeline,
+//! and the `Topic` type that routing code uses to name per-classification, per-node and per-channel topics.
 //!
 //! ## Topic Constants
 //!
@@ -32,6 +33,18 @@
 //!
 //! The function returns a new `String` representing the dynamically generated topic name.
 //!
+//! ## Validated Topics
+//!
+//! `Topic` is a topic name that is valid on both Kafka and MQTT: 1 to 249 ASCII letters, digits, `.`, `_` or `-`,
+//! and not `.` or `..`. It is built with:
+//!
+//! - `Topic::new`: Validates an arbitrary name.
+//! - `Topic::classification`: The topic for a classifier label, e.g. `Reply message` becomes `reply-topic`.
+//! - `Topic::node`: The topic a routing node consumes, `node-<node id>`.
+//! - `Topic::channel`: The topic for a channel, `channel-<channel uuid>`.
+//!
+//! Each constructor returns `BigbotError::InvalidInput` for a name that breaks these rules.
+//!
 //! ## Usage
 //!
 //! To use the topics defined in this module, simply import the desired constants or the `dynamic_topic` function
@@ -40,7 +53,7 @@
 //! For example:
 //!
 //! ```rust
-//! use crate::data_exchange::topics::{INPUT_TOPIC, RESULT_TOPIC, dynamic_topic};
+//! use crate::data_streams::topics::{INPUT_TOPIC, RESULT_TOPIC, dynamic_topic};
 //!
 //! println!("Input Topic: {}", INPUT_TOPIC);
 //! println!("Result Topic: {}", RESULT_TOPIC);
@@ -48,8 +61,17 @@
 //! let session_id = "123";
 //! let session_topic = dynamic_topic(SESSION_TOPIC_PREFIX, session_id);
 //! println!("Session Topic: {}", session_topic);
+//!
+//! let node_topic = Topic::node("node-a")?;
+//! println!("Node Topic: {}", node_topic);
 //! ```
 
+use std::fmt::{Display, Formatter};
+
+use uuid::Uuid;
+
+use crate::utils::bigboterror::BigbotError;
+
 pub const INPUT_TOPIC: &str = "input-topic";
 pub const PRE_PROCESSING_TOPIC: &str = "pre-processing-topic";
 pub const INFERENCE_TOPIC_PREFIX: &str = "inference-topic";
@@ -66,3 +88,119 @@ pub const CONFIGURATION_TOPIC: &str = "configuration-topic";
 pub fn dynamic_topic(topic_prefix: &str, topic_suffix: &str) -> String {
     format!("{}-{}", topic_prefix, topic_suffix)
 }
+
+// Kafka's limit; MQTT allows longer topics
+pub const MAX_TOPIC_LENGTH: usize = 249;
+
+/// A topic name that is valid on both Kafka and MQTT.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Topic(String);
+
+impl Topic {
+    pub fn new(name: impl Into<String>) -> Result<Self, BigbotError> {
+        let name = name.into();
+        if name.is_empty() || name.len() > MAX_TOPIC_LENGTH {
+            return Err(BigbotError::InvalidInput(format!(
+                "topic '{}' must be 1 to {} characters long",
+                name, MAX_TOPIC_LENGTH
+            )));
+        }
+        if name == "." || name == ".." {
+            return Err(BigbotError::InvalidInput(format!(
+                "'{}' is not a valid topic",
+                name
+            )));
+        }
+        if let Some(invalid) = name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')))
+        {
+            return Err(BigbotError::InvalidInput(format!(
+                "topic '{}' contains '{}'; only letters, digits, '.', '_' and '-' are allowed",
+                name, invalid
+            )));
+        }
+        Ok(Topic(name))
+    }
+
+    /// The topic for a classifier label: lowercased, without a trailing " message", with spaces
+    /// replaced by `-` and suffixed with `-topic`.
+    pub fn classification(classification: &str) -> Result<Self, BigbotError> {
+        let label = classification.trim().to_lowercase();
+        let label = label.strip_suffix(" message").unwrap_or(&label);
+        if label.is_empty() {
+            return Err(BigbotError::InvalidInput(format!(
+                "'{}' is not a valid classification",
+                classification
+            )));
+        }
+        Self::new(format!("{}-topic", label.replace(' ', "-")))
+    }
+
+    pub fn node(node_id: &str) -> Result<Self, BigbotError> {
+        Self::new(format!("node-{}", node_id))
+    }
+
+    pub fn channel(channel_id: Uuid) -> Result<Self, BigbotError> {
+        Self::new(format!("channel-{}", channel_id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Topic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Topic {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification_topics() {
+        assert_eq!(
+            Topic::classification("Location-based message")
+                .unwrap()
+                .as_str(),
+            "location-based-topic"
+        );
+        assert_eq!(
+            Topic::classification("Reply message").unwrap().as_str(),
+            "reply-topic"
+        );
+        assert!(matches!(
+            Topic::classification("Spam/abuse message"),
+            Err(BigbotError::InvalidInput(_))
+        ));
+        assert!(Topic::classification("  ").is_err());
+        assert!(Topic::classification(&"a".repeat(MAX_TOPIC_LENGTH)).is_err());
+    }
+
+    #[test]
+    fn test_node_and_channel_topics_format_consistently() {
+        let channel_id = Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+        assert_eq!(
+            Topic::node("eu-west.1").unwrap().to_string(),
+            "node-eu-west.1"
+        );
+        assert_eq!(
+            Topic::channel(channel_id).unwrap().as_str(),
+            "channel-67e55044-10b1-426f-9247-bb680e5fe0c8"
+        );
+        assert_eq!(
+            Topic::channel(channel_id).unwrap(),
+            Topic::channel(channel_id).unwrap()
+        );
+        assert!(Topic::node("node #1").is_err());
+    }
+}
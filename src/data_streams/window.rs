@@ -0,0 +1,213 @@
+//! # Event-Time Windows
+//!
+//! Groups a stream into windows by the time each item says it happened rather than when it
+//! arrived. `tumbling_window` produces back-to-back windows; `sliding_window` produces windows of
+//! `size` starting every `slide`, so an item can fall into several of them. Windows are aligned to
+//! the unix epoch, so a one-minute window always covers a whole clock minute.
+//!
+//! A window is emitted once the watermark, the latest event time seen minus the allowed lateness,
+//! passes its end. Items arriving out of order are still counted as long as their window hasn't
+//! been emitted; later ones are dropped. Empty windows are not emitted, and whatever is still open
+//! when the input ends is flushed in order.
+
+use chrono::{DateTime, Utc};
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+use std::collections::{BTreeMap, VecDeque};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+pin_project! {
+    pub struct Window<S, F>
+    where
+        S: Stream,
+    {
+        #[pin]
+        stream: S,
+        event_time: F,
+        // Window length and distance between window starts, in milliseconds
+        size: i64,
+        slide: i64,
+        lateness: i64,
+        max_event_time: Option<i64>,
+        // Open windows by start time
+        open: BTreeMap<i64, Vec<S::Item>>,
+        ready: VecDeque<Vec<S::Item>>,
+        done: bool,
+    }
+}
+
+impl<S, F> Window<S, F>
+where
+    S: Stream,
+    F: Fn(&S::Item) -> DateTime<Utc>,
+{
+    fn new(stream: S, size: Duration, slide: Duration, event_time: F) -> Self {
+        let size = size.as_millis() as i64;
+        let slide = slide.as_millis() as i64;
+        assert!(size > 0 && slide > 0, "window size and slide must be at least a millisecond");
+        Self {
+            stream,
+            event_time,
+            size,
+            slide,
+            lateness: 0,
+            max_event_time: None,
+            open: BTreeMap::new(),
+            ready: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    // How far behind the latest event an item may be and still be counted
+    pub fn with_allowed_lateness(mut self, lateness: Duration) -> Self {
+        self.lateness = lateness.as_millis() as i64;
+        self
+    }
+}
+
+pub trait WindowExt: Stream {
+    // Consecutive, non-overlapping windows of `size`
+    fn tumbling_window<F>(self, size: Duration, event_time: F) -> Window<Self, F>
+    where
+        Self: Sized,
+        F: Fn(&Self::Item) -> DateTime<Utc>,
+    {
+        Window::new(self, size, size, event_time)
+    }
+
+    // Windows of `size` starting every `slide`; items are cloned into each window they fall in
+    fn sliding_window<F>(self, size: Duration, slide: Duration, event_time: F) -> Window<Self, F>
+    where
+        Self: Sized,
+        Self::Item: Clone,
+        F: Fn(&Self::Item) -> DateTime<Utc>,
+    {
+        Window::new(self, size, slide, event_time)
+    }
+}
+
+impl<S: Stream> WindowExt for S {}
+
+impl<S, F> Stream for Window<S, F>
+where
+    S: Stream,
+    S::Item: Clone,
+    F: Fn(&S::Item) -> DateTime<Utc>,
+{
+    type Item = Vec<S::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            if let Some(window) = this.ready.pop_front() {
+                return Poll::Ready(Some(window));
+            }
+            if *this.done {
+                return Poll::Ready(None);
+            }
+
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    let time = (this.event_time)(&item).timestamp_millis();
+                    let max_event_time = this.max_event_time.map_or(time, |max| max.max(time));
+                    *this.max_event_time = Some(max_event_time);
+                    let watermark = max_event_time - *this.lateness;
+
+                    // Every window containing `time` whose end the watermark hasn't passed yet
+                    let mut start = time.div_euclid(*this.slide) * *this.slide;
+                    while start > time - *this.size {
+                        if start + *this.size > watermark {
+                            this.open.entry(start).or_default().push(item.clone());
+                        }
+                        start -= *this.slide;
+                    }
+
+                    while let Some(entry) = this.open.first_entry() {
+                        if entry.key() + *this.size > watermark {
+                            break;
+                        }
+                        this.ready.push_back(entry.remove());
+                    }
+                }
+                Poll::Ready(None) => {
+                    this.ready.extend(std::mem::take(this.open).into_values());
+                    *this.done = true;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tokio_stream::StreamExt;
+
+    // (label, seconds since the epoch)
+    type Event = (&'static str, i64);
+
+    fn event_time(event: &Event) -> DateTime<Utc> {
+        Utc.timestamp_opt(event.1, 0).unwrap()
+    }
+
+    fn labels(windows: Vec<Vec<Event>>) -> Vec<Vec<&'static str>> {
+        windows
+            .into_iter()
+            .map(|window| window.into_iter().map(|(label, _)| label).collect())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_tumbling_window_boundaries() {
+        let events = vec![("a", 0), ("b", 59), ("c", 60), ("d", 119), ("e", 185)];
+        let windows = tokio_stream::iter(events)
+            .tumbling_window(Duration::from_secs(60), event_time)
+            .collect::<Vec<_>>()
+            .await;
+
+        // 60s starts a new window; the empty 120s-180s window is skipped
+        assert_eq!(labels(windows), vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+    }
+
+    #[tokio::test]
+    async fn test_sliding_window_overlaps() {
+        let events = vec![("a", 10), ("b", 40), ("c", 70)];
+        let windows = tokio_stream::iter(events)
+            .sliding_window(Duration::from_secs(60), Duration::from_secs(30), event_time)
+            .collect::<Vec<_>>()
+            .await;
+
+        // Windows start at -30, 0, 30 and 60
+        assert_eq!(labels(windows), vec![vec!["a"], vec!["a", "b"], vec!["b", "c"], vec!["c"]]);
+    }
+
+    #[tokio::test]
+    async fn test_late_events_within_and_beyond_lateness() {
+        // "late" arrives after 65s was seen but is within the 10s bound, so [0, 60) is still open;
+        // "too_late" arrives after 80s was seen, when [0, 60) has been emitted
+        let events = vec![("a", 5), ("b", 65), ("late", 58), ("c", 80), ("too_late", 30), ("d", 90)];
+        let windows = tokio_stream::iter(events)
+            .tumbling_window(Duration::from_secs(60), event_time)
+            .with_allowed_lateness(Duration::from_secs(10))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(labels(windows), vec![vec!["a", "late"], vec!["b", "c", "d"]]);
+    }
+
+    #[tokio::test]
+    async fn test_without_lateness_out_of_order_events_are_dropped() {
+        let events = vec![("a", 5), ("b", 65), ("late", 58)];
+        let windows = tokio_stream::iter(events)
+            .tumbling_window(Duration::from_secs(60), event_time)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(labels(windows), vec![vec!["a"], vec!["b"]]);
+    }
+}
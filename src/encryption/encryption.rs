@@ -5,6 +5,7 @@ use async_trait::async_trait;
 use base64::Engine;
 use kafka::producer::AsBytes;
 use rand::{thread_rng, RngCore};
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
 use aes_gcm::{Aes256Gcm, Key, Nonce};
 use aes_gcm::aead::Aead;
@@ -193,9 +194,12 @@ pub fn decrypt_message(content: &str, recipient: &str) -> Result<String, bigbote
     Ok(content.to_string())
 }
 
+/// Hex-encoded SHA-256 of the content.
 pub fn hash_message(content: &str) -> Result<String, bigboterror::BigbotError> {
-    // Implement message hashing logic here
-    Ok("message_hash".to_string())
+    Ok(Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
 }
 
 #[cfg(test)]
@@ -0,0 +1,182 @@
+//! # Shamir Secret Sharing
+//!
+//! Splits a secret into `n` shares so that any `threshold` of them recover it and fewer reveal
+//! nothing about it. Each byte of the secret is the constant term of its own random polynomial of
+//! degree `threshold - 1` over GF(2^8); a share holds every polynomial evaluated at the share's
+//! index. Recovery is Lagrange interpolation at zero.
+//!
+//! Shares carry their threshold so that recovery from too few shares fails loudly instead of
+//! quietly producing the wrong secret.
+
+use crate::utils::bigboterror::BigbotError;
+
+use rand::{thread_rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    // The x coordinate, never zero since the secret lives at x = 0
+    pub index: u8,
+    pub threshold: u8,
+    pub data: Vec<u8>,
+}
+
+pub fn split_secret(secret: &[u8], threshold: u8, shares: u8) -> Result<Vec<Share>, BigbotError> {
+    if threshold == 0 || threshold > shares {
+        return Err(BigbotError::InvalidInput(format!(
+            "Threshold must be between 1 and the number of shares, got {} of {}",
+            threshold, shares
+        )));
+    }
+    if secret.is_empty() {
+        return Err(BigbotError::InvalidInput("Cannot split an empty secret".into()));
+    }
+
+    let mut rng = thread_rng();
+    let mut result: Vec<Share> = (1..=shares)
+        .map(|index| Share {
+            index,
+            threshold,
+            data: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+    let mut coefficients = vec![0u8; threshold as usize];
+    for &byte in secret {
+        coefficients[0] = byte;
+        rng.fill_bytes(&mut coefficients[1..]);
+        for share in &mut result {
+            share.data.push(evaluate(&coefficients, share.index));
+        }
+    }
+    Ok(result)
+}
+
+pub fn recover_secret(shares: &[Share]) -> Result<Vec<u8>, BigbotError> {
+    let first = shares
+        .first()
+        .ok_or_else(|| BigbotError::InvalidInput("No shares to recover from".into()))?;
+    if shares.iter().any(|share| share.threshold != first.threshold || share.data.len() != first.data.len()) {
+        return Err(BigbotError::InvalidInput("Shares come from different splits".into()));
+    }
+    let mut indices = HashSet::new();
+    if shares.iter().any(|share| share.index == 0 || !indices.insert(share.index)) {
+        return Err(BigbotError::InvalidInput("Share indices must be distinct and non-zero".into()));
+    }
+    if shares.len() < first.threshold as usize {
+        return Err(BigbotError::InvalidInput(format!(
+            "Need {} shares to recover the secret, got {}",
+            first.threshold,
+            shares.len()
+        )));
+    }
+
+    // Any `threshold` shares determine the polynomials; extra ones add nothing
+    let shares = &shares[..first.threshold as usize];
+    let weights: Vec<u8> = shares
+        .iter()
+        .map(|share| {
+            shares
+                .iter()
+                .filter(|other| other.index != share.index)
+                .fold(1, |weight, other| {
+                    // Lagrange basis at zero: x_j / (x_j - x_i); subtraction is XOR in GF(2^8)
+                    gf_mul(weight, gf_mul(other.index, gf_inv(other.index ^ share.index)))
+                })
+        })
+        .collect();
+
+    Ok((0..first.data.len())
+        .map(|position| {
+            shares
+                .iter()
+                .zip(&weights)
+                .fold(0, |secret, (share, &weight)| secret ^ gf_mul(share.data[position], weight))
+        })
+        .collect())
+}
+
+// Horner's rule, highest coefficient first
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients.iter().rev().fold(0, |acc, &coefficient| gf_mul(acc, x) ^ coefficient)
+}
+
+// Multiplication modulo the AES polynomial x^8 + x^4 + x^3 + x + 1
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+// a^254 = a^-1 for non-zero a
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    for _ in 0..254 {
+        result = gf_mul(result, a);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_any_threshold_subset_recovers() {
+        let secret = b"correct horse battery staple".to_vec();
+        let shares = split_secret(&secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let subset = vec![shares[c].clone(), shares[a].clone(), shares[b].clone()];
+                    assert_eq!(recover_secret(&subset).unwrap(), secret);
+                }
+            }
+        }
+        assert_eq!(recover_secret(&shares).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_too_few_shares_do_not_recover() {
+        let secret = vec![7u8; 32];
+        let shares = split_secret(&secret, 3, 5).unwrap();
+        assert!(recover_secret(&shares[..2]).is_err());
+
+        // Even ignoring the recorded threshold, two points don't pin down a degree-2 polynomial
+        let relabeled: Vec<Share> = shares[..2]
+            .iter()
+            .map(|share| Share { threshold: 2, ..share.clone() })
+            .collect();
+        assert_ne!(recover_secret(&relabeled).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_rejects_invalid_parameters_and_shares() {
+        assert!(split_secret(b"secret", 0, 3).is_err());
+        assert!(split_secret(b"secret", 4, 3).is_err());
+
+        let shares = split_secret(b"secret", 2, 3).unwrap();
+        assert!(recover_secret(&[shares[0].clone(), shares[0].clone()]).is_err());
+        let other = split_secret(b"another secret", 2, 3).unwrap();
+        assert!(recover_secret(&[shares[0].clone(), other[1].clone()]).is_err());
+    }
+
+    #[test]
+    fn test_field_inverse() {
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::flows::blocks::FlowBlock;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const BLOCK_LIBRARY: &str = include_str!("block_library.json");
+
+// Blocks kept for reuse across generated flows, keyed by a hash of their id
+#[derive(Default)]
+pub struct BlockLibrary {
+    blocks: HashMap<u64, FlowBlock>,
+}
+
+impl BlockLibrary {
+    pub fn new() -> Self {
+        BlockLibrary::default()
+    }
+
+    pub fn get_block(&self, block_hash: u64) -> Result<&FlowBlock, String> {
+        self.blocks.get(&block_hash).ok_or_else(|| format!("Block not found in library: {}", block_hash))
+    }
+
+    pub fn add_block(&mut self, block_hash: u64, block: FlowBlock) -> Result<(), String> {
+        self.blocks.insert(block_hash, block);
+        Ok(())
+    }
+}
+
+// Templates for block types the engine doesn't define, such as `OAuthProvider`, read from
+// `block_library.json`. When a type has several templates the first one is used.
+pub struct BlockTemplates {
+    templates: HashMap<String, Value>,
+}
+
+impl BlockTemplates {
+    pub fn new() -> Self {
+        let library: Value = serde_json::from_str(BLOCK_LIBRARY).expect("block_library.json is not valid JSON");
+        let mut templates = HashMap::new();
+        for template in library["blocks"].as_array().into_iter().flatten() {
+            if let Some(block_type) = template["type"].as_str() {
+                templates.entry(block_type.to_string()).or_insert_with(|| template.clone());
+            }
+        }
+        BlockTemplates { templates }
+    }
+
+    pub fn get_template(&self, block_type: &str) -> Result<&Value, String> {
+        self.templates.get(block_type).ok_or_else(|| format!("No template for block type: {}", block_type))
+    }
+}
+
+impl Default for BlockTemplates {
+    fn default() -> Self {
+        BlockTemplates::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templates_are_keyed_by_block_type() {
+        let templates = BlockTemplates::new();
+        let oauth = templates.get_template("OAuthProvider").unwrap();
+        assert_eq!(oauth["id"], "oauth_provider");
+        assert!(oauth["methods"].get("fetch_token").is_some());
+
+        // Two templates share the EventBlock type; the first one wins
+        assert_eq!(templates.get_template("EventBlock").unwrap()["id"], "calendar_event");
+        assert!(templates.get_template("TeleportBlock").is_err());
+    }
+}
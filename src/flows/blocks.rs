@@ -1,16 +1,29 @@
+use async_trait::async_trait;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use crate::flows::{FlowEngine, BlockResult, Binder};
-
+use std::time::Duration;
+use crate::data_exchange::data_bridging::JsonSchema;
+use crate::flows::flows::{FlowEngine, BlockResult, Binder};
+
+// Used by `ExternalDataBlock` when its properties do not set `timeout_ms`, `retries` or
+// `retry_delay_ms`
+pub const DEFAULT_EXTERNAL_DATA_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_EXTERNAL_DATA_RETRIES: u64 = 0;
+pub const DEFAULT_EXTERNAL_DATA_RETRY_DELAY: Duration = Duration::from_millis(200);
+// Longest wait between two attempts; the delay doubles after each failure up to this
+const MAX_EXTERNAL_DATA_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[async_trait]
 pub trait Block {
     fn id(&self) -> &str;
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String>;
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String>;
     fn binder(&self) -> Option<&Binder>;
     fn weights(&self) -> Option<&HashMap<String, f64>>;
     fn calculate_graph_weights(&mut self, graph: &HashMap<String, Vec<String>>);
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct InputBlock {
     pub id: String,
     pub properties: HashMap<String, serde_json::Value>,
@@ -18,12 +31,13 @@ pub struct InputBlock {
     pub parameters_schema: Option<serde_json::Value>,
 }
 
+#[async_trait]
 impl Block for InputBlock {
     fn id(&self) -> &str {
         &self.id
     }
 
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
         self.on_process(state, None)
     }
 
@@ -42,21 +56,35 @@ impl Block for InputBlock {
 
 impl InputBlock {
     pub fn on_process(&self, state: &mut HashMap<String, serde_json::Value>, input: Option<serde_json::Value>) -> Result<BlockResult, String> {
-        if !self.get_property("required").unwrap_or(&serde_json::Value::Bool(true)).as_bool().unwrap() && input.is_none() {
+        let schema = self.schema()?;
+        if input.is_none() && !self.is_required(schema.as_ref()) {
             self.save(state, None);
             Ok(BlockResult::Move("Next".to_string()))
         } else {
-            self.process_input(state, input)
+            self.process_input(state, input, schema.as_ref())
         }
     }
 
-    fn process_input(&self, state: &mut HashMap<String, serde_json::Value>, input: Option<serde_json::Value>) -> Result<BlockResult, String> {
+    fn schema(&self) -> Result<Option<JsonSchema>, String> {
+        self.parameters_schema
+            .as_ref()
+            .map(|schema| JsonSchema::new(schema).map_err(|e| format!("Input block {}: {}", self.id, e)))
+            .transpose()
+    }
+
+    // With a parameters_schema, input is required unless the schema accepts null; the `required`
+    // property only applies to blocks without a schema
+    fn is_required(&self, schema: Option<&JsonSchema>) -> bool {
+        match schema {
+            Some(schema) => schema.validate(&serde_json::Value::Null).is_err(),
+            None => self.get_property("required").and_then(|required| required.as_bool()).unwrap_or(true),
+        }
+    }
+
+    fn process_input(&self, state: &mut HashMap<String, serde_json::Value>, input: Option<serde_json::Value>, schema: Option<&JsonSchema>) -> Result<BlockResult, String> {
         if let Some(value) = input {
-            // Validate the input against the parameters_schema if provided
-            if let Some(schema) = &self.parameters_schema {
-                if let Err(e) = serde_json::from_value::<serde_json::Value>(value.clone()) {
-                    return Err(format!("Invalid input: {}", e));
-                }
+            if let Some(schema) = schema {
+                schema.validate(&value).map_err(|e| format!("Invalid input: {}", e))?;
             }
             self.save(state, Some(value));
             Ok(BlockResult::Move("Next".to_string()))
@@ -116,7 +144,7 @@ impl InputBlock {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct DecisionBlock {
     pub id: String,
     pub properties: HashMap<String, serde_json::Value>,
@@ -125,17 +153,18 @@ pub struct DecisionBlock {
     pub graph_weights: Option<HashMap<String, f64>>,
 }
 
+#[async_trait]
 impl Block for DecisionBlock {
     fn id(&self) -> &str {
         &self.id
     }
 
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
         let options = self.get_property("connections").unwrap().as_array().unwrap();
         for option in options {
             let value = option.get("value").unwrap().as_str().unwrap();
             let condition = option.get("condition").unwrap().as_str().unwrap();
-            if condition.is_empty() || self.evaluate_condition(condition, state) {
+            if condition.is_empty() || engine.evaluate_condition(condition, state)? {
                 return Ok(BlockResult::Move(value.to_string()));
             }
         }
@@ -164,16 +193,6 @@ impl Block for DecisionBlock {
 }
 
 impl DecisionBlock {
-    fn evaluate_condition(&self, condition: &str, state: &HashMap<String, serde_json::Value>) -> bool {
-        // Evaluate the condition based on the state
-        let mut context = rhai::Map::new();
-        for (key, value) in state {
-            context.insert(key.clone(), value.clone());
-        }
-        let engine = rhai::Engine::new();
-        engine.eval_with_scope(&mut context, condition).unwrap_or(false)
-    }
-
     fn calculate_connection_weight(&self, graph: &HashMap<String, Vec<String>>, target_block_id: &str) -> f64 {
         // Calculate the connection weight based on the graph
         let mut total_connections = 0;
@@ -196,39 +215,19 @@ impl DecisionBlock {
     }
 }
 
-pub trait Block {
-    fn id(&self) -> &str;
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String>;
-    fn binder(&self) -> Option<&Binder>;
-    fn weights(&self) -> Option<&HashMap<String, f64>>;
-    fn calculate_graph_weights(&mut self, graph: &HashMap<String, Vec<String>>);
-}
-
-// InputBlock implementation (same as before)
-
-#[derive(Deserialize, Serialize)]
-pub struct DecisionBlock {
-    pub id: String,
-    pub properties: HashMap<String, serde_json::Value>,
-    pub binder: Option<Binder>,
-    pub weights: Option<HashMap<String, f64>>,
-    pub graph_weights: Option<HashMap<String, f64>>,
-}
-
-// DecisionBlock implementation (same as before)
-
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct GoToBlock {
     pub id: String,
     pub properties: HashMap<String, serde_json::Value>,
 }
 
+#[async_trait]
 impl Block for GoToBlock {
     fn id(&self) -> &str {
         &self.id
     }
 
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
         let destination_block_id = self.get_property("destination_block_id").unwrap().as_str().unwrap();
         Ok(BlockResult::Move(destination_block_id.to_string()))
     }
@@ -252,20 +251,21 @@ impl GoToBlock {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct ConditionalBlock {
     pub id: String,
     pub properties: HashMap<String, serde_json::Value>,
 }
 
+#[async_trait]
 impl Block for ConditionalBlock {
     fn id(&self) -> &str {
         &self.id
     }
 
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
         let condition = self.get_property("condition").unwrap().as_str().unwrap();
-        if self.evaluate_condition(condition, state) {
+        if engine.evaluate_condition(condition, state)? {
             let true_block_id = self.get_property("true_block_id").unwrap().as_str().unwrap();
             Ok(BlockResult::Move(true_block_id.to_string()))
         } else {
@@ -288,33 +288,24 @@ impl Block for ConditionalBlock {
 }
 
 impl ConditionalBlock {
-    fn evaluate_condition(&self, condition: &str, state: &HashMap<String, serde_json::Value>) -> bool {
-        // Evaluate the condition based on the state
-        let mut context = rhai::Map::new();
-        for (key, value) in state {
-            context.insert(key.clone(), value.clone());
-        }
-        let engine = rhai::Engine::new();
-        engine.eval_with_scope(&mut context, condition).unwrap_or(false)
-    }
-
     fn get_property(&self, key: &str) -> Option<&serde_json::Value> {
         self.properties.get(key)
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct DisplayBlock {
     pub id: String,
     pub properties: HashMap<String, serde_json::Value>,
 }
 
+#[async_trait]
 impl Block for DisplayBlock {
     fn id(&self) -> &str {
         &self.id
     }
 
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
         let message = self.get_property("message").unwrap().as_str().unwrap();
         let resolved_message = engine.resolve_variables(message, state);
         println!("{}", resolved_message);
@@ -340,18 +331,19 @@ impl DisplayBlock {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct RandomBlock {
     pub id: String,
     pub properties: HashMap<String, serde_json::Value>,
 }
 
+#[async_trait]
 impl Block for RandomBlock {
     fn id(&self) -> &str {
         &self.id
     }
 
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
         let options = self.get_property("options").unwrap().as_array().unwrap();
         let mut rng = rand::thread_rng();
         let mut total_weight = 0.0;
@@ -390,18 +382,19 @@ impl RandomBlock {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct InteractiveBlock {
     pub id: String,
     pub properties: HashMap<String, serde_json::Value>,
 }
 
+#[async_trait]
 impl Block for InteractiveBlock {
     fn id(&self) -> &str {
         &self.id
     }
 
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
         let question = self.get_property("question").unwrap().as_str().unwrap();
         let options = self.get_property("options").unwrap().as_array().unwrap();
         println!("{}", question);
@@ -436,23 +429,26 @@ impl InteractiveBlock {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct ExternalDataBlock {
     pub id: String,
     pub properties: HashMap<String, serde_json::Value>,
 }
 
+#[async_trait]
 impl Block for ExternalDataBlock {
     fn id(&self) -> &str {
         &self.id
     }
 
-    fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        let api_url = self.get_property("api_url").unwrap().as_str().unwrap();
-        let data_path = self.get_property("data_path").unwrap().as_str().unwrap();
-        let response = reqwest::blocking::get(api_url).map_err(|e| format!("API request failed: {}", e))?;
-        let json_data: serde_json::Value = response.json().map_err(|e| format!("Failed to parse JSON response: {}", e))?;
-        let data = json_data.pointer(data_path).unwrap().clone();
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+        let api_url = self.get_str_property("api_url")?;
+        let data_path = self.get_str_property("data_path")?;
+        let json_data = self.fetch(engine.http_client(), api_url).await?;
+        let data = json_data
+            .pointer(data_path)
+            .ok_or_else(|| format!("External data block {}: no value at {} in response from {}", self.id, data_path, api_url))?
+            .clone();
         state.insert("external_data".to_string(), data);
         Ok(BlockResult::Move("Next".to_string()))
     }
@@ -471,11 +467,261 @@ impl Block for ExternalDataBlock {
 }
 
 impl ExternalDataBlock {
+    // Per-attempt request timeout, from the `timeout_ms` property
+    fn timeout(&self) -> Duration {
+        self.get_property("timeout_ms")
+            .and_then(|timeout_ms| timeout_ms.as_u64())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_EXTERNAL_DATA_TIMEOUT)
+    }
+
+    // Extra attempts after a timeout, connection failure or 5xx response, from the `retries` property
+    fn retries(&self) -> u64 {
+        self.get_property("retries")
+            .and_then(|retries| retries.as_u64())
+            .unwrap_or(DEFAULT_EXTERNAL_DATA_RETRIES)
+    }
+
+    // Wait before retry number `attempt` (counting from 1): the `retry_delay_ms` property, doubled
+    // for each earlier retry
+    fn retry_delay(&self, attempt: u64) -> Duration {
+        let base = self
+            .get_property("retry_delay_ms")
+            .and_then(|retry_delay_ms| retry_delay_ms.as_u64())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_EXTERNAL_DATA_RETRY_DELAY);
+        let doublings = attempt.saturating_sub(1).min(31) as u32;
+        base.saturating_mul(1 << doublings).min(MAX_EXTERNAL_DATA_RETRY_DELAY)
+    }
+
+    async fn fetch(&self, client: &reqwest::Client, api_url: &str) -> Result<serde_json::Value, String> {
+        let timeout = self.timeout();
+        let mut attempt = 0;
+        loop {
+            let error = match client.get(api_url).timeout(timeout).send().await {
+                Ok(response) if response.status().is_success() => {
+                    return response
+                        .json()
+                        .await
+                        .map_err(|e| format!("External data block {}: failed to parse JSON response from {}: {}", self.id, api_url, e));
+                }
+                Ok(response) if response.status().is_server_error() => {
+                    format!("External data block {}: {} returned {}", self.id, api_url, response.status())
+                }
+                Ok(response) => {
+                    return Err(format!("External data block {}: {} returned {}", self.id, api_url, response.status()));
+                }
+                Err(e) if e.is_timeout() => {
+                    format!("External data block {}: request to {} timed out after {}ms", self.id, api_url, timeout.as_millis())
+                }
+                Err(e) => format!("External data block {}: request to {} failed: {}", self.id, api_url, e),
+            };
+            if attempt >= self.retries() {
+                return Err(error);
+            }
+            attempt += 1;
+            tokio::time::sleep(self.retry_delay(attempt)).await;
+        }
+    }
+
+    fn get_str_property(&self, key: &str) -> Result<&str, String> {
+        self.get_property(key)
+            .and_then(|value| value.as_str())
+            .ok_or_else(|| format!("External data block {} has no {}", self.id, key))
+    }
+
+    fn get_property(&self, key: &str) -> Option<&serde_json::Value> {
+        self.properties.get(key)
+    }
+}
+
+// How the states of parallel branches are combined at their join block
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    // Later branches (in declaration order) overwrite keys written by earlier ones
+    #[default]
+    LastWriterWins,
+    // Each key may be written by any number of branches, but only with the same value
+    PerKey,
+}
+
+// Fans out into the blocks listed in `branches`, which run concurrently until they reach
+// `join_block_id`. The engine merges their states using `merge_strategy` and continues at the join.
+#[derive(Default, Deserialize, Serialize)]
+pub struct ParallelBlock {
+    pub id: String,
+    pub properties: HashMap<String, serde_json::Value>,
+}
+
+#[async_trait]
+impl Block for ParallelBlock {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+        let branches = self
+            .get_property("branches")
+            .and_then(|branches| branches.as_array())
+            .ok_or_else(|| format!("Parallel block {} has no branches", self.id))?
+            .iter()
+            .map(|branch| {
+                branch
+                    .as_str()
+                    .map(|branch| branch.to_string())
+                    .ok_or_else(|| format!("Parallel block {} has a non-string branch", self.id))
+            })
+            .collect::<Result<Vec<String>, String>>()?;
+        Ok(BlockResult::Fork(branches))
+    }
+
+    fn binder(&self) -> Option<&Binder> {
+        None
+    }
+
+    fn weights(&self) -> Option<&HashMap<String, f64>> {
+        None
+    }
+
+    fn calculate_graph_weights(&mut self, graph: &HashMap<String, Vec<String>>) {
+        // No graph weights for ParallelBlock
+    }
+}
+
+impl ParallelBlock {
+    pub fn join_block_id(&self) -> Result<&str, String> {
+        self.get_property("join_block_id")
+            .and_then(|join_block_id| join_block_id.as_str())
+            .ok_or_else(|| format!("Parallel block {} has no join_block_id", self.id))
+    }
+
+    pub fn merge_strategy(&self) -> Result<MergeStrategy, String> {
+        match self.get_property("merge_strategy") {
+            Some(strategy) => serde_json::from_value(strategy.clone())
+                .map_err(|e| format!("Invalid merge_strategy on parallel block {}: {}", self.id, e)),
+            None => Ok(MergeStrategy::default()),
+        }
+    }
+
     fn get_property(&self, key: &str) -> Option<&serde_json::Value> {
         self.properties.get(key)
     }
 }
 
+// A block built from a template in `block_library.json`, such as an OAuth provider. Its methods are
+// implemented outside the engine, so a run that reaches one fails instead of skipping it.
+#[derive(Default, Deserialize, Serialize)]
+pub struct CustomBlock {
+    pub id: String,
+    pub block_type: String,
+    pub properties: HashMap<String, serde_json::Value>,
+    pub api_integration: Option<ApiIntegration>,
+    pub parameters_schema: Option<serde_json::Value>,
+    pub methods: HashMap<String, CustomMethod>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct CustomMethod {
+    pub args: Vec<String>,
+    pub kwargs: serde_json::Map<String, serde_json::Value>,
+}
+
+#[async_trait]
+impl Block for CustomBlock {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn process(&self, _engine: &FlowEngine, _state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+        Err(format!("Block {} is a {}, which the flow engine can't run", self.id, self.block_type))
+    }
+
+    fn binder(&self) -> Option<&Binder> {
+        None
+    }
+
+    fn weights(&self) -> Option<&HashMap<String, f64>> {
+        None
+    }
+
+    fn calculate_graph_weights(&mut self, _graph: &HashMap<String, Vec<String>>) {
+        // No graph weights for CustomBlock
+    }
+}
+
+// A block in a flow definition, tagged with its type: `{"type": "GoToBlock", "id": ..., "properties": ...}`.
+// The engine drives every block through its `Block` implementation.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum FlowBlock {
+    InputBlock(InputBlock),
+    DecisionBlock(DecisionBlock),
+    GoToBlock(GoToBlock),
+    ConditionalBlock(ConditionalBlock),
+    DisplayBlock(DisplayBlock),
+    RandomBlock(RandomBlock),
+    InteractiveBlock(InteractiveBlock),
+    ExternalDataBlock(ExternalDataBlock),
+    ParallelBlock(ParallelBlock),
+    CustomBlock(CustomBlock),
+}
+
+impl FlowBlock {
+    fn block(&self) -> &(dyn Block + Sync) {
+        match self {
+            FlowBlock::InputBlock(block) => block,
+            FlowBlock::DecisionBlock(block) => block,
+            FlowBlock::GoToBlock(block) => block,
+            FlowBlock::ConditionalBlock(block) => block,
+            FlowBlock::DisplayBlock(block) => block,
+            FlowBlock::RandomBlock(block) => block,
+            FlowBlock::InteractiveBlock(block) => block,
+            FlowBlock::ExternalDataBlock(block) => block,
+            FlowBlock::ParallelBlock(block) => block,
+            FlowBlock::CustomBlock(block) => block,
+        }
+    }
+
+    fn block_mut(&mut self) -> &mut (dyn Block + Sync) {
+        match self {
+            FlowBlock::InputBlock(block) => block,
+            FlowBlock::DecisionBlock(block) => block,
+            FlowBlock::GoToBlock(block) => block,
+            FlowBlock::ConditionalBlock(block) => block,
+            FlowBlock::DisplayBlock(block) => block,
+            FlowBlock::RandomBlock(block) => block,
+            FlowBlock::InteractiveBlock(block) => block,
+            FlowBlock::ExternalDataBlock(block) => block,
+            FlowBlock::ParallelBlock(block) => block,
+            FlowBlock::CustomBlock(block) => block,
+        }
+    }
+}
+
+#[async_trait]
+impl Block for FlowBlock {
+    fn id(&self) -> &str {
+        self.block().id()
+    }
+
+    async fn process(&self, engine: &FlowEngine, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
+        self.block().process(engine, state).await
+    }
+
+    fn binder(&self) -> Option<&Binder> {
+        self.block().binder()
+    }
+
+    fn weights(&self) -> Option<&HashMap<String, f64>> {
+        self.block().weights()
+    }
+
+    fn calculate_graph_weights(&mut self, graph: &HashMap<String, Vec<String>>) {
+        self.block_mut().calculate_graph_weights(graph)
+    }
+}
+
 // ApiIntegration, RequestFormat, ResponseFormat, ResponseStatus, and Authentication structs (same as before)
 
 #[derive(Deserialize, Serialize)]
@@ -511,3 +757,152 @@ pub struct Authentication {
     pub auth_type: String,
     pub token: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn assert_block<T: Block>() {}
+
+    fn external_data_block(properties: serde_json::Value) -> ExternalDataBlock {
+        ExternalDataBlock {
+            id: "fetch".to_string(),
+            properties: serde_json::from_value(properties).unwrap(),
+        }
+    }
+
+    fn engine() -> FlowEngine {
+        FlowEngine::new(HashMap::new(), HashMap::new())
+    }
+
+    fn input_block(parameters_schema: Option<serde_json::Value>) -> InputBlock {
+        InputBlock {
+            id: "name".to_string(),
+            properties: serde_json::from_value(json!({ "key": "name", "required": false })).unwrap(),
+            api_integration: None,
+            parameters_schema,
+        }
+    }
+
+    fn name_schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": { "first": { "type": "string" } },
+            "required": ["first"],
+        })
+    }
+
+    #[test]
+    fn test_every_block_type_implements_block() {
+        assert_block::<InputBlock>();
+        assert_block::<DecisionBlock>();
+        assert_block::<GoToBlock>();
+        assert_block::<ConditionalBlock>();
+        assert_block::<DisplayBlock>();
+        assert_block::<RandomBlock>();
+        assert_block::<InteractiveBlock>();
+        assert_block::<ExternalDataBlock>();
+        assert_block::<ParallelBlock>();
+    }
+
+    #[tokio::test]
+    async fn test_external_data_block_extracts_pointer() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/weather"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "current": { "temp_c": 18 } })))
+            .mount(&server)
+            .await;
+
+        let block = external_data_block(json!({
+            "api_url": format!("{}/weather", server.uri()),
+            "data_path": "/current/temp_c",
+        }));
+        let mut state = HashMap::new();
+        let result = block.process(&engine(), &mut state).await.unwrap();
+        assert_eq!(result, BlockResult::Move("Next".to_string()));
+        assert_eq!(state.get("external_data"), Some(&json!(18)));
+
+        let block = external_data_block(json!({
+            "api_url": format!("{}/weather", server.uri()),
+            "data_path": "/current/wind_kph",
+        }));
+        let err = block.process(&engine(), &mut state).await.unwrap_err();
+        assert!(err.contains("no value at /current/wind_kph"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_external_data_block_times_out_after_retries() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let block = external_data_block(json!({
+            "api_url": format!("{}/slow", server.uri()),
+            "data_path": "/value",
+            "timeout_ms": 50,
+            "retries": 1,
+            "retry_delay_ms": 10,
+        }));
+        let mut state = HashMap::new();
+        let err = block.process(&engine(), &mut state).await.unwrap_err();
+        assert!(err.contains("timed out after 50ms"), "{}", err);
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn test_external_data_block_backs_off_exponentially() {
+        let block = external_data_block(json!({ "retry_delay_ms": 100 }));
+        assert_eq!(block.retry_delay(1), Duration::from_millis(100));
+        assert_eq!(block.retry_delay(2), Duration::from_millis(200));
+        assert_eq!(block.retry_delay(4), Duration::from_millis(800));
+        assert_eq!(block.retry_delay(40), MAX_EXTERNAL_DATA_RETRY_DELAY);
+
+        let block = external_data_block(json!({}));
+        assert_eq!(block.retry_delay(1), DEFAULT_EXTERNAL_DATA_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_input_schema_requires_string_field() {
+        let block = input_block(Some(name_schema()));
+        let mut state = HashMap::new();
+
+        // The schema does not accept null, so it overrides `required: false`
+        assert_eq!(block.on_process(&mut state, None).unwrap_err(), "Input is required");
+
+        let err = block.on_process(&mut state, Some(json!({}))).unwrap_err();
+        assert!(err.contains("\"first\" is a required property"), "{}", err);
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn test_input_schema_rejects_number() {
+        let block = input_block(Some(name_schema()));
+        let mut state = HashMap::new();
+        let err = block.on_process(&mut state, Some(json!({ "first": 42 }))).unwrap_err();
+        assert!(err.starts_with("Invalid input: "), "{}", err);
+        assert!(err.contains("at '/first'"), "{}", err);
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn test_input_schema_accepts_valid_object() {
+        let block = input_block(Some(name_schema()));
+        let mut state = HashMap::new();
+        let result = block.on_process(&mut state, Some(json!({ "first": "Ada" }))).unwrap();
+        assert_eq!(result, BlockResult::Move("Next".to_string()));
+        assert_eq!(state.get("name"), Some(&json!({ "first": "Ada" })));
+
+        // Without a schema the `required` property still decides
+        let block = input_block(None);
+        block.on_process(&mut state, None).unwrap();
+        assert_eq!(state.get("name"), Some(&serde_json::Value::Null));
+    }
+}
@@ -1,34 +1,118 @@
-use crate::blocks::{Block, InputBlock, DecisionBlock, GoToBlock, ConditionalBlock, DisplayBlock, RandomBlock, InteractiveBlock, ExternalDataBlock};
-use crate::flows::{FlowDefinition, Binder};
-use crate::bindings::spacy_bindings::{SpacyModule, Doc, EntityLabel};
-use crate::providers::anthropic::AnthropicProvider;
-use crate::flows::logic::scheduling_logic::SchedulingLogic;
+use crate::flows::flows::{FlowDefinition, Binder};
+use crate::bindings::spacy_bindings::{BigbotError as SpacyError, Doc, SPACY};
+use crate::provider_types::ai::{ChatMessage, ChatProvider, CompletionOpts};
+use crate::utils::bigboterror::BigbotError;
+use crate::flows::logic::scheduling_logic::{SchedulingLogic, Task};
 use crate::flows::sample_flow::SampleFlow;
-use crate::flows::blocks::{Block, InputBlock, DecisionBlock, GoToBlock, ConditionalBlock, DisplayBlock, RandomBlock, InteractiveBlock, ExternalDataBlock};
-use crate::flows::block_library::BlockLibrary;
+use crate::flows::blocks::{ApiIntegration, Block, CustomBlock, CustomMethod, FlowBlock, InputBlock, DecisionBlock, GoToBlock, ConditionalBlock, DisplayBlock, RandomBlock, InteractiveBlock, ExternalDataBlock};
+use crate::flows::block_library::{BlockLibrary, BlockTemplates};
 
-use serde_json::Value;
+use chrono::{Duration, NaiveDate};
+use pyo3::Python;
+use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::sync::Arc;
 use rand::Rng;
 use std::hash::{Hash, Hasher};
 use std::collections::hash_map::DefaultHasher;
 
+const BLOCK_SYSTEM_PROMPT: &str = "You design blocks for a conversational flow engine. Reply with a single JSON object for the block described by the user, with a string \"type\" field naming the block type and a \"properties\" object. Do not include any text outside the JSON object.";
+const BLOCK_RETRY_PROMPT: &str = "That was not a valid block. Return valid JSON only: a single object with a string \"type\" field.";
+
+// Asks the chat provider for a block matching `description` and returns it as a JSON object
+// string. A reply that isn't a block object is retried once with a follow-up asking for JSON only.
+pub async fn generate_block_json(chat_provider: &dyn ChatProvider, description: &str) -> Result<String, BigbotError> {
+    let mut messages = vec![ChatMessage::system(BLOCK_SYSTEM_PROMPT), ChatMessage::user(description)];
+    let reply = chat_provider.complete(messages.clone(), CompletionOpts::default()).await?.text;
+    if let Ok(block) = parse_block(&reply) {
+        return Ok(block);
+    }
+
+    messages.push(ChatMessage::assistant(reply));
+    messages.push(ChatMessage::user(BLOCK_RETRY_PROMPT));
+    let reply = chat_provider.complete(messages, CompletionOpts::default()).await?.text;
+    parse_block(&reply).map_err(|e| BigbotError::UnexpectedError(format!("Chat provider returned an invalid block: {}", e)))
+}
+
+// Checks that `reply` is a JSON object with a string `type`, returning it trimmed
+fn parse_block(reply: &str) -> Result<String, String> {
+    let reply = reply.trim();
+    let block: Value = serde_json::from_str(reply).map_err(|e| e.to_string())?;
+    match block.get("type") {
+        Some(Value::String(_)) => Ok(reply.to_string()),
+        _ => Err("block has no string \"type\"".to_string()),
+    }
+}
+
+// An entity spaCy found in a user instruction, with its label as spaCy names it (e.g. "PERSON")
+pub struct InstructionEntity {
+    pub label: String,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+// Maps a sentence of a user instruction to one of the intents `Flowgorithm` generates logic for
+pub trait IntentClassifier: Send + Sync {
+    fn predict(&self, text: &str) -> Result<Option<String>, String>;
+}
+
+// Matches case-insensitive phrases; intents are tried in the order they were added
+pub struct KeywordIntentClassifier {
+    intents: Vec<(String, Vec<String>)>,
+}
+
+impl KeywordIntentClassifier {
+    // Recognises the intents handled by `Flowgorithm::generate_logic`
+    pub fn new() -> Self {
+        KeywordIntentClassifier { intents: Vec::new() }
+            .with_intent("create_task", &["create a task", "create task", "add a task", "add task", "new task"])
+            .with_intent("assign_task", &["assign", "delegate", "give the task to"])
+            .with_intent("set_deadline", &["deadline", "due by", "due on", "due date"])
+    }
+
+    pub fn with_intent(mut self, intent: &str, phrases: &[&str]) -> Self {
+        let phrases = phrases.iter().map(|phrase| phrase.to_lowercase()).collect();
+        self.intents.push((intent.to_string(), phrases));
+        self
+    }
+}
+
+impl Default for KeywordIntentClassifier {
+    fn default() -> Self {
+        KeywordIntentClassifier::new()
+    }
+}
+
+impl IntentClassifier for KeywordIntentClassifier {
+    fn predict(&self, text: &str) -> Result<Option<String>, String> {
+        let text = text.to_lowercase();
+        let intent = self
+            .intents
+            .iter()
+            .find(|(_, phrases)| phrases.iter().any(|phrase| text.contains(phrase.as_str())))
+            .map(|(intent, _)| intent.clone());
+        Ok(intent)
+    }
+}
+
 pub struct Flowgorithm {
     block_library: BlockLibrary,
-    anthropic_provider: AnthropicProvider,
+    chat_provider: Arc<dyn ChatProvider>,
     block_templates: BlockTemplates,
+    intent_classifier: Arc<dyn IntentClassifier>,
 }
 
 impl Flowgorithm {
-    pub fn new() -> Self {
+    pub fn new(intent_classifier: Arc<dyn IntentClassifier>, chat_provider: Arc<dyn ChatProvider>) -> Self {
         let block_library = BlockLibrary::new();
-        let anthropic_provider = AnthropicProvider::new();
         let block_templates = BlockTemplates::new();
 
         Flowgorithm {
             block_library,
-            anthropic_provider,
+            chat_provider,
             block_templates,
+            intent_classifier,
         }
     }
 
@@ -38,13 +122,13 @@ impl Flowgorithm {
 
         // Extract entities and intents from the NLU result
         let entities = self.extract_entities(&doc)?;
-        let intents = self.extract_intents(&doc)?;
+        let intents = self.extract_intents(instruction)?;
 
         // Generate logic based on the extracted entities and intents
         let logic = self.generate_logic(&entities, &intents)?;
 
         // Generate a flow based on the generated logic
-        let flow = self.generate_flow(&logic)?;
+        let flow = self.generate_flow_from_logic(&logic)?;
 
         // Save the generated flow
         self.save_flow(&flow)?;
@@ -53,32 +137,30 @@ impl Flowgorithm {
     }
 
     async fn perform_nlu(&self, text: &str) -> Result<Doc, String> {
-        let spacy = SpacyModule::new();
-        let doc = spacy.process(text).await?;
-        Ok(doc)
-    }
-
-    fn extract_entities(&self, doc: &Doc) -> Result<Vec<EntityLabel>, String> {
-        let mut entities = Vec::new();
-        for ent in doc.ents() {
-            let label = ent.label().to_string();
-            let start = ent.start();
-            let end = ent.end();
-            let text = ent.text().to_string();
-            let entity = EntityLabel {
-                label,
-                start,
-                end,
-                text,
-            };
-            entities.push(entity);
-        }
-        Ok(entities)
+        SPACY.model_default().nlp(text.to_string()).await.map_err(|e| e.to_string())
+    }
+
+    fn extract_entities(&self, doc: &Doc) -> Result<Vec<InstructionEntity>, String> {
+        Python::with_gil(|py| -> Result<Vec<InstructionEntity>, SpacyError> {
+            let mut entities = Vec::new();
+            for ent in doc.ents(py)? {
+                let entity = InstructionEntity {
+                    label: ent.label(py)?,
+                    start: ent.start_char(py)?,
+                    end: ent.end_char(py)?,
+                    text: ent.text(py)?,
+                };
+                entities.push(entity);
+            }
+            Ok(entities)
+        })
+        .map_err(|e| e.to_string())
     }
     
-    fn extract_intents(&self, doc: &Doc) -> Result<Vec<String>, String> {
+    // Classifies each sentence of the instruction separately, so one instruction can carry several intents
+    fn extract_intents(&self, instruction: &str) -> Result<Vec<String>, String> {
         let mut intents = Vec::new();
-        for sent in doc.sents() {
+        for sent in instruction.split_terminator(['.', '!', '?', '\n']) {
             let intent = self.classify_intent(sent)?;
             if let Some(intent) = intent {
                 intents.push(intent);
@@ -87,18 +169,11 @@ impl Flowgorithm {
         Ok(intents)
     }
     
-    fn classify_intent(&self, sent: Sent) -> Result<Option<String>, String> {
-        let intent_classifier = self.load_intent_classifier()?;
-        let intent = intent_classifier.predict(sent.text())?;
-        Ok(intent)
+    fn classify_intent(&self, sent: &str) -> Result<Option<String>, String> {
+        self.intent_classifier.predict(sent)
     }
     
-    fn load_intent_classifier(&self) -> Result<IntentClassifier, String> {
-        // Load the intent classifier model
-        // ...
-    }
-    
-    fn generate_logic(&self, entities: &[EntityLabel], intents: &[String]) -> Result<SchedulingLogic, String> {
+    fn generate_logic(&self, entities: &[InstructionEntity], intents: &[String]) -> Result<SchedulingLogic, String> {
         let mut logic = SchedulingLogic::default();
         
         // Analyze entities and intents to generate logic
@@ -131,7 +206,7 @@ impl Flowgorithm {
         Ok(logic)
     }
     
-    fn extract_task_name(&self, entities: &[EntityLabel]) -> Result<String, String> {
+    fn extract_task_name(&self, entities: &[InstructionEntity]) -> Result<String, String> {
         for entity in entities {
             if entity.label == "TASK" {
                 return Ok(entity.text.clone());
@@ -140,7 +215,7 @@ impl Flowgorithm {
         Err("Task name not found in entities".to_string())
     }
     
-    fn extract_task_duration(&self, entities: &[EntityLabel]) -> Result<Duration, String> {
+    fn extract_task_duration(&self, entities: &[InstructionEntity]) -> Result<Duration, String> {
         for entity in entities {
             if entity.label == "DURATION" {
                 let duration_str = entity.text.clone();
@@ -167,7 +242,7 @@ impl Flowgorithm {
         }
     }
     
-    fn extract_assignee(&self, entities: &[EntityLabel]) -> Result<String, String> {
+    fn extract_assignee(&self, entities: &[InstructionEntity]) -> Result<String, String> {
         for entity in entities {
             if entity.label == "PERSON" {
                 return Ok(entity.text.clone());
@@ -176,7 +251,7 @@ impl Flowgorithm {
         Err("Assignee not found in entities".to_string())
     }
     
-    fn extract_deadline(&self, entities: &[EntityLabel]) -> Result<DateTime, String> {
+    fn extract_deadline(&self, entities: &[InstructionEntity]) -> Result<NaiveDate, String> {
         for entity in entities {
             if entity.label == "DATE" {
                 let date_str = entity.text.clone();
@@ -187,17 +262,17 @@ impl Flowgorithm {
         Err("Deadline not found in entities".to_string())
     }
     
-    fn parse_date(&self, date_str: &str) -> Result<DateTime, String> {
+    fn parse_date(&self, date_str: &str) -> Result<NaiveDate, String> {
         let formats = ["%Y-%m-%d", "%m/%d/%Y", "%d/%m/%Y", "%B %d, %Y"];
         for format in &formats {
-            if let Ok(date) = DateTime::parse_from_str(date_str, format) {
+            if let Ok(date) = NaiveDate::parse_from_str(date_str, format) {
                 return Ok(date);
             }
         }
         Err("Invalid date format".to_string())
     }
     
-    fn generate_flow(&self, logic: &SchedulingLogic) -> Result<SampleFlow, String> {
+    fn generate_flow_from_logic(&self, logic: &SchedulingLogic) -> Result<SampleFlow, String> {
         let mut flow = SampleFlow::default();
         
         // Generate flow based on the scheduling logic
@@ -208,7 +283,7 @@ impl Flowgorithm {
                 ("name".to_string(), Value::String(task.name.clone())),
                 ("duration".to_string(), Value::String(task.duration.to_string())),
             ]);
-            let block = self.create_block(&block_id, &block_type, properties)?;
+            let block = self.create_block(&block_type, &json!({ "id": block_id, "properties": properties }))?;
             flow.add_block(block);
         }
         
@@ -219,7 +294,7 @@ impl Flowgorithm {
                 ("task".to_string(), Value::String(task_name.clone())),
                 ("assignee".to_string(), Value::String(assignee.clone())),
             ]);
-            let block = self.create_block(&block_id, &block_type, properties)?;
+            let block = self.create_block(&block_type, &json!({ "id": block_id, "properties": properties }))?;
             flow.add_block(block);
         }
         
@@ -230,7 +305,7 @@ impl Flowgorithm {
                 ("task".to_string(), Value::String(task_name.clone())),
                 ("deadline".to_string(), Value::String(deadline.to_string())),
             ]);
-            let block = self.create_block(&block_id, &block_type, properties)?;
+            let block = self.create_block(&block_type, &json!({ "id": block_id, "properties": properties }))?;
             flow.add_block(block);
         }
         
@@ -238,8 +313,7 @@ impl Flowgorithm {
     }
     
     fn generate_block_id(&self) -> String {
-        // Generate a unique block ID
-        // ...
+        format!("block_{:016x}", rand::thread_rng().gen::<u64>())
     }
     
     fn save_flow(&mut self, flow: &SampleFlow) -> Result<(), String> {
@@ -249,20 +323,22 @@ impl Flowgorithm {
         Ok(())
     }
 
-    async fn generate_block(&mut self, description: &str) -> Result<Box<dyn Block>, String> {
-        // Use the AnthropicProvider to generate a block based on the description
-        let block_json = self.anthropic_provider.generate_block(description).await?;
+    async fn generate_block(&mut self, description: &str) -> Result<FlowBlock, String> {
+        // Use the chat provider to generate a block based on the description
+        let block_json = generate_block_json(self.chat_provider.as_ref(), description)
+            .await
+            .map_err(|e| e.to_string())?;
         let block = self.create_block_from_json(&block_json)?;
         Ok(block)
     }
 
-    fn pull_from_block_library(&mut self, block_id: &str) -> Result<Box<dyn Block>, String> {
-        let block_hash = self.calculate_hash(block_id);
+    fn pull_from_block_library(&self, block_id: &str) -> Result<&FlowBlock, String> {
+        let block_hash = self.calculate_hash(&block_id);
         self.block_library.get_block(block_hash)
     }
 
-    fn push_to_block_library(&mut self, block: Box<dyn Block>) -> Result<(), String> {
-        let block_hash = self.calculate_hash(&block.get_id());
+    fn push_to_block_library(&mut self, block: FlowBlock) -> Result<(), String> {
+        let block_hash = self.calculate_hash(&block.id());
         self.block_library.add_block(block_hash, block)
     }
 
@@ -272,14 +348,14 @@ impl Flowgorithm {
         hasher.finish()
     }
 
-    fn create_block_from_json(&self, block_json: &str) -> Result<Box<dyn Block>, String> {
+    fn create_block_from_json(&self, block_json: &str) -> Result<FlowBlock, String> {
         let block_data: Value = serde_json::from_str(block_json).map_err(|e| e.to_string())?;
-        let block_type = block_data["type"].as_str().unwrap();
+        let block_type = block_data["type"].as_str().ok_or("Block has no type")?;
         let block = self.create_block(block_type, &block_data)?;
         Ok(block)
     }
 
-    pub fn generate_flow(&self, flow_template: &Value) -> Result<FlowDefinition, String> {
+    pub fn generate_flow_from_template(&self, flow_template: &Value) -> Result<FlowDefinition, String> {
         let flow_name = flow_template["name"].as_str().unwrap().to_string();
         let start_block_id = flow_template["start_block_id"].as_str().unwrap().to_string();
         let blocks_data = flow_template["blocks"].as_array().unwrap();
@@ -298,9 +374,15 @@ impl Flowgorithm {
         })
     }
 
-    fn create_block(&self, block_type: &str, block_data: &Value) -> Result<Box<dyn Block>, String> {
-        let block_id = block_data["id"].as_str().unwrap().to_string();
-        let properties = block_data["properties"].as_object().unwrap().clone();
+    fn create_block(&self, block_type: &str, block_data: &Value) -> Result<FlowBlock, String> {
+        let block_id = match block_data["id"].as_str() {
+            Some(block_id) => block_id.to_string(),
+            None => self.generate_block_id(),
+        };
+        let properties: HashMap<String, Value> = block_data["properties"]
+            .as_object()
+            .map(|properties| properties.clone().into_iter().collect())
+            .unwrap_or_default();
 
         let block = match block_type {
             "InputBlock" => self.create_input_block(&block_id, properties),
@@ -320,65 +402,65 @@ impl Flowgorithm {
         Ok(block)
     }
 
-    fn create_input_block(&self, block_id: &str, properties: HashMap<String, Value>) -> Box<dyn Block> {
+    fn create_input_block(&self, block_id: &str, properties: HashMap<String, Value>) -> FlowBlock {
         let mut input_block = InputBlock::default();
         input_block.id = block_id.to_string();
         input_block.properties = properties;
-        Box::new(input_block)
+        FlowBlock::InputBlock(input_block)
     }
 
-    fn create_decision_block(&self, block_id: &str, properties: HashMap<String, Value>, block_data: &Value) -> Box<dyn Block> {
+    fn create_decision_block(&self, block_id: &str, properties: HashMap<String, Value>, block_data: &Value) -> FlowBlock {
         let mut decision_block = DecisionBlock::default();
         decision_block.id = block_id.to_string();
         decision_block.properties = properties;
         decision_block.binder = self.create_binder(block_data);
         decision_block.weights = self.create_weights(block_data);
-        Box::new(decision_block)
+        FlowBlock::DecisionBlock(decision_block)
     }
 
-    fn create_goto_block(&self, block_id: &str, properties: HashMap<String, Value>) -> Box<dyn Block> {
+    fn create_goto_block(&self, block_id: &str, properties: HashMap<String, Value>) -> FlowBlock {
         let mut goto_block = GoToBlock::default();
         goto_block.id = block_id.to_string();
         goto_block.properties = properties;
-        Box::new(goto_block)
+        FlowBlock::GoToBlock(goto_block)
     }
 
-    fn create_conditional_block(&self, block_id: &str, properties: HashMap<String, Value>) -> Box<dyn Block> {
+    fn create_conditional_block(&self, block_id: &str, properties: HashMap<String, Value>) -> FlowBlock {
         let mut conditional_block = ConditionalBlock::default();
         conditional_block.id = block_id.to_string();
         conditional_block.properties = properties;
-        Box::new(conditional_block)
+        FlowBlock::ConditionalBlock(conditional_block)
     }
 
-    fn create_display_block(&self, block_id: &str, properties: HashMap<String, Value>) -> Box<dyn Block> {
+    fn create_display_block(&self, block_id: &str, properties: HashMap<String, Value>) -> FlowBlock {
         let mut display_block = DisplayBlock::default();
         display_block.id = block_id.to_string();
         display_block.properties = properties;
-        Box::new(display_block)
+        FlowBlock::DisplayBlock(display_block)
     }
 
-    fn create_random_block(&self, block_id: &str, properties: HashMap<String, Value>) -> Box<dyn Block> {
+    fn create_random_block(&self, block_id: &str, properties: HashMap<String, Value>) -> FlowBlock {
         let mut random_block = RandomBlock::default();
         random_block.id = block_id.to_string();
         random_block.properties = properties;
-        Box::new(random_block)
+        FlowBlock::RandomBlock(random_block)
     }
 
-    fn create_interactive_block(&self, block_id: &str, properties: HashMap<String, Value>) -> Box<dyn Block> {
+    fn create_interactive_block(&self, block_id: &str, properties: HashMap<String, Value>) -> FlowBlock {
         let mut interactive_block = InteractiveBlock::default();
         interactive_block.id = block_id.to_string();
         interactive_block.properties = properties;
-        Box::new(interactive_block)
+        FlowBlock::InteractiveBlock(interactive_block)
     }
 
-    fn create_external_data_block(&self, block_id: &str, properties: HashMap<String, Value>) -> Box<dyn Block> {
+    fn create_external_data_block(&self, block_id: &str, properties: HashMap<String, Value>) -> FlowBlock {
         let mut external_data_block = ExternalDataBlock::default();
         external_data_block.id = block_id.to_string();
         external_data_block.properties = properties;
-        Box::new(external_data_block)
+        FlowBlock::ExternalDataBlock(external_data_block)
     }
 
-    fn create_custom_block(&self, block_id: &str, properties: HashMap<String, Value>, block_template: &Value) -> Box<dyn Block> {
+    fn create_custom_block(&self, block_id: &str, properties: HashMap<String, Value>, block_template: &Value) -> FlowBlock {
         let block_type = block_template["type"].as_str().unwrap();
         let mut custom_block = CustomBlock::default();
         custom_block.id = block_id.to_string();
@@ -416,11 +498,11 @@ impl Flowgorithm {
             custom_block.methods = custom_methods;
         }
     
-        Box::new(custom_block)
+        FlowBlock::CustomBlock(custom_block)
     }
 
     fn create_binder(&self, block_data: &Value) -> Option<Binder> {
-        let connections = block_data["connections"].as_object().unwrap();
+        let connections = block_data["connections"].as_object()?;
         let mut binder = Binder::new();
         for (from_block_id, to_block_id) in connections {
             binder.add_connection(from_block_id.to_string(), to_block_id.as_str().unwrap().to_string());
@@ -429,11 +511,33 @@ impl Flowgorithm {
     }
 
     fn create_weights(&self, block_data: &Value) -> Option<HashMap<String, f64>> {
-        let weights_data = block_data["weights"].as_object().unwrap();
+        let weights_data = block_data["weights"].as_object()?;
         let mut weights = HashMap::new();
         for (block_id, weight) in weights_data {
             weights.insert(block_id.to_string(), weight.as_f64().unwrap());
         }
         Some(weights)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyword_classifier_predicts_create_task() {
+        let classifier = KeywordIntentClassifier::new();
+        for utterance in ["Create a task to review the budget", "please ADD TASK: call the vendor", "New task for Monday"] {
+            assert_eq!(classifier.predict(utterance).unwrap(), Some("create_task".to_string()), "{}", utterance);
+        }
+        assert_eq!(classifier.predict("Assign the review to Sam").unwrap(), Some("assign_task".to_string()));
+        assert_eq!(classifier.predict("The deadline is Friday").unwrap(), Some("set_deadline".to_string()));
+        assert_eq!(classifier.predict("What's the weather like?").unwrap(), None);
+    }
+
+    #[test]
+    fn test_keyword_classifier_custom_intent() {
+        let classifier = KeywordIntentClassifier::new().with_intent("cancel_task", &["Cancel"]);
+        assert_eq!(classifier.predict("cancel the standup").unwrap(), Some("cancel_task".to_string()));
+    }
+}
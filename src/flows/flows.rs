@@ -12,7 +12,7 @@
 //! The main components of the `flows` module are:
 //!
 //! - `FlowDefinition`: A struct that represents the definition of a flow, including its name, start block ID, and blocks.
-//! - `Block`: A struct that represents a single block in a flow, containing its ID, type, properties, binder, and weights.
+//! - `Block`: The trait every block type implements. A flow definition holds its blocks as `FlowBlock`s, tagged by type.
 //! - `Binder`: A struct that manages the connections between blocks in a flow.
 //! - `FlowEngine`: A struct responsible for executing flows based on their definitions and a graph of block connections.
 //!
@@ -48,7 +48,21 @@
 //! The supported block types and their processing logic are as follows:
 //!
 //! - `DecisionBlock`: Evaluates the conditions specified in the block's properties and determines the next block to move to based on the matching condition.
+//!   Conditions are rhai expressions over the state. The engine compiles each one once and reuses it, and a condition
+//!   that references a variable missing from the state fails the block instead of evaluating to false.
 //! - `GoToBlock`: Moves the execution to the block specified by the `destination_block_id` property.
+//! - `ParallelBlock`: Forks the execution into several branches that run concurrently on copies of the
+//!   state. Each branch runs until it reaches the block named by `join_block_id` (or ends), and the branch
+//!   states are merged according to the block's `merge_strategy` before execution continues at the join block.
+//!
+//! ## Checkpoints
+//!
+//! When the engine has a checkpoint store (`with_checkpoint_store`), every run started with `execute_flow_run` is
+//! identified by the caller's flow run id and `{flow_name, current_block_id, state}` is written to the store before
+//! the first block and after each block. `execute_flow` gives the caller no run id to resume with, so it doesn't
+//! checkpoint.
+//! A run that fails or is interrupted can be continued with `resume_flow`, which starts again at the block that had
+//! not yet completed. The checkpoint is removed once the run finishes.
 //!
 //! ## Error Handling
 //!
@@ -64,16 +78,35 @@
 
 
 use rand::Rng;
-use crate::blocks::{Block, BlockResult, InputBlock, DecisionBlock, GoToBlock, ConditionalBlock, DisplayBlock, RandomBlock, InteractiveBlock, ExternalDataBlock};
+use crate::clients::kv::KVStore;
+use crate::flows::blocks::{Block, FlowBlock, MergeStrategy};
+use futures::future::{join_all, FutureExt, LocalBoxFuture};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
+use std::sync::{Arc, RwLock};
+
+const CHECKPOINT_KEY_PREFIX: &str = "flow_run:";
+
+// Blocks a single run may process before it is assumed to be stuck in a cycle
+pub const DEFAULT_MAX_STEPS: usize = 10_000;
+
+// What the engine should do after a block has been processed
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockResult {
+    // Continue along the named connection or to the named block
+    Move(String),
+    Reject(String),
+    Terminate,
+    // Run each of the named blocks as the start of a concurrent branch
+    Fork(Vec<String>),
+}
 
 #[derive(Deserialize, Serialize)]
 pub struct FlowDefinition {
     pub name: String,
     pub start_block_id: String,
-    pub blocks: Vec<Block>,
+    pub blocks: Vec<FlowBlock>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -82,13 +115,13 @@ pub struct Binder {
 }
 
 impl Binder {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Binder {
             connections: HashMap::new(),
         }
     }
 
-    fn add_connection(&mut self, from_block_id: String, to_block_id: String) {
+    pub(crate) fn add_connection(&mut self, from_block_id: String, to_block_id: String) {
         self.connections.insert(from_block_id, to_block_id);
     }
 
@@ -97,9 +130,75 @@ impl Binder {
     }
 }
 
+// Compiles block conditions once with a shared rhai engine and reuses the AST on later evaluations
+pub struct ConditionCache {
+    engine: rhai::Engine,
+    compiled: RwLock<HashMap<String, Arc<rhai::AST>>>,
+}
+
+impl ConditionCache {
+    pub fn new() -> Self {
+        ConditionCache {
+            engine: rhai::Engine::new(),
+            compiled: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn compile(&self, condition: &str) -> Result<Arc<rhai::AST>, String> {
+        if let Some(ast) = self.compiled.read().unwrap().get(condition) {
+            return Ok(ast.clone());
+        }
+        let ast = Arc::new(self.engine.compile_expression(condition).map_err(|e| format!("Failed to compile condition `{}`: {}", condition, e))?);
+        let mut compiled = self.compiled.write().unwrap();
+        Ok(compiled.entry(condition.to_string()).or_insert(ast).clone())
+    }
+
+    // Evaluates `condition` with each state entry bound as a variable. Referencing a variable that is
+    // not in the state is an error rather than `false`.
+    pub fn evaluate(&self, condition: &str, state: &HashMap<String, serde_json::Value>) -> Result<bool, String> {
+        let ast = self.compile(condition)?;
+        let mut scope = rhai::Scope::new();
+        for (key, value) in state {
+            let value = rhai::serde::to_dynamic(value).map_err(|e| format!("Failed to bind state key {}: {}", key, e))?;
+            scope.push_dynamic(key.clone(), value);
+        }
+        self.engine.eval_ast_with_scope::<bool>(&mut scope, &ast).map_err(|e| format!("Failed to evaluate condition `{}`: {}", condition, e))
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.compiled.read().unwrap().len()
+    }
+}
+
+impl Default for ConditionCache {
+    fn default() -> Self {
+        ConditionCache::new()
+    }
+}
+
+// Where an in-progress flow run has got to
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FlowCheckpoint {
+    pub flow_name: String,
+    pub current_block_id: String,
+    pub state: HashMap<String, serde_json::Value>,
+}
+
+// Identifies the top-level run being checkpointed; parallel branches are not checkpointed themselves
+#[derive(Clone, Copy)]
+struct FlowRun<'a> {
+    id: &'a str,
+    flow_name: &'a str,
+}
+
 pub struct FlowEngine {
     flow_definitions: HashMap<String, FlowDefinition>,
     graph: HashMap<String, Vec<String>>,
+    checkpoint_store: Option<Arc<dyn KVStore>>,
+    max_steps: usize,
+    conditions: ConditionCache,
+    http_client: reqwest::Client,
 }
 
 impl FlowEngine {
@@ -107,102 +206,194 @@ impl FlowEngine {
         FlowEngine {
             flow_definitions,
             graph,
+            checkpoint_store: None,
+            max_steps: DEFAULT_MAX_STEPS,
+            conditions: ConditionCache::new(),
+            http_client: reqwest::Client::new(),
         }
     }
 
-    pub async fn execute_flow(&self, flow_name: &str, input_data: HashMap<String, serde_json::Value>) -> Result<(), String> {
-        let flow_definition = self.flow_definitions.get(flow_name).ok_or_else(|| format!("Flow not found: {}", flow_name))?;
-        let mut state = input_data;
-        let mut current_block_id = flow_definition.start_block_id.clone();
-
-        loop {
-            let block = get_block_by_id(&flow_definition, &current_block_id)?;
-            let result = self.process_block(block, &mut state).await?;
-
-            match result {
-                BlockResult::Move(connection) => {
-                    if let Some(binder) = &block.binder() {
-                        if let Some(next_block_id) = binder.get_next_block_id(&block.id()) {
-                            current_block_id = next_block_id.clone();
-                        } else {
-                            return Ok(());
-                        }
-                    } else if let Some(weights) = &block.weights() {
-                        let mut rng = rand::thread_rng();
-                        let mut total_weight = 0.0;
-                        for weight in weights.values() {
-                            total_weight += weight;
-                        }
-                        let mut random_weight = rng.gen_range(0.0..total_weight);
-                        for (block_id, weight) in weights {
-                            random_weight -= weight;
-                            if random_weight <= 0.0 {
-                                current_block_id = block_id.clone();
-                                break;
-                            }
-                        }
-                    } else {
-                        return Ok(());
-                    }
-                }
-                BlockResult::Reject(reason) => {
-                    return Err(reason);
-                }
-                BlockResult::Terminate => {
-                    break;
-                }
-            }
-        }
+    // Caps how many blocks a run (or each parallel branch) may process, so a `GoToBlock` cycle
+    // fails instead of looping forever
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
 
-        Ok(())
+    // Evaluates a block condition against the run state, compiling it on first use
+    pub fn evaluate_condition(&self, condition: &str, state: &HashMap<String, serde_json::Value>) -> Result<bool, String> {
+        self.conditions.evaluate(condition, state)
     }
 
-    async fn process_block(&self, block: &Block, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        match block {
-            Block::InputBlock(input_block) => self.process_input_block(input_block, state).await,
-            Block::DecisionBlock(decision_block) => self.process_decision_block(decision_block, state).await,
-            Block::GoToBlock(goto_block) => self.process_goto_block(goto_block, state).await,
-            Block::ConditionalBlock(conditional_block) => self.process_conditional_block(conditional_block, state).await,
-            Block::DisplayBlock(display_block) => self.process_display_block(display_block, state).await,
-            Block::RandomBlock(random_block) => self.process_random_block(random_block, state).await,
-            Block::InteractiveBlock(interactive_block) => self.process_interactive_block(interactive_block, state).await,
-            Block::ExternalDataBlock(external_data_block) => self.process_external_data_block(external_data_block, state).await,
-        }
+    // Shared by blocks that call out over HTTP, so they reuse its connection pool
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    pub fn with_checkpoint_store(mut self, checkpoint_store: Arc<dyn KVStore>) -> Self {
+        self.checkpoint_store = Some(checkpoint_store);
+        self
+    }
+
+    // Executes a flow without checkpoints; use `execute_flow_run` for a run that can be resumed
+    pub async fn execute_flow(&self, flow_name: &str, input_data: HashMap<String, serde_json::Value>) -> Result<HashMap<String, serde_json::Value>, String> {
+        let flow_definition = self.flow_definitions.get(flow_name).ok_or_else(|| format!("Flow not found: {}", flow_name))?;
+        let mut state = input_data;
+        self.run_from(flow_definition, flow_definition.start_block_id.clone(), None, None, &mut state).await?;
+        Ok(state)
     }
 
-    async fn process_input_block(&self, input_block: &InputBlock, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        input_block.on_process(state, None).await
+    // Executes a flow under a caller-chosen run id, which `resume_flow` accepts if the run is interrupted
+    pub async fn execute_flow_run(&self, flow_run_id: &str, flow_name: &str, input_data: HashMap<String, serde_json::Value>) -> Result<HashMap<String, serde_json::Value>, String> {
+        let flow_definition = self.flow_definitions.get(flow_name).ok_or_else(|| format!("Flow not found: {}", flow_name))?;
+        let run = FlowRun { id: flow_run_id, flow_name };
+        let mut state = input_data;
+        self.save_checkpoint(run, &flow_definition.start_block_id, &state).await?;
+        self.run_from(flow_definition, flow_definition.start_block_id.clone(), None, Some(run), &mut state).await?;
+        self.clear_checkpoint(run).await?;
+        Ok(state)
     }
 
-    async fn process_decision_block(&self, decision_block: &DecisionBlock, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        decision_block.process(self, state).await
+    // Continues a run from its last checkpoint
+    pub async fn resume_flow(&self, flow_run_id: &str) -> Result<HashMap<String, serde_json::Value>, String> {
+        self.resume_flow_with_input(flow_run_id, HashMap::new()).await
     }
 
-    async fn process_goto_block(&self, goto_block: &GoToBlock, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        goto_block.process(self, state).await
+    // Continues a run from its last checkpoint after merging `input_data` into its state, e.g. the
+    // answer an interactive block was waiting for
+    pub async fn resume_flow_with_input(&self, flow_run_id: &str, input_data: HashMap<String, serde_json::Value>) -> Result<HashMap<String, serde_json::Value>, String> {
+        let checkpoint = self
+            .load_checkpoint(flow_run_id)
+            .await?
+            .ok_or_else(|| format!("No checkpoint for flow run: {}", flow_run_id))?;
+        let flow_definition = self
+            .flow_definitions
+            .get(&checkpoint.flow_name)
+            .ok_or_else(|| format!("Flow not found: {}", checkpoint.flow_name))?;
+        let run = FlowRun { id: flow_run_id, flow_name: &checkpoint.flow_name };
+        let mut state = checkpoint.state.clone();
+        state.extend(input_data);
+        self.run_from(flow_definition, checkpoint.current_block_id.clone(), None, Some(run), &mut state).await?;
+        self.clear_checkpoint(run).await?;
+        Ok(state)
     }
 
-    async fn process_conditional_block(&self, conditional_block: &ConditionalBlock, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        conditional_block.process(self, state).await
+    pub async fn load_checkpoint(&self, flow_run_id: &str) -> Result<Option<FlowCheckpoint>, String> {
+        let Some(store) = &self.checkpoint_store else {
+            return Err("Flow engine has no checkpoint store".to_string());
+        };
+        match store.get(&checkpoint_key(flow_run_id)).await.map_err(|e| e.to_string())? {
+            Some(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(|e| format!("Corrupt checkpoint for {}: {}", flow_run_id, e)),
+            None => Ok(None),
+        }
     }
 
-    async fn process_display_block(&self, display_block: &DisplayBlock, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        display_block.process(self, state).await
+    async fn save_checkpoint(&self, run: FlowRun<'_>, current_block_id: &str, state: &HashMap<String, serde_json::Value>) -> Result<(), String> {
+        let Some(store) = &self.checkpoint_store else {
+            return Ok(());
+        };
+        let checkpoint = FlowCheckpoint {
+            flow_name: run.flow_name.to_string(),
+            current_block_id: current_block_id.to_string(),
+            state: state.clone(),
+        };
+        let bytes = serde_json::to_vec(&checkpoint).map_err(|e| e.to_string())?;
+        store.set(checkpoint_key(run.id), bytes).await.map_err(|e| e.to_string())
     }
 
-    async fn process_random_block(&self, random_block: &RandomBlock, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        random_block.process(self, state).await
+    async fn clear_checkpoint(&self, run: FlowRun<'_>) -> Result<(), String> {
+        match &self.checkpoint_store {
+            Some(store) => store.delete(&checkpoint_key(run.id)).await.map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
     }
 
-    async fn process_interactive_block(&self, interactive_block: &InteractiveBlock, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        interactive_block.process(self, state).await
+    // Runs blocks starting at `start_block_id` until the flow ends or `stop_at` is reached. The block
+    // named by `stop_at` is not processed; it is the join point of an enclosing fork. With a `run`,
+    // a checkpoint naming the next block is saved after each block. Fails once `max_steps` blocks
+    // have been processed.
+    fn run_from<'a>(
+        &'a self,
+        flow_definition: &'a FlowDefinition,
+        start_block_id: String,
+        stop_at: Option<&'a str>,
+        run: Option<FlowRun<'a>>,
+        state: &'a mut HashMap<String, serde_json::Value>,
+    ) -> LocalBoxFuture<'a, Result<(), String>> {
+        async move {
+            let mut current_block_id = start_block_id;
+            let mut steps = 0;
+
+            loop {
+                if stop_at == Some(current_block_id.as_str()) {
+                    return Ok(());
+                }
+                if steps == self.max_steps {
+                    return Err(format!(
+                        "Flow exceeded max steps ({}), possible cycle at block {}",
+                        self.max_steps, current_block_id
+                    ));
+                }
+                steps += 1;
+
+                let block = get_block_by_id(flow_definition, &current_block_id)?;
+                let result = block.process(self, state).await?;
+
+                match result {
+                    BlockResult::Move(connection) => {
+                        match next_block_id(flow_definition, block, &connection) {
+                            Some(next_block_id) => current_block_id = next_block_id,
+                            None => return Ok(()),
+                        }
+                    }
+                    BlockResult::Fork(branches) => {
+                        let parallel_block = match block {
+                            FlowBlock::ParallelBlock(parallel_block) => parallel_block,
+                            _ => return Err(format!("Block {} forked but is not a parallel block", current_block_id)),
+                        };
+                        let join_block_id = parallel_block.join_block_id()?;
+                        *state = self
+                            .run_branches(flow_definition, branches, join_block_id, parallel_block.merge_strategy()?, state)
+                            .await?;
+                        current_block_id = join_block_id.to_string();
+                    }
+                    BlockResult::Reject(reason) => {
+                        return Err(reason);
+                    }
+                    BlockResult::Terminate => {
+                        return Ok(());
+                    }
+                }
+
+                if let Some(run) = run {
+                    self.save_checkpoint(run, &current_block_id, state).await?;
+                }
+            }
+        }
+        .boxed_local()
     }
 
-    async fn process_external_data_block(&self, external_data_block: &ExternalDataBlock, state: &mut HashMap<String, serde_json::Value>) -> Result<BlockResult, String> {
-        external_data_block.process(self, state).await
+    // Runs each branch concurrently on its own copy of the state and merges the results
+    async fn run_branches(
+        &self,
+        flow_definition: &FlowDefinition,
+        branches: Vec<String>,
+        join_block_id: &str,
+        merge_strategy: MergeStrategy,
+        state: &HashMap<String, serde_json::Value>,
+    ) -> Result<HashMap<String, serde_json::Value>, String> {
+        let mut branch_states = vec![state.clone(); branches.len()];
+        let runs = branches
+            .into_iter()
+            .zip(branch_states.iter_mut())
+            .map(|(branch, branch_state)| self.run_from(flow_definition, branch, Some(join_block_id), None, branch_state));
+        for result in join_all(runs).await {
+            result?;
+        }
+        merge_branch_states(state, branch_states, merge_strategy)
     }
 
-    fn resolve_variables(&self, template: &str, state: &HashMap<String, serde_json::Value>) -> String {
+    pub(crate) fn resolve_variables(&self, template: &str, state: &HashMap<String, serde_json::Value>) -> String {
         let mut resolved = template.to_string();
         for (key, value) in state {
             let placeholder = format!("{{{{{}}}}}", key);
@@ -211,28 +402,87 @@ impl FlowEngine {
         resolved
     }
 
-    fn calculate_block_weights(&mut self, flow_definition: &mut FlowDefinition) {
-        for block in &mut flow_definition.blocks {
-            self.calculate_graph_weights(block);
+    // Precomputes each block's weights from the graph of block connections
+    fn calculate_block_weights(&mut self) {
+        for flow_definition in self.flow_definitions.values_mut() {
+            for block in &mut flow_definition.blocks {
+                block.calculate_graph_weights(&self.graph);
+            }
         }
     }
+}
+
+fn checkpoint_key(flow_run_id: &str) -> Vec<u8> {
+    format!("{}{}", CHECKPOINT_KEY_PREFIX, flow_run_id).into_bytes()
+}
+
+fn get_block_by_id<'a>(flow_definition: &'a FlowDefinition, block_id: &str) -> Result<&'a FlowBlock, String> {
+    flow_definition.blocks.iter().find(|block| block.id() == block_id).ok_or_else(|| format!("Block not found: {}", block_id))
+}
 
-    fn calculate_graph_weights(&mut self, block: &mut Block) {
-        match block {
-            Block::InputBlock(input_block) => input_block.calculate_graph_weights(&self.graph),
-            Block::DecisionBlock(decision_block) => decision_block.calculate_graph_weights(&self.graph),
-            Block::GoToBlock(goto_block) => goto_block.calculate_graph_weights(&self.graph),
-            Block::ConditionalBlock(conditional_block) => conditional_block.calculate_graph_weights(&self.graph),
-            Block::DisplayBlock(display_block) => display_block.calculate_graph_weights(&self.graph),
-            Block::RandomBlock(random_block) => random_block.calculate_graph_weights(&self.graph),
-            Block::InteractiveBlock(interactive_block) => interactive_block.calculate_graph_weights(&self.graph),
-            Block::ExternalDataBlock(external_data_block) => external_data_block.calculate_graph_weights(&self.graph),
+// Resolves where a `Move` goes: the block's binder first, then its weights, then the connection itself
+// when it names a block in the flow. `None` means the flow is finished.
+fn next_block_id(flow_definition: &FlowDefinition, block: &FlowBlock, connection: &str) -> Option<String> {
+    if let Some(binder) = &block.binder() {
+        return binder.get_next_block_id(&block.id()).cloned();
+    }
+    if let Some(weights) = &block.weights() {
+        let mut rng = rand::thread_rng();
+        let total_weight: f64 = weights.values().sum();
+        let mut random_weight = rng.gen_range(0.0..total_weight);
+        for (block_id, weight) in weights.iter() {
+            random_weight -= weight;
+            if random_weight <= 0.0 {
+                return Some(block_id.clone());
+            }
         }
+        return None;
     }
+    if get_block_by_id(flow_definition, connection).is_ok() {
+        return Some(connection.to_string());
+    }
+    None
 }
 
-fn get_block_by_id(flow_definition: &FlowDefinition, block_id: &str) -> Result<&Block, String> {
-    flow_definition.blocks.iter().find(|block| block.id() == block_id).ok_or_else(|| format!("Block not found: {}", block_id))
+// Merges the states of parallel branches back into one. Only keys a branch actually changed relative
+// to `base` are taken from it, so a branch that leaves a key alone never clobbers another branch's write.
+// A key in `base` that a branch removed is carried through as a tombstone and removed from the merge.
+fn merge_branch_states(
+    base: &HashMap<String, serde_json::Value>,
+    branch_states: Vec<HashMap<String, serde_json::Value>>,
+    merge_strategy: MergeStrategy,
+) -> Result<HashMap<String, serde_json::Value>, String> {
+    let mut merged = base.clone();
+    // The last change to each key; None is a deletion
+    let mut written: HashMap<String, Option<serde_json::Value>> = HashMap::new();
+
+    for branch_state in branch_states {
+        let deleted: Vec<(String, Option<serde_json::Value>)> = base
+            .keys()
+            .filter(|key| !branch_state.contains_key(*key))
+            .map(|key| (key.clone(), None))
+            .collect();
+        let changed = branch_state
+            .into_iter()
+            .filter(|(key, value)| base.get(key) != Some(value))
+            .map(|(key, value)| (key, Some(value)));
+        for (key, change) in deleted.into_iter().chain(changed) {
+            if merge_strategy == MergeStrategy::PerKey {
+                if let Some(previous) = written.get(&key) {
+                    if previous != &change {
+                        return Err(format!("Parallel branches wrote conflicting values for '{}'", key));
+                    }
+                }
+            }
+            match &change {
+                Some(value) => merged.insert(key.clone(), value.clone()),
+                None => merged.remove(&key),
+            };
+            written.insert(key, change);
+        }
+    }
+
+    Ok(merged)
 }
 
 fn load_flow_definitions(file_path: &str) -> Result<HashMap<String, FlowDefinition>, String> {
@@ -245,16 +495,13 @@ fn main() {
     let flow_definitions = load_flow_definitions("flow_definitions.json").unwrap();
     let graph = load_graph("graph.json").unwrap();
     let mut engine = FlowEngine::new(flow_definitions, graph);
-
-    for flow_definition in engine.flow_definitions.values_mut() {
-        engine.calculate_block_weights(flow_definition);
-    }
+    engine.calculate_block_weights();
 
     let input_data = HashMap::new();
     let result = tokio::runtime::Runtime::new().unwrap().block_on(engine.execute_flow("example_flow", input_data));
 
     match result {
-        Ok(()) => println!("Flow executed successfully"),
+        Ok(_) => println!("Flow executed successfully"),
         Err(e) => println!("Flow execution failed: {}", e),
     }
 }
@@ -263,4 +510,218 @@ fn load_graph(file_path: &str) -> Result<HashMap<String, Vec<String>>, String> {
     let file_contents = fs::read_to_string(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
     let graph: HashMap<String, Vec<String>> = serde_json::from_str(&file_contents).map_err(|e| format!("Failed to parse JSON: {}", e))?;
     Ok(graph)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::kv::MemoryKVStore;
+    use crate::flows::blocks::{DecisionBlock, DisplayBlock, GoToBlock, InputBlock, ParallelBlock};
+    use serde_json::json;
+
+    fn properties(value: serde_json::Value) -> HashMap<String, serde_json::Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn optional_input(id: &str, key: &str) -> FlowBlock {
+        FlowBlock::InputBlock(InputBlock {
+            id: id.to_string(),
+            properties: properties(json!({ "key": key, "required": false })),
+            api_integration: None,
+            parameters_schema: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_flow_definition_blocks_are_tagged_by_type() {
+        let flow: FlowDefinition = serde_json::from_value(json!({
+            "name": "greeting",
+            "start_block_id": "greet",
+            "blocks": [
+                { "type": "GoToBlock", "id": "greet", "properties": { "destination_block_id": "done" } },
+                { "type": "InputBlock", "id": "done", "properties": { "key": "reply", "required": false } },
+            ],
+        }))
+        .unwrap();
+        assert!(matches!(flow.blocks[0], FlowBlock::GoToBlock(_)));
+        assert_eq!(flow.blocks[1].id(), "done");
+        assert_eq!(serde_json::to_value(&flow.blocks[0]).unwrap()["type"], json!("GoToBlock"));
+
+        let engine = FlowEngine::new(HashMap::from([("greeting".to_string(), flow)]), HashMap::new());
+        let state = engine.execute_flow("greeting", HashMap::new()).await.unwrap();
+        assert_eq!(state["reply"], serde_json::Value::Null);
+
+        let unknown = json!({ "type": "TeleportBlock", "id": "beam", "properties": {} });
+        assert!(serde_json::from_value::<FlowBlock>(unknown).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parallel_branches_merge_at_join() {
+        let flow = FlowDefinition {
+            name: "parallel_flow".to_string(),
+            start_block_id: "fork".to_string(),
+            blocks: vec![
+                FlowBlock::ParallelBlock(ParallelBlock {
+                    id: "fork".to_string(),
+                    properties: properties(json!({
+                        "branches": ["weather", "traffic"],
+                        "join_block_id": "join",
+                        "merge_strategy": "per_key",
+                    })),
+                }),
+                optional_input("weather", "weather_report"),
+                optional_input("traffic", "traffic_report"),
+                FlowBlock::DisplayBlock(DisplayBlock {
+                    id: "join".to_string(),
+                    properties: properties(json!({ "message": "Reports for {{city}} are in" })),
+                }),
+            ],
+        };
+        let engine = FlowEngine::new(HashMap::from([("parallel_flow".to_string(), flow)]), HashMap::new());
+
+        let input = HashMap::from([("city".to_string(), json!("Lisbon"))]);
+        let state = engine.execute_flow("parallel_flow", input).await.unwrap();
+
+        assert_eq!(state.len(), 3);
+        assert_eq!(state["city"], json!("Lisbon"));
+        assert!(state.contains_key("weather_report"));
+        assert!(state.contains_key("traffic_report"));
+    }
+
+    fn confirmation_flow() -> HashMap<String, FlowDefinition> {
+        let flow = FlowDefinition {
+            name: "confirmation_flow".to_string(),
+            start_block_id: "greet".to_string(),
+            blocks: vec![
+                FlowBlock::GoToBlock(GoToBlock {
+                    id: "greet".to_string(),
+                    properties: properties(json!({ "destination_block_id": "confirm" })),
+                }),
+                FlowBlock::DecisionBlock(DecisionBlock {
+                    id: "confirm".to_string(),
+                    properties: properties(json!({
+                        "connections": [{ "value": "done", "condition": "confirmed == true" }],
+                    })),
+                    binder: None,
+                    weights: None,
+                    graph_weights: None,
+                }),
+                FlowBlock::DisplayBlock(DisplayBlock {
+                    id: "done".to_string(),
+                    properties: properties(json!({ "message": "Thanks {{name}}" })),
+                }),
+            ],
+        };
+        HashMap::from([("confirmation_flow".to_string(), flow)])
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoint_after_restart() {
+        let store: Arc<dyn KVStore> = Arc::new(MemoryKVStore::default());
+        let input = HashMap::from([("name".to_string(), json!("Ada"))]);
+
+        // The decision can't be made until the user confirms, so the first run stops there
+        let engine = FlowEngine::new(confirmation_flow(), HashMap::new()).with_checkpoint_store(store.clone());
+        assert!(engine.execute_flow_run("run-1", "confirmation_flow", input).await.is_err());
+        let checkpoint = engine.load_checkpoint("run-1").await.unwrap().unwrap();
+        assert_eq!(checkpoint.current_block_id, "confirm");
+        assert_eq!(checkpoint.state["name"], json!("Ada"));
+        drop(engine);
+
+        let engine = FlowEngine::new(confirmation_flow(), HashMap::new()).with_checkpoint_store(store);
+        let state = engine
+            .resume_flow_with_input("run-1", HashMap::from([("confirmed".to_string(), json!(true))]))
+            .await
+            .unwrap();
+
+        assert_eq!(state["name"], json!("Ada"));
+        assert_eq!(state["confirmed"], json!(true));
+        assert_eq!(engine.load_checkpoint("run-1").await.unwrap(), None);
+        assert!(engine.resume_flow("run-1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_run_without_id_leaves_no_checkpoint() {
+        let store: Arc<dyn KVStore> = Arc::new(MemoryKVStore::default());
+        let engine = FlowEngine::new(confirmation_flow(), HashMap::new()).with_checkpoint_store(store.clone());
+        let input = HashMap::from([("name".to_string(), json!("Ada"))]);
+        assert!(engine.execute_flow("confirmation_flow", input).await.is_err());
+        assert!(store.keys(b"").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_goto_cycle_exceeds_max_steps() {
+        let goto = |id: &str, destination: &str| {
+            FlowBlock::GoToBlock(GoToBlock {
+                id: id.to_string(),
+                properties: properties(json!({ "destination_block_id": destination })),
+            })
+        };
+        let flow = FlowDefinition {
+            name: "loop_flow".to_string(),
+            start_block_id: "a".to_string(),
+            blocks: vec![goto("a", "b"), goto("b", "a")],
+        };
+        let engine = FlowEngine::new(HashMap::from([("loop_flow".to_string(), flow)]), HashMap::new()).with_max_steps(5);
+
+        let error = engine.execute_flow("loop_flow", HashMap::new()).await.unwrap_err();
+        assert!(error.contains("max steps"));
+        assert!(error.contains("block b"));
+    }
+
+    #[test]
+    fn test_condition_compiled_once_and_reused() {
+        let conditions = ConditionCache::new();
+        let state = properties(json!({ "age": 30, "country": "NZ" }));
+        let first = conditions.compile("age >= 18 && country == \"NZ\"").unwrap();
+        for _ in 0..1_000 {
+            assert!(conditions.evaluate("age >= 18 && country == \"NZ\"", &state).unwrap());
+        }
+        let second = conditions.compile("age >= 18 && country == \"NZ\"").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(conditions.len(), 1);
+
+        let err = conditions.evaluate("score > 10", &state).unwrap_err();
+        assert!(err.contains("score"), "{}", err);
+    }
+
+    #[test]
+    fn test_merge_strategies() {
+        let base = HashMap::from([("shared".to_string(), json!(0))]);
+        let first = HashMap::from([("shared".to_string(), json!(1)), ("a".to_string(), json!("a"))]);
+        let second = HashMap::from([("shared".to_string(), json!(2))]);
+
+        let merged = merge_branch_states(&base, vec![first.clone(), second.clone()], MergeStrategy::LastWriterWins).unwrap();
+        assert_eq!(merged["shared"], json!(2));
+        assert_eq!(merged["a"], json!("a"));
+
+        assert!(merge_branch_states(&base, vec![first, second], MergeStrategy::PerKey).is_err());
+
+        // A branch that leaves a key untouched doesn't conflict with one that writes it
+        let untouched = base.clone();
+        let writer = HashMap::from([("shared".to_string(), json!(3))]);
+        let merged = merge_branch_states(&base, vec![writer, untouched], MergeStrategy::PerKey).unwrap();
+        assert_eq!(merged["shared"], json!(3));
+    }
+
+    #[test]
+    fn test_merge_carries_deletions() {
+        let base = HashMap::from([("shared".to_string(), json!(0)), ("kept".to_string(), json!(1))]);
+        let deleter = HashMap::from([("kept".to_string(), json!(1))]);
+        let untouched = base.clone();
+
+        // A deletion survives a branch that left the key alone, whatever the branch order
+        for branches in [vec![deleter.clone(), untouched.clone()], vec![untouched.clone(), deleter.clone()]] {
+            let merged = merge_branch_states(&base, branches, MergeStrategy::PerKey).unwrap();
+            assert!(!merged.contains_key("shared"));
+            assert_eq!(merged["kept"], json!(1));
+        }
+
+        // Under last-writer-wins a later write brings the key back; per key it conflicts
+        let writer = HashMap::from([("shared".to_string(), json!(5)), ("kept".to_string(), json!(1))]);
+        let merged = merge_branch_states(&base, vec![deleter.clone(), writer.clone()], MergeStrategy::LastWriterWins).unwrap();
+        assert_eq!(merged["shared"], json!(5));
+        let merged = merge_branch_states(&base, vec![writer.clone(), deleter.clone()], MergeStrategy::LastWriterWins).unwrap();
+        assert!(!merged.contains_key("shared"));
+        assert!(merge_branch_states(&base, vec![deleter, writer], MergeStrategy::PerKey).is_err());
+    }
+}
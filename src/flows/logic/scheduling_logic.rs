@@ -0,0 +1,41 @@
+use chrono::{Duration, NaiveDate};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    pub name: String,
+    pub duration: Duration,
+}
+
+// The tasks, assignees and deadlines `Flowgorithm` extracts from a user's instructions
+#[derive(Debug, Default)]
+pub struct SchedulingLogic {
+    tasks: Vec<Task>,
+    assignments: Vec<(String, String)>,
+    deadlines: Vec<(String, NaiveDate)>,
+}
+
+impl SchedulingLogic {
+    pub fn add_task(&mut self, task: Task) {
+        self.tasks.push(task);
+    }
+
+    pub fn assign_task(&mut self, task_name: String, assignee: String) {
+        self.assignments.push((task_name, assignee));
+    }
+
+    pub fn set_deadline(&mut self, task_name: String, deadline: NaiveDate) {
+        self.deadlines.push((task_name, deadline));
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    pub fn assignments(&self) -> &[(String, String)] {
+        &self.assignments
+    }
+
+    pub fn deadlines(&self) -> &[(String, NaiveDate)] {
+        &self.deadlines
+    }
+}
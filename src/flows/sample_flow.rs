@@ -0,0 +1,14 @@
+use crate::flows::blocks::FlowBlock;
+use serde::Serialize;
+
+// A flow generated from scheduling logic, in the order its blocks were created
+#[derive(Default, Serialize)]
+pub struct SampleFlow {
+    pub blocks: Vec<FlowBlock>,
+}
+
+impl SampleFlow {
+    pub fn add_block(&mut self, block: FlowBlock) {
+        self.blocks.push(block);
+    }
+}
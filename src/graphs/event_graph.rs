@@ -2,10 +2,11 @@ use crate::bindings::spacy_bindings::Entity;
 use crate::utils::bigboterror::BigbotError;
 use crate::event::Event;
 use crate::event::Location;
+use crate::graphs::graph_backend::{GraphBackend, GraphQuery, GraphTransaction};
+use crate::significance::event_significance::SignificanceModel;
 
 use chrono::{DateTime, Utc};
 use std::collections::HashMap;
-use neo4rs::{Graph, query};
 use std::sync::Arc;
 use thiserror::Error;
 
@@ -23,10 +24,22 @@ pub enum EventHandlerError {
     InvalidLocationFormat,
     #[error("Location must have exactly three components")]
     InvalidLocationComponents,
+    #[error("Location coordinates must be finite")]
+    NonFiniteLocation,
+    // The event's transaction was rolled back because one of its dependencies couldn't be written
+    #[error("Dependency {dependency_id} of event {event_id} failed, event rolled back: {source}")]
+    DependencyRolledBack {
+        event_id: i64,
+        dependency_id: i64,
+        source: BigbotError,
+    },
+    // Opening or committing the transaction failed; nothing was written
+    #[error("Graph connection error: {0}")]
+    ConnectionError(BigbotError),
 }
 
 pub struct EventHandler {
-    graph_client: Arc<Graph>,
+    graph_client: Arc<dyn GraphBackend>,
 }
 
 impl Event {
@@ -108,15 +121,18 @@ impl TryFrom<String> for Location {
     fn try_from(value: String) -> Result<Self, Self::Error> {
         let parts: Vec<f32> = value
             .split(',')
-            .map(str::parse)
+            .map(|part| part.trim().parse())
             .collect::<Result<Vec<_>, _>>()
             .map_err(|_| EventHandlerError::InvalidLocationFormat)?;
 
-        if parts.len() == 3 {
-            Ok(Self::new(parts[0], parts[1], parts[2]))
-        } else {
-            Err(EventHandlerError::InvalidLocationComponents)
+        if parts.len() != 3 {
+            return Err(EventHandlerError::InvalidLocationComponents);
+        }
+        // `f32::from_str` accepts "inf" and "NaN", which would poison every distance computed later
+        if !parts.iter().all(|part| part.is_finite()) {
+            return Err(EventHandlerError::NonFiniteLocation);
         }
+        Ok(Self::new(parts[0], parts[1], parts[2]))
     }
 }
 
@@ -127,61 +143,127 @@ impl From<Location> for String {
 }
 
 impl EventHandler {
-    pub fn new(graph_client: Arc<Graph>) -> Self {
+    // Takes any graph backend, e.g. `Arc::new(neo4j_client)` for a shared `clients::neo4j::Neo4jClient`
+    pub fn new(graph_client: Arc<dyn GraphBackend>) -> Self {
         Self { graph_client }
     }
 
+    // Writes the event, its dependencies and its entity link in one transaction, so a failure
+    // part-way through leaves no partial event behind.
     pub async fn add_new_event(&self, event: &Event) -> Result<(), EventHandlerError> {
-        self.create_event_node(event).await?;
+        let mut txn = self
+            .graph_client
+            .begin()
+            .await
+            .map_err(EventHandlerError::ConnectionError)?;
+        match Self::write_event(txn.as_mut(), event).await {
+            Ok(()) => txn.commit().await.map_err(EventHandlerError::ConnectionError),
+            Err(e) => {
+                // The write error says more than a failed rollback would; the server discards
+                // the transaction either way once the connection drops.
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn write_event(txn: &mut dyn GraphTransaction, event: &Event) -> Result<(), EventHandlerError> {
+        txn.run(Self::event_node_query(event)).await?;
         for dependency in &event.dependencies {
-            self.create_event_node(dependency).await?;
-            self.link_events(event.id, dependency.id()).await?;
+            Self::write_dependency(txn, event, dependency)
+                .await
+                .map_err(|source| EventHandlerError::DependencyRolledBack {
+                    event_id: event.id,
+                    dependency_id: dependency.id(),
+                    source,
+                })?;
         }
         if let Some(event_type) = &event.event_type {
-            self.link_event_to_entity(event, event_type).await?;
+            txn.run(Self::entity_link_query(event, event_type)).await?;
         }
         Ok(())
     }
 
-    async fn create_event_node(&self, event: &Event) -> Result<(), EventHandlerError> {
-        const QUERY: &str = "MERGE (e:Event {id: $id, location: $location, start: $start, end: $end, significance: $significance})";
+    async fn write_dependency(
+        txn: &mut dyn GraphTransaction,
+        event: &Event,
+        dependency: &Event,
+    ) -> Result<(), BigbotError> {
+        if dependency.id() == event.id {
+            return Err(BigbotError::InvalidInput(format!(
+                "Event {} cannot depend on itself",
+                event.id
+            )));
+        }
+        txn.run(Self::event_node_query(dependency)).await?;
+        txn.run(Self::dependency_link_query(event.id, dependency.id()))
+            .await
+    }
+
+    // Recomputes the significance of an edited event and persists it, so rankings read from
+    // the graph stay consistent with the event's current attributes and tags.
+    pub async fn on_event_edited(
+        &self,
+        event: &mut Event,
+        model: &SignificanceModel,
+    ) -> Result<f64, EventHandlerError> {
+        event.significance = model.recompute_for(event);
+        self.update_significance(event.id, event.significance).await?;
+        Ok(event.significance)
+    }
+
+    // Bulk variant of `on_event_edited` for migrations; returns how many stored values changed.
+    pub async fn recompute_all(
+        &self,
+        events: &mut [Event],
+        model: &SignificanceModel,
+    ) -> Result<usize, EventHandlerError> {
+        let mut changed = 0;
+        for event in events.iter_mut() {
+            let significance = model.recompute_for(event);
+            if significance != event.significance {
+                event.significance = significance;
+                self.update_significance(event.id, significance).await?;
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    async fn update_significance(&self, id: i64, significance: f64) -> Result<(), EventHandlerError> {
+        const QUERY: &str = "MATCH (e:Event {id: $id}) SET e.significance = $significance";
         self.graph_client
-            .run(
-                query(QUERY)
-                    .param("id", event.id)
-                    .param("location", String::from(event.location))
-                    .param("start", event.duration.0 as i64)
-                    .param("end", event.duration.1 as i64)
-                    .param("significance", event.significance),
-            )
+            .run(GraphQuery::new(QUERY).param("id", id).param("significance", significance))
             .await?;
         Ok(())
     }
 
-    async fn link_events(&self, parent_id: i64, child_id: i64) -> Result<(), EventHandlerError> {
+    fn event_node_query(event: &Event) -> GraphQuery {
+        const QUERY: &str = "MERGE (e:Event {id: $id, location: $location, start: $start, end: $end, significance: $significance})";
+        GraphQuery::new(QUERY)
+            .param("id", event.id)
+            .param("location", String::from(event.location))
+            .param("start", event.duration.0 as i64)
+            .param("end", event.duration.1 as i64)
+            .param("significance", event.significance)
+    }
+
+    fn dependency_link_query(parent_id: i64, child_id: i64) -> GraphQuery {
         const QUERY: &str = "\
             MATCH (e1:Event {id: $parent_id}), (e2:Event {id: $child_id}) \
             MERGE (e1)-[:DEPENDS_ON]->(e2)";
-        self.graph_client
-            .run(query(QUERY).param("parent_id", parent_id).param("child_id", child_id))
-            .await?;
-        Ok(())
+        GraphQuery::new(QUERY).param("parent_id", parent_id).param("child_id", child_id)
     }
 
-    async fn link_event_to_entity(&self, event: &Event, entity: &Entity) -> Result<(), EventHandlerError> {
+    fn entity_link_query(event: &Event, entity: &Entity) -> GraphQuery {
         const QUERY: &str = "\
             MERGE (ent:Entity {label: $label, text: $text}) \
             MERGE (ev:Event {id: $id}) \
             MERGE (ent)-[:RELATED_TO]->(ev)";
-        self.graph_client
-            .run(
-                query(QUERY)
-                    .param("label", &entity.label.to_string())
-                    .param("text", &entity.text)
-                    .param("id", event.id),
-            )
-            .await?;
-        Ok(())
+        GraphQuery::new(QUERY)
+            .param("label", entity.label.to_string())
+            .param("text", entity.text.as_str())
+            .param("id", event.id)
     }
 }
 
@@ -191,25 +273,44 @@ mod tests {
     use super::*;
     use crate::bindings::spacy_bindings::Entity;
     use crate::bindings::spacy_bindings::EntityLabel::Gpe;
+    use crate::graphs::graph_backend::InMemoryGraph;
+    use crate::clients::neo4j::Neo4jClient;
+    use crate::event::{EventBuilder, EventType as StreamEventType};
     use tokio::sync::OnceCell;
     use std::env;
 
-    static GRAPH_CLIENT: OnceCell<Arc<Graph>> = OnceCell::const_new();
+    static GRAPH_CLIENT: OnceCell<Arc<dyn GraphBackend>> = OnceCell::const_new();
 
-    async fn setup_graph_client() -> Arc<Graph> {
+    async fn setup_graph_client() -> Arc<dyn GraphBackend> {
         GRAPH_CLIENT
             .get_or_init(|| async {
                 let uri = env::var("NEO4J_URI").unwrap_or_else(|_| "neo4j://localhost:7687".into());
                 let username = env::var("NEO4J_USERNAME").unwrap_or_else(|_| "neo4j".into());
                 let password = env::var("NEO4J_PASSWORD").unwrap_or_else(|_| "password".into());
-                Graph::new(&uri, &username, &password)
+                let client = Neo4jClient::connect(&uri, &username, &password)
                     .await
-                    .expect("Failed to connect to Neo4j")
+                    .expect("Failed to connect to Neo4j");
+                Arc::new(client) as Arc<dyn GraphBackend>
             })
             .await
             .clone()
     }
 
+    fn dependency_graph_event(id: i64, dependencies: Vec<Arc<Event>>, entity: Option<Entity>) -> Event {
+        let mut event = EventBuilder::new(id, format!("name{}", id), StreamEventType::ScheduledEvent)
+            .unique_id(format!("unique_id{}", id))
+            .location(Location::from((id as f32, 0.0, 0.0)))
+            .significance(1.0)
+            .time_range(id as u64, id as u64 + 1)
+            .dependencies(dependencies)
+            .resource(format!("resource{}", id))
+            .build()
+            .unwrap();
+        // The builder has no entity setter; the graph reads the entity link from `event_type`
+        event.event_type = entity;
+        event
+    }
+
     #[tokio::test]
     async fn test_location_distance() {
         let location1 = Location::from((1.0, 1.0, 1.0));
@@ -218,6 +319,30 @@ mod tests {
         assert_eq!(distance, 5.0);
     }
 
+    #[test]
+    fn test_location_try_from_trims_components() {
+        let location = Location::try_from(" 1.0, 2.0 ,3.0 ".to_string()).unwrap();
+        assert_eq!((location.0, location.1, location.2), (1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn test_location_try_from_rejects_non_finite() {
+        for value in ["inf,nan,0", "1.0, -inf, 2.0"] {
+            assert!(matches!(
+                Location::try_from(value.to_string()),
+                Err(EventHandlerError::NonFiniteLocation)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_location_try_from_requires_three_components() {
+        assert!(matches!(
+            Location::try_from("1.0, 2.0".to_string()),
+            Err(EventHandlerError::InvalidLocationComponents)
+        ));
+    }
+
     #[tokio::test]
     async fn test_event_is_schedulable() {
         let event1 = Arc::new(Event::new(
@@ -396,21 +521,155 @@ mod tests {
             MATCH (e:Event {id: $id})-[:DEPENDS_ON]->(d:Event)
             RETURN e.id AS event_id, d.id AS dependency_id";
     
-        let result = handler
+        let rows = handler
             .graph_client
-            .run(query(query).param("id", main_event.id))
+            .query_rows(
+                GraphQuery::new(query)
+                    .param("id", main_event.id)
+                    .returns(&["event_id", "dependency_id"]),
+            )
             .await
             .unwrap();
-    
-        let mut event_dependencies = Vec::new();
-        for row in result {
-            let event_id: i64 = row.get("event_id").unwrap();
-            let dependency_id: i64 = row.get("dependency_id").unwrap();
-            event_dependencies.push((event_id, dependency_id));
-        }
-    
+
+        let event_dependencies: Vec<(i64, i64)> = rows
+            .iter()
+            .map(|row| (row["event_id"].as_i64().unwrap(), row["dependency_id"].as_i64().unwrap()))
+            .collect();
+
         assert_eq!(event_dependencies.len(), 2);
         assert!(event_dependencies.contains(&(main_event.id, dependency_event1.id)));
         assert!(event_dependencies.contains(&(main_event.id, dependency_event2.id)));
     }
+
+    #[tokio::test]
+    async fn test_on_event_edited_persists_recomputed_significance() {
+        let client = setup_graph_client().await;
+        let handler = EventHandler::new(client);
+        let model = SignificanceModel::new().with_attribute_weight("importance", 10.0);
+
+        // The Neo4j database is shared between runs, so use an id no earlier run has written
+        let id = rand::random::<u32>() as i64;
+        let mut attributes = HashMap::new();
+        attributes.insert("importance".to_string(), 1.0);
+        let mut event = Event::new(
+            id,
+            format!("unique_id{}", id),
+            format!("user_id{}", id),
+            Utc::now(),
+            format!("header{}", id),
+            format!("name{}", id),
+            Utc::now(),
+            Utc::now(),
+            attributes,
+            None,
+            Location::from((1.0, 1.0, 1.0)),
+            0.0,
+            Duration(10, 20),
+            vec![],
+            10,
+            20,
+            format!("resource{}", id),
+            vec![],
+        );
+        // `on_event_edited` only updates an existing node, so the event must be stored first
+        handler.add_new_event(&event).await.unwrap();
+        handler.on_event_edited(&mut event, &model).await.unwrap();
+        let before = event.significance;
+
+        event.attributes.insert("importance".to_string(), 5.0);
+        let after = handler.on_event_edited(&mut event, &model).await.unwrap();
+        assert!(after > before);
+
+        let rows = handler
+            .graph_client
+            .query_rows(
+                GraphQuery::new("MATCH (e:Event {id: $id}) RETURN e.significance AS significance")
+                    .param("id", event.id)
+                    .returns(&["significance"]),
+            )
+            .await
+            .unwrap();
+        let persisted = rows.first().expect("event node should exist")["significance"].as_f64().unwrap();
+        assert_eq!(persisted, after);
+    }
+
+    #[tokio::test]
+    async fn test_add_new_event_links_dependencies_in_memory() {
+        let graph = Arc::new(InMemoryGraph::new());
+        let handler = EventHandler::new(graph.clone());
+
+        let dependency1 = Arc::new(dependency_graph_event(2, vec![], None));
+        let dependency2 = Arc::new(dependency_graph_event(3, vec![], None));
+        let spain = Entity {
+            text: "Spain".to_string(),
+            label: Gpe,
+        };
+        let event = dependency_graph_event(1, vec![dependency1, dependency2], Some(spain));
+
+        handler.add_new_event(&event).await.unwrap();
+        // Re-adding the same event must not duplicate nodes or relationships
+        handler.add_new_event(&event).await.unwrap();
+
+        assert_eq!(graph.node_count("Event"), 3);
+        assert_eq!(graph.node_count("Entity"), 1);
+        assert_eq!(graph.relationship_count("DEPENDS_ON"), 2);
+        assert_eq!(graph.relationship_count("RELATED_TO"), 1);
+
+        let rows = graph
+            .query_rows(
+                GraphQuery::new("MATCH (e:Event {id: $id})-[:DEPENDS_ON]->(d:Event) RETURN d.id AS dependency_id")
+                    .param("id", 1i64)
+                    .returns(&["dependency_id"]),
+            )
+            .await
+            .unwrap();
+        let mut dependency_ids: Vec<i64> = rows.iter().map(|row| row["dependency_id"].as_i64().unwrap()).collect();
+        dependency_ids.sort();
+        assert_eq!(dependency_ids, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_add_new_event_rolls_back_on_bad_dependency_in_memory() {
+        let graph = Arc::new(InMemoryGraph::new());
+        let handler = EventHandler::new(graph.clone());
+
+        let good = Arc::new(dependency_graph_event(2, vec![], None));
+        // An event listing itself as a dependency is rejected after its own node has been written
+        let bad = Arc::new(dependency_graph_event(1, vec![], None));
+        let event = dependency_graph_event(1, vec![good, bad], None);
+
+        let err = handler.add_new_event(&event).await.unwrap_err();
+        assert!(matches!(
+            err,
+            EventHandlerError::DependencyRolledBack { event_id: 1, dependency_id: 1, .. }
+        ));
+        assert_eq!(graph.node_count("Event"), 0);
+        assert_eq!(graph.relationship_count("DEPENDS_ON"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_add_new_event_rolls_back_on_bad_dependency() {
+        if env::var("NEO4J_URI").is_err() {
+            return;
+        }
+        let graph = setup_graph_client().await;
+        let handler = EventHandler::new(graph.clone());
+        const EVENT_ID: i64 = 90_001;
+
+        let bad = Arc::new(dependency_graph_event(EVENT_ID, vec![], None));
+        let event = dependency_graph_event(EVENT_ID, vec![bad], None);
+
+        let err = handler.add_new_event(&event).await.unwrap_err();
+        assert!(matches!(err, EventHandlerError::DependencyRolledBack { .. }));
+
+        let rows = graph
+            .query_rows(
+                GraphQuery::new("MATCH (e:Event {id: $id}) RETURN e.id AS id")
+                    .param("id", EVENT_ID)
+                    .returns(&["id"]),
+            )
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+    }
 }
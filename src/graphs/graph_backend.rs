@@ -0,0 +1,624 @@
+//! # Graph Backend
+//!
+//! Abstracts the graph store used by the graph handlers so their logic can run against
+//! Neo4j in production and an in-memory graph in unit tests.
+//!
+//! ## Main Components
+//!
+//! - `GraphBackend`: the trait handlers depend on, exposing `run`, `query_rows` and `begin`.
+//! - `GraphTransaction`: statements run through it apply together on `commit`, or not at all.
+//! - `GraphQuery`: a Cypher statement together with its parameters and returned columns.
+//! - `InMemoryGraph`: a test backend for the statements the handlers run: `MATCH` over node and
+//!   single-relationship patterns, `MERGE` of one node or of a relationship between bound nodes,
+//!   `SET var.prop = $param` and `RETURN var.prop AS alias`. Property values must be parameters.
+//!
+//! `neo4rs::Graph` implements `GraphBackend` directly, so an existing `Arc<Graph>` can be
+//! passed wherever an `Arc<dyn GraphBackend>` is expected.
+
+use crate::utils::bigboterror::BigbotError;
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use neo4rs::{query, Graph};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl From<bool> for GraphValue {
+    fn from(value: bool) -> Self {
+        GraphValue::Bool(value)
+    }
+}
+
+impl From<i64> for GraphValue {
+    fn from(value: i64) -> Self {
+        GraphValue::Int(value)
+    }
+}
+
+impl From<f64> for GraphValue {
+    fn from(value: f64) -> Self {
+        GraphValue::Float(value)
+    }
+}
+
+impl From<&str> for GraphValue {
+    fn from(value: &str) -> Self {
+        GraphValue::String(value.to_string())
+    }
+}
+
+impl From<String> for GraphValue {
+    fn from(value: String) -> Self {
+        GraphValue::String(value)
+    }
+}
+
+impl GraphValue {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            GraphValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            GraphValue::Float(value) => Some(*value),
+            GraphValue::Int(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            GraphValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+pub type GraphRow = HashMap<String, GraphValue>;
+
+// A Cypher statement with named parameters. `returns` lists the columns `query_rows` reads
+// back, since a Bolt row can only be read by column name.
+#[derive(Debug, Clone)]
+pub struct GraphQuery {
+    text: String,
+    params: HashMap<String, GraphValue>,
+    columns: Vec<String>,
+}
+
+impl GraphQuery {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            params: HashMap::new(),
+            columns: Vec::new(),
+        }
+    }
+
+    pub fn param(mut self, key: &str, value: impl Into<GraphValue>) -> Self {
+        self.params.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn returns(mut self, columns: &[&str]) -> Self {
+        self.columns = columns.iter().map(|column| column.to_string()).collect();
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn params(&self) -> &HashMap<String, GraphValue> {
+        &self.params
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+}
+
+#[async_trait]
+pub trait GraphBackend: Send + Sync {
+    // Executes a statement for its side effects.
+    async fn run(&self, query: GraphQuery) -> Result<(), BigbotError>;
+
+    // Executes a statement and returns its rows, keyed by the query's `returns` columns.
+    async fn query_rows(&self, query: GraphQuery) -> Result<Vec<GraphRow>, BigbotError>;
+
+    // Opens a transaction; nothing run through it is visible to others until it commits.
+    async fn begin<'a>(&'a self) -> Result<Box<dyn GraphTransaction + 'a>, BigbotError>;
+}
+
+#[async_trait]
+pub trait GraphTransaction: Send {
+    async fn run(&mut self, query: GraphQuery) -> Result<(), BigbotError>;
+
+    async fn commit(self: Box<Self>) -> Result<(), BigbotError>;
+
+    // Discards every statement run so far. Dropping an uncommitted transaction also discards them.
+    async fn rollback(self: Box<Self>) -> Result<(), BigbotError>;
+}
+
+fn to_neo4j_query(graph_query: &GraphQuery) -> neo4rs::Query {
+    let mut neo4j_query = query(graph_query.text());
+    for (key, value) in graph_query.params() {
+        neo4j_query = match value {
+            GraphValue::Null => neo4j_query.param(key, Option::<String>::None),
+            GraphValue::Bool(value) => neo4j_query.param(key, *value),
+            GraphValue::Int(value) => neo4j_query.param(key, *value),
+            GraphValue::Float(value) => neo4j_query.param(key, *value),
+            GraphValue::String(value) => neo4j_query.param(key, value.clone()),
+        };
+    }
+    neo4j_query
+}
+
+fn read_neo4j_column(row: &neo4rs::Row, column: &str) -> GraphValue {
+    if let Ok(value) = row.get::<i64>(column) {
+        GraphValue::Int(value)
+    } else if let Ok(value) = row.get::<f64>(column) {
+        GraphValue::Float(value)
+    } else if let Ok(value) = row.get::<bool>(column) {
+        GraphValue::Bool(value)
+    } else if let Ok(value) = row.get::<String>(column) {
+        GraphValue::String(value)
+    } else {
+        GraphValue::Null
+    }
+}
+
+#[async_trait]
+impl GraphBackend for Graph {
+    async fn run(&self, query: GraphQuery) -> Result<(), BigbotError> {
+        Graph::run(self, to_neo4j_query(&query)).await?;
+        Ok(())
+    }
+
+    async fn query_rows(&self, query: GraphQuery) -> Result<Vec<GraphRow>, BigbotError> {
+        let mut stream = self.execute(to_neo4j_query(&query)).await?;
+        let mut rows = Vec::new();
+        while let Some(row) = stream.next().await? {
+            rows.push(
+                query
+                    .columns()
+                    .iter()
+                    .map(|column| (column.clone(), read_neo4j_column(&row, column)))
+                    .collect(),
+            );
+        }
+        Ok(rows)
+    }
+
+    async fn begin<'a>(&'a self) -> Result<Box<dyn GraphTransaction + 'a>, BigbotError> {
+        Ok(Box::new(Neo4jTransaction(self.start_txn().await?)))
+    }
+}
+
+struct Neo4jTransaction(neo4rs::Txn);
+
+#[async_trait]
+impl GraphTransaction for Neo4jTransaction {
+    async fn run(&mut self, query: GraphQuery) -> Result<(), BigbotError> {
+        self.0.run(to_neo4j_query(&query)).await?;
+        Ok(())
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), BigbotError> {
+        self.0.commit().await?;
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), BigbotError> {
+        self.0.rollback().await?;
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref CLAUSE_RE: Regex = Regex::new(r"(?i)\b(MATCH|MERGE|SET|RETURN)\b").unwrap();
+    static ref NODE_RE: Regex =
+        Regex::new(r"^\(\s*(\w+)\s*(?::\s*(\w+))?\s*(?:\{([^}]*)\})?\s*\)").unwrap();
+    static ref REL_RE: Regex = Regex::new(r"^-\s*\[\s*:\s*(\w+)\s*\]\s*->").unwrap();
+    static ref SET_RE: Regex = Regex::new(r"^(\w+)\.(\w+)\s*=\s*\$(\w+)$").unwrap();
+    static ref RETURN_RE: Regex = Regex::new(r"(?i)^(\w+)\.(\w+)\s+AS\s+(\w+)$").unwrap();
+}
+
+#[derive(Debug, Clone)]
+struct StoredNode {
+    label: Option<String>,
+    properties: HashMap<String, GraphValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct StoredRelationship {
+    from: usize,
+    rel_type: String,
+    to: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct GraphState {
+    nodes: Vec<StoredNode>,
+    relationships: Vec<StoredRelationship>,
+}
+
+// `(var:Label {key: $param, ...})`; a bare `(var)` refers to an already bound node
+#[derive(Debug, Clone)]
+struct NodePattern {
+    variable: String,
+    label: Option<String>,
+    properties: HashMap<String, GraphValue>,
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Node(NodePattern),
+    Path(NodePattern, String, NodePattern),
+}
+
+type Binding = HashMap<String, usize>;
+
+// An in-memory graph for tests. Each statement runs atomically under a single lock; a transaction
+// works on a copy of the graph that replaces it on commit.
+#[derive(Debug, Default)]
+pub struct InMemoryGraph {
+    state: Mutex<GraphState>,
+}
+
+impl InMemoryGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn node_count(&self, label: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .nodes
+            .iter()
+            .filter(|node| node.label.as_deref() == Some(label))
+            .count()
+    }
+
+    pub fn relationship_count(&self, rel_type: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .relationships
+            .iter()
+            .filter(|relationship| relationship.rel_type == rel_type)
+            .count()
+    }
+
+    fn execute(&self, query: &GraphQuery) -> Result<Vec<GraphRow>, BigbotError> {
+        execute_on(&mut self.state.lock().unwrap(), query)
+    }
+}
+
+fn execute_on(state: &mut GraphState, query: &GraphQuery) -> Result<Vec<GraphRow>, BigbotError> {
+    let params = query.params();
+    let mut bindings: Vec<Binding> = vec![Binding::new()];
+    let mut rows = Vec::new();
+
+    for (keyword, body) in split_clauses(query.text())? {
+        match keyword.as_str() {
+            "MATCH" => {
+                for pattern in parse_patterns(&body, params)? {
+                    bindings = bindings
+                        .iter()
+                        .flat_map(|binding| match_pattern(state, &pattern, binding))
+                        .collect();
+                }
+            }
+            "MERGE" => {
+                let pattern = match parse_patterns(&body, params)?.as_slice() {
+                    [pattern] => pattern.clone(),
+                    _ => return Err(unsupported(&body)),
+                };
+                for binding in &mut bindings {
+                    merge_pattern(state, &pattern, binding)?;
+                }
+            }
+            "SET" => {
+                let captures = SET_RE.captures(&body).ok_or_else(|| unsupported(&body))?;
+                let value = param(&captures[3], params)?;
+                for binding in &bindings {
+                    let node = bound(binding, &captures[1])?;
+                    state.nodes[node]
+                        .properties
+                        .insert(captures[2].to_string(), value.clone());
+                }
+            }
+            "RETURN" => {
+                let projections = body
+                    .split(',')
+                    .map(|item| RETURN_RE.captures(item.trim()).ok_or_else(|| unsupported(item)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for binding in &bindings {
+                    let mut row = GraphRow::new();
+                    for projection in &projections {
+                        let node = bound(binding, &projection[1])?;
+                        let value = state.nodes[node].properties.get(&projection[2]).cloned();
+                        row.insert(projection[3].to_string(), value.unwrap_or(GraphValue::Null));
+                    }
+                    rows.push(row);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    Ok(rows)
+}
+
+#[async_trait]
+impl GraphBackend for InMemoryGraph {
+    async fn run(&self, query: GraphQuery) -> Result<(), BigbotError> {
+        self.execute(&query)?;
+        Ok(())
+    }
+
+    async fn query_rows(&self, query: GraphQuery) -> Result<Vec<GraphRow>, BigbotError> {
+        self.execute(&query)
+    }
+
+    async fn begin<'a>(&'a self) -> Result<Box<dyn GraphTransaction + 'a>, BigbotError> {
+        let staged = self.state.lock().unwrap().clone();
+        Ok(Box::new(InMemoryTransaction {
+            graph: self,
+            staged,
+        }))
+    }
+}
+
+struct InMemoryTransaction<'a> {
+    graph: &'a InMemoryGraph,
+    staged: GraphState,
+}
+
+#[async_trait]
+impl GraphTransaction for InMemoryTransaction<'_> {
+    async fn run(&mut self, query: GraphQuery) -> Result<(), BigbotError> {
+        execute_on(&mut self.staged, &query)?;
+        Ok(())
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), BigbotError> {
+        *self.graph.state.lock().unwrap() = self.staged;
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), BigbotError> {
+        Ok(())
+    }
+}
+
+fn unsupported(fragment: &str) -> BigbotError {
+    BigbotError::InvalidInput(format!("Unsupported Cypher in in-memory graph: {}", fragment.trim()))
+}
+
+fn bound(binding: &Binding, variable: &str) -> Result<usize, BigbotError> {
+    binding.get(variable).copied().ok_or_else(|| {
+        BigbotError::InvalidInput(format!("Unbound variable in in-memory graph: {}", variable))
+    })
+}
+
+fn param(name: &str, params: &HashMap<String, GraphValue>) -> Result<GraphValue, BigbotError> {
+    params
+        .get(name)
+        .cloned()
+        .ok_or_else(|| BigbotError::InvalidInput(format!("Missing query parameter: {}", name)))
+}
+
+fn split_clauses(text: &str) -> Result<Vec<(String, String)>, BigbotError> {
+    let keywords: Vec<_> = CLAUSE_RE.find_iter(text).collect();
+    if keywords.is_empty() || !text[..keywords[0].start()].trim().is_empty() {
+        return Err(unsupported(text));
+    }
+    Ok(keywords
+        .iter()
+        .enumerate()
+        .map(|(i, keyword)| {
+            let end = keywords.get(i + 1).map_or(text.len(), |next| next.start());
+            (
+                keyword.as_str().to_uppercase(),
+                text[keyword.end()..end].trim().to_string(),
+            )
+        })
+        .collect())
+}
+
+// Parses one node pattern off the front of `text`, returning it and the bytes it took up
+fn parse_node(
+    text: &str,
+    params: &HashMap<String, GraphValue>,
+) -> Result<(NodePattern, usize), BigbotError> {
+    let captures = NODE_RE.captures(text).ok_or_else(|| unsupported(text))?;
+    let mut properties = HashMap::new();
+    if let Some(body) = captures.get(3) {
+        for entry in body.as_str().split(',') {
+            let (key, value) = entry.split_once(':').ok_or_else(|| unsupported(entry))?;
+            let name = value.trim().strip_prefix('$').ok_or_else(|| unsupported(entry))?;
+            properties.insert(key.trim().to_string(), param(name, params)?);
+        }
+    }
+    let pattern = NodePattern {
+        variable: captures[1].to_string(),
+        label: captures.get(2).map(|m| m.as_str().to_string()),
+        properties,
+    };
+    Ok((pattern, captures[0].len()))
+}
+
+// Parses a comma-separated list of `(node)` or `(node)-[:TYPE]->(node)` patterns
+fn parse_patterns(
+    text: &str,
+    params: &HashMap<String, GraphValue>,
+) -> Result<Vec<Pattern>, BigbotError> {
+    let mut patterns = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let (start, consumed) = parse_node(rest, params)?;
+        rest = rest[consumed..].trim_start();
+        if let Some(captures) = REL_RE.captures(rest) {
+            let (end, consumed) = parse_node(rest[captures[0].len()..].trim_start(), params)?;
+            patterns.push(Pattern::Path(start, captures[1].to_string(), end));
+            rest = rest[captures[0].len()..].trim_start()[consumed..].trim_start();
+        } else {
+            patterns.push(Pattern::Node(start));
+        }
+        rest = match rest.strip_prefix(',') {
+            Some(remaining) => remaining.trim_start(),
+            None if rest.is_empty() => rest,
+            None => return Err(unsupported(rest)),
+        };
+    }
+    Ok(patterns)
+}
+
+fn node_matches(node: &StoredNode, pattern: &NodePattern) -> bool {
+    (pattern.label.is_none() || node.label == pattern.label)
+        && pattern
+            .properties
+            .iter()
+            .all(|(key, value)| node.properties.get(key) == Some(value))
+}
+
+// Candidate nodes for a pattern: the bound node if its variable is bound, otherwise every
+// matching node
+fn candidates(state: &GraphState, pattern: &NodePattern, binding: &Binding) -> Vec<usize> {
+    match binding.get(&pattern.variable) {
+        Some(&node) if node_matches(&state.nodes[node], pattern) => vec![node],
+        Some(_) => Vec::new(),
+        None => (0..state.nodes.len())
+            .filter(|&node| node_matches(&state.nodes[node], pattern))
+            .collect(),
+    }
+}
+
+fn has_relationship(state: &GraphState, from: usize, rel_type: &str, to: usize) -> bool {
+    state.relationships.contains(&StoredRelationship {
+        from,
+        rel_type: rel_type.to_string(),
+        to,
+    })
+}
+
+fn match_pattern(state: &GraphState, pattern: &Pattern, binding: &Binding) -> Vec<Binding> {
+    let bind = |binding: &Binding, pattern: &NodePattern, node: usize| {
+        let mut binding = binding.clone();
+        binding.insert(pattern.variable.clone(), node);
+        binding
+    };
+    match pattern {
+        Pattern::Node(node) => candidates(state, node, binding)
+            .into_iter()
+            .map(|candidate| bind(binding, node, candidate))
+            .collect(),
+        Pattern::Path(start, rel_type, end) => {
+            let mut matched = Vec::new();
+            for from in candidates(state, start, binding) {
+                let binding = bind(binding, start, from);
+                for to in candidates(state, end, &binding) {
+                    if has_relationship(state, from, rel_type, to) {
+                        matched.push(bind(&binding, end, to));
+                    }
+                }
+            }
+            matched
+        }
+    }
+}
+
+// Binds a node pattern to the first matching node, creating it if there is none. A relationship
+// is created between two already bound nodes unless it exists.
+fn merge_pattern(state: &mut GraphState, pattern: &Pattern, binding: &mut Binding) -> Result<(), BigbotError> {
+    match pattern {
+        Pattern::Node(node) => {
+            let existing = candidates(state, node, binding).first().copied();
+            let index = existing.unwrap_or_else(|| {
+                state.nodes.push(StoredNode {
+                    label: node.label.clone(),
+                    properties: node.properties.clone(),
+                });
+                state.nodes.len() - 1
+            });
+            binding.insert(node.variable.clone(), index);
+        }
+        Pattern::Path(start, rel_type, end) => {
+            let (from, to) = (bound(binding, &start.variable)?, bound(binding, &end.variable)?);
+            if !has_relationship(state, from, rel_type, to) {
+                state.relationships.push(StoredRelationship {
+                    from,
+                    rel_type: rel_type.clone(),
+                    to,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_merge_is_idempotent_and_match_returns_rows() {
+        let graph = InMemoryGraph::new();
+        for _ in 0..2 {
+            graph
+                .run(GraphQuery::new("MERGE (u:User {id: $id, name: $name})").param("id", 1i64).param("name", "ada"))
+                .await
+                .unwrap();
+        }
+        graph
+            .run(GraphQuery::new("MERGE (u:User {id: $id, name: $name})").param("id", 2i64).param("name", "alan"))
+            .await
+            .unwrap();
+        graph
+            .run(
+                GraphQuery::new("MATCH (a:User {id: $a}), (b:User {id: $b}) MERGE (a)-[:KNOWS]->(b)")
+                    .param("a", 1i64)
+                    .param("b", 2i64),
+            )
+            .await
+            .unwrap();
+        graph
+            .run(GraphQuery::new("MATCH (u:User {id: $id}) SET u.active = $active").param("id", 2i64).param("active", true))
+            .await
+            .unwrap();
+
+        assert_eq!(graph.node_count("User"), 2);
+        assert_eq!(graph.relationship_count("KNOWS"), 1);
+
+        let rows = graph
+            .query_rows(
+                GraphQuery::new("MATCH (a:User {id: $id})-[:KNOWS]->(b:User) RETURN b.name AS name, b.active AS active")
+                    .param("id", 1i64)
+                    .returns(&["name", "active"]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["name"], GraphValue::from("alan"));
+        assert_eq!(rows[0]["active"], GraphValue::Bool(true));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_cypher_is_rejected() {
+        let graph = InMemoryGraph::new();
+        let result = graph.run(GraphQuery::new("CREATE INDEX ON :User(id)")).await;
+        assert!(matches!(result, Err(BigbotError::InvalidInput(_))));
+    }
+}
@@ -27,6 +27,7 @@ use crate::bindings::spacy_bindings::{SpacyModule, TokenPos};
 use crate::recommendations::rlhf::{RLHFConfig, run_reinforcement_learning};
 use crate::graphs::user_graph::{UserGraph, UserNode, calculate_total_reward};
 use crate::iam::user::User;
+use crate::utils::privacy::{noisy_top_interests, PrivacyBudget, PrivacyError};
 
 // Enum to represent different types of nodes in the personalisation graph
 pub enum PersonalisationNodeType {
@@ -56,6 +57,7 @@ struct DepTriple {
 struct PersonalisationGraph {
     nodes: HashMap<String, PersonalisationNode>,
     edges: Vec<DepTriple>,
+    privacy_budget: PrivacyBudget,
 }
 
 impl PersonalisationGraph {
@@ -64,9 +66,30 @@ impl PersonalisationGraph {
         PersonalisationGraph {
             nodes: HashMap::new(),
             edges: Vec::new(),
+            privacy_budget: PrivacyBudget::default(),
         }
     }
 
+    fn with_privacy_budget(mut self, total: f64) -> Self {
+        self.privacy_budget = PrivacyBudget::new(total);
+        self
+    }
+
+    fn privacy_budget(&self) -> &PrivacyBudget {
+        &self.privacy_budget
+    }
+
+    // The `n` most common preference values with differentially private counts. Each preference
+    // node counts as one contributor; `epsilon` is taken from the graph's privacy budget.
+    fn top_interests_dp(&mut self, epsilon: f64, n: usize) -> Result<Vec<(String, f64)>, PrivacyError> {
+        let contributors = self
+            .nodes
+            .values()
+            .filter(|node| matches!(node.node_type, PersonalisationNodeType::Preference(_)))
+            .map(|node| node.values.iter().map(String::as_str));
+        noisy_top_interests(&mut self.privacy_budget, contributors, epsilon, n)
+    }
+
     // Add a new node to the personalisation graph
     fn add_node(&mut self, node_id: String, node_type: PersonalisationNodeType, values: HashSet<String>, embeddings: Vec<f32>, timestamp: SystemTime) {
         let node = PersonalisationNode {
@@ -287,4 +310,41 @@ fn calculate_edge_weight(dep_triple: &DepTriple) -> f32 {
         "conj" | "cc" | "punct" => 0.4,
         _ => 0.2,
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_preferences(graph: &mut PersonalisationGraph, interest: &str, contributors: usize) {
+        for i in 0..contributors {
+            let values = HashSet::from([interest.to_string()]);
+            let node_type = PersonalisationNodeType::Preference("music".to_string());
+            graph.add_node(format!("{}-{}", interest, i), node_type, values, vec![], SystemTime::now());
+        }
+    }
+
+    #[test]
+    fn test_top_interests_dp_is_noisy_and_spends_budget() {
+        let mut graph = PersonalisationGraph::new();
+        add_preferences(&mut graph, "jazz", 400);
+        add_preferences(&mut graph, "rock", 300);
+        add_preferences(&mut graph, "opera", 3);
+        let entity = PersonalisationNodeType::Entity("jazz".to_string());
+        graph.add_node("entity".to_string(), entity, HashSet::from(["jazz".to_string()]), vec![], SystemTime::now());
+
+        let first = graph.top_interests_dp(0.5, 3).unwrap();
+        assert_eq!(graph.privacy_budget().remaining(), 0.5);
+        let second = graph.top_interests_dp(0.5, 3).unwrap();
+        assert_eq!(graph.privacy_budget().remaining(), 0.0);
+
+        for result in [&first, &second] {
+            // Opera has too few contributors to be released
+            let interests: Vec<&str> = result.iter().map(|(interest, _)| interest.as_str()).collect();
+            assert_eq!(interests, vec!["jazz", "rock"]);
+            assert!((result[0].1 - 400.0).abs() < 150.0 && (result[1].1 - 300.0).abs() < 150.0, "{:?}", result);
+        }
+        assert_ne!(first, second);
+
+        assert!(matches!(graph.top_interests_dp(0.5, 3), Err(PrivacyError::BudgetExhausted { .. })));
+    }
+}
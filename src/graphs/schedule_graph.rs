@@ -1,7 +1,9 @@
-use std::collections::HashMap;
-use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::{Ordering, Reverse};
 
-use crate::event::Event;
+use thiserror::Error;
+
+use crate::event::{Event, EventBuilder, EventType};
 
 impl Ord for Event {
     fn cmp(&self, other: &Self) -> Ordering {
@@ -15,6 +17,11 @@ impl PartialOrd for Event {
     }
 }
 
+// Whether two events' `start`..`end` slots intersect. Events that only touch end to start don't overlap.
+pub fn events_overlap(a: &Event, b: &Event) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
 #[derive(Debug, Clone)]
 struct Dependency {
     event: Event,
@@ -27,29 +34,30 @@ struct Itinerary {
 }
 
 impl Itinerary {
-    fn new(events: Vec<Dependency>) -> Itinerary {
-        let schedules = schedule_resources(events.clone());
-        Itinerary { events, schedules }
+    fn new(events: Vec<Dependency>) -> Result<Itinerary, ScheduleError> {
+        let schedules = schedule_resources(events.clone())?;
+        Ok(Itinerary { events, schedules })
     }
 
-    fn insert_event(&mut self, event: Dependency) {
+    fn insert_event(&mut self, event: Dependency) -> Result<(), ScheduleError> {
         self.events.push(event);
-        self.reschedule();
+        self.reschedule()
     }
 
-    fn modify_event(&mut self, index: usize, event: Dependency) {
+    fn modify_event(&mut self, index: usize, event: Dependency) -> Result<(), ScheduleError> {
         self.events[index] = event;
-        self.reschedule();
+        self.reschedule()
     }
 
-    fn remove_event(&mut self, index: usize) {
+    fn remove_event(&mut self, index: usize) -> Result<(), ScheduleError> {
         self.events.remove(index);
-        self.reschedule();
+        self.reschedule()
     }
 
-    fn reschedule(&mut self) {
+    fn reschedule(&mut self) -> Result<(), ScheduleError> {
         self.events.sort_by(|a, b| a.event.start.cmp(&b.event.start));
-        self.schedules = schedule_resources(self.events.clone());
+        self.schedules = schedule_resources(self.events.clone())?;
+        Ok(())
     }
 
     fn print_schedules(&self) {
@@ -75,7 +83,7 @@ impl Itinerary {
             for j in i+1..self.events.len() {
                 let event1 = &self.events[i].event;
                 let event2 = &self.events[j].event;
-                if event1.start < event2.end && event2.start < event1.end {
+                if events_overlap(event1, event2) {
                     overlapping_events.push((event1, event2));
                 }
             }
@@ -90,15 +98,39 @@ impl Itinerary {
             .sum()
     }
 
-    fn find_free_time_slots(&self, resource: &str, min_duration: i32) -> Vec<(i32, i32)> {
-        let events = self.find_events_by_resource(resource);
+    // Gaps of at least `min_duration` in the resource's bookings between time 0 and `horizon`,
+    // including the one after its last event. Overlapping bookings are merged first, so a slot is
+    // never reported inside a busy interval.
+    fn find_free_time_slots(
+        &self,
+        resource: &str,
+        min_duration: i32,
+        horizon: i32,
+    ) -> Vec<(i32, i32)> {
+        let mut events = self.find_events_by_resource(resource);
+        events.sort_by_key(|event| event.start);
+        let mut busy: Vec<(i32, i32)> = Vec::new();
+        for event in events {
+            match busy.last_mut() {
+                Some(last) if event.start <= last.1 => last.1 = last.1.max(event.end),
+                _ => busy.push((event.start, event.end)),
+            }
+        }
+
         let mut free_time_slots = Vec::new();
         let mut start_time = 0;
-        for event in events {
-            if event.start - start_time >= min_duration {
-                free_time_slots.push((start_time, event.start));
+        for (busy_start, busy_end) in busy {
+            let slot_end = busy_start.min(horizon);
+            if slot_end - start_time >= min_duration {
+                free_time_slots.push((start_time, slot_end));
+            }
+            start_time = start_time.max(busy_end);
+            if start_time >= horizon {
+                return free_time_slots;
             }
-            start_time = event.end;
+        }
+        if horizon - start_time >= min_duration {
+            free_time_slots.push((start_time, horizon));
         }
         free_time_slots
     }
@@ -122,179 +154,209 @@ impl Itinerary {
     }
 
     // Novel method: Calculate the critical path (longest path) in the itinerary
-    fn calculate_critical_path(&self) -> Vec<&Event> {
-        let mut critical_path = Vec::new();
-        let mut max_end_time = 0;
-
-        for dependency in &self.events {
-            let mut path_end_time = dependency.event.end;
-            for dep_event in &dependency.dependencies {
-                path_end_time = path_end_time.max(dep_event.end);
+    // Each event's weight is its duration, and the path follows `dependencies` from an event with
+    // none to the event that finishes the heaviest chain; across disconnected groups of events the
+    // longest path overall wins. Events caught in a dependency cycle have no path and are skipped.
+    fn calculate_critical_path(&self) -> Vec<Event> {
+        let index_of: HashMap<&str, usize> = self
+            .events
+            .iter()
+            .enumerate()
+            .map(|(i, dependency)| (dependency.event.unique_id.as_str(), i))
+            .collect();
+        let requires: Vec<Vec<usize>> = self
+            .events
+            .iter()
+            .map(|dependency| {
+                dependency
+                    .dependencies
+                    .iter()
+                    .filter_map(|required| index_of.get(required.unique_id.as_str()).copied())
+                    .collect()
+            })
+            .collect();
+        let mut waiting_on: Vec<usize> = requires.iter().map(Vec::len).collect();
+        let mut dependents = vec![Vec::new(); self.events.len()];
+        for (i, required) in requires.iter().enumerate() {
+            for &j in required {
+                dependents[j].push(i);
             }
-            if path_end_time > max_end_time {
-                max_end_time = path_end_time;
-                critical_path.clear();
-                critical_path.push(&dependency.event);
-            } else if path_end_time == max_end_time {
-                critical_path.push(&dependency.event);
+        }
+
+        // Heaviest chain ending at each event, and the event before it on that chain
+        let mut longest: Vec<Option<i32>> = vec![None; self.events.len()];
+        let mut previous: Vec<Option<usize>> = vec![None; self.events.len()];
+        let mut ready: Vec<usize> = (0..self.events.len())
+            .filter(|&i| waiting_on[i] == 0)
+            .collect();
+        while let Some(i) = ready.pop() {
+            let heaviest_dependency = requires[i]
+                .iter()
+                .filter_map(|&j| longest[j].map(|length| (length, j)))
+                .max_by_key(|&(length, _)| length);
+            let event = &self.events[i].event;
+            longest[i] =
+                Some(event.end - event.start + heaviest_dependency.map_or(0, |(length, _)| length));
+            previous[i] = heaviest_dependency.map(|(_, j)| j);
+            for &k in &dependents[i] {
+                waiting_on[k] -= 1;
+                if waiting_on[k] == 0 {
+                    ready.push(k);
+                }
             }
         }
 
+        let Some((_, sink)) = longest
+            .iter()
+            .enumerate()
+            .filter_map(|(i, length)| length.map(|length| (length, i)))
+            .max_by_key(|&(length, i)| (length, std::cmp::Reverse(i)))
+        else {
+            return Vec::new();
+        };
+        let mut critical_path = vec![self.events[sink].event.clone()];
+        let mut current = sink;
+        while let Some(before) = previous[current] {
+            critical_path.push(self.events[before].event.clone());
+            current = before;
+        }
+        critical_path.reverse();
         critical_path
     }
 }
 
-fn schedule_resources(events: Vec<Dependency>) -> HashMap<String, Vec<Event>> {
-    let mut events = events;
-    events.sort_by(|a, b| a.event.start.cmp(&b.event.start));
-    let mut schedules = HashMap::new();
-    let mut end_time = 0;
-
-    for event in events {
-        let mut can_schedule = true;
-        for dependency in &event.dependencies {
-            if dependency.end > event.event.start {
-                can_schedule = false;
-                break;
+#[derive(Debug, Error, PartialEq)]
+pub enum ScheduleError {
+    #[error("Dependency cycle between events: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+}
+
+// Places events in dependency order: an event is scheduled only once every event it depends on has
+// been placed, and never starts before the latest of them ends, being pushed back if necessary.
+// Events waiting on others stay pending instead of being dropped; among the events that are ready,
+// the earliest start goes first. Dependencies on events outside the itinerary count as met.
+fn schedule_resources(
+    events: Vec<Dependency>,
+) -> Result<HashMap<String, Vec<Event>>, ScheduleError> {
+    let index_of: HashMap<&str, usize> = events
+        .iter()
+        .enumerate()
+        .map(|(i, dependency)| (dependency.event.unique_id.as_str(), i))
+        .collect();
+    let mut waiting_on = vec![0; events.len()];
+    let mut dependents = vec![Vec::new(); events.len()];
+    for (i, dependency) in events.iter().enumerate() {
+        for required in &dependency.dependencies {
+            if let Some(&j) = index_of.get(required.unique_id.as_str()) {
+                waiting_on[i] += 1;
+                dependents[j].push(i);
             }
         }
-        if can_schedule {
-            let resource = event.event.resource.clone();
-            schedules
-                .entry(resource)
-                .or_insert(Vec::new())
-                .push(event.event.clone());
-            end_time = event.event.end;
-        } else {
-            let break_event = Event {
-                start: end_time,
-                end: event.event.start,
-                resource: "break".to_string(),
-                unique_id: "break".to_string(),
-                user_id: "".to_string(),
-                time: 0,
-                header: "".to_string(),
-                event_type: "".to_string(),
-                id: "".to_string(),
-                name: "break".to_string(),
-                location: "break".to_string(),
-                start_time: end_time,
-                end_time: event.event.start,
-                significance: 0,
-                attributes: HashMap::new(),
-                duration: event.event.start - end_time,
-                dependencies: vec![],
-                tags: vec![],
-            };
-            schedules
-                .entry(break_event.resource.clone())
-                .or_insert(Vec::new())
-                .push(break_event);
+    }
+
+    let mut ready: BinaryHeap<Reverse<(i32, usize)>> = (0..events.len())
+        .filter(|&i| waiting_on[i] == 0)
+        .map(|i| Reverse((events[i].event.start, i)))
+        .collect();
+    let mut placed: Vec<Option<Event>> = vec![None; events.len()];
+    let mut schedules: HashMap<String, Vec<Event>> = HashMap::new();
+    while let Some(Reverse((_, i))) = ready.pop() {
+        let mut event = events[i].event.clone();
+        let earliest_start = events[i]
+            .dependencies
+            .iter()
+            .filter_map(|required| index_of.get(required.unique_id.as_str()))
+            .filter_map(|&j| placed[j].as_ref().map(|e| e.end))
+            .max()
+            .unwrap_or(i32::MIN);
+        if event.start < earliest_start {
+            let delay = earliest_start - event.start;
+            event.start += delay;
+            event.end += delay;
+        }
+        schedules
+            .entry(event.resource.clone())
+            .or_default()
+            .push(event.clone());
+        placed[i] = Some(event);
+        for &k in &dependents[i] {
+            waiting_on[k] -= 1;
+            if waiting_on[k] == 0 {
+                ready.push(Reverse((events[k].event.start, k)));
+            }
         }
     }
 
-    schedules
+    if let Some(stuck) = (0..events.len()).find(|&i| placed[i].is_none()) {
+        return Err(ScheduleError::Cycle(find_cycle(
+            &events, &index_of, &placed, stuck,
+        )));
+    }
+    Ok(schedules)
+}
+
+// Walks unplaced dependencies from an event that never became ready. Every unplaced event waits on
+// another unplaced one, so the walk must revisit an event, and the loop from there is a cycle.
+fn find_cycle(
+    events: &[Dependency],
+    index_of: &HashMap<&str, usize>,
+    placed: &[Option<Event>],
+    start: usize,
+) -> Vec<String> {
+    let mut path = vec![start];
+    loop {
+        let current = *path.last().unwrap();
+        let next = events[current]
+            .dependencies
+            .iter()
+            .filter_map(|required| index_of.get(required.unique_id.as_str()).copied())
+            .find(|&j| placed[j].is_none())
+            .expect("an unplaced event waits on another unplaced event");
+        if let Some(position) = path.iter().position(|&i| i == next) {
+            let mut cycle: Vec<String> = path[position..]
+                .iter()
+                .map(|&i| events[i].event.unique_id.clone())
+                .collect();
+            cycle.push(events[next].event.unique_id.clone());
+            return cycle;
+        }
+        path.push(next);
+    }
+}
+
+// Builds an event occupying `resource` for the `start..end` slot
+fn schedule_event(resource: &str, start: i32, end: i32) -> Event {
+    EventBuilder::new(0, resource, EventType::ScheduledEvent)
+        .unique_id(resource)
+        .resource(resource)
+        .time_range(start as u64, end as u64)
+        .build()
+        .expect("schedule events have a valid time range")
 }
 
 pub fn generate_schedules() -> Result<(), Box<dyn std::error::Error>> {
     // Define the events
     let events = vec![
         Dependency {
-            event: Event {
-                start: 1,
-                end: 2,
-                resource: "A".to_string(),
-                unique_id: "A".to_string(),
-                user_id: "".to_string(),
-                time: 0,
-                header: "".to_string(),
-                event_type: "".to_string(),
-                id: "".to_string(),
-                name: "A".to_string(),
-                location: "A".to_string(),
-                start_time: 1,
-                end_time: 2,
-                significance: 0,
-                attributes: HashMap::new(),
-                duration: 1,
-                dependencies: vec![],
-                tags: vec![],
-            },
+            event: schedule_event("A", 1, 2),
             dependencies: vec![],
         },
         Dependency {
-            event: Event {
-                start: 2,
-                end: 4,
-                resource: "B".to_string(),
-                unique_id: "B".to_string(),
-                user_id: "".to_string(),
-                time: 0,
-                header: "".to_string(),
-                event_type: "".to_string(),
-                id: "".to_string(),
-                name: "B".to_string(),
-                location: "B".to_string(),
-                start_time: 2,
-                end_time: 4,
-                significance: 0,
-                attributes: HashMap::new(),
-                duration: 2,
-                dependencies: vec![],
-                tags: vec![],
-            },
+            event: schedule_event("B", 2, 4),
             dependencies: vec![],
         },
         Dependency {
-            event: Event {
-                start: 4,
-                end: 6,
-                resource: "C".to_string(),
-                unique_id: "C".to_string(),
-                user_id: "".to_string(),
-                time: 0,
-                header: "".to_string(),
-                event_type: "".to_string(),
-                id: "".to_string(),
-                name: "C".to_string(),
-                location: "C".to_string(),
-                start_time: 4,
-                end_time: 6,
-                significance: 0,
-                attributes: HashMap::new(),
-                duration: 2,
-                dependencies: vec![],
-                tags: vec![],
-            },
+            event: schedule_event("C", 4, 6),
             dependencies: vec![],
         },
         Dependency {
-            event: Event {
-                start: 3,
-                end: 5,
-                resource: "D".to_string(),
-                unique_id: "D".to_string(),
-                user_id: "".to_string(),
-                time: 0,
-                header: "".to_string(),
-                event_type: "".to_string(),
-                id: "".to_string(),
-                name: "D".to_string(),
-                location: "D".to_string(),
-                start_time: 3,
-                end_time: 5,
-                significance: 0,
-                attributes: HashMap::new(),
-                duration: 2,
-                dependencies: vec![],
-                tags: vec![],
-            },
+            event: schedule_event("D", 3, 5),
             dependencies: vec![],
         },
     ];
 
     // Create an itinerary from the events
-    let mut itinerary = Itinerary::new(events);
+    let mut itinerary = Itinerary::new(events)?;
 
     // Print the initial schedules
     println!("Initial schedules:");
@@ -302,29 +364,10 @@ pub fn generate_schedules() -> Result<(), Box<dyn std::error::Error>> {
 
     // Insert a new event into the itinerary
     let new_event = Dependency {
-        event: Event {
-            start: 7,
-            end: 8,
-            resource: "E".to_string(),
-            unique_id: "E".to_string(),
-            user_id: "".to_string(),
-            time: 0,
-            header: "".to_string(),
-            event_type: "".to_string(),
-            id: "".to_string(),
-            name: "E".to_string(),
-            location: "E".to_string(),
-            start_time: 7,
-            end_time: 8,
-            significance: 0,
-            attributes: HashMap::new(),
-            duration: 1,
-            dependencies: vec![],
-            tags: vec![],
-        },
+        event: schedule_event("E", 7, 8),
         dependencies: vec![],
     };
-    itinerary.insert_event(new_event);
+    itinerary.insert_event(new_event)?;
 
     // Print the updated schedules after inserting a new event
     println!("\nUpdated schedules after inserting a new event:");
@@ -332,36 +375,17 @@ pub fn generate_schedules() -> Result<(), Box<dyn std::error::Error>> {
 
     // Modify an existing event in the itinerary
     let modified_event = Dependency {
-        event: Event {
-            start: 2,
-            end: 3,
-            resource: "B".to_string(),
-            unique_id: "B".to_string(),
-            user_id: "".to_string(),
-            time: 0,
-            header: "".to_string(),
-            event_type: "".to_string(),
-            id: "".to_string(),
-            name: "B".to_string(),
-            location: "B".to_string(),
-            start_time: 2,
-            end_time: 3,
-            significance: 0,
-            attributes: HashMap::new(),
-            duration: 1,
-            dependencies: vec![],
-            tags: vec![],
-        },
+        event: schedule_event("B", 2, 3),
         dependencies: vec![],
     };
-    itinerary.modify_event(1, modified_event);
+    itinerary.modify_event(1, modified_event)?;
 
     // Print the updated schedules after modifying an event
     println!("\nUpdated schedules after modifying an event:");
     itinerary.print_schedules();
 
     // Remove an event from the itinerary
-    itinerary.remove_event(2);
+    itinerary.remove_event(2)?;
 
     // Print the updated schedules after removing an event
     println!("\nUpdated schedules after removing an event:");
@@ -389,7 +413,8 @@ pub fn generate_schedules() -> Result<(), Box<dyn std::error::Error>> {
     // Find free time slots for a resource
     let resource = "A";
     let min_duration = 2;
-    let free_time_slots = itinerary.find_free_time_slots(resource, min_duration);
+    let horizon = 10;
+    let free_time_slots = itinerary.find_free_time_slots(resource, min_duration, horizon);
     println!("\nFree time slots for resource '{}' with minimum duration {}:", resource, min_duration);
     for (start, end) in free_time_slots {
         println!(" Start: {}, End: {}", start, end);
@@ -421,3 +446,168 @@ pub fn generate_schedules() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An event named `id` booked on the shared "room" resource
+    fn room_event(id: &str, start: i32, end: i32) -> Event {
+        EventBuilder::new(0, id, EventType::ScheduledEvent)
+            .unique_id(id)
+            .resource("room")
+            .time_range(start as u64, end as u64)
+            .build()
+            .unwrap()
+    }
+
+    fn ids(events: &[Event]) -> Vec<&str> {
+        events
+            .iter()
+            .map(|event| event.unique_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_chain_is_scheduled_in_dependency_order() {
+        let a = room_event("A", 5, 6);
+        let b = room_event("B", 0, 2);
+        let c = room_event("C", 1, 2);
+        // Listed out of order, and with B and C starting before what they depend on has ended
+        let events = vec![
+            Dependency {
+                event: c,
+                dependencies: vec![b.clone()],
+            },
+            Dependency {
+                event: b,
+                dependencies: vec![a.clone()],
+            },
+            Dependency {
+                event: a,
+                dependencies: vec![],
+            },
+        ];
+
+        let schedules = schedule_resources(events).unwrap();
+        let room = &schedules["room"];
+        assert_eq!(ids(room), vec!["A", "B", "C"]);
+        assert_eq!(
+            room.iter()
+                .map(|event| (event.start, event.end))
+                .collect::<Vec<_>>(),
+            vec![(5, 6), (6, 8), (8, 9)]
+        );
+    }
+
+    #[test]
+    fn test_dependency_cycle_is_an_error() {
+        let a = room_event("A", 0, 1);
+        let b = room_event("B", 1, 2);
+        let events = vec![
+            Dependency {
+                event: a.clone(),
+                dependencies: vec![b.clone()],
+            },
+            Dependency {
+                event: b,
+                dependencies: vec![a],
+            },
+            Dependency {
+                event: room_event("free", 3, 4),
+                dependencies: vec![],
+            },
+        ];
+
+        match schedule_resources(events) {
+            Err(ScheduleError::Cycle(cycle)) => {
+                assert_eq!(cycle.len(), 3);
+                assert_eq!(cycle.first(), cycle.last());
+            }
+            other => panic!("expected a cycle error, got {:?}", other),
+        }
+    }
+
+    fn itinerary(events: Vec<Event>) -> Itinerary {
+        Itinerary::new(
+            events
+                .into_iter()
+                .map(|event| Dependency {
+                    event,
+                    dependencies: vec![],
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_free_slots_for_unsorted_events_include_the_tail() {
+        let itinerary = itinerary(vec![
+            room_event("late", 8, 9),
+            room_event("early", 1, 3),
+            room_event("middle", 4, 6),
+        ]);
+        assert_eq!(
+            itinerary.find_free_time_slots("room", 1, 12),
+            vec![(0, 1), (3, 4), (6, 8), (9, 12)]
+        );
+        assert_eq!(
+            itinerary.find_free_time_slots("room", 2, 12),
+            vec![(6, 8), (9, 12)]
+        );
+    }
+
+    #[test]
+    fn test_free_slots_skip_overlapping_bookings() {
+        // "long" covers 2..7, so the gap between "short" ending at 4 and "next" is not free
+        let itinerary = itinerary(vec![
+            room_event("long", 2, 7),
+            room_event("short", 3, 4),
+            room_event("next", 5, 8),
+        ]);
+        assert_eq!(
+            itinerary.find_free_time_slots("room", 1, 10),
+            vec![(0, 2), (8, 10)]
+        );
+        assert_eq!(itinerary.find_free_time_slots("room", 1, 6), vec![(0, 2)]);
+    }
+
+    #[test]
+    fn test_critical_path_takes_longer_branch_of_diamond() {
+        let start = room_event("start", 0, 1);
+        let short = room_event("short", 1, 2);
+        let long = room_event("long", 1, 5);
+        let finish = room_event("finish", 5, 6);
+        let diamond = Itinerary::new(vec![
+            Dependency {
+                event: finish,
+                dependencies: vec![short.clone(), long.clone()],
+            },
+            Dependency {
+                event: short,
+                dependencies: vec![start.clone()],
+            },
+            Dependency {
+                event: long,
+                dependencies: vec![start.clone()],
+            },
+            Dependency {
+                event: start,
+                dependencies: vec![],
+            },
+            // A separate, shorter chain
+            Dependency {
+                event: room_event("other", 0, 3),
+                dependencies: vec![],
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(
+            ids(&diamond.calculate_critical_path()),
+            vec!["start", "long", "finish"]
+        );
+        assert!(itinerary(vec![]).calculate_critical_path().is_empty());
+    }
+}
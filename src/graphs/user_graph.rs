@@ -2,6 +2,7 @@ use crate::iam::user::User;
 use crate::iam::group::Group;
 use crate::agents::q_learning_agent::QLearningAgent;
 use crate::messaging::message::Message;
+use crate::utils::privacy::{noisy_count, PrivacyBudget, PrivacyError};
 
 use std::fs::File;
 use std::io::BufReader;
@@ -20,6 +21,10 @@ pub struct UserGraph {
     pub nodes: Vec<Node>,
     pub users: Vec<User>,
     pub groups: Vec<Group>,
+    // Epsilon spent by aggregate queries against this graph. It is saved with the graph so reloading
+    // doesn't hand out a fresh budget; graphs saved without one get the default.
+    #[serde(default)]
+    privacy_budget: PrivacyBudget,
 }
 
 // Enum to represent different types of nodes in the user graph
@@ -73,6 +78,25 @@ impl UserGraph {
         Ok(user_graph)
     }
 
+    pub fn with_privacy_budget(mut self, total: f64) -> Self {
+        self.privacy_budget = PrivacyBudget::new(total);
+        self
+    }
+
+    pub fn privacy_budget(&self) -> &PrivacyBudget {
+        &self.privacy_budget
+    }
+
+    // Number of users matching `predicate`, with Laplace noise calibrated to `epsilon`, which is
+    // taken from the graph's privacy budget
+    pub fn count_with_noise<P>(&mut self, predicate: P, epsilon: f64) -> Result<f64, PrivacyError>
+    where
+        P: Fn(&User) -> bool,
+    {
+        let count = self.users.iter().filter(|user| predicate(user)).count();
+        noisy_count(&mut self.privacy_budget, count, epsilon)
+    }
+
     pub fn train_q_learning_agent(&mut self, num_iterations: usize) {
         let num_states = self.users.len();
         let num_actions = self.users.len();
@@ -107,7 +131,9 @@ impl UserGraph {
                     break;
                 }
 
-                let action = q_agent.choose_action(current_state, &valid_actions);
+                let Ok(action) = q_agent.choose_action(current_state, &valid_actions) else {
+                    break;
+                };
                 let next_state = action;
                 let reward = self.calculate_reward(current_state, next_state);
                 total_reward += reward;
@@ -227,7 +253,9 @@ pub fn run_reinforcement_learning(user_graph: &mut UserGraph) -> Result<(), std:
         // Define valid_actions
         let valid_actions: Vec<usize> = user_graph.nodes[agent.agent.state].edges.iter().map(|edge| edge.to).collect();
         
-        let action = agent.choose_action(agent.agent.state, &valid_actions);
+        let action = agent
+            .choose_action(agent.agent.state, &valid_actions)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
         let (next_state, reward) = simulate_action(user_graph, &agent, action);
         let feedback_text = read_message(user_graph, &agent, action);
         let feedback = process_feedback(&feedback_text);
@@ -298,4 +326,63 @@ impl QLearningAgent {
     fn reset_state(&mut self, initial_state: Option<usize>) {
         self.agent.state = initial_state.unwrap_or(0); // Reset state to the starting state or the provided initial state
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iam::user::UserBuilder;
+
+    fn graph() -> UserGraph {
+        let users = (0..100)
+            .map(|i| {
+                let status = if i < 30 { "active" } else { "idle" };
+                UserBuilder::new(format!("user-{}", i)).username(format!("{}-{}", status, i)).build()
+            })
+            .collect();
+        UserGraph {
+            nodes: vec![],
+            users,
+            groups: vec![],
+            privacy_budget: PrivacyBudget::default(),
+        }
+    }
+
+    #[test]
+    fn test_count_with_noise_adds_noise_and_spends_budget() {
+        let mut graph = graph().with_privacy_budget(5.0);
+        let is_active = |user: &User| user.username.starts_with("active");
+
+        let mut counts = Vec::new();
+        for query in 1..=10 {
+            counts.push(graph.count_with_noise(is_active, 0.5).unwrap());
+            assert!((graph.privacy_budget().remaining() - (5.0 - 0.5 * query as f64)).abs() < 1e-9);
+        }
+        // Noise has scale 2 here, so 40 away from the true count is practically impossible
+        assert!(counts.iter().all(|count| (count - 30.0).abs() < 40.0), "{:?}", counts);
+        assert!(counts.iter().any(|count| *count != 30.0), "{:?}", counts);
+
+        assert!(matches!(
+            graph.count_with_noise(is_active, 0.5),
+            Err(PrivacyError::BudgetExhausted { .. })
+        ));
+    }
+
+    #[test]
+    fn test_spent_budget_survives_save_and_load() {
+        let mut graph = UserGraph {
+            nodes: vec![],
+            users: vec![],
+            groups: vec![],
+            privacy_budget: PrivacyBudget::new(2.0),
+        };
+        graph.count_with_noise(|_| true, 1.5).unwrap();
+
+        let saved = serde_json::to_string(&graph).unwrap();
+        let mut reloaded: UserGraph = serde_json::from_str(&saved).unwrap();
+        assert_eq!(reloaded.privacy_budget(), graph.privacy_budget());
+        assert!(reloaded.count_with_noise(|_| true, 1.0).is_err());
+
+        let legacy: UserGraph = serde_json::from_str(r#"{"nodes": [], "users": [], "groups": []}"#).unwrap();
+        assert_eq!(legacy.privacy_budget(), &PrivacyBudget::default());
+    }
+}
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use serde::de::DeserializeOwned;
+use async_trait::async_trait;
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH};
+use thiserror::Error;
 
 use crate::iam::iam::CredentialProof;
 
@@ -19,24 +22,57 @@ pub struct DID {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct VerificationMethod {
     pub id: String,
+    #[serde(rename = "type")]
     pub type_: String,
     pub controller: String,
+    #[serde(rename = "publicKeyBase58")]
     pub public_key_base58: String,
 }
 
+// The ed25519 verification method type whose key is published as `publicKeyBase58`
+pub const ED25519_VERIFICATION_KEY_2018: &str = "Ed25519VerificationKey2018";
+
 impl VerificationMethod {
+    // Publishes an ed25519 public key as a verification method of `controller`
+    pub fn ed25519(id: String, controller: String, key: &VerifyingKey) -> Self {
+        Self {
+            id,
+            type_: ED25519_VERIFICATION_KEY_2018.to_string(),
+            controller,
+            public_key_base58: bs58::encode(key.as_bytes()).into_string(),
+        }
+    }
+
+    // Checks a detached signature against the method's public key. Unsupported key types,
+    // malformed keys and malformed signatures all fail verification.
     pub fn verify(&self, signature: &[u8], data: &[u8]) -> bool {
-        // Verify the signature using the public key
-        // Implementation depends on the specific verification method type
-        // Here's a dummy implementation that always returns true
-        true
+        if self.type_ != ED25519_VERIFICATION_KEY_2018 {
+            return false;
+        }
+        let key = match bs58::decode(&self.public_key_base58).into_vec() {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        let key = match <[u8; PUBLIC_KEY_LENGTH]>::try_from(key.as_slice()) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        match (
+            VerifyingKey::from_bytes(&key),
+            Signature::from_slice(signature),
+        ) {
+            (Ok(key), Ok(signature)) => key.verify_strict(data, &signature).is_ok(),
+            _ => false,
+        }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Service {
     pub id: String,
+    #[serde(rename = "type")]
     pub type_: String,
+    #[serde(rename = "serviceEndpoint")]
     pub service_endpoint: String,
 }
 
@@ -68,23 +104,160 @@ impl DID {
 
         (did, doc)
     }
+
+    // Creates a `did:key` DID for an ed25519 public key. The document is derived from the key alone.
+    pub fn from_ed25519(key: &VerifyingKey) -> (Self, DIDDocument) {
+        let mut multicodec = ED25519_MULTICODEC_PREFIX.to_vec();
+        multicodec.extend_from_slice(key.as_bytes());
+        let fingerprint = format!("z{}", bs58::encode(multicodec).into_string());
+        let did = format!("did:key:{}", fingerprint);
+
+        let method =
+            VerificationMethod::ed25519(format!("{}#{}", did, fingerprint), did.clone(), key);
+        let did = Self {
+            did,
+            authentication: vec![method.id.clone()],
+            assertion_method: vec![method.id.clone()],
+            capability_invocation: vec![method.id.clone()],
+            capability_delegation: vec![method.id.clone()],
+            verification_methods: vec![method],
+            key_agreement: vec![],
+            service: vec![],
+        };
+        let doc = resolve(did.clone());
+        (did, doc)
+    }
+}
+
+// Multicodec header identifying an ed25519 public key inside a `did:key` fingerprint
+const ED25519_MULTICODEC_PREFIX: [u8; 2] = [0xed, 0x01];
+
+#[derive(Error, Debug)]
+pub enum DidError {
+    #[error("Unsupported DID method for {0}")]
+    UnsupportedMethod(String),
+    #[error("Malformed DID {did}: {reason}")]
+    InvalidDid { did: String, reason: String },
+    #[error("Failed to fetch DID document for {did}: {reason}")]
+    FetchFailed { did: String, reason: String },
+}
+
+// Resolves a DID to its document. Implementations cover one DID method each.
+#[async_trait]
+pub trait DidResolver: Send + Sync {
+    async fn resolve(&self, did: &str) -> Result<DIDDocument, DidError>;
+}
+
+// Resolves `did:key` DIDs locally from the ed25519 key embedded in the identifier
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyDidResolver;
+
+#[async_trait]
+impl DidResolver for KeyDidResolver {
+    async fn resolve(&self, did: &str) -> Result<DIDDocument, DidError> {
+        let invalid = |reason: &str| DidError::InvalidDid {
+            did: did.to_string(),
+            reason: reason.to_string(),
+        };
+        let fingerprint = did
+            .strip_prefix("did:key:")
+            .ok_or_else(|| DidError::UnsupportedMethod(did.to_string()))?;
+        let encoded = fingerprint
+            .strip_prefix('z')
+            .ok_or_else(|| invalid("fingerprint is not base58btc multibase"))?;
+        let decoded = bs58::decode(encoded)
+            .into_vec()
+            .map_err(|e| invalid(&e.to_string()))?;
+        let key = decoded
+            .strip_prefix(&ED25519_MULTICODEC_PREFIX[..])
+            .ok_or_else(|| invalid("only ed25519 keys are supported"))?;
+        let key =
+            <[u8; PUBLIC_KEY_LENGTH]>::try_from(key).map_err(|_| invalid("wrong key length"))?;
+        let key = VerifyingKey::from_bytes(&key).map_err(|e| invalid(&e.to_string()))?;
+        Ok(DID::from_ed25519(&key).1)
+    }
+}
+
+// Resolves `did:web` DIDs by fetching `did.json` over HTTPS from the domain in the identifier
+#[derive(Debug, Clone, Default)]
+pub struct WebDidResolver {
+    client: reqwest::Client,
+}
+
+impl WebDidResolver {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+
+    // Maps `did:web:example.com:users:alice` to `https://example.com/users/alice/did.json`,
+    // and a bare domain to its `/.well-known/did.json`
+    pub fn document_url(did: &str) -> Result<String, DidError> {
+        let identifier = did
+            .strip_prefix("did:web:")
+            .ok_or_else(|| DidError::UnsupportedMethod(did.to_string()))?;
+        let mut segments = identifier.split(':');
+        let domain = segments.next().unwrap_or_default().replace("%3A", ":");
+        let path: Vec<&str> = segments.collect();
+        if domain.is_empty() || path.iter().any(|segment| segment.is_empty()) {
+            return Err(DidError::InvalidDid {
+                did: did.to_string(),
+                reason: "empty domain or path segment".to_string(),
+            });
+        }
+        Ok(if path.is_empty() {
+            format!("https://{}/.well-known/did.json", domain)
+        } else {
+            format!("https://{}/{}/did.json", domain, path.join("/"))
+        })
+    }
+}
+
+#[async_trait]
+impl DidResolver for WebDidResolver {
+    async fn resolve(&self, did: &str) -> Result<DIDDocument, DidError> {
+        let url = Self::document_url(did)?;
+        let fetch_failed = |reason: String| DidError::FetchFailed {
+            did: did.to_string(),
+            reason,
+        };
+        let document: DIDDocument = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| fetch_failed(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| fetch_failed(e.to_string()))?;
+        if document.id != did {
+            return Err(fetch_failed(format!(
+                "document at {} is for {}",
+                url, document.id
+            )));
+        }
+        Ok(document)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DIDDocument {
+    #[serde(rename = "@context", default)]
     pub context: Vec<String>,
     pub id: String,
-    #[serde(rename = "verificationMethod")]
+    #[serde(rename = "verificationMethod", default)]
     pub verification_method: Vec<VerificationMethod>,
+    #[serde(default)]
     pub authentication: Vec<String>,
-    #[serde(rename = "keyAgreement")]
+    #[serde(rename = "keyAgreement", default)]
     pub key_agreement: Vec<String>,
-    #[serde(rename = "assertionMethod")]
+    #[serde(rename = "assertionMethod", default)]
     pub assertion_method: Vec<String>,
-    #[serde(rename = "capabilityInvocation")]
+    #[serde(rename = "capabilityInvocation", default)]
     pub capability_invocation: Vec<String>,
-    #[serde(rename = "capabilityDelegation")]
+    #[serde(rename = "capabilityDelegation", default)]
     pub capability_delegation: Vec<String>,
+    #[serde(default)]
     pub service: Vec<Service>,
 }
 
@@ -165,3 +338,47 @@ impl SigningKey {
         vec![0x01, 0x02, 0x03]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[tokio::test]
+    async fn test_key_did_resolves_to_its_embedded_key() {
+        let key = SigningKey::from_bytes(&[5u8; 32]);
+        let (did, _) = DID::from_ed25519(&key.verifying_key());
+        assert!(did.did.starts_with("did:key:z6Mk"));
+
+        let document = KeyDidResolver.resolve(&did.did).await.unwrap();
+        assert_eq!(document.id, did.did);
+        let signature = key.sign(b"payload").to_vec();
+        assert!(document
+            .verification_method
+            .iter()
+            .any(|m| m.verify(&signature, b"payload")));
+
+        assert!(matches!(
+            KeyDidResolver.resolve("did:web:example.com").await,
+            Err(DidError::UnsupportedMethod(_))
+        ));
+        assert!(matches!(
+            KeyDidResolver.resolve("did:key:z6MkInvalid0").await,
+            Err(DidError::InvalidDid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_web_did_document_urls() {
+        assert_eq!(
+            WebDidResolver::document_url("did:web:example.com").unwrap(),
+            "https://example.com/.well-known/did.json"
+        );
+        assert_eq!(
+            WebDidResolver::document_url("did:web:example.com%3A8443:users:alice").unwrap(),
+            "https://example.com:8443/users/alice/did.json"
+        );
+        assert!(WebDidResolver::document_url("did:web:example.com::alice").is_err());
+        assert!(WebDidResolver::document_url("did:key:z6Mk").is_err());
+    }
+}
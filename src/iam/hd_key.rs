@@ -0,0 +1,215 @@
+//! # Hierarchical Deterministic Keys
+//!
+//! BIP32-style key derivation over secp256k1, used by `Wallet` to derive reproducible
+//! addresses from a single master seed.
+//!
+//! A child private key is derived as `k_i = parse256(IL) + k_par (mod n)` where
+//! `IL || IR = HMAC-SHA512(c_par, data)` and `IR` becomes the child chain code. For a hardened index
+//! `data` is `0x00 || k_par || ser32(i)`; for a normal index it is `serP(K_par) || ser32(i)`, the
+//! compressed parent public key. Following BIP44, the account levels of a path are hardened and
+//! address indices below them are normal.
+
+use crate::utils::bigboterror::BigbotError;
+
+use hmac::{Hmac, Mac};
+use secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey};
+use sha2::Sha512;
+use sha3::{Digest, Keccak256};
+use std::fmt;
+use web3::types::Address;
+
+// Offset marking an index as hardened
+pub const HARDENED_OFFSET: u32 = 0x8000_0000;
+
+// BIP44 external chain of the first Ethereum account; address indices are derived below it
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/60'/0'/0";
+
+const MASTER_HMAC_KEY: &[u8] = b"Bitcoin seed";
+
+#[derive(Clone)]
+pub struct ExtendedKey {
+    secret_key: SecretKey,
+    chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    // Derives the master key from a seed of 16 to 64 bytes
+    pub fn from_seed(seed: &[u8]) -> Result<Self, BigbotError> {
+        if !(16..=64).contains(&seed.len()) {
+            return Err(BigbotError::InvalidInput(format!(
+                "HD seed must be between 16 and 64 bytes, got {}",
+                seed.len()
+            )));
+        }
+        let (il, ir) = hmac_sha512(MASTER_HMAC_KEY, &[seed])?;
+        let secret_key = SecretKey::from_slice(&il)
+            .map_err(|_| BigbotError::InvalidInput("Seed produced an invalid master key".into()))?;
+        Ok(Self {
+            secret_key,
+            chain_code: ir,
+        })
+    }
+
+    // Derives the hardened child at `index` (the hardened offset is applied here)
+    pub fn derive_hardened(&self, index: u32) -> Result<Self, BigbotError> {
+        if index >= HARDENED_OFFSET {
+            return Err(BigbotError::InvalidInput(format!("Child index out of range: {}", index)));
+        }
+        self.derive_child(index | HARDENED_OFFSET)
+    }
+
+    // Derives the normal (non-hardened) child at `index`, as used for address indices
+    pub fn derive_normal(&self, index: u32) -> Result<Self, BigbotError> {
+        if index >= HARDENED_OFFSET {
+            return Err(BigbotError::InvalidInput(format!("Child index out of range: {}", index)));
+        }
+        self.derive_child(index)
+    }
+
+    // `index` already carries the hardened offset if it is hardened
+    fn derive_child(&self, index: u32) -> Result<Self, BigbotError> {
+        let (il, ir) = if index >= HARDENED_OFFSET {
+            hmac_sha512(
+                &self.chain_code,
+                &[&[0u8], &self.secret_key.secret_bytes(), &index.to_be_bytes()],
+            )?
+        } else {
+            let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &self.secret_key);
+            hmac_sha512(&self.chain_code, &[&public_key.serialize(), &index.to_be_bytes()])?
+        };
+        let tweak = Scalar::from_be_bytes(il)
+            .map_err(|_| BigbotError::InvalidInput(format!("Invalid child key at index {}", index)))?;
+        let secret_key = self
+            .secret_key
+            .add_tweak(&tweak)
+            .map_err(|_| BigbotError::InvalidInput(format!("Invalid child key at index {}", index)))?;
+        Ok(Self {
+            secret_key,
+            chain_code: ir,
+        })
+    }
+
+    // Derives along a path such as `m/44'/60'/0'/0`, where a trailing `'` marks a hardened index
+    pub fn derive_path(&self, path: &str) -> Result<Self, BigbotError> {
+        let mut components = path.split('/');
+        if components.next() != Some("m") {
+            return Err(BigbotError::InvalidInput(format!("Derivation path must start with 'm': {}", path)));
+        }
+        components.try_fold(self.clone(), |key, component| {
+            let (index, hardened) = match component.strip_suffix('\'') {
+                Some(index) => (index, true),
+                None => (component, false),
+            };
+            let index = index.parse::<u32>().map_err(|_| {
+                BigbotError::InvalidInput(format!("Unsupported path component '{}' in {}", component, path))
+            })?;
+            if hardened {
+                key.derive_hardened(index)
+            } else {
+                key.derive_normal(index)
+            }
+        })
+    }
+
+    pub fn secret_key(&self) -> &SecretKey {
+        &self.secret_key
+    }
+
+    // Ethereum address: the last 20 bytes of the Keccak-256 hash of the uncompressed public key
+    pub fn address(&self) -> Address {
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &self.secret_key);
+        let hash = Keccak256::digest(&public_key.serialize_uncompressed()[1..]);
+        Address::from_slice(&hash[12..])
+    }
+}
+
+// Keeps key material out of logs
+impl fmt::Debug for ExtendedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ExtendedKey(..)")
+    }
+}
+
+// The seed an `ExtendedKey` master was derived from, kept so it can be backed up
+#[derive(Clone, PartialEq, Eq)]
+pub struct MasterSeed(Vec<u8>);
+
+impl MasterSeed {
+    pub fn new(seed: &[u8]) -> Self {
+        Self(seed.to_vec())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for MasterSeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MasterSeed(..)")
+    }
+}
+
+fn hmac_sha512(key: &[u8], parts: &[&[u8]]) -> Result<([u8; 32], [u8; 32]), BigbotError> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key)
+        .map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+    for part in parts {
+        mac.update(part);
+    }
+    let output = mac.finalize().into_bytes();
+    let mut il = [0u8; 32];
+    let mut ir = [0u8; 32];
+    il.copy_from_slice(&output[..32]);
+    ir.copy_from_slice(&output[32..]);
+    Ok((il, ir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derivation_is_deterministic_and_index_sensitive() {
+        let master = ExtendedKey::from_seed(&[7u8; 32]).unwrap();
+        let account = master.derive_path(DEFAULT_DERIVATION_PATH).unwrap();
+
+        let first = account.derive_normal(0).unwrap().address();
+        let again = ExtendedKey::from_seed(&[7u8; 32])
+            .unwrap()
+            .derive_path(DEFAULT_DERIVATION_PATH)
+            .unwrap()
+            .derive_normal(0)
+            .unwrap()
+            .address();
+        let second = account.derive_normal(1).unwrap().address();
+
+        assert_eq!(first, again);
+        assert_ne!(first, second);
+    }
+
+    // BIP32 test vector 1, chain m/0'/1
+    #[test]
+    fn test_matches_bip32_vector_for_hardened_and_normal_children() {
+        let seed: Vec<u8> = (0u8..16).collect();
+        let master = ExtendedKey::from_seed(&seed).unwrap();
+        let hardened = master.derive_path("m/0'").unwrap();
+        assert_eq!(
+            hardened.secret_key().display_secret().to_string(),
+            "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea"
+        );
+        let normal = master.derive_path("m/0'/1").unwrap();
+        assert_eq!(
+            normal.secret_key().display_secret().to_string(),
+            "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368"
+        );
+    }
+
+    #[test]
+    fn test_rejects_short_seed_and_malformed_path() {
+        assert!(ExtendedKey::from_seed(&[1u8; 8]).is_err());
+        let master = ExtendedKey::from_seed(&[1u8; 16]).unwrap();
+        assert!(master.derive_path("44'/60'/0'").is_err());
+        assert!(master.derive_path("m/44'/sixty'/0'").is_err());
+        assert!(master.derive_path("m/2147483648").is_err());
+    }
+}
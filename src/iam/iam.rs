@@ -1,12 +1,17 @@
+use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use web3::types::Address;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
 
 use crate::iam::jwt::{sign_credential_with_wallet, verify_credential_with_wallet};
 use crate::iam::keycloak_provider::UserRepresentation;
-use crate::iam::wallet::{Wallet, WalletAddress};
-use crate::iam::did::VerifiableCredential;
+use crate::iam::session::{SessionAuthority, TokenGrant};
+use crate::iam::user::{User, UserBuilder};
+use crate::iam::wallet::{Wallet, WalletStore};
+use crate::iam::did::{DidResolver, VerifiableCredential};
 use crate::utils::bigboterror::BigbotError;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +27,27 @@ struct Token {
     created_at: u64,
 }
 
+// Admin tokens are refreshed this long before Keycloak would expire them
+const ADMIN_TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+struct AdminTokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Debug, Clone)]
+struct AdminToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+impl AdminToken {
+    fn is_fresh(&self) -> bool {
+        Instant::now() + ADMIN_TOKEN_REFRESH_MARGIN < self.expires_at
+    }
+}
+
 #[derive(Debug, Clone)]
 struct KeycloakAdmin {
     client: Client,
@@ -31,6 +57,8 @@ struct KeycloakAdmin {
     client_secret: String,
     admin_username: String,
     admin_password: String,
+    // Shared by clones so every handle reuses the same token
+    admin_token: Arc<RwLock<Option<AdminToken>>>,
 }
 
 impl KeycloakAdmin {
@@ -50,39 +78,102 @@ impl KeycloakAdmin {
             client_secret: client_secret.to_string(),
             admin_username: admin_username.to_string(),
             admin_password: admin_password.to_string(),
+            admin_token: Arc::new(RwLock::new(None)),
         }
     }
 
+    // Returns a cached admin access token, requesting a new one when it is missing or about to
+    // expire. Uses the admin password grant when admin credentials are configured and the
+    // client credentials grant otherwise.
+    async fn admin_token(&self) -> Result<String, BigbotError> {
+        if let Some(token) = self
+            .admin_token
+            .read()
+            .await
+            .as_ref()
+            .filter(|token| token.is_fresh())
+        {
+            return Ok(token.access_token.clone());
+        }
+
+        let mut cached = self.admin_token.write().await;
+        // Another caller may have refreshed the token while we waited for the lock
+        if let Some(token) = cached.as_ref().filter(|token| token.is_fresh()) {
+            return Ok(token.access_token.clone());
+        }
+
+        let url = format!(
+            "{}/realms/{}/protocol/openid-connect/token",
+            self.base_url, self.realm_name
+        );
+        let mut params = vec![
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+        ];
+        if self.admin_username.is_empty() {
+            params.push(("grant_type", "client_credentials"));
+        } else {
+            params.push(("grant_type", "password"));
+            params.push(("username", self.admin_username.as_str()));
+            params.push(("password", self.admin_password.as_str()));
+        }
+        let response = self
+            .client
+            .post(&url)
+            .form(&params)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                BigbotError::AuthenticationError(format!("Failed to obtain admin token: {}", e))
+            })?;
+        let token: AdminTokenResponse = response.json().await.map_err(|e| {
+            BigbotError::AuthenticationError(format!("Failed to parse admin token: {}", e))
+        })?;
+
+        let access_token = token.access_token.clone();
+        *cached = Some(AdminToken {
+            access_token: token.access_token,
+            expires_at: Instant::now() + Duration::from_secs(token.expires_in),
+        });
+        Ok(access_token)
+    }
+
     async fn get_users(&self) -> Result<Vec<KeycloakUserModel>, BigbotError> {
         let url = format!("{}/admin/realms/{}/users", self.base_url, self.realm_name);
-        let response = self.client.get(&url).send().await.map_err(|e| BigbotError::UserGetError(e.to_string()))?;
+        let token = self.admin_token().await?;
+        let response = self.client.get(&url).bearer_auth(token).send().await.map_err(|e| BigbotError::UserGetError(e.to_string()))?;
         let users: Vec<KeycloakUserModel> = response.json().await.map_err(|e| BigbotError::UserGetError(e.to_string()))?;
         Ok(users)
     }
 
-    async fn create_user(&self, user: UserRepresentation) -> Result<KeycloakUserModel, reqwest::Error> {
+    async fn create_user(&self, user: UserRepresentation) -> Result<KeycloakUserModel, BigbotError> {
         let url = format!("{}/admin/realms/{}/users", self.base_url, self.realm_name);
-        let response = self.client.post(&url).json(&user).send().await?;
-        let created_user: KeycloakUserModel = response.json().await?;
+        let token = self.admin_token().await?;
+        let response = self.client.post(&url).bearer_auth(token).json(&user).send().await.map_err(|e| BigbotError::UserCreateError(e.to_string()))?;
+        let created_user: KeycloakUserModel = response.json().await.map_err(|e| BigbotError::UserCreateError(e.to_string()))?;
         Ok(created_user)
     }
 
-    async fn update_user(&self, user: UserRepresentation) -> Result<KeycloakUserModel, reqwest::Error> {
+    async fn update_user(&self, user: UserRepresentation) -> Result<KeycloakUserModel, BigbotError> {
         let url = format!("{}/admin/realms/{}/users/{}", self.base_url, self.realm_name, user.id);
-        let response = self.client.put(&url).json(&user).send().await?;
-        let updated_user: KeycloakUserModel = response.json().await?;
+        let token = self.admin_token().await?;
+        let response = self.client.put(&url).bearer_auth(token).json(&user).send().await.map_err(|e| BigbotError::UserUpdateError(e.to_string()))?;
+        let updated_user: KeycloakUserModel = response.json().await.map_err(|e| BigbotError::UserUpdateError(e.to_string()))?;
         Ok(updated_user)
     }
 
-    async fn delete_user(&self, username: &str) -> Result<bool, reqwest::Error> {
+    async fn delete_user(&self, username: &str) -> Result<bool, BigbotError> {
         let url = format!("{}/admin/realms/{}/users/{}", self.base_url, self.realm_name, username);
-        let response = self.client.delete(&url).send().await?;
+        let token = self.admin_token().await?;
+        let response = self.client.delete(&url).bearer_auth(token).send().await.map_err(|e| BigbotError::UserDeleteError(e.to_string()))?;
         Ok(response.status().is_success())
     }
 
     async fn get_user(&self, user_id: &str) -> Result<UserRepresentation, BigbotError> {
         let url = format!("{}/admin/realms/{}/users/{}", self.base_url, self.realm_name, user_id);
-        let response = self.client.get(&url).send().await.map_err(|e| BigbotError::UserGetError(e.to_string()))?;
+        let token = self.admin_token().await?;
+        let response = self.client.get(&url).bearer_auth(token).send().await.map_err(|e| BigbotError::UserGetError(e.to_string()))?;
         let user: UserRepresentation = response.json().await.map_err(|e| BigbotError::UserGetError(e.to_string()))?;
         Ok(user)
     }
@@ -90,6 +181,8 @@ impl KeycloakAdmin {
 
 struct KeycloakController {
     keycloak_admin: KeycloakAdmin,
+    wallet_store: Arc<WalletStore>,
+    did_resolver: Arc<dyn DidResolver>,
 }
 
 impl KeycloakController {
@@ -100,6 +193,8 @@ impl KeycloakController {
         client_secret: &str,
         admin_username: &str,
         admin_password: &str,
+        wallet_store: Arc<WalletStore>,
+        did_resolver: Arc<dyn DidResolver>,
     ) -> Self {
         let keycloak_admin = KeycloakAdmin::new(
             base_url,
@@ -109,7 +204,11 @@ impl KeycloakController {
             admin_username,
             admin_password,
         );
-        KeycloakController { keycloak_admin }
+        KeycloakController {
+            keycloak_admin,
+            wallet_store,
+            did_resolver,
+        }
     }
 
     async fn issue_credential(
@@ -134,7 +233,7 @@ impl KeycloakController {
     ) -> Result<bool, BigbotError> {
         let issuer_wallet = self.get_user_wallet(&credential.issuer).await?;
     
-        let is_valid = verify_credential_with_wallet(&credential, &issuer_wallet)
+        let is_valid = verify_credential_with_wallet(&credential, &issuer_wallet, self.did_resolver.as_ref())
             .await
             .map_err(BigbotError::CredentialVerificationError)?;
     
@@ -142,36 +241,11 @@ impl KeycloakController {
     }
 
     async fn create_user_wallet(&self, user_id: &str) -> Result<Wallet, BigbotError> {
-        let wallet = Wallet::new_wallet().await;
-
-        let mut user_representation = self.keycloak_admin.get_user(user_id).await.map_err(|e| BigbotError::UserGetError(e.to_string()))?;
-        user_representation.attributes.insert("wallet_id".to_string(), vec![wallet.id.clone()]);
-        user_representation.attributes.insert("wallet_public_key".to_string(), vec![wallet.public_key.clone()]);
-        self.keycloak_admin.update_user(user_representation).await.map_err(|e| BigbotError::UserUpdateError(e.to_string()))?;
-
-        Ok(wallet)
+        provision_wallet(&self.keycloak_admin, &self.wallet_store, user_id).await
     }
 
     async fn get_user_wallet(&self, user_id: &str) -> Result<Wallet, BigbotError> {
-        let user_representation = self.keycloak_admin.get_user(user_id).await?;
-        let wallet_id = user_representation.attributes.get("wallet_id").and_then(|v| v.first().cloned());
-        let wallet_public_key = user_representation.attributes.get("wallet_public_key").and_then(|v| v.first().cloned());
-    
-        match (wallet_id, wallet_public_key) {
-            (Some(id), Some(public_key)) => Ok(Wallet {
-                id,
-                public_key,
-                did: "".to_string(),
-                identity_doc: "".to_string(),
-                credentials: HashMap::new(),
-                keys: vec![],
-                addresses: vec![],
-                preferred_address: WalletAddress(Address::default()),
-                base_currency: "".to_string(),
-                payment_thresholds: HashMap::new(),
-            }),
-            _ => Err(BigbotError::WalletNotFound),
-        }
+        load_wallet(&self.keycloak_admin, &self.wallet_store, user_id).await
     }
 
     async fn openid_token(
@@ -203,18 +277,129 @@ impl KeycloakController {
         Ok(user)
     }
 
-    async fn logout(&self, token: &Token) -> Result<bool, BigbotError> {
-        let url = format!("{}/realms/{}/protocol/openid-connect/logout", self.keycloak_admin.base_url, self.keycloak_admin.realm_name);
+    async fn refresh_token(&self, refresh_token: &str) -> Result<Token, BigbotError> {
+        let url = format!("{}/realms/{}/protocol/openid-connect/token", self.keycloak_admin.base_url, self.keycloak_admin.realm_name);
         let params = [
+            ("grant_type", "refresh_token"),
             ("client_id", &self.keycloak_admin.client_id),
             ("client_secret", &self.keycloak_admin.client_secret),
-            ("refresh_token", &token.refresh_token),
+            ("refresh_token", refresh_token),
+        ];
+        let response = self.keycloak_admin.client.post(&url).form(&params).send().await.map_err(BigbotError::OpenIDTokenError)?;
+        if !response.status().is_success() {
+            return Err(BigbotError::AuthenticationError("Refresh token rejected".to_string()));
+        }
+        let token: Token = response.json().await.map_err(BigbotError::OpenIDTokenError)?;
+        Ok(token)
+    }
+
+    async fn logout(&self, token: &Token) -> Result<bool, BigbotError> {
+        self.logout_refresh_token(&token.refresh_token).await
+    }
+
+    async fn logout_refresh_token(&self, refresh_token: &str) -> Result<bool, BigbotError> {
+        let url = format!("{}/realms/{}/protocol/openid-connect/logout", self.keycloak_admin.base_url, self.keycloak_admin.realm_name);
+        let params = [
+            ("client_id", self.keycloak_admin.client_id.as_str()),
+            ("client_secret", self.keycloak_admin.client_secret.as_str()),
+            ("refresh_token", refresh_token),
         ];
         let response = self.keycloak_admin.client.post(&url).form(&params).send().await.map_err(BigbotError::LogoutError)?;
         Ok(response.status().is_success())
     }
 }
 
+// Persists a new wallet and records its id on the Keycloak user. The wallet itself,
+// including its signing keys, lives encrypted in the wallet store.
+async fn provision_wallet(
+    admin: &KeycloakAdmin,
+    wallet_store: &WalletStore,
+    user_id: &str,
+) -> Result<Wallet, BigbotError> {
+    let wallet = wallet_store.create_wallet().await?;
+
+    let mut user_representation = admin
+        .get_user(user_id)
+        .await
+        .map_err(|e| BigbotError::UserGetError(e.to_string()))?;
+    set_wallet_id(&mut user_representation, &wallet.id);
+    admin
+        .update_user(user_representation)
+        .await
+        .map_err(|e| BigbotError::UserUpdateError(e.to_string()))?;
+
+    Ok(wallet)
+}
+
+// Loads the wallet the Keycloak user points at
+async fn load_wallet(
+    admin: &KeycloakAdmin,
+    wallet_store: &WalletStore,
+    user_id: &str,
+) -> Result<Wallet, BigbotError> {
+    let user_representation = admin
+        .get_user(user_id)
+        .await
+        .map_err(|e| BigbotError::UserGetError(e.to_string()))?;
+    load_user_wallet(wallet_store, &user_representation).await
+}
+
+fn set_wallet_id(user_representation: &mut UserRepresentation, wallet_id: &str) {
+    user_representation
+        .attributes
+        .insert("wallet_id".to_string(), vec![wallet_id.to_string()]);
+}
+
+async fn load_user_wallet(
+    wallet_store: &WalletStore,
+    user_representation: &UserRepresentation,
+) -> Result<Wallet, BigbotError> {
+    match user_representation
+        .attributes
+        .get("wallet_id")
+        .and_then(|v| v.first())
+    {
+        Some(id) => wallet_store.load(id).await,
+        None => Err(BigbotError::WalletNotFound),
+    }
+}
+
+impl From<Token> for TokenGrant {
+    fn from(token: Token) -> Self {
+        TokenGrant {
+            access_token: token.access_token,
+            refresh_token: token.refresh_token,
+            expires_in: token.expires_in,
+            refresh_expires_in: token.refresh_expires_in,
+        }
+    }
+}
+
+#[async_trait]
+impl SessionAuthority for KeycloakController {
+    async fn refresh(&self, refresh_token: &str) -> Result<TokenGrant, BigbotError> {
+        Ok(self.refresh_token(refresh_token).await?.into())
+    }
+
+    async fn revoke(&self, refresh_token: &str) -> Result<(), BigbotError> {
+        if self.logout_refresh_token(refresh_token).await? {
+            Ok(())
+        } else {
+            Err(BigbotError::AuthenticationError("Failed to revoke refresh token".to_string()))
+        }
+    }
+
+    async fn user(&self, user_id: &str) -> Result<User, BigbotError> {
+        let representation = self.keycloak_admin.get_user(user_id).await?;
+        let wallet = self.get_user_wallet(user_id).await?;
+        Ok(UserBuilder::new(representation.id)
+            .username(representation.username)
+            .email(representation.email)
+            .wallet(wallet)
+            .build())
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), BigbotError> {
     // ... (existing code)
@@ -223,12 +408,20 @@ async fn main() -> Result<(), BigbotError> {
 
 struct KeycloakUserManager {
     keycloak_admin: KeycloakAdmin,
+    wallet_store: Arc<WalletStore>,
+    did_resolver: Arc<dyn DidResolver>,
 }
 
 impl KeycloakUserManager {
-    fn new(keycloak_admin: &KeycloakAdmin) -> Self {
+    fn new(
+        keycloak_admin: &KeycloakAdmin,
+        wallet_store: Arc<WalletStore>,
+        did_resolver: Arc<dyn DidResolver>,
+    ) -> Self {
         KeycloakUserManager {
             keycloak_admin: keycloak_admin.clone(),
+            wallet_store,
+            did_resolver,
         }
     }
 
@@ -260,8 +453,7 @@ impl KeycloakUserManager {
         let created_user = self
             .keycloak_admin
             .create_user(user_representation)
-            .await
-            .map_err(|e| BigbotError::UserCreateError(e.to_string()))?;
+            .await?;
         Ok(created_user)
     }
 
@@ -273,8 +465,7 @@ impl KeycloakUserManager {
         let updated_user = self
             .keycloak_admin
             .update_user(user_representation)
-            .await
-            .map_err(|e| BigbotError::UserUpdateError(e.to_string()))?;
+            .await?;
         Ok(updated_user)
     }
 
@@ -282,52 +473,16 @@ impl KeycloakUserManager {
         let deleted = self
             .keycloak_admin
             .delete_user(username)
-            .await
-            .map_err(|e| BigbotError::UserDeleteError(e.to_string()))?;
+            .await?;
         Ok(deleted)
     }
 
     async fn create_user_wallet(&self, user_id: &str) -> Result<Wallet, BigbotError> {
-        let wallet = Wallet::new_wallet().await;
-
-        let mut user_representation = self.keycloak_admin.get_user(user_id).await.map_err(|e| BigbotError::UserGetError(e.to_string()))?;
-        user_representation
-            .attributes
-            .insert("wallet_id".to_string(), vec![wallet.id.clone()]);
-        user_representation
-            .attributes
-            .insert("wallet_public_key".to_string(), vec![wallet.public_key.clone()]);
-        self.keycloak_admin.update_user(user_representation).await.map_err(|e| BigbotError::UserUpdateError(e.to_string()))?;
-
-        Ok(wallet)
+        provision_wallet(&self.keycloak_admin, &self.wallet_store, user_id).await
     }
 
     async fn get_user_wallet(&self, user_id: &str) -> Result<Wallet, BigbotError> {
-        let user_representation = self.keycloak_admin.get_user(user_id).await.map_err(|e| BigbotError::UserGetError(e.to_string()))?;
-        let wallet_id = user_representation
-            .attributes
-            .get("wallet_id")
-            .and_then(|v| v.first().cloned());
-        let wallet_public_key = user_representation
-            .attributes
-            .get("wallet_public_key")
-            .and_then(|v| v.first().cloned());
-    
-        match (wallet_id, wallet_public_key) {
-            (Some(id), Some(public_key)) => Ok(Wallet {
-                id,
-                public_key,
-                did: "".to_string(),
-                identity_doc: "".to_string(),
-                credentials: HashMap::new(),
-                keys: vec![],
-                addresses: vec![],
-                preferred_address: WalletAddress(Address::default()),
-                base_currency: "".to_string(),
-                payment_thresholds: HashMap::new(),
-            }),
-            _ => Err(BigbotError::WalletNotFound),
-        }
+        load_wallet(&self.keycloak_admin, &self.wallet_store, user_id).await
     }
 
     async fn issue_credential(
@@ -353,7 +508,7 @@ impl KeycloakUserManager {
     ) -> Result<bool, BigbotError> {
         let issuer_wallet = self.get_user_wallet(&credential.issuer).await?;
 
-        let is_valid = verify_credential_with_wallet(&credential, &issuer_wallet)
+        let is_valid = verify_credential_with_wallet(&credential, &issuer_wallet, self.did_resolver.as_ref())
             .await
             .map_err(|e| BigbotError::CredentialVerificationError(e.to_string()))?;
 
@@ -443,4 +598,76 @@ impl CredentialProof {
             signature,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::kv::MemoryKVStore;
+    use crate::encryption::encryption::EncryptHandler;
+    use crate::iam::did::KeyDidResolver;
+
+    #[tokio::test]
+    async fn test_wallet_id_attribute_points_at_the_stored_wallet() {
+        let handler = Arc::new(EncryptHandler::new(Arc::new(MemoryKVStore::default())));
+        let wallet_store =
+            WalletStore::new(Arc::new(MemoryKVStore::default()), handler, vec![8u8; 32]);
+        let mut user_representation = UserRepresentation {
+            id: "user-1".to_string(),
+            username: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            first_name: "Alice".to_string(),
+            last_name: "Example".to_string(),
+            enabled: true,
+            email_verified: true,
+            attributes: HashMap::new(),
+        };
+        assert!(matches!(
+            load_user_wallet(&wallet_store, &user_representation).await,
+            Err(BigbotError::WalletNotFound)
+        ));
+
+        let wallet = wallet_store.create_wallet().await.unwrap();
+        set_wallet_id(&mut user_representation, &wallet.id);
+        assert_eq!(user_representation.attributes.len(), 1);
+
+        let loaded = load_user_wallet(&wallet_store, &user_representation)
+            .await
+            .unwrap();
+        let signature = loaded.sign(b"credential").unwrap();
+        assert!(wallet
+            .verify(&KeyDidResolver, &signature, b"credential")
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_admin_token_is_requested_once_and_reused() {
+        use wiremock::matchers::{body_string_contains, header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/realms/test/protocol/openid-connect/token"))
+            .and(body_string_contains("grant_type=client_credentials"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "admin-token",
+                "expires_in": 300,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/admin/realms/test/users"))
+            .and(header("authorization", "Bearer admin-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let admin = KeycloakAdmin::new(&server.uri(), "test", "bigbot", "secret", "", "");
+        assert!(admin.get_users().await.unwrap().is_empty());
+        assert!(admin.clone().get_users().await.unwrap().is_empty());
+        server.verify().await;
+    }
+}
@@ -2,7 +2,7 @@ use crate::clients::kv::KVStore;
 use crate::clients::postgres::PGTableKVClient;
 use crate::utils::bigboterror::BigbotError;
 use crate::iam::wallet::Wallet;
-use crate::iam::did::VerifiableCredential;
+use crate::iam::did::{DidResolver, VerifiableCredential};
 use crate::iam::iam::CredentialProof;
 
 use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
@@ -12,9 +12,13 @@ use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub const JWK_CENTER_URL: &str = "https://yourown.ai/auth/jwks.json";
 
+// Clock skew tolerated by `JWT::decode` when checking `exp` and `nbf`
+pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JWT {
     pub(crate) header: jsonwebtoken::Header,
@@ -43,6 +47,44 @@ impl JWT {
         self.payload.get(key)
     }
 
+    // Sets the `exp` claim so the token expires `duration` from now
+    pub fn add_exp(&mut self, duration: Duration) {
+        self.add_exp_at(unix_now(), duration);
+    }
+
+    // Sets the `exp` claim so the token expires `duration` after `now` (seconds since the epoch)
+    pub fn add_exp_at(&mut self, now: u64, duration: Duration) {
+        let exp = now.saturating_add(duration.as_secs());
+        self.add_payload("exp".to_string(), exp.to_string());
+    }
+
+    // Checks the `exp` and `nbf` claims, if present, against `now` (seconds since the epoch),
+    // allowing `clock_skew` either way
+    fn validate_time_claims(&self, now: u64, clock_skew: Duration) -> Result<(), BigbotError> {
+        let claim = |name: &str| -> Result<Option<u64>, BigbotError> {
+            self.payload
+                .get(name)
+                .map(|value| {
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| BigbotError::RejectedError(format!("Invalid {} claim", name)))
+                })
+                .transpose()
+        };
+        let skew = clock_skew.as_secs();
+        if let Some(exp) = claim("exp")? {
+            if now > exp.saturating_add(skew) {
+                return Err(BigbotError::TokenExpired(exp));
+            }
+        }
+        if let Some(nbf) = claim("nbf")? {
+            if now.saturating_add(skew) < nbf {
+                return Err(BigbotError::TokenNotYetValid(nbf));
+            }
+        }
+        Ok(())
+    }
+
     pub(crate) async fn encode(&mut self) -> Result<String, BigbotError> {
         let jwks = JwksCenter::new(JWK_CENTER_URL.to_string());
         let jwk = match jwks.select_random().await? {
@@ -57,6 +99,16 @@ impl JWT {
     }
 
     pub(crate) async fn decode(jwt: &str) -> Result<Self, BigbotError> {
+        Self::decode_at(jwt, unix_now(), DEFAULT_CLOCK_SKEW).await
+    }
+
+    // Decodes and verifies the token, rejecting it if it has expired or is not yet valid at `now`
+    // (seconds since the epoch)
+    pub(crate) async fn decode_at(
+        jwt: &str,
+        now: u64,
+        clock_skew: Duration,
+    ) -> Result<Self, BigbotError> {
         let err = BigbotError::RejectedError("Invalid verifiable credential".to_string());
         let kid = match decode_header(jwt).map_err(|_x| err.clone())?.kid.as_ref() {
             None => return Err(err),
@@ -72,17 +124,27 @@ impl JWT {
         let key = DecodingKey::from_secret(jwk.pem.as_bytes());
         let mut validation = Validation::new(Algorithm::HS256);
         validation.required_spec_claims.clear();
+        // Claims are decoded as strings, so `exp` and `nbf` are checked by `validate_time_claims`
         validation.validate_exp = false;
         let token_data = jsonwebtoken::decode::<HashMap<String, String>>(jwt, &key, &validation)
             .map_err(|_e| err.clone())?;
-        Ok(Self {
+        let token = Self {
             header: token_data.header,
             payload: token_data.claims,
             sign: None,
-        })
+        };
+        token.validate_time_claims(now, clock_skew)?;
+        Ok(token)
     }
 }
 
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
 // Define the function to verify a verifiable credential
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Jwks {
@@ -254,6 +316,7 @@ pub async fn sign_credential_with_wallet(
 pub async fn verify_credential_with_wallet(
     credential: &VerifiableCredential,
     wallet: &Wallet,
+    resolver: &dyn DidResolver,
 ) -> Result<bool, String> {
     // Extract the proof from the credential
     let proof = credential
@@ -287,6 +350,9 @@ pub async fn verify_credential_with_wallet(
 
     // Verify the signature using the wallet's verification method
     let credential_json = serde_json::to_string(credential).map_err(|e| e.to_string())?;
-    let is_valid = wallet.verify(signature.as_bytes(), credential_json.as_bytes());
+    let is_valid = wallet
+        .verify(resolver, signature.as_bytes(), credential_json.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
     Ok(is_valid)
 }
\ No newline at end of file
@@ -1,131 +1,302 @@
-use js_sys::Uint8Array;
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
-use std::collections::HashMap;
 use sha3::{Digest, Keccak256};
 
+pub type Hash = [u8; 32];
+
+// Leaves, interior nodes and the root are hashed with different prefixes so an interior node can
+// never be passed off as a leaf
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+const ROOT_PREFIX: u8 = 0x02;
+
+// Root of a tree with no leaves
+pub const EMPTY_ROOT: Hash = [0u8; 32];
+
+pub fn hash_leaf(data: &[u8]) -> Hash {
+    let mut hasher = Keccak256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+fn hash_combine(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Keccak256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// The root commits to the leaf count as well as the top node. Duplicating the last node of an
+// odd level means a tree of n leaves has the same top node for a padded index n; binding n makes
+// such a phantom index fail verification.
+fn hash_root(leaf_count: u64, top: &Hash) -> Hash {
+    let mut hasher = Keccak256::new();
+    hasher.update([ROOT_PREFIX]);
+    hasher.update(leaf_count.to_le_bytes());
+    hasher.update(top);
+    hasher.finalize().into()
+}
+
+// Number of levels between the leaves and the top node of a tree with `leaf_count` leaves
+fn tree_depth(leaf_count: u64) -> usize {
+    let mut depth = 0;
+    let mut width = leaf_count;
+    while width > 1 {
+        width = width.div_ceil(2);
+        depth += 1;
+    }
+    depth
+}
+
+// The sibling hashes on the path from a leaf up to the root, lowest level first. Whether each
+// sibling goes on the left or the right follows from the bits of `leaf_index`. `leaf_count` is the
+// size of the tree the proof came from, which the root commits to.
 #[wasm_bindgen]
-pub struct CompressedMerkleProof {
-    path: Vec<bool>,
-    hashes: Vec<Uint8Array>,
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
     leaf_index: u64,
+    leaf_count: u64,
+    siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    pub fn siblings(&self) -> &[Hash] {
+        &self.siblings
+    }
 }
 
 #[wasm_bindgen]
-impl CompressedMerkleProof {
-    #[wasm_bindgen(constructor)]
-    pub fn new(path: Vec<bool>, hashes: Vec<Uint8Array>, leaf_index: u64) -> CompressedMerkleProof {
-        CompressedMerkleProof {
-            path,
-            hashes,
-            leaf_index,
-        }
+impl MerkleProof {
+    #[wasm_bindgen(getter)]
+    pub fn leaf_index(&self) -> u64 {
+        self.leaf_index
     }
 
-    pub fn verify(&self, root_hash: &Uint8Array, leaf_hash: &Uint8Array) -> bool {
-        if self.path.len() != self.hashes.len() {
-            return false;
-        }
+    #[wasm_bindgen(getter)]
+    pub fn leaf_count(&self) -> u64 {
+        self.leaf_count
+    }
 
-        let mut hash = leaf_hash.to_vec();
-        for (i, bit) in self.path.iter().enumerate() {
-            let sibling_hash = &self.hashes[i];
-            hash = if *bit {
-                MerkleTree::hash_combine(&hash, &sibling_hash.to_vec())
-            } else {
-                MerkleTree::hash_combine(&sibling_hash.to_vec(), &hash)
-            };
+    // `root_hash` and `leaf_hash` must be 32 bytes; anything else fails verification
+    pub fn verify(&self, root_hash: &[u8], leaf_hash: &[u8]) -> bool {
+        match (Hash::try_from(root_hash), Hash::try_from(leaf_hash)) {
+            (Ok(root), Ok(leaf)) => verify_proof(&root, &leaf, self),
+            _ => false,
         }
+    }
+}
 
-        hash == root_hash.to_vec()
+// Checks that `leaf` (a `hash_leaf` output) is included under `root`, without the rest of the tree
+pub fn verify_proof(root: &Hash, leaf: &Hash, proof: &MerkleProof) -> bool {
+    let depth = tree_depth(proof.leaf_count);
+    if proof.leaf_index >= proof.leaf_count || proof.siblings.len() != depth {
+        return false;
     }
+    let mut index = proof.leaf_index;
+    let mut hash = *leaf;
+    for sibling in &proof.siblings {
+        hash = if index % 2 == 0 {
+            hash_combine(&hash, sibling)
+        } else {
+            hash_combine(sibling, &hash)
+        };
+        index /= 2;
+    }
+    hash_root(proof.leaf_count, &hash) == *root
 }
 
+// An append-only binary Merkle tree over leaf hashes. A level with an odd number of nodes pairs
+// its last node with itself, so any leaf count gives one deterministic root, and the root also
+// commits to the leaf count.
 #[wasm_bindgen]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MerkleTree {
-    levels: usize,
-    next_index: u64,
-    root: Uint8Array,
-    filled_subtrees: Vec<Vec<Uint8Array>>,
-    zero_values: Vec<Vec<Uint8Array>>,
-    nodes: HashMap<String, Uint8Array>,
+    leaves: Vec<Hash>,
 }
 
 #[wasm_bindgen]
 impl MerkleTree {
     #[wasm_bindgen(constructor)]
     pub fn new() -> MerkleTree {
-        MerkleTree {
-            levels: 0,
-            next_index: 0,
-            root: Uint8Array::new_with_length(32),
-            filled_subtrees: Vec::new(),
-            zero_values: Vec::new(),
-            nodes: HashMap::new(),
+        MerkleTree { leaves: Vec::new() }
+    }
+
+    // Hashes `data` into a new leaf and returns its index
+    pub fn update(&mut self, data: &[u8]) -> u64 {
+        self.push_leaf(hash_leaf(data))
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    #[wasm_bindgen(js_name = root)]
+    pub fn root_bytes(&self) -> Vec<u8> {
+        self.root().to_vec()
+    }
+
+    #[wasm_bindgen(js_name = proof)]
+    pub fn proof_for(&self, leaf_index: u64) -> Option<MerkleProof> {
+        self.proof(leaf_index)
+    }
+}
+
+impl MerkleTree {
+    pub fn push_leaf(&mut self, leaf: Hash) -> u64 {
+        self.leaves.push(leaf);
+        (self.leaves.len() - 1) as u64
+    }
+
+    pub fn leaves(&self) -> &[Hash] {
+        &self.leaves
+    }
+
+    // Index of the first leaf with this hash
+    pub fn leaf_index(&self, leaf: &Hash) -> Option<u64> {
+        self.leaves
+            .iter()
+            .position(|candidate| candidate == leaf)
+            .map(|index| index as u64)
+    }
+
+    pub fn root(&self) -> Hash {
+        if self.leaves.is_empty() {
+            return EMPTY_ROOT;
+        }
+        let mut level = self.leaves.clone();
+        while level.len() > 1 {
+            level = next_level(&level);
         }
+        hash_root(self.leaves.len() as u64, &level[0])
     }
 
-    pub fn update(&mut self, leaf: &Uint8Array) -> u64 {
-        let leaf_index = self.next_index;
-        let mut path = Vec::new();
-        let mut hash = leaf.to_vec();
-        let mut current_node = hash.clone();
-
-        while !current_node.is_empty() {
-            let bit = current_node[current_node.len() - 1] == 1;
-            path.push(bit);
-            self.nodes.insert(path.iter().map(|&b| if b { '1' } else { '0' }).collect(), Uint8Array::from(&hash[..]));
-            let sibling_path = MerkleTree::sibling_path(&path);
-            let sibling_hash = self.nodes.get(&sibling_path.iter().map(|&b| if b { '1' } else { '0' }).collect()).cloned().unwrap_or_else(|| Uint8Array::new_with_length(32));
-            hash = MerkleTree::hash_combine(&hash, &sibling_hash.to_vec());
-            current_node = hash.clone();
+    // None if there is no leaf at `leaf_index`
+    pub fn proof(&self, leaf_index: u64) -> Option<MerkleProof> {
+        let mut index = usize::try_from(leaf_index).ok()?;
+        if index >= self.leaves.len() {
+            return None;
         }
+        let mut siblings = Vec::new();
+        let mut level = self.leaves.clone();
+        while level.len() > 1 {
+            let sibling = if index % 2 == 0 {
+                // The last node on an odd-sized level is its own sibling
+                level.get(index + 1).unwrap_or(&level[index])
+            } else {
+                &level[index - 1]
+            };
+            siblings.push(*sibling);
+            level = next_level(&level);
+            index /= 2;
+        }
+        Some(MerkleProof {
+            leaf_index,
+            leaf_count: self.leaves.len() as u64,
+            siblings,
+        })
+    }
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_combine(left, right),
+            [last] => hash_combine(last, last),
+            _ => unreachable!("chunks(2) yields one or two nodes"),
+        })
+        .collect()
+}
 
-        self.next_index += 1;
-        leaf_index
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(leaf_count: usize) -> MerkleTree {
+        let mut tree = MerkleTree::new();
+        for i in 0..leaf_count {
+            tree.update(format!("message-{}", i).as_bytes());
+        }
+        tree
     }
 
-    pub fn get_proof(&self, leaf_index: u64) -> CompressedMerkleProof {
-        if leaf_index >= self.next_index {
-            panic!("Leaf index out of bounds");
+    #[test]
+    fn test_every_leaf_of_an_odd_tree_has_a_valid_proof() {
+        let tree = tree(5);
+        let root = tree.root();
+        for (index, leaf) in tree.leaves().iter().enumerate() {
+            let proof = tree.proof(index as u64).unwrap();
+            // 5 leaves pad out to 8, three levels below the root
+            assert_eq!(proof.siblings().len(), 3);
+            assert!(verify_proof(&root, leaf, &proof), "leaf {} failed", index);
+            assert!(proof.verify(&root, leaf));
         }
+        assert_eq!(tree.proof(5), None);
 
-        let mut path = Vec::new();
-        let mut current_index = leaf_index;
+        // Duplicating the last leaf is deterministic, so the same leaves give the same root
+        assert_eq!(root, self::tree(5).root());
+        assert_ne!(root, self::tree(6).root());
+    }
 
-        while current_index > 0 {
-            let bit = current_index % 2 == 1;
-            path.push(bit);
-            current_index /= 2;
-        }
+    #[test]
+    fn test_tampered_leaf_or_proof_is_rejected() {
+        let tree = tree(5);
+        let root = tree.root();
+        let proof = tree.proof(2).unwrap();
 
-        path.reverse();
+        let tampered = hash_leaf(b"message-2 (edited)");
+        assert!(!verify_proof(&root, &tampered, &proof));
+        // A valid leaf doesn't verify at another leaf's position
+        assert!(!verify_proof(&root, &tree.leaves()[3], &proof));
 
-        let mut hashes = Vec::new();
-        for bit in &path {
-            let sibling_path = MerkleTree::sibling_path(&path[..path.len() - 1]);
-            let sibling_hash = self.nodes.get(&sibling_path.iter().map(|&b| if b { '1' } else { '0' }).collect()).cloned();
-            if let Some(hash) = sibling_hash {
-                hashes.push(hash);
-            }
-            path.pop();
-        }
+        let mut forged = proof.clone();
+        forged.leaf_index += 8;
+        assert!(!verify_proof(&root, &tree.leaves()[2], &forged));
 
-        hashes.reverse();
+        // Claiming a different tree size changes the root the proof folds up to
+        let mut forged = proof.clone();
+        forged.leaf_count = 6;
+        assert!(!verify_proof(&root, &tree.leaves()[2], &forged));
 
-        CompressedMerkleProof::new(path, hashes, leaf_index)
+        let mut forged = proof;
+        forged.siblings[1][0] ^= 1;
+        assert!(!verify_proof(&root, &tree.leaves()[2], &forged));
     }
 
-    fn sibling_path(path: &[bool]) -> Vec<bool> {
-        let mut sibling_path = path.to_vec();
-        let last_bit = sibling_path.pop().unwrap();
-        sibling_path.push(!last_bit);
-        sibling_path
+    #[test]
+    fn test_single_and_empty_trees() {
+        assert_eq!(MerkleTree::new().root(), EMPTY_ROOT);
+
+        let tree = tree(1);
+        let proof = tree.proof(0).unwrap();
+        assert!(proof.siblings().is_empty());
+        assert_ne!(tree.root(), tree.leaves()[0]);
+        assert!(verify_proof(&tree.root(), &tree.leaves()[0], &proof));
     }
 
-    fn hash_combine(left: &[u8], right: &[u8]) -> Vec<u8> {
-        let mut hasher = Keccak256::new();
-        hasher.update(left);
-        hasher.update(right);
-        hasher.finalize().to_vec()
+    #[test]
+    fn test_padded_index_past_the_last_leaf_is_rejected() {
+        let tree = tree(5);
+        let root = tree.root();
+        let last = tree.leaves()[4];
+        let proof = tree.proof(4).unwrap();
+
+        // Index 5 is the padding copy of leaf 4, so the same siblings fold to the same top node
+        let mut phantom = proof.clone();
+        phantom.leaf_index = 5;
+        assert!(!verify_proof(&root, &last, &phantom));
+        // Growing the claimed size to cover the phantom index doesn't match the committed root
+        phantom.leaf_count = 6;
+        assert!(!verify_proof(&root, &last, &phantom));
+        // Nor does padding the proof out with an extra level
+        let mut deeper = proof;
+        deeper.siblings.push(root);
+        assert!(!verify_proof(&root, &last, &deeper));
     }
 }
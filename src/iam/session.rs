@@ -0,0 +1,306 @@
+//! # Session Management
+//!
+//! Ties OpenID tokens to users for the lifetime of a login session.
+//!
+//! Sessions are stored in a `KVStore` keyed by session id. Access tokens are refreshed
+//! transparently when they are within `refresh_margin` of expiry, and every refresh rotates
+//! the refresh token: the new one replaces the old in the store, so a refresh token is only
+//! ever used once. Refreshes of one session are serialised, so concurrent requests never race
+//! to spend the same refresh token. Logging out revokes the refresh token with the identity
+//! provider and removes the session.
+
+use crate::clients::kv::KVStore;
+use crate::iam::user::User;
+use crate::utils::bigboterror::BigbotError;
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_REFRESH_MARGIN_SECS: i64 = 30;
+
+const SESSION_KEY_PREFIX: &str = "session:";
+
+// Tokens issued by the identity provider for a login or a refresh.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenGrant {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: u64,
+    pub refresh_expires_in: u64,
+}
+
+// The identity provider operations a session needs; implemented by the Keycloak controller.
+#[async_trait]
+pub trait SessionAuthority: Send + Sync {
+    async fn refresh(&self, refresh_token: &str) -> Result<TokenGrant, BigbotError>;
+    async fn revoke(&self, refresh_token: &str) -> Result<(), BigbotError>;
+    async fn user(&self, user_id: &str) -> Result<User, BigbotError>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    // Unix timestamps in seconds
+    pub access_expires_at: i64,
+    pub refresh_expires_at: i64,
+}
+
+impl Session {
+    fn apply_grant(&mut self, grant: TokenGrant) {
+        let now = Utc::now().timestamp();
+        self.access_token = grant.access_token;
+        self.refresh_token = grant.refresh_token;
+        self.access_expires_at = now + grant.expires_in as i64;
+        self.refresh_expires_at = now + grant.refresh_expires_in as i64;
+    }
+}
+
+pub struct SessionManager {
+    store: Arc<dyn KVStore>,
+    authority: Arc<dyn SessionAuthority>,
+    refresh_margin: Duration,
+    // One lock per session being refreshed or logged out; entries are removed once unused
+    session_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl SessionManager {
+    pub fn new(store: Arc<dyn KVStore>, authority: Arc<dyn SessionAuthority>) -> Self {
+        Self {
+            store,
+            authority,
+            refresh_margin: Duration::seconds(DEFAULT_REFRESH_MARGIN_SECS),
+            session_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    // Creates a session from a fresh login and returns its id
+    pub async fn start_session(&self, user_id: &str, grant: TokenGrant) -> Result<String, BigbotError> {
+        let mut session = Session {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            access_token: String::new(),
+            refresh_token: String::new(),
+            access_expires_at: 0,
+            refresh_expires_at: 0,
+        };
+        session.apply_grant(grant);
+        self.save(&session).await?;
+        Ok(session.id)
+    }
+
+    // Returns the session with a valid access token, refreshing it first if it is about to expire
+    pub async fn session(&self, session_id: &str) -> Result<Session, BigbotError> {
+        let session = self.load(session_id).await?;
+        if self.is_fresh(&session) {
+            return Ok(session);
+        }
+        let lock = self.session_lock(session_id);
+        let result = {
+            let _guard = lock.lock().await;
+            self.refresh(session_id).await
+        };
+        self.release_session_lock(session_id, lock);
+        result
+    }
+
+    // Called with the session's lock held. Another caller may have refreshed the session while
+    // this one waited for the lock, so it is reloaded before its refresh token is spent.
+    async fn refresh(&self, session_id: &str) -> Result<Session, BigbotError> {
+        let mut session = self.load(session_id).await?;
+        if self.is_fresh(&session) {
+            return Ok(session);
+        }
+        let now = Utc::now().timestamp();
+        if session.refresh_expires_at <= now {
+            self.store.delete(&session_key(session_id)).await?;
+            return Err(BigbotError::AuthenticationError("Session expired".to_string()));
+        }
+        let grant = self.authority.refresh(&session.refresh_token).await?;
+        session.apply_grant(grant);
+        self.save(&session).await?;
+        Ok(session)
+    }
+
+    pub async fn access_token(&self, session_id: &str) -> Result<String, BigbotError> {
+        Ok(self.session(session_id).await?.access_token)
+    }
+
+    pub async fn current_user(&self, session_id: &str) -> Result<User, BigbotError> {
+        let session = self.session(session_id).await?;
+        self.authority.user(&session.user_id).await
+    }
+
+    // Revokes the session's refresh token and removes the session. The session is removed even
+    // if revocation fails, so a local logout always takes effect.
+    pub async fn logout(&self, session_id: &str) -> Result<(), BigbotError> {
+        let lock = self.session_lock(session_id);
+        let result = {
+            let _guard = lock.lock().await;
+            self.remove_session(session_id).await
+        };
+        self.release_session_lock(session_id, lock);
+        self.authority.revoke(&result?.refresh_token).await
+    }
+
+    async fn remove_session(&self, session_id: &str) -> Result<Session, BigbotError> {
+        let session = self.load(session_id).await?;
+        self.store.delete(&session_key(session_id)).await?;
+        Ok(session)
+    }
+
+    fn is_fresh(&self, session: &Session) -> bool {
+        session.access_expires_at - self.refresh_margin.num_seconds() > Utc::now().timestamp()
+    }
+
+    fn session_lock(&self, session_id: &str) -> Arc<tokio::sync::Mutex<()>> {
+        self.session_locks
+            .lock()
+            .unwrap()
+            .entry(session_id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    // Drops the session's lock from the map unless another caller is waiting on it
+    fn release_session_lock(&self, session_id: &str, lock: Arc<tokio::sync::Mutex<()>>) {
+        let mut session_locks = self.session_locks.lock().unwrap();
+        // One reference is held by the map and one by `lock`
+        if Arc::strong_count(&lock) == 2 {
+            session_locks.remove(session_id);
+        }
+    }
+
+    async fn load(&self, session_id: &str) -> Result<Session, BigbotError> {
+        let bytes = self
+            .store
+            .get(&session_key(session_id))
+            .await?
+            .ok_or_else(|| BigbotError::AuthenticationError("Unknown or expired session".to_string()))?;
+        serde_json::from_slice(&bytes).map_err(|e| BigbotError::SystemError(e.to_string()))
+    }
+
+    async fn save(&self, session: &Session) -> Result<(), BigbotError> {
+        let bytes = serde_json::to_vec(session).map_err(|e| BigbotError::SystemError(e.to_string()))?;
+        self.store.set(session_key(&session.id), bytes).await
+    }
+}
+
+fn session_key(session_id: &str) -> Vec<u8> {
+    format!("{}{}", SESSION_KEY_PREFIX, session_id).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::kv::MemoryKVStore;
+    use crate::iam::user::UserBuilder;
+    use std::collections::HashSet;
+
+    // Issues numbered tokens and only accepts each refresh token once, like a rotating provider
+    #[derive(Default)]
+    struct MockAuthority {
+        issued: Mutex<u32>,
+        valid_refresh_tokens: Mutex<HashSet<String>>,
+        revoked: Mutex<Vec<String>>,
+    }
+
+    impl MockAuthority {
+        fn grant(&self, expires_in: u64) -> TokenGrant {
+            let mut issued = self.issued.lock().unwrap();
+            *issued += 1;
+            let refresh_token = format!("refresh-{}", issued);
+            self.valid_refresh_tokens.lock().unwrap().insert(refresh_token.clone());
+            TokenGrant {
+                access_token: format!("access-{}", issued),
+                refresh_token,
+                expires_in,
+                refresh_expires_in: 3600,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl SessionAuthority for MockAuthority {
+        async fn refresh(&self, refresh_token: &str) -> Result<TokenGrant, BigbotError> {
+            // Lets concurrent callers interleave, as a network round trip would
+            tokio::task::yield_now().await;
+            if !self.valid_refresh_tokens.lock().unwrap().remove(refresh_token) {
+                return Err(BigbotError::AuthenticationError("Refresh token reused".to_string()));
+            }
+            Ok(self.grant(300))
+        }
+
+        async fn revoke(&self, refresh_token: &str) -> Result<(), BigbotError> {
+            self.valid_refresh_tokens.lock().unwrap().remove(refresh_token);
+            self.revoked.lock().unwrap().push(refresh_token.to_string());
+            Ok(())
+        }
+
+        async fn user(&self, user_id: &str) -> Result<User, BigbotError> {
+            Ok(UserBuilder::new(user_id.to_string()).username("ada".to_string()).build())
+        }
+    }
+
+    fn manager(authority: Arc<MockAuthority>) -> SessionManager {
+        SessionManager::new(Arc::new(MemoryKVStore::default()), authority)
+    }
+
+    #[tokio::test]
+    async fn test_refreshes_and_rotates_before_expiry() {
+        let authority = Arc::new(MockAuthority::default());
+        let sessions = manager(authority.clone());
+
+        // Expires within the refresh margin, so the first use refreshes it
+        let session_id = sessions.start_session("user-1", authority.grant(10)).await.unwrap();
+        let refreshed = sessions.session(&session_id).await.unwrap();
+        assert_eq!(refreshed.access_token, "access-2");
+        assert_eq!(refreshed.refresh_token, "refresh-2");
+        assert!(authority.refresh("refresh-1").await.is_err(), "old refresh token must be rotated out");
+
+        // Now well within its lifetime: no further refresh
+        let user = sessions.current_user(&session_id).await.unwrap();
+        assert_eq!(user.id, "user-1");
+        assert_eq!(sessions.access_token(&session_id).await.unwrap(), "access-2");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_refreshes_spend_the_refresh_token_once() {
+        let authority = Arc::new(MockAuthority::default());
+        let sessions = manager(authority.clone());
+
+        let session_id = sessions.start_session("user-1", authority.grant(10)).await.unwrap();
+        let (first, second) = tokio::join!(sessions.session(&session_id), sessions.session(&session_id));
+        // The second caller waits for the first refresh and reuses its tokens
+        assert_eq!(first.unwrap().access_token, "access-2");
+        assert_eq!(second.unwrap().access_token, "access-2");
+        assert_eq!(*authority.issued.lock().unwrap(), 2);
+        assert!(sessions.session_locks.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_logout_invalidates_session() {
+        let authority = Arc::new(MockAuthority::default());
+        let sessions = manager(authority.clone());
+
+        let session_id = sessions.start_session("user-1", authority.grant(300)).await.unwrap();
+        sessions.logout(&session_id).await.unwrap();
+
+        assert_eq!(*authority.revoked.lock().unwrap(), vec!["refresh-1".to_string()]);
+        assert!(matches!(
+            sessions.current_user(&session_id).await,
+            Err(BigbotError::AuthenticationError(_))
+        ));
+        assert!(sessions.logout(&session_id).await.is_err());
+    }
+}
@@ -10,7 +10,7 @@
 //! The module is structured around several key components, including the `User` struct for representing user profiles, the `UserError` enum for error management, and the `UserService` class for handling business logic related to user operations.
 
 
-use crate::iam::merkle_tree::MerkleTree;
+use crate::iam::merkle_tree::{hash_leaf, MerkleTree};
 use crate::iam::verifiable_credentials::{VerifiableCredential, VCBuilder};
 use crate::iam::wallet::Wallet;
 use chrono::{DateTime, Utc};
@@ -89,8 +89,8 @@ impl User {
 
     /// Adds a new verifiable credential to the user.
     pub fn add_credential(&mut self, credential: VerifiableCredential) {
+        self.credential_tree.update(credential.id.as_bytes());
         self.credentials.insert(credential.id.clone(), credential);
-        self.credential_tree.update(&credential.id.as_bytes());
         self.last_modified_at = Utc::now();
     }
 
@@ -102,7 +102,8 @@ impl User {
     /// Generates a proof for a specific credential.
     pub fn generate_credential_proof(&self, credential_id: &str) -> Option<String> {
         let credential = self.get_credential(credential_id)?;
-        let proof = self.credential_tree.get_proof(&credential.id.as_bytes());
+        let leaf_index = self.credential_tree.leaf_index(&hash_leaf(credential.id.as_bytes()))?;
+        let proof = self.credential_tree.proof(leaf_index)?;
         Some(serde_json::to_string(&proof).ok()?)
     }
 
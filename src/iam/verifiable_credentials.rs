@@ -1,3 +1,4 @@
+use crate::iam::did::DidResolver;
 use crate::iam::jwt::JWT;
 use crate::iam::wallet::Wallet;
 use crate::utils::bigboterror::BigbotError;
@@ -6,10 +7,13 @@ use jsonwebtoken::{decode, Algorithm, Validation};
 use jsonwebtoken::decode_header as jwt_decode_header;use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use serde_json::Value;
+use serde_json::{Map, Value};
 use std::str::FromStr;
 use web3::types::Address;
 
+// The W3C Verifiable Credentials base context; it must be the first entry of a credential's `@context`
+pub const BASE_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+
 #[derive(Debug, Deserialize, Default, Serialize, Clone)]
 pub struct VerifiableCredential {
     #[serde(rename = "@context")]
@@ -23,11 +27,13 @@ pub struct VerifiableCredential {
     pub credential_subject: CredentialSubject,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct CredentialSubject {
     pub id: String,
     pub wallet_address: Address,
-    // Add other relevant fields for the credential subject
+    // Claims about the subject, e.g. `degree`. These are what selective disclosure chooses between.
+    #[serde(flatten)]
+    pub claims: Map<String, Value>,
 }
 
 #[derive(Debug)]
@@ -40,7 +46,7 @@ impl VCBuilder {
         Self {
             vc: VerifiableCredential {
                 context: vec![
-                    "https://www.w3.org/2018/credentials/v1".to_string(),
+                    BASE_CONTEXT.to_string(),
                     "https://www.w3.org/2018/credentials/examples/v1".to_string(),
                 ],
                 types: vec![
@@ -54,6 +60,7 @@ impl VCBuilder {
                 credential_subject: CredentialSubject {
                     id: "".to_string(),
                     wallet_address: Address::default(),
+                    claims: Map::new(),
                 },
             },
         }
@@ -99,6 +106,11 @@ impl VCBuilder {
         self
     }
 
+    pub fn add_subject_claim(mut self, name: String, value: Value) -> Self {
+        self.vc.credential_subject.claims.insert(name, value);
+        self
+    }
+
     pub fn build(self) -> VerifiableCredential {
         self.vc
     }
@@ -112,6 +124,80 @@ impl VerifiableCredential {
     pub fn get_subject_wallet_address(&self) -> Address {
         self.credential_subject.wallet_address
     }
+
+    // Checks that `@context` starts with the W3C base context and holds no empty or repeated entries
+    pub fn validate_contexts(&self) -> Result<(), BigbotError> {
+        match self.context.first() {
+            Some(first) if first == BASE_CONTEXT => {}
+            Some(first) => {
+                return Err(BigbotError::CredentialVerificationError(format!(
+                    "Credential {} must start its @context with {}, not {}",
+                    self.id, BASE_CONTEXT, first
+                )))
+            }
+            None => {
+                return Err(BigbotError::CredentialVerificationError(format!(
+                    "Credential {} has no @context",
+                    self.id
+                )))
+            }
+        }
+        for (i, context) in self.context.iter().enumerate() {
+            if context.trim().is_empty() {
+                return Err(BigbotError::CredentialVerificationError(format!(
+                    "Credential {} has an empty @context entry",
+                    self.id
+                )));
+            }
+            if self.context[..i].contains(context) {
+                return Err(BigbotError::CredentialVerificationError(format!(
+                    "Credential {} repeats @context {}",
+                    self.id, context
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Copies the credential with only the listed subject claims and signs the copy afresh. The
+    // subject's `id` and `wallet_address` are always kept. Only the issuer can sign the result,
+    // since verifiers check it against the issuer's key.
+    pub fn derive_disclosure(
+        &self,
+        fields: &[&str],
+        wallet: &Wallet,
+    ) -> Result<VerifiableCredential, BigbotError> {
+        self.validate_contexts()?;
+        if wallet.did != self.issuer {
+            return Err(BigbotError::CredentialSignError(format!(
+                "Only the issuer {} can sign a disclosure of credential {}, not {}",
+                self.issuer, self.id, wallet.did
+            )));
+        }
+        if let Some(unknown) = fields
+            .iter()
+            .find(|field| !self.credential_subject.claims.contains_key(**field))
+        {
+            return Err(BigbotError::InvalidInput(format!(
+                "Credential {} has no subject claim {}",
+                self.id, unknown
+            )));
+        }
+
+        let mut disclosed = VerifiableCredential {
+            proof: None,
+            ..self.clone()
+        };
+        disclosed
+            .credential_subject
+            .claims
+            .retain(|name, _| fields.contains(&name.as_str()));
+        let signing_bytes = disclosed
+            .signing_bytes()
+            .map_err(BigbotError::CredentialSignError)?;
+        disclosed.proof = Some(signed_proof(wallet, &signing_bytes)?);
+        Ok(disclosed)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -290,8 +376,7 @@ pub async fn sign_credential_with_wallet(
     wallet: &Wallet,
 ) -> Result<String, String> {
     // Sign the credential using the wallet's signing key
-    let credential_json = serde_json::to_string(credential).map_err(|e| e.to_string())?;
-    let signature = wallet.sign(credential_json.as_bytes());
+    let signature = wallet.sign(&credential.signing_bytes()?).map_err(|e| e.to_string())?;
 
     // Create a new proof object with the signature
     let proof = Proof {
@@ -314,6 +399,7 @@ pub async fn sign_credential_with_wallet(
 pub async fn verify_credential_with_wallet(
     credential: &VerifiableCredential,
     wallet: &Wallet,
+    resolver: &dyn DidResolver,
 ) -> Result<bool, String> {
     // Extract the proof from the credential
     let proof = credential
@@ -325,7 +411,256 @@ pub async fn verify_credential_with_wallet(
     let signature = base64::engine::general_purpose::STANDARD.decode(&proof.jwt.as_ref().ok_or("No JWT found in the proof")?)
         .map_err(|e| e.to_string())?;
 
-    // Verify the signature using the wallet's verification method
-    let credential_json = serde_json::to_string(credential).map_err(|e| e.to_string())?;
-    wallet.verify(&signature, credential_json.as_bytes())
+    // Verify the signature over the credential as it was before the proof was attached
+    wallet
+        .verify(resolver, &signature, &credential.signing_bytes()?)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+impl VerifiableCredential {
+    // The bytes covered by the issuer's signature: the credential without its proof
+    fn signing_bytes(&self) -> Result<Vec<u8>, String> {
+        let unsigned = VerifiableCredential {
+            proof: None,
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).map_err(|e| e.to_string())
+    }
+}
+
+// A set of credentials bundled and signed by their holder
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VerifiablePresentation {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    pub id: String,
+    pub holder: String,
+    pub verifiable_credential: Vec<VerifiableCredential>,
+    pub proof: Option<Proof>,
+}
+
+impl VerifiablePresentation {
+    pub fn new(id: String) -> Self {
+        Self {
+            context: vec!["https://www.w3.org/2018/credentials/v1".to_string()],
+            types: vec!["VerifiablePresentation".to_string()],
+            id,
+            holder: "".to_string(),
+            verifiable_credential: vec![],
+            proof: None,
+        }
+    }
+
+    // Adds a signed credential. Any existing holder proof no longer covers the contents, so it is dropped.
+    pub fn add_credential(&mut self, credential: VerifiableCredential) {
+        self.verifiable_credential.push(credential);
+        self.proof = None;
+    }
+
+    // Signs the presentation, including every embedded credential and its proof, as the holder
+    pub fn sign_with_wallet(&mut self, holder_wallet: &Wallet) -> Result<(), BigbotError> {
+        self.holder = holder_wallet.did.clone();
+        self.proof = Some(signed_proof(holder_wallet, &self.signing_bytes()?)?);
+        Ok(())
+    }
+
+    // Checks the holder's signature over the presentation and each credential's signature by its
+    // issuer. `issuer_wallets` must include a wallet for every issuer DID in the presentation, and
+    // `resolver` must handle the DID methods of the holder and every issuer.
+    pub async fn verify(
+        &self,
+        resolver: &dyn DidResolver,
+        holder_wallet: &Wallet,
+        issuer_wallets: &[&Wallet],
+    ) -> Result<(), BigbotError> {
+        if self.holder != holder_wallet.did {
+            return Err(BigbotError::CredentialVerificationError(format!(
+                "Presentation holder {} does not match wallet {}",
+                self.holder, holder_wallet.did
+            )));
+        }
+        let signature = proof_signature(self.proof.as_ref())?;
+        let valid = holder_wallet
+            .verify(resolver, &signature, &self.signing_bytes()?)
+            .await
+            .map_err(|e| BigbotError::CredentialVerificationError(e.to_string()))?;
+        if !valid {
+            return Err(BigbotError::CredentialVerificationError(
+                "Invalid holder signature on presentation".to_string(),
+            ));
+        }
+
+        for credential in &self.verifiable_credential {
+            let issuer_wallet = issuer_wallets
+                .iter()
+                .find(|wallet| wallet.did == credential.issuer)
+                .ok_or_else(|| {
+                    BigbotError::CredentialVerificationError(format!("Unknown issuer {}", credential.issuer))
+                })?;
+            let signature = proof_signature(credential.proof.as_ref())?;
+            let signing_bytes = credential
+                .signing_bytes()
+                .map_err(BigbotError::CredentialVerificationError)?;
+            let valid = issuer_wallet
+                .verify(resolver, &signature, &signing_bytes)
+                .await
+                .map_err(|e| BigbotError::CredentialVerificationError(e.to_string()))?;
+            if !valid {
+                return Err(BigbotError::CredentialVerificationError(format!(
+                    "Invalid issuer signature on credential {}",
+                    credential.id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn signing_bytes(&self) -> Result<Vec<u8>, BigbotError> {
+        let unsigned = VerifiablePresentation {
+            proof: None,
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).map_err(|e| BigbotError::CredentialSignError(e.to_string()))
+    }
+}
+
+// A proof carrying the wallet's signature over `signing_bytes`
+fn signed_proof(wallet: &Wallet, signing_bytes: &[u8]) -> Result<Proof, BigbotError> {
+    let signature = wallet.sign(signing_bytes)?;
+    Ok(Proof {
+        proof_type: "JsonWebSignature2020".to_string(),
+        created: chrono::Utc::now().to_rfc3339(),
+        verification_method: format!("{}#keys-1", wallet.did),
+        jwt: Some(base64::engine::general_purpose::STANDARD.encode(&signature)),
+    })
+}
+
+fn proof_signature(proof: Option<&Proof>) -> Result<Vec<u8>, BigbotError> {
+    let encoded = proof
+        .and_then(|proof| proof.jwt.as_ref())
+        .ok_or_else(|| BigbotError::CredentialVerificationError("Missing proof".to_string()))?;
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| BigbotError::CredentialVerificationError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iam::did::KeyDidResolver;
+
+    async fn signed_credential(issuer: &Wallet, subject: &Wallet, id: &str) -> VerifiableCredential {
+        let credential = VCBuilder::default()
+            .set_id(id.to_string())
+            .set_issuer(issuer.did.clone())
+            .set_subject_id(subject.did.clone())
+            .set_subject_wallet_address(subject.get_address())
+            .build();
+        let signed = sign_credential_with_wallet(&credential, issuer).await.unwrap();
+        serde_json::from_str(&signed).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_presentation_verifies_holder_and_issuers() {
+        let (issuer, other_issuer, holder) = (Wallet::new_wallet().await, Wallet::new_wallet().await, Wallet::new_wallet().await);
+
+        let mut presentation = VerifiablePresentation::new("urn:uuid:presentation-1".to_string());
+        presentation.add_credential(signed_credential(&issuer, &holder, "urn:uuid:degree").await);
+        presentation.add_credential(signed_credential(&other_issuer, &holder, "urn:uuid:membership").await);
+        presentation.sign_with_wallet(&holder).unwrap();
+
+        assert_eq!(presentation.holder, holder.did);
+        presentation
+            .verify(&KeyDidResolver, &holder, &[&issuer, &other_issuer])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_presentation_rejects_tampered_credential() {
+        let (issuer, holder) = (Wallet::new_wallet().await, Wallet::new_wallet().await);
+
+        // The credential is altered after the issuer signed it; the holder's own signature is fresh
+        let mut credential = signed_credential(&issuer, &holder, "urn:uuid:degree").await;
+        credential.credential_subject.id = "did:example:someone-else".to_string();
+
+        let mut presentation = VerifiablePresentation::new("urn:uuid:presentation-2".to_string());
+        presentation.add_credential(credential);
+        presentation.sign_with_wallet(&holder).unwrap();
+
+        assert!(matches!(
+            presentation.verify(&KeyDidResolver, &holder, &[&issuer]).await,
+            Err(BigbotError::CredentialVerificationError(message)) if message.contains("urn:uuid:degree")
+        ));
+    }
+
+    #[test]
+    fn test_credential_without_base_context_fails_validation() {
+        let credential = VCBuilder::default()
+            .set_id("urn:uuid:degree".to_string())
+            .build();
+        credential.validate_contexts().unwrap();
+
+        let mut missing_base = credential.clone();
+        missing_base.context.remove(0);
+        assert!(matches!(
+            missing_base.validate_contexts(),
+            Err(BigbotError::CredentialVerificationError(message)) if message.contains(BASE_CONTEXT)
+        ));
+
+        let mut repeated = credential;
+        repeated.context.push(BASE_CONTEXT.to_string());
+        assert!(repeated.validate_contexts().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_selective_disclosure_drops_undisclosed_claims_and_verifies() {
+        let (issuer, holder) = (Wallet::new_wallet().await, Wallet::new_wallet().await);
+        let credential = VCBuilder::default()
+            .set_id("urn:uuid:degree".to_string())
+            .set_issuer(issuer.did.clone())
+            .set_subject_id(holder.did.clone())
+            .set_subject_wallet_address(holder.get_address())
+            .add_subject_claim("degree".to_string(), json!({ "type": "BachelorDegree" }))
+            .add_subject_claim("birthDate".to_string(), json!("1990-01-01"))
+            .add_subject_claim("gpa".to_string(), json!(3.7))
+            .build();
+
+        let disclosed = credential.derive_disclosure(&["degree"], &issuer).unwrap();
+        let claims = &disclosed.credential_subject.claims;
+        assert_eq!(claims.keys().collect::<Vec<_>>(), vec!["degree"]);
+        assert_eq!(disclosed.credential_subject.id, holder.did);
+        assert!(!serde_json::to_string(&disclosed)
+            .unwrap()
+            .contains("birthDate"));
+        assert!(
+            verify_credential_with_wallet(&disclosed, &issuer, &KeyDidResolver)
+                .await
+                .unwrap()
+        );
+
+        // Re-adding a dropped claim breaks the issuer's signature
+        let mut tampered = disclosed.clone();
+        tampered
+            .credential_subject
+            .claims
+            .insert("gpa".to_string(), json!(4.0));
+        assert!(
+            !verify_credential_with_wallet(&tampered, &issuer, &KeyDidResolver)
+                .await
+                .unwrap()
+        );
+
+        assert!(matches!(
+            credential.derive_disclosure(&["degree"], &holder),
+            Err(BigbotError::CredentialSignError(_))
+        ));
+        assert!(matches!(
+            credential.derive_disclosure(&["nationality"], &issuer),
+            Err(BigbotError::InvalidInput(_))
+        ));
+    }
 }
@@ -3,16 +3,22 @@ use std::collections::HashMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
 use web3::types::Address;
 use std::sync::Arc;
 use ockam_vault::legacy::SecretAttributes;
+use ed25519_dalek::{Signer, SigningKey, SECRET_KEY_LENGTH};
+use rand::rngs::OsRng;
 
 
 use crate::clients::kv::{KVStore, MemoryKVStore, PrefixedKVStore};
-use crate::iam::did::{DID, resolve, VerifiableCredential};
+use crate::iam::did::{DID, DidError, DidResolver, VerifiableCredential};
+use crate::iam::hd_key::{ExtendedKey, MasterSeed, DEFAULT_DERIVATION_PATH};
 use crate::iam::public_key_store::PublicKeyStore;
 use crate::encryption::encryption::EncryptHandler;
+use crate::encryption::shamir::{recover_secret, split_secret, Share};
 use crate::iam::user_data::UserData;
+use crate::utils::bigboterror::BigbotError;
 
 // Custom struct to represent a wallet address
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
@@ -27,28 +33,59 @@ impl From<Address> for WalletAddress {
 // Implement the Display trait for the DID struct
 impl fmt::Display for DID {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.to_string())
+        write!(f, "{}", self.did)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Derivation metadata for HD addresses. Only the path and the derived indices are persisted;
+// private keys are re-derived from the master seed when needed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HdDerivation {
+    pub path: String,
+    pub indices: Vec<u32>,
+}
+
+impl Default for HdDerivation {
+    fn default() -> Self {
+        Self {
+            path: DEFAULT_DERIVATION_PATH.to_string(),
+            indices: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Wallet {
     pub id: String,
     pub public_key: String,
     pub did: String,
     pub identity_doc: String,
     pub credentials: HashMap<String, String>,
-    pub keys: Vec<String>,
+    // Signing keys encrypted under the wallet's key id, in the same order as `keys`
+    #[serde(default)]
+    pub encrypted_keys: Vec<String>,
+    // Decrypted signing keys; never serialized, restored with `load_signing_keys`
+    #[serde(skip)]
+    pub(crate) keys: Vec<SigningKey>,
     pub addresses: Vec<WalletAddress>,
     pub preferred_address: WalletAddress,
     pub base_currency: String,
     pub payment_thresholds: HashMap<String, u64>,   
+    #[serde(default)]
+    pub derivation: Option<HdDerivation>,
+    // Account-level key at `derivation.path`; never serialized
+    #[serde(skip)]
+    pub(crate) account_key: Option<ExtendedKey>,
+    // Seed behind `account_key`, for recovery shares; never serialized
+    #[serde(skip)]
+    pub(crate) master_seed: Option<MasterSeed>,
 }
 
 impl Wallet {
-    // Generate a new DID/DID document
+    // Generate a new signing key and the `did:key` DID/DID document for it
     pub async fn new_wallet() -> Self {
-        let (did, id_doc) = DID::generate();
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let (did, id_doc) = DID::from_ed25519(&signing_key.verifying_key());
         let store: Arc<dyn KVStore> = Arc::new(MemoryKVStore::default());
         let keyid_store: Arc<dyn KVStore> = Arc::new(PrefixedKVStore::new(store.clone(), "OCKAM_KEYID:".into()));
         let handler = EncryptHandler::new(keyid_store);
@@ -59,60 +96,202 @@ impl Wallet {
         // Encrypt the identity document using the KeyId
         let enc_doc = handler.aes_encrypt_message(&key_id, id_doc.to_string().as_bytes(), [0u8; 8]).await.unwrap();
         
-        Self {
+        let mut wallet = Self {
             id: did.to_string(),
             public_key: did.public_key(), // Use the PublicKeyStore trait to retrieve the public key
             did: did.to_string(),
             identity_doc: enc_doc,
             credentials: HashMap::new(),
+            encrypted_keys: vec![],
             keys: vec![],
             addresses: vec![],
             preferred_address: WalletAddress::default(),
             base_currency: "ETH".to_string(),
             payment_thresholds: HashMap::new(),
+            derivation: None,
+            account_key: None,
+            master_seed: None,
+        };
+
+        wallet
+            .add_signing_key(&handler, &key_id, signing_key)
+            .await
+            .unwrap();
+        wallet
+    }
+
+    // Encrypts `key` under `key_id` for persistence and makes it available for signing
+    pub async fn add_signing_key(
+        &mut self,
+        handler: &EncryptHandler,
+        key_id: &[u8],
+        key: SigningKey,
+    ) -> Result<(), BigbotError> {
+        let encrypted = handler
+            .aes_encrypt_message(key_id, &key.to_bytes(), [0u8; 8])
+            .await?;
+        self.encrypted_keys.push(encrypted);
+        self.keys.push(key);
+        Ok(())
+    }
+
+    // Re-encrypts the loaded signing keys under `key_id`, replacing the persisted key material
+    pub async fn seal_signing_keys(
+        &mut self,
+        handler: &EncryptHandler,
+        key_id: &[u8],
+    ) -> Result<(), BigbotError> {
+        let mut encrypted_keys = Vec::with_capacity(self.keys.len());
+        for key in &self.keys {
+            encrypted_keys.push(
+                handler
+                    .aes_encrypt_message(key_id, &key.to_bytes(), [0u8; 8])
+                    .await?,
+            );
+        }
+        self.encrypted_keys = encrypted_keys;
+        Ok(())
+    }
+
+    // Decrypts the persisted signing keys of a reloaded wallet, replacing any already loaded
+    pub async fn load_signing_keys(
+        &mut self,
+        handler: &EncryptHandler,
+        key_id: &[u8],
+    ) -> Result<(), BigbotError> {
+        let mut keys = Vec::with_capacity(self.encrypted_keys.len());
+        for encrypted in &self.encrypted_keys {
+            let bytes = handler
+                .aes_decrypt_message(key_id, encrypted.as_bytes())
+                .await?;
+            let secret = <[u8; SECRET_KEY_LENGTH]>::try_from(bytes.as_slice()).map_err(|_| {
+                BigbotError::InvalidInput("Stored signing key has the wrong length".into())
+            })?;
+            keys.push(SigningKey::from_bytes(&secret));
+        }
+        self.keys = keys;
+        Ok(())
+    }
+
+    // Attaches the master seed used for HD derivation. On a reloaded wallet this restores the
+    // account key for the persisted derivation path and checks it still produces the stored addresses.
+    pub fn load_master_seed(&mut self, seed: &[u8]) -> Result<(), BigbotError> {
+        let derivation = self.derivation.get_or_insert_with(HdDerivation::default);
+        let account_key = ExtendedKey::from_seed(seed)?.derive_path(&derivation.path)?;
+        for &index in &derivation.indices {
+            let address = WalletAddress::from(account_key.derive_normal(index)?.address());
+            if !self.addresses.contains(&address) {
+                return Err(BigbotError::InvalidInput(format!(
+                    "Seed does not match the wallet's derived address at index {}",
+                    index
+                )));
+            }
         }
+        self.account_key = Some(account_key);
+        self.master_seed = Some(MasterSeed::new(seed));
+        Ok(())
+    }
+
+    // Splits the master seed into `n` shares for guardians, any `k` of which restore it
+    pub fn export_recovery_shares(&self, k: u8, n: u8) -> Result<Vec<Share>, BigbotError> {
+        let seed = self
+            .master_seed
+            .as_ref()
+            .ok_or_else(|| BigbotError::InvalidInput("Wallet has no master seed loaded".into()))?;
+        split_secret(seed.as_bytes(), k, n)
+    }
+
+    // Restores the master seed from guardians' shares. The recovered seed goes through
+    // `load_master_seed`, so it must reproduce any addresses the wallet has already derived.
+    pub fn recover_from_shares(&mut self, shares: &[Share]) -> Result<(), BigbotError> {
+        let seed = recover_secret(shares)?;
+        self.load_master_seed(&seed)
+    }
+
+    // Derives the address at `index` below the wallet's derivation path and records it.
+    // Deriving the same index again returns the same address without duplicating it.
+    pub fn derive_address(&mut self, index: u32) -> Result<Address, BigbotError> {
+        let account_key = self
+            .account_key
+            .as_ref()
+            .ok_or_else(|| BigbotError::InvalidInput("Wallet has no master seed loaded".into()))?;
+        let address = account_key.derive_normal(index)?.address();
+
+        let derivation = self.derivation.get_or_insert_with(HdDerivation::default);
+        if !derivation.indices.contains(&index) {
+            derivation.indices.push(index);
+        }
+        let wallet_address = WalletAddress::from(address);
+        if !self.addresses.contains(&wallet_address) {
+            self.addresses.push(wallet_address);
+        }
+        Ok(address)
     }
 
     pub fn get_address(&self) -> Address {
         self.preferred_address.0
     }
 
-    // Persist verifiable credential
-    pub async fn store_vc(&mut self, vc: VerifiableCredential) {
-        let store = Arc::new(MemoryKVStore::default());
-        let keyid_store = Arc::new(PrefixedKVStore::new(store.clone(), "OCKAM_KEYID:".into()));
-        let handler = EncryptHandler::new(keyid_store);
-        let encrypted = handler.aes_encrypt_message(&self.did.as_bytes(), serde_json::to_vec(&vc).unwrap().as_slice(), [0u8; 8]).await.unwrap();
+    // Encrypts a verifiable credential under `key_id` and stores it by credential id. Use the same
+    // handler and key id with `get_vc` to read it back.
+    pub async fn store_vc(
+        &mut self,
+        handler: &EncryptHandler,
+        key_id: &[u8],
+        vc: VerifiableCredential,
+    ) -> Result<(), BigbotError> {
+        let plaintext =
+            serde_json::to_vec(&vc).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+        let encrypted = handler
+            .aes_encrypt_message(key_id, &plaintext, [0u8; 8])
+            .await?;
         self.credentials.insert(vc.id.clone(), encrypted);
+        Ok(())
     }
 
-    // Retrieve verifiable credential
-    pub async fn get_vc(&self, id: &str) -> Option<VerifiableCredential> {
-        self.credentials.get(id).map(|enc| async {
-            let store: Arc<dyn KVStore> = Arc::new(MemoryKVStore::default());
-            let keyid_store: Arc<dyn KVStore> = Arc::new(PrefixedKVStore::new(store.clone(), "OCKAM_KEYID:".into()));
-            let handler = EncryptHandler::new(keyid_store);
-            let decrypted = handler.aes_decrypt_message(&self.did.to_string().as_bytes(), enc.as_bytes()).await.unwrap();
-            serde_json::from_slice(&decrypted).unwrap()
+    // Decrypts the stored credential with the given id, or returns `None` if the wallet has none
+    pub async fn get_vc(
+        &self,
+        handler: &EncryptHandler,
+        key_id: &[u8],
+        id: &str,
+    ) -> Result<Option<VerifiableCredential>, BigbotError> {
+        let encrypted = match self.credentials.get(id) {
+            Some(encrypted) => encrypted,
+            None => return Ok(None),
+        };
+        let decrypted = handler
+            .aes_decrypt_message(key_id, encrypted.as_bytes())
+            .await?;
+        serde_json::from_slice(&decrypted).map(Some).map_err(|e| {
+            BigbotError::InvalidInput(format!("Stored credential {} is corrupt: {}", id, e))
         })
-        .map(|fut| futures::executor::block_on(fut))
     }
 
-    // Sign credential or other verification
-    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
-        // Sign using stored keys
-        self.keys[0].sign(data)
+    // Produces a detached ed25519 signature with the wallet's primary key
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, BigbotError> {
+        let key = self
+            .keys
+            .first()
+            .ok_or_else(|| BigbotError::InvalidInput("Wallet has no signing keys loaded".into()))?;
+        Ok(key.sign(data).to_vec())
     }
 
-    // Verify signature
-    pub fn verify(&self, signature: &[u8], data: &[u8]) -> bool {
-        // Lookup DID verification methods
-        let methods = resolve(&self.did).verification_methods;
-        // Verify signature using methods
-        methods.iter().any(|m| m.verify(signature, data))
+    // Checks a detached signature against the public keys the wallet's DID resolves to
+    pub async fn verify(
+        &self,
+        resolver: &dyn DidResolver,
+        signature: &[u8],
+        data: &[u8],
+    ) -> Result<bool, DidError> {
+        let document = resolver.resolve(&self.did).await?;
+        Ok(document
+            .verification_method
+            .iter()
+            .any(|m| m.verify(signature, data)))
     }
 
-    // Add a new wallet address
+    // Add an externally supplied wallet address; prefer `derive_address` for wallet-owned addresses
     pub fn add_address(&mut self, address: Address) {
         self.addresses.push(WalletAddress::from(address));
     }
@@ -132,6 +311,99 @@ impl Wallet {
     }
 }
 
+// Persists whole wallets, encrypted under a single key id, in a KV namespace keyed by wallet id.
+// Loaded wallets have their signing keys decrypted and ready to sign.
+pub struct WalletStore {
+    store: PrefixedKVStore<Arc<dyn KVStore>>,
+    handler: Arc<EncryptHandler>,
+    key_id: Vec<u8>,
+}
+
+impl WalletStore {
+    pub fn new(store: Arc<dyn KVStore>, handler: Arc<EncryptHandler>, key_id: Vec<u8>) -> Self {
+        Self {
+            store: PrefixedKVStore::new(store, "WALLET:".into()),
+            handler,
+            key_id,
+        }
+    }
+
+    // Creates a new wallet and persists it before returning it
+    pub async fn create_wallet(&self) -> Result<Wallet, BigbotError> {
+        let mut wallet = Wallet::new_wallet().await;
+        self.save(&mut wallet).await?;
+        Ok(wallet)
+    }
+
+    // Encrypts the wallet, including its signing keys, and stores it under its id
+    pub async fn save(&self, wallet: &mut Wallet) -> Result<(), BigbotError> {
+        wallet
+            .seal_signing_keys(&self.handler, &self.key_id)
+            .await?;
+        let plaintext =
+            serde_json::to_vec(wallet).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+        let encrypted = self
+            .handler
+            .aes_encrypt_message(&self.key_id, &plaintext, [0u8; 8])
+            .await?;
+        self.store
+            .set(wallet.id.clone().into_bytes(), encrypted.into_bytes())
+            .await
+    }
+
+    // Loads and decrypts the wallet stored under `wallet_id`
+    pub async fn load(&self, wallet_id: &str) -> Result<Wallet, BigbotError> {
+        let encrypted = self
+            .store
+            .get(wallet_id.as_bytes())
+            .await?
+            .ok_or(BigbotError::WalletNotFound)?;
+        let plaintext = self
+            .handler
+            .aes_decrypt_message(&self.key_id, &encrypted)
+            .await?;
+        let mut wallet: Wallet = serde_json::from_slice(&plaintext).map_err(|e| {
+            BigbotError::InvalidInput(format!("Stored wallet {} is corrupt: {}", wallet_id, e))
+        })?;
+        wallet
+            .load_signing_keys(&self.handler, &self.key_id)
+            .await?;
+        Ok(wallet)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum WalletError {
+    #[error("Wallet cannot transact in {0}: no balance provider configured and it is not the base currency")]
+    UnsupportedCurrency(String),
+    #[error("Insufficient funds for {currency}: balance {balance}, payment {amount}")]
+    InsufficientFunds {
+        currency: String,
+        balance: u64,
+        amount: u64,
+    },
+    #[error("Payment of {amount} exceeds the {currency} threshold of {threshold}")]
+    ThresholdExceeded {
+        currency: String,
+        amount: u64,
+        threshold: u64,
+    },
+    #[error("Wallet has no addresses to pay from")]
+    NoAddresses,
+    #[error("Failed to sign payment: {0}")]
+    SigningFailed(#[from] BigbotError),
+    #[error("Payment provider error: {0}")]
+    ProviderError(String),
+}
+
+// A wallet can transact in its base currency and in any currency the user has a balance provider for
+fn is_supported_currency(wallet: &Wallet, currency: &str, user_data: &UserData) -> bool {
+    currency == wallet.base_currency
+        || user_data
+            .preferences
+            .contains_key(&format!("balance_provider_{}", currency))
+}
+
 // Function to make a payment with the wallet
 pub async fn make_payment_with_wallet(
     wallet: &Wallet,
@@ -139,35 +411,62 @@ pub async fn make_payment_with_wallet(
     amount: u64,
     currency: &str,
     user_data: &UserData,
-) -> Result<String, String> {
+) -> Result<String, WalletError> {
+    if !is_supported_currency(wallet, currency, user_data) {
+        return Err(WalletError::UnsupportedCurrency(currency.to_string()));
+    }
+
     // Check if the wallet has sufficient funds
-    let balance = get_wallet_balance(wallet, currency, user_data).await.map_err(|e| e.to_string())?;
+    let balance = get_wallet_balance(wallet, currency, user_data)
+        .await
+        .map_err(WalletError::ProviderError)?;
     if balance < amount {
-        return Err(format!("Insufficient funds in the wallet for currency: {}", currency));
+        return Err(WalletError::InsufficientFunds {
+            currency: currency.to_string(),
+            balance,
+            amount,
+        });
     }
 
     // Check if the payment amount exceeds the threshold for the currency
-    if let Some(threshold) = wallet.payment_thresholds.get(currency) {
-        if amount > *threshold {
-            return Err(format!("Payment amount exceeds the threshold for currency: {}", currency));
+    if let Some(&threshold) = wallet.payment_thresholds.get(currency) {
+        if amount > threshold {
+            return Err(WalletError::ThresholdExceeded {
+                currency: currency.to_string(),
+                amount,
+                threshold,
+            });
         }
     }
 
     // Determine the wallet address to use for the payment
-    let from_address = if wallet.addresses.contains(&WalletAddress::from(wallet.preferred_address.0)) {
+    let from_address = if wallet
+        .addresses
+        .contains(&WalletAddress::from(wallet.preferred_address.0))
+    {
         wallet.preferred_address.0
     } else {
         // Use a distributed approach to select the from address
-        let index = calculate_distributed_index(wallet, amount, user_data);
+        let index = calculate_distributed_index(wallet, amount, user_data)
+            .ok_or(WalletError::NoAddresses)?;
         wallet.addresses[index].0
     };
 
     // Sign the payment transaction
     let tx_data = create_transaction_data(from_address, to_address, amount, currency, user_data);
-    let signature = wallet.sign(&tx_data);
+    let signature = wallet.sign(&tx_data)?;
 
     // Send the payment transaction
-    let tx_hash = send_transaction(from_address, to_address, amount, currency, signature, user_data).await.map_err(|e| e.to_string())?;
+    let tx_hash = send_transaction(
+        from_address,
+        to_address,
+        amount,
+        currency,
+        signature,
+        user_data,
+    )
+    .await
+    .map_err(WalletError::ProviderError)?;
     Ok(tx_hash)
 }
 
@@ -178,46 +477,78 @@ struct UserWalletData {
     history: Vec<(SystemTime, String)>,
 }
 
-// Function to calculate the distributed index for selecting the from address
-fn calculate_distributed_index(wallet: &Wallet, amount: u64, user_data: &UserData) -> usize {
+// Function to calculate the distributed index for selecting the from address.
+// Returns `None` when the wallet has no addresses.
+fn calculate_distributed_index(
+    wallet: &Wallet,
+    amount: u64,
+    user_data: &UserData,
+) -> Option<usize> {
+    if wallet.addresses.is_empty() {
+        return None;
+    }
+
     // Create a deterministic seed based on the payment amount, user preferences, and current time
     let seed = format!(
         "{}-{}-{:?}",
         amount,
-        user_data.preferences.get("payment_seed").unwrap_or(&"default_seed".to_string()),
-        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+        user_data
+            .preferences
+            .get("payment_seed")
+            .unwrap_or(&"default_seed".to_string()),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
     );
 
-    // Create a HashMap to store the distribution weights for each address
-    let mut weights = HashMap::new();
-
     // Calculate the distribution weight for each address based on user profile and history
-    for (i, address) in wallet.addresses.iter().enumerate() {
-        let profile_weight = user_data.profile.get(&format!("address_{}", i)).unwrap_or(&"1".to_string()).parse::<u64>().unwrap_or(1);
-        let history_weight = user_data.history.iter().filter(|(_, addr)| addr == &address.0).count() as u64;
-        let total_weight = profile_weight * history_weight;
-        weights.insert(address, total_weight);
-    }
-
-    // Calculate the total weight sum
-    let total_weight_sum: u64 = weights.values().sum();
+    let weights: Vec<u64> = wallet
+        .addresses
+        .iter()
+        .enumerate()
+        .map(|(i, address)| {
+            let profile_weight = user_data
+                .profile
+                .get(&format!("address_{}", i))
+                .unwrap_or(&"1".to_string())
+                .parse::<u64>()
+                .unwrap_or(1);
+            let address = format!("{:?}", address.0);
+            let history_weight = user_data
+                .history
+                .iter()
+                .filter(|(_, addr)| *addr == address)
+                .count() as u64;
+            profile_weight.saturating_mul(history_weight)
+        })
+        .collect();
 
     // Generate a random value based on the seed
     let mut hasher = std::collections::hash_map::DefaultHasher::new();
     seed.hash(&mut hasher);
-    let random_value = hasher.finish() % total_weight_sum;
+    let random_value = hasher.finish();
+
+    // With no weight anywhere (e.g. no payment history yet), every address is equally likely
+    let total_weight_sum = weights
+        .iter()
+        .fold(0u64, |sum, weight| sum.saturating_add(*weight));
+    if total_weight_sum == 0 {
+        return Some((random_value % wallet.addresses.len() as u64) as usize);
+    }
 
     // Select the address based on the random value and distribution weights
-    let mut cumulative_weight = 0;
-    for (i, (address, weight)) in weights.iter().enumerate() {
-        cumulative_weight += weight;
-        if random_value < cumulative_weight {
-            return i;
+    let target = random_value % total_weight_sum;
+    let mut cumulative_weight = 0u64;
+    for (i, weight) in weights.iter().enumerate() {
+        cumulative_weight = cumulative_weight.saturating_add(*weight);
+        if target < cumulative_weight {
+            return Some(i);
         }
     }
 
     // Fallback to the last address if no address is selected
-    wallet.addresses.len() - 1
+    Some(wallet.addresses.len() - 1)
 }
 
 // Function to get the balance of a specific currency in the wallet
@@ -358,4 +689,244 @@ async fn retrieve_balance_from_default(wallet: &Wallet, currency: &str) -> Resul
 async fn send_transaction_with_default(from: Address, to: Address, amount: u64, currency: &str, signature: Vec<u8>) -> Result<String, String> {
     // Send the transaction using the default blockchain API
     Ok("default_tx_hash".to_string()) // Dummy transaction hash
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iam::did::{DIDDocument, KeyDidResolver};
+
+    fn empty_wallet() -> Wallet {
+        Wallet {
+            id: "did:example:123".to_string(),
+            public_key: "".to_string(),
+            did: "did:example:123".to_string(),
+            identity_doc: "".to_string(),
+            credentials: HashMap::new(),
+            encrypted_keys: vec![],
+            keys: vec![],
+            addresses: vec![],
+            preferred_address: WalletAddress::default(),
+            base_currency: "ETH".to_string(),
+            payment_thresholds: HashMap::new(),
+            derivation: None,
+            account_key: None,
+            master_seed: None,
+        }
+    }
+
+    #[test]
+    fn test_derive_address_is_reproducible_across_reloads() {
+        let seed = [42u8; 32];
+        let mut wallet = empty_wallet();
+        wallet.load_master_seed(&seed).unwrap();
+        let first = wallet.derive_address(0).unwrap();
+        let third = wallet.derive_address(2).unwrap();
+        assert_ne!(first, third);
+
+        let serialized = serde_json::to_string(&wallet).unwrap();
+        assert!(!serialized.contains("account_key"));
+
+        let mut reloaded: Wallet = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(reloaded.derivation, wallet.derivation);
+        assert!(reloaded.derive_address(2).is_err());
+
+        reloaded.load_master_seed(&seed).unwrap();
+        assert_eq!(reloaded.derive_address(2).unwrap(), third);
+        assert_eq!(reloaded.derive_address(0).unwrap(), first);
+        assert_eq!(reloaded.addresses.len(), 2);
+    }
+
+    #[test]
+    fn test_recovery_shares_restore_the_wallet() {
+        let mut wallet = empty_wallet();
+        wallet.load_master_seed(&[9u8; 32]).unwrap();
+        let address = wallet.derive_address(0).unwrap();
+        let shares = wallet.export_recovery_shares(2, 3).unwrap();
+
+        let mut restored: Wallet = serde_json::from_str(&serde_json::to_string(&wallet).unwrap()).unwrap();
+        assert!(restored.export_recovery_shares(2, 3).is_err());
+        assert!(restored.recover_from_shares(&shares[..1]).is_err());
+
+        restored.recover_from_shares(&[shares[2].clone(), shares[0].clone()]).unwrap();
+        assert_eq!(restored.derive_address(0).unwrap(), address);
+    }
+
+    #[test]
+    fn test_load_master_seed_rejects_mismatched_seed() {
+        let mut wallet = empty_wallet();
+        wallet.load_master_seed(&[1u8; 32]).unwrap();
+        wallet.derive_address(0).unwrap();
+
+        let mut reloaded: Wallet = serde_json::from_str(&serde_json::to_string(&wallet).unwrap()).unwrap();
+        assert!(reloaded.load_master_seed(&[2u8; 32]).is_err());
+    }
+
+    // Resolves every DID to the same document, so tests don't depend on a DID method
+    struct FixedDidResolver(DIDDocument);
+
+    #[async_trait::async_trait]
+    impl DidResolver for FixedDidResolver {
+        async fn resolve(&self, _did: &str) -> Result<DIDDocument, DidError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signature_round_trips_through_encrypted_keys() {
+        let handler = EncryptHandler::new(Arc::new(MemoryKVStore::default()));
+        let key_id = [7u8; 32];
+        let key = SigningKey::generate(&mut OsRng);
+        let mut wallet = empty_wallet();
+        wallet.did = DID::from_ed25519(&key.verifying_key()).0.did;
+        wallet
+            .add_signing_key(&handler, &key_id, key)
+            .await
+            .unwrap();
+
+        let signature = wallet.sign(b"payload").unwrap();
+        assert!(wallet
+            .verify(&KeyDidResolver, &signature, b"payload")
+            .await
+            .unwrap());
+        assert!(!wallet
+            .verify(&KeyDidResolver, &signature, b"tampered")
+            .await
+            .unwrap());
+
+        let serialized = serde_json::to_string(&wallet).unwrap();
+        let mut reloaded: Wallet = serde_json::from_str(&serialized).unwrap();
+        assert!(reloaded.sign(b"payload").is_err());
+
+        reloaded.load_signing_keys(&handler, &key_id).await.unwrap();
+        assert_eq!(reloaded.sign(b"payload").unwrap(), signature);
+    }
+
+    #[tokio::test]
+    async fn test_sign_without_keys_is_an_error() {
+        let wallet = empty_wallet();
+        assert!(matches!(
+            wallet.sign(b"payload"),
+            Err(BigbotError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            wallet.verify(&KeyDidResolver, &[0u8; 64], b"payload").await,
+            Err(DidError::UnsupportedMethod(_))
+        ));
+
+        let (_, document) = DID::generate();
+        let resolver = FixedDidResolver(document);
+        assert!(!wallet
+            .verify(&resolver, &[0u8; 64], b"payload")
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stored_credentials_decrypt_with_the_same_handler() {
+        let handler = EncryptHandler::new(Arc::new(MemoryKVStore::default()));
+        let key_id = [3u8; 32];
+        let vc = VerifiableCredential {
+            id: "urn:uuid:degree".to_string(),
+            issuer: "did:example:university".to_string(),
+            subject: "did:example:123".to_string(),
+            issuance_date: "2024-01-01T00:00:00Z".to_string(),
+            expiration_date: None,
+            credential_type: vec!["VerifiableCredential".to_string()],
+            credential_subject: serde_json::json!({ "degree": "BSc" }),
+            proof: None,
+            context: vec![],
+            types: vec![],
+        };
+
+        let mut wallet = empty_wallet();
+        wallet
+            .store_vc(&handler, &key_id, vc.clone())
+            .await
+            .unwrap();
+        assert_ne!(
+            wallet.credentials[&vc.id],
+            serde_json::to_string(&vc).unwrap()
+        );
+
+        let stored = wallet
+            .get_vc(&handler, &key_id, &vc.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.credential_subject, vc.credential_subject);
+        assert!(wallet
+            .get_vc(&handler, &key_id, "urn:uuid:missing")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    fn funded_wallet() -> Wallet {
+        let mut wallet = empty_wallet();
+        wallet.add_address(Address::from_low_u64_be(1));
+        wallet.add_address(Address::from_low_u64_be(2));
+        wallet.keys.push(SigningKey::generate(&mut OsRng));
+        wallet
+    }
+
+    #[test]
+    fn test_distributed_index_without_weights_stays_in_range() {
+        let wallet = funded_wallet();
+        let mut user_data = UserData::new();
+        user_data
+            .profile
+            .insert("address_0".to_string(), "0".to_string());
+        user_data
+            .profile
+            .insert("address_1".to_string(), "0".to_string());
+
+        for amount in 0..50 {
+            let index = calculate_distributed_index(&wallet, amount, &user_data).unwrap();
+            assert!(index < wallet.addresses.len());
+        }
+        assert_eq!(
+            calculate_distributed_index(&empty_wallet(), 1, &user_data),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_payment_rejects_unknown_currency() {
+        let wallet = funded_wallet();
+        let user_data = UserData::new();
+
+        let result =
+            make_payment_with_wallet(&wallet, Address::from_low_u64_be(9), 10, "DOGE", &user_data)
+                .await;
+        assert!(
+            matches!(result, Err(WalletError::UnsupportedCurrency(currency)) if currency == "DOGE")
+        );
+
+        let tx_hash =
+            make_payment_with_wallet(&wallet, Address::from_low_u64_be(9), 10, "ETH", &user_data)
+                .await
+                .unwrap();
+        assert_eq!(tx_hash, "default_tx_hash");
+    }
+
+    #[tokio::test]
+    async fn test_wallet_store_round_trips_signing_keys() {
+        let handler = Arc::new(EncryptHandler::new(Arc::new(MemoryKVStore::default())));
+        let wallets = WalletStore::new(Arc::new(MemoryKVStore::default()), handler, vec![4u8; 32]);
+        let created = wallets.create_wallet().await.unwrap();
+
+        let loaded = wallets.load(&created.id).await.unwrap();
+        assert_eq!(loaded.did, created.did);
+        let signature = loaded.sign(b"credential").unwrap();
+        assert!(created
+            .verify(&KeyDidResolver, &signature, b"credential")
+            .await
+            .unwrap());
+
+        assert!(matches!(
+            wallets.load("did:key:unknown").await,
+            Err(BigbotError::WalletNotFound)
+        ));
+    }
+}
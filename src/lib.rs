@@ -27,6 +27,7 @@ pub mod clients {
     pub mod kv;
     pub mod neo4j;
     pub mod postgres;
+    pub mod wal;
 }
 
 pub mod commons;
@@ -44,24 +45,40 @@ pub mod data_exchange {
 }
 
 pub mod data_streams {
+    pub mod buffered;
     pub mod cloudevents;
     pub mod combine;
     pub mod grpc;
     pub mod kafka;
     pub mod mock;
     pub mod mqtt;
+    pub mod postgres;
     pub mod topics;
+    pub mod window;
 }
 
 pub mod encryption {
     pub mod encryption;
+    pub mod shamir;
 }
 
 pub mod event;
 
+pub mod flows {
+    pub mod block_library;
+    pub mod blocks;
+    pub mod flowgorithm;
+    pub mod flows;
+    pub mod logic {
+        pub mod scheduling_logic;
+    }
+    pub mod sample_flow;
+}
+
 pub mod graphs {
     pub mod delegate_graph;
     pub mod event_graph;
+    pub mod graph_backend;
     pub mod identity_graph;
     pub mod message_entity_graph;
     pub mod nl_to_graph;
@@ -75,11 +92,13 @@ pub mod graphs {
 pub mod iam {
     pub mod did;
     pub mod group;
+    pub mod hd_key;
     pub mod iam;
     pub mod jwt;
     pub mod keycloak_provider;
     pub mod merkle_tree;
     pub mod public_key_store;
+    pub mod session;
     pub mod user;
     pub mod user_data;
     pub mod verifiable_credentials;
@@ -91,6 +110,7 @@ pub mod messaging {
     pub mod app_state;
     pub mod consensus;
     pub mod decentralised_messaging;
+    pub mod message_batches;
     pub mod message_classifier;
     pub mod message_encryption;
     pub mod message_hashmap;
@@ -106,14 +126,18 @@ pub mod messaging {
 pub mod provider_types {
     pub mod ai;
     pub mod charts;
+    pub mod chat_stream;
     pub mod payments;
     pub mod search;
 }
 
 pub mod providers {
     pub mod anthropic;
+    pub mod client;
     pub mod openai;
+    pub mod sse;
     pub mod telegram;
+    pub mod testing;
     pub mod wikipedia;
 }
 
@@ -135,5 +159,7 @@ pub mod utils {
     pub mod bigboterror;
     pub mod dlopen;
     pub mod file_storage;
+    pub mod metrics;
+    pub mod privacy;
     pub mod random;
 }
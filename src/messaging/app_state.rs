@@ -1,23 +1,297 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::{Arc, Mutex};
 
+use sha2::{Digest, Sha256};
+
+use crate::messaging::message_metadata::Gazetteer;
+use crate::messaging::message_routing::{ClassificationRules, GeofenceRoute, RouteDeduplicator};
+
+// Points each node gets on the hash ring; more points spread recipients more evenly
+pub const DEFAULT_VIRTUAL_NODES: usize = 100;
+
+// A consistent-hash ring. Every node is hashed onto the ring at `virtual_nodes` points and a key
+// belongs to the first point at or after its own hash, so adding or removing a node only moves
+// the keys on that node's arcs.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    virtual_nodes: usize,
+    ring: BTreeMap<u64, String>,
+    nodes: BTreeSet<String>,
+}
+
+impl HashRing {
+    pub fn new(virtual_nodes: usize) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            ring: BTreeMap::new(),
+            nodes: BTreeSet::new(),
+        }
+    }
+
+    // Returns false if the node was already on the ring
+    pub fn add_node(&mut self, node_id: &str) -> bool {
+        if !self.nodes.insert(node_id.to_string()) {
+            return false;
+        }
+        for point in 0..self.virtual_nodes {
+            self.ring.insert(
+                ring_hash(&format!("{}#{}", node_id, point)),
+                node_id.to_string(),
+            );
+        }
+        true
+    }
+
+    // Returns false if the node wasn't on the ring
+    pub fn remove_node(&mut self, node_id: &str) -> bool {
+        if !self.nodes.remove(node_id) {
+            return false;
+        }
+        self.ring.retain(|_, owner| owner != node_id);
+        true
+    }
+
+    pub fn node_for(&self, key: &str) -> Option<&str> {
+        let hash = ring_hash(key);
+        self.ring
+            .range(hash..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node_id)| node_id.as_str())
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &str> {
+        self.nodes.iter().map(String::as_str)
+    }
+
+    pub fn virtual_nodes(&self) -> usize {
+        self.virtual_nodes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl Default for HashRing {
+    fn default() -> Self {
+        Self::new(DEFAULT_VIRTUAL_NODES)
+    }
+}
+
+// Stable across processes and Rust versions, unlike `DefaultHasher`
+fn ring_hash(value: &str) -> u64 {
+    let digest = Sha256::digest(value.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+// The ring and the recipient -> node assignments looked up from it
+#[derive(Default)]
+struct Routing {
+    ring: HashRing,
+    assignments: HashMap<String, String>,
+}
+
 pub struct AppState {
-    routing_table: Arc<Mutex<HashMap<String, String>>>,
+    routing: Arc<Mutex<Routing>>,
+    route_dedup: RouteDeduplicator,
+    geofence_routes: Vec<GeofenceRoute>,
+    gazetteer: Gazetteer,
+    classification_rules: ClassificationRules,
     // Add other necessary fields
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
-            routing_table: Arc::new(Mutex::new(HashMap::new())),
+            routing: Arc::new(Mutex::new(Routing::default())),
+            route_dedup: RouteDeduplicator::default(),
+            geofence_routes: Vec::new(),
+            gazetteer: Gazetteer::default(),
+            classification_rules: ClassificationRules::default(),
             // Initialize other fields
         }
     }
 
+    // Rebuilds the ring with `virtual_nodes` points per node, keeping its members
+    pub fn with_virtual_nodes(self, virtual_nodes: usize) -> Self {
+        {
+            let mut routing = self.routing.lock().unwrap();
+            let mut ring = HashRing::new(virtual_nodes);
+            for node_id in routing.ring.nodes() {
+                ring.add_node(node_id);
+            }
+            routing.ring = ring;
+            routing.assignments.clear();
+        }
+        self
+    }
+
+    // Replaces the in-memory dedup store, e.g. with a shared store so all routers see the same ids
+    pub fn with_route_dedup(mut self, route_dedup: RouteDeduplicator) -> Self {
+        self.route_dedup = route_dedup;
+        self
+    }
+
+    pub fn route_dedup(&self) -> &RouteDeduplicator {
+        &self.route_dedup
+    }
+
+    pub fn with_geofence_routes(mut self, geofence_routes: Vec<GeofenceRoute>) -> Self {
+        self.geofence_routes = geofence_routes;
+        self
+    }
+
+    pub fn geofence_routes(&self) -> &[GeofenceRoute] {
+        &self.geofence_routes
+    }
+
+    // Places the GPE and LOC entities of routed messages for geofence checks
+    pub fn with_gazetteer(mut self, gazetteer: Gazetteer) -> Self {
+        self.gazetteer = gazetteer;
+        self
+    }
+
+    pub fn gazetteer(&self) -> &Gazetteer {
+        &self.gazetteer
+    }
+
+    pub fn with_classification_rules(mut self, classification_rules: ClassificationRules) -> Self {
+        self.classification_rules = classification_rules;
+        self
+    }
+
+    pub fn classification_rules(&self) -> &ClassificationRules {
+        &self.classification_rules
+    }
+
+    // Adds a routing node. Cached assignments are dropped since some recipients now belong to it.
+    pub fn add_node(&self, node_id: &str) -> bool {
+        let mut routing = self.routing.lock().unwrap();
+        let added = routing.ring.add_node(node_id);
+        if added {
+            routing.assignments.clear();
+        }
+        added
+    }
+
+    // Removes a routing node. Cached assignments are dropped since its recipients move elsewhere.
+    pub fn remove_node(&self, node_id: &str) -> bool {
+        let mut routing = self.routing.lock().unwrap();
+        let removed = routing.ring.remove_node(node_id);
+        if removed {
+            routing.assignments.clear();
+        }
+        removed
+    }
+
+    // The node serving `recipient`, cached until the ring's membership changes. None if there
+    // are no nodes.
+    pub fn node_for(&self, recipient: &str) -> Option<String> {
+        let mut routing = self.routing.lock().unwrap();
+        if let Some(node_id) = routing.assignments.get(recipient) {
+            return Some(node_id.clone());
+        }
+        let node_id = routing.ring.node_for(recipient)?.to_string();
+        routing
+            .assignments
+            .insert(recipient.to_string(), node_id.clone());
+        Some(node_id)
+    }
+
+    // The cached recipient -> node assignments
     pub async fn get_routing_table(&self) -> HashMap<String, String> {
-        let routing_table = self.routing_table.lock().unwrap();
-        routing_table.clone()
+        let routing = self.routing.lock().unwrap();
+        routing.assignments.clone()
     }
 
     // Implement other methods as needed
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<String> {
+        (0..2000).map(|i| format!("recipient-{}", i)).collect()
+    }
+
+    #[test]
+    fn test_removing_a_node_only_remaps_its_keys() {
+        let mut ring = HashRing::default();
+        for node_id in ["a", "b", "c", "d"] {
+            ring.add_node(node_id);
+        }
+        let before: Vec<String> = keys()
+            .iter()
+            .map(|key| ring.node_for(key).unwrap().to_string())
+            .collect();
+
+        assert!(ring.remove_node("c"));
+        for (key, previous) in keys().iter().zip(&before) {
+            let current = ring.node_for(key).unwrap();
+            if previous == "c" {
+                assert_ne!(current, "c");
+            } else {
+                assert_eq!(current, previous, "{} moved off a node that stayed", key);
+            }
+        }
+        assert!(!ring.remove_node("c"));
+    }
+
+    #[test]
+    fn test_virtual_nodes_spread_load_evenly() {
+        let mut ring = HashRing::new(DEFAULT_VIRTUAL_NODES);
+        for node_id in ["a", "b", "c", "d"] {
+            ring.add_node(node_id);
+        }
+        let mut load: HashMap<&str, usize> = HashMap::new();
+        let keys = keys();
+        for key in &keys {
+            *load.entry(ring.node_for(key).unwrap()).or_default() += 1;
+        }
+
+        // Each of the 4 nodes should get close to a quarter of the keys
+        for node_id in ["a", "b", "c", "d"] {
+            let share = load[node_id] as f64 / keys.len() as f64;
+            assert!(
+                (0.15..0.35).contains(&share),
+                "{} got {:.2} of the keys",
+                node_id,
+                share
+            );
+        }
+    }
+
+    #[test]
+    fn test_assignments_are_invalidated_on_membership_change() {
+        let app_state = AppState::new();
+        assert_eq!(app_state.node_for("bob"), None);
+
+        app_state.add_node("a");
+        assert_eq!(app_state.node_for("bob").as_deref(), Some("a"));
+        assert!(app_state
+            .routing
+            .lock()
+            .unwrap()
+            .assignments
+            .contains_key("bob"));
+
+        app_state.add_node("b");
+        assert!(app_state.routing.lock().unwrap().assignments.is_empty());
+        let expected = app_state
+            .routing
+            .lock()
+            .unwrap()
+            .ring
+            .node_for("bob")
+            .map(str::to_string);
+        assert_eq!(app_state.node_for("bob"), expected);
+
+        app_state.remove_node("a");
+        app_state.remove_node("b");
+        assert_eq!(app_state.node_for("bob"), None);
+    }
+}
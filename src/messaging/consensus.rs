@@ -8,6 +8,9 @@ use crate::messaging::message::Message;
 use crate::messaging::message_hashmap::MessageMetadata;
 use crate::messaging::message_routing::route_message;
 use crate::messaging::app_state::AppState;
+use crate::utils::bigboterror::BigbotError;
+
+pub use distributed_hash::{DistributedHash, QuorumConfig};
 
 mod replication {
     use tikv_client::RawClient;
@@ -42,15 +45,176 @@ mod local_storage {
     }
 }
 
-mod distributed_hash {
+pub mod distributed_hash {
+    //! Quorum replication of message hashes.
+    //!
+    //! Every distributed hash endpoint is an independent node holding `message id -> content hash`.
+    //! A write is durable once `write_quorum` nodes acknowledge it. A read needs answers from
+    //! `read_quorum` nodes, and a message validates only when at least `read_quorum` of them hold
+    //! its hash, so it must have been replicated first. Quorums must satisfy
+    //! `read_quorum + write_quorum > nodes`, which makes every read overlap the last successful
+    //! write in at least one node.
+
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use futures::future::join_all;
+    use tikv_client::RawClient;
+    use uuid::Uuid;
+
+    use crate::clients::kv::KVStore;
+    use crate::utils::bigboterror::BigbotError;
+
+    const MESSAGE_HASH_PREFIX: &str = "message_hash:";
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct QuorumConfig {
+        pub write_quorum: usize,
+        pub read_quorum: usize,
+    }
+
+    impl QuorumConfig {
+        /// Simple majority for both reads and writes over `nodes` nodes.
+        pub fn majority(nodes: usize) -> Self {
+            let quorum = nodes / 2 + 1;
+            Self {
+                write_quorum: quorum,
+                read_quorum: quorum,
+            }
+        }
+
+        /// Rejects quorums of zero and quorums whose reads need not overlap the last write.
+        pub fn validate(&self, nodes: usize) -> Result<(), BigbotError> {
+            if self.write_quorum == 0 || self.read_quorum == 0 {
+                return Err(BigbotError::InvalidInput(
+                    "Read and write quorums must be at least 1".to_string(),
+                ));
+            }
+            if self.read_quorum + self.write_quorum <= nodes {
+                return Err(BigbotError::InvalidInput(format!(
+                    "Read quorum {} and write quorum {} do not overlap across {} nodes",
+                    self.read_quorum, self.write_quorum, nodes
+                )));
+            }
+            Ok(())
+        }
+    }
+
     pub struct DistributedHash {
-        // Implement distributed hash logic
+        nodes: Vec<Arc<dyn KVStore>>,
+        quorum: QuorumConfig,
     }
 
     impl DistributedHash {
-        pub async fn new(distributed_hash_endpoints: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
-            // Initialize distributed hash
-            Ok(Self {})
+        /// Connects one TiKV node per endpoint and uses majority quorums.
+        pub async fn connect(distributed_hash_endpoints: &[String]) -> Result<Self, BigbotError> {
+            let mut nodes: Vec<Arc<dyn KVStore>> =
+                Vec::with_capacity(distributed_hash_endpoints.len());
+            for endpoint in distributed_hash_endpoints {
+                let client = RawClient::new(vec![endpoint.clone()])
+                    .await
+                    .map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+                nodes.push(Arc::new(TikvNode(client)));
+            }
+            let quorum = QuorumConfig::majority(nodes.len());
+            Self::new(nodes, quorum)
+        }
+
+        pub fn new(
+            nodes: Vec<Arc<dyn KVStore>>,
+            quorum: QuorumConfig,
+        ) -> Result<Self, BigbotError> {
+            quorum.validate(nodes.len())?;
+            Ok(Self { nodes, quorum })
+        }
+
+        pub fn with_quorum(mut self, quorum: QuorumConfig) -> Result<Self, BigbotError> {
+            quorum.validate(self.nodes.len())?;
+            self.quorum = quorum;
+            Ok(self)
+        }
+
+        pub fn quorum(&self) -> QuorumConfig {
+            self.quorum
+        }
+
+        /// Writes the hash to every node and succeeds once `write_quorum` of them acknowledge it.
+        pub async fn replicate(&self, message_id: &Uuid, hash: &str) -> Result<(), BigbotError> {
+            let key = hash_key(message_id);
+            let writes = self
+                .nodes
+                .iter()
+                .map(|node| node.set(key.clone(), hash.as_bytes().to_vec()));
+            let acks = join_all(writes)
+                .await
+                .into_iter()
+                .filter(Result::is_ok)
+                .count();
+            if acks < self.quorum.write_quorum {
+                return Err(BigbotError::QuorumNotReached {
+                    required: self.quorum.write_quorum,
+                    reached: acks,
+                });
+            }
+            Ok(())
+        }
+
+        /// Reads the stored hash from every node and returns true only if at least `read_quorum`
+        /// of the answering nodes hold `hash`.
+        pub async fn validate(&self, message_id: &Uuid, hash: &str) -> Result<bool, BigbotError> {
+            let key = hash_key(message_id);
+            let reads = join_all(self.nodes.iter().map(|node| node.get(&key))).await;
+            let answers: Vec<Option<Vec<u8>>> = reads.into_iter().filter_map(Result::ok).collect();
+            if answers.len() < self.quorum.read_quorum {
+                return Err(BigbotError::QuorumNotReached {
+                    required: self.quorum.read_quorum,
+                    reached: answers.len(),
+                });
+            }
+            let agreeing = answers
+                .iter()
+                .flatten()
+                .filter(|stored| stored.as_slice() == hash.as_bytes())
+                .count();
+            Ok(agreeing >= self.quorum.read_quorum)
+        }
+    }
+
+    fn hash_key(message_id: &Uuid) -> Vec<u8> {
+        format!("{}{}", MESSAGE_HASH_PREFIX, message_id).into_bytes()
+    }
+
+    /// A single distributed hash endpoint backed by its own TiKV cluster.
+    struct TikvNode(RawClient);
+
+    #[async_trait]
+    impl KVStore for TikvNode {
+        async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BigbotError> {
+            self.0
+                .get(key.to_vec())
+                .await
+                .map_err(|e| BigbotError::DatabaseError(e.to_string()))
+        }
+
+        async fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), BigbotError> {
+            self.0
+                .put(key, value)
+                .await
+                .map_err(|e| BigbotError::DatabaseError(e.to_string()))
+        }
+
+        async fn delete(&self, key: &[u8]) -> Result<(), BigbotError> {
+            self.0
+                .delete(key.to_vec())
+                .await
+                .map_err(|e| BigbotError::DatabaseError(e.to_string()))
+        }
+
+        async fn keys(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, BigbotError> {
+            let _ = prefix;
+            Err(BigbotError::DatabaseError(
+                "Distributed hash nodes do not support key listing".to_string(),
+            ))
         }
     }
 }
@@ -96,6 +260,15 @@ impl SyncState {
     }
 }
 
+// Sealed messages carry their hash; fall back to hashing the content for unsealed ones.
+fn message_hash(message: &Message) -> Result<String, BigbotError> {
+    if message.hash.is_empty() {
+        message.content_hash()
+    } else {
+        Ok(message.hash.clone())
+    }
+}
+
 pub struct ConsensusLayer {
     tikv_client: RawClient,
     local_storage: local_storage::LocalStorage,
@@ -116,7 +289,7 @@ impl ConsensusLayer {
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let tikv_client = RawClient::new(tikv_endpoints).await?;
         let local_storage = local_storage::LocalStorage::new(local_storage_path)?;
-        let distributed_hash = distributed_hash::DistributedHash::new(distributed_hash_endpoints).await?;
+        let distributed_hash = distributed_hash::DistributedHash::connect(distributed_hash_endpoints).await?;
         let zkp = zkp::ZKP::new()?;
         let replication_manager = replication::ReplicationManager::new(tikv_client.clone(), 3).await?;
         let sync_state = Arc::new(Mutex::new(SyncState::new()));
@@ -134,18 +307,24 @@ impl ConsensusLayer {
         })
     }
 
-    pub async fn validate_message(&self, message: &Message) -> Result<bool, Box<dyn std::error::Error>> {
-        // Perform message validation using the necessary components
-        // Example validation logic:
-        let is_valid = true; // Replace with actual validation logic
-        Ok(is_valid)
+    /// Overrides the majority quorums used for message hash replication.
+    pub fn with_quorum(mut self, quorum: QuorumConfig) -> Result<Self, BigbotError> {
+        self.distributed_hash = self.distributed_hash.with_quorum(quorum)?;
+        Ok(self)
     }
 
-    pub async fn replicate_message(&self, message: &Message) -> Result<(), Box<dyn std::error::Error>> {
-        // Perform message replication using the replication_manager
-        // Example replication logic:
-        self.replication_manager.replicate_message(message).await?;
-        Ok(())
+    /// Checks the message hash against a read quorum of distributed hash nodes. Returns false
+    /// unless a quorum holds this hash for the message id.
+    pub async fn validate_message(&self, message: &Message) -> Result<bool, BigbotError> {
+        let hash = message_hash(message)?;
+        self.distributed_hash.validate(&message.id, &hash).await
+    }
+
+    /// Writes the message hash to the distributed hash nodes, failing unless a write quorum
+    /// acknowledges it.
+    pub async fn replicate_message(&self, message: &Message) -> Result<(), BigbotError> {
+        let hash = message_hash(message)?;
+        self.distributed_hash.replicate(&message.id, &hash).await
     }
 
     pub async fn route_message(&self, message: Message) -> Result<(), Box<dyn std::error::Error>> {
@@ -165,3 +344,94 @@ impl ConsensusLayer {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::kv::{KVStore, MemoryKVStore};
+
+    fn nodes(count: usize) -> Vec<Arc<dyn KVStore>> {
+        (0..count)
+            .map(|_| Arc::new(MemoryKVStore::default()) as Arc<dyn KVStore>)
+            .collect()
+    }
+
+    async fn overwrite(nodes: &[Arc<dyn KVStore>], message_id: &Uuid, hash: &str) {
+        let key = format!("message_hash:{}", message_id);
+        for node in nodes {
+            node.set(key.clone().into_bytes(), hash.as_bytes().to_vec())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_minority_disagreement_still_validates() {
+        let nodes = nodes(5);
+        let hash = DistributedHash::new(nodes.clone(), QuorumConfig::majority(5)).unwrap();
+        let message_id = Uuid::new_v4();
+        // A message that was never replicated has no agreeing answers
+        assert!(!hash.validate(&message_id, "abc").await.unwrap());
+
+        hash.replicate(&message_id, "abc").await.unwrap();
+        overwrite(&nodes[..2], &message_id, "tampered").await;
+
+        assert!(hash.validate(&message_id, "abc").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_majority_disagreement_fails_validation() {
+        let nodes = nodes(5);
+        let hash = DistributedHash::new(nodes.clone(), QuorumConfig::majority(5)).unwrap();
+        let message_id = Uuid::new_v4();
+        hash.replicate(&message_id, "abc").await.unwrap();
+        overwrite(&nodes[..3], &message_id, "tampered").await;
+
+        assert!(!hash.validate(&message_id, "abc").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_quorum_is_an_error() {
+        let hash = DistributedHash::new(
+            nodes(2),
+            QuorumConfig {
+                write_quorum: 3,
+                read_quorum: 3,
+            },
+        )
+        .unwrap();
+        let message_id = Uuid::new_v4();
+
+        let write = hash.replicate(&message_id, "abc").await;
+        assert!(matches!(
+            write,
+            Err(BigbotError::QuorumNotReached {
+                required: 3,
+                reached: 2
+            })
+        ));
+        let read = hash.validate(&message_id, "abc").await;
+        assert!(matches!(
+            read,
+            Err(BigbotError::QuorumNotReached {
+                required: 3,
+                reached: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_quorums_must_overlap() {
+        assert!(DistributedHash::new(nodes(3), QuorumConfig::majority(3)).is_ok());
+        for (write_quorum, read_quorum) in [(0, 3), (3, 0), (1, 2), (2, 1)] {
+            let quorum = QuorumConfig {
+                write_quorum,
+                read_quorum,
+            };
+            assert!(matches!(
+                DistributedHash::new(nodes(3), quorum),
+                Err(BigbotError::InvalidInput(_))
+            ));
+        }
+    }
+}
@@ -16,14 +16,26 @@ struct CustomClient {
     // Implement the necessary fields and methods
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub enum Intent {
+    #[default]
     TextMessage,
     Payment,
     GroupInvitation,
     // Add more intents as needed
 }
 
+// Parses the intent names callers pass as strings; anything unrecognised is a plain text message
+impl From<String> for Intent {
+    fn from(intent: String) -> Self {
+        match intent.to_ascii_lowercase().replace(['_', '-', ' '], "").as_str() {
+            "payment" => Intent::Payment,
+            "groupinvitation" => Intent::GroupInvitation,
+            _ => Intent::TextMessage,
+        }
+    }
+}
+
 struct AppState {
     etcd_client: Arc<RwLock<KvClient>>,
     watch_client: Arc<RwLock<WatchClient>>,
@@ -1,14 +1,23 @@
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use actix_web::web::Data;
 use async_graphql::connection::Edge;
 
 use crate::messaging::message_metadata::MessageMetadata;
 use crate::graphs::nl_to_graph::EntityGraphImpl;
 use crate::messaging::decentralised_messaging::{Intent};
 use crate::provider_types::payments::Payment;
+use crate::encryption::encryption::hash_message;
+use crate::utils::bigboterror::BigbotError;
 
+/// The one message type shared by the channel store, the router and the providers.
+///
+/// Every message has an `id`, `channel_id`, `sender`, `recipient`, `content`, `timestamp`,
+/// `intent`, `metadata` and `entity_graph`. Messages that don't belong to a channel, such as those
+/// arriving on the router's ingest topic, use `Uuid::nil()` as their `channel_id`.
+///
+/// The remaining fields are optional: they default to empty when missing from serialized input,
+/// and producers that have nothing to put in them leave them empty.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
     pub id: Uuid,
@@ -17,21 +26,99 @@ pub struct Message {
     pub recipient: String,
     pub content: String,
     pub timestamp: chrono::DateTime<Utc>,
+    /// Set when the content has been edited after sending.
+    #[serde(default)]
     pub edited_at: Option<chrono::DateTime<Utc>>,
+    /// Filled in by the channel store when the message is sealed; see `content_hash`.
+    #[serde(default)]
     pub hash: String,
     pub metadata: MessageMetadata,
+    #[serde(default)]
     pub feedback_weights: Vec<f32>,
+    /// The plain text the router classifies, when it differs from `content`.
+    #[serde(default)]
     pub text: String,
     pub intent: Intent,
+    #[serde(default)]
     pub payment: Option<Payment>,
+    #[serde(default)]
     pub nonce: u64,
+    #[serde(default)]
     pub name: String,
-    pub data: Vec<Data<String>>,
+    /// Raw payload bytes for messages that carry more than text.
+    #[serde(default)]
+    pub data: Vec<u8>,
+    #[serde(default)]
     pub header: String,
+    #[serde(default)]
     pub body: String,
+    #[serde(default)]
     pub contexts: Vec<i32>,
+    #[serde(default)]
     pub values: Vec<String>,
     pub entity_graph: EntityGraphImpl,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+impl Message {
+    /// Hashes `content` exactly as held, which for a stored message is the ciphertext. Stored
+    /// messages carry this in `hash`; decrypted copies no longer match it.
+    pub fn content_hash(&self) -> Result<String, BigbotError> {
+        hash_message(&self.content)
+    }
+
+    /// A text message sent now, with a fresh id, no channel and every optional field empty.
+    pub fn new(sender: &str, recipient: &str, content: &str) -> Self {
+        Message {
+            id: Uuid::new_v4(),
+            channel_id: Uuid::nil(),
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            content: content.to_string(),
+            timestamp: Utc::now(),
+            edited_at: None,
+            hash: String::new(),
+            metadata: MessageMetadata::new(),
+            feedback_weights: Vec::new(),
+            text: String::new(),
+            intent: Intent::TextMessage,
+            payment: None,
+            nonce: 0,
+            name: String::new(),
+            data: Vec::new(),
+            header: String::new(),
+            body: String::new(),
+            contexts: Vec::new(),
+            values: Vec::new(),
+            entity_graph: EntityGraphImpl::new(),
+            attachments: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Message {
+    /// A text message with a fresh id, no channel and every optional field empty. Tests adjust it
+    /// with struct update syntax rather than spelling out every field.
+    pub(crate) fn for_test(sender: &str, recipient: &str, content: &str) -> Self {
+        Message::new(sender, recipient, content)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Attachment {
+    pub content_type: String,
+    pub content: String,
+    pub file_path: Option<String>,
+}
+
+impl Attachment {
+    // Text-bearing attachments carry user-authored text that may contain PII
+    pub fn is_text(&self) -> bool {
+        let content_type = self.content_type.to_ascii_lowercase();
+        content_type.starts_with("text/") || content_type == "application/json"
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,430 @@
+//! Commits a channel's message hashes to Merkle roots.
+//!
+//! Every stored message's hash joins its channel's open batch. Once the batch holds the channel's
+//! `message_hash_batch_size` hashes, they are folded into a Merkle tree and the root is persisted
+//! under `/channels/{id}/roots/{batch_no}`. A `BatchProof` then shows that one message belongs to
+//! a committed batch, so a client holding the root can check a message without the rest of the
+//! channel.
+//!
+//! The open batch record is the only thing writers contend on, and it is only ever replaced with a
+//! compare-and-swap, so any number of nodes can append to the same channel. A batch that fills up
+//! is sealed into that record in the same swap; its root and index entries are written afterwards
+//! and rewritten by the next append if the writer died first.
+
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::clients::kv::KVStore;
+use crate::iam::merkle_tree::{hash_leaf, verify_proof, Hash, MerkleProof, MerkleTree};
+use crate::utils::bigboterror::BigbotError;
+
+// How many times an append re-reads the open batch after losing a race for it
+const MAX_APPEND_ATTEMPTS: usize = 16;
+
+// The leaf a message contributes to its batch. It covers the id as well as the content hash, so
+// a proof for one message can't be replayed for another with the same content.
+pub fn message_leaf(message_id: &Uuid, hash: &str) -> Hash {
+    let mut data = message_id.as_bytes().to_vec();
+    data.extend_from_slice(hash.as_bytes());
+    hash_leaf(&data)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct BatchEntry {
+    message_id: Uuid,
+    hash: String,
+}
+
+// The batch still being filled, and the number the next committed batch will get. `sealed` holds
+// the last full batch until its root and index have been written.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct OpenBatch {
+    next_batch_no: u64,
+    entries: Vec<BatchEntry>,
+    #[serde(default)]
+    sealed: Option<(u64, CommittedBatch)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CommittedBatch {
+    root: Hash,
+    entries: Vec<BatchEntry>,
+}
+
+fn batch_tree(entries: &[BatchEntry]) -> MerkleTree {
+    let mut tree = MerkleTree::new();
+    for entry in entries {
+        tree.push_leaf(message_leaf(&entry.message_id, &entry.hash));
+    }
+    tree
+}
+
+// Shows that the message whose leaf is `leaf` was committed in batch `batch_no` under `root`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchProof {
+    pub batch_no: u64,
+    pub root: Hash,
+    pub leaf: Hash,
+    pub proof: MerkleProof,
+}
+
+impl BatchProof {
+    // Checks the proof against its own root; callers should also compare `root` with the one
+    // they trust for `batch_no`
+    pub fn verify(&self) -> bool {
+        verify_proof(&self.root, &self.leaf, &self.proof)
+    }
+
+    pub fn verify_message(&self, message_id: &Uuid, hash: &str) -> bool {
+        self.leaf == message_leaf(message_id, hash) && self.verify()
+    }
+}
+
+// The store must support `compare_and_swap`
+pub struct MessageBatches {
+    store: Arc<dyn KVStore>,
+}
+
+impl MessageBatches {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self { store }
+    }
+
+    // Adds a stored message's hash to the channel's open batch and commits the batch once it holds
+    // `batch_size` hashes, returning the committed batch number. A `batch_size` of 0 disables
+    // batching for the channel.
+    pub async fn append(
+        &self,
+        channel_id: Uuid,
+        batch_size: usize,
+        message_id: Uuid,
+        hash: &str,
+    ) -> Result<Option<u64>, BigbotError> {
+        if batch_size == 0 {
+            return Ok(None);
+        }
+        let open_key = open_batch_key(channel_id);
+        for _ in 0..MAX_APPEND_ATTEMPTS {
+            let current = self.store.get(open_key.as_bytes()).await?;
+            let mut open: OpenBatch = match &current {
+                Some(value) => decode(&open_key, value)?,
+                None => OpenBatch::default(),
+            };
+            // The writer that sealed this batch may have stopped before committing it
+            if let Some((batch_no, batch)) = open.sealed.take() {
+                self.commit(channel_id, batch_no, &batch).await?;
+            }
+            open.entries.push(BatchEntry {
+                message_id,
+                hash: hash.to_string(),
+            });
+            let mut committed = None;
+            if open.entries.len() >= batch_size {
+                let batch_no = open.next_batch_no;
+                let entries = std::mem::take(&mut open.entries);
+                let batch = CommittedBatch {
+                    root: batch_tree(&entries).root(),
+                    entries,
+                };
+                open.sealed = Some((batch_no, batch));
+                open.next_batch_no += 1;
+                committed = Some(batch_no);
+            }
+
+            let value = encode(&open)?;
+            if !self
+                .store
+                .compare_and_swap(open_key.as_bytes().to_vec(), current, value)
+                .await?
+            {
+                // Another writer changed the open batch since it was read
+                continue;
+            }
+            if let Some((batch_no, batch)) = &open.sealed {
+                self.commit(channel_id, *batch_no, batch).await?;
+            }
+            return Ok(committed);
+        }
+        Err(BigbotError::DatabaseError(format!(
+            "Gave up appending to channel {} after {} conflicting writes",
+            channel_id, MAX_APPEND_ATTEMPTS
+        )))
+    }
+
+    // Writes a sealed batch's root before its index entries, so an indexed message always has a
+    // root. Rewriting the same batch is harmless.
+    async fn commit(
+        &self,
+        channel_id: Uuid,
+        batch_no: u64,
+        batch: &CommittedBatch,
+    ) -> Result<(), BigbotError> {
+        self.write(&root_key(channel_id, batch_no), batch).await?;
+        for entry in &batch.entries {
+            self.write(&batch_index_key(channel_id, &entry.message_id), &batch_no)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn committed_root(
+        &self,
+        channel_id: Uuid,
+        batch_no: u64,
+    ) -> Result<Option<Hash>, BigbotError> {
+        let batch: Option<CommittedBatch> = self.read(&root_key(channel_id, batch_no)).await?;
+        Ok(batch.map(|batch| batch.root))
+    }
+
+    // None while the message is still in the open batch, or if it was never appended
+    pub async fn proof(
+        &self,
+        channel_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<Option<BatchProof>, BigbotError> {
+        let Some(batch_no) = self
+            .read::<u64>(&batch_index_key(channel_id, &message_id))
+            .await?
+        else {
+            return Ok(None);
+        };
+        let batch: CommittedBatch = self
+            .read(&root_key(channel_id, batch_no))
+            .await?
+            .ok_or_else(|| {
+                BigbotError::DatabaseError(format!(
+                    "Batch {} of channel {} is indexed but has no root",
+                    batch_no, channel_id
+                ))
+            })?;
+        let Some(index) = batch
+            .entries
+            .iter()
+            .position(|entry| entry.message_id == message_id)
+        else {
+            return Ok(None);
+        };
+        let tree = batch_tree(&batch.entries);
+        let proof = tree.proof(index as u64).ok_or_else(|| {
+            BigbotError::UnexpectedError(format!(
+                "No proof for leaf {} of batch {}",
+                index, batch_no
+            ))
+        })?;
+        Ok(Some(BatchProof {
+            batch_no,
+            root: batch.root,
+            leaf: tree.leaves()[index],
+            proof,
+        }))
+    }
+
+    async fn read<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, BigbotError> {
+        match self.store.get(key.as_bytes()).await? {
+            Some(value) => decode(key, &value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn write<T: Serialize>(&self, key: &str, value: &T) -> Result<(), BigbotError> {
+        self.store
+            .set(key.as_bytes().to_vec(), encode(value)?)
+            .await
+    }
+}
+
+fn decode<T: DeserializeOwned>(key: &str, value: &[u8]) -> Result<T, BigbotError> {
+    serde_json::from_slice(value)
+        .map_err(|e| BigbotError::DatabaseError(format!("Corrupt batch record at {}: {}", key, e)))
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BigbotError> {
+    serde_json::to_vec(value).map_err(|e| BigbotError::InvalidInput(e.to_string()))
+}
+
+fn open_batch_key(channel_id: Uuid) -> String {
+    format!("/channels/{}/open_batch", channel_id)
+}
+
+fn root_key(channel_id: Uuid, batch_no: u64) -> String {
+    format!("/channels/{}/roots/{}", channel_id, batch_no)
+}
+
+fn batch_index_key(channel_id: Uuid, message_id: &Uuid) -> String {
+    format!("/channels/{}/batch_of/{}", channel_id, message_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::kv::MemoryKVStore;
+
+    fn batches() -> MessageBatches {
+        MessageBatches::new(Arc::new(MemoryKVStore::default()))
+    }
+
+    #[tokio::test]
+    async fn test_full_batch_is_committed_with_a_proof_per_message() {
+        let batches = batches();
+        let channel_id = Uuid::new_v4();
+        let batch_size = 5;
+        let messages: Vec<(Uuid, String)> = (0..batch_size)
+            .map(|i| (Uuid::new_v4(), format!("hash-{}", i)))
+            .collect();
+
+        for (i, (message_id, hash)) in messages.iter().enumerate() {
+            let committed = batches
+                .append(channel_id, batch_size, *message_id, hash)
+                .await
+                .unwrap();
+            if i + 1 < batch_size {
+                assert_eq!(committed, None);
+                assert_eq!(batches.proof(channel_id, *message_id).await.unwrap(), None);
+            } else {
+                assert_eq!(committed, Some(0));
+            }
+        }
+
+        let root = batches
+            .committed_root(channel_id, 0)
+            .await
+            .unwrap()
+            .unwrap();
+        for (message_id, hash) in &messages {
+            let proof = batches
+                .proof(channel_id, *message_id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(proof.batch_no, 0);
+            assert_eq!(proof.root, root);
+            assert!(verify_proof(
+                &root,
+                &message_leaf(message_id, hash),
+                &proof.proof
+            ));
+            assert!(proof.verify_message(message_id, hash));
+            // An edited message no longer matches what was committed
+            assert!(!proof.verify_message(message_id, "edited"));
+        }
+
+        // The next message opens batch 1
+        let next = Uuid::new_v4();
+        assert_eq!(
+            batches
+                .append(channel_id, batch_size, next, "hash-5")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(batches.committed_root(channel_id, 1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_zero_batch_size_disables_batching() {
+        let batches = batches();
+        let channel_id = Uuid::new_v4();
+        let message_id = Uuid::new_v4();
+        assert_eq!(
+            batches
+                .append(channel_id, 0, message_id, "hash")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(batches.proof(channel_id, message_id).await.unwrap(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_writers_sharing_a_store_commit_every_message_once() {
+        let store: Arc<dyn KVStore> = Arc::new(MemoryKVStore::default());
+        let channel_id = Uuid::new_v4();
+        let batch_size = 4;
+        let writers = 4;
+        let per_writer = 8;
+
+        let mut handles = Vec::new();
+        for _ in 0..writers {
+            // One MessageBatches per writer, as on separate nodes
+            let batches = MessageBatches::new(store.clone());
+            handles.push(tokio::spawn(async move {
+                let mut appended = Vec::new();
+                let mut committed = Vec::new();
+                for i in 0..per_writer {
+                    let message_id = Uuid::new_v4();
+                    let hash = format!("hash-{}", i);
+                    if let Some(batch_no) = batches
+                        .append(channel_id, batch_size, message_id, &hash)
+                        .await
+                        .unwrap()
+                    {
+                        committed.push(batch_no);
+                    }
+                    appended.push((message_id, hash));
+                }
+                (appended, committed)
+            }));
+        }
+
+        let mut appended = Vec::new();
+        let mut committed = Vec::new();
+        for handle in handles {
+            let (messages, batch_nos) = handle.await.unwrap();
+            appended.extend(messages);
+            committed.extend(batch_nos);
+        }
+        committed.sort();
+        let batch_count = (writers * per_writer / batch_size) as u64;
+        assert_eq!(committed, (0..batch_count).collect::<Vec<_>>());
+
+        let batches = MessageBatches::new(store);
+        for (message_id, hash) in &appended {
+            let proof = batches
+                .proof(channel_id, *message_id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(proof.verify_message(message_id, hash));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sealed_batch_left_by_a_failed_writer_is_committed_by_the_next_append() {
+        let store: Arc<dyn KVStore> = Arc::new(MemoryKVStore::default());
+        let batches = MessageBatches::new(store);
+        let channel_id = Uuid::new_v4();
+        let sealed_id = Uuid::new_v4();
+        let entries = vec![BatchEntry {
+            message_id: sealed_id,
+            hash: "hash-0".to_string(),
+        }];
+        // A writer sealed batch 0 and stopped before writing its root or index
+        let open = OpenBatch {
+            next_batch_no: 1,
+            entries: Vec::new(),
+            sealed: Some((
+                0,
+                CommittedBatch {
+                    root: batch_tree(&entries).root(),
+                    entries,
+                },
+            )),
+        };
+        batches
+            .write(&open_batch_key(channel_id), &open)
+            .await
+            .unwrap();
+        assert_eq!(batches.proof(channel_id, sealed_id).await.unwrap(), None);
+
+        batches
+            .append(channel_id, 2, Uuid::new_v4(), "hash-1")
+            .await
+            .unwrap();
+        let proof = batches.proof(channel_id, sealed_id).await.unwrap().unwrap();
+        assert_eq!(proof.batch_no, 0);
+        assert!(proof.verify_message(&sealed_id, "hash-0"));
+    }
+}
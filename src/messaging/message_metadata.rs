@@ -1,5 +1,13 @@
 use std::collections::HashMap;
 
+use geo::{HaversineDistance, Point};
+
+// Key under which parsed message coordinates are stored
+pub const LOCATIONS_KEY: &str = "locations";
+
+// Key under which the router's classification of the message is stored
+pub const CLASSIFICATION_KEY: &str = "classification";
+
 #[derive(Debug)]
 pub struct MessageMetadata {
     pub metadata: HashMap<String, MetadataValue>,
@@ -15,6 +23,78 @@ pub enum MetadataValue {
     MediaAttachment(Box<MediaAttachment>),
     Entities(Vec<MessageEntity>),
     Reactions(Vec<Reaction>),
+    Locations(Vec<GeoPoint>),
+}
+
+// A WGS84 coordinate in decimal degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl GeoPoint {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        GeoPoint { lat, lon }
+    }
+
+    // Parses "lat,lon", e.g. a coordinate pair mentioned in a message; None if it isn't one
+    pub fn parse(text: &str) -> Option<GeoPoint> {
+        let (lat, lon) = text.split_once(',')?;
+        let lat: f64 = lat.trim().parse().ok()?;
+        let lon: f64 = lon.trim().parse().ok()?;
+        if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) {
+            Some(GeoPoint { lat, lon })
+        } else {
+            None
+        }
+    }
+
+    // Great-circle (haversine) distance in kilometres
+    pub fn distance_km(&self, other: &GeoPoint) -> f64 {
+        Point::new(self.lon, self.lat).haversine_distance(&Point::new(other.lon, other.lat)) / 1000.0
+    }
+}
+
+// Place names with known coordinates, so GPE and LOC entities such as "Paris" can be placed.
+// Names match case-insensitively, ignoring surrounding whitespace.
+#[derive(Debug, Clone, Default)]
+pub struct Gazetteer {
+    places: HashMap<String, GeoPoint>,
+}
+
+impl Gazetteer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_place(mut self, name: &str, point: GeoPoint) -> Self {
+        self.insert(name, point);
+        self
+    }
+
+    pub fn insert(&mut self, name: &str, point: GeoPoint) {
+        self.places.insert(place_key(name), point);
+    }
+
+    // Literal "lat,lon" coordinates, else the named place; None if it is neither
+    pub fn resolve(&self, text: &str) -> Option<GeoPoint> {
+        GeoPoint::parse(text).or_else(|| self.places.get(&place_key(text)).copied())
+    }
+}
+
+fn place_key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+// Resolves location entity texts to coordinates, skipping those `resolve` can't place, e.g. with
+// `Gazetteer::resolve`.
+pub fn locations_from_entities<'a, I, F>(entities: I, resolve: F) -> Vec<GeoPoint>
+where
+    I: IntoIterator<Item = &'a str>,
+    F: Fn(&str) -> Option<GeoPoint>,
+{
+    entities.into_iter().filter_map(|entity| resolve(entity)).collect()
 }
 
 pub struct ReplyInfo {
@@ -100,7 +180,85 @@ impl MessageMetadata {
         self.metadata.iter()
     }
 
+    pub fn set_locations(&mut self, locations: Vec<GeoPoint>) {
+        if locations.is_empty() {
+            self.metadata.remove(LOCATIONS_KEY);
+        } else {
+            self.metadata.insert(LOCATIONS_KEY.to_string(), MetadataValue::Locations(locations));
+        }
+    }
+
+    pub fn locations(&self) -> &[GeoPoint] {
+        match self.metadata.get(LOCATIONS_KEY) {
+            Some(MetadataValue::Locations(locations)) => locations,
+            _ => &[],
+        }
+    }
+
+    pub fn set_classification(&mut self, classification: String) {
+        self.metadata.insert(CLASSIFICATION_KEY.to_string(), MetadataValue::String(classification));
+    }
+
+    pub fn classification(&self) -> Option<&str> {
+        match self.metadata.get(CLASSIFICATION_KEY) {
+            Some(MetadataValue::String(classification)) => Some(classification),
+            _ => None,
+        }
+    }
+
+    // True if any of the message's locations is within `radius_km` of `center`
+    pub fn within_geofence(&self, center: GeoPoint, radius_km: f64) -> bool {
+        self.locations().iter().any(|location| location.distance_km(&center) <= radius_km)
+    }
+
     pub fn iter_mut(&mut self) -> std::collections::hash_map::IterMut<String, MetadataValue> {
         self.metadata.iter_mut()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: GeoPoint = GeoPoint { lat: 51.5074, lon: -0.1278 };
+
+    #[test]
+    fn test_haversine_distance() {
+        let paris = GeoPoint::new(48.8566, 2.3522);
+        let distance = LONDON.distance_km(&paris);
+        assert!((distance - 343.5).abs() < 1.0, "London-Paris was {} km", distance);
+        assert_eq!(LONDON.distance_km(&LONDON), 0.0);
+    }
+
+    #[test]
+    fn test_within_geofence_matches_any_location() {
+        let mut metadata = MessageMetadata::new();
+        assert!(!metadata.within_geofence(LONDON, 50.0));
+
+        // "Paris" is placed by the gazetteer; the unknown "Atlantis" is skipped
+        let gazetteer = Gazetteer::new().with_place("Paris", GeoPoint::new(48.8566, 2.3522));
+        let locations = locations_from_entities(["Paris", "Atlantis", "51.75,-1.2577"], |text| gazetteer.resolve(text));
+        assert_eq!(locations.len(), 2);
+        metadata.set_locations(locations);
+
+        // Oxford is ~84 km from London, Paris ~344 km
+        assert!(metadata.within_geofence(LONDON, 100.0));
+        assert!(!metadata.within_geofence(LONDON, 50.0));
+        assert!(metadata.within_geofence(GeoPoint::new(48.86, 2.35), 5.0));
+    }
+
+    #[test]
+    fn test_gazetteer_resolves_place_names_and_coordinates() {
+        let gazetteer = Gazetteer::new().with_place("New York", GeoPoint::new(40.7128, -74.006));
+        assert_eq!(gazetteer.resolve(" new york "), Some(GeoPoint::new(40.7128, -74.006)));
+        assert_eq!(gazetteer.resolve("1.5,2.5"), Some(GeoPoint::new(1.5, 2.5)));
+        assert_eq!(gazetteer.resolve("Berlin"), None);
+    }
+
+    #[test]
+    fn test_parse_rejects_non_coordinates() {
+        assert_eq!(GeoPoint::parse("Berlin"), None);
+        assert_eq!(GeoPoint::parse("91.0,0.0"), None);
+        assert_eq!(GeoPoint::parse(" -33.87 , 151.21 "), Some(GeoPoint::new(-33.87, 151.21)));
+    }
+}
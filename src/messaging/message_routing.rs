@@ -17,8 +17,14 @@
 //! - `create_cloudevent`: Creates a CloudEvent based on the classified message.
 //! - `handle_mqtt_messages`: Handles incoming MQTT messages and sends them for classification.
 //! - `handle_kafka_messages`: Handles incoming Kafka messages and sends them for classification.
-//! - `classify_message`: Classifies a message based on its metadata and entity graph.
-//! - `route_message`: Routes a classified message to the appropriate Kafka and MQTT topics.
+//! - `classify_message`: Classifies a message based on its metadata and entity graph using the built-in rules.
+//! - `ClassificationRules`: An ordered list of `{ condition, label }` rules; operators can add rules ahead of the
+//!   built-in ones through the `classification_rules` setting of the `routing` config module.
+//! - `classify_message_with_rules`: Classifies a message with the first matching rule of a `ClassificationRules`.
+//! - `route_message`: Routes a classified message to the appropriate Kafka and MQTT topics. Every leg is attempted,
+//!   and `BigbotError::RoutingFailed` lists the legs whose send or publish failed.
+//! - `RouteDeduplicator`: Skips forwarding a message whose id was already forwarded within the dedup window.
+//! - `GeofenceRoute`: Also publishes a message to a topic when any of its locations falls inside a geofence.
 //! - `classify_and_route_message`: Classifies a message and routes it to the appropriate destinations.
 //! - `parse_message`: Parses a message using spaCy and extracts entities to build an entity graph.
 //! - `message_classifier`: Main function that receives messages, classifies them, and routes them.
@@ -39,7 +45,6 @@
 //! To run the tests, use the `cargo test` command.
 
 
-use cloudevents::{EventBuilder, EventBuilderV10};
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use rdkafka::consumer::{BaseConsumer, Consumer};
 use rdkafka::config::FromClientConfig;
@@ -50,13 +55,92 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::task;
-use std::hash::{Hash, Hasher};
-
+use serde::Deserialize;
+
+use crate::clients::kv::{KVStore, MemoryKVStore};
+use crate::config::config::Config;
+use crate::data_streams::cloudevents::message_to_cloudevent;
+use crate::data_streams::topics::Topic;
+use crate::graphs::nl_to_graph::{EntityGraph as _, EntityGraphImpl};
+use crate::messaging::decentralised_messaging::Intent;
 use crate::messaging::message::Message;
 use crate::messaging::app_state::AppState;
-use crate::messaging::message_metadata::{MessageMetadata, MetadataValue};
-use crate::bindings::spacy_bindings::{Doc, EntityGraph, LangModel, SPACY};
+use crate::messaging::message_metadata::{locations_from_entities, GeoPoint, MessageMetadata, MetadataValue};
+use crate::bindings::spacy_bindings::{Doc, EntityGraph, EntityLabel, LangModel, SPACY};
+use crate::utils::bigboterror::BigbotError;
+use crate::utils::metrics::{self, names};
+
+// How long a forwarded message id is remembered
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+const DEDUP_KEY_PREFIX: &str = "routed:";
+
+// Remembers which message ids have been forwarded so a redelivered message (e.g. a Kafka message
+// reprocessed after a consumer restart) is forwarded at most once within the window.
+pub struct RouteDeduplicator {
+    store: Arc<dyn KVStore>,
+    window: Duration,
+}
+
+impl RouteDeduplicator {
+    pub fn new(store: Arc<dyn KVStore>) -> Self {
+        Self {
+            store,
+            window: DEFAULT_DEDUP_WINDOW,
+        }
+    }
+
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
 
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    // Runs `forward` unless the message was already forwarded within the window, returning whether
+    // it ran. If forwarding fails the claim is released so a redelivery can try again.
+    pub async fn forward_once<F, Fut>(&self, message_id: &uuid::Uuid, forward: F) -> Result<bool, BigbotError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<(), BigbotError>>,
+    {
+        let key = format!("{}{}", DEDUP_KEY_PREFIX, message_id).into_bytes();
+        if !self.store.set_if_absent(key.clone(), Vec::new(), Some(self.window)).await? {
+            return Ok(false);
+        }
+        if let Err(e) = forward().await {
+            self.store.delete(&key).await?;
+            return Err(e);
+        }
+        Ok(true)
+    }
+}
+
+impl Default for RouteDeduplicator {
+    fn default() -> Self {
+        Self::new(Arc::new(MemoryKVStore::default()))
+    }
+}
+
+// Sends messages mentioning a place within `radius_km` of `center` to `topic`, in addition to
+// their classification topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeofenceRoute {
+    pub topic: String,
+    pub center: GeoPoint,
+    pub radius_km: f64,
+}
+
+// Topics of the geofences the message falls in, in route order
+pub fn geofence_topics<'a>(metadata: &MessageMetadata, routes: &'a [GeofenceRoute]) -> Vec<&'a str> {
+    routes
+        .iter()
+        .filter(|route| metadata.within_geofence(route.center, route.radius_km))
+        .map(|route| route.topic.as_str())
+        .collect()
+}
 
 async fn setup_kafka_producer() -> FutureProducer {
     FutureProducer::from_config(
@@ -72,16 +156,11 @@ fn setup_mqtt_client() -> AsyncClient {
     AsyncClient::new(mqtt_options, 10).unwrap()
 }
 
-fn create_cloudevent(classification: String, message: String) -> Event {
-    let event = EventBuilderV10::new()
-        .id(uuid::Uuid::new_v4().to_string())
-        .source("example.com/message")
-        .ty("message.classified")
-        .data("text/plain", message.as_bytes())
-        .extension("classification", classification)
-        .build()
-        .unwrap();
-    event
+// The event for `message` under `classification`, which replaces any classification it carries
+fn create_cloudevent(message: &Message, classification: &str) -> cloudevents::Event {
+    let mut message = message.clone();
+    message.metadata.set_classification(classification.to_string());
+    message_to_cloudevent(&message)
 }
 
 async fn handle_mqtt_messages(mut mqtt_client: AsyncClient, tx: mpsc::Sender<String>) {
@@ -112,22 +191,128 @@ async fn handle_kafka_messages(consumer: BaseConsumer, tx: mpsc::Sender<String>)
     }
 }
 
-fn classify_message(metadata: &HashMap<String, MetadataValue>, entity_graph: &EntityGraph) -> String {
-    let mut classification = String::new();
-    if entity_graph.has_entities_of_type(crate::bindings::spacy_bindings::EntityLabel::Location) {
-        classification = "Location-based message".to_string();
-    } else if let Some(MetadataValue::ReplyInfo(_)) = metadata.get("reply_to") {
-        classification = "Reply message".to_string();
-    } else if let Some(MetadataValue::MediaAttachment(_)) = metadata.get("media") {
-        classification = "Media message".to_string();
-    } else if let Some(MetadataValue::Bool(true)) = metadata.get("post") {
-        classification = "Post message".to_string();
-    } else if let Some(MetadataValue::Bool(true)) = metadata.get("pinned") {
-        classification = "Pinned message".to_string();
-    } else {
-        classification = "Regular message".to_string();
+// Label for messages no rule matches
+pub const DEFAULT_CLASSIFICATION: &str = "Regular message";
+
+// The app config module whose `classification_rules` setting holds operator-defined rules
+const ROUTING_MODULE: &str = "routing";
+
+// A test on a message's metadata or entities. In config, written with a `type` tag, e.g.
+// `{ "type": "metadata_equals", "key": "priority", "value": "high" }`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleCondition {
+    // The metadata has a value under `key`
+    MetadataPresent { key: String },
+    // The metadata value under `key` is a string, bool or integer equal to `value`
+    MetadataEquals { key: String, value: serde_json::Value },
+    // The entity graph has an entity with this spaCy label, e.g. "LOC"
+    HasEntity {
+        #[serde(deserialize_with = "deserialize_entity_label")]
+        label: EntityLabel,
+    },
+}
+
+impl RuleCondition {
+    fn matches(&self, metadata: &HashMap<String, MetadataValue>, entity_graph: &EntityGraph) -> bool {
+        match self {
+            RuleCondition::MetadataPresent { key } => metadata.contains_key(key),
+            RuleCondition::MetadataEquals { key, value } => match (metadata.get(key), value) {
+                (Some(MetadataValue::String(actual)), serde_json::Value::String(expected)) => actual == expected,
+                (Some(MetadataValue::Bool(actual)), serde_json::Value::Bool(expected)) => actual == expected,
+                (Some(MetadataValue::Int(actual)), serde_json::Value::Number(expected)) => expected.as_i64() == Some(*actual),
+                _ => false,
+            },
+            RuleCondition::HasEntity { label } => entity_graph.entities_of(label).next().is_some(),
+        }
+    }
+}
+
+fn deserialize_entity_label<'de, D>(deserializer: D) -> Result<EntityLabel, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let label = String::deserialize(deserializer)?;
+    label.parse().map_err(|e| serde::de::Error::custom(format!("{:?}", e)))
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ClassificationRule {
+    pub condition: RuleCondition,
+    pub label: String,
+}
+
+impl ClassificationRule {
+    pub fn new(condition: RuleCondition, label: impl Into<String>) -> Self {
+        Self { condition, label: label.into() }
+    }
+}
+
+// An ordered list of rules; a message gets the label of the first rule it matches, or
+// `DEFAULT_CLASSIFICATION` if none do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassificationRules {
+    rules: Vec<ClassificationRule>,
+}
+
+impl ClassificationRules {
+    pub fn new(rules: Vec<ClassificationRule>) -> Self {
+        Self { rules }
+    }
+
+    // Location entities, then replies, media, posts and pinned messages
+    pub fn builtin() -> Self {
+        let flag = |key: &str| RuleCondition::MetadataEquals { key: key.to_string(), value: serde_json::Value::Bool(true) };
+        Self::new(vec![
+            ClassificationRule::new(RuleCondition::HasEntity { label: EntityLabel::Loc }, "Location-based message"),
+            ClassificationRule::new(RuleCondition::MetadataPresent { key: "reply_to".to_string() }, "Reply message"),
+            ClassificationRule::new(RuleCondition::MetadataPresent { key: "media".to_string() }, "Media message"),
+            ClassificationRule::new(flag("post"), "Post message"),
+            ClassificationRule::new(flag("pinned"), "Pinned message"),
+        ])
     }
-    classification
+
+    // The built-in rules preceded by the `classification_rules` setting of the `routing` module,
+    // so operator-defined categories take priority
+    pub fn from_config(config: &Config) -> Result<Self, BigbotError> {
+        let mut rules = match config.module_setting(ROUTING_MODULE, "classification_rules") {
+            Some(value) => Vec::<ClassificationRule>::deserialize(value)
+                .map_err(|e| BigbotError::InvalidInput(format!("Invalid classification_rules: {}", e)))?,
+            None => Vec::new(),
+        };
+        rules.extend(Self::builtin().rules);
+        Ok(Self::new(rules))
+    }
+
+    pub fn rules(&self) -> &[ClassificationRule] {
+        &self.rules
+    }
+
+    pub fn classify(&self, metadata: &HashMap<String, MetadataValue>, entity_graph: &EntityGraph) -> String {
+        self.rules
+            .iter()
+            .find(|rule| rule.condition.matches(metadata, entity_graph))
+            .map_or(DEFAULT_CLASSIFICATION, |rule| rule.label.as_str())
+            .to_string()
+    }
+}
+
+impl Default for ClassificationRules {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+pub fn classify_message_with_rules(
+    metadata: &HashMap<String, MetadataValue>,
+    entity_graph: &EntityGraph,
+    rules: &ClassificationRules,
+) -> String {
+    rules.classify(metadata, entity_graph)
+}
+
+pub fn classify_message(metadata: &HashMap<String, MetadataValue>, entity_graph: &EntityGraph) -> String {
+    classify_message_with_rules(metadata, entity_graph, &ClassificationRules::builtin())
 }
 
 
@@ -137,101 +322,201 @@ pub async fn route_message(
     producer: &FutureProducer,
     mqtt_client: &mut AsyncClient,
     app_state: Arc<AppState>,
-) {
+) -> Result<(), BigbotError> {
     // Parse the message using the language model
-    let doc = lang_model.nlp(message.text.clone()).await.unwrap();
+    let doc = lang_model.nlp(message.text.clone()).await?;
 
     // Generate the entity graph from the parsed message
     let entity_graph = parse_message(&doc, lang_model);
 
-    // Extract the metadata from the message
-    let metadata = message.metadata.clone();
-
     // Classify the message based on the metadata and entity graph
-    let classification = classify_message(&metadata.metadata, &entity_graph);
-
-    let topic = match classification.as_str() {
-        "Location-based message" => "location-based-topic",
-        "Reply message" => "reply-topic",
-        "Media message" => "media-topic",
-        "Post message" => "post-topic",
-        "Pinned message" => "pinned-topic",
-        _ => "regular-topic",
-    };
+    let classification = classify_message_with_rules(&message.metadata.metadata, &entity_graph, app_state.classification_rules());
+    metrics::increment_counter(names::MESSAGES_CLASSIFIED, &[("route", classification.as_str())]);
 
-    let record = FutureRecord::to(&topic).payload(&message.text).key(&classification);
-    let _ = producer.send(record, Duration::from_secs(0));
+    route_classified(&message, &classification, producer, mqtt_client, &app_state).await
+}
 
-    if classification != "Regular message" {
-        let event = create_cloudevent(classification, message.text.clone());
-        let _ = mqtt_client.publish(topic, QoS::AtLeastOnce, false, serde_json::to_string(&event).unwrap().as_bytes());
+// Sends a classified message to its classification topic, any matching geofence topics and the
+// node serving its recipient. Every leg is attempted even after one fails, and the error lists
+// each leg that failed.
+async fn route_classified(
+    message: &Message,
+    classification: &str,
+    producer: &FutureProducer,
+    mqtt_client: &mut AsyncClient,
+    app_state: &AppState,
+) -> Result<(), BigbotError> {
+    let mut failures = RouteFailures::default();
+
+    // A label with no valid topic is routed as a regular message rather than dropped
+    let (topic, classification) = match Topic::classification(classification) {
+        Ok(topic) => (topic, classification),
+        Err(e) => {
+            log::warn!("Routing message {} as a regular message: {}", message.id, e);
+            let topic = Topic::classification(DEFAULT_CLASSIFICATION)
+                .expect("the default classification has a valid topic");
+            (topic, DEFAULT_CLASSIFICATION)
+        }
+    };
+    failures.record(
+        format!("Kafka topic '{}'", topic),
+        send_record(producer, topic.as_str(), classification, message.text.as_bytes()).await,
+    );
+    if classification != DEFAULT_CLASSIFICATION {
+        let event = create_cloudevent(message, classification);
+        failures.record(
+            format!("MQTT topic '{}'", topic),
+            publish_event(mqtt_client, topic.as_str(), &event).await,
+        );
     }
 
-    // Get the sender from the message
-    let sender = message.sender.clone();
+    for geofence_topic in geofence_topics(&message.metadata, app_state.geofence_routes()) {
+        failures.record(
+            format!("Kafka topic '{}'", geofence_topic),
+            send_record(producer, geofence_topic, classification, message.text.as_bytes()).await,
+        );
+    }
 
-    // Get the routing table from the app state
-    let mut routing_table = app_state.get_routing_table().await;
+    let node_id = match assign_node(app_state, &message.recipient) {
+        Ok(node_id) => node_id,
+        Err(e) => {
+            failures.record("node assignment", Err(e));
+            return failures.into_result();
+        }
+    };
 
-    let recipient = message.recipient.clone();
-    let node_id = match routing_table.get(&recipient) {
-        Some(node_id) => node_id.clone(),
-        None => {
-            // If recipient is not found in the routing table, use consistent hashing to assign a node
-            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-            recipient.hash(&mut hasher);
-            let hash = hasher.finish();
-            let node_index = hash as usize % routing_table.len();
-            let node_id = routing_table.values().nth(node_index).unwrap().clone();
-            routing_table.insert(recipient, node_id.clone());
-            node_id
+    // Forward the message to the assigned node over Kafka and MQTT, once per message id
+    let node_topic = match Topic::node(&node_id) {
+        Ok(node_topic) => node_topic,
+        Err(e) => {
+            failures.record(format!("forward to node '{}'", node_id), Err(e));
+            return failures.into_result();
         }
     };
+    let payload = match serde_json::to_string(message) {
+        Ok(payload) => payload,
+        Err(e) => {
+            failures.record(
+                format!("forward to node '{}'", node_id),
+                Err(BigbotError::InvalidInput(e.to_string())),
+            );
+            return failures.into_result();
+        }
+    };
+    let forwarded = app_state
+        .route_dedup()
+        .forward_once(&message.id, || async {
+            let mut legs = RouteFailures::default();
+            legs.record(
+                format!("Kafka topic '{}'", node_topic),
+                send_record(producer, node_topic.as_str(), &message.sender, payload.as_bytes()).await,
+            );
+            let node_event = create_cloudevent(message, "message.forwarded");
+            legs.record(
+                format!("MQTT topic '{}'", node_topic),
+                publish_event(mqtt_client, node_topic.as_str(), &node_event).await,
+            );
+            legs.into_result()
+        })
+        .await;
+    match forwarded {
+        Ok(true) => {}
+        Ok(false) => log::debug!("Skipping duplicate forward of message {}", message.id),
+        Err(BigbotError::RoutingFailed(legs)) => failures.legs.extend(legs),
+        Err(e) => failures.record(format!("forward to node '{}'", node_id), Err(e)),
+    }
+    failures.into_result()
+}
+
+// The node that serves `recipient` on the app state's hash ring
+fn assign_node(app_state: &AppState, recipient: &str) -> Result<String, BigbotError> {
+    app_state.node_for(recipient).ok_or_else(|| {
+        BigbotError::SystemError(format!("No routing nodes are known to serve recipient '{}'", recipient))
+    })
+}
 
-    // Forward the message to the assigned node using Kafka
-    let node_topic = format!("node-{}", node_id);
-    let node_record = FutureRecord::to(&node_topic).payload(&serde_json::to_string(&message).unwrap()).key(&sender);
-    let _ = producer.send(node_record, Duration::from_secs(0));
+// Waits for the broker to confirm delivery
+async fn send_record(producer: &FutureProducer, topic: &str, key: &str, payload: &[u8]) -> Result<(), BigbotError> {
+    let record = FutureRecord::to(topic).payload(payload).key(key);
+    producer
+        .send(record, Duration::from_secs(0))
+        .await
+        .map(|_| ())
+        .map_err(|(e, _message)| BigbotError::KafkaError(e.to_string()))
+}
 
-    // Forward the message to the assigned node using MQTT
-    let node_event = create_cloudevent("message.forwarded".to_string(), serde_json::to_string(&message).unwrap());
-    let _ = mqtt_client.publish(&node_topic, QoS::AtLeastOnce, false, serde_json::to_string(&node_event).unwrap().as_bytes());
+// Queues the event on the client; this only fails once the client's event loop has gone away
+async fn publish_event(mqtt_client: &mut AsyncClient, topic: &str, event: &cloudevents::Event) -> Result<(), BigbotError> {
+    let payload = serde_json::to_vec(event).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+    mqtt_client
+        .publish(topic, QoS::AtLeastOnce, false, payload)
+        .await
+        .map_err(|_| BigbotError::MqttDisconnectionError)
+}
+
+// Collects the legs of a route that failed, as "<leg>: <error>"
+#[derive(Default)]
+struct RouteFailures {
+    legs: Vec<String>,
+}
+
+impl RouteFailures {
+    fn record(&mut self, leg: impl std::fmt::Display, result: Result<(), BigbotError>) {
+        if let Err(e) = result {
+            self.legs.push(format!("{}: {}", leg, e));
+        }
+    }
+
+    fn into_result(self) -> Result<(), BigbotError> {
+        if self.legs.is_empty() {
+            Ok(())
+        } else {
+            Err(BigbotError::RoutingFailed(self.legs))
+        }
+    }
 }
 
-async fn classify_and_route_message(message: &str, metadata: MessageMetadata, producer: &FutureProducer, mqtt_client: &mut AsyncClient, lang_model: &LangModel, app_state: Arc<AppState>) {
+async fn classify_and_route_message(message: &str, mut metadata: MessageMetadata, producer: &FutureProducer, mqtt_client: &mut AsyncClient, lang_model: &LangModel, app_state: Arc<AppState>) {
     let entity_graph = parse_message(&lang_model.nlp(message.to_string()).await.unwrap(), lang_model);
-    let classification = classify_message(&metadata.metadata, &entity_graph);
+    let places = entity_graph.entities_of(&EntityLabel::Gpe).chain(entity_graph.entities_of(&EntityLabel::Loc));
+    metadata.set_locations(locations_from_entities(places, |text| app_state.gazetteer().resolve(text)));
     let message_struct = Message {
-        text: message.to_string(),
         id: uuid::Uuid::new_v4(),
-        channel_id: String::new(),  // Replace with the actual channel ID
-        sender: String::new(),  // Replace with the actual sender
-        recipient: String::new(),  // Replace with the actual recipient
+        // Messages on the ingest topic aren't tied to a channel or a known sender
+        channel_id: uuid::Uuid::nil(),
+        sender: String::new(),
+        recipient: String::new(),
+        content: message.to_string(),
         timestamp: chrono::Utc::now(),
         edited_at: None,
-        hash: String::new(),  // Replace with the actual hash
-        content: String::new(),  // Add the missing content field
-        metadata: metadata.clone(),
-        feedback_weights: Vec::new(),  // Replace with the actual feedback weights
-        intent: String::new(),  // Add the missing intent field
-        payment: None,  // Add the missing payment field
-        nonce: String::new(),  // Add the missing nonce field
-        name: String::new(),  // Add the missing name field
-        data: String::new(),  // Add the missing data field
-        header: String::new(),  // Add the missing header field
-        body: String::new(),  // Add the missing body field
-        contexts: Vec::new(),  // Add the missing contexts field
-        values: Vec::new(),  // Add the missing values field
-        entity_graph: entity_graph.clone(),  // Add the missing entity_graph field
+        hash: String::new(),
+        metadata,
+        feedback_weights: Vec::new(),
+        text: message.to_string(),
+        intent: Intent::TextMessage,
+        payment: None,
+        nonce: 0,
+        name: String::new(),
+        data: Vec::new(),
+        header: String::new(),
+        body: String::new(),
+        contexts: Vec::new(),
+        values: Vec::new(),
+        // `route_message` derives the entities it routes on from `text`
+        entity_graph: EntityGraphImpl::new(),
+        attachments: Vec::new(),
     };
-    route_message(
+    if let Err(e) = route_message(
         message_struct,
         lang_model,
         producer,
         mqtt_client,
         app_state.clone(),
     )
-    .await;
+    .await
+    {
+        error!("Failed to route message: {}", e);
+    }
 }
 
 
@@ -322,4 +607,152 @@ mod tests {
         ).await;
         // Add assertions to check the expected behavior
     }
+
+    #[tokio::test]
+    async fn test_duplicate_message_is_forwarded_once_within_window() {
+        let dedup = RouteDeduplicator::default().with_window(Duration::from_secs(60));
+        let message_id = uuid::Uuid::new_v4();
+        let forwards = std::sync::atomic::AtomicUsize::new(0);
+        let forward = || async {
+            forwards.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        };
+
+        assert!(dedup.forward_once(&message_id, forward).await.unwrap());
+        assert!(!dedup.forward_once(&message_id, forward).await.unwrap());
+        assert!(dedup.forward_once(&uuid::Uuid::new_v4(), forward).await.unwrap());
+        assert_eq!(forwards.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_geofence_topics() {
+        let routes = vec![
+            GeofenceRoute { topic: "london".to_string(), center: GeoPoint::new(51.5074, -0.1278), radius_km: 25.0 },
+            GeofenceRoute { topic: "berlin".to_string(), center: GeoPoint::new(52.52, 13.405), radius_km: 25.0 },
+        ];
+
+        let mut inside = MessageMetadata::default();
+        inside.set_locations(vec![GeoPoint::new(40.7128, -74.006), GeoPoint::new(51.5155, -0.0922)]);
+        assert_eq!(geofence_topics(&inside, &routes), vec!["london"]);
+
+        let mut outside = MessageMetadata::default();
+        outside.set_locations(vec![GeoPoint::new(48.8566, 2.3522)]);
+        assert!(geofence_topics(&outside, &routes).is_empty());
+        assert!(geofence_topics(&MessageMetadata::default(), &routes).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_forward_can_be_retried() {
+        let dedup = RouteDeduplicator::default();
+        let message_id = uuid::Uuid::new_v4();
+
+        let failed = dedup
+            .forward_once(&message_id, || async { Err(BigbotError::KafkaError("broker down".to_string())) })
+            .await;
+        assert!(failed.is_err());
+        assert!(dedup.forward_once(&message_id, || async { Ok(()) }).await.unwrap());
+    }
+
+    fn routed_message(recipient: &str) -> Message {
+        Message {
+            text: "See you there".to_string(),
+            ..Message::for_test("alice", recipient, "See you there")
+        }
+    }
+
+    #[test]
+    fn test_assign_node_with_empty_routing_table() {
+        let app_state = AppState::new();
+        assert!(matches!(assign_node(&app_state, "bob"), Err(BigbotError::SystemError(_))));
+
+        app_state.add_node("a");
+        assert_eq!(assign_node(&app_state, "bob").unwrap(), "a");
+    }
+
+    #[tokio::test]
+    async fn test_failing_producer_reports_each_failed_leg() {
+        // Nothing listens on port 1, so every delivery times out
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .set("message.timeout.ms", "100")
+            .create()
+            .unwrap();
+        // Publishes only need the event loop to exist; they queue until it is polled
+        let (mut mqtt_client, _event_loop) = AsyncClient::new(MqttOptions::new("route-test", "127.0.0.1", 1), 10);
+        let app_state = AppState::new();
+        app_state.add_node("a");
+        let message = routed_message("bob");
+
+        let result = route_classified(&message, "Reply message", &producer, &mut mqtt_client, &app_state).await;
+
+        let Err(BigbotError::RoutingFailed(legs)) = result else {
+            panic!("expected the Kafka legs to fail, got {:?}", result);
+        };
+        assert_eq!(legs.len(), 2);
+        assert!(legs[0].starts_with("Kafka topic 'reply-topic'"));
+        assert!(legs[1].starts_with("Kafka topic 'node-a'"));
+        // The failed forward released its dedup claim so a redelivery is forwarded again
+        assert!(app_state.route_dedup().forward_once(&message.id, || async { Ok(()) }).await.unwrap());
+
+        let unroutable = route_classified(&routed_message("dave"), "Reply message", &producer, &mut mqtt_client, &AppState::new()).await;
+        let Err(BigbotError::RoutingFailed(legs)) = unroutable else {
+            panic!("expected routing to fail");
+        };
+        assert!(legs.iter().any(|leg| leg.starts_with("node assignment")));
+
+        // A classification with no valid topic is sent to the regular topic and still forwarded
+        let result = route_classified(&routed_message("bob"), " ", &producer, &mut mqtt_client, &app_state).await;
+        let Err(BigbotError::RoutingFailed(legs)) = result else {
+            panic!("expected the Kafka legs to fail, got {:?}", result);
+        };
+        assert_eq!(legs.len(), 2);
+        assert!(legs[0].starts_with("Kafka topic 'regular-topic'"));
+        assert!(legs[1].starts_with("Kafka topic 'node-a'"));
+    }
+
+    #[test]
+    fn test_builtin_rules_match_the_original_cascade() {
+        let mut located = EntityGraph::default();
+        located.add_entity(EntityLabel::Loc, "Lake Geneva".to_string());
+        let mut pinned_post = HashMap::new();
+        pinned_post.insert("post".to_string(), MetadataValue::Bool(true));
+        pinned_post.insert("pinned".to_string(), MetadataValue::Bool(true));
+
+        assert_eq!(classify_message(&pinned_post, &located), "Location-based message");
+        assert_eq!(classify_message(&pinned_post, &EntityGraph::default()), "Post message");
+        assert_eq!(classify_message(&HashMap::new(), &EntityGraph::default()), DEFAULT_CLASSIFICATION);
+    }
+
+    #[test]
+    fn test_configured_rule_fires_ahead_of_builtins() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "version": "1.0.0",
+            "user_types": {},
+            "preference_types": {},
+            "modules": {
+                "routing": {
+                    "enabled": true,
+                    "settings": {
+                        "classification_rules": [
+                            { "condition": { "type": "metadata_equals", "key": "priority", "value": "high" }, "label": "URGENT" },
+                        ],
+                    },
+                    "overrides": {},
+                }
+            },
+        }))
+        .unwrap();
+        let rules = ClassificationRules::from_config(&config).unwrap();
+        assert_eq!(rules.rules().len(), ClassificationRules::builtin().rules().len() + 1);
+
+        let mut located = EntityGraph::default();
+        located.add_entity(EntityLabel::Loc, "Lake Geneva".to_string());
+        let mut metadata = HashMap::new();
+        metadata.insert("priority".to_string(), MetadataValue::String("high".to_string()));
+        metadata.insert("pinned".to_string(), MetadataValue::Bool(true));
+        assert_eq!(classify_message_with_rules(&metadata, &located, &rules), "URGENT");
+
+        metadata.insert("priority".to_string(), MetadataValue::String("low".to_string()));
+        assert_eq!(classify_message_with_rules(&metadata, &located, &rules), "Location-based message");
+    }
 }
@@ -19,9 +19,18 @@
 //! - `new`: Creates a new instance of `ChannelStore` with a database connection.
 //! - `create_channel`: Creates a new channel with the given name and message hash batch size.
 //! - `send_message`: Sends a message to a specific channel with the provided details.
-//! - `edit_message`: Edits the content of a message identified by its ID.
+//! - `edit_message`: Edits the content of a message identified by its channel and ID, retrying optimistic write
+//!   conflicts with backoff up to `max_edit_retries` times.
 //! - `get_messages`: Retrieves messages for a specific channel and recipient.
-//! - `validate_message`: Validates the integrity of a message by comparing its stored hash with the computed hash.
+//! - `get_messages_paginated`: Retrieves a page of a recipient's messages, sorted newest first within the page, with a
+//!   cursor for the next page.
+//! - `count_messages`: Counts the messages in a channel without fetching them.
+//! - `validate_message`: Validates the integrity of a message by comparing its stored hash with the computed hash, and
+//!   optionally checks that the hash was committed in one of the channel's Merkle batches.
+//! - `get_batch_proof`: Returns a Merkle inclusion proof for a message against its batch's committed root.
+//!
+//! Every `message_hash_batch_size` messages sent to a channel are folded into a Merkle tree whose root is persisted
+//! under `/channels/{id}/roots/{batch_no}`; see `message_batches`.
 //!
 //! ## Messaging Handler
 //!
@@ -38,7 +47,9 @@
 //!
 //! - `Active`: Indicates that the channel is active, and messages should be sent using WebSocket.
 //! - `Inactive`: Indicates that the channel is inactive, and messages should be sent using Kafka.
-//! - `LowBandwidth`: Indicates that the channel has low bandwidth, and messages should be sent using MQTT.
+//! - `LowBandwidth`: Indicates that the channel has low bandwidth, and messages should be sent using MQTT. Each channel
+//!   publishes to its own topic at QoS 1; payloads over the compression threshold are gzipped, and the encoding used is
+//!   recorded in the `content-encoding` user property so consumers know how to decode them.
 //! - `Nats`: Indicates that messages should be sent using NATS.
 //!
 //! ## Run Function
@@ -47,7 +58,7 @@
 //!
 //! It creates instances of the messaging protocols (WebSocket, Kafka, MQTT, NATS) and the `MessagingHandler`. It then sends messages using the `MessagingHandler` with different channel states.
 
-use crate::encryption::encryption::{encrypt_message, decrypt_message, hash_message, EncryptHandler};
+use crate::encryption::encryption::{encrypt_message, decrypt_message, EncryptHandler};
 use crate::messaging::decentralised_messaging::Intent;
 use crate::messaging::message::{Message, MessageBody};
 use crate::messaging::message_metadata::MessageMetadata;
@@ -55,18 +66,20 @@ use crate::messaging::pii_handler::PIIHandler;
 use crate::messaging::consensus::ConsensusLayer;
 use crate::messaging::route_classifier::MessageRouter;
 use crate::clients::kv::{MemoryKVStore, PrefixedKVStore, KVStore};
+use crate::messaging::message_batches::{BatchProof, MessageBatches};
+use crate::data_streams::{Error as StreamError, Sink};
 use crate::messaging::app_state::AppState;
 use crate::utils::bigboterror::BigbotError;
+use crate::utils::metrics::{self, names};
 use crate::provider_types::payments::Payment;
 use crate::data_exchange::exchange_adapters::MessageHeader;
-use ockam::Context;
 use crate::graphs::nl_to_graph::EntityGraph;
 
 use chrono::Utc;
 use tikv_client::{RawClient, TransactionClient, BoundRange};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::sync::Arc;
 use nats::Connection;
 use kafka::producer::{Producer, RequiredAcks};
@@ -74,8 +87,14 @@ use kafka::client::Compression;
 use kafka::consumer::{Consumer, FetchOffset};
 use rdkafka::producer::FutureRecord;
 use serde_json::Value;
+use base64::Engine;
+use rand::Rng;
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::mqttbytes::v5::PublishProperties;
+use flate2::write::GzEncoder;
+use std::io::Write;
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct Channel {
     id: Uuid,
     name: String,
@@ -83,10 +102,155 @@ struct Channel {
     message_hash_batch_size: usize,
 }
 
+// TiKV rejects raw scans over this many keys
+const MAX_SCAN_BATCH: u32 = 10240;
+
+/// Where a paginated read of a channel left off. Callers treat it as opaque and hand it back, or
+/// pass its `encode`d form to a client and `decode` it on the next request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageCursor(Vec<u8>);
+
+impl MessageCursor {
+    pub fn encode(&self) -> String {
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&self.0)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, BigbotError> {
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(cursor)
+            .map(MessageCursor)
+            .map_err(|e| BigbotError::InvalidInput(format!("Invalid message cursor: {}", e)))
+    }
+
+    // The first key strictly after the one the cursor points at
+    fn next_key(&self) -> Vec<u8> {
+        let mut key = self.0.clone();
+        key.push(0);
+        key
+    }
+}
+
+// The key range holding a channel's messages; '0' is the byte after '/', so the end stops at the prefix
+fn channel_key_range(channel_id: Uuid) -> (Vec<u8>, Vec<u8>) {
+    let start = format!("/messages/{}/", channel_id).into_bytes();
+    let end = format!("/messages/{}0", channel_id).into_bytes();
+    (start, end)
+}
+
+// Where a page of the channel starts. A cursor is clamped into the channel's key range, so one
+// from another channel can't read outside it; None means the cursor is past the channel's end.
+fn page_start(channel_id: Uuid, cursor: Option<&MessageCursor>) -> Option<Vec<u8>> {
+    let (channel_start, end) = channel_key_range(channel_id);
+    let start = match cursor {
+        Some(cursor) => cursor.next_key().max(channel_start),
+        None => channel_start,
+    };
+    (start < end).then_some(start)
+}
+
+/// How many times an optimistic edit that lost a write conflict is retried by default.
+pub const DEFAULT_MAX_EDIT_RETRIES: u32 = 5;
+const EDIT_RETRY_BASE_DELAY: Duration = Duration::from_millis(10);
+const EDIT_RETRY_MAX_DELAY: Duration = Duration::from_secs(1);
+
+// Full-jitter exponential backoff: a random delay up to base * 2^retry, capped, so
+// writers contending on the same key spread out instead of retrying in lockstep
+fn edit_retry_backoff(retry: u32) -> Duration {
+    let ceiling = EDIT_RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(retry))
+        .min(EDIT_RETRY_MAX_DELAY);
+    let millis = rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64);
+    Duration::from_millis(millis)
+}
+
+// The outcome of one optimistic edit attempt
+enum EditAttempt<T> {
+    Committed(T),
+    // Lost a write conflict; holds the conflict error
+    Conflicted(String),
+}
+
+// Runs edit attempts until one commits, backing off between conflicts and giving up after
+// `max_retries` retries. Iterative, so the stack stays flat however hot the key is.
+async fn retry_edit_conflicts<T, F, Fut>(message_id: Uuid, max_retries: u32, mut attempt: F) -> Result<T, BigbotError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<EditAttempt<T>, BigbotError>>,
+{
+    let mut retries = 0;
+    loop {
+        match attempt().await? {
+            EditAttempt::Committed(value) => return Ok(value),
+            EditAttempt::Conflicted(conflict) => {
+                if retries >= max_retries {
+                    return Err(BigbotError::DatabaseError(format!(
+                        "Editing message {} still conflicted after {} retries: {}",
+                        message_id, retries, conflict
+                    )));
+                }
+                tokio::time::sleep(edit_retry_backoff(retries)).await;
+                retries += 1;
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 struct ChannelStore {
     raw_client: RawClient,
     txn_client: TransactionClient,
+    max_edit_retries: u32,
+    // The raw client as a KVStore, shared with the batches
+    store: Arc<dyn KVStore>,
+    batches: Arc<MessageBatches>,
+}
+
+// Exposes the raw TiKV client as a KVStore so message batches live alongside the channel's messages
+struct RawClientStore(RawClient);
+
+#[async_trait::async_trait]
+impl KVStore for RawClientStore {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BigbotError> {
+        self.0.get(key.to_vec()).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))
+    }
+
+    async fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), BigbotError> {
+        self.0.put(key, value).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<(), BigbotError> {
+        self.0.delete(key.to_vec()).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))
+    }
+
+    async fn keys(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>, BigbotError> {
+        let keys = self.0.scan_keys(prefix.to_vec().., MAX_SCAN_BATCH).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+        Ok(keys
+            .into_iter()
+            .map(Vec::<u8>::from)
+            .take_while(|key| key.starts_with(prefix))
+            .collect())
+    }
+
+    // Needs a client created with `with_atomic_for_cas`
+    async fn compare_and_swap(&self, key: Vec<u8>, expected: Option<Vec<u8>>, value: Vec<u8>) -> Result<bool, BigbotError> {
+        let (_, swapped) = self
+            .0
+            .compare_and_swap(key, expected, value)
+            .await
+            .map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+        Ok(swapped)
+    }
+}
+
+// Lets a ChannelStore archive messages from a stream, e.g. the final streamed chat reply
+#[async_trait::async_trait]
+impl Sink<Message, StreamError> for ChannelStore {
+    async fn consume(&self, message: Message) -> Result<(), StreamError> {
+        self.store_message(&message)
+            .await
+            .map(|_| ())
+            .map_err(|e| StreamError::InternalError(Box::new(e)))
+    }
 }
 
 pub struct RouteClassifier;
@@ -106,11 +270,19 @@ impl ChannelStore {
     async fn new(pd_endpoints: &[String]) -> Result<Self, BigbotError> {
         let raw_client = RawClient::new(pd_endpoints.to_vec()).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
         let txn_client = TransactionClient::new(pd_endpoints.to_vec()).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
-        Ok(Self { raw_client, txn_client })
+        // Batches are appended with compare-and-swap, which TiKV only allows on an atomic client
+        let store: Arc<dyn KVStore> = Arc::new(RawClientStore(raw_client.with_atomic_for_cas()));
+        let batches = Arc::new(MessageBatches::new(store.clone()));
+        Ok(Self { raw_client, txn_client, max_edit_retries: DEFAULT_MAX_EDIT_RETRIES, store, batches })
     }
 
-    async fn get_message(&self, message_id: Uuid) -> Result<Message, BigbotError> {
-        let key = format!("/messages/{}", message_id);
+    fn with_max_edit_retries(mut self, max_edit_retries: u32) -> Self {
+        self.max_edit_retries = max_edit_retries;
+        self
+    }
+
+    async fn get_message(&self, channel_id: Uuid, message_id: Uuid) -> Result<Message, BigbotError> {
+        let key = message_key(channel_id, message_id);
         let value = self.raw_client.get(key).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?.ok_or(BigbotError::InvalidInput("Message not found".to_string()))?;
         let message: Message = serde_json::from_slice(&value[..]).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
         Ok(message)
@@ -139,18 +311,26 @@ impl ChannelStore {
         metadata: MessageMetadata,
         feedback_weights: Vec<f32>,
         text: String,
-        intent: String,
+        intent: Intent,
         payment: Option<Payment>,
-        nonce: String,
+        nonce: u64,
         name: String,
         data: Vec<u8>,
         header: MessageHeader,
         body: MessageBody,
-        contexts: Vec<Context>,
+        contexts: Vec<usize>,
         values: Vec<Value>,
         entity_graph: &impl EntityGraph,
     ) -> Result<Message, BigbotError> {
-        let message = Message {
+        let contexts = contexts
+            .into_iter()
+            .map(|context| {
+                i32::try_from(context).map_err(|_| {
+                    BigbotError::InvalidInput(format!("Context id {} is out of range", context))
+                })
+            })
+            .collect::<Result<Vec<i32>, BigbotError>>()?;
+        let mut message = Message {
             id: Uuid::new_v4(),
             channel_id,
             sender: sender.to_string(),
@@ -161,63 +341,79 @@ impl ChannelStore {
             metadata,
             feedback_weights,
             text,
-            intent: Intent::from(intent),
+            intent,
             payment,
-            nonce: nonce.parse().unwrap(),
+            nonce,
             name,
-            data: data.into_iter().map(|d| actix_web::web::Data::new(d.to_string())).collect(),
+            data,
             header: header.to_string(),
             body: body.to_string(),
-            contexts: contexts.into_iter().map(|_| 0).collect(),
+            contexts,
             values: values.into_iter().map(|v| v.to_string()).collect(),
             entity_graph: entity_graph.clone(),
             hash: String::new(),
+            attachments: Vec::new(),
         };
-        let encrypted_content = encrypt_message(&message.content, &message.recipient).map_err(|e| BigbotError::NlpError(e.to_string()))?;
-        let hash = hash_message(&encrypted_content).map_err(|e| BigbotError::NlpError(e.to_string()))?;
-        let mut message_with_hash = message.clone();
-        message_with_hash.content = encrypted_content;
-        message_with_hash.hash = hash;
-        let key = format!("/messages/{}/{}", channel_id, message_with_hash.id);
-        let value = serde_json::to_string(&message_with_hash).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
-        self.raw_client.put(key, value).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+        message.hash = self.store_message(&message).await?;
         Ok(message)
     }
 
+    // Persists a message with its content encrypted for the recipient, returning the hash recorded
+    // for the stored copy
+    async fn store_message(&self, message: &Message) -> Result<String, BigbotError> {
+        let message_with_hash = put_sealed(self.store.as_ref(), message).await?;
+        let batch_size = self.message_hash_batch_size(message.channel_id).await?;
+        self.batches.append(message.channel_id, batch_size, message_with_hash.id, &message_with_hash.hash).await?;
+        Ok(message_with_hash.hash)
+    }
+
+    // Messages stored for a channel that was never created aren't batched
+    async fn message_hash_batch_size(&self, channel_id: Uuid) -> Result<usize, BigbotError> {
+        let key = format!("/channels/{}", channel_id);
+        let Some(value) = self.raw_client.get(key).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))? else {
+            return Ok(0);
+        };
+        let channel: Channel = serde_json::from_slice(&value).map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+        Ok(channel.message_hash_batch_size)
+    }
+
     async fn edit_message(
         &self,
+        channel_id: Uuid,
         message_id: Uuid,
         content: &str,
         use_pessimistic_txn: bool,
     ) -> Result<Message, BigbotError> {
-        let key = format!("/messages/{}", message_id);
-        let txn_result = if use_pessimistic_txn {
-            self.txn_client.begin_pessimistic().await
-        } else {
-            self.txn_client.begin_optimistic().await
-        };
-    
-        let mut txn = txn_result.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
-    
-        let value = txn.get(key.clone()).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?.ok_or(BigbotError::InvalidInput("Message not found".to_string()))?;
-        let mut message: Message = serde_json::from_str(&String::from_utf8_lossy(&value)).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
-        let encrypted_content = encrypt_message(content, &message.recipient).map_err(|e| BigbotError::NlpError(e.to_string()))?;
-        let hash = hash_message(&encrypted_content).map_err(|e| BigbotError::NlpError(e.to_string()))?;
-        message.content = encrypted_content;
-        message.edited_at = Some(Utc::now());
-        message.hash = hash;
-        let value = serde_json::to_string(&message).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
-        txn.put(key, value).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
-    
-        match txn.commit().await {
-            Ok(_) => Ok(message),
-            Err(e) if !use_pessimistic_txn && e.to_string().contains("TxnAbortedError") => {
-                // Retry the transaction using Box::pin
-                Box::pin(self.edit_message(message_id, content, use_pessimistic_txn)).await
+        let key = &message_key(channel_id, message_id);
+        // Only optimistic commits that lose a write conflict are retried
+        retry_edit_conflicts(message_id, self.max_edit_retries, || async move {
+            let txn_result = if use_pessimistic_txn {
+                self.txn_client.begin_pessimistic().await
+            } else {
+                self.txn_client.begin_optimistic().await
+            };
+
+            let mut txn = txn_result.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+
+            let value = txn.get(key.clone()).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?.ok_or(BigbotError::InvalidInput("Message not found".to_string()))?;
+            let mut message: Message = serde_json::from_str(&String::from_utf8_lossy(&value)).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+            let encrypted_content = encrypt_message(content, &message.recipient).map_err(|e| BigbotError::NlpError(e.to_string()))?;
+            message.content = encrypted_content;
+            message.edited_at = Some(Utc::now());
+            message.hash = message.content_hash()?;
+            let value = serde_json::to_string(&message).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+            txn.put(key.clone(), value).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+
+            match txn.commit().await {
+                Ok(_) => Ok(EditAttempt::Committed(message)),
+                Err(e) if !use_pessimistic_txn && e.to_string().contains("TxnAbortedError") => {
+                    Ok(EditAttempt::Conflicted(e.to_string()))
+                }
+                Err(e) => Err(BigbotError::DatabaseError(e.to_string())),
             }
-            Err(e) => Err(BigbotError::DatabaseError(e.to_string())),
-        }
-    }    
+        })
+        .await
+    }
 
     async fn get_messages(
         &self,
@@ -229,70 +425,283 @@ impl ChannelStore {
         let bound_range = BoundRange::from(prefix.clone()..);
         let kv_pairs = self.raw_client.scan(bound_range, u32::MAX).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
         for kv_pair in kv_pairs {
-            let _key = kv_pair.key();
-            let value = kv_pair.value();
-            let message: Message = serde_json::from_str(&String::from_utf8_lossy(&value)).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
-            if message.recipient == recipient {
-                let decrypted_content = decrypt_message(&message.content, recipient).map_err(|e| BigbotError::NlpError(e.to_string()))?;
-                let mut decrypted_message = message.clone();
-                decrypted_message.content = decrypted_content;
-                messages.push(decrypted_message);
+            if let Some(message) = decrypt_for_recipient(kv_pair.value(), recipient)? {
+                messages.push(message);
             }
         }
         Ok(messages)
     }        
 
-    async fn validate_message(&self, message_id: Uuid) -> Result<bool, BigbotError> {
-        let key = format!("/messages/{}", message_id);
-        let value = self.raw_client.get(key).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?.ok_or(BigbotError::InvalidInput("Message not found".to_string()))?;
-        let message: Message = serde_json::from_str(&String::from_utf8_lossy(&value)).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
-        let computed_hash = hash_message(&message.content).map_err(|e| BigbotError::NlpError(e.to_string()))?;
-        Ok(message.hash == computed_hash)
+    /// Returns up to `limit` of the recipient's messages after `cursor`, along with the cursor for
+    /// the next page, or `None` once the channel has been read to the end.
+    ///
+    /// Messages are keyed by random UUIDs, so key order says nothing about when they were sent.
+    /// Pages follow key order and only the messages within a page are sorted newest first; a later
+    /// page can hold newer messages than an earlier one. Callers wanting the whole channel in time
+    /// order must read every page and sort. Each call scans keys in batches, decoding and
+    /// decrypting every value until `limit` messages for the recipient are found, so a page costs
+    /// at least `limit` reads, and many more for a recipient with few messages in a busy channel.
+    async fn get_messages_paginated(
+        &self,
+        channel_id: Uuid,
+        recipient: &str,
+        cursor: Option<MessageCursor>,
+        limit: usize,
+    ) -> Result<(Vec<Message>, Option<MessageCursor>), BigbotError> {
+        if limit == 0 {
+            return Ok((Vec::new(), cursor));
+        }
+        let Some(mut start) = page_start(channel_id, cursor.as_ref()) else {
+            return Ok((Vec::new(), None));
+        };
+        let (_, end) = channel_key_range(channel_id);
+        let batch = limit.min(MAX_SCAN_BATCH as usize) as u32;
+        let mut page = Vec::with_capacity(limit);
+        loop {
+            let kv_pairs = self.raw_client.scan(start.clone()..end.clone(), batch).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+            let exhausted = kv_pairs.len() < batch as usize;
+            for kv_pair in kv_pairs {
+                let last = MessageCursor(kv_pair.key().clone().into());
+                if let Some(message) = decrypt_for_recipient(kv_pair.value(), recipient)? {
+                    page.push(message);
+                }
+                if page.len() == limit {
+                    page.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+                    return Ok((page, Some(last)));
+                }
+                start = last.next_key();
+            }
+            if exhausted {
+                page.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+                return Ok((page, None));
+            }
+        }
+    }
+
+    /// Counts the messages stored in a channel using key-only scans, without fetching or decrypting values.
+    async fn count_messages(&self, channel_id: Uuid) -> Result<usize, BigbotError> {
+        let (mut start, end) = channel_key_range(channel_id);
+        let mut count = 0;
+        loop {
+            let keys = self.raw_client.scan_keys(start.clone()..end.clone(), MAX_SCAN_BATCH).await.map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
+            count += keys.len();
+            match keys.last() {
+                Some(last) if keys.len() == MAX_SCAN_BATCH as usize => {
+                    start = MessageCursor(last.clone().into()).next_key();
+                }
+                _ => return Ok(count),
+            }
+        }
+    }
+
+    /// Checks the stored message against its recorded hash. With `check_batch` the hash must also
+    /// have been committed in one of the channel's batches, so a message that is still in the open
+    /// batch, or was edited after its batch was committed, fails.
+    async fn validate_message(&self, channel_id: Uuid, message_id: Uuid, check_batch: bool) -> Result<bool, BigbotError> {
+        validate_in_store(self.store.as_ref(), &self.batches, channel_id, message_id, check_batch).await
     }
+
+    /// Whether the stored copy of the message is intact and still carries the message's hash.
+    async fn stored_hash_matches(&self, message: &Message) -> Result<bool, BigbotError> {
+        stored_hash_matches(self.store.as_ref(), message.channel_id, message.id, &message.hash).await
+    }
+
+    /// An inclusion proof for the message against its batch's committed root, or `None` while the
+    /// message's batch is still open.
+    async fn get_batch_proof(&self, channel_id: Uuid, message_id: Uuid) -> Result<Option<BatchProof>, BigbotError> {
+        self.batches.proof(channel_id, message_id).await
+    }
+}
+
+// Encrypts the content for the recipient and records the hash of the ciphertext, as stored
+fn seal_message(message: &Message) -> Result<Message, BigbotError> {
+    let mut sealed = message.clone();
+    sealed.content = encrypt_message(&message.content, &message.recipient).map_err(|e| BigbotError::NlpError(e.to_string()))?;
+    sealed.hash = sealed.content_hash()?;
+    Ok(sealed)
+}
+
+// Where a message is stored: under its channel, so a channel's messages can be scanned together
+fn message_key(channel_id: Uuid, message_id: Uuid) -> String {
+    format!("/messages/{}/{}", channel_id, message_id)
+}
+
+// Seals the message and writes it under its channel, returning the sealed copy
+async fn put_sealed(store: &dyn KVStore, message: &Message) -> Result<Message, BigbotError> {
+    let sealed = seal_message(message)?;
+    let value = serde_json::to_vec(&sealed).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+    store.set(message_key(sealed.channel_id, sealed.id).into_bytes(), value).await?;
+    Ok(sealed)
+}
+
+async fn validate_in_store(
+    store: &dyn KVStore,
+    batches: &MessageBatches,
+    channel_id: Uuid,
+    message_id: Uuid,
+    check_batch: bool,
+) -> Result<bool, BigbotError> {
+    let value = store.get(message_key(channel_id, message_id).as_bytes()).await?.ok_or(BigbotError::InvalidInput("Message not found".to_string()))?;
+    if !validate_stored(&value)? {
+        return Ok(false);
+    }
+    if !check_batch {
+        return Ok(true);
+    }
+    let message: Message = serde_json::from_slice(&value).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+    Ok(match batches.proof(channel_id, message_id).await? {
+        Some(proof) => proof.verify_message(&message.id, &message.hash),
+        None => false,
+    })
+}
+
+// The stored message must match its own recorded hash and the hash the caller holds for it, so a
+// corrupted write or one overwritten since is caught
+async fn stored_hash_matches(store: &dyn KVStore, channel_id: Uuid, message_id: Uuid, hash: &str) -> Result<bool, BigbotError> {
+    let value = store.get(message_key(channel_id, message_id).as_bytes()).await?.ok_or(BigbotError::InvalidInput("Message not found".to_string()))?;
+    let message: Message = serde_json::from_slice(&value).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+    Ok(message.hash == hash && validate_stored(&value)?)
+}
+
+// Checks a raw stored value against its recorded hash; the content is never decrypted, so the
+// hash is compared against the same ciphertext it was computed from
+fn validate_stored(value: &[u8]) -> Result<bool, BigbotError> {
+    let message: Message = serde_json::from_slice(value).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+    Ok(message.hash == message.content_hash()?)
+}
+
+// Decodes a stored message and decrypts it if it is addressed to `recipient`
+fn decrypt_for_recipient(value: &[u8], recipient: &str) -> Result<Option<Message>, BigbotError> {
+    let mut message: Message = serde_json::from_slice(value).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+    if message.recipient != recipient {
+        return Ok(None);
+    }
+    message.content = decrypt_message(&message.content, recipient).map_err(|e| BigbotError::NlpError(e.to_string()))?;
+    Ok(Some(message))
 }
 
 pub mod messaging_handler {
     use super::*;
 
+    /// The MQTT user property naming the encoding of a low bandwidth payload.
+    pub const CONTENT_ENCODING_PROPERTY: &str = "content-encoding";
+    pub const ENCODING_IDENTITY: &str = "identity";
+    pub const ENCODING_GZIP: &str = "gzip";
+
+    /// Payloads at or below this many bytes are sent uncompressed by default.
+    pub const DEFAULT_GZIP_THRESHOLD: usize = 1024;
+
+    /// How low bandwidth payloads are compressed before publishing.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PayloadCompression {
+        Never,
+        // Gzip payloads larger than this many bytes
+        GzipAbove(usize),
+    }
+
+    impl Default for PayloadCompression {
+        fn default() -> Self {
+            PayloadCompression::GzipAbove(DEFAULT_GZIP_THRESHOLD)
+        }
+    }
+
+    /// The MQTT topic a channel's low bandwidth messages are published on.
+    pub fn low_bandwidth_topic(channel_id: &Uuid) -> String {
+        format!("channels/{}/messages", channel_id)
+    }
+
+    /// Compresses the payload if the policy calls for it, returning it with the encoding used.
+    pub fn encode_payload(
+        payload: Vec<u8>,
+        compression: PayloadCompression,
+    ) -> Result<(Vec<u8>, &'static str), BigbotError> {
+        match compression {
+            PayloadCompression::GzipAbove(threshold) if payload.len() > threshold => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&payload)?;
+                Ok((encoder.finish()?, ENCODING_GZIP))
+            }
+            _ => Ok((payload, ENCODING_IDENTITY)),
+        }
+    }
+
     pub struct MessagingHandler {
         kafka_producer: Producer,
         nats: Arc<Connection>,
+        mqtt: rumqttc::v5::AsyncClient,
+        compression: PayloadCompression,
     }
 
     impl MessagingHandler {
         pub fn new(
             kafka_producer: Producer,
             nats: Arc<Connection>,
+            mqtt: rumqttc::v5::AsyncClient,
         ) -> Self {
             MessagingHandler {
                 kafka_producer,
                 nats,
+                mqtt,
+                compression: PayloadCompression::default(),
             }
         }
 
+        pub fn with_payload_compression(mut self, compression: PayloadCompression) -> Self {
+            self.compression = compression;
+            self
+        }
+
         pub fn send(&self, message: &Message, channel_state: ChannelState) -> Result<(), BigbotError> {
             match channel_state {
-                ChannelState::Active => {
+                ChannelState::Active => instrument_send("kafka", || {
                     let future_record = FutureRecord::to(&message.channel_id.to_string())
                         .payload(&serde_json::to_string(&message).map_err(|e| BigbotError::InvalidInput(e.to_string()))?)
                         .key(&message.id.to_string());
                     self.kafka_producer.send(future_record).map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
-                }
+                    Ok(())
+                })?,
                 ChannelState::Inactive => {
                     // Handle inactive channel state, e.g., log a warning or error
                     eprintln!("Warning: Attempting to send a message to an inactive channel");
                 }
-                ChannelState::LowBandwidth => {
-                    // Handle low bandwidth channel state, e.g., use MQTT or a different protocol
-                    // TODO: Implement low bandwidth message sending logic
-                    unimplemented!("Low bandwidth message sending is not implemented yet");
-                }
-                ChannelState::Nats => self.nats.publish(&message.channel_id.to_string(), message.content.as_bytes()).map_err(|e| BigbotError::DatabaseError(e.to_string()))?,
+                ChannelState::LowBandwidth => instrument_send("mqtt", || {
+                    let payload = serde_json::to_vec(&message).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+                    let (payload, encoding) = encode_payload(payload, self.compression)?;
+                    let properties = PublishProperties {
+                        user_properties: vec![(CONTENT_ENCODING_PROPERTY.to_string(), encoding.to_string())],
+                        ..Default::default()
+                    };
+                    // Queues the publish for the event loop spawned alongside the client
+                    self.mqtt
+                        .try_publish_with_properties(low_bandwidth_topic(&message.channel_id), QoS::AtLeastOnce, false, payload, properties)
+                        .map_err(|e| BigbotError::SystemError(e.to_string()))
+                })?,
+                ChannelState::Nats => instrument_send("nats", || {
+                    self.nats.publish(&message.channel_id.to_string(), message.content.as_bytes()).map_err(|e| BigbotError::DatabaseError(e.to_string()))
+                })?,
             }
             Ok(())
         }
     }
 
+    // Runs a transport send, counting successful sends and recording the latency of every attempt
+    pub(crate) fn instrument_send<F>(transport: &str, send: F) -> Result<(), BigbotError>
+    where
+        F: FnOnce() -> Result<(), BigbotError>,
+    {
+        let started = Instant::now();
+        let result = send();
+        metrics::record_histogram(
+            names::SEND_LATENCY_SECONDS,
+            &[("transport", transport)],
+            started.elapsed().as_secs_f64(),
+        );
+        if result.is_ok() {
+            metrics::increment_counter(names::MESSAGES_SENT, &[("transport", transport)]);
+        }
+        result
+    }
+
     pub enum ChannelState {
         Active,
         Inactive,
@@ -330,7 +739,18 @@ impl MessagingApp {
             .create()
             .map_err(|e| BigbotError::DatabaseError(e.to_string()))?;
         let nats = Arc::new(nats::connect("nats://localhost:4222").map_err(|e| BigbotError::DatabaseError(e.to_string()))?);
-        let messaging_handler = messaging_handler::MessagingHandler::new(kafka_producer, nats);
+        let mqtt_options = rumqttc::v5::MqttOptions::new(format!("messaging-{}", Uuid::new_v4()), mqtt_broker, 1883);
+        let (mqtt, mut mqtt_event_loop) = rumqttc::v5::AsyncClient::new(mqtt_options, 64);
+        // Publishes only leave the client while its event loop is being polled
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = mqtt_event_loop.poll().await {
+                    log::warn!("MQTT connection error: {:?}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        });
+        let messaging_handler = messaging_handler::MessagingHandler::new(kafka_producer, nats, mqtt);
         let app_state = Arc::new(AppState::new());
         let consensus_layer = if enable_consensus {
             Some(ConsensusLayer::new(tikv_endpoints, local_storage_path, distributed_hash_endpoints, app_state).await?)
@@ -338,7 +758,7 @@ impl MessagingApp {
             None
         };
         let message_router = MessageRouter::new(kafka_brokers, mqtt_broker).await;
-        let pii_handler = PIIHandler::new(encrypt_handler);
+        let pii_handler = PIIHandler::new(encrypt_handler)?;
         let route_classifier = RouteClassifier::new();
         Ok(Self {
             channel_store,
@@ -351,6 +771,12 @@ impl MessagingApp {
         })
     }
 
+    /// Sets how many times an optimistic edit is retried after a write conflict before giving up.
+    pub fn with_max_edit_retries(mut self, max_edit_retries: u32) -> Self {
+        self.channel_store = self.channel_store.with_max_edit_retries(max_edit_retries);
+        self
+    }
+
     pub async fn create_channel(&self, name: &str, message_hash_batch_size: usize) -> Result<Channel, BigbotError> {
         self.channel_store.create_channel(name, message_hash_batch_size).await
     }
@@ -364,14 +790,14 @@ impl MessagingApp {
         metadata: MessageMetadata,
         feedback_weights: Vec<f32>,
         text: String,
-        intent: String,
+        intent: Intent,
         payment: Option<Payment>,
-        nonce: String,
+        nonce: u64,
         name: String,
         data: Vec<u8>,
         header: MessageHeader,
         body: MessageBody,
-        contexts: Vec<Context>,
+        contexts: Vec<usize>,
         values: Vec<Value>,
         entity_graph: &impl EntityGraph,
     ) -> Result<Message, BigbotError> {
@@ -395,7 +821,15 @@ impl MessagingApp {
             entity_graph,
         ).await?;
         if let Some(consensus_layer) = &self.consensus_layer {
-            if !consensus_layer.validate_message(&message).await? {
+            // Check the stored copy before replicating its hash, so a write quorum never vouches
+            // for a message that was corrupted or overwritten on the way into the store. Readers
+            // check the replicated hash with `validate_message` on the consensus layer.
+            let valid = self.channel_store.stored_hash_matches(&message).await?;
+            metrics::increment_counter(
+                names::CONSENSUS_VALIDATIONS,
+                &[("result", if valid { "valid" } else { "invalid" })],
+            );
+            if !valid {
                 return Err(BigbotError::InvalidInput("Message validation failed".into()));
             }
             consensus_layer.replicate_message(&message).await?;
@@ -406,18 +840,37 @@ impl MessagingApp {
 
     pub async fn edit_message(
         &self,
+        channel_id: Uuid,
         message_id: Uuid,
         content: &str,
     ) -> Result<Message, BigbotError> {
-        self.channel_store.edit_message(message_id, content, self.use_pessimistic_txn).await
+        self.channel_store.edit_message(channel_id, message_id, content, self.use_pessimistic_txn).await
+    }
+
+    pub async fn get_message(&self, channel_id: Uuid, message_id: Uuid) -> Result<Message, BigbotError> {
+        self.channel_store.get_message(channel_id, message_id).await
+    }
+
+    pub async fn get_messages_paginated(
+        &self,
+        channel_id: Uuid,
+        recipient: &str,
+        cursor: Option<MessageCursor>,
+        limit: usize,
+    ) -> Result<(Vec<Message>, Option<MessageCursor>), BigbotError> {
+        self.channel_store.get_messages_paginated(channel_id, recipient, cursor, limit).await
+    }
+
+    pub async fn count_messages(&self, channel_id: Uuid) -> Result<usize, BigbotError> {
+        self.channel_store.count_messages(channel_id).await
     }
 
-    pub async fn get_message(&self, message_id: Uuid) -> Result<Message, BigbotError> {
-        self.channel_store.get_message(message_id).await
+    pub async fn validate_message(&self, channel_id: Uuid, message_id: Uuid, check_batch: bool) -> Result<bool, BigbotError> {
+        self.channel_store.validate_message(channel_id, message_id, check_batch).await
     }
 
-    pub async fn validate_message(&self, message_id: Uuid) -> Result<bool, BigbotError> {
-        self.channel_store.validate_message(message_id).await
+    pub async fn get_batch_proof(&self, channel_id: Uuid, message_id: Uuid) -> Result<Option<BatchProof>, BigbotError> {
+        self.channel_store.get_batch_proof(channel_id, message_id).await
     }
 
     pub async fn sync_messages(&self) -> Result<(), BigbotError> {
@@ -432,7 +885,7 @@ impl MessagingApp {
         message: &Message,
     ) -> Result<(), BigbotError> {
         // Apply PII handling
-        let sanitized_message = self.pii_handler.sanitize(message)?;
+        let (sanitized_message, _unmask_token) = self.pii_handler.sanitize(message).await?;
         // Classify the message route
         let route = self.route_classifier.classify(&sanitized_message)?;
         // Route the message
@@ -516,3 +969,219 @@ async fn main() -> Result<(), BigbotError> {
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{channel_key_range, edit_retry_backoff, message_key, page_start, put_sealed, retry_edit_conflicts, seal_message, stored_hash_matches, validate_in_store, validate_stored, EditAttempt, MessageCursor, EDIT_RETRY_MAX_DELAY};
+    use crate::clients::kv::{KVStore, MemoryKVStore};
+    use crate::messaging::message_batches::MessageBatches;
+    use std::sync::Arc;
+    use crate::messaging::message::Message;
+    use uuid::Uuid;
+    use super::messaging_handler::{encode_payload, instrument_send, PayloadCompression, ENCODING_GZIP, ENCODING_IDENTITY};
+    use crate::utils::bigboterror::BigbotError;
+    use crate::utils::metrics::{self, names};
+
+    #[test]
+    fn test_send_records_sent_counter_and_latency() {
+        // A transport label unique to this test keeps it independent of the shared registry
+        let labels = [("transport", "test-sent-metrics")];
+        let registry = metrics::registry();
+
+        instrument_send("test-sent-metrics", || Ok(())).unwrap();
+        assert_eq!(registry.counter(names::MESSAGES_SENT, &labels), 1);
+        assert_eq!(registry.histogram(names::SEND_LATENCY_SECONDS, &labels).count, 1);
+
+        // Failed sends are timed but not counted as sent
+        let _ = instrument_send("test-sent-metrics", || Err(BigbotError::SystemError("down".into())));
+        assert_eq!(registry.counter(names::MESSAGES_SENT, &labels), 1);
+        assert_eq!(registry.histogram(names::SEND_LATENCY_SECONDS, &labels).count, 2);
+    }
+
+    #[test]
+    fn test_low_bandwidth_payload_is_gzipped_over_threshold() {
+        use std::io::Read;
+
+        let small = b"hello".to_vec();
+        let (encoded, encoding) = encode_payload(small.clone(), PayloadCompression::GzipAbove(16)).unwrap();
+        assert_eq!(encoding, ENCODING_IDENTITY);
+        assert_eq!(encoded, small);
+
+        let large = "low bandwidth ".repeat(100).into_bytes();
+        let (encoded, encoding) = encode_payload(large.clone(), PayloadCompression::GzipAbove(16)).unwrap();
+        assert_eq!(encoding, ENCODING_GZIP);
+        assert!(encoded.len() < large.len());
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(encoded.as_slice()).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, large);
+
+        let (encoded, encoding) = encode_payload(large.clone(), PayloadCompression::Never).unwrap();
+        assert_eq!((encoded, encoding), (large, ENCODING_IDENTITY));
+    }
+
+    #[test]
+    fn test_message_cursor_round_trips_and_channel_range_excludes_other_channels() {
+        let cursor = MessageCursor(b"/messages/abc/def".to_vec());
+        assert_eq!(MessageCursor::decode(&cursor.encode()).unwrap(), cursor);
+        assert!(MessageCursor::decode("not base64!").is_err());
+
+        let channel = uuid::Uuid::new_v4();
+        let (start, end) = channel_key_range(channel);
+        let inside = format!("/messages/{}/{}", channel, uuid::Uuid::new_v4()).into_bytes();
+        let other_channel = format!("/messages/{}-x/1", channel).into_bytes();
+        assert!(start <= inside && inside < end);
+        assert!(!(start <= other_channel && other_channel < end));
+        assert!(MessageCursor(inside.clone()).next_key() > inside);
+    }
+
+    #[test]
+    fn test_page_start_clamps_cursor_to_channel() {
+        let channel = uuid::Uuid::new_v4();
+        let (start, end) = channel_key_range(channel);
+        assert_eq!(page_start(channel, None), Some(start.clone()));
+
+        let inside = format!("/messages/{}/{}", channel, uuid::Uuid::new_v4()).into_bytes();
+        assert_eq!(page_start(channel, Some(&MessageCursor(inside.clone()))), Some(MessageCursor(inside).next_key()));
+
+        // A cursor before the channel starts at the channel, one after it yields nothing
+        assert_eq!(page_start(channel, Some(&MessageCursor(b"/a".to_vec()))), Some(start));
+        assert_eq!(page_start(channel, Some(&MessageCursor(end))), None);
+        assert_eq!(page_start(channel, Some(&MessageCursor(b"/z".to_vec()))), None);
+    }
+
+    #[test]
+    fn test_edit_retry_backoff_grows_and_is_capped() {
+        for retry in 0..4 {
+            let ceiling = std::time::Duration::from_millis(10 * 2u64.pow(retry));
+            for _ in 0..20 {
+                assert!(edit_retry_backoff(retry) <= ceiling);
+            }
+        }
+        for retry in [10, 31, 32, u32::MAX] {
+            assert!(edit_retry_backoff(retry) <= EDIT_RETRY_MAX_DELAY);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_edit_gives_up_after_max_retries_of_conflicts() {
+        let message_id = Uuid::new_v4();
+        let mut attempts = 0;
+        let result: Result<(), BigbotError> = retry_edit_conflicts(message_id, 3, || {
+            attempts += 1;
+            async { Ok(EditAttempt::Conflicted("TxnAbortedError: write conflict".to_string())) }
+        })
+        .await;
+
+        // The first attempt plus three retries
+        assert_eq!(attempts, 4);
+        match result {
+            Err(BigbotError::DatabaseError(e)) => {
+                assert!(e.contains(&format!("Editing message {} still conflicted after 3 retries", message_id)), "{}", e);
+                assert!(e.contains("write conflict"), "{}", e);
+            }
+            other => panic!("expected a database error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_edit_retries_conflicts_until_committed() {
+        let mut attempts = 0;
+        let result = retry_edit_conflicts(Uuid::new_v4(), 3, || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                if attempt < 3 {
+                    Ok(EditAttempt::Conflicted("TxnAbortedError".to_string()))
+                } else {
+                    Ok(EditAttempt::Committed(attempt))
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        // Other errors are returned without retrying
+        let mut attempts = 0;
+        let result: Result<(), BigbotError> = retry_edit_conflicts(Uuid::new_v4(), 3, || {
+            attempts += 1;
+            async { Err(BigbotError::DatabaseError("region unavailable".to_string())) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    fn message(content: &str) -> Message {
+        Message {
+            channel_id: Uuid::new_v4(),
+            ..Message::for_test("alice", "bob", content)
+        }
+    }
+
+    #[test]
+    fn test_stored_message_validates() {
+        let stored = serde_json::to_vec(&seal_message(&message("see you at noon")).unwrap()).unwrap();
+        assert!(validate_stored(&stored).unwrap());
+    }
+
+    #[test]
+    fn test_tampered_stored_message_fails_validation() {
+        let mut sealed = seal_message(&message("see you at noon")).unwrap();
+        sealed.content = "see you at midnight".to_string();
+        let stored = serde_json::to_vec(&sealed).unwrap();
+        assert!(!validate_stored(&stored).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stored_message_validates_by_channel_and_id() {
+        let store: Arc<dyn KVStore> = Arc::new(MemoryKVStore::default());
+        let batches = MessageBatches::new(store.clone());
+        let original = message("see you at noon");
+        let sealed = put_sealed(store.as_ref(), &original).await.unwrap();
+
+        assert!(validate_in_store(store.as_ref(), &batches, sealed.channel_id, sealed.id, false).await.unwrap());
+        // Not committed to a batch yet
+        assert!(!validate_in_store(store.as_ref(), &batches, sealed.channel_id, sealed.id, true).await.unwrap());
+        batches.append(sealed.channel_id, 1, sealed.id, &sealed.hash).await.unwrap();
+        assert!(validate_in_store(store.as_ref(), &batches, sealed.channel_id, sealed.id, true).await.unwrap());
+
+        // The message is only found under its own channel
+        assert!(validate_in_store(store.as_ref(), &batches, Uuid::new_v4(), sealed.id, false).await.is_err());
+
+        let mut tampered = sealed.clone();
+        tampered.content = "see you at midnight".to_string();
+        store
+            .set(message_key(sealed.channel_id, sealed.id).into_bytes(), serde_json::to_vec(&tampered).unwrap())
+            .await
+            .unwrap();
+        assert!(!validate_in_store(store.as_ref(), &batches, sealed.channel_id, sealed.id, false).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stored_hash_must_match_the_senders_hash() {
+        let store: Arc<dyn KVStore> = Arc::new(MemoryKVStore::default());
+        let sealed = put_sealed(store.as_ref(), &message("see you at noon")).await.unwrap();
+        assert!(stored_hash_matches(store.as_ref(), sealed.channel_id, sealed.id, &sealed.hash).await.unwrap());
+
+        // The sender holds a different hash than the one stored
+        assert!(!stored_hash_matches(store.as_ref(), sealed.channel_id, sealed.id, "not the stored hash").await.unwrap());
+
+        // The stored copy was overwritten after the sender's write
+        let overwritten = Message {
+            id: sealed.id,
+            channel_id: sealed.channel_id,
+            ..Message::for_test("alice", "bob", "see you at midnight")
+        };
+        put_sealed(store.as_ref(), &overwritten).await.unwrap();
+        assert!(!stored_hash_matches(store.as_ref(), sealed.channel_id, sealed.id, &sealed.hash).await.unwrap());
+
+        // The stored copy no longer matches its own hash
+        let mut tampered = sealed.clone();
+        tampered.content = "see you at midnight".to_string();
+        store
+            .set(message_key(sealed.channel_id, sealed.id).into_bytes(), serde_json::to_vec(&tampered).unwrap())
+            .await
+            .unwrap();
+        assert!(!stored_hash_matches(store.as_ref(), sealed.channel_id, sealed.id, &sealed.hash).await.unwrap());
+    }
+}
@@ -73,7 +73,7 @@ use std::collections::HashMap;
 use uuid::Uuid;
 
 use crate::agents::knowledge_agent::KnowledgeAgent;
-use crate::agents::q_learning_agent::QLearningAgent;
+use crate::agents::q_learning_agent::{QLearningAgent, QLearningConfig};
 use crate::graphs::delegate_graph::{Attribute, Delegate};
 use crate::messaging::message::Message;
 
@@ -225,59 +225,20 @@ fn main() {
     generic_knowledge_agent.update_knowledge_graph(&generic_input);
 
     // Create Q-learning agents for each modality
-    let num_states = 10;
-    let num_actions = 5;
-    let gamma = 0.9;
-    let learning_rate = 0.1;
-    let exploration_rate = 0.1;
-    let min_exploration_rate = 0.01;
-    let exploration_decay_rate = 0.001;
+    let q_learning_config = QLearningConfig {
+        gamma: 0.9,
+        learning_rate: 0.1,
+        exploration_rate: 0.1,
+        min_exploration_rate: 0.01,
+        exploration_decay_rate: Some(0.001),
+        ..QLearningConfig::new(10, 5)
+    };
 
-    let mut text_q_learning_agent = QLearningAgent::new(
-        num_states,
-        num_actions,
-        gamma,
-        learning_rate,
-        exploration_rate,
-        min_exploration_rate,
-        exploration_decay_rate,
-    );
-    let mut audio_q_learning_agent = QLearningAgent::new(
-        num_states,
-        num_actions,
-        gamma,
-        learning_rate,
-        exploration_rate,
-        min_exploration_rate,
-        exploration_decay_rate,
-    );
-    let mut image_q_learning_agent = QLearningAgent::new(
-        num_states,
-        num_actions,
-        gamma,
-        learning_rate,
-        exploration_rate,
-        min_exploration_rate,
-        exploration_decay_rate,
-    );
-    let mut video_q_learning_agent = QLearningAgent::new(
-        num_states,
-        num_actions,
-        gamma,
-        learning_rate,
-        exploration_rate,
-        min_exploration_rate,
-        exploration_decay_rate,
-    );
-    let mut generic_q_learning_agent = QLearningAgent::new(
-        num_states,
-        num_actions,
-        gamma,
-        learning_rate,
-        exploration_rate,
-        min_exploration_rate,
-        exploration_decay_rate,
-    );
+    let mut text_q_learning_agent = QLearningAgent::from_config(q_learning_config.clone());
+    let mut audio_q_learning_agent = QLearningAgent::from_config(q_learning_config.clone());
+    let mut image_q_learning_agent = QLearningAgent::from_config(q_learning_config.clone());
+    let mut video_q_learning_agent = QLearningAgent::from_config(q_learning_config.clone());
+    let mut generic_q_learning_agent = QLearningAgent::from_config(q_learning_config);
 
     // Train the Q-learning agents
     text_q_learning_agent.train(100);
@@ -349,10 +310,11 @@ fn process_message(
 
     // Use the knowledge agent to search for relevant information in the knowledge graph
     let relevant_info = knowledge_agent
-        .search(&interests.join(" "))
+        .search(&interests.join(" "), 5)
         .into_iter()
-        .chain(knowledge_agent.search(&expertise.join(" ")))
-        .collect::<Vec<&str>>();
+        .chain(knowledge_agent.search(&expertise.join(" "), 5))
+        .map(|hit| hit.text)
+        .collect::<Vec<String>>();
 
     // Use the Q-learning agent to select the best action based on the current state
     let state = q_learning_agent.get_state(data);
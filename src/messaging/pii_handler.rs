@@ -71,37 +71,96 @@
 //! Make sure to have the necessary dependencies installed and configured before using the module.
 
 use crate::bindings::spacy_bindings::{EntityLabel, LangModel, SPACY};
+use crate::clients::kv::Clock;
 use crate::encryption::encryption::EncryptHandler;
-use crate::iam::jwt::JWT;
+use crate::iam::jwt::{unix_now, DEFAULT_CLOCK_SKEW, JWT};
 use crate::iam::verifiable_credentials::{Proof, VerifiableCredential, VCBuilder};
 use crate::utils::bigboterror::BigbotError;
+use crate::utils::metrics::{self, names};
 use crate::messaging::message::Message;
 
 use kafka::producer::AsBytes;
 use regex::{Captures, Regex};
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::Duration;
 use pyo3::prelude::*;
 
+/// How long an unmask credential issued by `apply_for_masked_message` stays valid.
+pub const DEFAULT_UNMASK_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
 #[derive(Serialize, Deserialize)]
 pub struct LogEntry {
     pub masked_message: String,
     pub unmasked_message: String,
 }
 
+/// Where `PIIHandler::mask` looks for PII.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskStrategy {
+    /// spaCy entities with a sensitive label, each replaced by `**`.
+    Entities,
+    /// The regex patterns from `static/pii_masking.yml`, each replaced by its configured mask.
+    Patterns,
+}
+
+/// One pattern from `static/pii_masking.yml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PiiPattern {
+    pub pattern: String,
+    pub mask: PiiMask,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PiiMask {
+    /// Replaces the match with the string as is.
+    Fixed(String),
+    /// Replaces the match with the string after expanding `$n` capture group references.
+    Partial(String),
+    /// Expanded like `Partial`.
+    Random(String),
+}
+
+/// A mask in a masked message and the original text it replaced, recorded so the message can be
+/// unmasked whatever the mask looks like. `start` is a byte offset into the masked message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaskedSpan {
+    pub start: usize,
+    pub len: usize,
+    pub original: String,
+}
+
+// What spaCy-based masking puts in place of a sensitive entity
+const ENTITY_MASK: &str = "**";
+
+// A byte range of the unmasked text and the mask to put in its place
+struct PiiHit {
+    range: Range<usize>,
+    mask: String,
+}
+
 pub struct PIIHandler {
     pub sensitive_entities: Vec<EntityLabel>,
     pub mask_char: char,
     pub language: LangModel,
     pub encrypt_handler: Arc<EncryptHandler>,
-    pub pii_patterns: HashMap<String, String>,
+    pub pii_patterns: HashMap<String, PiiPattern>,
+    /// Lifetime of issued unmask credentials.
+    pub unmask_token_ttl: Duration,
+    /// Clock skew tolerated when checking an unmask credential's expiry.
+    pub clock_skew: Duration,
+    /// Current time in seconds since the epoch, used to issue and check unmask credentials.
+    pub clock: Clock<u64>,
 }
 
 impl PIIHandler {
-    pub fn new(encrypt_handler: Arc<EncryptHandler>) -> PIIHandler {
-        let pii_patterns = load_pii_patterns();
-        PIIHandler {
+    pub fn new(encrypt_handler: Arc<EncryptHandler>) -> Result<PIIHandler, BigbotError> {
+        let pii_patterns = load_pii_patterns()?;
+        Ok(PIIHandler {
             sensitive_entities: vec![
                 EntityLabel::Phone,
                 EntityLabel::Email,
@@ -111,34 +170,118 @@ impl PIIHandler {
             language: SPACY.model_default().clone(),
             encrypt_handler,
             pii_patterns,
+            unmask_token_ttl: DEFAULT_UNMASK_TOKEN_TTL,
+            clock_skew: DEFAULT_CLOCK_SKEW,
+            clock: Arc::new(unix_now),
+        })
+    }
+
+    /// Masks PII in every text-bearing part of the message: the content and any text attachments.
+    /// Returns the sanitized message and a single token covering all masked parts, encrypted with
+    /// the key of the sender, whose `sender` must be their numeric user id.
+    pub async fn sanitize(&self, message: &Message) -> Result<(Message, String), BigbotError> {
+        let sender_id: i64 = message
+            .sender
+            .parse()
+            .map_err(|_| BigbotError::InvalidInput(format!("Message sender is not a user id: {}", message.sender)))?;
+        let mut part_masks: HashMap<String, Vec<MaskedSpan>> = HashMap::new();
+        let mut sanitized_message = message.clone();
+
+        let (masked_content, masks) = self
+            .mask_text(&message.content, MaskStrategy::Entities)
+            .await?;
+        sanitized_message.content = masked_content;
+        part_masks.insert(CONTENT_PART.to_string(), masks);
+
+        for (index, attachment) in sanitized_message.attachments.iter_mut().enumerate() {
+            if !attachment.is_text() {
+                continue;
+            }
+            let (masked_attachment, masks) = self
+                .mask_text(&attachment.content, MaskStrategy::Entities)
+                .await?;
+            attachment.content = masked_attachment;
+            part_masks.insert(attachment_part(index), masks);
         }
+
+        let token = self.generate_token(part_masks, sender_id).await?;
+        Ok((sanitized_message, token))
     }
 
-    pub fn sanitize(&self, message: &Message) -> Result<Message, BigbotError> {
-        let (masked_content, token) = self.mask_pii(&message.content, message.sender_id)?;
-        let sanitized_message = Message {
-            content: masked_content,
-            ..message.clone()
-        };
-        Ok(sanitized_message)
+    /// Restores every part masked by `sanitize`, given a credential issued by `apply_for_masked_message`.
+    pub async fn unmask_sanitized_message(
+        &self,
+        message: &Message,
+        sender_id: i64,
+        recipient_id: i64,
+        vc_str: String,
+    ) -> Result<Message, BigbotError> {
+        let json_token = self.decrypt_unmask_credential(sender_id, recipient_id, vc_str).await?;
+        let part_masks: HashMap<String, Vec<MaskedSpan>> = serde_json::from_slice(&json_token)
+            .map_err(|_| BigbotError::RejectedError("Invalid verifiable credential".to_string()))?;
+
+        let mut unmasked_message = message.clone();
+        if let Some(masks) = part_masks.get(CONTENT_PART) {
+            unmasked_message.content = restore_masks(&message.content, masks);
+        }
+        for (index, attachment) in unmasked_message.attachments.iter_mut().enumerate() {
+            if let Some(masks) = part_masks.get(&attachment_part(index)) {
+                attachment.content = restore_masks(&attachment.content, masks);
+            }
+        }
+        Ok(unmasked_message)
     }
 
+    /// Masks PII matched by the configured patterns, without recording anything to unmask it with.
+    /// Use `mask` with `MaskStrategy::Patterns` when the message needs to be unmasked later.
     pub fn mask_pii_with_patterns(&self, message: &str) -> String {
-        let mut masked_message = message.to_string();
-        for (pii_type, pattern) in &self.pii_patterns {
-            let re = Regex::new(pattern).unwrap();
-            masked_message = re.replace_all(&masked_message, |caps: &Captures| {
-                match pii_type.as_str() {
-                    "email" => "[EMAIL_MASKED]".to_string(),
-                    "phone" => format!("XXX-XXX-{}", &caps[1]),
-                    "credit_card" => "[CREDIT_CARD_MASKED]".to_string(),
-                    "ssn" => format!("XXX-XX-{}", &caps[1]),
-                    "ip_address" => format!("{}.{}.{}.{}", &caps[1], &caps[2], &caps[3], &caps[4]),
-                    _ => caps[0].to_string(),
-                }
-            }).to_string();
+        apply_masks(message, self.pattern_hits(message)).0
+    }
+
+    // Matches every configured pattern against the original text, so one pattern's mask is never
+    // matched by another
+    fn pattern_hits(&self, text: &str) -> Vec<PiiHit> {
+        let mut hits = Vec::new();
+        for pii_pattern in self.pii_patterns.values() {
+            let re = Regex::new(&pii_pattern.pattern).unwrap();
+            for caps in re.captures_iter(text) {
+                let whole = caps.get(0).unwrap();
+                hits.push(PiiHit {
+                    range: whole.range(),
+                    mask: pii_pattern.mask.render(&caps),
+                });
+            }
         }
-        masked_message
+        hits
+    }
+
+    // Finds the sensitive entities spaCy recognises in `text`
+    async fn entity_hits(&self, text: &str) -> Result<Vec<PiiHit>, BigbotError> {
+        let doc = Python::with_gil(|py| self.language.nlp(text.to_string())).await?;
+        // spaCy reports character offsets; masking works on byte offsets
+        let byte_offset = |char_offset: usize| {
+            text.char_indices()
+                .nth(char_offset)
+                .map_or(text.len(), |(offset, _)| offset)
+        };
+        let mut hits = Vec::new();
+        Python::with_gil(|py| {
+            for raw_ent in doc.ents(py)?.iter() {
+                let entity = raw_ent.export(py)?;
+                if self.is_sensitive_entity(entity.label) {
+                    let (start, end) = (
+                        raw_ent.start_char(py).unwrap() as usize,
+                        raw_ent.end_char(py).unwrap() as usize,
+                    );
+                    hits.push(PiiHit {
+                        range: byte_offset(start)..byte_offset(end),
+                        mask: ENTITY_MASK.to_string(),
+                    });
+                }
+            }
+            Ok::<(), BigbotError>(())
+        })?;
+        Ok(hits)
     }
 
     pub fn is_sensitive_entity(&self, label: EntityLabel) -> bool {
@@ -153,38 +296,57 @@ impl PIIHandler {
         self.language = language;
     }
 
+    pub fn set_unmask_token_ttl(&mut self, ttl: Duration) {
+        self.unmask_token_ttl = ttl;
+    }
+
+    pub fn set_clock_skew(&mut self, clock_skew: Duration) {
+        self.clock_skew = clock_skew;
+    }
+
+    pub fn set_clock(&mut self, clock: Clock<u64>) {
+        self.clock = clock;
+    }
+
     pub async fn mask_pii(
         &self,
         message: &str,
         sender_id: i64,
-    ) -> Result<(String, String), BigbotError> {   
-        let doc = Python::with_gil(|py| self.language.nlp(message.to_string())).await?;
-        let mut masked_message = message.to_string();
-        let mut masks = HashMap::new();
-        let mut pos_diff = 0isize;
-        Python::with_gil(|py| {
-            for raw_ent in doc.ents(py)?.iter() {
-                let entity = raw_ent.export(py)?;
-                if self.is_sensitive_entity(entity.label) {
-                    let (start, end) = (
-                        raw_ent.start_char(py).unwrap() as isize,
-                        raw_ent.end_char(py).unwrap() as isize,
-                    );
-                    masks.insert(start + pos_diff, entity.text);
-                    masked_message
-                        .replace_range((start + pos_diff) as usize..(end + pos_diff) as usize, "**");
-                    pos_diff += (2 - (end - start)) as isize;
-                }
-            }
-            Ok::<(), BigbotError>(())
-        })?;
+    ) -> Result<(String, String), BigbotError> {
+        self.mask(message, sender_id, MaskStrategy::Entities).await
+    }
+
+    /// Masks PII found by `strategy`, returning the masked message and a token that
+    /// `unmask_message` can restore it from once the recipient has been granted access.
+    pub async fn mask(
+        &self,
+        message: &str,
+        sender_id: i64,
+        strategy: MaskStrategy,
+    ) -> Result<(String, String), BigbotError> {
+        let (masked_message, masks) = self.mask_text(message, strategy).await?;
         let masked_token = self.generate_token(masks, sender_id).await?;
         let _log_entry = LogEntry {
             masked_message: masked_message.clone(),
             unmasked_message: "".to_string(),
         };
         Ok::<(String, String), BigbotError>((masked_message, masked_token))
-    }         
+    }
+
+    // Masks the PII `strategy` finds in `text`, returning the masked text and the spans to restore
+    async fn mask_text(
+        &self,
+        text: &str,
+        strategy: MaskStrategy,
+    ) -> Result<(String, Vec<MaskedSpan>), BigbotError> {
+        let hits = match strategy {
+            MaskStrategy::Entities => self.entity_hits(text).await?,
+            MaskStrategy::Patterns => self.pattern_hits(text),
+        };
+        let (masked_message, masks) = apply_masks(text, hits);
+        metrics::increment_counter_by(names::PII_SPANS_MASKED, &[], masks.len() as u64);
+        Ok((masked_message, masks))
+    }
 
     pub async fn unmask_message(
         &self,
@@ -193,13 +355,28 @@ impl PIIHandler {
         recipient_id: i64,
         vc_str: String,
     ) -> Result<String, BigbotError> {
+        let json_token = self.decrypt_unmask_credential(sender_id, recipient_id, vc_str).await?;
+        let masks: Vec<MaskedSpan> = serde_json::from_slice(json_token.as_bytes())
+            .map_err(|_x| BigbotError::RejectedError(format!("Invalid verifiable credential")))?;
+
+        // 3. Replace masked PII with original values
+        Ok(restore_masks(masked_message, &masks))
+    }
+
+    // Validates an unmask credential and decrypts the PII token it carries
+    async fn decrypt_unmask_credential(
+        &self,
+        sender_id: i64,
+        recipient_id: i64,
+        vc_str: String,
+    ) -> Result<Vec<u8>, BigbotError> {
         // 1. Validate the VC
         let err_invalid_vc = BigbotError::RejectedError(format!("Invalid verifiable credential"));
         let vc: VerifiableCredential =
             serde_json::from_str(vc_str.as_str()).map_err(|_x| err_invalid_vc.clone())?;
-        let jwt = match vc.get_proof() {
+        let jwt = match vc.get_proof().and_then(|proof| proof.jwt.as_ref()) {
             None => return Err(err_invalid_vc.clone()),
-            Some(proof) => JWT::decode(&serde_json::to_string(proof).map_err(|_| err_invalid_vc.clone())?).await?,
+            Some(token) => JWT::decode_at(token, (self.clock)(), self.clock_skew).await?,
         };
         let encrypted_token: Vec<u8> = match jwt.get_payload("pii") {
             None => return Err(err_invalid_vc.clone()),
@@ -211,29 +388,14 @@ impl PIIHandler {
             .encrypt_handler
             .negotiate_shared_keyid(recipient_id, sender_id)
             .await?;
-        let json_token = self
-            .encrypt_handler
+        self.encrypt_handler
             .aes_decrypt_message(&shared_keyid, encrypted_token.as_bytes())
-            .await?;
-        let pii_map: HashMap<isize, String> =
-            serde_json::from_slice(json_token.as_bytes()).map_err(|_x| err_invalid_vc.clone())?;
-
-        // 3. Replace masked PII with original values
-        let mut pos_diff = 0isize;
-        let mut masked_message = masked_message.to_string();
-        for (pos, text) in pii_map {
-            masked_message.replace_range(
-                (pos + pos_diff) as usize..(pos_diff + pos + 2) as usize,
-                &text,
-            );
-            pos_diff = text.len() as isize - 2;
-        }
-        Ok(masked_message)
+            .await
     }
 
-    async fn generate_token(
+    async fn generate_token<T: Serialize>(
         &self,
-        masked_info: HashMap<isize, String>,
+        masked_info: T,
         sender_id: i64,
     ) -> Result<String, BigbotError> {
         // Query or create a secret for the sender user
@@ -283,6 +445,7 @@ impl PIIHandler {
         // Place the generated encrypted token into a VC
         let mut jwt = JWT::empty();
         jwt.add_payload("pii".to_string(), token_for_recipient);
+        jwt.add_exp_at((self.clock)(), self.unmask_token_ttl);
         let proof = jwt.encode().await?;
         let vc_builder = VCBuilder::default();
         let vc = vc_builder
@@ -298,26 +461,113 @@ impl PIIHandler {
     }
 }
 
-fn load_pii_patterns() -> HashMap<String, String> {
-    let yaml_str = include_str!("../../static/pii_masking.yml");
-    serde_yaml::from_str(yaml_str).unwrap()
+// Key of the message content in the per-part mask map produced by `sanitize`
+const CONTENT_PART: &str = "content";
+
+// Key of a text attachment in the per-part mask map produced by `sanitize`
+fn attachment_part(index: usize) -> String {
+    format!("attachment:{}", index)
+}
+
+impl PiiMask {
+    fn render(&self, caps: &Captures) -> String {
+        match self {
+            PiiMask::Fixed(mask) => mask.clone(),
+            PiiMask::Partial(template) | PiiMask::Random(template) => {
+                let mut mask = String::new();
+                caps.expand(template, &mut mask);
+                mask
+            }
+        }
+    }
+}
+
+// Replaces each hit with its mask. Overlapping hits keep the earliest, longest one.
+fn apply_masks(text: &str, mut hits: Vec<PiiHit>) -> (String, Vec<MaskedSpan>) {
+    hits.sort_by_key(|hit| (hit.range.start, Reverse(hit.range.end)));
+    let mut masked_message = String::with_capacity(text.len());
+    let mut masks = Vec::new();
+    let mut cursor = 0;
+    for hit in hits {
+        if hit.range.start < cursor {
+            continue;
+        }
+        masked_message.push_str(&text[cursor..hit.range.start]);
+        masks.push(MaskedSpan {
+            start: masked_message.len(),
+            len: hit.mask.len(),
+            original: text[hit.range.clone()].to_string(),
+        });
+        masked_message.push_str(&hit.mask);
+        cursor = hit.range.end;
+    }
+    masked_message.push_str(&text[cursor..]);
+    (masked_message, masks)
+}
+
+// Puts the original values back at the positions recorded while masking. Spans that don't fit the
+// message, e.g. because it was edited after masking, are left masked.
+fn restore_masks(masked_message: &str, masks: &[MaskedSpan]) -> String {
+    let mut masks: Vec<&MaskedSpan> = masks.iter().collect();
+    // Working from the end keeps the earlier offsets valid
+    masks.sort_by_key(|mask| Reverse(mask.start));
+    let mut message = masked_message.to_string();
+    for mask in masks {
+        let range = mask.start..mask.start + mask.len;
+        if message.get(range.clone()).is_some() {
+            message.replace_range(range, &mask.original);
+        }
+    }
+    message
+}
+
+fn load_pii_patterns() -> Result<HashMap<String, PiiPattern>, BigbotError> {
+    parse_pii_patterns(include_str!("../../static/pii_masking.yml"))
+}
+
+// The YAML file also carries the API description, so only entries with a `pattern` key are
+// patterns. A pattern entry that doesn't deserialize or compile is an error rather than skipped.
+fn parse_pii_patterns(yaml_str: &str) -> Result<HashMap<String, PiiPattern>, BigbotError> {
+    let entries: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(yaml_str)
+        .map_err(|e| BigbotError::InvalidInput(format!("Invalid PII pattern file: {}", e)))?;
+    let mut patterns = HashMap::new();
+    for (name, value) in entries {
+        if value.get("pattern").is_none() {
+            continue;
+        }
+        let pii_pattern: PiiPattern = serde_yaml::from_value(value).map_err(|e| {
+            BigbotError::InvalidInput(format!("Invalid PII pattern '{}': {}", name, e))
+        })?;
+        Regex::new(&pii_pattern.pattern).map_err(|e| {
+            BigbotError::InvalidInput(format!("Invalid regex for PII pattern '{}': {}", name, e))
+        })?;
+        patterns.insert(name, pii_pattern);
+    }
+    Ok(patterns)
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
     use std::sync::Arc;
+    use std::time::Duration;
+    use crate::utils::bigboterror::BigbotError;
+    use crate::bindings::spacy_bindings::EntityLabel;
     use crate::clients::kv::{MemoryKVStore, PrefixedKVStore};
     use crate::encryption::encryption::{EncryptHandler, KeysStore};
 
+    fn handler() -> super::PIIHandler {
+        let store = Arc::new(MemoryKVStore::default());
+        let secret_store = PrefixedKVStore::new(store, "OCKAM_SECRET:".into());
+        let keys_store = KeysStore::new(Arc::new(secret_store));
+        super::PIIHandler::new(Arc::new(EncryptHandler::new(keys_store))).unwrap()
+    }
+
     #[tokio::test]
     async fn test_pii_masking() {
         let msg = "I am Paul, and my phone number is 12345678909, nice to meet you";
         let (sender_id, recipient_id) = (1, 2);
-        let store = Arc::new(MemoryKVStore::default());
-        let secret_store = PrefixedKVStore::new(store.clone(), "OCKAM_SECRET:".into());
-        let keys_store = KeysStore::new(Arc::new(secret_store));
-        let encrypt_handler = Arc::new(EncryptHandler::new(keys_store));
-        let handler = super::PIIHandler::new(encrypt_handler);
+        let handler = handler();
         let (masked_msg, token) = handler.mask_pii(msg, sender_id).await.unwrap();
         assert!(!masked_msg.contains("12345678909"));
         let vc = handler
@@ -330,4 +580,151 @@ mod test {
             .unwrap();
         assert_eq!(msg, unmasked_msg);
     }
+
+    #[tokio::test]
+    async fn test_expired_unmask_credential_is_rejected() {
+        let msg = "Call me on 12345678909";
+        let (sender_id, recipient_id) = (1, 2);
+        let mut handler = handler();
+        let now = Arc::new(AtomicU64::new(1_700_000_000));
+        let reading = now.clone();
+        handler.set_clock(Arc::new(move || reading.load(Ordering::SeqCst)));
+        handler.set_unmask_token_ttl(Duration::from_secs(60));
+        handler.set_clock_skew(Duration::ZERO);
+
+        let (masked_msg, token) = handler.mask_pii(msg, sender_id).await.unwrap();
+        let vc = handler
+            .apply_for_masked_message(token, sender_id, recipient_id)
+            .await
+            .unwrap();
+        let unmasked = handler
+            .unmask_message(masked_msg.as_str(), sender_id, recipient_id, vc.clone())
+            .await
+            .unwrap();
+        assert_eq!(unmasked, msg);
+
+        now.fetch_add(61, Ordering::SeqCst);
+
+        let result = handler
+            .unmask_message(masked_msg.as_str(), sender_id, recipient_id, vc)
+            .await;
+        assert!(matches!(result, Err(BigbotError::TokenExpired(_))));
+    }
+
+    #[tokio::test]
+    async fn test_sanitize_masks_content_and_text_attachments() {
+        use crate::messaging::message::{Attachment, Message};
+        use uuid::Uuid;
+
+        let (sender_id, recipient_id) = (1, 2);
+        let handler = handler();
+
+        let message = Message {
+            channel_id: Uuid::new_v4(),
+            attachments: vec![
+                Attachment {
+                    content_type: "text/plain".to_string(),
+                    content: "Backup number: 98765432101".to_string(),
+                    file_path: None,
+                },
+                Attachment {
+                    content_type: "image/png".to_string(),
+                    content: "98765432101".to_string(),
+                    file_path: Some("/tmp/receipt.png".to_string()),
+                },
+            ],
+            ..Message::for_test(
+                &sender_id.to_string(),
+                &recipient_id.to_string(),
+                "Call me on 12345678909 tonight",
+            )
+        };
+
+        let (sanitized, token) = handler.sanitize(&message).await.unwrap();
+        assert!(!sanitized.content.contains("12345678909"));
+        assert!(!sanitized.attachments[0].content.contains("98765432101"));
+        // Non-text attachments are passed through untouched
+        assert_eq!(sanitized.attachments[1], message.attachments[1]);
+
+        let vc = handler
+            .apply_for_masked_message(token, sender_id, recipient_id)
+            .await
+            .unwrap();
+        let unmasked = handler
+            .unmask_sanitized_message(&sanitized, sender_id, recipient_id, vc)
+            .await
+            .unwrap();
+        assert_eq!(unmasked.content, message.content);
+        assert_eq!(unmasked.attachments, message.attachments);
+
+        let anonymous = Message::for_test("anonymous", &recipient_id.to_string(), "Call me on 12345678909 tonight");
+        let err = handler.sanitize(&anonymous).await.unwrap_err();
+        assert!(matches!(err, BigbotError::InvalidInput(ref msg) if msg.contains("anonymous")));
+    }
+
+    #[tokio::test]
+    async fn test_pii_masking_multi_byte_entities() {
+        let msg = "This is José, his phone number is 12345678909";
+        let (sender_id, recipient_id) = (1, 2);
+        let mut handler = handler();
+        handler.sensitive_entities.push(EntityLabel::Person);
+
+        let (masked_msg, token) = handler.mask_pii(msg, sender_id).await.unwrap();
+        assert!(!masked_msg.contains("José"));
+        assert!(!masked_msg.contains("12345678909"));
+
+        let vc = handler
+            .apply_for_masked_message(token, sender_id, recipient_id)
+            .await
+            .unwrap();
+        let unmasked_msg = handler
+            .unmask_message(masked_msg.as_str(), sender_id, recipient_id, vc)
+            .await
+            .unwrap();
+        assert_eq!(msg, unmasked_msg);
+    }
+
+    #[tokio::test]
+    async fn test_pattern_masking_round_trips() {
+        let msg = "Mail john@example.com or call 555-123-4567 before noon";
+        let (sender_id, recipient_id) = (1, 2);
+        let handler = handler();
+
+        let (masked_msg, token) = handler
+            .mask(msg, sender_id, super::MaskStrategy::Patterns)
+            .await
+            .unwrap();
+        assert!(masked_msg.contains("[EMAIL_MASKED]"));
+        assert!(!masked_msg.contains("john@example.com"));
+        assert!(!masked_msg.contains("555-123-4567"));
+        assert_eq!(masked_msg, handler.mask_pii_with_patterns(msg));
+
+        let vc = handler
+            .apply_for_masked_message(token, sender_id, recipient_id)
+            .await
+            .unwrap();
+        let unmasked_msg = handler
+            .unmask_message(masked_msg.as_str(), sender_id, recipient_id, vc)
+            .await
+            .unwrap();
+        assert_eq!(msg, unmasked_msg);
+    }
+
+    #[test]
+    fn test_bad_pii_patterns_are_rejected() {
+        let patterns = super::load_pii_patterns().unwrap();
+        assert!(patterns.contains_key("email"));
+        assert!(!patterns.contains_key("openapi"));
+
+        let missing_mask = "openapi: 3.0.0\nemail:\n  pattern: 'x@y'\n";
+        assert!(matches!(
+            super::parse_pii_patterns(missing_mask),
+            Err(BigbotError::InvalidInput(_))
+        ));
+        let bad_regex = "email:\n  pattern: '(unclosed'\n  mask:\n    fixed: '[EMAIL]'\n";
+        assert!(matches!(
+            super::parse_pii_patterns(bad_regex),
+            Err(BigbotError::InvalidInput(_))
+        ));
+    }
 }
@@ -224,10 +224,11 @@ fn process_message(
 
     // Use the knowledge agent to search for relevant information in the knowledge graph
     let relevant_info = knowledge_agent
-        .search(&interests.join(" "))
+        .search(&interests.join(" "), 5)
         .into_iter()
-        .chain(knowledge_agent.search(&expertise.join(" ")))
-        .collect::<Vec<&str>>();
+        .chain(knowledge_agent.search(&expertise.join(" "), 5))
+        .map(|hit| hit.text)
+        .collect::<Vec<String>>();
 
     // Use the Q-learning agent to select the best action based on the current state
     let state = q_learning_agent.get_state(data);
@@ -0,0 +1,2 @@
+pub mod message_stream;
+pub mod my_grpc_service;
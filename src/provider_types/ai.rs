@@ -16,6 +16,11 @@
 //! ## Traits
 //!
 //! - `AIProviderTrait`: Defines the interface for an AI provider, including methods for running inference, generation, and retrieving provider information.
+//!   `stream_generation` yields the generated text in chunks; providers without streaming support return the whole reply as one chunk.
+//! - `ChatProvider`: A provider-neutral chat completion interface. Implementations map `ChatMessage` roles onto their API
+//!   and report the reply as a `ChatCompletion` with normalized token usage and finish reason.
+//!   `complete_stream` yields the reply as `ChatDelta`s; the last one has `done` set, and API errors part-way through
+//!   arrive as `Err` items. Dropping the stream closes the underlying connection.
 //!
 //! ## Functions
 //!
@@ -31,6 +36,7 @@
 //! 4. The response from the AI provider will be returned, and the generated message will be routed using the `MessageRouter`.
 //!
 
+use futures::stream::{self, BoxStream, StreamExt};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -39,6 +45,13 @@ use tokio::sync::Mutex;
 
 use crate::messaging::message::Message;
 use crate::messaging::message_classifier::classify_message;
+use crate::utils::bigboterror::BigbotError;
+
+// Generated text chunks, in order; an error ends the stream
+pub type GenerationStream = BoxStream<'static, Result<String, BigbotError>>;
+
+// Chat reply chunks, in order; an error ends the stream
+pub type ChatStream = BoxStream<'static, Result<ChatDelta, BigbotError>>;
 
 
 #[derive(Serialize, Deserialize)]
@@ -69,11 +82,107 @@ pub struct GenerationResponse {
     pub model_used: Option<String>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: ChatRole, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::Assistant, content)
+    }
+}
+
+// Per-request overrides; `None` uses the provider's configured default
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompletionOpts {
+    pub model: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinishReason {
+    // The model finished its reply or hit a stop sequence
+    Stop,
+    // The reply was cut off by the token limit
+    Length,
+    // Any other provider-specific reason, as reported
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatCompletion {
+    pub text: String,
+    pub usage: TokenUsage,
+    pub finish_reason: FinishReason,
+}
+
+// A piece of a streamed reply. The final delta has `done` set and may carry no text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChatDelta {
+    pub text: String,
+    pub done: bool,
+}
+
+#[async_trait::async_trait]
+pub trait ChatProvider: Send + Sync {
+    async fn complete(&self, messages: Vec<ChatMessage>, opts: CompletionOpts) -> Result<ChatCompletion, BigbotError>;
+
+    async fn complete_stream(&self, messages: Vec<ChatMessage>, opts: CompletionOpts) -> Result<ChatStream, BigbotError> {
+        let completion = self.complete(messages, opts).await?;
+        let delta = ChatDelta {
+            text: completion.text,
+            done: true,
+        };
+        Ok(stream::once(async move { Ok(delta) }).boxed())
+    }
+}
+
 #[async_trait::async_trait]
 pub trait AIProviderTrait {
     async fn run_inference(&self, request: InferenceRequest) -> Result<InferenceResponse, reqwest::Error>;
     async fn run_generation(&self, request: GenerationRequest) -> Result<GenerationResponse, reqwest::Error>;
     async fn get_provider_info(&self) -> Result<ProviderInfo, reqwest::Error>;
+
+    async fn stream_generation(&self, request: GenerationRequest) -> GenerationStream {
+        let chunk = self
+            .run_generation(request)
+            .await
+            .map(|response| response.message.content)
+            .map_err(|e| BigbotError::SystemError(e.to_string()));
+        stream::once(async move { chunk }).boxed()
+    }
 }
 
 struct AIProvider {
@@ -8,43 +8,185 @@
 // - max(&self, field: &str): Finds the maximum value in a specified field.
 // - filter(&mut self, field: &str, value: &str): Filters the data based on a specified field and value.
 // - query(&mut self, query: &str): Queries the data based on a specified condition.
+// - resample(&self, field_time: &str, field_value: &str, interval: Duration, agg: Aggregation, gap_fill: GapFill):
+//   Buckets rows into fixed time intervals, aggregates each bucket and fills empty buckets.
 
-// Model: Represents a pseudo ORM model with fields and provides CRUD operations.
-// - new(name: String, fields: Vec<String>): Creates a new Model instance.
-// - create(&self, data: HashMap<String, String>): Creates a new record.
+// Model: Represents a pseudo ORM model with fields and provides CRUD operations. Records are JSON maps
+// stored in a KVStore under `{name}/{id}`, with auto-incrementing ids starting at "1".
+// - new(name: String, fields: Vec<String>, store: Arc<dyn KVStore>): Creates a new Model instance.
+// - create(&self, data: HashMap<String, String>): Creates a new record and returns its ID.
 // - read(&self, id: &str): Reads a record by its ID.
-// - update(&self, id: &str, data: HashMap<String, String>): Updates a record by its ID.
+// - update(&self, id: &str, data: HashMap<String, String>): Overwrites the given fields of a record.
 // - delete(&self, id: &str): Deletes a record by its ID.
-// - search(&self, query: &str): Searches for records based on a specified query.
+// - search(&self, query: &str): Finds the records with a value containing the query.
+// - bulk_create, bulk_update, bulk_delete: The single-record operations over many records.
+// - count(&self): The number of records.
+// - paginate(&self, page: usize, per_page: usize): One page of records in ID order, counting pages from 1.
 
 // ChartConfig: Represents the configuration options for a chart.
 // - new(): Creates a new ChartConfig instance with default values.
 // - set_plot_width(&mut self, width: u32): Sets the plot width of the chart.
 // - set_plot_height(&mut self, height: u32): Sets the plot height of the chart.
-// - set_color_scheme(&mut self, scheme: &str): Sets the color scheme of the chart.
+// - set_color_scheme(&mut self, scheme: &str): Sets a named palette; unknown names are rejected.
+// - set_palette(&mut self, palette: Palette): Sets a palette, validating the colors of a custom one.
 // - set_transparency(&mut self, transparency: f32): Sets the transparency of the chart.
 
 // InteractiveChart: Represents an interactive chart with data, configuration, and rendering capabilities.
 // - new(chart_type: String, data: HashMap<String, Vec<f64>>, config: ChartConfig): Creates a new InteractiveChart instance.
 // - update_data(&mut self, data: HashMap<String, Vec<f64>>): Updates the data of the chart.
 // - update_config(&mut self, config: ChartConfig): Updates the configuration of the chart.
-// - render(&self): Renders the chart based on its type, data, and configuration.
-
-// suggest_chart_types(data_bin: &DataBin): Suggests suitable chart types based on the data in the DataBin.
-// is_numeric_field(field: &str): Checks if a field contains numeric data.
-// is_categorical_field(field: &str): Checks if a field contains categorical data.
+// - series_colors(&self): Assigns a palette color to each series, in series name order.
+// - to_spec(&self): Builds a serializable ChartSpec, with a concrete hex color per series, for a Bokeh/Vega frontend.
+// - render(&self): Serializes the chart spec to JSON.
+// ChartSpec::with_default_config(chart_type: &str, data): Builds a spec for the data with the default ChartConfig.
+
+// Palette: A color-blind-safe named color scheme, or a custom list of hex colors.
+// - from_name(name: &str): Looks up a named palette, ignoring case and separators.
+// - colors(&self): The palette's colors as `#RRGGBB` strings.
+// - color_for(&self, index: usize): The color for the index-th series, cycling through the palette.
+
+// suggest_chart_types(data_bin: &DataBin): Suggests suitable chart types for the data in the DataBin, ranked by score, each with a rationale.
+// is_numeric_field(data_bin: &DataBin, field: &str): Checks if a field's values are (almost all) numbers.
+// is_categorical_field(data_bin: &DataBin, field: &str): Checks if a field has values that aren't numeric.
 // prepare_data_for_chart(data_bin: &DataBin, chart_type: &str): Prepares the data for a specific chart type.
 
 
-use std::collections::HashMap;
+use chrono::DateTime;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::clients::kv::KVStore;
+use crate::utils::bigboterror::BigbotError;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ChartError {
+    #[error("Resample interval must be at least one second")]
+    InvalidInterval,
+    #[error("Invalid timestamp '{value}' in field '{field}'")]
+    InvalidTimestamp { field: String, value: String },
+    #[error("Unknown palette '{0}'")]
+    UnknownPalette(String),
+    #[error("Invalid palette color '{0}', expected #RRGGBB")]
+    InvalidColor(String),
+    #[error("Custom palette must have at least one color")]
+    EmptyPalette,
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+}
+
+const VIRIDIS: &[&str] = &["#440154", "#46327E", "#365C8D", "#277F8E", "#1FA187", "#4AC16D", "#A0DA39", "#FDE725"];
+const PLASMA: &[&str] = &["#0D0887", "#5B02A3", "#9A179B", "#CB4678", "#EB7852", "#FBB32F", "#F0F921"];
+const CIVIDIS: &[&str] = &["#00204D", "#31446B", "#666970", "#958F78", "#CBBA69", "#FFEA46"];
+const OKABE_ITO: &[&str] = &["#E69F00", "#56B4E9", "#009E73", "#F0E442", "#0072B2", "#D55E00", "#CC79A7", "#000000"];
+const TOL_BRIGHT: &[&str] = &["#4477AA", "#EE6677", "#228833", "#CCBB44", "#66CCEE", "#AA3377", "#BBBBBB"];
+
+// Series colors. The named palettes are all distinguishable under the common forms of color blindness.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Palette {
+    Viridis,
+    Plasma,
+    Cividis,
+    OkabeIto,
+    TolBright,
+    // `#RRGGBB` colors, used in order
+    Custom(Vec<String>),
+}
+
+impl Palette {
+    pub fn from_name(name: &str) -> Result<Palette, ChartError> {
+        let normalized: String = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        match normalized.as_str() {
+            "viridis" => Ok(Palette::Viridis),
+            "plasma" => Ok(Palette::Plasma),
+            "cividis" => Ok(Palette::Cividis),
+            "okabeito" => Ok(Palette::OkabeIto),
+            "tolbright" => Ok(Palette::TolBright),
+            _ => Err(ChartError::UnknownPalette(name.to_string())),
+        }
+    }
+
+    pub fn colors(&self) -> Vec<String> {
+        let named = match self {
+            Palette::Viridis => VIRIDIS,
+            Palette::Plasma => PLASMA,
+            Palette::Cividis => CIVIDIS,
+            Palette::OkabeIto => OKABE_ITO,
+            Palette::TolBright => TOL_BRIGHT,
+            Palette::Custom(colors) => return colors.clone(),
+        };
+        named.iter().map(|color| color.to_string()).collect()
+    }
+
+    // The name `from_name` accepts, or "custom"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Palette::Viridis => "viridis",
+            Palette::Plasma => "plasma",
+            Palette::Cividis => "cividis",
+            Palette::OkabeIto => "okabe-ito",
+            Palette::TolBright => "tol-bright",
+            Palette::Custom(_) => "custom",
+        }
+    }
+
+    // Cycles when there are more series than colors
+    pub fn color_for(&self, index: usize) -> String {
+        let colors = self.colors();
+        colors[index % colors.len()].clone()
+    }
+
+    fn validate(&self) -> Result<(), ChartError> {
+        if let Palette::Custom(colors) = self {
+            if colors.is_empty() {
+                return Err(ChartError::EmptyPalette);
+            }
+            if let Some(color) = colors.iter().find(|color| !is_hex_color(color)) {
+                return Err(ChartError::InvalidColor(color.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_hex_color(color: &str) -> bool {
+    color
+        .strip_prefix('#')
+        .map_or(false, |hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+// How the values falling into one resample bucket are combined
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Mean,
+    Sum,
+    Last,
+}
+
+// What an empty resample bucket is filled with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GapFill {
+    // Leave the value out of the row
+    Null,
+    Zero,
+    // Repeat the previous bucket's value; null if no earlier bucket had one
+    CarryForward,
+}
 
-struct DataBin {
+pub struct DataBin {
     data: Vec<HashMap<String, String>>,
     fields: Vec<String>,
 }
 
 impl DataBin {
-    fn new(data: Vec<HashMap<String, String>>, fields: Vec<String>) -> Self {
+    pub fn new(data: Vec<HashMap<String, String>>, fields: Vec<String>) -> Self {
         DataBin { data, fields }
     }
 
@@ -102,80 +244,417 @@ impl DataBin {
         self.data = self.data.iter().filter(|item| item.get(field) == Some(value)).cloned().collect();
     }
 
-    fn query(&mut self, query: &str) {
-        // Implement the query logic based on your specific requirements
-        // Example: self.data = self.data.iter().filter(|item| /* query condition */).cloned().collect();
+    // Keeps the rows matching a predicate such as `value1 > 15 AND category LIKE 'north'`. See
+    // `Predicate` for the syntax. A malformed query leaves the data untouched.
+    fn query(&mut self, query: &str) -> Result<(), ChartError> {
+        let predicate = Predicate::parse(query)?;
+        self.data.retain(|row| predicate.matches(row));
+        Ok(())
+    }
+
+    // Time-series resampling for line/area charts over irregular timestamps. Timestamps may be
+    // unix seconds or RFC 3339 strings; the result has one row per interval between the first and
+    // last timestamp, keyed by the bucket start in unix seconds. Rows with a missing or
+    // non-numeric value don't contribute to their bucket.
+    fn resample(
+        &self,
+        field_time: &str,
+        field_value: &str,
+        interval: Duration,
+        agg: Aggregation,
+        gap_fill: GapFill,
+    ) -> Result<DataBin, ChartError> {
+        let step = interval.as_secs() as i64;
+        if step == 0 {
+            return Err(ChartError::InvalidInterval);
+        }
+
+        let mut samples = Vec::new();
+        for item in &self.data {
+            let raw_time = item.get(field_time).map(String::as_str).unwrap_or_default();
+            let timestamp = parse_timestamp(raw_time).ok_or_else(|| ChartError::InvalidTimestamp {
+                field: field_time.to_string(),
+                value: raw_time.to_string(),
+            })?;
+            let value = item.get(field_value).and_then(|value| value.parse::<f64>().ok());
+            samples.push((timestamp, value));
+        }
+        // Stable, so rows sharing a timestamp keep their order for `Last`
+        samples.sort_by_key(|(timestamp, _)| *timestamp);
+
+        let mut buckets: BTreeMap<i64, Vec<f64>> = BTreeMap::new();
+        for (timestamp, value) in &samples {
+            let bucket = buckets.entry(timestamp.div_euclid(step) * step).or_default();
+            if let Some(value) = value {
+                bucket.push(*value);
+            }
+        }
+
+        let fields = vec![field_time.to_string(), field_value.to_string()];
+        let (first, last) = match (buckets.keys().next(), buckets.keys().next_back()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return Ok(DataBin::new(vec![], fields)),
+        };
+
+        let mut data = Vec::new();
+        let mut previous = None;
+        for bucket_start in (first..=last).step_by(step as usize) {
+            let aggregated = buckets
+                .get(&bucket_start)
+                .filter(|values| !values.is_empty())
+                .map(|values| match agg {
+                    Aggregation::Mean => values.iter().sum::<f64>() / values.len() as f64,
+                    Aggregation::Sum => values.iter().sum(),
+                    Aggregation::Last => values[values.len() - 1],
+                });
+            let value = match (aggregated, gap_fill) {
+                (Some(value), _) => Some(value),
+                (None, GapFill::Null) => None,
+                (None, GapFill::Zero) => Some(0.0),
+                (None, GapFill::CarryForward) => previous,
+            };
+            if value.is_some() {
+                previous = value;
+            }
+
+            let mut row = HashMap::from([(field_time.to_string(), bucket_start.to_string())]);
+            if let Some(value) = value {
+                row.insert(field_value.to_string(), value.to_string());
+            }
+            data.push(row);
+        }
+        Ok(DataBin::new(data, fields))
     }
 }
 
+fn parse_timestamp(value: &str) -> Option<i64> {
+    value
+        .parse::<i64>()
+        .ok()
+        .or_else(|| DateTime::parse_from_rfc3339(value).ok().map(|time| time.timestamp()))
+}
+
 // Pseudo ORM
 struct Model {
     name: String,
     fields: Vec<String>,
+    store: Arc<dyn KVStore>,
+    // Serializes id allocation and read-modify-write updates so concurrent writers in this
+    // process never share an id or lose each other's fields
+    write_lock: Mutex<()>,
 }
 
+type Record = HashMap<String, String>;
+
 impl Model {
-    fn new(name: String, fields: Vec<String>) -> Self {
-        Model { name, fields }
+    fn new(name: String, fields: Vec<String>, store: Arc<dyn KVStore>) -> Self {
+        Model {
+            name,
+            fields,
+            store,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    fn record_key(&self, id: &str) -> Vec<u8> {
+        format!("{}/{}", self.name, id).into_bytes()
+    }
+
+    // Kept outside the `{name}/` prefix so it isn't listed as a record
+    fn next_id_key(&self) -> Vec<u8> {
+        format!("{}#next_id", self.name).into_bytes()
+    }
+
+    fn validate(&self, data: &Record) -> Result<(), BigbotError> {
+        match data.keys().find(|field| !self.fields.contains(field)) {
+            Some(field) => Err(BigbotError::InvalidInput(format!(
+                "Unknown field '{}' for model {}",
+                field, self.name
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    async fn write(&self, id: &str, data: &Record) -> Result<(), BigbotError> {
+        let value =
+            serde_json::to_vec(data).map_err(|e| BigbotError::InvalidInput(e.to_string()))?;
+        self.store.set(self.record_key(id), value).await
+    }
+
+    async fn create(&self, data: Record) -> Result<String, BigbotError> {
+        self.validate(&data)?;
+        let _guard = self.write_lock.lock().await;
+        let next_id = match self.store.get(&self.next_id_key()).await? {
+            Some(raw) => String::from_utf8_lossy(&raw).parse::<u64>().map_err(|e| {
+                BigbotError::DatabaseError(format!("Corrupt id counter for {}: {}", self.name, e))
+            })?,
+            None => 1,
+        };
+        let id = next_id.to_string();
+        self.write(&id, &data).await?;
+        self.store
+            .set(self.next_id_key(), (next_id + 1).to_string().into_bytes())
+            .await?;
+        Ok(id)
+    }
+
+    async fn read(&self, id: &str) -> Result<Option<Record>, BigbotError> {
+        match self.store.get(&self.record_key(id)).await? {
+            Some(raw) => serde_json::from_slice(&raw).map(Some).map_err(|e| {
+                BigbotError::DatabaseError(format!("Corrupt record {}/{}: {}", self.name, id, e))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    // Overwrites the given fields, keeping the others. Returns false if there is no such record.
+    async fn update(&self, id: &str, data: Record) -> Result<bool, BigbotError> {
+        self.validate(&data)?;
+        let _guard = self.write_lock.lock().await;
+        let Some(mut record) = self.read(id).await? else {
+            return Ok(false);
+        };
+        record.extend(data);
+        self.write(id, &record).await?;
+        Ok(true)
+    }
+
+    // Returns false if there was no such record.
+    async fn delete(&self, id: &str) -> Result<bool, BigbotError> {
+        let _guard = self.write_lock.lock().await;
+        if self.read(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.store.delete(&self.record_key(id)).await?;
+        Ok(true)
+    }
+
+    // Every record in id order
+    async fn all(&self) -> Result<Vec<Record>, BigbotError> {
+        let prefix = format!("{}/", self.name);
+        let mut ids: Vec<u64> = self
+            .store
+            .keys(prefix.as_bytes())
+            .await?
+            .iter()
+            .filter_map(|key| String::from_utf8_lossy(&key[prefix.len()..]).parse().ok())
+            .collect();
+        ids.sort_unstable();
+        let mut records = Vec::with_capacity(ids.len());
+        for id in ids {
+            // Skip records deleted since the keys were listed
+            if let Some(record) = self.read(&id.to_string()).await? {
+                records.push(record);
+            }
+        }
+        Ok(records)
     }
 
-    fn create(&self, data: HashMap<String, String>) {
-        // Implement the create operation logic
+    // Records with any value containing `query`, in id order
+    async fn search(&self, query: &str) -> Result<Vec<Record>, BigbotError> {
+        Ok(self
+            .all()
+            .await?
+            .into_iter()
+            .filter(|record| record.values().any(|value| value.contains(query)))
+            .collect())
     }
 
-    fn read(&self, id: &str) -> Option<HashMap<String, String>> {
-        // Implement the read operation logic
-        None
+    async fn bulk_create(&self, data: Vec<Record>) -> Result<Vec<String>, BigbotError> {
+        let mut ids = Vec::with_capacity(data.len());
+        for record in data {
+            ids.push(self.create(record).await?);
+        }
+        Ok(ids)
     }
 
-    fn update(&self, id: &str, data: HashMap<String, String>) {
-        // Implement the update operation logic
+    // Returns how many of the records existed and were updated
+    async fn bulk_update(&self, updates: Vec<(String, Record)>) -> Result<usize, BigbotError> {
+        let mut updated = 0;
+        for (id, data) in updates {
+            if self.update(&id, data).await? {
+                updated += 1;
+            }
+        }
+        Ok(updated)
     }
 
-    fn delete(&self, id: &str) {
-        // Implement the delete operation logic
+    // Returns how many of the records existed and were deleted
+    async fn bulk_delete(&self, ids: Vec<String>) -> Result<usize, BigbotError> {
+        let mut deleted = 0;
+        for id in ids {
+            if self.delete(&id).await? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn count(&self) -> Result<usize, BigbotError> {
+        Ok(self
+            .store
+            .keys(format!("{}/", self.name).as_bytes())
+            .await?
+            .len())
     }
 
-    fn search(&self, query: &str) -> Vec<HashMap<String, String>> {
-        // Implement the search operation logic
-        Vec::new()
+    // Pages are counted from 1; a page past the end is empty
+    async fn paginate(&self, page: usize, per_page: usize) -> Result<Vec<Record>, BigbotError> {
+        let start = page.saturating_sub(1).saturating_mul(per_page);
+        Ok(self
+            .all()
+            .await?
+            .into_iter()
+            .skip(start)
+            .take(per_page)
+            .collect())
     }
 }
 
-fn suggest_chart_types(data_bin: &DataBin) -> Vec<String> {
-    let mut chart_types = Vec::new();
-    let num_fields = data_bin.fields.iter().filter(|&f| is_numeric_field(f)).count();
-    let cat_fields = data_bin.fields.iter().filter(|&f| is_categorical_field(f)).count();
+// Pie and donut charts get hard to read past this many slices
+const MAX_PIE_SLICES: usize = 6;
 
-    if num_fields >= 1 {
-        chart_types.extend_from_slice(&["bar", "line", "area"]);
+// Bar charts get hard to label past this many categories
+const MAX_BAR_CATEGORIES: usize = 20;
+
+// Rows needed before a line, area or scatter chart shows a meaningful trend
+const MIN_TREND_ROWS: usize = 10;
+
+// A chart type that suits a DataBin, with how well it fits (0 to 1) and why
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChartSuggestion {
+    pub chart_type: String,
+    pub score: f32,
+    pub rationale: String,
+}
+
+impl ChartSuggestion {
+    fn new(chart_type: &str, score: f32, rationale: String) -> Self {
+        ChartSuggestion {
+            chart_type: chart_type.to_string(),
+            score,
+            rationale,
+        }
     }
-    if num_fields >= 2 {
-        chart_types.extend_from_slice(&["scatter", "bubble"]);
+}
+
+// Suggests chart types for the data, best first. Scores weigh how many numeric and categorical
+// fields there are, the cardinality of the first categorical field and the row count.
+pub fn suggest_chart_types(data_bin: &DataBin) -> Vec<ChartSuggestion> {
+    let num_fields: Vec<&String> = data_bin
+        .fields
+        .iter()
+        .filter(|&f| is_numeric_field(data_bin, f))
+        .collect();
+    let cat_fields: Vec<&String> = data_bin
+        .fields
+        .iter()
+        .filter(|&f| is_categorical_field(data_bin, f))
+        .collect();
+    let rows = data_bin.data.len();
+    let mut suggestions = Vec::new();
+
+    if let Some(&num) = num_fields.first() {
+        let bar = match cat_fields.first() {
+            Some(&cat) => {
+                let categories = cardinality(data_bin, cat);
+                if categories <= MAX_BAR_CATEGORIES {
+                    (0.85, format!("Compares {} across the {} values of {}", num, categories, cat))
+                } else {
+                    (0.6, format!("{} has {} values, too many to label every bar clearly", cat, categories))
+                }
+            }
+            None => (0.6, format!("Shows each row's {} as a bar", num)),
+        };
+        suggestions.push(ChartSuggestion::new("bar", bar.0, bar.1));
+
+        let (line, trend) = if rows >= MIN_TREND_ROWS {
+            (0.7, format!("{} rows are enough to show how {} trends", rows, num))
+        } else {
+            (0.45, format!("Only {} rows, too few for a meaningful trend in {}", rows, num))
+        };
+        suggestions.push(ChartSuggestion::new("line", line, trend.clone()));
+        suggestions.push(ChartSuggestion::new("area", line - 0.05, format!("{}, shading the area underneath", trend)));
     }
-    if cat_fields >= 1 && num_fields >= 1 {
-        chart_types.extend_from_slice(&["grouped_bar", "stacked_bar"]);
+
+    if let [x, y, rest @ ..] = num_fields.as_slice() {
+        let scatter = if rows >= MIN_TREND_ROWS { 0.8 } else { 0.65 };
+        suggestions.push(ChartSuggestion::new("scatter", scatter, format!("Plots {} against {} to show how they relate", x, y)));
+        let bubble = match rest.first() {
+            Some(size) => (0.6, format!("Plots {} against {}, sized by {}", x, y, size)),
+            None => (0.3, "Only two numeric fields, so bubble size has nothing to encode".to_string()),
+        };
+        suggestions.push(ChartSuggestion::new("bubble", bubble.0, bubble.1));
     }
-    if cat_fields >= 2 && num_fields >= 1 {
-        chart_types.push("heatmap");
+
+    if let (Some(&cat), Some(_)) = (cat_fields.first(), num_fields.first()) {
+        let (grouped, rationale) = if num_fields.len() >= 2 {
+            (0.7, format!("Compares {} numeric fields within each {}", num_fields.len(), cat))
+        } else {
+            (0.4, format!("Only one numeric field, so each {} gets a single bar", cat))
+        };
+        suggestions.push(ChartSuggestion::new("grouped_bar", grouped, rationale.clone()));
+        suggestions.push(ChartSuggestion::new("stacked_bar", grouped - 0.05, rationale));
     }
-    if cat_fields == 1 && num_fields == 1 {
-        chart_types.extend_from_slice(&["pie", "donut"]);
+
+    if let ([first, second, ..], Some(&num)) = (cat_fields.as_slice(), num_fields.first()) {
+        suggestions.push(ChartSuggestion::new("heatmap", 0.7, format!("Shows {} for each pair of {} and {}", num, first, second)));
     }
 
-    chart_types
+    if let ([cat], [num]) = (cat_fields.as_slice(), num_fields.as_slice()) {
+        let categories = cardinality(data_bin, cat);
+        let (pie, rationale) = if categories <= MAX_PIE_SLICES {
+            (0.9, format!("{} has {} categories, few enough to read {} as slices of a whole", cat, categories, num))
+        } else {
+            (0.4, format!("{} has {} categories, too many slices to compare", cat, categories))
+        };
+        suggestions.push(ChartSuggestion::new("pie", pie, rationale.clone()));
+        suggestions.push(ChartSuggestion::new("donut", pie - 0.1, rationale));
+    }
+
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions
+}
+
+// Distinct non-empty values of a field
+fn cardinality(data_bin: &DataBin, field: &str) -> usize {
+    data_bin.count(field).keys().filter(|value| !value.trim().is_empty()).count()
+}
+
+// Rows inspected when classifying a field
+const FIELD_SAMPLE_SIZE: usize = 1000;
+
+// Share of sampled non-empty values that must parse as numbers for a field to count as numeric,
+// so the odd "n/a" or typo doesn't turn a numeric column categorical
+const NUMERIC_FIELD_THRESHOLD: f64 = 0.95;
+
+// The share of the field's sampled non-empty values that parse as numbers, or None if it has none
+fn numeric_share(data_bin: &DataBin, field: &str) -> Option<f64> {
+    let values: Vec<&str> = data_bin
+        .data
+        .iter()
+        .take(FIELD_SAMPLE_SIZE)
+        .filter_map(|row| row.get(field))
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+    let numeric = values
+        .iter()
+        .filter(|value| value.parse::<f64>().is_ok())
+        .count();
+    Some(numeric as f64 / values.len() as f64)
 }
 
-fn is_numeric_field(field: &str) -> bool {
-    // Check if the field contains numeric data
-    // Implement the logic based on your data structure
-    true
+fn is_numeric_field(data_bin: &DataBin, field: &str) -> bool {
+    numeric_share(data_bin, field).is_some_and(|share| share >= NUMERIC_FIELD_THRESHOLD)
 }
 
-fn is_categorical_field(field: &str) -> bool {
-    // Check if the field contains categorical data
-    // Implement the logic based on your data structure
-    true
+// A field with no values at all is neither numeric nor categorical
+fn is_categorical_field(data_bin: &DataBin, field: &str) -> bool {
+    numeric_share(data_bin, field).is_some_and(|share| share < NUMERIC_FIELD_THRESHOLD)
 }
 
 fn prepare_data_for_chart(data_bin: &DataBin, chart_type: &str) -> HashMap<String, Vec<f64>> {
@@ -183,7 +662,7 @@ fn prepare_data_for_chart(data_bin: &DataBin, chart_type: &str) -> HashMap<Strin
         "bar" | "line" | "area" => {
             let mut data = HashMap::new();
             for field in &data_bin.fields {
-                if is_numeric_field(field) {
+                if is_numeric_field(data_bin, field) {
                     let values = data_bin.data.iter().map(|item| {
                         item.get(field).unwrap_or(&String::new()).parse::<f64>().unwrap_or(0.0)
                     }).collect();
@@ -210,12 +689,12 @@ fn prepare_data_for_chart(data_bin: &DataBin, chart_type: &str) -> HashMap<Strin
         }
         "grouped_bar" | "stacked_bar" => {
             let mut data = HashMap::new();
-            if let Some(group_field) = data_bin.fields.iter().find(|&f| is_categorical_field(f)) {
+            if let Some(group_field) = data_bin.fields.iter().find(|&f| is_categorical_field(data_bin, f)) {
                 let groups = data_bin.group_by(group_field);
                 for (group, items) in groups {
                     let mut group_data = HashMap::new();
                     for field in &data_bin.fields {
-                        if is_numeric_field(field) {
+                        if is_numeric_field(data_bin, field) {
                             let values = items.iter().map(|item| {
                                 item.get(field).unwrap_or(&String::new()).parse::<f64>().unwrap_or(0.0)
                             }).collect();
@@ -250,7 +729,7 @@ fn prepare_data_for_chart(data_bin: &DataBin, chart_type: &str) -> HashMap<Strin
         }
         "pie" | "donut" => {
             let mut data = HashMap::new();
-            if let Some(category_field) = data_bin.fields.iter().find(|&f| is_categorical_field(f)) {
+            if let Some(category_field) = data_bin.fields.iter().find(|&f| is_categorical_field(data_bin, f)) {
                 let counts = data_bin.count(category_field);
                 let categories = counts.keys().cloned().collect();
                 let values = counts.values().cloned().map(|v| v as f64).collect();
@@ -264,11 +743,12 @@ fn prepare_data_for_chart(data_bin: &DataBin, chart_type: &str) -> HashMap<Strin
 }
 
 // Dynamic Chart Configuration
+#[derive(Clone)]
 struct ChartConfig {
-    plot_width: u32,
-    plot_height: u32,
-    color_scheme: String,
-    transparency: f32,
+    pub plot_width: u32,
+    pub plot_height: u32,
+    pub palette: Palette,
+    pub transparency: f32,
     // Add more configuration options as needed
 }
 
@@ -277,7 +757,7 @@ impl ChartConfig {
         ChartConfig {
             plot_width: 800,
             plot_height: 600,
-            color_scheme: "Viridis".to_string(),
+            palette: Palette::Viridis,
             transparency: 0.8,
         }
     }
@@ -290,8 +770,15 @@ impl ChartConfig {
         self.plot_height = height;
     }
 
-    fn set_color_scheme(&mut self, scheme: &str) {
-        self.color_scheme = scheme.to_string();
+    fn set_color_scheme(&mut self, scheme: &str) -> Result<(), ChartError> {
+        self.palette = Palette::from_name(scheme)?;
+        Ok(())
+    }
+
+    fn set_palette(&mut self, palette: Palette) -> Result<(), ChartError> {
+        palette.validate()?;
+        self.palette = palette;
+        Ok(())
     }
 
     fn set_transparency(&mut self, transparency: f32) {
@@ -301,6 +788,33 @@ impl ChartConfig {
     // Add more configuration methods as needed
 }
 
+// What the frontend draws: everything it needs to render the chart, with colors already resolved
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChartSpec {
+    #[serde(rename = "type")]
+    pub chart_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub color_scheme: String,
+    // The config's transparency, applied as the fill opacity
+    pub opacity: f32,
+    pub series: Vec<SeriesSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SeriesSpec {
+    pub name: String,
+    pub color: String,
+    pub values: Vec<f64>,
+}
+
+impl ChartSpec {
+    // The spec for `data` drawn with the default chart configuration
+    pub fn with_default_config(chart_type: &str, data: HashMap<String, Vec<f64>>) -> ChartSpec {
+        InteractiveChart::new(chart_type.to_string(), data, ChartConfig::new()).to_spec()
+    }
+}
+
 // Interactive Chart Customization
 struct InteractiveChart {
     chart_type: String,
@@ -326,18 +840,205 @@ impl InteractiveChart {
         self.config = config;
     }
 
-    fn render(&self) {
-        // Implement the chart rendering logic based on the chart type and configuration
-        // You can use a charting library or create your own rendering logic
-        println!("Rendering chart of type: {}", self.chart_type);
-        println!("Data: {:?}", self.data);
-        println!("Configuration: plot_width={}, plot_height={}, color_scheme={}, transparency={}",
-                 self.config.plot_width, self.config.plot_height, self.config.color_scheme, self.config.transparency);
+    // Series are colored in name order so the same data always gets the same colors
+    fn series_colors(&self) -> Vec<(String, String)> {
+        let mut names: Vec<&String> = self.data.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .enumerate()
+            .map(|(index, name)| (name.clone(), self.config.palette.color_for(index)))
+            .collect()
+    }
+
+    fn to_spec(&self) -> ChartSpec {
+        let series = self
+            .series_colors()
+            .into_iter()
+            .map(|(name, color)| SeriesSpec {
+                values: self.data[&name].clone(),
+                name,
+                color,
+            })
+            .collect();
+        ChartSpec {
+            chart_type: self.chart_type.clone(),
+            width: self.config.plot_width,
+            height: self.config.plot_height,
+            color_scheme: self.config.palette.name().to_string(),
+            opacity: self.config.transparency,
+            series,
+        }
+    }
+
+    // The spec as JSON, ready to hand to the frontend
+    fn render(&self) -> String {
+        serde_json::to_string(&self.to_spec()).expect("chart specs always serialize")
     }
 
     // Add more methods for interactive chart customization
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Gt,
+    Lt,
+    Ge,
+    Le,
+    Eq,
+    Ne,
+    Like,
+}
+
+#[derive(Debug, PartialEq)]
+struct Comparison {
+    field: String,
+    op: CompareOp,
+    value: String,
+}
+
+impl Comparison {
+    // Compares numerically when both sides parse as numbers, otherwise as strings. Rows without
+    // the field never match.
+    fn matches(&self, row: &HashMap<String, String>) -> bool {
+        let Some(actual) = row.get(&self.field) else {
+            return false;
+        };
+        if self.op == CompareOp::Like {
+            return actual.contains(self.value.trim_matches('%'));
+        }
+        let ordering = match (actual.trim().parse::<f64>(), self.value.parse::<f64>()) {
+            (Ok(actual), Ok(expected)) => actual.partial_cmp(&expected),
+            _ => Some(actual.as_str().cmp(self.value.as_str())),
+        };
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match self.op {
+            CompareOp::Gt => ordering.is_gt(),
+            CompareOp::Lt => ordering.is_lt(),
+            CompareOp::Ge => ordering.is_ge(),
+            CompareOp::Le => ordering.is_le(),
+            CompareOp::Eq => ordering.is_eq(),
+            CompareOp::Ne => ordering.is_ne(),
+            CompareOp::Like => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum QueryToken {
+    Word(String),
+    Quoted(String),
+    Op(CompareOp),
+    And,
+    Or,
+}
+
+// A row filter: `field OP value` comparisons joined by `AND` and `OR`, with `AND` binding tighter.
+// OP is one of `> < >= <= == != LIKE`; `LIKE` matches a substring, ignoring leading and trailing
+// `%`. Keywords are case-insensitive, and values containing spaces can be quoted with ' or ".
+// Held as the alternatives of an OR, each a list of comparisons that must all hold.
+#[derive(Debug, PartialEq)]
+struct Predicate(Vec<Vec<Comparison>>);
+
+impl Predicate {
+    fn parse(query: &str) -> Result<Predicate, ChartError> {
+        let invalid = |reason: &str| ChartError::InvalidQuery(format!("{} in '{}'", reason, query));
+        let mut tokens = tokenize_query(query)
+            .map_err(|reason| invalid(&reason))?
+            .into_iter();
+        let mut alternatives = vec![];
+        let mut conjunction = vec![];
+        loop {
+            let field = match tokens.next() {
+                Some(QueryToken::Word(field)) => field,
+                _ => return Err(invalid("expected a field name")),
+            };
+            let op = match tokens.next() {
+                Some(QueryToken::Op(op)) => op,
+                _ => return Err(invalid(&format!("expected an operator after '{}'", field))),
+            };
+            let value = match tokens.next() {
+                Some(QueryToken::Word(value)) | Some(QueryToken::Quoted(value)) => value,
+                _ => return Err(invalid(&format!("expected a value after '{}'", field))),
+            };
+            conjunction.push(Comparison { field, op, value });
+            match tokens.next() {
+                None => break,
+                Some(QueryToken::And) => {}
+                Some(QueryToken::Or) => alternatives.push(std::mem::take(&mut conjunction)),
+                Some(_) => return Err(invalid("expected AND or OR between comparisons")),
+            }
+        }
+        alternatives.push(conjunction);
+        Ok(Predicate(alternatives))
+    }
+
+    fn matches(&self, row: &HashMap<String, String>) -> bool {
+        self.0
+            .iter()
+            .any(|conjunction| conjunction.iter().all(|comparison| comparison.matches(row)))
+    }
+}
+
+fn tokenize_query(query: &str) -> Result<Vec<QueryToken>, String> {
+    const OPERATOR_CHARS: &[char] = &['<', '>', '=', '!'];
+    let mut tokens = vec![];
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut quoted = String::new();
+            let mut closed = false;
+            for next in chars.by_ref() {
+                if next == c {
+                    closed = true;
+                    break;
+                }
+                quoted.push(next);
+            }
+            if !closed {
+                return Err("unterminated quote".to_string());
+            }
+            tokens.push(QueryToken::Quoted(quoted));
+        } else if OPERATOR_CHARS.contains(&c) {
+            let mut op = String::new();
+            while let Some(&next) = chars.peek().filter(|next| OPERATOR_CHARS.contains(next)) {
+                op.push(next);
+                chars.next();
+            }
+            tokens.push(QueryToken::Op(match op.as_str() {
+                ">" => CompareOp::Gt,
+                "<" => CompareOp::Lt,
+                ">=" => CompareOp::Ge,
+                "<=" => CompareOp::Le,
+                "==" => CompareOp::Eq,
+                "!=" => CompareOp::Ne,
+                _ => return Err(format!("unknown operator '{}'", op)),
+            }));
+        } else {
+            let mut word = String::new();
+            while let Some(&next) = chars
+                .peek()
+                .filter(|next| !next.is_whitespace() && !OPERATOR_CHARS.contains(next))
+            {
+                word.push(next);
+                chars.next();
+            }
+            tokens.push(match word.to_ascii_uppercase().as_str() {
+                "AND" => QueryToken::And,
+                "OR" => QueryToken::Or,
+                "LIKE" => QueryToken::Op(CompareOp::Like),
+                _ => QueryToken::Word(word),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
 fn main() {
     // Example usage
     let data = vec![
@@ -362,29 +1063,31 @@ fn main() {
 
     // Data Filtering and Querying
     data_bin.filter("category", "A");
-    data_bin.query("value1 > 15");
+    data_bin.query("value1 > 15").unwrap();
 
-    let chart_types = suggest_chart_types(&data_bin);
-    println!("Suggested chart types: {:?}", chart_types);
+    let suggestions = suggest_chart_types(&data_bin);
+    for suggestion in &suggestions {
+        println!("Suggested {} chart ({:.2}): {}", suggestion.chart_type, suggestion.score, suggestion.rationale);
+    }
 
     // Dynamic Chart Configuration
     let mut chart_config = ChartConfig::new();
     chart_config.set_plot_width(1200);
     chart_config.set_plot_height(800);
-    chart_config.set_color_scheme("Plasma");
+    chart_config.set_color_scheme("Plasma").expect("Plasma is a known palette");
     chart_config.set_transparency(0.7);
 
     // Interactive Chart Customization
     let mut interactive_charts = Vec::new();
-    for chart_type in &chart_types {
-        let chart_data = prepare_data_for_chart(&data_bin, chart_type);
-        let interactive_chart = InteractiveChart::new(chart_type.clone(), chart_data, chart_config.clone());
+    for suggestion in &suggestions {
+        let chart_data = prepare_data_for_chart(&data_bin, &suggestion.chart_type);
+        let interactive_chart = InteractiveChart::new(suggestion.chart_type.clone(), chart_data, chart_config.clone());
         interactive_charts.push(interactive_chart);
     }
 
     // Render and customize the interactive charts
     for mut chart in interactive_charts {
-        chart.render();
+        println!("Chart spec: {}", chart.render());
         // Customize the chart based on user interactions or real-time data updates
         // Example:
         // chart.update_data(updated_data);
@@ -393,26 +1096,35 @@ fn main() {
     }
 
     // Pseudo ORM Usage
-    let model = Model::new("MyModel".to_string(), vec!["field1".to_string(), "field2".to_string()]);
+    tokio::runtime::Runtime::new().unwrap().block_on(model_example());
+}
+
+async fn model_example() {
+    let store: Arc<dyn KVStore> = Arc::new(crate::clients::kv::MemoryKVStore::default());
+    let model = Model::new(
+        "MyModel".to_string(),
+        vec!["field1".to_string(), "field2".to_string()],
+        store,
+    );
 
     // Create operation
     let data1 = HashMap::from([
         ("field1".to_string(), "value1".to_string()),
         ("field2".to_string(), "value2".to_string()),
     ]);
-    model.create(data1);
+    let id1 = model.create(data1).await.unwrap();
 
     let data2 = HashMap::from([
         ("field1".to_string(), "value3".to_string()),
         ("field2".to_string(), "value4".to_string()),
     ]);
-    model.create(data2);
+    let id2 = model.create(data2).await.unwrap();
 
     // Read operation
-    let result1 = model.read("1");
+    let result1 = model.read(&id1).await.unwrap();
     println!("Read result 1: {:?}", result1);
 
-    let result2 = model.read("2");
+    let result2 = model.read(&id2).await.unwrap();
     println!("Read result 2: {:?}", result2);
 
     // Update operation
@@ -420,20 +1132,19 @@ fn main() {
         ("field1".to_string(), "updated_value1".to_string()),
         ("field2".to_string(), "updated_value2".to_string()),
     ]);
-    model.update("1", update_data);
+    model.update(&id1, update_data).await.unwrap();
 
-    let updated_result = model.read("1");
+    let updated_result = model.read(&id1).await.unwrap();
     println!("Updated result: {:?}", updated_result);
 
     // Delete operation
-    model.delete("2");
+    model.delete(&id2).await.unwrap();
 
-    let deleted_result = model.read("2");
+    let deleted_result = model.read(&id2).await.unwrap();
     println!("Deleted result: {:?}", deleted_result);
 
     // Search operation
-    let search_query = "field1 LIKE '%value%'";
-    let search_results = model.search(search_query);
+    let search_results = model.search("value").await.unwrap();
     println!("Search results: {:?}", search_results);
 
     // Bulk create operation
@@ -447,33 +1158,429 @@ fn main() {
             ("field2".to_string(), "bulk_value4".to_string()),
         ]),
     ];
-    model.bulk_create(bulk_data);
+    let bulk_ids = model.bulk_create(bulk_data).await.unwrap();
 
     // Bulk update operation
     let bulk_update_data = vec![
-        ("1".to_string(), HashMap::from([
-            ("field1".to_string(), "updated_bulk_value1".to_string()),
-            ("field2".to_string(), "updated_bulk_value2".to_string()),
-        ])),
-        ("3".to_string(), HashMap::from([
-            ("field1".to_string(), "updated_bulk_value3".to_string()),
-            ("field2".to_string(), "updated_bulk_value4".to_string()),
-        ])),
+        (
+            id1.clone(),
+            HashMap::from([
+                ("field1".to_string(), "updated_bulk_value1".to_string()),
+                ("field2".to_string(), "updated_bulk_value2".to_string()),
+            ]),
+        ),
+        (
+            bulk_ids[0].clone(),
+            HashMap::from([
+                ("field1".to_string(), "updated_bulk_value3".to_string()),
+                ("field2".to_string(), "updated_bulk_value4".to_string()),
+            ]),
+        ),
     ];
-    model.bulk_update(bulk_update_data);
+    model.bulk_update(bulk_update_data).await.unwrap();
 
     // Bulk delete operation
-    let delete_ids = vec!["1".to_string(), "3".to_string()];
-    model.bulk_delete(delete_ids);
+    let delete_ids = vec![id1, bulk_ids[0].clone()];
+    model.bulk_delete(delete_ids).await.unwrap();
 
     // Count operation
-    let count = model.count();
+    let count = model.count().await.unwrap();
     println!("Count: {}", count);
 
     // Pagination operation
     let page = 1;
     let per_page = 10;
-    let paginated_results = model.paginate(page, per_page);
+    let paginated_results = model.paginate(page, per_page).await.unwrap();
     println!("Paginated results: {:?}", paginated_results);
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    // Readings at 0s, 30s, 70s and 190s: the 120s-180s minute has no rows
+    fn irregular_series() -> DataBin {
+        let rows = [("0", "1"), ("30", "3"), ("70", "5"), ("1970-01-01T00:03:10Z", "7")];
+        let data = rows
+            .iter()
+            .map(|(time, value)| {
+                HashMap::from([
+                    ("time".to_string(), time.to_string()),
+                    ("value".to_string(), value.to_string()),
+                ])
+            })
+            .collect();
+        DataBin::new(data, vec!["time".to_string(), "value".to_string()])
+    }
+
+    fn resampled_values(bin: &DataBin) -> Vec<Option<f64>> {
+        bin.data
+            .iter()
+            .map(|row| row.get("value").map(|value| value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resample_aggregates_into_fixed_buckets() {
+        let series = irregular_series();
+        let mean = series
+            .resample("time", "value", Duration::from_secs(60), Aggregation::Mean, GapFill::Null)
+            .unwrap();
+        let times: Vec<&str> = mean.data.iter().map(|row| row["time"].as_str()).collect();
+        assert_eq!(times, vec!["0", "60", "120", "180"]);
+        assert_eq!(resampled_values(&mean), vec![Some(2.0), Some(5.0), None, Some(7.0)]);
+
+        let sum = series
+            .resample("time", "value", Duration::from_secs(60), Aggregation::Sum, GapFill::Null)
+            .unwrap();
+        assert_eq!(resampled_values(&sum)[0], Some(4.0));
+        let last = series
+            .resample("time", "value", Duration::from_secs(60), Aggregation::Last, GapFill::Null)
+            .unwrap();
+        assert_eq!(resampled_values(&last)[0], Some(3.0));
+    }
+
+    #[test]
+    fn test_resample_gap_fill_policies() {
+        let series = irregular_series();
+        let fill = |gap_fill| {
+            let bin = series
+                .resample("time", "value", Duration::from_secs(60), Aggregation::Mean, gap_fill)
+                .unwrap();
+            resampled_values(&bin)[2]
+        };
+        assert_eq!(fill(GapFill::Null), None);
+        assert_eq!(fill(GapFill::Zero), Some(0.0));
+        assert_eq!(fill(GapFill::CarryForward), Some(5.0));
+    }
+
+    #[test]
+    fn test_resample_rejects_bad_input() {
+        let series = irregular_series();
+        assert_eq!(
+            series
+                .resample("time", "value", Duration::from_millis(500), Aggregation::Sum, GapFill::Zero)
+                .err(),
+            Some(ChartError::InvalidInterval)
+        );
+        assert!(matches!(
+            series.resample("value2", "value", Duration::from_secs(60), Aggregation::Sum, GapFill::Zero),
+            Err(ChartError::InvalidTimestamp { .. })
+        ));
+    }
+
+    #[test]
+    fn test_custom_palette_colors_series_in_order() {
+        let mut config = ChartConfig::new();
+        config
+            .set_palette(Palette::Custom(vec!["#112233".to_string(), "#AABBCC".to_string()]))
+            .unwrap();
+        let data = HashMap::from([
+            ("cherry".to_string(), vec![3.0]),
+            ("apple".to_string(), vec![1.0]),
+            ("banana".to_string(), vec![2.0]),
+        ]);
+        let chart = InteractiveChart::new("bar".to_string(), data, config);
+
+        assert_eq!(
+            chart.series_colors(),
+            vec![
+                ("apple".to_string(), "#112233".to_string()),
+                ("banana".to_string(), "#AABBCC".to_string()),
+                // More series than colors: the palette cycles
+                ("cherry".to_string(), "#112233".to_string()),
+            ]
+        );
+        assert_eq!(
+            chart.to_spec().series[1],
+            SeriesSpec {
+                name: "banana".to_string(),
+                color: "#AABBCC".to_string(),
+                values: vec![2.0],
+            }
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_palettes() {
+        let mut config = ChartConfig::new();
+        assert_eq!(
+            config.set_color_scheme("Rainbow"),
+            Err(ChartError::UnknownPalette("Rainbow".to_string()))
+        );
+        assert_eq!(
+            config.set_palette(Palette::Custom(vec!["#12345".to_string()])),
+            Err(ChartError::InvalidColor("#12345".to_string()))
+        );
+        assert_eq!(config.set_palette(Palette::Custom(vec![])), Err(ChartError::EmptyPalette));
+        // The config keeps its previous palette
+        assert_eq!(config.palette, Palette::Viridis);
+
+        config.set_color_scheme("okabe-ito").unwrap();
+        assert_eq!(config.palette, Palette::OkabeIto);
+    }
+
+    fn query_bin() -> DataBin {
+        // The "north" row has no value1, so only region comparisons can match it
+        let rows = [
+            ("north-east", Some("9")),
+            ("north-west", Some("15")),
+            ("south", Some("20")),
+            ("north", None),
+        ];
+        let data = rows
+            .iter()
+            .map(|(region, value)| {
+                let mut row = HashMap::from([("region".to_string(), region.to_string())]);
+                if let Some(value) = value {
+                    row.insert("value1".to_string(), value.to_string());
+                }
+                row
+            })
+            .collect();
+        DataBin::new(data, vec!["region".to_string(), "value1".to_string()])
+    }
+
+    fn regions(bin: &DataBin) -> Vec<&str> {
+        bin.data.iter().map(|row| row["region"].as_str()).collect()
+    }
+
+    #[test]
+    fn test_query_compares_numbers_numerically() {
+        let mut bin = query_bin();
+        // "9" > "15" as strings, so this only passes when compared as numbers
+        bin.query("value1 >= 15").unwrap();
+        assert_eq!(regions(&bin), vec!["north-west", "south"]);
+
+        let mut bin = query_bin();
+        bin.query("value1 != 15").unwrap();
+        assert_eq!(regions(&bin), vec!["north-east", "south"]);
+    }
+
+    #[test]
+    fn test_query_like_matches_substrings() {
+        let mut bin = query_bin();
+        bin.query("region like 'north'").unwrap();
+        assert_eq!(regions(&bin), vec!["north-east", "north-west", "north"]);
+    }
+
+    #[test]
+    fn test_query_and_binds_tighter_than_or() {
+        let mut bin = query_bin();
+        bin.query("region LIKE north AND value1 < 12 OR region == south")
+            .unwrap();
+        assert_eq!(regions(&bin), vec!["north-east", "south"]);
+    }
+
+    #[test]
+    fn test_malformed_query_is_rejected_and_leaves_data() {
+        let mut bin = query_bin();
+        for query in [
+            "value1 >",
+            "value1 15",
+            "value1 => 15",
+            "region == 'north",
+            "value1 > 1 AND",
+        ] {
+            assert!(
+                matches!(bin.query(query), Err(ChartError::InvalidQuery(_))),
+                "{}",
+                query
+            );
+        }
+        assert_eq!(bin.data.len(), 4);
+    }
+
+    fn sales_bin(extra_numeric_field: bool) -> DataBin {
+        let rows = [
+            ("A", "10", "1.5"),
+            ("B", "15", "2"),
+            ("C", "n/a", "-3e2"),
+            ("D", "", "4"),
+        ];
+        let mut fields = vec!["category".to_string(), "value1".to_string()];
+        if extra_numeric_field {
+            fields.push("value2".to_string());
+        }
+        let data = rows
+            .iter()
+            .map(|(category, value1, value2)| {
+                let mut row = HashMap::from([
+                    ("category".to_string(), category.to_string()),
+                    ("value1".to_string(), value1.to_string()),
+                ]);
+                if extra_numeric_field {
+                    row.insert("value2".to_string(), value2.to_string());
+                }
+                row
+            })
+            .collect();
+        DataBin::new(data, fields)
+    }
+
+    #[test]
+    fn test_fields_are_classified_from_their_values() {
+        let bin = sales_bin(true);
+        assert!(is_categorical_field(&bin, "category"));
+        assert!(!is_numeric_field(&bin, "category"));
+        assert!(is_numeric_field(&bin, "value2"));
+        assert!(!is_categorical_field(&bin, "value2"));
+        // One "n/a" in three non-empty values is too many to call the field numeric
+        assert!(is_categorical_field(&bin, "value1"));
+        assert!(!is_numeric_field(&bin, "missing") && !is_categorical_field(&bin, "missing"));
+    }
+
+    fn chart_types(suggestions: &[ChartSuggestion]) -> Vec<&str> {
+        suggestions.iter().map(|suggestion| suggestion.chart_type.as_str()).collect()
+    }
+
+    #[test]
+    fn test_pie_suggested_only_for_one_category_and_one_number() {
+        let mut one_of_each = sales_bin(false);
+        one_of_each.query("value1 != 'n/a'").unwrap();
+        let suggestions = suggest_chart_types(&one_of_each);
+        assert!(chart_types(&suggestions).contains(&"pie"));
+        assert!(chart_types(&suggestions).contains(&"donut"));
+
+        let mut two_numbers = sales_bin(true);
+        two_numbers.query("value1 != 'n/a'").unwrap();
+        let suggestions = suggest_chart_types(&two_numbers);
+        assert!(chart_types(&suggestions).contains(&"scatter"));
+        assert!(!chart_types(&suggestions).contains(&"pie"));
+        assert!(!chart_types(&suggestions).contains(&"donut"));
+    }
+
+    #[test]
+    fn test_suggestions_are_ranked_for_one_category_and_one_number() {
+        let mut one_of_each = sales_bin(false);
+        one_of_each.query("value1 != 'n/a'").unwrap();
+        let suggestions = suggest_chart_types(&one_of_each);
+
+        assert_eq!(chart_types(&suggestions)[..2], ["pie", "bar"]);
+        assert!(suggestions.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        let categories = cardinality(&one_of_each, "category");
+        assert_eq!(
+            suggestions[0].rationale,
+            format!("category has {} categories, few enough to read value1 as slices of a whole", categories)
+        );
+        assert_eq!(suggestions[1].rationale, format!("Compares value1 across the {} values of category", categories));
+
+        // Past the slice limit a pie is no longer a good fit
+        let rows = (0..=MAX_PIE_SLICES)
+            .map(|i| HashMap::from([("region".to_string(), format!("r{}", i)), ("total".to_string(), i.to_string())]))
+            .collect();
+        let many_regions = DataBin::new(rows, vec!["region".to_string(), "total".to_string()]);
+        let suggestions = suggest_chart_types(&many_regions);
+        assert_eq!(suggestions[0].chart_type, "bar");
+        let pie = suggestions.iter().find(|suggestion| suggestion.chart_type == "pie").unwrap();
+        assert!(pie.score < 0.5);
+    }
+
+    fn model() -> Model {
+        let store: Arc<dyn KVStore> = Arc::new(crate::clients::kv::MemoryKVStore::default());
+        Model::new(
+            "Sales".to_string(),
+            vec!["region".to_string(), "total".to_string()],
+            store,
+        )
+    }
+
+    fn record(region: &str, total: &str) -> Record {
+        HashMap::from([
+            ("region".to_string(), region.to_string()),
+            ("total".to_string(), total.to_string()),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_model_crud_round_trip() {
+        let model = model();
+        let north = model.create(record("north", "10")).await.unwrap();
+        let south = model.create(record("south", "20")).await.unwrap();
+        assert_eq!((north.as_str(), south.as_str()), ("1", "2"));
+        assert_eq!(
+            model.read(&north).await.unwrap(),
+            Some(record("north", "10"))
+        );
+
+        let partial = HashMap::from([("total".to_string(), "15".to_string())]);
+        assert!(model.update(&north, partial).await.unwrap());
+        assert_eq!(
+            model.read(&north).await.unwrap(),
+            Some(record("north", "15"))
+        );
+        assert!(!model.update("99", record("west", "1")).await.unwrap());
+
+        assert!(model.delete(&south).await.unwrap());
+        assert!(!model.delete(&south).await.unwrap());
+        assert_eq!(model.read(&south).await.unwrap(), None);
+        // Ids aren't reused after a delete
+        assert_eq!(model.create(record("east", "5")).await.unwrap(), "3");
+
+        let unknown = HashMap::from([("colour".to_string(), "red".to_string())]);
+        assert!(matches!(
+            model.create(unknown).await,
+            Err(BigbotError::InvalidInput(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_model_bulk_search_and_paginate() {
+        let model = model();
+        let regions = ["north", "north-east", "south", "west", "north-west"];
+        let ids = model
+            .bulk_create(regions.iter().map(|region| record(region, "1")).collect())
+            .await
+            .unwrap();
+        assert_eq!(ids, vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(model.count().await.unwrap(), 5);
+
+        let found = model.search("north").await.unwrap();
+        let found: Vec<&str> = found
+            .iter()
+            .map(|record| record["region"].as_str())
+            .collect();
+        assert_eq!(found, vec!["north", "north-east", "north-west"]);
+
+        let updates = vec![
+            ("2".to_string(), record("east", "2")),
+            ("9".to_string(), record("x", "0")),
+        ];
+        assert_eq!(model.bulk_update(updates).await.unwrap(), 1);
+        assert_eq!(
+            model
+                .bulk_delete(vec!["1".to_string(), "9".to_string()])
+                .await
+                .unwrap(),
+            1
+        );
+
+        let second_page = model.paginate(2, 2).await.unwrap();
+        let second_page: Vec<&str> = second_page
+            .iter()
+            .map(|record| record["region"].as_str())
+            .collect();
+        assert_eq!(second_page, vec!["west", "north-west"]);
+        assert!(model.paginate(3, 2).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_render_emits_json_spec() {
+        let mut config = ChartConfig::new();
+        config.set_plot_width(1200);
+        config.set_plot_height(400);
+        let data = HashMap::from([("sales".to_string(), vec![1.0, 2.5])]);
+        let chart = InteractiveChart::new("line".to_string(), data, config);
+
+        let spec: Value = serde_json::from_str(&chart.render()).unwrap();
+        assert_eq!(spec["type"], "line");
+        assert_eq!(spec["width"], 1200);
+        assert_eq!(spec["height"], 400);
+        assert_eq!(spec["color_scheme"], "viridis");
+        assert_eq!(
+            spec["series"][0],
+            json!({ "name": "sales", "color": "#440154", "values": [1.0, 2.5] })
+        );
+    }
+}
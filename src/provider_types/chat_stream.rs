@@ -0,0 +1,207 @@
+//! # Streaming Chat Replies
+//!
+//! Turns a provider's streamed generation into `Message` updates for the messaging pipeline.
+//!
+//! Every update is the reply message so far: all updates share the reply's id and carry the
+//! accumulated content, so a UI can simply replace what it shows. The last update is marked with
+//! `is_final` in the message metadata and is also handed to the archive sink (a `ChannelStore` in
+//! production) so only the complete reply is persisted. If the provider fails mid-stream the final
+//! update keeps the partial content and records the failure under `stream_error`.
+
+use crate::data_streams::{Error, Sink};
+use crate::messaging::message::Message;
+use crate::messaging::message_metadata::MetadataValue;
+use crate::provider_types::ai::{AIProviderTrait, GenerationRequest, GenerationStream};
+
+use chrono::Utc;
+use futures::StreamExt;
+use std::sync::Arc;
+
+pub const IS_FINAL_KEY: &str = "is_final";
+pub const STREAM_ERROR_KEY: &str = "stream_error";
+
+// Whether a streamed reply update is the last one
+pub fn is_final(message: &Message) -> bool {
+    matches!(message.metadata.get(IS_FINAL_KEY), Some(MetadataValue::Bool(true)))
+}
+
+pub struct StreamingChatAdapter {
+    updates: Arc<dyn Sink<Message, Error> + Send + Sync>,
+    archive: Arc<dyn Sink<Message, Error> + Send + Sync>,
+}
+
+impl StreamingChatAdapter {
+    pub fn new(
+        updates: Arc<dyn Sink<Message, Error> + Send + Sync>,
+        archive: Arc<dyn Sink<Message, Error> + Send + Sync>,
+    ) -> Self {
+        Self { updates, archive }
+    }
+
+    // Streams a generation from `provider` as updates to `reply`, whose id, channel and
+    // participants are kept; its content is replaced by the generated text
+    pub async fn relay(
+        &self,
+        provider: &(dyn AIProviderTrait + Send + Sync),
+        request: GenerationRequest,
+        reply: Message,
+    ) -> Result<Message, Error> {
+        let chunks = provider.stream_generation(request).await;
+        self.relay_stream(chunks, reply).await
+    }
+
+    // Emits an update per chunk and returns the final message once it has been archived
+    pub async fn relay_stream(&self, mut chunks: GenerationStream, reply: Message) -> Result<Message, Error> {
+        let mut content = String::new();
+        let mut stream_error = None;
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Ok(text) => {
+                    content.push_str(&text);
+                    self.updates.consume(update(&reply, &content, false)).await?;
+                }
+                Err(e) => {
+                    stream_error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+
+        let mut final_message = update(&reply, &content, true);
+        if let Some(e) = stream_error {
+            final_message
+                .metadata
+                .insert(STREAM_ERROR_KEY.to_string(), MetadataValue::String(e));
+        }
+        self.updates.consume(final_message.clone()).await?;
+        self.archive.consume(final_message.clone()).await?;
+        Ok(final_message)
+    }
+}
+
+fn update(reply: &Message, content: &str, is_final: bool) -> Message {
+    let mut message = reply.clone();
+    message.content = content.to_string();
+    message.text = content.to_string();
+    message.edited_at = Some(Utc::now());
+    message
+        .metadata
+        .insert(IS_FINAL_KEY.to_string(), MetadataValue::Bool(is_final));
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_streams::mock::MockBroker;
+    use crate::provider_types::ai::{GenerationResponse, InferenceRequest, InferenceResponse, ProviderInfo};
+    use crate::utils::bigboterror::BigbotError;
+    use uuid::Uuid;
+
+    // Streams its chunks, then fails if `fail_after` is set
+    struct MockProvider {
+        chunks: Vec<&'static str>,
+        fail_after: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl AIProviderTrait for MockProvider {
+        // Echoes the request message back
+        async fn run_inference(&self, request: InferenceRequest) -> Result<InferenceResponse, reqwest::Error> {
+            Ok(InferenceResponse {
+                message: request.message,
+                confidence: None,
+                model_used: None,
+            })
+        }
+
+        // Answers with every chunk joined together
+        async fn run_generation(&self, request: GenerationRequest) -> Result<GenerationResponse, reqwest::Error> {
+            Ok(GenerationResponse {
+                message: Message {
+                    content: self.chunks.concat(),
+                    ..request.message
+                },
+                model_used: None,
+            })
+        }
+
+        async fn get_provider_info(&self) -> Result<ProviderInfo, reqwest::Error> {
+            Ok(ProviderInfo {
+                name: "mock".to_string(),
+                description: "Streams fixed chunks".to_string(),
+                capabilities: vec!["streaming".to_string()],
+            })
+        }
+
+        async fn stream_generation(&self, _request: GenerationRequest) -> GenerationStream {
+            let mut chunks: Vec<Result<String, BigbotError>> =
+                self.chunks.iter().map(|chunk| Ok(chunk.to_string())).collect();
+            if self.fail_after {
+                chunks.push(Err(BigbotError::SystemError("connection reset".to_string())));
+            }
+            futures::stream::iter(chunks).boxed()
+        }
+    }
+
+    fn reply() -> Message {
+        Message {
+            channel_id: Uuid::new_v4(),
+            ..Message::for_test("assistant", "user", "")
+        }
+    }
+
+    fn request(message: Message) -> GenerationRequest {
+        GenerationRequest {
+            message,
+            max_length: None,
+            temperature: None,
+            n_best: None,
+        }
+    }
+
+    fn adapter(broker: &MockBroker<Message>) -> StreamingChatAdapter {
+        StreamingChatAdapter::new(Arc::new(broker.sink("updates")), Arc::new(broker.sink("archive")))
+    }
+
+    #[tokio::test]
+    async fn test_streams_three_chunks_then_finalizes() {
+        let broker = MockBroker::new();
+        let provider = MockProvider { chunks: vec!["Hel", "lo, ", "world"], fail_after: false };
+        let reply = reply();
+
+        let final_message = adapter(&broker).relay(&provider, request(reply.clone()), reply.clone()).await.unwrap();
+
+        let updates = broker.published("updates");
+        let contents: Vec<&str> = updates.iter().map(|message| message.content.as_str()).collect();
+        assert_eq!(contents, vec!["Hel", "Hello, ", "Hello, world", "Hello, world"]);
+        let finals: Vec<bool> = updates.iter().map(is_final).collect();
+        assert_eq!(finals, vec![false, false, false, true]);
+        assert!(updates.iter().all(|message| message.id == reply.id));
+
+        // Only the complete reply is persisted
+        let archived = broker.published("archive");
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].content, "Hello, world");
+        assert!(is_final(&final_message));
+        assert!(final_message.metadata.get(STREAM_ERROR_KEY).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mid_stream_error_emits_final_error_message() {
+        let broker = MockBroker::new();
+        let provider = MockProvider { chunks: vec!["Partial"], fail_after: true };
+        let reply = reply();
+
+        let final_message = adapter(&broker).relay(&provider, request(reply.clone()), reply).await.unwrap();
+
+        assert_eq!(final_message.content, "Partial");
+        assert!(is_final(&final_message));
+        assert!(matches!(
+            final_message.metadata.get(STREAM_ERROR_KEY),
+            Some(MetadataValue::String(e)) if e.contains("connection reset")
+        ));
+        assert_eq!(broker.published("updates").len(), 2);
+        assert_eq!(broker.published("archive").len(), 1);
+    }
+}
@@ -1,10 +1,21 @@
 use std::collections::HashMap;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::env;
 
-use crate::provider_types::ai::{AIProviderManager, GenerationRequest, GenerationResponse, InferenceRequest, InferenceResponse};
+use crate::config::config::{AnthropicConfig, DEFAULT_ANTHROPIC_MAX_TOKENS, DEFAULT_ANTHROPIC_MODEL};
+use crate::flows::flowgorithm::generate_block_json;
+use crate::provider_types::ai::{
+    AIProviderManager, ChatCompletion, ChatDelta, ChatMessage, ChatProvider, ChatRole, ChatStream, CompletionOpts,
+    FinishReason, GenerationRequest, GenerationResponse, InferenceRequest, InferenceResponse, TokenUsage,
+};
 use crate::messaging::message::Message;
+use crate::providers::client::ProviderClient;
+use crate::providers::sse::{chat_deltas, sse_events, SseEvent};
+use crate::utils::bigboterror::BigbotError;
+
+const ANTHROPIC_COMPLETE_URL: &str = "https://api.anthropic.com/v1/complete";
+const ANTHROPIC_MESSAGES_URL: &str = "https://api.anthropic.com/v1/messages";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
 
 #[derive(Serialize, Deserialize)]
 struct AnthropicGenerationRequest {
@@ -19,38 +30,119 @@ struct AnthropicGenerationResponse {
     output: String,
 }
 
-struct AnthropicProvider {
+#[derive(Serialize)]
+struct AnthropicMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct AnthropicMessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct AnthropicMessagesResponse {
+    content: Vec<AnthropicContentBlock>,
+    stop_reason: Option<String>,
+    #[serde(default)]
+    usage: AnthropicUsage,
+}
+
+#[derive(Deserialize)]
+struct AnthropicContentBlock {
+    #[serde(rename = "type")]
+    block_type: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize, Default)]
+struct AnthropicUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+pub struct AnthropicProvider {
     api_key: String,
-    client: Client,
+    model: String,
+    max_tokens: u32,
+    client: ProviderClient,
 }
 
 impl AnthropicProvider {
     fn new(api_key: &str) -> Self {
+        Self::with_client(api_key, ProviderClient::new())
+    }
+
+    fn with_client(api_key: &str, client: ProviderClient) -> Self {
         Self {
             api_key: api_key.to_string(),
-            client: Client::new(),
+            model: DEFAULT_ANTHROPIC_MODEL.to_string(),
+            max_tokens: DEFAULT_ANTHROPIC_MAX_TOKENS,
+            client,
         }
     }
 
-    async fn run_generation(&self, request: GenerationRequest) -> Result<GenerationResponse, reqwest::Error> {
+    pub fn from_config(config: &AnthropicConfig) -> Self {
+        Self::with_config(config, ProviderClient::new())
+    }
+
+    pub fn with_config(config: &AnthropicConfig, client: ProviderClient) -> Self {
+        Self {
+            api_key: config.api_key.clone(),
+            model: config.model.clone(),
+            max_tokens: config.max_tokens,
+            client,
+        }
+    }
+
+    // Asks the model for a flow block matching `description` and returns it as a JSON object
+    // string. A reply that isn't a block object is retried once with a follow-up asking for JSON only.
+    pub async fn generate_block(&self, description: &str) -> Result<String, BigbotError> {
+        generate_block_json(self, description).await
+    }
+
+    async fn complete_prompt(&self, request: AnthropicGenerationRequest) -> Result<String, BigbotError> {
+        let response = self
+            .client
+            .send(
+                self.client
+                    .post(ANTHROPIC_COMPLETE_URL)
+                    .bearer_auth(&self.api_key)
+                    .json(&request),
+            )
+            .await?;
+        if !response.is_success() {
+            return Err(BigbotError::SystemError(format!(
+                "Anthropic API returned {}: {}",
+                response.status,
+                response.text()
+            )));
+        }
+        Ok(response.json::<AnthropicGenerationResponse>()?.output)
+    }
+
+    async fn run_generation(&self, request: GenerationRequest) -> Result<GenerationResponse, BigbotError> {
         let anthropic_request = AnthropicGenerationRequest {
-            prompt: request.message.content,
+            prompt: request.message.content.clone(),
             max_tokens_to_sample: request.max_length,
             temperature: request.temperature,
             top_k: request.n_best,
         };
 
-        let response = self.client
-            .post("https://api.anthropic.com/v1/complete")
-            .bearer_auth(&self.api_key)
-            .json(&anthropic_request)
-            .send()
-            .await?
-            .json::<AnthropicGenerationResponse>()
-            .await?;
+        let output = self.complete_prompt(anthropic_request).await?;
 
         let mut message = request.message;
-        message.content = response.output;
+        message.content = output;
 
         Ok(GenerationResponse {
             message,
@@ -59,6 +151,110 @@ impl AnthropicProvider {
     }
 }
 
+// Text from `content_block_delta` events; `message_stop` ends the reply and `error` events fail it
+fn stream_delta(event: &SseEvent) -> Result<Option<ChatDelta>, BigbotError> {
+    let data: serde_json::Value = serde_json::from_str(&event.data)
+        .map_err(|e| BigbotError::UnexpectedError(format!("Failed to parse Anthropic stream event: {}", e)))?;
+    match data["type"].as_str() {
+        Some("content_block_delta") => Ok(data["delta"]["text"].as_str().map(|text| ChatDelta {
+            text: text.to_string(),
+            done: false,
+        })),
+        Some("message_stop") => Ok(Some(ChatDelta {
+            text: String::new(),
+            done: true,
+        })),
+        Some("error") => Err(BigbotError::SystemError(format!(
+            "Anthropic stream error: {}",
+            data["error"]["message"].as_str().unwrap_or("unknown error")
+        ))),
+        _ => Ok(None),
+    }
+}
+
+impl AnthropicProvider {
+    // The Messages API takes system prompts as a separate field, so they are joined and lifted out
+    fn messages_request<'a>(&'a self, messages: &'a [ChatMessage], opts: &'a CompletionOpts, stream: bool) -> AnthropicMessagesRequest<'a> {
+        let system = messages
+            .iter()
+            .filter(|message| message.role == ChatRole::System)
+            .map(|message| message.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        AnthropicMessagesRequest {
+            model: opts.model.as_deref().unwrap_or(&self.model),
+            max_tokens: opts.max_tokens.unwrap_or(self.max_tokens),
+            system: (!system.is_empty()).then_some(system),
+            messages: messages
+                .iter()
+                .filter_map(|message| match message.role {
+                    ChatRole::System => None,
+                    ChatRole::User => Some(AnthropicMessage { role: "user", content: &message.content }),
+                    ChatRole::Assistant => Some(AnthropicMessage { role: "assistant", content: &message.content }),
+                })
+                .collect(),
+            temperature: opts.temperature,
+            stream,
+        }
+    }
+
+    fn messages_post(&self, request: &AnthropicMessagesRequest) -> reqwest::RequestBuilder {
+        self.client
+            .post(ANTHROPIC_MESSAGES_URL)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(request)
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatProvider for AnthropicProvider {
+    async fn complete(&self, messages: Vec<ChatMessage>, opts: CompletionOpts) -> Result<ChatCompletion, BigbotError> {
+        let request = self.messages_request(&messages, &opts, false);
+        let response = self.client.send(self.messages_post(&request)).await?;
+        if !response.is_success() {
+            return Err(BigbotError::SystemError(format!(
+                "Anthropic API returned {}: {}",
+                response.status,
+                response.text()
+            )));
+        }
+        let response = response.json::<AnthropicMessagesResponse>()?;
+        let finish_reason = match response.stop_reason.as_deref() {
+            Some("end_turn") | Some("stop_sequence") => FinishReason::Stop,
+            Some("max_tokens") => FinishReason::Length,
+            reason => FinishReason::Other(reason.unwrap_or_default().to_string()),
+        };
+        Ok(ChatCompletion {
+            text: response
+                .content
+                .into_iter()
+                .filter(|block| block.block_type == "text")
+                .map(|block| block.text)
+                .collect(),
+            usage: TokenUsage {
+                input_tokens: response.usage.input_tokens,
+                output_tokens: response.usage.output_tokens,
+            },
+            finish_reason,
+        })
+    }
+
+    async fn complete_stream(&self, messages: Vec<ChatMessage>, opts: CompletionOpts) -> Result<ChatStream, BigbotError> {
+        let request = self.messages_request(&messages, &opts, true);
+        let response = self.client.send_stream(self.messages_post(&request)).await?;
+        if !response.is_success() {
+            let status = response.status;
+            return Err(BigbotError::SystemError(format!(
+                "Anthropic API returned {}: {}",
+                status,
+                response.text().await?
+            )));
+        }
+        Ok(chat_deltas(sse_events(response.body), stream_delta))
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // Load the Anthropic API key from an environment variable
@@ -113,4 +309,140 @@ async fn main() {
             eprintln!("Error: {}", error);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{RecordMode, Recorder};
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_complete_prompt_replays_recorded_fixture() {
+        let recorder = Recorder::with_mode(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/providers/anthropic_complete.json"),
+            RecordMode::Replay,
+        )
+        .unwrap();
+        let provider = AnthropicProvider::with_client("test-key", ProviderClient::with_recorder(Arc::new(recorder)));
+
+        let output = provider
+            .complete_prompt(AnthropicGenerationRequest {
+                prompt: "Once upon a time".to_string(),
+                max_tokens_to_sample: Some(16),
+                temperature: None,
+                top_k: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(output, " there was a small robot who loved to read.");
+
+        // Each recorded interaction is replayed once
+        let err = provider
+            .complete_prompt(AnthropicGenerationRequest {
+                prompt: "Once upon a time".to_string(),
+                max_tokens_to_sample: Some(16),
+                temperature: None,
+                top_k: None,
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No recorded interaction"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_maps_into_common_type() {
+        let recorder = Recorder::with_mode(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/providers/anthropic_messages.json"),
+            RecordMode::Replay,
+        )
+        .unwrap();
+        let config = AnthropicConfig {
+            api_key: "test-key".to_string(),
+            model: "claude-3-haiku-20240307".to_string(),
+            max_tokens: 512,
+        };
+        let provider = AnthropicProvider::with_config(&config, ProviderClient::with_recorder(Arc::new(recorder)));
+
+        let completion = provider
+            .complete(
+                vec![
+                    ChatMessage::system("You are terse."),
+                    ChatMessage::user("Name a primary colour."),
+                ],
+                CompletionOpts {
+                    max_tokens: Some(16),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            completion,
+            ChatCompletion {
+                text: "Red".to_string(),
+                usage: TokenUsage {
+                    input_tokens: 17,
+                    output_tokens: 2,
+                },
+                finish_reason: FinishReason::Stop,
+            }
+        );
+    }
+
+    fn generate_block_provider() -> AnthropicProvider {
+        let recorder = Recorder::with_mode(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/providers/anthropic_generate_block.json"),
+            RecordMode::Replay,
+        )
+        .unwrap();
+        let config = AnthropicConfig {
+            api_key: "test-key".to_string(),
+            model: "claude-3-haiku-20240307".to_string(),
+            max_tokens: 512,
+        };
+        AnthropicProvider::with_config(&config, ProviderClient::with_recorder(Arc::new(recorder)))
+    }
+
+    #[tokio::test]
+    async fn test_generate_block_returns_valid_json() {
+        let block = generate_block_provider().generate_block("Ask the user for their email address").await.unwrap();
+        assert_eq!(block, r#"{"type":"InputBlock","properties":{"key":"email","prompt":"What is your email address?"}}"#);
+    }
+
+    #[tokio::test]
+    async fn test_generate_block_retries_invalid_json_once() {
+        let block = generate_block_provider().generate_block("Show a goodbye message").await.unwrap();
+        assert_eq!(block, r#"{"type":"DisplayBlock","properties":{"text":"Goodbye!"}}"#);
+
+        // The fixture answers the retry for this description with a block missing its type
+        let err = generate_block_provider().generate_block("Pick a random greeting").await.unwrap_err();
+        assert!(matches!(err, BigbotError::UnexpectedError(ref msg) if msg.contains("invalid block")));
+    }
+
+    #[tokio::test]
+    async fn test_stream_deltas_concatenate_to_message() {
+        let events = crate::providers::sse::canned_events(vec![
+            b"event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_01\",\"content\":[]}}\n\n",
+            b"event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\nevent: ping\ndata: {\"type\":\"ping\"}\n\n",
+            b"event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\nevent: content_bl",
+            b"ock_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\", world\"}}\n\n",
+            b"event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\nevent: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        ]);
+        let deltas: Vec<ChatDelta> = chat_deltas(events, stream_delta).map(|delta| delta.unwrap()).collect().await;
+        assert!(deltas.last().unwrap().done);
+        assert_eq!(deltas.iter().map(|delta| delta.text.as_str()).collect::<String>(), "Hello, world");
+    }
+
+    #[tokio::test]
+    async fn test_stream_error_event_is_an_error_item() {
+        let events = crate::providers::sse::canned_events(vec![
+            b"event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hel\"}}\n\n",
+            b"event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+        ]);
+        let deltas: Vec<Result<ChatDelta, BigbotError>> = chat_deltas(events, stream_delta).collect().await;
+        assert_eq!(deltas.len(), 2);
+        assert!(matches!(deltas[1], Err(BigbotError::SystemError(ref msg)) if msg.contains("Overloaded")));
+    }
+}
@@ -0,0 +1,142 @@
+//! # Provider HTTP Client
+//!
+//! Shared HTTP client used by the provider integrations (Anthropic, OpenAI, Telegram, Wikipedia).
+//!
+//! Requests are sent through `ProviderClient::send`, which either performs them directly with
+//! `reqwest` or, when a `Recorder` is attached, records/replays them from a fixture file so
+//! provider tests can run offline and deterministically (see `providers::testing`).
+//!
+//! `ProviderClient::send_stream` hands back the body as it arrives, for server-sent event endpoints.
+//! Recorded interactions replay their whole body as a single chunk.
+
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use reqwest::{Client, RequestBuilder};
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+
+use crate::providers::testing::Recorder;
+use crate::utils::bigboterror::BigbotError;
+
+#[derive(Clone, Default)]
+pub struct ProviderClient {
+    client: Client,
+    recorder: Option<Arc<Recorder>>,
+}
+
+/// A fully buffered provider response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+pub type ByteStream = BoxStream<'static, Result<Bytes, BigbotError>>;
+
+/// A provider response whose body is read incrementally. Dropping it closes the connection.
+pub struct ProviderStream {
+    pub status: u16,
+    pub body: ByteStream,
+}
+
+impl ProviderStream {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Reads the rest of the body, e.g. to report an error response.
+    pub async fn text(self) -> Result<String, BigbotError> {
+        let body = self.body.try_concat().await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+impl ProviderResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn text(&self) -> &str {
+        &self.body
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, BigbotError> {
+        serde_json::from_str(&self.body).map_err(|e| {
+            BigbotError::UnexpectedError(format!("Failed to parse provider response: {}", e))
+        })
+    }
+}
+
+impl ProviderClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Routes every request through the recorder instead of hitting the network directly.
+    pub fn with_recorder(recorder: Arc<Recorder>) -> Self {
+        Self {
+            client: Client::new(),
+            recorder: Some(recorder),
+        }
+    }
+
+    pub fn inner(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub async fn send(&self, request: RequestBuilder) -> Result<ProviderResponse, BigbotError> {
+        let request = request
+            .build()
+            .map_err(|e| BigbotError::InvalidInput(format!("Invalid provider request: {}", e.without_url())))?;
+        match &self.recorder {
+            Some(recorder) => recorder.send(&self.client, request).await,
+            None => execute(&self.client, request).await,
+        }
+    }
+
+    /// Sends a request whose response body is consumed as it arrives.
+    pub async fn send_stream(&self, request: RequestBuilder) -> Result<ProviderStream, BigbotError> {
+        if self.recorder.is_some() {
+            let response = self.send(request).await?;
+            let body = Bytes::from(response.body);
+            return Ok(ProviderStream {
+                status: response.status,
+                body: stream::once(async move { Ok(body) }).boxed(),
+            });
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| BigbotError::SystemError(format!("Provider request failed: {}", e.without_url())))?;
+        Ok(ProviderStream {
+            status: response.status().as_u16(),
+            body: response
+                .bytes_stream()
+                .map_err(|e| BigbotError::SystemError(format!("Failed to read provider response: {}", e.without_url())))
+                .boxed(),
+        })
+    }
+}
+
+/// Performs the request against the live API and buffers the response body.
+// Request errors are reported without their URL, since some providers put credentials in it
+pub(crate) async fn execute(client: &Client, request: reqwest::Request) -> Result<ProviderResponse, BigbotError> {
+    let response = client
+        .execute(request)
+        .await
+        .map_err(|e| BigbotError::SystemError(format!("Provider request failed: {}", e.without_url())))?;
+    let status = response.status().as_u16();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| BigbotError::SystemError(format!("Failed to read provider response: {}", e.without_url())))?;
+    Ok(ProviderResponse { status, body })
+}
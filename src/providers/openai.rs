@@ -1,14 +1,22 @@
 use crate::provider_types::ai::{AIProviderManager, GenerationRequest, GenerationResponse, InferenceRequest, InferenceResponse};
 use crate::messaging::message::Message;
-use crate::provider_types::ai::{AIProviderTrait, ProviderInfo};
+use crate::provider_types::ai::{
+    AIProviderTrait, ChatCompletion, ChatDelta, ChatMessage, ChatProvider, ChatStream, CompletionOpts, FinishReason, ProviderInfo,
+    TokenUsage,
+};
+use crate::providers::client::ProviderClient;
+use crate::providers::sse::{chat_deltas, sse_events, SseEvent};
+use crate::utils::bigboterror::BigbotError;
 
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::collections::HashMap;
 use async_trait::async_trait;
 use uuid::Uuid;
 
+const OPENAI_CHAT_COMPLETIONS_URL: &str = "https://api.openai.com/v1/chat/completions";
+pub const DEFAULT_OPENAI_MODEL: &str = "gpt-3.5-turbo";
+
 
 #[derive(Serialize, Deserialize)]
 struct OpenAIGenerationRequest {
@@ -29,19 +37,152 @@ struct OpenAIGenerationChoice {
     text: String,
 }
 
-struct OpenAIProvider {
+// Chat roles serialize as OpenAI expects, so messages are sent as they are
+#[derive(Serialize)]
+struct OpenAIChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct OpenAIChatResponse {
+    choices: Vec<OpenAIChatChoice>,
+    #[serde(default)]
+    usage: OpenAIUsage,
+}
+
+#[derive(Deserialize)]
+struct OpenAIChatChoice {
+    message: OpenAIChatChoiceMessage,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAIChatChoiceMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct OpenAIUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+pub struct OpenAIProvider {
     api_key: String,
-    client: Client,
+    model: String,
+    client: ProviderClient,
 }
 
 
 impl OpenAIProvider {
-    fn new(api_key: &str) -> Self {
+    pub fn new(api_key: &str) -> Self {
+        Self::with_client(api_key, ProviderClient::new())
+    }
+
+    pub fn with_client(api_key: &str, client: ProviderClient) -> Self {
         Self {
             api_key: api_key.to_string(),
-            client: Client::new(),
+            model: DEFAULT_OPENAI_MODEL.to_string(),
+            client,
         }
     }
+
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    fn chat_post(&self, messages: &[ChatMessage], opts: &CompletionOpts, stream: bool) -> reqwest::RequestBuilder {
+        let request = OpenAIChatRequest {
+            model: opts.model.as_deref().unwrap_or(&self.model),
+            messages,
+            max_tokens: opts.max_tokens,
+            temperature: opts.temperature,
+            stream,
+        };
+        self.client
+            .post(OPENAI_CHAT_COMPLETIONS_URL)
+            .bearer_auth(&self.api_key)
+            .json(&request)
+    }
+}
+
+// Text from each chunk's first choice; `[DONE]` ends the reply and `error` payloads fail it
+fn stream_delta(event: &SseEvent) -> Result<Option<ChatDelta>, BigbotError> {
+    if event.data == "[DONE]" {
+        return Ok(Some(ChatDelta {
+            text: String::new(),
+            done: true,
+        }));
+    }
+    let data: serde_json::Value = serde_json::from_str(&event.data)
+        .map_err(|e| BigbotError::UnexpectedError(format!("Failed to parse OpenAI stream chunk: {}", e)))?;
+    if let Some(error) = data.get("error") {
+        return Err(BigbotError::SystemError(format!(
+            "OpenAI stream error: {}",
+            error["message"].as_str().unwrap_or("unknown error")
+        )));
+    }
+    Ok(data["choices"][0]["delta"]["content"]
+        .as_str()
+        .filter(|text| !text.is_empty())
+        .map(|text| ChatDelta {
+            text: text.to_string(),
+            done: false,
+        }))
+}
+
+#[async_trait]
+impl ChatProvider for OpenAIProvider {
+    async fn complete(&self, messages: Vec<ChatMessage>, opts: CompletionOpts) -> Result<ChatCompletion, BigbotError> {
+        let response = self.client.send(self.chat_post(&messages, &opts, false)).await?;
+        if !response.is_success() {
+            return Err(BigbotError::SystemError(format!(
+                "OpenAI API returned {}: {}",
+                response.status,
+                response.text()
+            )));
+        }
+        let response = response.json::<OpenAIChatResponse>()?;
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| BigbotError::UnexpectedError("OpenAI returned no choices".to_string()))?;
+        let finish_reason = match choice.finish_reason.as_deref() {
+            Some("stop") => FinishReason::Stop,
+            Some("length") => FinishReason::Length,
+            reason => FinishReason::Other(reason.unwrap_or_default().to_string()),
+        };
+        Ok(ChatCompletion {
+            text: choice.message.content.unwrap_or_default(),
+            usage: TokenUsage {
+                input_tokens: response.usage.prompt_tokens,
+                output_tokens: response.usage.completion_tokens,
+            },
+            finish_reason,
+        })
+    }
+
+    async fn complete_stream(&self, messages: Vec<ChatMessage>, opts: CompletionOpts) -> Result<ChatStream, BigbotError> {
+        let response = self.client.send_stream(self.chat_post(&messages, &opts, true)).await?;
+        if !response.is_success() {
+            let status = response.status;
+            return Err(BigbotError::SystemError(format!(
+                "OpenAI API returned {}: {}",
+                status,
+                response.text().await?
+            )));
+        }
+        Ok(chat_deltas(sse_events(response.body), stream_delta))
+    }
 }
 
 #[async_trait]
@@ -143,4 +284,60 @@ async fn main() {
             eprintln!("Error: {}", error);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{RecordMode, Recorder};
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_chat_completion_maps_into_common_type() {
+        let recorder = Recorder::with_mode(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/providers/openai_chat_completion.json"),
+            RecordMode::Replay,
+        )
+        .unwrap();
+        let provider = OpenAIProvider::with_client("test-key", ProviderClient::with_recorder(Arc::new(recorder)));
+
+        let completion = provider
+            .complete(
+                vec![
+                    ChatMessage::system("You are terse."),
+                    ChatMessage::user("Name a primary colour."),
+                ],
+                CompletionOpts {
+                    max_tokens: Some(16),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            completion,
+            ChatCompletion {
+                text: "Blue".to_string(),
+                usage: TokenUsage {
+                    input_tokens: 21,
+                    output_tokens: 1,
+                },
+                finish_reason: FinishReason::Length,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_deltas_concatenate_to_message() {
+        let events = crate::providers::sse::canned_events(vec![
+            b"data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"},\"finish_reason\":null}]}\n\n",
+            b"data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"},\"finish_reason\":null}]}\n\ndata: {\"choi",
+            b"ces\":[{\"index\":0,\"delta\":{\"content\":\", world\"},\"finish_reason\":null}]}\n\n",
+            b"data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+        ]);
+        let deltas: Vec<ChatDelta> = chat_deltas(events, stream_delta).map(|delta| delta.unwrap()).collect().await;
+        assert!(deltas.last().unwrap().done);
+        assert_eq!(deltas.iter().map(|delta| delta.text.as_str()).collect::<String>(), "Hello, world");
+    }
+}
@@ -0,0 +1,148 @@
+//! # Server-Sent Events
+//!
+//! Parses `text/event-stream` response bodies from `ProviderClient::send_stream` into events, and
+//! turns a provider's events into the `ChatDelta` stream returned by `ChatProvider::complete_stream`.
+//!
+//! Events may be split across body chunks at any byte, including inside a UTF-8 character. A stream
+//! that ends before the provider signalled completion yields an error rather than ending quietly.
+
+use futures::stream::{self, StreamExt};
+
+use crate::provider_types::ai::{ChatDelta, ChatStream};
+use crate::providers::client::ByteStream;
+use crate::utils::bigboterror::BigbotError;
+
+/// A single event; `data` joins multi-line data fields with newlines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+impl SseEvent {
+    /// Parses one event block, None for blocks with only comments.
+    fn parse(raw: &str) -> Option<SseEvent> {
+        let mut event = None;
+        let mut data = Vec::new();
+        for line in raw.lines() {
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => event = Some(value.to_string()),
+                "data" => data.push(value),
+                _ => {}
+            }
+        }
+        if event.is_none() && data.is_empty() {
+            return None;
+        }
+        Some(SseEvent {
+            event,
+            data: data.join("\n"),
+        })
+    }
+}
+
+/// Splits a response body into events.
+pub fn sse_events(body: ByteStream) -> stream::BoxStream<'static, Result<SseEvent, BigbotError>> {
+    stream::unfold(Some((body, Vec::new())), |state| async move {
+        let (mut body, mut buffer) = state?;
+        loop {
+            if let Some(end) = buffer.windows(2).position(|window| window == b"\n\n") {
+                let raw: Vec<u8> = buffer.drain(..end + 2).collect();
+                if let Some(event) = SseEvent::parse(&String::from_utf8_lossy(&raw)) {
+                    return Some((Ok(event), Some((body, buffer))));
+                }
+                continue;
+            }
+            match body.next().await {
+                Some(Ok(chunk)) => buffer.extend(chunk.iter().filter(|byte| **byte != b'\r')),
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => {
+                    let event = SseEvent::parse(&String::from_utf8_lossy(&buffer))?;
+                    return Some((Ok(event), None));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Maps events to deltas with `to_delta`, which returns Ok(None) for events that carry no text.
+/// The stream ends after the first error or `done` delta.
+pub fn chat_deltas<F>(events: stream::BoxStream<'static, Result<SseEvent, BigbotError>>, to_delta: F) -> ChatStream
+where
+    F: FnMut(&SseEvent) -> Result<Option<ChatDelta>, BigbotError> + Send + 'static,
+{
+    stream::unfold(Some((events, to_delta)), |state| async move {
+        let (mut events, mut to_delta) = state?;
+        loop {
+            let delta = match events.next().await {
+                Some(Ok(event)) => match to_delta(&event) {
+                    Ok(Some(delta)) => Ok(delta),
+                    Ok(None) => continue,
+                    Err(e) => Err(e),
+                },
+                Some(Err(e)) => Err(e),
+                None => Err(BigbotError::SystemError(
+                    "Provider stream ended before the reply was complete".to_string(),
+                )),
+            };
+            let more = matches!(&delta, Ok(delta) if !delta.done);
+            return Some((delta, more.then_some((events, to_delta))));
+        }
+    })
+    .boxed()
+}
+
+/// Feeds canned body chunks through `sse_events`, for provider stream tests.
+#[cfg(test)]
+pub(crate) fn canned_events(chunks: Vec<&'static [u8]>) -> stream::BoxStream<'static, Result<SseEvent, BigbotError>> {
+    let body = stream::iter(chunks.into_iter().map(|chunk| Ok(bytes::Bytes::from_static(chunk)))).boxed();
+    sse_events(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_events_split_across_chunks() {
+        let events: Vec<SseEvent> = canned_events(vec![
+            b": keep-alive\n\nevent: greeting\r\ndata: caf",
+            b"\xc3",
+            b"\xa9\r\ndata: au lait\r\n\r\ndata: last",
+        ])
+        .map(|event| event.unwrap())
+        .collect()
+        .await;
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: Some("greeting".to_string()),
+                    data: "café\nau lait".to_string(),
+                },
+                SseEvent {
+                    event: None,
+                    data: "last".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_deltas_error_when_stream_ends_early() {
+        let deltas: Vec<Result<ChatDelta, BigbotError>> = chat_deltas(canned_events(vec![b"data: a\n\ndata: b\n\n"]), |event| {
+            Ok(Some(ChatDelta {
+                text: event.data.clone(),
+                done: false,
+            }))
+        })
+        .collect()
+        .await;
+        assert_eq!(deltas.len(), 3);
+        assert_eq!(deltas[1].as_ref().unwrap().text, "b");
+        assert!(matches!(deltas[2], Err(BigbotError::SystemError(_))));
+    }
+}
@@ -1,13 +1,22 @@
+use futures::stream::{self, Stream};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
 use teloxide::{
     prelude::*,
     types::Message as TelegramMessage,
     utils::command::BotCommands,
 };
 
+use crate::data_streams::Sink;
 use crate::messaging::message::Message;
+use crate::providers::client::ProviderClient;
+use crate::utils::bigboterror::BigbotError;
+
+const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+pub const DEFAULT_TELEGRAM_POLL_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Serialize, Deserialize)]
 struct User {
@@ -252,3 +261,232 @@ impl Application {
         // Register any necessary components or dependencies
     }
 }
+
+// An update from the Bot API `getUpdates` method; only message updates are decoded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TelegramUpdate {
+    pub update_id: i64,
+    #[serde(default)]
+    pub message: Option<TelegramChatMessage>,
+}
+
+impl TelegramUpdate {
+    // The message text, which is what the message classifier works on
+    pub fn text(&self) -> Option<&str> {
+        self.message.as_ref().and_then(|message| message.text.as_deref())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TelegramChatMessage {
+    pub message_id: i64,
+    pub date: i64,
+    pub chat: TelegramChat,
+    #[serde(default)]
+    pub from: Option<TelegramUser>,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TelegramChat {
+    pub id: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TelegramUser {
+    pub id: i64,
+    #[serde(default)]
+    pub username: Option<String>,
+}
+
+// The envelope every Bot API response is wrapped in
+#[derive(Deserialize)]
+struct TelegramResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Serialize)]
+struct SendMessageRequest<'a> {
+    chat_id: i64,
+    text: &'a str,
+}
+
+// Receives updates by long polling `getUpdates` and sends messages through the Bot API
+pub struct TelegramProvider {
+    bot_url: String,
+    client: ProviderClient,
+    poll_timeout: Duration,
+    // The next update id to ask for; updates below it have been delivered already
+    offset: AtomicI64,
+}
+
+impl TelegramProvider {
+    pub fn new(bot_token: &str) -> Self {
+        Self::with_client(bot_token, ProviderClient::new())
+    }
+
+    pub fn with_client(bot_token: &str, client: ProviderClient) -> Self {
+        Self {
+            bot_url: format!("{}/bot{}", TELEGRAM_API_URL, bot_token),
+            client,
+            poll_timeout: DEFAULT_TELEGRAM_POLL_TIMEOUT,
+            offset: AtomicI64::new(0),
+        }
+    }
+
+    // How long each `getUpdates` call waits for an update before returning empty
+    pub fn with_poll_timeout(mut self, poll_timeout: Duration) -> Self {
+        self.poll_timeout = poll_timeout;
+        self
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    // Long polls for the updates from the current offset. The offset is not advanced, so the same
+    // updates come back until they are marked delivered.
+    pub async fn get_updates(&self) -> Result<Vec<TelegramUpdate>, BigbotError> {
+        let url = format!(
+            "{}/getUpdates?offset={}&timeout={}",
+            self.bot_url,
+            self.offset(),
+            self.poll_timeout.as_secs()
+        );
+        self.call("getUpdates", self.client.get(&url)).await
+    }
+
+    // Moves the offset past `update_id`, so Telegram drops it and the updates before it
+    pub fn mark_delivered(&self, update_id: i64) {
+        self.offset.fetch_max(update_id + 1, Ordering::SeqCst);
+    }
+
+    // Polls forever, yielding updates in order. Each update is marked delivered as it is yielded,
+    // so updates fetched but not yet yielded are fetched again if the stream is dropped. The
+    // stream ends after yielding an error; calling `updates` again resumes from the current offset.
+    pub fn updates(&self) -> impl Stream<Item = Result<TelegramUpdate, BigbotError>> + '_ {
+        stream::unfold(Some(VecDeque::new()), move |pending| async move {
+            let mut pending = pending?;
+            loop {
+                if let Some(update) = pending.pop_front() {
+                    self.mark_delivered(update.update_id);
+                    return Some((Ok(update), Some(pending)));
+                }
+                match self.get_updates().await {
+                    Ok(updates) => pending.extend(updates),
+                    Err(e) => return Some((Err(e), None)),
+                }
+            }
+        })
+    }
+
+    // Feeds every update into `sink`, stopping at the first polling or sink error. An update is
+    // marked delivered only once the sink has consumed it, so the one it rejects is polled again.
+    pub async fn forward_updates<S>(&self, sink: &S) -> Result<(), BigbotError>
+    where
+        S: Sink<TelegramUpdate, BigbotError> + Sync,
+    {
+        loop {
+            for update in self.get_updates().await? {
+                let update_id = update.update_id;
+                sink.consume(update).await?;
+                self.mark_delivered(update_id);
+            }
+        }
+    }
+
+    pub async fn send_message(&self, chat_id: i64, text: &str) -> Result<TelegramChatMessage, BigbotError> {
+        let url = format!("{}/sendMessage", self.bot_url);
+        let request = self.client.post(&url).json(&SendMessageRequest { chat_id, text });
+        self.call("sendMessage", request).await
+    }
+
+    async fn call<T: serde::de::DeserializeOwned>(&self, method: &str, request: reqwest::RequestBuilder) -> Result<T, BigbotError> {
+        let response = self.client.send(request).await?;
+        if response.status == 409 {
+            return Err(BigbotError::RejectedError(format!(
+                "Telegram {} conflict: a webhook is set for this bot or another poller is running; delete the webhook before long polling",
+                method
+            )));
+        }
+        let envelope: TelegramResponse<T> = response.json()?;
+        match envelope.result {
+            Some(result) if envelope.ok => Ok(result),
+            _ => Err(BigbotError::SystemError(format!(
+                "Telegram {} returned {}: {}",
+                method,
+                response.status,
+                envelope.description
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{RecordMode, Recorder};
+    use async_trait::async_trait;
+    use futures::StreamExt;
+    use std::sync::{Arc, Mutex};
+
+    fn provider() -> TelegramProvider {
+        let recorder = Recorder::with_mode(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/providers/telegram_updates.json"),
+            RecordMode::Replay,
+        )
+        .unwrap();
+        TelegramProvider::with_client("123:test-token", ProviderClient::with_recorder(Arc::new(recorder)))
+            .with_poll_timeout(Duration::from_secs(5))
+    }
+
+    #[tokio::test]
+    async fn test_updates_advance_offset() {
+        let provider = provider();
+        let updates: Vec<Result<TelegramUpdate, BigbotError>> = provider.updates().collect().await;
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[0].as_ref().unwrap().update_id, 100);
+        assert_eq!(updates[1].as_ref().unwrap().text(), Some("second"));
+        // The second poll asks for offset 102, which the fixture answers with a webhook conflict
+        assert!(matches!(updates[2], Err(BigbotError::RejectedError(ref msg)) if msg.contains("webhook")));
+        assert_eq!(provider.offset(), 102);
+
+        let sent = provider.send_message(42, "hello").await.unwrap();
+        assert_eq!(sent.chat.id, 42);
+        assert_eq!(sent.text.as_deref(), Some("hello"));
+    }
+
+    // Accepts updates until it sees one with `reject_text`
+    struct RejectingSink {
+        reject_text: &'static str,
+        accepted: Mutex<Vec<i64>>,
+    }
+
+    #[async_trait]
+    impl Sink<TelegramUpdate, BigbotError> for RejectingSink {
+        async fn consume(&self, update: TelegramUpdate) -> Result<(), BigbotError> {
+            if update.text() == Some(self.reject_text) {
+                return Err(BigbotError::SystemError("sink is full".to_string()));
+            }
+            self.accepted.lock().unwrap().push(update.update_id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_update_is_not_marked_delivered() {
+        let provider = provider();
+        let sink = RejectingSink {
+            reject_text: "second",
+            accepted: Mutex::new(Vec::new()),
+        };
+        assert!(provider.forward_updates(&sink).await.is_err());
+        assert_eq!(*sink.accepted.lock().unwrap(), vec![100]);
+        // Update 101 was fetched but not consumed, so the next poll asks for it again
+        assert_eq!(provider.offset(), 101);
+    }
+}
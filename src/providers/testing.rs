@@ -0,0 +1,240 @@
+//! # Provider Test Harness
+//!
+//! VCR-style recording and replay of provider HTTP interactions.
+//!
+//! A `Recorder` is attached to a `ProviderClient` and backed by a JSON fixture file. In
+//! `Record` mode requests hit the live API and each interaction is appended to the fixture;
+//! in `Replay` mode requests are answered from the fixture without touching the network.
+//! `Auto` records when the fixture doesn't exist yet and replays otherwise.
+//!
+//! The mode is read from the `PROVIDER_RECORD_MODE` environment variable
+//! (`record`, `replay`, `auto` or `off`) and defaults to `replay` so CI never calls live APIs.
+//! Request headers are never written to fixtures, and credentials passed in the query string
+//! (`key`, `api_key`, `token` and the like) are redacted from recorded URLs, so API keys don't
+//! leak into the repository.
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::providers::client::{execute, ProviderResponse};
+use crate::utils::bigboterror::BigbotError;
+
+pub const RECORD_MODE_ENV: &str = "PROVIDER_RECORD_MODE";
+
+// Query parameters whose values are replaced with `REDACTED_VALUE` in recorded URLs
+const SENSITIVE_QUERY_PARAMS: &[&str] = &[
+    "key",
+    "api_key",
+    "apikey",
+    "access_token",
+    "token",
+    "secret",
+    "client_secret",
+    "password",
+    "signature",
+    "sig",
+];
+const REDACTED_VALUE: &str = "REDACTED";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordMode {
+    /// Always call the live API and (re)write the fixture.
+    Record,
+    /// Only answer from the fixture; unknown requests are errors.
+    Replay,
+    /// Record if the fixture is missing, replay otherwise.
+    Auto,
+    /// Call the live API without touching the fixture.
+    Off,
+}
+
+/// Reads the record mode from `PROVIDER_RECORD_MODE`, defaulting to `Replay`.
+pub fn record_mode() -> RecordMode {
+    match std::env::var(RECORD_MODE_ENV)
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "record" => RecordMode::Record,
+        "auto" => RecordMode::Auto,
+        "off" => RecordMode::Off,
+        _ => RecordMode::Replay,
+    }
+}
+
+/// A single recorded request/response pair.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    pub request_body: Option<String>,
+    pub status: u16,
+    pub response_body: String,
+}
+
+impl Interaction {
+    fn matches(&self, method: &str, url: &str, body: &Option<String>) -> bool {
+        self.method == method && self.url == url && &self.request_body == body
+    }
+}
+
+pub struct Recorder {
+    fixture_path: PathBuf,
+    mode: RecordMode,
+    state: Mutex<RecorderState>,
+}
+
+struct RecorderState {
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+}
+
+impl Recorder {
+    /// Creates a recorder for the fixture using the mode from the environment.
+    pub fn new(fixture_path: impl AsRef<Path>) -> Result<Self, BigbotError> {
+        Self::with_mode(fixture_path, record_mode())
+    }
+
+    pub fn with_mode(fixture_path: impl AsRef<Path>, mode: RecordMode) -> Result<Self, BigbotError> {
+        let fixture_path = fixture_path.as_ref().to_path_buf();
+        let mode = match mode {
+            RecordMode::Auto if fixture_path.exists() => RecordMode::Replay,
+            RecordMode::Auto => RecordMode::Record,
+            mode => mode,
+        };
+        let interactions = match mode {
+            RecordMode::Replay => {
+                let raw = std::fs::read_to_string(&fixture_path)?;
+                serde_json::from_str(&raw).map_err(|e| {
+                    BigbotError::InvalidInput(format!(
+                        "Invalid fixture {}: {}",
+                        fixture_path.display(),
+                        e
+                    ))
+                })?
+            }
+            _ => Vec::new(),
+        };
+        let used = vec![false; interactions.len()];
+        Ok(Self {
+            fixture_path,
+            mode,
+            state: Mutex::new(RecorderState { interactions, used }),
+        })
+    }
+
+    pub fn mode(&self) -> RecordMode {
+        self.mode
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.state.lock().unwrap().interactions.clone()
+    }
+
+    pub(crate) async fn send(&self, client: &Client, request: reqwest::Request) -> Result<ProviderResponse, BigbotError> {
+        let method = request.method().to_string();
+        // Replay matches on the redacted URL too, so fixtures never need the real key
+        let url = redact_url(request.url());
+        let body = request
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| String::from_utf8_lossy(b).into_owned());
+
+        match self.mode {
+            RecordMode::Replay => self.replay(&method, &url, &body),
+            RecordMode::Off => execute(client, request).await,
+            _ => {
+                let response = execute(client, request).await?;
+                self.record(Interaction {
+                    method,
+                    url,
+                    request_body: body,
+                    status: response.status,
+                    response_body: response.body.clone(),
+                })?;
+                Ok(response)
+            }
+        }
+    }
+
+    // Answers with the first unused interaction matching the request
+    fn replay(&self, method: &str, url: &str, body: &Option<String>) -> Result<ProviderResponse, BigbotError> {
+        let mut state = self.state.lock().unwrap();
+        let RecorderState { interactions, used } = &mut *state;
+        let index = interactions
+            .iter()
+            .enumerate()
+            .position(|(i, interaction)| !used[i] && interaction.matches(method, url, body))
+            .ok_or_else(|| {
+                BigbotError::InvalidInput(format!(
+                    "No recorded interaction for {} {} in {}",
+                    method,
+                    url,
+                    self.fixture_path.display()
+                ))
+            })?;
+        used[index] = true;
+        let interaction = &interactions[index];
+        Ok(ProviderResponse {
+            status: interaction.status,
+            body: interaction.response_body.clone(),
+        })
+    }
+
+    // Appends the interaction and rewrites the fixture so a crashed run keeps what it recorded
+    fn record(&self, interaction: Interaction) -> Result<(), BigbotError> {
+        let mut state = self.state.lock().unwrap();
+        state.interactions.push(interaction);
+        state.used.push(true);
+        if let Some(parent) = self.fixture_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let raw = serde_json::to_string_pretty(&state.interactions)
+            .map_err(|e| BigbotError::UnexpectedError(e.to_string()))?;
+        std::fs::write(&self.fixture_path, raw)?;
+        Ok(())
+    }
+}
+
+// The URL with the values of credential-like query parameters replaced
+fn redact_url(url: &reqwest::Url) -> String {
+    if url.query().is_none() {
+        return url.to_string();
+    }
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if SENSITIVE_QUERY_PARAMS.contains(&name.to_ascii_lowercase().as_str()) {
+                REDACTED_VALUE.to_string()
+            } else {
+                value.into_owned()
+            };
+            (name.into_owned(), value)
+        })
+        .collect();
+    let mut redacted = url.clone();
+    redacted.query_pairs_mut().clear().extend_pairs(pairs);
+    redacted.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_string_credentials_are_redacted() {
+        let url = reqwest::Url::parse(
+            "https://example.com/v1/search?q=rust&api_key=sk-live-123&Token=abc&page=2",
+        )
+        .unwrap();
+        assert_eq!(
+            redact_url(&url),
+            "https://example.com/v1/search?q=rust&api_key=REDACTED&Token=REDACTED&page=2"
+        );
+
+        let url = reqwest::Url::parse("https://api.anthropic.com/v1/complete").unwrap();
+        assert_eq!(redact_url(&url), "https://api.anthropic.com/v1/complete");
+    }
+}
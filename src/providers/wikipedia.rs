@@ -1,6 +1,198 @@
 use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
 use crate::provider_types::search::SearchProvider;
 use crate::data_exchange::exchange_interfaces::DataExchange;
+use crate::providers::client::ProviderClient;
+use crate::utils::bigboterror::BigbotError;
+
+// `{lang}` is replaced with the language code, e.g. "en" or "de"
+const WIKIPEDIA_URL_TEMPLATE: &str = "https://{lang}.wikipedia.org";
+pub const DEFAULT_WIKIPEDIA_USER_AGENT: &str = concat!("bigbot_rust/", env!("CARGO_PKG_VERSION"));
+
+// A page summary returned by `WikipediaProvider::search`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WikiSummary {
+    pub title: String,
+    pub extract: String,
+    pub page_url: String,
+    pub thumbnail: Option<String>,
+    // Titles linked from a disambiguation page; empty for other pages
+    #[serde(default)]
+    pub candidates: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    pages: Vec<SearchPage>,
+}
+
+#[derive(Deserialize)]
+struct SearchPage {
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct SummaryResponse {
+    #[serde(rename = "type")]
+    page_type: String,
+    title: String,
+    #[serde(default)]
+    extract: String,
+    content_urls: Option<ContentUrls>,
+    thumbnail: Option<Thumbnail>,
+}
+
+#[derive(Deserialize)]
+struct ContentUrls {
+    desktop: PageUrls,
+}
+
+#[derive(Deserialize)]
+struct PageUrls {
+    page: String,
+}
+
+#[derive(Deserialize)]
+struct Thumbnail {
+    source: String,
+}
+
+#[derive(Deserialize)]
+struct LinksResponse {
+    query: LinksQuery,
+}
+
+#[derive(Deserialize)]
+struct LinksQuery {
+    pages: Vec<LinksPage>,
+}
+
+#[derive(Deserialize)]
+struct LinksPage {
+    #[serde(default)]
+    links: Vec<Link>,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    title: String,
+}
+
+// Searches Wikipedia through its REST API and returns page summaries
+pub struct WikipediaProvider {
+    client: ProviderClient,
+    user_agent: String,
+}
+
+impl WikipediaProvider {
+    pub fn new() -> Self {
+        Self::with_client(ProviderClient::new())
+    }
+
+    pub fn with_client(client: ProviderClient) -> Self {
+        Self {
+            client,
+            user_agent: DEFAULT_WIKIPEDIA_USER_AGENT.to_string(),
+        }
+    }
+
+    // Wikimedia asks API clients to identify themselves with a descriptive User-Agent
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    // Finds up to `limit` pages matching `query` on the `lang` Wikipedia and summarises each one
+    pub async fn search(&self, query: &str, lang: &str, limit: usize) -> Result<Vec<WikiSummary>, BigbotError> {
+        let mut url = self.api_url(lang, &["w", "rest.php", "v1", "search", "page"])?;
+        url.query_pairs_mut()
+            .append_pair("q", query)
+            .append_pair("limit", &limit.to_string());
+        let search: SearchResponse = self.get_json(url).await?;
+
+        let mut summaries = Vec::with_capacity(search.pages.len());
+        for page in search.pages.iter().take(limit) {
+            summaries.push(self.summary(lang, &page.key).await?);
+        }
+        Ok(summaries)
+    }
+
+    async fn summary(&self, lang: &str, key: &str) -> Result<WikiSummary, BigbotError> {
+        let url = self.api_url(lang, &["api", "rest_v1", "page", "summary", key])?;
+        let summary: SummaryResponse = self.get_json(url).await?;
+        let candidates = if summary.page_type == "disambiguation" {
+            self.disambiguation_candidates(lang, &summary.title).await?
+        } else {
+            Vec::new()
+        };
+        let page_url = match summary.content_urls {
+            Some(content_urls) => content_urls.desktop.page,
+            None => self.api_url(lang, &["wiki", key])?.to_string(),
+        };
+        Ok(WikiSummary {
+            title: summary.title,
+            extract: summary.extract,
+            page_url,
+            thumbnail: summary.thumbnail.map(|thumbnail| thumbnail.source),
+            candidates,
+        })
+    }
+
+    // The REST summary doesn't list a disambiguation page's options, so read its article links
+    async fn disambiguation_candidates(&self, lang: &str, title: &str) -> Result<Vec<String>, BigbotError> {
+        let mut url = self.api_url(lang, &["w", "api.php"])?;
+        url.query_pairs_mut()
+            .append_pair("action", "query")
+            .append_pair("format", "json")
+            .append_pair("formatversion", "2")
+            .append_pair("prop", "links")
+            .append_pair("plnamespace", "0")
+            .append_pair("pllimit", "max")
+            .append_pair("titles", title);
+        let links: LinksResponse = self.get_json(url).await?;
+        Ok(links
+            .query
+            .pages
+            .into_iter()
+            .flat_map(|page| page.links)
+            .map(|link| link.title)
+            .collect())
+    }
+
+    fn api_url(&self, lang: &str, segments: &[&str]) -> Result<Url, BigbotError> {
+        if lang.is_empty() || !lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(BigbotError::InvalidInput(format!("Invalid Wikipedia language: {}", lang)));
+        }
+        let mut url = Url::parse(&WIKIPEDIA_URL_TEMPLATE.replace("{lang}", lang))
+            .map_err(|e| BigbotError::InvalidInput(format!("Invalid Wikipedia URL: {}", e)))?;
+        url.path_segments_mut()
+            .map_err(|_| BigbotError::InvalidInput("Invalid Wikipedia URL".to_string()))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: Url) -> Result<T, BigbotError> {
+        let response = self
+            .client
+            .send(
+                self.client
+                    .get(url.as_str())
+                    .header(reqwest::header::USER_AGENT, &self.user_agent),
+            )
+            .await?;
+        if !response.is_success() {
+            return Err(BigbotError::SystemError(format!(
+                "Wikipedia API returned {}: {}",
+                response.status,
+                response.text()
+            )));
+        }
+        response.json()
+    }
+}
 
 pub struct WikipediaSearchProvider {
     api_url: String,
@@ -109,3 +301,50 @@ impl DataExchange<String, Result<HashMap<String, String>, String>> for Wikipedia
         self.search_provider.search(&data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{RecordMode, Recorder};
+    use std::sync::Arc;
+
+    fn provider() -> WikipediaProvider {
+        let recorder = Recorder::with_mode(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/providers/wikipedia_search.json"),
+            RecordMode::Replay,
+        )
+        .unwrap();
+        WikipediaProvider::with_client(ProviderClient::with_recorder(Arc::new(recorder)))
+    }
+
+    #[tokio::test]
+    async fn test_search_replays_recorded_fixture() {
+        let summaries = provider().search("Mercury", "en", 2).await.unwrap();
+        assert_eq!(summaries.len(), 2);
+
+        assert_eq!(summaries[0].title, "Mercury");
+        assert_eq!(summaries[0].page_url, "https://en.wikipedia.org/wiki/Mercury");
+        assert_eq!(summaries[0].thumbnail, None);
+        assert_eq!(
+            summaries[0].candidates,
+            vec!["Mercury (element)", "Mercury (mythology)", "Mercury (planet)"]
+        );
+
+        assert_eq!(
+            summaries[1],
+            WikiSummary {
+                title: "Mercury (planet)".to_string(),
+                extract: "Mercury is the first planet from the Sun and the smallest in the Solar System.".to_string(),
+                page_url: "https://en.wikipedia.org/wiki/Mercury_(planet)".to_string(),
+                thumbnail: Some("https://upload.wikimedia.org/wikipedia/commons/thumb/4/4a/Mercury_in_true_color.jpg/320px-Mercury_in_true_color.jpg".to_string()),
+                candidates: Vec::new(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_rejects_invalid_language() {
+        let err = provider().search("Mercury", "en.evil.com/x", 2).await.unwrap_err();
+        assert!(matches!(err, BigbotError::InvalidInput(_)));
+    }
+}
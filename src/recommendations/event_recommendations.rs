@@ -12,6 +12,15 @@
 //! 4. **Sorting Stage**: Sorts the remaining event candidates based on a combination of user preferences, event significance, and other relevant metrics to rank the most
 //!    suitable events highest.
 //!
+//! The ranking score is the event's significance scaled by the user's preference for the interest that matched it. Setting `explain` on the
+//! handler attaches an `Explanation` to each `Alert`: the significance, plus the change the interest match makes to it, e.g.
+//! "significant (0.4), matches interest 'music' (0.2)".
+//!
+//! For callers that already hold candidate events, `EventRecommender::recommend_events` ranks them without the database: each candidate's
+//! `SignificanceScorer` score is combined with the Q-learning agent's value for the candidate's action in the user's current state. Candidates
+//! overlapping an event the user has accepted, or falling outside the user's free time slots, are dropped. Each `ScoredEvent` carries both parts
+//! of its score so a UI can explain the ranking.
+//!
 //! The `RecommendHandler` utilizes async/await for asynchronous operations, particularly for database interactions and the processing pipeline. It is designed to integrate
 //! seamlessly with a larger system that manages user interactions, event data, and user preferences.
 //!
@@ -29,16 +38,20 @@
 //! - [`thiserror`]: Provides the `Error` derive macro for custom error types.
 
 
+use crate::agents::q_learning_agent::QLearningAgent;
 use crate::event::Location;
 use crate::event::{Event, EventHandler};
 use crate::graphs::event_graph::EventHandlerError;
-use crate::significance::event_significance::{EventSignificance, EventType};
+use crate::graphs::schedule_graph::events_overlap;
+use crate::significance::event_significance::{
+    EventSignificance, EventType, Explanation, SignificanceContext, SignificanceScorer,
+};
 
 use futures::future::join_all;
 use neo4rs::{query, Graph};
 use std::sync::Arc;
 use std::convert::TryFrom;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -56,6 +69,26 @@ pub struct RecommendHandler {
     neo_client: Arc<Graph>,
     pub distance_threshold: f32,
     pub time_to_start_threshold: u64,
+    /// Attach an `Explanation` of the ranking score to each alert.
+    pub explain: bool,
+}
+
+// Ranks a candidate: its significance scaled by the user's preference for the matched interest
+fn ranking_score(candidate: &EventCandidate) -> f64 {
+    candidate.event.significance * candidate.preference
+}
+
+// Breaks `ranking_score` into factors that add up to it: the significance, and what scaling it by
+// the interest preference adds or takes away
+fn explain_ranking(candidate: &EventCandidate) -> Explanation {
+    let significance = candidate.event.significance;
+    Explanation::new(vec![
+        ("significant".to_string(), significance),
+        (
+            format!("matches interest '{}'", candidate.interest),
+            ranking_score(candidate) - significance,
+        ),
+    ])
 }
 
 #[derive(Debug)]
@@ -63,12 +96,16 @@ pub struct Alert {
     pub event_name: String,
     pub message: String,
     pub event: Event,
+    pub score: f64,
+    pub explanation: Option<Explanation>,
 }
 
 #[derive(Debug)]
 struct EventCandidate {
     pub event: Event,
     pub distance: f32,
+    // The user's interest (the mentioned entity) the event was recalled through
+    pub interest: String,
     pub preference: f64,
     pub filter_reason: Option<CandidateFilterReason>,
 }
@@ -104,10 +141,13 @@ impl RecommendHandler {
                     ("location".to_string(), event.location.to_string()),
                 ]);
                 let message = self.generate_message(message_data);
+                let explanation = self.explain.then(|| explain_ranking(&event_candidate));
                 Alert {
                     event_name: event.name.clone(),
                     message,
                     event: event.clone(),
+                    score: ranking_score(&event_candidate),
+                    explanation,
                 }
             })
             .collect();
@@ -191,6 +231,7 @@ impl RecommendHandler {
                 Ok(EventCandidate {
                     event,
                     distance,
+                    interest: row.get("e.text").unwrap_or_default(),
                     preference: row.get("m.score").unwrap_or_default(),
                     filter_reason,
                 })
@@ -240,9 +281,259 @@ impl RecommendHandler {
     /// Sorts events based on preferences and significance.
     fn sort_events(&self, events: &mut Vec<EventCandidate>) {
         events.sort_unstable_by(|a, b| {
-            let a_weight = a.event.significance * a.preference;
-            let b_weight = b.event.significance * b.preference;
+            let a_weight = ranking_score(a);
+            let b_weight = ranking_score(b);
             b_weight.total_cmp(&a_weight)
         });
     }
 }
+
+/// Weights of the terms in `EventRecommender`'s combined score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecommenderWeights {
+    pub significance: f64,
+    pub action_value: f64,
+}
+
+impl Default for RecommenderWeights {
+    fn default() -> Self {
+        Self {
+            significance: 1.0,
+            action_value: 1.0,
+        }
+    }
+}
+
+/// What the recommender knows about a user when ranking events for them.
+#[derive(Debug, Clone, Default)]
+pub struct UserSchedule {
+    /// The user's current state in the Q-learning agent.
+    pub state: usize,
+    /// Events the user has accepted; candidates overlapping any of them are dropped.
+    pub accepted: Vec<Event>,
+    /// `(start, end)` slots the user is free in. A candidate must fit inside one; `None` means the user is always free.
+    pub free_slots: Option<Vec<(i32, i32)>>,
+    pub significance: SignificanceContext,
+}
+
+impl UserSchedule {
+    fn is_available_for(&self, event: &Event) -> bool {
+        let fits_free_slot = self
+            .free_slots
+            .as_ref()
+            .map_or(true, |slots| slots.iter().any(|&(start, end)| start <= event.start && event.end <= end));
+        fits_free_slot && !self.accepted.iter().any(|accepted| events_overlap(accepted, event))
+    }
+}
+
+/// A recommended event with its combined score and the parts it is made of, both between 0 and 1.
+#[derive(Debug, Clone)]
+pub struct ScoredEvent {
+    pub event: Event,
+    pub score: f64,
+    pub significance: f64,
+    pub action_value: f64,
+}
+
+/// Ranks candidate events for users by significance and learned action value.
+pub struct EventRecommender {
+    scorer: SignificanceScorer,
+    agent: QLearningAgent,
+    action_for: Box<dyn Fn(&Event) -> Option<usize> + Send + Sync>,
+    weights: RecommenderWeights,
+    users: HashMap<i64, UserSchedule>,
+}
+
+impl EventRecommender {
+    /// `action_for` maps a candidate to the agent action that represents attending it; events without
+    /// an action get a neutral action value of 0.5.
+    pub fn new(
+        scorer: SignificanceScorer,
+        agent: QLearningAgent,
+        action_for: impl Fn(&Event) -> Option<usize> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            scorer,
+            agent,
+            action_for: Box::new(action_for),
+            weights: RecommenderWeights::default(),
+            users: HashMap::new(),
+        }
+    }
+
+    pub fn with_weights(mut self, weights: RecommenderWeights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Replaces what the recommender knows about a user. Users without a schedule are treated as
+    /// free at all times, in state 0, with nothing accepted.
+    pub fn set_user_schedule(&mut self, user_id: i64, schedule: UserSchedule) {
+        self.users.insert(user_id, schedule);
+    }
+
+    pub fn user_schedule(&self, user_id: i64) -> Option<&UserSchedule> {
+        self.users.get(&user_id)
+    }
+
+    /// The best `k` candidates the user can attend, highest combined score first. Candidates clashing
+    /// with the user's accepted events or free slots are dropped, as are repeats of the same event id.
+    pub fn recommend_events(&self, user_id: i64, candidates: &[Event], k: usize) -> Vec<ScoredEvent> {
+        let default_schedule = UserSchedule::default();
+        let schedule = self.users.get(&user_id).unwrap_or(&default_schedule);
+        let action_values = self.normalized_action_values(schedule.state);
+
+        let mut seen = HashSet::new();
+        let mut scored: Vec<ScoredEvent> = candidates
+            .iter()
+            .filter(|event| schedule.is_available_for(event) && seen.insert(event.id))
+            .map(|event| {
+                let significance = self.scorer.score(event, &schedule.significance);
+                let action_value = (self.action_for)(event)
+                    .and_then(|action| action_values.get(action).copied())
+                    .unwrap_or(NEUTRAL_ACTION_VALUE);
+                ScoredEvent {
+                    event: event.clone(),
+                    score: self.weights.significance * significance + self.weights.action_value * action_value,
+                    significance,
+                    action_value,
+                }
+            })
+            .collect();
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.event.start.cmp(&b.event.start)));
+        scored.truncate(k);
+        scored
+    }
+
+    /// The agent's action values in `state`, rescaled to 0..1 so they combine with significance.
+    /// All actions are neutral if the state is unknown or its values are all equal.
+    fn normalized_action_values(&self, state: usize) -> Vec<f64> {
+        if state >= self.agent.agent.q_table.len() {
+            return Vec::new();
+        }
+        let values = self.agent.action_values(state);
+        let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        values
+            .iter()
+            .map(|&value| {
+                if max > min {
+                    ((value - min) / (max - min)) as f64
+                } else {
+                    NEUTRAL_ACTION_VALUE
+                }
+            })
+            .collect()
+    }
+}
+
+/// Action value given to candidates the agent has no opinion on.
+const NEUTRAL_ACTION_VALUE: f64 = 0.5;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::q_learning_agent::QLearningConfig;
+    use crate::event::{EventBuilder, EventType as StreamEventType};
+
+    fn candidate(id: i64, name: &str, interest: &str, significance: f64, preference: f64) -> EventCandidate {
+        let event = EventBuilder::new(id, name, StreamEventType::ScheduledEvent)
+            .time_range(0, 10)
+            .resource("venue")
+            .significance(significance)
+            .build()
+            .unwrap();
+        EventCandidate {
+            event,
+            distance: 0.0,
+            interest: interest.to_string(),
+            preference,
+            filter_reason: None,
+        }
+    }
+
+    #[test]
+    fn test_explanation_factors_sum_to_score() {
+        let concert = candidate(1, "Friday Night Jazz", "music", 0.4, 1.5);
+
+        let explanation = explain_ranking(&concert);
+
+        assert!((ranking_score(&concert) - 0.6).abs() < 1e-9);
+        assert!((explanation.total() - ranking_score(&concert)).abs() < 1e-9);
+        assert_eq!(explanation.factors[0], ("significant".to_string(), 0.4));
+        // Labelled with the matched interest, not the event
+        assert_eq!(explanation.factors[1].0, "matches interest 'music'");
+        assert!((explanation.factors[1].1 - 0.2).abs() < 1e-9);
+    }
+
+    fn slot_event(id: i64, start: i32, end: i32, significance: f64) -> Event {
+        EventBuilder::new(id, format!("event {}", id), StreamEventType::ScheduledEvent)
+            .time_range(start as u64, end as u64)
+            .significance(significance)
+            .build()
+            .unwrap()
+    }
+
+    // Two states and three actions. In state 0 action 2 is clearly best and action 0 worst.
+    fn recommender() -> EventRecommender {
+        let mut agent = QLearningAgent::with_seed(QLearningConfig::new(2, 3), 7);
+        agent.agent.q_table = vec![vec![-1.0, 0.0, 1.0], vec![0.0, 0.0, 0.0]];
+        EventRecommender::new(SignificanceScorer::default(), agent, |event: &Event| Some(event.id as usize % 3))
+    }
+
+    #[test]
+    fn test_recommend_events_drops_overlapping_and_unavailable_candidates() {
+        let mut recommender = recommender();
+        recommender.set_user_schedule(
+            1,
+            UserSchedule {
+                accepted: vec![slot_event(10, 100, 200, 0.0)],
+                free_slots: Some(vec![(0, 500)]),
+                ..Default::default()
+            },
+        );
+        let candidates = vec![
+            slot_event(1, 150, 250, 9.0), // overlaps the accepted event
+            slot_event(2, 200, 300, 1.0), // starts as the accepted event ends
+            slot_event(4, 450, 550, 9.0), // runs past the free slot
+            slot_event(5, 0, 100, 1.0),
+            slot_event(5, 0, 100, 1.0), // repeat
+        ];
+
+        let recommended = recommender.recommend_events(1, &candidates, 10);
+        let ids: Vec<i64> = recommended.iter().map(|scored| scored.event.id).collect();
+        assert_eq!(ids, vec![5, 2]);
+
+        // Without a schedule every distinct candidate is a fit
+        assert_eq!(recommender.recommend_events(2, &candidates, 10).len(), 4);
+    }
+
+    #[test]
+    fn test_recommend_events_orders_by_combined_score() {
+        let recommender = recommender();
+        // Event 3 is the most significant but maps to the worst action, event 5 to the best and
+        // event 4 to the middle one
+        let candidates = vec![
+            slot_event(3, 0, 10, 9.0),
+            slot_event(4, 20, 30, 1.0),
+            slot_event(5, 40, 50, 1.0),
+        ];
+
+        let recommended = recommender.recommend_events(1, &candidates, 2);
+        let ids: Vec<i64> = recommended.iter().map(|scored| scored.event.id).collect();
+        assert_eq!(ids, vec![5, 4]);
+        assert!((recommended[0].action_value - 1.0).abs() < 1e-9);
+        assert!((recommended[0].significance - 0.5).abs() < 1e-9);
+        assert!((recommended[0].score - 1.5).abs() < 1e-9);
+        assert!(recommended[0].score > recommended[1].score);
+        assert!(recommended.iter().all(|scored| {
+            (scored.score - (scored.significance + scored.action_value)).abs() < 1e-9
+        }));
+
+        let significance_only = recommender.with_weights(RecommenderWeights {
+            significance: 1.0,
+            action_value: 0.0,
+        });
+        assert_eq!(significance_only.recommend_events(1, &candidates, 1)[0].event.id, 3);
+    }
+}
@@ -21,11 +21,15 @@ Key functionalities of this process include:
 The learning loop proceeds until a terminal condition is met, which could be due to convergence (minimal improvement), reaching a maximum number of iterations, or encountering an operational error.
 
 Constants for the learning parameters (gamma, learning rate, and initial exploration rate) are defined within the module, supporting the configuration and tuning of the learning process.
+
+Human feedback on live messages flows through "Rlhf". When an agent's action produces a message, "record_decision" remembers which agent, state and action it came from. "record_feedback" later turns a thumbs-up/down or rating on that message into a reward and pushes the resulting experience into that agent's replay buffer, and "update" replays the buffers. Feedback can arrive long after the action, in batches ("record_feedback_batch"), or even before the decision is recorded, in which case it is held until the decision arrives.
 */
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
 
 use crate::agents::q_learning_agent::QLearningAgent;
 use crate::graphs::user_graph::UserGraph;
@@ -83,7 +87,9 @@ pub fn run_reinforcement_learning(user_graph: &mut UserGraph, config: &RLHFConfi
         num_iterations += 1;
         exploration_rate = update_exploration_rate(num_iterations, config);
         let valid_actions = get_valid_actions(user_graph, &agent);
-        let action = agent.choose_action(agent.state(), &valid_actions);
+        let action = agent
+            .choose_action(agent.state(), &valid_actions)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
         let (next_state, reward) = simulate_action(user_graph, &agent, action);
         let feedback_text = read_message(user_graph, &agent, action);
         let feedback = process_feedback(&feedback_text);
@@ -165,3 +171,285 @@ fn update_message_feedback(user_graph: &mut UserGraph, agent: &QLearningAgent, a
         }
     }
 }
+
+// Default number of message decisions kept for crediting feedback
+pub const DEFAULT_DECISION_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum RlhfError {
+    #[error("No agent registered as '{0}'")]
+    UnknownAgent(String),
+    #[error("Decision is outside agent '{agent_key}': state {state}, action {action}, next state {next_state}")]
+    InvalidDecision {
+        agent_key: String,
+        state: usize,
+        action: usize,
+        next_state: usize,
+    },
+    #[error("Rating {value} is not between 0 and {max}")]
+    InvalidRating { value: f32, max: f32 },
+}
+
+// What a user said about a message
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FeedbackSignal {
+    ThumbsUp,
+    ThumbsDown,
+    // A rating from 0 to `max`, e.g. 4 of 5 stars
+    Rating { value: f32, max: f32 },
+}
+
+impl FeedbackSignal {
+    // The reward the signal is worth, from -1 to 1. Ratings map linearly, so the midpoint is neutral.
+    pub fn reward(&self) -> Result<f32, RlhfError> {
+        match *self {
+            FeedbackSignal::ThumbsUp => Ok(1.0),
+            FeedbackSignal::ThumbsDown => Ok(-1.0),
+            FeedbackSignal::Rating { value, max } => {
+                if !(max > 0.0 && (0.0..=max).contains(&value)) {
+                    return Err(RlhfError::InvalidRating { value, max });
+                }
+                Ok(2.0 * value / max - 1.0)
+            }
+        }
+    }
+}
+
+// The agent decision that produced a message: the state it was in, the action it took and the
+// state that action led to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageDecision {
+    pub agent_key: String,
+    pub state: usize,
+    pub action: usize,
+    pub next_state: usize,
+}
+
+// Routes human feedback on messages to the Q-learning agents whose decisions produced them
+pub struct Rlhf {
+    agents: HashMap<String, QLearningAgent>,
+    decision_capacity: usize,
+    decisions: HashMap<Uuid, MessageDecision>,
+    decision_order: VecDeque<Uuid>, // Front is the oldest decision
+    // Feedback for messages whose decision hasn't been recorded yet
+    pending: HashMap<Uuid, Vec<FeedbackSignal>>,
+    pending_order: VecDeque<Uuid>, // Front is the message that got feedback first
+}
+
+impl Rlhf {
+    pub fn new() -> Self {
+        Self {
+            agents: HashMap::new(),
+            decision_capacity: DEFAULT_DECISION_CAPACITY,
+            decisions: HashMap::new(),
+            decision_order: VecDeque::new(),
+            pending: HashMap::new(),
+            pending_order: VecDeque::new(),
+        }
+    }
+
+    // Keeps at most `capacity` decisions, and pending feedback for at most `capacity` messages. The
+    // oldest are dropped first, so feedback on an evicted or never recorded decision is discarded.
+    pub fn with_decision_capacity(mut self, capacity: usize) -> Self {
+        self.decision_capacity = capacity.max(1);
+        self
+    }
+
+    pub fn add_agent(&mut self, agent_key: &str, agent: QLearningAgent) {
+        self.agents.insert(agent_key.to_string(), agent);
+    }
+
+    pub fn agent(&self, agent_key: &str) -> Option<&QLearningAgent> {
+        self.agents.get(agent_key)
+    }
+
+    // Remembers which decision produced a message, then applies any feedback that was waiting on it.
+    // Returns the number of pending signals applied.
+    pub fn record_decision(&mut self, message_id: Uuid, decision: MessageDecision) -> Result<usize, RlhfError> {
+        let agent = self
+            .agents
+            .get(&decision.agent_key)
+            .ok_or_else(|| RlhfError::UnknownAgent(decision.agent_key.clone()))?;
+        let num_states = agent.agent.q_table.len();
+        let num_actions = agent.agent.q_table.first().map_or(0, Vec::len);
+        if decision.state >= num_states || decision.next_state >= num_states || decision.action >= num_actions {
+            return Err(RlhfError::InvalidDecision {
+                agent_key: decision.agent_key,
+                state: decision.state,
+                action: decision.action,
+                next_state: decision.next_state,
+            });
+        }
+
+        if self.decisions.insert(message_id, decision).is_none() {
+            self.decision_order.push_back(message_id);
+        }
+        while self.decision_order.len() > self.decision_capacity {
+            if let Some(oldest) = self.decision_order.pop_front() {
+                self.decisions.remove(&oldest);
+            }
+        }
+
+        let pending = match self.pending.remove(&message_id) {
+            Some(pending) => {
+                self.pending_order.retain(|id| *id != message_id);
+                pending
+            }
+            None => Vec::new(),
+        };
+        for signal in &pending {
+            self.apply_feedback(message_id, *signal)?;
+        }
+        Ok(pending.len())
+    }
+
+    // Converts feedback on a message into a reward for the decision behind it. Returns true if it
+    // was pushed into the agent's replay buffer now, false if it is held until the message's
+    // decision is recorded. The Q-values move on the next `update`.
+    pub fn record_feedback(&mut self, message_id: Uuid, signal: FeedbackSignal) -> Result<bool, RlhfError> {
+        signal.reward()?;
+        if !self.decisions.contains_key(&message_id) {
+            self.hold_feedback(message_id, signal);
+            return Ok(false);
+        }
+        self.apply_feedback(message_id, signal)?;
+        Ok(true)
+    }
+
+    // Records a batch of feedback, e.g. collected offline. Invalid signals are skipped so one bad
+    // rating doesn't drop the rest; returns how many were applied immediately.
+    pub fn record_feedback_batch(&mut self, feedback: impl IntoIterator<Item = (Uuid, FeedbackSignal)>) -> usize {
+        feedback
+            .into_iter()
+            .filter(|(message_id, signal)| matches!(self.record_feedback(*message_id, *signal), Ok(true)))
+            .count()
+    }
+
+    // Replays every agent's buffer, moving Q-values towards the recorded feedback
+    pub fn update(&mut self) {
+        for agent in self.agents.values_mut() {
+            agent.update_q_values();
+        }
+    }
+
+    fn hold_feedback(&mut self, message_id: Uuid, signal: FeedbackSignal) {
+        let signals = self.pending.entry(message_id).or_default();
+        if signals.is_empty() {
+            self.pending_order.push_back(message_id);
+        }
+        signals.push(signal);
+        while self.pending_order.len() > self.decision_capacity {
+            if let Some(oldest) = self.pending_order.pop_front() {
+                self.pending.remove(&oldest);
+            }
+        }
+    }
+
+    fn apply_feedback(&mut self, message_id: Uuid, signal: FeedbackSignal) -> Result<(), RlhfError> {
+        let reward = signal.reward()?;
+        let decision = &self.decisions[&message_id];
+        let agent = self
+            .agents
+            .get_mut(&decision.agent_key)
+            .ok_or_else(|| RlhfError::UnknownAgent(decision.agent_key.clone()))?;
+        agent.add_experience(decision.state, decision.action, reward, decision.next_state);
+        Ok(())
+    }
+}
+
+impl Default for Rlhf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::q_learning_agent::QLearningConfig;
+
+    fn rlhf() -> Rlhf {
+        let config = QLearningConfig {
+            batch_size: 1,
+            ..QLearningConfig::new(3, 2)
+        };
+        let mut rlhf = Rlhf::new();
+        rlhf.add_agent("text", QLearningAgent::with_seed(config, 42));
+        rlhf
+    }
+
+    fn decision(state: usize, action: usize) -> MessageDecision {
+        MessageDecision {
+            agent_key: "text".to_string(),
+            state,
+            action,
+            next_state: 2,
+        }
+    }
+
+    fn q_value(rlhf: &Rlhf, state: usize, action: usize) -> f32 {
+        rlhf.agent("text").unwrap().action_values(state)[action]
+    }
+
+    #[test]
+    fn test_positive_feedback_raises_q_value() {
+        let mut rlhf = rlhf();
+        let message_id = Uuid::new_v4();
+        rlhf.record_decision(message_id, decision(0, 1)).unwrap();
+        let before = q_value(&rlhf, 0, 1);
+
+        assert_eq!(rlhf.record_feedback(message_id, FeedbackSignal::ThumbsUp), Ok(true));
+        rlhf.update();
+
+        assert!(q_value(&rlhf, 0, 1) > before);
+    }
+
+    #[test]
+    fn test_feedback_before_its_decision_is_applied_later() {
+        let mut rlhf = rlhf();
+        let message_id = Uuid::new_v4();
+        let applied = rlhf.record_feedback_batch([
+            (message_id, FeedbackSignal::Rating { value: 0.0, max: 5.0 }),
+            (message_id, FeedbackSignal::Rating { value: 7.0, max: 5.0 }),
+        ]);
+        assert_eq!(applied, 0);
+        let before = q_value(&rlhf, 1, 0);
+
+        assert_eq!(rlhf.record_decision(message_id, decision(1, 0)), Ok(1));
+        rlhf.update();
+        assert!(q_value(&rlhf, 1, 0) < before);
+    }
+
+    #[test]
+    fn test_pending_feedback_is_capped() {
+        let mut rlhf = rlhf().with_decision_capacity(2);
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            assert_eq!(rlhf.record_feedback(*id, FeedbackSignal::ThumbsUp), Ok(false));
+        }
+        assert_eq!(rlhf.pending.len(), 2);
+
+        // The first message's feedback was dropped to make room for the third's
+        assert_eq!(rlhf.record_decision(ids[0], decision(0, 0)), Ok(0));
+        assert_eq!(rlhf.record_decision(ids[2], decision(0, 1)), Ok(1));
+        assert_eq!(rlhf.pending_order.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_decisions_and_ratings_are_rejected() {
+        let mut rlhf = rlhf();
+        let message_id = Uuid::new_v4();
+        assert!(matches!(rlhf.record_decision(message_id, decision(5, 0)), Err(RlhfError::InvalidDecision { .. })));
+        let unknown = MessageDecision {
+            agent_key: "audio".to_string(),
+            ..decision(0, 0)
+        };
+        assert_eq!(rlhf.record_decision(message_id, unknown), Err(RlhfError::UnknownAgent("audio".to_string())));
+        assert_eq!(
+            rlhf.record_feedback(message_id, FeedbackSignal::Rating { value: 1.0, max: 0.0 }),
+            Err(RlhfError::InvalidRating { value: 1.0, max: 0.0 })
+        );
+        assert_eq!(FeedbackSignal::Rating { value: 2.5, max: 5.0 }.reward(), Ok(0.0));
+        assert!((FeedbackSignal::Rating { value: 4.0, max: 5.0 }.reward().unwrap() - 0.6).abs() < 1e-6);
+    }
+}
@@ -8,6 +8,7 @@ utterance_to_query_mapping function: This function serves as the entry point for
 generate_query_from_mapping function: This function constructs a GraphQL query string based on the QueryMapping instance obtained from the user's utterance. It leverages the construct_query_string function to build the query by incorporating the mapped entities and slots. The generated query is then sent to a GraphQL endpoint using the send_query function, which handles the communication with the knowledge graph and retrieves the relevant chart information.
 prepare_data_for_chart function: Once the suggested chart type and associated data fields are obtained from the GraphQL response, this function prepares the data for visualization. It utilizes the DataBin and ChartPlot structs to organize and structure the data according to the requirements of the selected chart type. The prepared data is then ready to be passed to the charting library for rendering.
 bokeh_bindings::plot_figure function: This function serves as the interface to the Bokeh charting library. It receives the prepared data, suggested chart type, and chart configuration settings, and generates the corresponding chart using Bokeh's plotting capabilities. The resulting chart provides a visual representation of the data based on the user's expressed preferences and the underlying data schema.
+Visualiser::suggest_chart: The single entry point for consumers. It runs the (cached) NLU and query mapping for an utterance, infers the chart type from the CHART_TYPE entity or the utterance's wording, falling back to the best drawable suggestion from charts::suggest_chart_types for the data, prepares one numeric series per mentioned data field and returns a ChartSpec. Utterances that name no drawable chart type, over data no drawable chart type suits, fail with BigbotError::ChartTypeNotInferred.
 The main flow of the module involves the following steps:
 
 Accepting a user utterance expressing the desired chart type and data fields.
@@ -18,19 +19,21 @@ Configuring the chart's appearance and plotting it using the Bokeh library.
 By combining NLP, knowledge graphs, and data visualization techniques, this module provides a powerful and flexible framework for automating the creation and suggestion of relevant charts based on user inputs and data schemas. It streamlines the process of translating user requirements into meaningful visual representations, enhancing data exploration and decision-making capabilities.
 */
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
 use reqwest::blocking::Client;
 use serde_json::Value;
 use pyo3::Python;
 
-use crate::bindings::bokeh_charts::prepare_data_for_chart;
 use crate::bindings::bokeh_bindings::{DataBin, ChartConfig, plot_figure, find_group, group_by, ticker, group_commons, array_count, array_sum, array_average, linear_scale_mixin};
-use crate::bindings::spacy_bindings::{BigbotError, Doc, SpacyModule, TokenPos};
+use crate::bindings::spacy_bindings::{Doc, SpacyModule, TokenPos};
+use crate::provider_types::charts::{self, ChartSpec};
+use crate::utils::bigboterror::BigbotError;
 
 
 // To integrate with charting functionality
 // Define a struct to hold the mappings of entities and slots identified in an utterance
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 struct QueryMapping {
     entity_map: HashMap<String, String>, // Maps recognized entities to their values
     slot_map: HashMap<String, String>,   // Maps identified slots to their values
@@ -78,6 +81,259 @@ fn utterance_to_query_mapping(utterance: &str) -> Result<QueryMapping, BigbotErr
     Ok(mapping)
 }
 
+// Default number of utterances kept by the query mapping cache
+const DEFAULT_QUERY_MAPPING_CACHE_CAPACITY: usize = 256;
+
+// LRU cache of QueryMappings keyed on the normalized utterance, so repeated chart requests
+// skip the spaCy round trip. Entries are tied to the entity mapping they were built with.
+struct QueryMappingCache {
+    inner: Mutex<QueryMappingCacheInner>,
+}
+
+struct QueryMappingCacheInner {
+    capacity: usize,
+    entries: HashMap<String, QueryMapping>,
+    recency: VecDeque<String>, // Front is least recently used
+    entity_mapping_fingerprint: Vec<(String, String)>,
+}
+
+impl QueryMappingCache {
+    // Constructor for a cache holding at most `capacity` utterances
+    fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(QueryMappingCacheInner {
+                capacity: capacity.max(1),
+                entries: HashMap::new(),
+                recency: VecDeque::new(),
+                entity_mapping_fingerprint: entity_mapping_fingerprint(&get_entity_mapping()),
+            }),
+        }
+    }
+
+    // Returns the cached mapping for the utterance, or computes and caches it with `compute`
+    fn get_or_compute<F>(&self, utterance: &str, compute: F) -> Result<QueryMapping, BigbotError>
+    where
+        F: FnOnce(&str) -> Result<QueryMapping, BigbotError>,
+    {
+        let key = normalize_utterance(utterance);
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(mapping) = inner.entries.get(&key).cloned() {
+                inner.touch(&key);
+                return Ok(mapping);
+            }
+        }
+
+        // Compute outside the lock so a slow NLP call doesn't block other lookups
+        let mapping = compute(utterance)?;
+        self.inner.lock().unwrap().insert(key, mapping.clone());
+        Ok(mapping)
+    }
+
+    // Invalidation hook for entity mapping config changes; drops every entry if the mapping differs
+    fn on_entity_mapping_changed(&self, entity_mapping: &HashMap<&str, &str>) {
+        let fingerprint = entity_mapping_fingerprint(entity_mapping);
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entity_mapping_fingerprint != fingerprint {
+            inner.entity_mapping_fingerprint = fingerprint;
+            inner.clear();
+        }
+    }
+
+    // Drops every cached entry
+    fn invalidate(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+
+    // Returns the number of cached utterances
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+}
+
+impl QueryMappingCacheInner {
+    // Marks a key as most recently used
+    fn touch(&mut self, key: &str) {
+        if let Some(pos) = self.recency.iter().position(|k| k == key) {
+            if let Some(k) = self.recency.remove(pos) {
+                self.recency.push_back(k);
+            }
+        }
+    }
+
+    // Inserts an entry, evicting the least recently used one when full
+    fn insert(&mut self, key: String, mapping: QueryMapping) {
+        if self.entries.insert(key.clone(), mapping).is_some() {
+            self.touch(&key);
+            return;
+        }
+        self.recency.push_back(key);
+        while self.entries.len() > self.capacity {
+            match self.recency.pop_front() {
+                Some(oldest) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+// Normalizes an utterance so trivially different phrasings share a cache entry:
+// lowercased, whitespace collapsed and trailing punctuation removed
+fn normalize_utterance(utterance: &str) -> String {
+    utterance
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(|c: char| c.is_ascii_punctuation())
+        .to_lowercase()
+}
+
+// Produces an order-independent fingerprint of an entity mapping
+fn entity_mapping_fingerprint(entity_mapping: &HashMap<&str, &str>) -> Vec<(String, String)> {
+    let mut fingerprint: Vec<(String, String)> = entity_mapping
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    fingerprint.sort();
+    fingerprint
+}
+
+// Converts an utterance into a QueryMapping, reusing the cached mapping when available
+fn cached_utterance_to_query_mapping(cache: &QueryMappingCache, utterance: &str) -> Result<QueryMapping, BigbotError> {
+    cache.get_or_compute(utterance, utterance_to_query_mapping)
+}
+
+// Suggests charts for utterances in one call: NLU, query mapping, chart preparation and spec.
+// Query mappings are cached, so repeating an utterance skips the spaCy round trip.
+pub struct Visualiser {
+    query_mappings: QueryMappingCache,
+}
+
+impl Visualiser {
+    pub fn new() -> Self {
+        Self {
+            query_mappings: QueryMappingCache::new(DEFAULT_QUERY_MAPPING_CACHE_CAPACITY),
+        }
+    }
+
+    // Builds the chart the utterance asks for from `data_bin`, or the chart best suited to the data
+    // if the utterance names none. Fails with `ChartTypeNotInferred` if neither gives a chart type
+    // we can draw.
+    pub fn suggest_chart(&self, utterance: &str, data_bin: &DataBin) -> Result<ChartSpec, BigbotError> {
+        let mapping = cached_utterance_to_query_mapping(&self.query_mappings, utterance)?;
+        chart_spec_from_mapping(&mapping, utterance, data_bin)
+    }
+}
+
+impl Default for Visualiser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Turns an utterance's query mapping into a chart spec over `data_bin`
+fn chart_spec_from_mapping(mapping: &QueryMapping, utterance: &str, data_bin: &DataBin) -> Result<ChartSpec, BigbotError> {
+    let chart_type = infer_chart_type(mapping, utterance)
+        .or_else(|| suggested_chart_type(data_bin))
+        .ok_or_else(|| BigbotError::ChartTypeNotInferred(utterance.to_string()))?;
+    let series = prepare_chart_series(mapping, data_bin);
+    if series.is_empty() {
+        return Err(BigbotError::InvalidInput(format!("No numeric fields to plot a {} chart from", chart_type)));
+    }
+    Ok(ChartSpec::with_default_config(chart_type, series))
+}
+
+// The chart type from the CHART_TYPE entity if spaCy found one, otherwise from the first chart
+// type token in the utterance
+fn infer_chart_type(mapping: &QueryMapping, utterance: &str) -> Option<&'static str> {
+    let entity = mapping.entity_map.get("chartType").map(String::as_str).unwrap_or_default();
+    entity
+        .split(|c: char| !c.is_alphanumeric())
+        .find_map(chart_type_for_word)
+        .or_else(|| chart_type_in_utterance(utterance))
+}
+
+// Words like "line" or "area" only name a chart type when followed by a chart noun, as in
+// "line chart" or "area graph"; "heatmap" is one on its own
+fn chart_type_in_utterance(utterance: &str) -> Option<&'static str> {
+    let words: Vec<String> = utterance
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    words.iter().enumerate().find_map(|(i, word)| {
+        let names_chart = word == "heatmap"
+            || words.get(i + 1).map_or(false, |next| {
+                matches!(next.as_str(), "chart" | "charts" | "graph" | "graphs" | "plot" | "plots" | "diagram")
+            });
+        if names_chart {
+            chart_type_for_word(word)
+        } else {
+            None
+        }
+    })
+}
+
+// The best-scoring chart type suggested for the data that chart specs can draw
+fn suggested_chart_type(data_bin: &DataBin) -> Option<&'static str> {
+    let data_bin = charts::DataBin::new(data_bin.data.clone(), data_bin.fields.clone());
+    charts::suggest_chart_types(&data_bin)
+        .iter()
+        .find_map(|suggestion| chart_type_for_word(&suggestion.chart_type))
+}
+
+// Maps a word onto one of the chart types the chart specs support
+fn chart_type_for_word(word: &str) -> Option<&'static str> {
+    match word.to_lowercase().as_str() {
+        "line" | "lines" => Some("line"),
+        "bar" | "bars" | "column" | "columns" => Some("bar"),
+        "area" => Some("area"),
+        "scatter" => Some("scatter"),
+        "bubble" => Some("bubble"),
+        "pie" => Some("pie"),
+        "donut" | "doughnut" => Some("donut"),
+        "heatmap" => Some("heatmap"),
+        _ => None,
+    }
+}
+
+// One series per numeric field the utterance mentions, or per numeric field if it mentions none
+fn prepare_chart_series(mapping: &QueryMapping, data_bin: &DataBin) -> HashMap<String, Vec<f64>> {
+    let numeric_fields: Vec<&String> = data_bin
+        .fields
+        .iter()
+        .filter(|field| {
+            // With no rows there is nothing to show a field is numeric
+            !data_bin.data.is_empty()
+                && data_bin
+                    .data
+                    .iter()
+                    .all(|item| item.get(*field).map_or(false, |value| value.trim().parse::<f64>().is_ok()))
+        })
+        .collect();
+    let mentioned: Vec<&String> = numeric_fields
+        .iter()
+        .copied()
+        .filter(|field| mapping.slot_map.contains_key(&field.to_lowercase()))
+        .collect();
+    let fields = if mentioned.is_empty() { numeric_fields } else { mentioned };
+
+    fields
+        .into_iter()
+        .map(|field| {
+            let values = data_bin.data.iter().map(|item| item[field].trim().parse().unwrap_or(0.0)).collect();
+            (field.clone(), values)
+        })
+        .collect()
+}
+
 // Returns a mapping of spaCy entity labels to GraphQL query fields
 fn get_entity_mapping() -> HashMap<&'static str, &'static str> {
     [("CHART_TYPE", "chartType"), ("DATA_FIELD", "dataField")]
@@ -118,45 +374,200 @@ fn generate_query_from_mapping(mapping: &QueryMapping) -> Result<String, Box<dyn
     Ok(response)
 }
 
-// Constructs the GraphQL query string from the entity and slot mappings
-fn construct_query_string(mapping: &QueryMapping) -> String {
-    let mut query = String::from("query { ");
+// Top-level field queried when the mapping has no entities to attach the slot filter to
+const DEFAULT_QUERY_FIELD: &str = "charts";
 
-    // Construct query parts for entities
-    construct_entity_queries(&mut query, mapping);
+// A GraphQL name (/[_A-Za-z][_0-9A-Za-z]*/). Names can't be escaped, so anything else is refused.
+#[derive(Debug, Clone, PartialEq)]
+struct GraphQLName(String);
 
-    // Construct query parts for slots
-    construct_slot_queries(&mut query, mapping);
+impl GraphQLName {
+    fn new(name: &str) -> Option<Self> {
+        let mut chars = name.chars();
+        let valid_start = chars.next().map_or(false, |c| c == '_' || c.is_ascii_alphabetic());
+        (valid_start && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())).then(|| Self(name.to_string()))
+    }
+}
 
-    query.push('}');
-    query
+// An argument value. Strings are escaped when the query is written.
+#[derive(Debug, Clone, PartialEq)]
+enum GraphQLValue {
+    String(String),
+    Object(Vec<(GraphQLName, GraphQLValue)>),
 }
 
-// Adds query parts for each entity in the mapping to the query string
-fn construct_entity_queries(query: &mut String, mapping: &QueryMapping) {
-    for (entity_field, entity_value) in &mapping.entity_map {
-        query.push_str(&format!("{}(name: \"{}\") {{ id name {} }} ", entity_field, entity_value, entity_field));
+impl GraphQLValue {
+    fn write(&self, out: &mut String) {
+        match self {
+            GraphQLValue::String(value) => write_graphql_string(out, value),
+            GraphQLValue::Object(entries) => {
+                out.push('{');
+                for (i, (name, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    out.push_str(&name.0);
+                    out.push_str(": ");
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+// A selected field with its arguments and sub-selections
+#[derive(Debug, Clone, PartialEq)]
+struct GraphQLField {
+    name: GraphQLName,
+    arguments: Vec<(GraphQLName, GraphQLValue)>,
+    selections: Vec<GraphQLField>,
+}
+
+impl GraphQLField {
+    fn new(name: GraphQLName) -> Self {
+        Self {
+            name,
+            arguments: Vec::new(),
+            selections: Vec::new(),
+        }
+    }
+
+    fn argument(mut self, name: GraphQLName, value: GraphQLValue) -> Self {
+        self.arguments.push((name, value));
+        self
+    }
+
+    fn select(mut self, field: GraphQLField) -> Self {
+        self.selections.push(field);
+        self
+    }
+
+    fn write(&self, out: &mut String) {
+        out.push_str(&self.name.0);
+        if !self.arguments.is_empty() {
+            out.push('(');
+            for (i, (name, value)) in self.arguments.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&name.0);
+                out.push_str(": ");
+                value.write(out);
+            }
+            out.push(')');
+        }
+        write_selection_set(out, &self.selections);
+    }
+}
+
+// An anonymous query operation
+#[derive(Debug, Clone, Default, PartialEq)]
+struct GraphQLQuery {
+    fields: Vec<GraphQLField>,
+}
+
+impl GraphQLQuery {
+    fn field(mut self, field: GraphQLField) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    fn build(&self) -> String {
+        let mut query = String::from("query");
+        write_selection_set(&mut query, &self.fields);
+        query
+    }
+}
+
+fn write_selection_set(out: &mut String, fields: &[GraphQLField]) {
+    if fields.is_empty() {
+        return;
+    }
+    out.push_str(" { ");
+    for field in fields {
+        field.write(out);
+        out.push(' ');
     }
+    out.push('}');
 }
 
-// Adds query parts for each slot in the mapping to the query string
-fn construct_slot_queries(query: &mut String, mapping: &QueryMapping) {
-    // Iterate over each slot in the slot_map
-    for (slot_name, slot_value) in &mapping.slot_map {
-        // Assume slots are additional filters or query parameters for entities
-        // This part may need adjustments based on the specific GraphQL schema and requirements
-        // Check if the slot corresponds to any entity in the entity_map to avoid redundancy
-        if !mapping.entity_map.contains_key(slot_name) {
-            // Here we are adding the slot as a filter to the query.
-            // Adjust the query structure as needed based on your GraphQL schema.
-            // For example, this could be a generic filter applied to a specific entity type
-            // or a way to add additional fields to the query based on the slot's context.
-            query.push_str(&format!(
-                "{{ filter: {{ {} : {{ eq: \"{}\" }} }} }} ",
-                slot_name, slot_value
-            ));
+// Writes a quoted GraphQL string literal, escaping quotes, backslashes and control characters
+fn write_graphql_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
         }
     }
+    out.push('"');
+}
+
+// Builds the GraphQL query for a mapping: one field per entity, each filtered by the mapping's
+// slots. Entities and slots are sorted so a mapping always produces the same query, and slots
+// whose names aren't valid GraphQL names are left out since no schema field could match them.
+fn construct_query_string(mapping: &QueryMapping) -> String {
+    let filter = slot_filter(mapping);
+    let mut entities: Vec<(&String, &String)> = mapping.entity_map.iter().collect();
+    entities.sort();
+
+    let mut query = GraphQLQuery::default();
+    for (entity_field, entity_value) in entities {
+        let Some(field_name) = GraphQLName::new(entity_field) else {
+            continue;
+        };
+        let mut field = GraphQLField::new(field_name.clone())
+            .argument(GraphQLName("name".to_string()), GraphQLValue::String(entity_value.clone()));
+        if let Some(filter) = &filter {
+            field = field.argument(GraphQLName("filter".to_string()), filter.clone());
+        }
+        query = query.field(with_default_selections(field).select(GraphQLField::new(field_name)));
+    }
+
+    if query.fields.is_empty() {
+        let mut field = GraphQLField::new(GraphQLName(DEFAULT_QUERY_FIELD.to_string()));
+        if let Some(filter) = filter {
+            field = field.argument(GraphQLName("filter".to_string()), filter);
+        }
+        query = query.field(with_default_selections(field));
+    }
+    query.build()
+}
+
+fn with_default_selections(field: GraphQLField) -> GraphQLField {
+    field
+        .select(GraphQLField::new(GraphQLName("id".to_string())))
+        .select(GraphQLField::new(GraphQLName("name".to_string())))
+}
+
+// `{slot: {eq: "value"}, ...}` for every slot that isn't also an entity, or None if there are none
+fn slot_filter(mapping: &QueryMapping) -> Option<GraphQLValue> {
+    let mut slots: Vec<(&String, &String)> = mapping
+        .slot_map
+        .iter()
+        .filter(|(slot_name, _)| !mapping.entity_map.contains_key(*slot_name))
+        .collect();
+    slots.sort();
+
+    let conditions: Vec<(GraphQLName, GraphQLValue)> = slots
+        .into_iter()
+        .filter_map(|(slot_name, slot_value)| {
+            let condition = GraphQLValue::Object(vec![(
+                GraphQLName("eq".to_string()),
+                GraphQLValue::String(slot_value.clone()),
+            )]);
+            GraphQLName::new(slot_name).map(|name| (name, condition))
+        })
+        .collect();
+    (!conditions.is_empty()).then(|| GraphQLValue::Object(conditions))
 }
 
 // Sends the constructed GraphQL query to the specified endpoint and returns the response
@@ -192,30 +603,35 @@ fn main() {
         ]),
     ];
     let fields = vec!["category".to_string(), "value1".to_string(), "value2".to_string()];
-    let mut data_bin = DataBin::new(data, fields);
+    let data_bin = DataBin::new(data, fields);
 
     let utterance = "Show me a line chart for value1 and value2"; // Example utterance
-    let mapping = utterance_to_query_mapping(utterance); // Convert utterance to QueryMapping
+    let mapping = match utterance_to_query_mapping(utterance) { // Convert utterance to QueryMapping
+        Ok(mapping) => mapping,
+        Err(e) => {
+            eprintln!("Error processing utterance: {}", e);
+            return;
+        }
+    };
 
     match generate_query_from_mapping(&mapping) { // Generate and send the query, then handle the response
-        Ok(query) => {
-            println!("Query: {}", query);
-            // Parse the response and extract the suggested chart type and data fields
-            let chart_type = mapping.entity_map.get("chartType").cloned().unwrap_or_default();
-            let data_fields: Vec<String> = mapping.slot_map.values().cloned().collect();
+        Ok(query) => println!("Query: {}", query),
+        Err(e) => eprintln!("Error generating query: {}", e),
+    }
 
-            // Prepare the data for the suggested chart type
-            let chart_data = prepare_data_for_chart(&data_bin, &chart_type);
+    // Build the chart spec for the utterance and plot it using Bokeh
+    match chart_spec_from_mapping(&mapping, utterance, &data_bin) {
+        Ok(chart_spec) => {
+            println!("Chart spec: {:?}", chart_spec);
 
             // Create the chart configuration
             let mut chart_config = ChartConfig::new();
-            chart_config.set_plot_width(800);
-            chart_config.set_plot_height(600);
+            chart_config.set_plot_width(chart_spec.width);
+            chart_config.set_plot_height(chart_spec.height);
 
-            // Plot the chart using Bokeh
-            plot_figure(&data_bin, &chart_type, &chart_config);
+            plot_figure(&data_bin, &chart_spec.chart_type, &chart_config);
         }
-        Err(e) => eprintln!("Error generating query: {}", e),
+        Err(e) => eprintln!("Error suggesting chart: {}", e),
     }
 
     // Example usage of find_group
@@ -252,4 +668,190 @@ fn main() {
     let array = vec![1, 2, 3, 4, 5];
     let scaled_array = linear_scale_mixin(&array, 2, false, 10);
     println!("Scaled Array: {:?}", scaled_array);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn mocked_mapping(utterance: &str) -> QueryMapping {
+        let mut mapping = QueryMapping::new();
+        mapping.add_entity("chartType".to_string(), "line".to_string());
+        mapping.add_slot("utterance".to_string(), utterance.to_string());
+        mapping
+    }
+
+    fn sample_data_bin() -> DataBin {
+        let data = [("A", "10", "20"), ("B", "15", "25")]
+            .iter()
+            .map(|(category, value1, value2)| {
+                HashMap::from([
+                    ("category".to_string(), category.to_string()),
+                    ("value1".to_string(), value1.to_string()),
+                    ("value2".to_string(), value2.to_string()),
+                ])
+            })
+            .collect();
+        DataBin::new(data, vec!["category".to_string(), "value1".to_string(), "value2".to_string()])
+    }
+
+    #[test]
+    fn test_chart_spec_from_mapping() {
+        let data_bin = sample_data_bin();
+
+        let mut mapping = QueryMapping::new();
+        mapping.add_entity("chartType".to_string(), "Bar chart".to_string());
+        mapping.add_slot("value2".to_string(), "value2".to_string());
+        let spec = chart_spec_from_mapping(&mapping, "compare value2 across categories", &data_bin).unwrap();
+        assert_eq!(spec.chart_type, "bar");
+        assert_eq!(spec.series.len(), 1);
+        assert_eq!(spec.series[0].name, "value2");
+        assert_eq!(spec.series[0].values, vec![20.0, 25.0]);
+
+        // Without a CHART_TYPE entity the utterance itself is searched, and with no fields
+        // mentioned every numeric field is plotted
+        let spec = chart_spec_from_mapping(&QueryMapping::new(), "Show me a line chart", &data_bin).unwrap();
+        assert_eq!(spec.chart_type, "line");
+        let names: Vec<&str> = spec.series.iter().map(|series| series.name.as_str()).collect();
+        assert_eq!(names, vec!["value1", "value2"]);
+    }
+
+    #[test]
+    fn test_unnamed_chart_type_falls_back_to_suggestion() {
+        // One category with two values and numeric fields suit a bar chart best
+        let spec = chart_spec_from_mapping(&QueryMapping::new(), "show me value1", &sample_data_bin()).unwrap();
+        assert_eq!(spec.chart_type, "bar");
+    }
+
+    #[test]
+    fn test_only_chart_type_tokens_name_a_chart() {
+        let mapping = QueryMapping::new();
+        assert_eq!(infer_chart_type(&mapping, "Plot sales as a pie chart"), Some("pie"));
+        assert_eq!(infer_chart_type(&mapping, "show the heatmap"), Some("heatmap"));
+        // "area" and "line" here are ordinary words, not chart types
+        assert_eq!(infer_chart_type(&mapping, "sales by area, line by line"), None);
+    }
+
+    #[test]
+    fn test_empty_data_has_no_numeric_fields() {
+        let empty = DataBin::new(Vec::new(), vec!["category".to_string(), "value1".to_string()]);
+        assert!(prepare_chart_series(&QueryMapping::new(), &empty).is_empty());
+        let result = chart_spec_from_mapping(&QueryMapping::new(), "Show me a line chart", &empty);
+        assert!(matches!(result, Err(BigbotError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_uninferable_chart_type_is_an_error() {
+        let labels = DataBin::new(
+            vec![HashMap::from([("category".to_string(), "A".to_string())])],
+            vec!["category".to_string()],
+        );
+        let result = chart_spec_from_mapping(&QueryMapping::new(), "show me categories", &labels);
+        assert!(matches!(result, Err(BigbotError::ChartTypeNotInferred(utterance)) if utterance == "show me categories"));
+    }
+
+    fn parse_fields(query: &str) -> usize {
+        let document = async_graphql::parser::parse_query(query).unwrap_or_else(|e| panic!("{} in {}", e, query));
+        match document.operations {
+            async_graphql::parser::types::DocumentOperations::Single(operation) => operation.node.selection_set.node.items.len(),
+            _ => panic!("expected a single operation in {}", query),
+        }
+    }
+
+    #[test]
+    fn test_generated_queries_parse() {
+        let mut mapping = QueryMapping::new();
+        mapping.add_entity("chartType".to_string(), "line".to_string());
+        mapping.add_entity("dataField".to_string(), "sales".to_string());
+        mapping.add_slot("region".to_string(), "North".to_string());
+        mapping.add_slot("year".to_string(), "2024".to_string());
+        mapping.add_slot("not-a-name".to_string(), "dropped".to_string());
+
+        let query = construct_query_string(&mapping);
+        assert_eq!(
+            query,
+            "query { chartType(name: \"line\", filter: {region: {eq: \"North\"}, year: {eq: \"2024\"}}) { id name chartType } \
+             dataField(name: \"sales\", filter: {region: {eq: \"North\"}, year: {eq: \"2024\"}}) { id name dataField } }"
+        );
+        assert_eq!(parse_fields(&query), 2);
+
+        // With no entities the filter goes on the default field
+        let mut slots_only = QueryMapping::new();
+        slots_only.add_slot("region".to_string(), "North".to_string());
+        assert_eq!(parse_fields(&construct_query_string(&slots_only)), 1);
+        assert_eq!(parse_fields(&construct_query_string(&QueryMapping::new())), 1);
+    }
+
+    #[test]
+    fn test_values_are_escaped() {
+        let mut mapping = QueryMapping::new();
+        mapping.add_entity("chartType".to_string(), "say \"hi\" \\ bye\n".to_string());
+        mapping.add_slot("region".to_string(), "\"}) { secret } #".to_string());
+
+        let query = construct_query_string(&mapping);
+        assert!(query.contains(r#"name: "say \"hi\" \\ bye\n""#), "{}", query);
+        assert!(query.contains(r#"eq: "\"}) { secret } #""#), "{}", query);
+        assert_eq!(parse_fields(&query), 1);
+    }
+
+    #[test]
+    fn test_identical_utterance_hits_cache() {
+        let cache = QueryMappingCache::new(DEFAULT_QUERY_MAPPING_CACHE_CAPACITY);
+        let calls = Cell::new(0);
+        let mock_nlp = |utterance: &str| {
+            calls.set(calls.get() + 1);
+            Ok(mocked_mapping(utterance))
+        };
+
+        let first = cache.get_or_compute("Show me a line chart for value1", mock_nlp).unwrap();
+        let second = cache.get_or_compute("Show me a line chart for value1", mock_nlp).unwrap();
+        let normalized = cache.get_or_compute("  show me a LINE chart for value1? ", mock_nlp).unwrap();
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(first, second);
+        assert_eq!(first, normalized);
+    }
+
+    #[test]
+    fn test_least_recently_used_entry_is_evicted() {
+        let cache = QueryMappingCache::new(2);
+        let calls = Cell::new(0);
+        let mock_nlp = |utterance: &str| {
+            calls.set(calls.get() + 1);
+            Ok(mocked_mapping(utterance))
+        };
+
+        cache.get_or_compute("a", mock_nlp).unwrap();
+        cache.get_or_compute("b", mock_nlp).unwrap();
+        cache.get_or_compute("a", mock_nlp).unwrap();
+        cache.get_or_compute("c", mock_nlp).unwrap();
+        assert_eq!(cache.len(), 2);
+
+        cache.get_or_compute("a", mock_nlp).unwrap();
+        assert_eq!(calls.get(), 3);
+        cache.get_or_compute("b", mock_nlp).unwrap();
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn test_entity_mapping_change_invalidates_cache() {
+        let cache = QueryMappingCache::new(DEFAULT_QUERY_MAPPING_CACHE_CAPACITY);
+        let calls = Cell::new(0);
+        let mock_nlp = |utterance: &str| {
+            calls.set(calls.get() + 1);
+            Ok(mocked_mapping(utterance))
+        };
+
+        cache.get_or_compute("bar chart of sales", mock_nlp).unwrap();
+        cache.on_entity_mapping_changed(&get_entity_mapping());
+        cache.get_or_compute("bar chart of sales", mock_nlp).unwrap();
+        assert_eq!(calls.get(), 1);
+
+        let mut updated = get_entity_mapping();
+        updated.insert("AGGREGATION", "aggregation");
+        cache.on_entity_mapping_changed(&updated);
+        cache.get_or_compute("bar chart of sales", mock_nlp).unwrap();
+        assert_eq!(calls.get(), 2);
+    }
+}
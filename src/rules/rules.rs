@@ -119,12 +119,14 @@ pub enum BlockResult {
 // Registry for dynamic block management
 pub struct BlockRegistry {
     blocks: HashMap<BlockType, Box<dyn BlockTrait>>,
+    default_handler: Option<Box<dyn BlockTrait>>,
 }
 
 impl BlockRegistry {
     pub fn new() -> Self {
         Self {
             blocks: HashMap::new(),
+            default_handler: None,
         }
     }
 
@@ -132,17 +134,95 @@ impl BlockRegistry {
         self.blocks.insert(block_type, Box::new(block));
     }
 
+    // Processes blocks whose type has nothing registered, e.g. to log and skip them with
+    // `BlockResult::Accept(None)`. Without one such blocks fail with `BlockTypeNotFound`.
+    pub fn set_default_handler<B: BlockTrait + 'static>(&mut self, handler: B) {
+        self.default_handler = Some(Box::new(handler));
+    }
+
     pub async fn process_block(
         &self,
         block_type: &BlockType,
         state: &mut ChannelState,
         input: &Input,
     ) -> Result<BlockResult, BlockError> {
-        if let Some(block) = self.blocks.get(block_type) {
-            block.process(state, input).await.map_err(|e| BlockError::ProcessingError(e))
-        } else {
-            Err(BlockError::BlockTypeNotFound)
+        let block = self
+            .blocks
+            .get(block_type)
+            .or(self.default_handler.as_ref())
+            .ok_or(BlockError::BlockTypeNotFound)?;
+        block.process(state, input).await.map_err(|e| BlockError::ProcessingError(e))
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SkillError {
+    #[error("malformed skill: {0}")]
+    Malformed(String),
+    #[error("duplicate block id: {0}")]
+    DuplicateBlockId(String),
+    #[error("start block {0} does not exist")]
+    UnknownStartBlock(String),
+    #[error("block {block_id} has unknown type {block_type}")]
+    UnknownBlockType { block_id: String, block_type: String },
+}
+
+// A block within a skill, as authored in the skill JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SkillBlock {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub block_type: String,
+    #[serde(default)]
+    pub properties: JsonValue,
+}
+
+impl SkillBlock {
+    // Resolves the authored type name, None if it isn't a known BlockType
+    pub fn block_type(&self) -> Option<BlockType> {
+        BlockType::from_str(&self.block_type)
+    }
+}
+
+// Typed representation of a skill: a set of blocks and the block execution starts from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Skill {
+    pub id: String,
+    pub start: String,
+    pub blocks: Vec<SkillBlock>,
+}
+
+impl Skill {
+    // Parses and validates a skill from its JSON representation
+    pub fn from_json(skill_json: JsonValue) -> Result<Skill, SkillError> {
+        let skill: Skill =
+            serde_json::from_value(skill_json).map_err(|e| SkillError::Malformed(e.to_string()))?;
+        skill.validate()?;
+        Ok(skill)
+    }
+
+    // Ensures block ids are unique, `start` references a real block and every block type is known
+    pub fn validate(&self) -> Result<(), SkillError> {
+        let mut seen = std::collections::HashSet::new();
+        for block in &self.blocks {
+            if !seen.insert(block.id.as_str()) {
+                return Err(SkillError::DuplicateBlockId(block.id.clone()));
+            }
+            if block.block_type().is_none() {
+                return Err(SkillError::UnknownBlockType {
+                    block_id: block.id.clone(),
+                    block_type: block.block_type.clone(),
+                });
+            }
+        }
+        if !seen.contains(self.start.as_str()) {
+            return Err(SkillError::UnknownStartBlock(self.start.clone()));
         }
+        Ok(())
+    }
+
+    pub fn block(&self, block_id: &str) -> Option<&SkillBlock> {
+        self.blocks.iter().find(|b| b.id == block_id)
     }
 }
 
@@ -325,32 +405,37 @@ impl SkillExecutor {
 
     async fn execute_skill(
         &self,
-        skill_json: JsonValue,
+        skill: &Skill,
         state: &mut ChannelState,
         input: &Input,
     ) -> Result<(), String> {
-        let blocks = skill_json["blocks"].as_array().unwrap();
-        let start_block_id = skill_json["start"].as_str().unwrap();
-        let mut current_block_id = start_block_id.to_string();
-
-        while let Some(block_json) = blocks.iter().find(|b| b["id"] == current_block_id) {
-            let block_type = BlockType::from_str(block_json["type"].as_str().unwrap()).unwrap();
-            let result = self.process_block(&block_type, state, input).await?;
+        let mut current_block_id = skill.start.clone();
+
+        loop {
+            let block = skill
+                .block(&current_block_id)
+                .ok_or_else(|| format!("skill {} has no block {}", skill.id, current_block_id))?;
+            let block_type = block
+                .block_type()
+                .ok_or_else(|| format!("block {} has unknown type {}", block.id, block.block_type))?;
+            let result = self
+                .process_block(&block_type, state, input)
+                .await
+                .map_err(|e| e.to_string())?;
 
             match result {
-                BlockResult::Accept(connection) => {
-                    current_block_id = connection.unwrap_or_default();
+                // Accepting without a connection ends the skill
+                BlockResult::Accept(Some(connection)) => {
+                    current_block_id = connection;
+                }
+                BlockResult::Accept(None) | BlockResult::Finish => {
+                    return Ok(());
                 }
                 BlockResult::Reject => {
                     return Err("Skill execution rejected".to_string());
                 }
-                BlockResult::Finish => {
-                    return Ok(());
-                }
             }
         }
-
-        Ok(())
     }
 
     async fn process_block(
@@ -366,7 +451,7 @@ impl SkillExecutor {
 }
 
 struct SkillManager {
-    skills: Vec<JsonValue>,
+    skills: Vec<Skill>,
 }
 
 impl SkillManager {
@@ -374,12 +459,17 @@ impl SkillManager {
         Self { skills: Vec::new() }
     }
 
-    fn load_skills(&mut self, skills: Vec<JsonValue>) {
-        self.skills = skills;
+    // Parses and validates every skill; nothing is loaded if any skill is invalid
+    fn load_skills(&mut self, skills: Vec<JsonValue>) -> Result<(), SkillError> {
+        self.skills = skills
+            .into_iter()
+            .map(Skill::from_json)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(())
     }
 
-    fn get_skill(&self, skill_id: &str) -> Option<&JsonValue> {
-        self.skills.iter().find(|s| s["id"] == skill_id)
+    fn get_skill(&self, skill_id: &str) -> Option<&Skill> {
+        self.skills.iter().find(|s| s.id == skill_id)
     }
 }
 
@@ -388,13 +478,230 @@ async fn process_input(
     skill_manager: &SkillManager,
     skill_executor: &SkillExecutor,
 ) -> Result<(), String> {
-    let skill_id = input.metadata["skill_id"].as_str().unwrap();
-    let skill_json = skill_manager.get_skill(skill_id).unwrap();
-    let mut state = ChannelState::from_json(&input.metadata["state"].to_string());
+    let skill_id = input
+        .metadata
+        .get("skill_id")
+        .and_then(|skill_id| skill_id.as_str())
+        .ok_or_else(|| "input missing 'skill_id'".to_string())?;
+    let skill = skill_manager
+        .get_skill(skill_id)
+        .ok_or_else(|| format!("skill {} not found", skill_id))?;
+    let state = input
+        .metadata
+        .get("state")
+        .ok_or_else(|| "input missing 'state'".to_string())?;
+    let mut state: ChannelState =
+        serde_json::from_value(state.clone()).map_err(|e| format!("invalid channel state: {}", e))?;
 
     skill_executor
-        .execute_skill(skill_json.clone(), &mut state, input)
+        .execute_skill(skill, &mut state, input)
         .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Accepts, moving to the `next` block
+    struct NextBlock {
+        next: Option<String>,
+    }
+
+    #[async_trait]
+    impl BlockTrait for NextBlock {
+        async fn process(&self, _state: &mut ChannelState, _input: &Input) -> Result<BlockResult, String> {
+            Ok(BlockResult::Accept(self.next.clone()))
+        }
+
+        fn serialize(&self) -> JsonValue {
+            JsonValue::Null
+        }
+    }
+
+    // Skips any block and counts how often it was asked to
+    struct SkipUnknownBlock {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl BlockTrait for SkipUnknownBlock {
+        async fn process(&self, _state: &mut ChannelState, _input: &Input) -> Result<BlockResult, String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(BlockResult::Accept(None))
+        }
+
+        fn serialize(&self) -> JsonValue {
+            JsonValue::Null
+        }
+    }
+
+    fn channel_state() -> ChannelState {
+        ChannelState {
+            user_id: "u1".to_string(),
+            operator_id: "o1".to_string(),
+            channel_id: "c1".to_string(),
+            skill: None,
+            block_id: None,
+            data: HashMap::new(),
+            extra: HashMap::new(),
+        }
+    }
+
+    fn input() -> Input {
+        Input {
+            text: "hello".to_string(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_valid_skill_loads() {
+        let mut manager = SkillManager::new();
+        let skill = json!({
+            "id": "greet",
+            "start": "b1",
+            "blocks": [
+                { "id": "b1", "type": "InputIntent" },
+                { "id": "b2", "type": "Messaging", "properties": { "text": "hi" } }
+            ]
+        });
+        manager.load_skills(vec![skill]).unwrap();
+        let skill = manager.get_skill("greet").unwrap();
+        assert_eq!(skill.blocks[1].block_type(), Some(BlockType::Messaging));
+    }
+
+    #[test]
+    fn test_skill_missing_start_is_rejected() {
+        let mut manager = SkillManager::new();
+        let skill = json!({
+            "id": "greet",
+            "blocks": [{ "id": "b1", "type": "InputIntent" }]
+        });
+        let err = manager.load_skills(vec![skill]).unwrap_err();
+        assert!(matches!(err, SkillError::Malformed(ref msg) if msg.contains("start")));
+        assert!(manager.get_skill("greet").is_none());
+
+        let dangling = json!({
+            "id": "greet",
+            "start": "missing",
+            "blocks": [{ "id": "b1", "type": "InputIntent" }]
+        });
+        assert_eq!(
+            Skill::from_json(dangling).unwrap_err(),
+            SkillError::UnknownStartBlock("missing".to_string())
+        );
+    }
+
+    #[test]
+    fn test_skill_with_unknown_block_type_is_rejected() {
+        let mut manager = SkillManager::new();
+        let skill = json!({
+            "id": "greet",
+            "start": "b1",
+            "blocks": [
+                { "id": "b1", "type": "InputIntent" },
+                { "id": "b2", "type": "Teleport" }
+            ]
+        });
+        assert_eq!(
+            manager.load_skills(vec![skill]).unwrap_err(),
+            SkillError::UnknownBlockType {
+                block_id: "b2".to_string(),
+                block_type: "Teleport".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_default_handler_runs_unregistered_blocks() {
+        let skill = Skill::from_json(json!({
+            "id": "greet",
+            "start": "b1",
+            "blocks": [
+                { "id": "b1", "type": "Messaging" },
+                { "id": "b2", "type": "Delay" }
+            ]
+        }))
+        .unwrap();
+
+        let mut registry = BlockRegistry::new();
+        registry.register(BlockType::Messaging, NextBlock { next: Some("b2".to_string()) });
+        let mut state = channel_state();
+        assert!(matches!(
+            registry.process_block(&BlockType::Delay, &mut state, &input()).await,
+            Err(BlockError::BlockTypeNotFound)
+        ));
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        registry.set_default_handler(SkipUnknownBlock { calls: calls.clone() });
+        let executor = SkillExecutor::new(registry);
+        executor.execute_skill(&skill, &mut state, &input()).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_skill_missing_blocks_or_type_is_rejected() {
+        let missing_blocks = json!({ "id": "greet", "start": "b1" });
+        assert!(matches!(
+            Skill::from_json(missing_blocks).unwrap_err(),
+            SkillError::Malformed(ref msg) if msg.contains("blocks")
+        ));
+
+        let missing_type = json!({ "id": "greet", "start": "b1", "blocks": [{ "id": "b1" }] });
+        assert!(matches!(
+            Skill::from_json(missing_type).unwrap_err(),
+            SkillError::Malformed(ref msg) if msg.contains("type")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_move_to_missing_block_is_an_error() {
+        let skill = Skill::from_json(json!({
+            "id": "greet",
+            "start": "b1",
+            "blocks": [{ "id": "b1", "type": "Messaging" }]
+        }))
+        .unwrap();
+        let mut registry = BlockRegistry::new();
+        registry.register(BlockType::Messaging, NextBlock { next: Some("b9".to_string()) });
+        let executor = SkillExecutor::new(registry);
+        let err = executor.execute_skill(&skill, &mut channel_state(), &input()).await.unwrap_err();
+        assert_eq!(err, "skill greet has no block b9");
+    }
+
+    #[tokio::test]
+    async fn test_process_input_rejects_malformed_metadata() {
+        let mut manager = SkillManager::new();
+        manager
+            .load_skills(vec![json!({
+                "id": "greet",
+                "start": "b1",
+                "blocks": [{ "id": "b1", "type": "Messaging" }]
+            })])
+            .unwrap();
+        let mut registry = BlockRegistry::new();
+        registry.register(BlockType::Messaging, NextBlock { next: None });
+        let executor = SkillExecutor::new(registry);
+
+        let mut input = input();
+        assert_eq!(process_input(&input, &manager, &executor).await.unwrap_err(), "input missing 'skill_id'");
+
+        input.metadata.insert("skill_id".to_string(), json!("farewell"));
+        assert_eq!(process_input(&input, &manager, &executor).await.unwrap_err(), "skill farewell not found");
+
+        input.metadata.insert("skill_id".to_string(), json!("greet"));
+        assert_eq!(process_input(&input, &manager, &executor).await.unwrap_err(), "input missing 'state'");
+
+        input.metadata.insert("state".to_string(), json!({ "user_id": "u1" }));
+        let err = process_input(&input, &manager, &executor).await.unwrap_err();
+        assert!(err.starts_with("invalid channel state: "), "{}", err);
+
+        input.metadata.insert("state".to_string(), channel_state().serialize());
+        process_input(&input, &manager, &executor).await.unwrap();
+    }
+}
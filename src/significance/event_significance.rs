@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use crate::event::{Event, Location};
+
 // Define an enum to represent the different types of events that can occur.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum EventType {
@@ -131,4 +133,369 @@ impl EventCollection {
             .iter()
             .max_by(|a, b| a.calculate_significance().partial_cmp(&b.calculate_significance()).unwrap())
     }
-}
\ No newline at end of file
+}
+// Define a struct to explain a score as the named contributions that add up to it, largest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Explanation {
+    pub factors: Vec<(String, f64)>,
+}
+
+impl Explanation {
+    // Define a constructor that orders the factors by the size of their contribution.
+    pub fn new(mut factors: Vec<(String, f64)>) -> Self {
+        factors.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()).then_with(|| a.0.cmp(&b.0)));
+        Self { factors }
+    }
+
+    // Define a method to get the score the factors add up to.
+    pub fn total(&self) -> f64 {
+        self.factors.iter().map(|(_, contribution)| contribution).sum()
+    }
+}
+
+// Define a model that derives an `Event`'s significance from its attributes and tags.
+// Attribute values are multiplied by their configured weight and tag weights are added on top,
+// so editing a high-weight attribute moves the score more than editing a low-weight one.
+#[derive(Debug, Clone, Default)]
+pub struct SignificanceModel {
+    attribute_weights: HashMap<String, f64>, // Weight applied to each attribute value
+    tag_weights: HashMap<String, f64>,       // Flat contribution of each tag
+    default_attribute_weight: f64,           // Weight used for attributes without an explicit weight
+}
+
+impl SignificanceModel {
+    // Define a constructor for the model with no weights configured.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Define a builder-style method to set the weight of an attribute.
+    pub fn with_attribute_weight(mut self, attribute_name: &str, weight: f64) -> Self {
+        self.attribute_weights.insert(attribute_name.to_string(), weight);
+        self
+    }
+
+    // Define a builder-style method to set the weight of a tag.
+    pub fn with_tag_weight(mut self, tag: &str, weight: f64) -> Self {
+        self.tag_weights.insert(tag.to_string(), weight);
+        self
+    }
+
+    // Define a builder-style method to set the weight used for unconfigured attributes.
+    pub fn with_default_attribute_weight(mut self, weight: f64) -> Self {
+        self.default_attribute_weight = weight;
+        self
+    }
+
+    // Define a method to get the weight of an attribute, falling back to the default weight.
+    pub fn attribute_weight(&self, attribute_name: &str) -> f64 {
+        self.attribute_weights
+            .get(attribute_name)
+            .copied()
+            .unwrap_or(self.default_attribute_weight)
+    }
+
+    // Define a method to calculate the significance of an event from its current attributes and tags.
+    pub fn recompute_for(&self, event: &Event) -> f64 {
+        let attribute_score: f64 = event
+            .attributes
+            .iter()
+            .map(|(name, value)| value * self.attribute_weight(name))
+            .sum();
+        let tag_score: f64 = event
+            .tags
+            .iter()
+            .filter_map(|tag| self.tag_weights.get(tag))
+            .sum();
+        attribute_score + tag_score
+    }
+
+    // Define a method to break an event's significance down into per-attribute and per-tag contributions.
+    // The contributions add up to `recompute_for(event)`.
+    pub fn explain(&self, event: &Event) -> Explanation {
+        let attribute_factors = event
+            .attributes
+            .iter()
+            .map(|(name, value)| (format!("attribute '{}'", name), value * self.attribute_weight(name)));
+        let tag_factors = event
+            .tags
+            .iter()
+            .filter_map(|tag| self.tag_weights.get(tag).map(|weight| (format!("tag '{}'", tag), *weight)));
+        Explanation::new(attribute_factors.chain(tag_factors).collect())
+    }
+
+    // Define a method to recompute and store the significance of every event, e.g. during a migration.
+    // Returns the number of events whose significance changed.
+    pub fn recompute_all(&self, events: &mut [Event]) -> usize {
+        let mut changed = 0;
+        for event in events.iter_mut() {
+            let significance = self.recompute_for(event);
+            if significance != event.significance {
+                event.significance = significance;
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+// Define the default time, in seconds, over which an event's recency score halves.
+pub const DEFAULT_RECENCY_HALF_LIFE_SECS: u64 = 24 * 60 * 60;
+
+// Define the default distance, in location units, at which proximity falls to 1/e.
+pub const DEFAULT_PROXIMITY_SCALE: f64 = 10.0;
+
+// Define the number of dependencies at which the dependency score reaches one half.
+const DEPENDENCY_HALF_SATURATION: f64 = 5.0;
+
+// Define the relative weight of each feature a `SignificanceScorer` combines. Weights are
+// relative to each other, so only their ratios matter; negative weights are treated as zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignificanceWeights {
+    pub base: f64,         // The event's stored `significance`
+    pub recency: f64,      // How close the event's start is to now
+    pub dependencies: f64, // How many events it depends on
+    pub tags: f64,         // How important its tags are to the user
+    pub proximity: f64,    // How close it is to the user
+}
+
+// The default only counts the stored significance, so scores rank events exactly as
+// `SignificanceModel` does until a personalization layer shifts weight onto other features.
+impl Default for SignificanceWeights {
+    fn default() -> Self {
+        Self {
+            base: 1.0,
+            recency: 0.0,
+            dependencies: 0.0,
+            tags: 0.0,
+            proximity: 0.0,
+        }
+    }
+}
+
+// Define the per-user, per-request inputs to a significance score.
+#[derive(Debug, Clone, Default)]
+pub struct SignificanceContext {
+    pub now: u64,                              // On the same clock as `Event::start_time`
+    pub user_location: Option<Location>,       // Proximity scores zero without it
+    pub tag_importance: HashMap<String, f64>,  // Importance of each tag to the user, 0 to 1
+}
+
+// Define a scorer that combines an event's features into a significance between 0 and 1.
+// Every feature is scaled to 0..1 and the score is their weighted mean.
+#[derive(Debug, Clone)]
+pub struct SignificanceScorer {
+    weights: SignificanceWeights,
+    recency_half_life_secs: u64,
+    proximity_scale: f64,
+}
+
+impl SignificanceScorer {
+    // Define a constructor for a scorer with the given feature weights.
+    pub fn new(weights: SignificanceWeights) -> Self {
+        Self {
+            weights,
+            recency_half_life_secs: DEFAULT_RECENCY_HALF_LIFE_SECS,
+            proximity_scale: DEFAULT_PROXIMITY_SCALE,
+        }
+    }
+
+    // Define a builder-style method to set how quickly recency decays.
+    pub fn with_recency_half_life(mut self, half_life_secs: u64) -> Self {
+        self.recency_half_life_secs = half_life_secs.max(1);
+        self
+    }
+
+    // Define a builder-style method to set how quickly proximity decays with distance.
+    pub fn with_proximity_scale(mut self, scale: f64) -> Self {
+        self.proximity_scale = scale;
+        self
+    }
+
+    // Define a method to get the feature weights.
+    pub fn weights(&self) -> &SignificanceWeights {
+        &self.weights
+    }
+
+    // Define a method to score an event for a user, normalized to 0..1. Zero if every weight is zero.
+    pub fn score(&self, event: &Event, ctx: &SignificanceContext) -> f64 {
+        let weighted = [
+            (self.weights.base, base_feature(event)),
+            (self.weights.recency, self.recency_feature(event, ctx)),
+            (self.weights.dependencies, dependency_feature(event)),
+            (self.weights.tags, tag_feature(event, ctx)),
+            (self.weights.proximity, self.proximity_feature(event, ctx)),
+        ];
+        let total_weight: f64 = weighted.iter().map(|(weight, _)| weight.max(0.0)).sum();
+        if total_weight == 0.0 {
+            return 0.0;
+        }
+        let score: f64 = weighted.iter().map(|(weight, feature)| weight.max(0.0) * feature).sum();
+        (score / total_weight).clamp(0.0, 1.0)
+    }
+
+    // 1 when the event starts now, halving every half-life before or after
+    fn recency_feature(&self, event: &Event, ctx: &SignificanceContext) -> f64 {
+        let distance = event.start_time.abs_diff(ctx.now) as f64;
+        0.5f64.powf(distance / self.recency_half_life_secs as f64)
+    }
+
+    // 1 at the user's location, decaying exponentially with distance
+    fn proximity_feature(&self, event: &Event, ctx: &SignificanceContext) -> f64 {
+        let Some(Location(x, y, z)) = ctx.user_location else {
+            return 0.0;
+        };
+        let Location(event_x, event_y, event_z) = event.location;
+        let distance = (((x - event_x).powi(2) + (y - event_y).powi(2) + (z - event_z).powi(2)) as f64).sqrt();
+        if self.proximity_scale <= 0.0 {
+            return if distance == 0.0 { 1.0 } else { 0.0 };
+        }
+        (-distance / self.proximity_scale).exp()
+    }
+}
+
+impl Default for SignificanceScorer {
+    fn default() -> Self {
+        Self::new(SignificanceWeights::default())
+    }
+}
+
+// The stored significance squashed into 0..1, keeping the order of non-negative values
+fn base_feature(event: &Event) -> f64 {
+    let significance = event.significance.max(0.0);
+    significance / (1.0 + significance)
+}
+
+fn dependency_feature(event: &Event) -> f64 {
+    let dependencies = event.dependencies.len() as f64;
+    dependencies / (dependencies + DEPENDENCY_HALF_SATURATION)
+}
+
+// The chance that at least one tag matters, treating each tag's importance as independent
+fn tag_feature(event: &Event, ctx: &SignificanceContext) -> f64 {
+    let unimportant: f64 = event
+        .tags
+        .iter()
+        .filter_map(|tag| ctx.tag_importance.get(tag))
+        .map(|importance| 1.0 - importance.clamp(0.0, 1.0))
+        .product();
+    1.0 - unimportant
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{EventBuilder, EventType as StreamEventType};
+
+    fn test_event() -> Event {
+        EventBuilder::new(1, "concert", StreamEventType::ScheduledEvent)
+            .time_range(0, 10)
+            .resource("venue")
+            .attribute("importance", 1.0)
+            .attribute("noise", 1.0)
+            .build()
+            .unwrap()
+    }
+
+    fn scored_event(id: i64, start_time: u64, significance: f64) -> Event {
+        EventBuilder::new(id, "concert", StreamEventType::ScheduledEvent)
+            .time_range(start_time, start_time + 10)
+            .significance(significance)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_recency_weight_favours_recent_events() {
+        let ctx = SignificanceContext {
+            now: 10 * DEFAULT_RECENCY_HALF_LIFE_SECS,
+            ..Default::default()
+        };
+        let recent = scored_event(1, ctx.now - 60, 1.0);
+        let older = scored_event(2, ctx.now - 5 * DEFAULT_RECENCY_HALF_LIFE_SECS, 4.0);
+
+        let default_scorer = SignificanceScorer::default();
+        assert!(default_scorer.score(&older, &ctx) > default_scorer.score(&recent, &ctx));
+
+        let recency_scorer = SignificanceScorer::new(SignificanceWeights {
+            recency: 5.0,
+            ..Default::default()
+        });
+        assert!(recency_scorer.score(&recent, &ctx) > recency_scorer.score(&older, &ctx));
+        for event in [&recent, &older] {
+            let score = recency_scorer.score(event, &ctx);
+            assert!((0.0..=1.0).contains(&score));
+        }
+    }
+
+    #[test]
+    fn test_tags_and_proximity_contribute_when_weighted() {
+        let scorer = SignificanceScorer::new(SignificanceWeights {
+            base: 0.0,
+            recency: 0.0,
+            dependencies: 0.0,
+            tags: 1.0,
+            proximity: 1.0,
+        });
+        let ctx = SignificanceContext {
+            now: 0,
+            user_location: Some(Location(0.0, 0.0, 0.0)),
+            tag_importance: HashMap::from([("music".to_string(), 0.8)]),
+        };
+        let mut nearby = scored_event(1, 0, 0.0);
+        nearby.tags.push("music".to_string());
+        let mut far = nearby.clone();
+        far.location = Location(100.0, 0.0, 0.0);
+
+        assert!((scorer.score(&nearby, &ctx) - 0.9).abs() < 1e-9);
+        assert!(scorer.score(&far, &ctx) < scorer.score(&nearby, &ctx));
+        assert_eq!(SignificanceScorer::new(SignificanceWeights { base: 0.0, ..Default::default() }).score(&nearby, &ctx), 0.0);
+    }
+
+    #[test]
+    fn test_recompute_for_tracks_high_weight_attribute() {
+        let model = SignificanceModel::new()
+            .with_attribute_weight("importance", 10.0)
+            .with_attribute_weight("noise", 0.1);
+        let mut event = test_event();
+        let before = model.recompute_for(&event);
+
+        event.attributes.insert("importance".to_string(), 3.0);
+        let after = model.recompute_for(&event);
+
+        assert!((before - 10.1).abs() < 1e-9);
+        assert!((after - 30.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_recompute_all_updates_stale_significance() {
+        let model = SignificanceModel::new()
+            .with_attribute_weight("importance", 10.0)
+            .with_tag_weight("music", 2.0);
+        let mut fresh = test_event();
+        fresh.significance = model.recompute_for(&fresh);
+        let mut stale = test_event();
+        stale.tags.push("music".to_string());
+
+        let mut events = vec![fresh, stale];
+        assert_eq!(model.recompute_all(&mut events), 1);
+        assert!((events[1].significance - 12.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_explain_factors_sum_to_significance() {
+        let model = SignificanceModel::new()
+            .with_attribute_weight("importance", 10.0)
+            .with_default_attribute_weight(0.5)
+            .with_tag_weight("music", 2.0);
+        let mut event = test_event();
+        event.tags.push("music".to_string());
+        event.tags.push("untracked".to_string());
+
+        let explanation = model.explain(&event);
+
+        assert!((explanation.total() - model.recompute_for(&event)).abs() < 1e-9);
+        assert_eq!(explanation.factors[0], ("attribute 'importance'".to_string(), 10.0));
+        assert_eq!(explanation.factors.len(), 3);
+    }
+}
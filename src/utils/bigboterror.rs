@@ -90,6 +90,21 @@ pub enum BigbotError {
     #[error("Failed to get user: {0}")]
     UserGetError(String),
 
+    #[error("Token expired at {0}")]
+    TokenExpired(u64),
+
+    #[error("Token not valid before {0}")]
+    TokenNotYetValid(u64),
+
+    #[error("Quorum not reached: {reached} of {required} nodes responded")]
+    QuorumNotReached { required: usize, reached: usize },
+
+    #[error("Routing failed: {}", .0.join("; "))]
+    RoutingFailed(Vec<String>),
+
+    #[error("No chart type could be inferred from '{0}'")]
+    ChartTypeNotInferred(String),
+
     #[error("An unexpected error occurred: {0}")]
     UnexpectedError(String),
 }
@@ -108,6 +123,12 @@ impl From<KVError> for BigbotError {
     }
 }
 
+impl From<tokio_postgres::Error> for BigbotError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        BigbotError::DatabaseError(err.to_string())
+    }
+}
+
 impl From<Box<dyn StdError>> for BigbotError {
     fn from(error: Box<dyn StdError>) -> Self {
         BigbotError::UnexpectedError(error.to_string())
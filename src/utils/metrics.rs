@@ -0,0 +1,180 @@
+//! # Metrics
+//!
+//! A lightweight in-process metrics facade for counters and histograms.
+//!
+//! Components record through the free functions (`increment_counter`, `record_histogram`),
+//! which write to a process-wide `MetricsRegistry`. The registry can be read synchronously,
+//! which keeps assertions in tests simple and gives an exporter a single place to scrape.
+//! Each metric is identified by a name plus a set of labels, e.g.
+//! `messages_sent{transport="kafka"}`.
+//!
+//! Histograms count samples into fixed buckets rather than keeping them, so their memory use
+//! doesn't grow with the number of samples recorded.
+
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Names of the metrics emitted by the messaging pipeline.
+pub mod names {
+    /// Counter of messages handed to a transport, labelled by `transport`.
+    pub const MESSAGES_SENT: &str = "messages_sent";
+    /// Counter of classified messages, labelled by `route`.
+    pub const MESSAGES_CLASSIFIED: &str = "messages_classified";
+    /// Counter of PII spans masked.
+    pub const PII_SPANS_MASKED: &str = "pii_spans_masked";
+    /// Counter of consensus validations, labelled by `result` (`valid`/`invalid`).
+    pub const CONSENSUS_VALIDATIONS: &str = "consensus_validations";
+    /// Histogram of send latency in seconds, labelled by `transport`.
+    pub const SEND_LATENCY_SECONDS: &str = "send_latency_seconds";
+}
+
+/// Upper bounds of the histogram buckets, suited to latencies in seconds. Samples above the last
+/// bound are counted in an overflow bucket.
+pub const HISTOGRAM_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+lazy_static! {
+    static ref REGISTRY: MetricsRegistry = MetricsRegistry::default();
+}
+
+/// Returns the process-wide registry.
+pub fn registry() -> &'static MetricsRegistry {
+    &REGISTRY
+}
+
+pub fn increment_counter(name: &str, labels: &[(&str, &str)]) {
+    registry().increment_counter_by(name, labels, 1);
+}
+
+pub fn increment_counter_by(name: &str, labels: &[(&str, &str)], value: u64) {
+    registry().increment_counter_by(name, labels, value);
+}
+
+pub fn record_histogram(name: &str, labels: &[(&str, &str)], value: f64) {
+    registry().record_histogram(name, labels, value);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct MetricKey {
+    name: String,
+    labels: Vec<(String, String)>,
+}
+
+impl MetricKey {
+    fn new(name: &str, labels: &[(&str, &str)]) -> Self {
+        let mut labels: Vec<(String, String)> = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        labels.sort();
+        Self {
+            name: name.to_string(),
+            labels,
+        }
+    }
+}
+
+/// Samples recorded for one histogram, bucketed by `HISTOGRAM_BUCKETS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Samples per bucket: `buckets[i]` counts samples at most `HISTOGRAM_BUCKETS[i]` and above
+    /// the previous bound, and the last entry counts samples above every bound.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; HISTOGRAM_BUCKETS.len() + 1],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&mut self, value: f64) {
+        let bucket = HISTOGRAM_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(HISTOGRAM_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Default)]
+pub struct MetricsRegistry {
+    counters: Mutex<HashMap<MetricKey, u64>>,
+    histograms: Mutex<HashMap<MetricKey, Histogram>>,
+}
+
+impl MetricsRegistry {
+    pub fn increment_counter_by(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(MetricKey::new(name, labels))
+            .or_insert(0) += value;
+    }
+
+    pub fn record_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry(MetricKey::new(name, labels))
+            .or_default()
+            .record(value);
+    }
+
+    /// Current value of a counter; 0 if it was never incremented.
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        self.counters
+            .lock()
+            .unwrap()
+            .get(&MetricKey::new(name, labels))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The buckets, count and sum of a histogram; empty if nothing was recorded.
+    pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Histogram {
+        self.histograms
+            .lock()
+            .unwrap()
+            .get(&MetricKey::new(name, labels))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_counts_samples_into_fixed_buckets() {
+        let registry = MetricsRegistry::default();
+        let labels = [("transport", "kafka")];
+        for value in [0.0005, 0.003, 0.003, 0.2, 60.0] {
+            registry.record_histogram("latency", &labels, value);
+        }
+
+        let histogram = registry.histogram("latency", &labels);
+        assert_eq!(histogram.count, 5);
+        assert!((histogram.sum - 60.2065).abs() < 1e-9);
+        assert_eq!(histogram.buckets.len(), HISTOGRAM_BUCKETS.len() + 1);
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[1], 2);
+        assert_eq!(histogram.buckets[6], 1);
+        assert_eq!(histogram.buckets[HISTOGRAM_BUCKETS.len()], 1);
+
+        assert_eq!(registry.histogram("latency", &[]), Histogram::default());
+    }
+}
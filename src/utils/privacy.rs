@@ -0,0 +1,163 @@
+//! # Differential Privacy
+//!
+//! Laplace-mechanism helpers for aggregate queries over user data, and a `PrivacyBudget` that
+//! tracks how much privacy loss (epsilon) the queries against a dataset have spent. Each query
+//! spends its epsilon up front and is refused once the budget can't cover it, since repeated
+//! noisy answers average out to the exact one.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+// Total epsilon available to a dataset unless configured otherwise
+pub const DEFAULT_PRIVACY_BUDGET: f64 = 1.0;
+
+// Probability of releasing an interest held by too few contributors to hide in the noise
+pub const DEFAULT_DELTA: f64 = 1e-6;
+
+// Interests counted per contributor; this bounds how much one contributor can move the histogram
+pub const MAX_INTERESTS_PER_CONTRIBUTOR: usize = 5;
+
+// Rounding slack when comparing spent epsilon with the total
+const BUDGET_TOLERANCE: f64 = 1e-9;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum PrivacyError {
+    #[error("Epsilon must be positive and finite, got {0}")]
+    InvalidEpsilon(f64),
+    #[error("Privacy budget exhausted: requested {requested}, remaining {remaining}")]
+    BudgetExhausted { requested: f64, remaining: f64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrivacyBudget {
+    total: f64,
+    spent: f64,
+}
+
+impl PrivacyBudget {
+    pub fn new(total: f64) -> Self {
+        Self { total, spent: 0.0 }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    pub fn spent(&self) -> f64 {
+        self.spent
+    }
+
+    pub fn remaining(&self) -> f64 {
+        (self.total - self.spent).max(0.0)
+    }
+
+    // Records the cost of a query; nothing is spent if the budget can't cover it
+    pub fn spend(&mut self, epsilon: f64) -> Result<(), PrivacyError> {
+        if !(epsilon.is_finite() && epsilon > 0.0) {
+            return Err(PrivacyError::InvalidEpsilon(epsilon));
+        }
+        if self.spent + epsilon > self.total + BUDGET_TOLERANCE {
+            return Err(PrivacyError::BudgetExhausted {
+                requested: epsilon,
+                remaining: self.remaining(),
+            });
+        }
+        self.spent += epsilon;
+        Ok(())
+    }
+}
+
+impl Default for PrivacyBudget {
+    fn default() -> Self {
+        Self::new(DEFAULT_PRIVACY_BUDGET)
+    }
+}
+
+// A sample from Laplace(0, scale)
+pub fn laplace_noise<R: Rng + ?Sized>(rng: &mut R, scale: f64) -> f64 {
+    // Uniform on (-0.5, 0.5); the endpoints would give an infinite sample
+    let u: f64 = loop {
+        let u = rng.gen::<f64>() - 0.5;
+        if u != -0.5 {
+            break u;
+        }
+    };
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+// A count with sensitivity 1, released with Laplace noise after spending `epsilon`.
+// Negative results are clamped to zero, which is post-processing and costs no privacy.
+pub fn noisy_count(budget: &mut PrivacyBudget, count: usize, epsilon: f64) -> Result<f64, PrivacyError> {
+    budget.spend(epsilon)?;
+    let noise = laplace_noise(&mut rand::thread_rng(), 1.0 / epsilon);
+    Ok((count as f64 + noise).max(0.0))
+}
+
+// The `n` most common interests with noisy counts, highest first. Each contributor's interests
+// are deduplicated and capped at `MAX_INTERESTS_PER_CONTRIBUTOR` before counting, and an
+// interest is only released if its noisy count clears a threshold, so interests held by a
+// handful of contributors aren't revealed just by appearing in the result.
+pub fn noisy_top_interests<'a, I, C>(
+    budget: &mut PrivacyBudget,
+    contributors: I,
+    epsilon: f64,
+    n: usize,
+) -> Result<Vec<(String, f64)>, PrivacyError>
+where
+    I: IntoIterator<Item = C>,
+    C: IntoIterator<Item = &'a str>,
+{
+    budget.spend(epsilon)?;
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for interests in contributors {
+        let mut interests: Vec<&str> = interests.into_iter().collect();
+        interests.sort_unstable();
+        interests.dedup();
+        for interest in interests.into_iter().take(MAX_INTERESTS_PER_CONTRIBUTOR) {
+            *counts.entry(interest).or_default() += 1;
+        }
+    }
+
+    let sensitivity = MAX_INTERESTS_PER_CONTRIBUTOR as f64;
+    let scale = sensitivity / epsilon;
+    let threshold = 1.0 + scale * (1.0 / DEFAULT_DELTA).ln();
+    let mut rng = rand::thread_rng();
+    let mut noisy: Vec<(String, f64)> = counts
+        .into_iter()
+        .map(|(interest, count)| (interest.to_string(), count as f64 + laplace_noise(&mut rng, scale)))
+        .filter(|(_, count)| *count >= threshold)
+        .collect();
+    noisy.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    noisy.truncate(n);
+    Ok(noisy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_decrements_and_refuses_overspend() {
+        let mut budget = PrivacyBudget::new(1.0);
+        budget.spend(0.4).unwrap();
+        budget.spend(0.6).unwrap();
+        assert_eq!(budget.remaining(), 0.0);
+        assert!(matches!(budget.spend(0.1), Err(PrivacyError::BudgetExhausted { .. })));
+        assert_eq!(budget.spent(), 1.0);
+        assert_eq!(PrivacyBudget::default().spend(0.0), Err(PrivacyError::InvalidEpsilon(0.0)));
+    }
+
+    #[test]
+    fn test_laplace_noise_is_centred_with_expected_spread() {
+        let mut rng = rand::thread_rng();
+        let samples: Vec<f64> = (0..20_000).map(|_| laplace_noise(&mut rng, 2.0)).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let mean_abs = samples.iter().map(|x| x.abs()).sum::<f64>() / samples.len() as f64;
+        // E|X| equals the scale for a Laplace distribution
+        assert!(mean.abs() < 0.1, "mean {}", mean);
+        assert!((mean_abs - 2.0).abs() < 0.1, "mean absolute deviation {}", mean_abs);
+    }
+}